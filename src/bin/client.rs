@@ -1,7 +1,12 @@
 use std::env;
 
 use bevy::app::App;
-use rust_space_trading::{prelude::*, ui::gui::GuiPlugin, utils::args::get_keymap};
+use rust_space_trading::{
+    client::PlayerName,
+    prelude::*,
+    ui::gui::GuiPlugin,
+    utils::args::{get_keymap, get_name, get_seed},
+};
 
 fn main() {
     #[allow(unused_variables)]
@@ -13,6 +18,11 @@ fn main() {
         .add_plugins((
             ClientPlugin {
                 singleplayer_bodies_config,
+                seed: WorldSeed(get_seed(env::args()).unwrap().unwrap_or_default()),
+                player_name: get_name(env::args())
+                    .unwrap()
+                    .map(PlayerName)
+                    .unwrap_or_default(),
                 ..Default::default()
             },
             TuiPlugin {
@@ -0,0 +1,61 @@
+use std::time::{Duration, Instant};
+
+use bevy::math::DVec3;
+use rust_space_trading::physics::{
+    leapfrog::{get_acceleration, get_dv, get_dx},
+    rk4::rk4_step,
+    time::GAMETIME_PER_SIMTICK,
+    G,
+};
+
+const EARTH_MASS: f64 = 5.972e24;
+const ORBIT_RADIUS: f64 = 1e5;
+const STEPS: u64 = 100_000;
+const REPORT_EVERY: u64 = 10_000;
+
+/// Runs the leapfrog and RK4 integrators on a circular orbit around the Earth
+/// and prints the error of both compared to the analytic solution
+fn main() {
+    let mu = G * EARTH_MASS;
+    let speed = (mu / ORBIT_RADIUS).sqrt();
+    let angular_speed = speed / ORBIT_RADIUS;
+    let dt = GAMETIME_PER_SIMTICK;
+    let accel = |pos| get_acceleration(pos, std::iter::once((DVec3::ZERO, EARTH_MASS)));
+
+    let (mut lf_pos, mut lf_vel) = (DVec3::new(ORBIT_RADIUS, 0., 0.), DVec3::new(0., speed, 0.));
+    let mut lf_acc = accel(lf_pos);
+    let (mut rk_pos, mut rk_vel) = (lf_pos, lf_vel);
+    let (mut lf_duration, mut rk_duration) = (Duration::ZERO, Duration::ZERO);
+
+    println!(
+        "{:>8} {:>12} {:>20} {:>20}",
+        "step", "time (d)", "leapfrog error (km)", "rk4 error (km)"
+    );
+    for step in 1..=STEPS {
+        let start = Instant::now();
+        lf_pos += get_dx(lf_vel, lf_acc, dt);
+        let previous = lf_acc;
+        lf_acc = accel(lf_pos);
+        lf_vel += get_dv(previous, lf_acc, dt);
+        lf_duration += start.elapsed();
+
+        let start = Instant::now();
+        (rk_pos, rk_vel) = rk4_step(rk_pos, rk_vel, accel, dt);
+        rk_duration += start.elapsed();
+
+        if step % REPORT_EVERY == 0 {
+            let time = step as f64 * dt;
+            let angle = angular_speed * time;
+            let expected = ORBIT_RADIUS * DVec3::new(angle.cos(), angle.sin(), 0.);
+            println!(
+                "{:>8} {:>12.1} {:>20.6} {:>20.6}",
+                step,
+                time,
+                (lf_pos - expected).length(),
+                (rk_pos - expected).length()
+            );
+        }
+    }
+    println!("leapfrog total time: {:?}", lf_duration);
+    println!("rk4 total time: {:?}", rk_duration);
+}
@@ -1,14 +1,45 @@
-use bevy::app::App;
+use bevy::{app::App, log::Level};
 
-use rust_space_trading::prelude::*;
-use std::net::{IpAddr, Ipv4Addr};
+use rust_space_trading::{
+    network::transport::TransportKind,
+    objects::bodies::validation::check_main_bodies,
+    prelude::*,
+    utils::{
+        args::{get_check, get_quiet, get_seed},
+        log::DEFAULT_LOG_LEVEL,
+    },
+};
+use std::{
+    env,
+    net::{IpAddr, Ipv4Addr},
+    process,
+};
 
 fn main() {
+    if get_check(env::args()) {
+        process::exit(match check_main_bodies() {
+            Ok(false) => 0,
+            Ok(true) => 1,
+            Err(e) => {
+                eprintln!("could not read the bodies: {e}");
+                2
+            }
+        });
+    }
     App::new()
         .add_plugins((
             ServerPlugin {
                 server_address: ServerNetworkInfo(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 6000),
                 config: BodiesConfig::default(),
+                physics_rate: PhysicsRate::default(),
+                seed: WorldSeed(get_seed(env::args()).unwrap().unwrap_or_default()),
+                log_level: if get_quiet(env::args()) {
+                    Level::WARN
+                } else {
+                    DEFAULT_LOG_LEVEL
+                },
+                transport: TransportKind::Quinnet,
+                testing: false,
             },
             bevy::app::ScheduleRunnerPlugin::default(),
         ))
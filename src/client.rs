@@ -8,10 +8,22 @@ use bevy_quinnet::client::{
 
 use crate::{
     game::GamePlugin,
-    network::{ClientChannel, ServerMessage},
-    objects::prelude::BodiesConfig,
-    physics::{prelude::Position, Velocity},
-    prelude::{GameTime, Influenced, ShipInfo, ShipsMapping, ToggleTime},
+    game::{GameStage, WorldSeed},
+    network::{
+        time_sync::{self, ClockSync},
+        transport::{ClientMessageTransport, ClientTransport, TransportKind},
+        ClientChannel, ClientMessage, InterestRegion, PermissionDenied, Role, ServerMessage,
+    },
+    objects::{
+        bodies::{bodies_hash, PendingBodies},
+        prelude::BodiesConfig,
+        ships::trajectory::{TrajectoryUpdate, VelocityUpdate},
+    },
+    physics::{orbit, prelude::Position, time::time_running, Velocity},
+    prelude::{
+        BodiesMapping, GameTime, Influenced, PhysicsRate, ShipEvent, ShipID, ShipInfo,
+        ShipsMapping, ToggleTime,
+    },
     utils::ecs::exit_on_error_if_app,
 };
 
@@ -25,6 +37,10 @@ pub struct ClientPlugin {
     pub server_info: ServerNetworkInfo,
     pub singleplayer_bodies_config: BodiesConfig,
     pub initial_mode: ClientMode,
+    pub physics_rate: PhysicsRate,
+    pub seed: WorldSeed,
+    pub player_name: PlayerName,
+    pub transport: TransportKind,
     pub testing: bool,
 }
 
@@ -52,6 +68,35 @@ impl ClientPlugin {
             ..self
         }
     }
+
+    pub fn with_physics_rate(self, hz: f64) -> Self {
+        Self {
+            physics_rate: PhysicsRate(hz),
+            ..self
+        }
+    }
+
+    /// Seed of the singleplayer world, replaced by the one of the server in multiplayer
+    pub fn with_seed(self, seed: u64) -> Self {
+        Self {
+            seed: WorldSeed(seed),
+            ..self
+        }
+    }
+
+    /// Name sent to the server when joining a multiplayer game
+    pub fn with_player_name(self, name: impl Into<String>) -> Self {
+        Self {
+            player_name: PlayerName(name.into()),
+            ..self
+        }
+    }
+
+    /// With [TransportKind::Loopback], the [crate::network::transport::LoopbackClient] linking
+    /// the client to the server must be inserted before entering [ClientMode::Multiplayer]
+    pub fn with_transport(self, transport: TransportKind) -> Self {
+        Self { transport, ..self }
+    }
 }
 
 impl Plugin for ClientPlugin {
@@ -59,21 +104,34 @@ impl Plugin for ClientPlugin {
         if self.testing {
             app.insert_resource(Testing);
         }
+        if self.transport == TransportKind::Quinnet {
+            app.add_plugins(QuinnetClientPlugin::default()).add_systems(
+                OnEnter(ClientMode::Multiplayer),
+                start_connection.pipe(exit_on_error_if_app),
+            );
+        }
         app.add_plugins((
             GamePlugin {
                 testing: self.testing,
             },
-            QuinnetClientPlugin::default(),
+            time_sync::plugin,
         ))
         .insert_resource(self.network_info.clone())
         .insert_resource(self.server_info.clone())
         .insert_state(SyncStatus::NotSynced)
         .insert_resource(self.singleplayer_bodies_config.clone())
+        .insert_resource(self.physics_rate)
+        .insert_resource(self.seed)
+        .insert_resource(self.player_name.clone())
+        .init_resource::<ClientRole>()
+        .init_resource::<FollowedRegion>()
+        .add_event::<ActionDenied>()
         .insert_state(self.initial_mode)
         .add_systems(
             OnEnter(ClientMode::Multiplayer),
-            start_connection.pipe(exit_on_error_if_app),
+            |mut commands: Commands| commands.insert_resource(BodiesCheck::Waiting),
         )
+        .add_systems(OnExit(ClientMode::Multiplayer), close_connection)
         .add_systems(
             OnEnter(ClientMode::Explorer),
             move |mut toggle: ResMut<ToggleTime>, mut time: ResMut<GameTime>| {
@@ -87,7 +145,38 @@ impl Plugin for ClientPlugin {
         )
         .add_systems(
             FixedUpdate,
-            handle_server_messages.run_if(in_state(ClientMode::Multiplayer)),
+            (
+                handle_server_messages,
+                check_bodies
+                    .run_if(resource_exists::<BodiesCheck>)
+                    .run_if(resource_exists::<PendingBodies>),
+                // The server can set the simtick while the time is stopped, in which case the
+                // physics doesn't run to move the bodies to it
+                (orbit::update_local, orbit::update_global)
+                    .chain()
+                    .run_if(resource_exists::<BodiesMapping>)
+                    .run_if(resource_changed::<GameTime>)
+                    .run_if(not(time_running)),
+            )
+                .chain()
+                .run_if(in_state(ClientMode::Multiplayer)),
+        )
+        .add_systems(
+            FixedUpdate,
+            send_thrusts
+                .after(TrajectoryUpdate)
+                .run_if(in_state(ClientMode::Multiplayer)),
+        )
+        .add_systems(
+            OnEnter(SyncStatus::Synced),
+            send_followed_region.run_if(in_state(ClientMode::Multiplayer)),
+        )
+        .add_systems(
+            Update,
+            send_followed_region
+                .run_if(resource_changed::<FollowedRegion>)
+                .run_if(in_state(SyncStatus::Synced))
+                .run_if(in_state(ClientMode::Multiplayer)),
         );
     }
 }
@@ -102,6 +191,59 @@ pub enum ClientMode {
     Server,
 }
 
+/// Last maneuver preview computed by the server, see [crate::network::ClientMessage::SandboxManeuver]
+#[derive(Resource, Debug, Clone)]
+pub struct ManeuverPreview {
+    pub ship: ShipID,
+    /// Simticks and positions of the ship along the previewed trajectory
+    pub positions: Vec<(u64, Position)>,
+    pub fuel_remaining: f64,
+}
+
+/// Role given by the server to this client, which decides the actions the server accepts from it.
+/// In singleplayer, the client can create and control its ships as a player
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientRole(pub Role);
+
+/// An action of this client that the server refused for lack of permission
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActionDenied(pub PermissionDenied);
+
+/// Region of the system the player looks at, whose ships the server sends at full rate. The
+/// server sends every ship at full rate until the client gives one
+#[derive(Resource, Default, Debug, Clone, PartialEq)]
+pub struct FollowedRegion(pub Option<InterestRegion>);
+
+/// Simtick of the last periodic update of the server with the state of a ship, and whether it was
+/// a coarse one. Ships outside the [FollowedRegion] are updated less often, so their state is
+/// simulated locally from older data, and they are drawn dimmer on the space map
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LastServerUpdate {
+    pub simtick: u64,
+    pub coarse: bool,
+}
+
+impl LastServerUpdate {
+    /// Simticks elapsed since the update
+    pub fn age(&self, simtick: u64) -> u64 {
+        simtick.saturating_sub(self.simtick)
+    }
+}
+
+/// Name of the player, by which the server admin can kick or ban the client
+#[derive(Resource, Debug, Clone, PartialEq, Eq)]
+pub struct PlayerName(pub String);
+
+impl Default for PlayerName {
+    fn default() -> Self {
+        Self("player".into())
+    }
+}
+
+/// Why the server closed the connection, shown on the start menu
+#[derive(Resource, Debug, Clone, PartialEq, Eq)]
+pub struct DisconnectReason(pub String);
+
 #[derive(Clone, Resource)]
 pub struct ClientNetworkInfo(pub IpAddr, pub u16);
 impl Default for ClientNetworkInfo {
@@ -133,6 +275,35 @@ fn start_connection(
     Ok(())
 }
 
+fn close_connection(
+    mut commands: Commands,
+    mut transport: ClientTransport,
+    mut sync: ResMut<NextState<SyncStatus>>,
+) {
+    if let Err(e) = transport.disconnect() {
+        warn!("could not close the connection to the server: {e}");
+    }
+    commands.remove_resource::<BodiesCheck>();
+    sync.set(SyncStatus::NotSynced);
+}
+
+/// Comparison of the bodies read by the client with the ones of the server, whose
+/// [bodies_hash] is given by the initial data. A client with other bodies (from another version
+/// of the bundled file, or another config) would compute other positions and influences, so it
+/// reads the bodies of the server instead. The loading waits for the resource to be removed,
+/// once the bodies match, before spawning them
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodiesCheck {
+    /// The initial data of the server didn't arrive yet
+    Waiting,
+    /// The hash of the bodies of the client is compared to the one of the server
+    Pending(u64),
+    /// The bodies differ, and the client asked for the ones of the server
+    Requested(u64),
+    /// The client replaced its bodies with the ones it received from the server
+    Rebuilt(u64),
+}
+
 #[derive(States, Debug, Default, Clone, PartialEq, Eq, Hash)]
 pub enum SyncStatus {
     #[default]
@@ -140,19 +311,26 @@ pub enum SyncStatus {
     Synced,
 }
 
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn handle_server_messages(
-    mut client: ResMut<QuinnetClient>,
+    mut transport: ClientTransport,
     mut commands: Commands,
     mut time: ResMut<GameTime>,
     mut sync: ResMut<NextState<SyncStatus>>,
     mut toggle_time: ResMut<ToggleTime>,
-    mut query: Query<(&ShipInfo, &mut Position, &mut Velocity)>,
-    ships: Res<ShipsMapping>,
+    mut query: Query<(&mut Position, &mut Velocity, Option<&mut LastServerUpdate>), With<ShipInfo>>,
+    ships: Option<Res<ShipsMapping>>,
+    mut ship_events: EventWriter<ShipEvent>,
+    mut role: ResMut<ClientRole>,
+    mut next_stage: Option<ResMut<NextState<GameStage>>>,
+    mut next_mode: ResMut<NextState<ClientMode>>,
+    player_name: Res<PlayerName>,
+    mut clock: ResMut<ClockSync>,
+    real_time: Res<Time<Real>>,
+    mut denied: EventWriter<ActionDenied>,
+    check: Option<Res<BodiesCheck>>,
 ) {
-    while let Some((_, message)) = client
-        .connection_mut()
-        .try_receive_message::<ServerMessage>()
-    {
+    while let Some(message) = transport.try_receive() {
         match message {
             ServerMessage::BodiesConfig(bodies) => {
                 commands.insert_resource(bodies);
@@ -161,26 +339,179 @@ fn handle_server_messages(
             ServerMessage::UpdateTime(simtick) => time.simtick = simtick,
             ServerMessage::InitialData(initial_data) => {
                 commands.insert_resource(initial_data.bodies_config);
+                commands.insert_resource(initial_data.seed);
+                commands.insert_resource(BodiesCheck::Pending(initial_data.bodies_hash));
                 toggle_time.0 = initial_data.toggle_time;
                 sync.set(SyncStatus::Synced);
+                transport
+                    .send(
+                        ClientChannel::Once,
+                        &ClientMessage::Hello {
+                            name: player_name.0.clone(),
+                        },
+                    )
+                    .unwrap_or_else(|e| error!("could not send message to the server: {e}"));
             }
             ServerMessage::ToggleTime(b) => toggle_time.0 = b,
+            ServerMessage::ShipCreateRejected { id, reason } => {
+                warn!("server rejected ship {id}: {reason}");
+                ship_events.send(ShipEvent::Rejected(id, reason));
+            }
+            ServerMessage::RoleAssigned(r) => {
+                info!("server assigned role {r}");
+                role.0 = r;
+            }
+            ServerMessage::Denied(reason) => {
+                warn!("server denied action: {reason}");
+                denied.send(ActionDenied(reason));
+            }
+            ServerMessage::ShipRemoved(id) => {
+                ship_events.send(ShipEvent::Remove(id));
+            }
+            ServerMessage::ShipRenamed { from, to } => {
+                info!("server renamed ship {from} to {to}");
+                ship_events.send(ShipEvent::Renamed { from, to });
+            }
+            ServerMessage::ManeuverPreview {
+                ship,
+                positions,
+                fuel_remaining,
+            } => commands.insert_resource(ManeuverPreview {
+                ship,
+                positions,
+                fuel_remaining,
+            }),
+            ServerMessage::Kicked(reason) | ServerMessage::Rejected(reason) => {
+                warn!("disconnected by the server: {reason}");
+                commands.insert_resource(DisconnectReason(reason));
+                next_mode.set(ClientMode::None);
+                return;
+            }
+            ServerMessage::Bodies(data) => match check.as_deref() {
+                Some(&BodiesCheck::Requested(expected)) => {
+                    info!("loading the {} bodies of the server", data.len());
+                    commands.insert_resource(PendingBodies::new(data));
+                    commands.insert_resource(BodiesCheck::Rebuilt(expected));
+                }
+                _ => warn!("ignoring the bodies sent by the server, which were not requested"),
+            },
+            ServerMessage::TimeSyncResponse {
+                client_local_tick,
+                server_tick,
+                ..
+            } => clock.answer(
+                client_local_tick,
+                server_tick,
+                time.simtick,
+                real_time.elapsed().as_nanos() as u64,
+            ),
+            ServerMessage::ChangeStage(stage) => {
+                if let Some(next_stage) = next_stage.as_mut() {
+                    next_stage.set(stage);
+                }
+            }
             ServerMessage::PeriodicUpdate(periodic_update) => {
-                time.simtick = periodic_update.time;
-                let new_ships = periodic_update.ships;
-                for (id, pos, velocity) in new_ships {
-                    let entity = ships.0.get(&id);
-                    match entity {
-                        Some(entity) => {
-                            let mut tmp = query.get_mut(*entity).unwrap();
-                            tmp.1 .0 = pos.0;
-                            tmp.2 .0 = velocity.0;
+                let simtick = periodic_update.time;
+                time.simtick = simtick;
+                // The ships are replicated by the updates following the loading of the system
+                let Some(ships) = ships.as_deref() else {
+                    continue;
+                };
+                let full = periodic_update.ships.into_iter().map(|ship| (ship, false));
+                let coarse = periodic_update
+                    .coarse_ships
+                    .into_iter()
+                    .map(|ship| (ship, true));
+                for ((id, Position(pos), Velocity(velocity)), coarse) in full.chain(coarse) {
+                    let update = LastServerUpdate { simtick, coarse };
+                    match ships.0.get(&id) {
+                        Some(&entity) => {
+                            let (mut ship_pos, mut ship_velocity, last_update) =
+                                query.get_mut(entity).unwrap();
+                            ship_pos.0 = pos;
+                            ship_velocity.0 = velocity;
+                            match last_update {
+                                Some(mut last_update) => *last_update = update,
+                                None => {
+                                    commands.entity(entity).insert(update);
+                                }
+                            }
+                        }
+                        None => {
+                            ship_events.send(ShipEvent::Replicate(ShipInfo {
+                                id,
+                                spawn_pos: pos,
+                                spawn_speed: velocity,
+                                spawn_orbit: None,
+                            }));
                         }
-                        None => (),
                     }
                 }
             }
-            _ => warn!("message not implemented on client side"),
+        }
+    }
+}
+
+/// Sends the thrusts of the maneuvers the ships of the client went through to the server, which
+/// applies them to its own copy of the ships
+fn send_thrusts(mut thrusts: EventReader<VelocityUpdate>, mut transport: ClientTransport) {
+    for &VelocityUpdate { ship_id, thrust } in thrusts.read() {
+        transport
+            .send(
+                ClientChannel::Once,
+                &ClientMessage::Thrust {
+                    id: ship_id,
+                    dv: thrust,
+                },
+            )
+            .unwrap_or_else(|e| error!("could not send message to the server: {e}"));
+    }
+}
+
+fn send_followed_region(region: Res<FollowedRegion>, mut transport: ClientTransport) {
+    let Some(region) = region.0.clone() else {
+        return;
+    };
+    transport
+        .send(
+            ClientChannel::Once,
+            &ClientMessage::InterestUpdate {
+                focus: region.focus,
+                radius: region.radius,
+                always: region.always,
+            },
+        )
+        .unwrap_or_else(|e| error!("could not send message to the server: {e}"));
+}
+
+fn check_bodies(
+    mut commands: Commands,
+    mut check: ResMut<BodiesCheck>,
+    pending: Res<PendingBodies>,
+    mut transport: ClientTransport,
+    mut next_mode: ResMut<NextState<ClientMode>>,
+) {
+    let hash = bodies_hash(pending.bodies());
+    match *check {
+        BodiesCheck::Waiting => {}
+        BodiesCheck::Pending(expected) | BodiesCheck::Rebuilt(expected) if hash == expected => {
+            commands.remove_resource::<BodiesCheck>();
+        }
+        BodiesCheck::Pending(expected) => {
+            warn!("the bodies differ from the ones of the server, asking for them");
+            transport
+                .send(ClientChannel::Once, &ClientMessage::RequestBodies)
+                .unwrap_or_else(|e| error!("could not send message to the server: {e}"));
+            *check = BodiesCheck::Requested(expected);
+        }
+        BodiesCheck::Requested(_) => {}
+        BodiesCheck::Rebuilt(_) => {
+            let reason =
+                "the bodies still differ from the ones of the server after rebuilding them";
+            error!("{reason}");
+            commands.insert_resource(DisconnectReason(reason.into()));
+            commands.remove_resource::<BodiesCheck>();
+            next_mode.set(ClientMode::None);
         }
     }
 }
@@ -1,29 +1,42 @@
-use bevy::log::LogPlugin;
+use bevy::log::{Level, LogPlugin};
 use bevy::{prelude::*, state::app::StatesPlugin};
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
 use std::{
     fs::create_dir_all,
+    hash::Hash,
     path::{Path, PathBuf},
 };
 
+use loading::{LoadingPhase, LoadingProgress};
 use tempfile::{tempdir, TempDir};
 
 use crate::{
     client::ClientMode,
     objects::{
-        bodies::BodiesPlugin,
+        bodies::{BodiesPlugin, PendingBodies},
         prelude::BodiesMapping,
-        ships::{trajectory::TRAJECTORIES_PATH, ShipsMapping, ShipsPlugin},
-        ObjectsUpdate,
+        ships::{history::HISTORY_PATH, trajectory::TRAJECTORIES_PATH, ShipsMapping, ShipsPlugin},
+        waypoints, ObjectsUpdate,
     },
     physics::{
-        influence::InfluenceUpdate, orbit::OrbitsUpdate, prelude::ToggleTime, PhysicsPlugin,
-        PhysicsUpdate,
+        gravity::GRAVITY_PATH,
+        influence::InfluenceUpdate,
+        orbit::OrbitsUpdate,
+        prelude::{GameTime, Position, ToggleTime, Velocity},
+        PhysicsPlugin, PhysicsUpdate,
     },
+    prelude::{BodyInfo, ShipInfo},
+    throttled,
     ui::gui::GUIUpdate,
+    utils::{hash::hash, log::reloadable_filter_layer},
 };
 
+pub mod budget;
+pub mod loading;
+
 pub mod prelude {
-    pub use super::{GameStage, InGame, Loaded};
+    pub use super::{GameStage, InGame, Loaded, WorldSeed};
 }
 
 pub const GAME_FILES_PATH: &str = "gamefiles";
@@ -42,7 +55,7 @@ impl GamePlugin {
 
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
-        info!("loading GamePlugin");
+        debug!("loading GamePlugin");
         let path = if self.testing {
             let dir = TempDirectory::default();
             let path = dir.0.path().to_owned();
@@ -54,42 +67,58 @@ impl Plugin for GamePlugin {
         if self.testing {
             app.add_plugins((MinimalPlugins, StatesPlugin));
         } else {
+            // The reloadable layer does the filtering, so that it can be changed at runtime
             app.add_plugins(DefaultPlugins.set(LogPlugin {
-                level: bevy::log::Level::DEBUG,
-                filter: "debug,wgpu_core=warn,wgpu_hal=warn".into(),
-                ..Default::default()
+                level: Level::TRACE,
+                filter: String::new(),
+                custom_layer: reloadable_filter_layer,
             }));
         }
-        info!("loading PhysicsPlugin,BodiesPlugin,ShipsPlugin");
-        app.add_plugins((PhysicsPlugin, BodiesPlugin, ShipsPlugin));
+        debug!("loading PhysicsPlugin,BodiesPlugin,ShipsPlugin,waypoints::plugin");
+        app.add_plugins((PhysicsPlugin, BodiesPlugin, ShipsPlugin, waypoints::plugin));
+        debug!("loading budget::plugin");
+        app.add_plugins((budget::plugin, loading::plugin));
 
-        info!("adding InGame state");
+        debug!("adding InGame state");
         app.add_computed_state::<InGame>();
-        info!("adding Authoritative state");
+        debug!("adding Authoritative state");
         app.add_computed_state::<Authoritative>();
-        info!("adding sub state GameStage");
+        debug!("adding sub state GameStage");
         app.add_sub_state::<GameStage>();
-        info!("adding computed_state Loaded");
+        debug!("adding computed_state Loaded");
         app.add_computed_state::<Loaded>();
-        info!("inserting resource GameFiles::new(path).unwrap()");
+        debug!("inserting resource GameFiles::new(path).unwrap()");
         app.insert_resource(GameFiles::new(path).unwrap());
-        info!(
-            "configuring states (ObjectsUpdate, OrbitsUpdate, InfluenceUpdate, GUIUpdate).chain()"
-        );
+        debug!("configuring states (OrbitsUpdate, InfluenceUpdate).chain()");
         app.configure_sets(
-            OnEnter(Loaded),
-            (ObjectsUpdate, OrbitsUpdate, InfluenceUpdate, GUIUpdate).chain(),
+            OnEnter(LoadingPhase::ComputingOrbits),
+            (OrbitsUpdate, InfluenceUpdate).chain(),
         );
-        info!("configuring states ObjectsUpdate.run_if(in_state(Loaded))");
+        debug!("configuring states (OrbitsUpdate, GUIUpdate).chain()");
+        app.configure_sets(OnEnter(Loaded), (OrbitsUpdate, GUIUpdate).chain());
+        debug!("configuring states ObjectsUpdate.run_if(in_state(Loaded))");
         app.configure_sets(Update, ObjectsUpdate.run_if(in_state(Loaded)));
-        info!("configuring states PhysicsUpdate.run_if(in_state(Loaded))");
+        debug!("configuring states PhysicsUpdate.run_if(in_state(Loaded))");
         app.configure_sets(FixedUpdate, PhysicsUpdate.run_if(in_state(Loaded)));
-        info!("adding system clear_loaded");
+        debug!("adding system clear_loaded");
         app.add_systems(OnExit(Loaded), clear_loaded);
-        info!("adding system enable_time");
+        // Loaded is never entered when the loading is cancelled
+        app.add_systems(
+            OnEnter(ClientMode::None),
+            clear_loaded.run_if(resource_exists::<LoadingProgress>),
+        );
+        debug!("adding system enable_time");
         app.add_systems(OnEnter(GameStage::Action), enable_time);
-        info!("adding system disable_time");
-        app.add_systems(OnEnter(GameStage::Preparation), disable_time);
+        debug!("adding system disable_time");
+        // The time is already stopped when the game is loaded, except for the clients joining a
+        // server whose time runs
+        app.add_systems(
+            OnTransition {
+                exited: GameStage::Action,
+                entered: GameStage::Preparation,
+            },
+            disable_time,
+        );
     }
 }
 
@@ -107,21 +136,69 @@ impl Default for TempDirectory {
 pub struct GameFiles {
     pub root: PathBuf,
     pub trajectories: PathBuf,
+    pub history: PathBuf,
+    pub gravity: PathBuf,
 }
 
 impl GameFiles {
     pub fn new(path: impl AsRef<Path>) -> Result<Self, std::io::Error> {
-        info!("creating GameFiles");
+        debug!("creating GameFiles");
         let root: PathBuf = path.as_ref().into();
         let trajectories = root.join(TRAJECTORIES_PATH);
         create_dir_all(trajectories)?;
+        create_dir_all(root.join(HISTORY_PATH))?;
+        create_dir_all(root.join(GRAVITY_PATH))?;
         Ok(Self {
             trajectories: root.join(TRAJECTORIES_PATH),
+            history: root.join(HISTORY_PATH),
+            gravity: root.join(GRAVITY_PATH),
             root,
         })
     }
 }
 
+/// Seed from which all the random generation of a world is derived, so that the same seed and
+/// inputs always produce the same world.
+///
+/// Each subsystem draws from its own child RNG, seeded with the hash of the world seed and a tag
+/// naming the subsystem (see [WorldSeed::rng]), so that adding random draws to one subsystem
+/// doesn't perturb the others
+#[derive(Resource, Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorldSeed(pub u64);
+
+impl WorldSeed {
+    /// Child RNG of the subsystem identified by `tag`, which can be any hashable value
+    /// (e.g. `"belt"` or `("ship_spawn", id)`)
+    pub fn rng(&self, tag: impl Hash) -> StdRng {
+        StdRng::seed_from_u64(hash(&(self.0, tag)))
+    }
+}
+
+/// Checksum of the state of the simulation: the time, and the position and velocity of every
+/// body and ship. Two worlds with the same hash are in the same state, so the hashes of two
+/// servers running the same build can be compared to detect a desync
+pub fn world_hash(world: &mut World) -> u64 {
+    let mut objects: Vec<_> = world
+        .query::<(AnyOf<(&ShipInfo, &BodyInfo)>, &Position, &Velocity)>()
+        .iter(world)
+        .map(|((ship, body), pos, vel)| {
+            let id = match (ship, body) {
+                (Some(ship), _) => (0, ship.id.as_str()),
+                (_, Some(body)) => (1, body.0.id.as_str()),
+                _ => unreachable!(),
+            };
+            (
+                id,
+                pos.0.to_array().map(f64::to_bits),
+                vel.0.to_array().map(f64::to_bits),
+            )
+        })
+        .collect();
+    objects.sort_unstable_by_key(|(id, ..)| *id);
+    let simtick = world.get_resource::<GameTime>().map(|t| t.simtick);
+    hash(&(simtick, objects))
+}
+
 /// This state represents whether the app is running the main game (singleplayer or multiplayer) or not, and is loaded
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct InGame;
@@ -130,7 +207,7 @@ impl ComputedStates for InGame {
     type SourceStates = (Option<ClientMode>, Loaded);
 
     fn compute(sources: Self::SourceStates) -> Option<Self> {
-        info!("computing state InGame");
+        throttled!(Level::INFO, "computing state InGame");
         if !matches!(sources.1, Loaded) {
             None
         } else {
@@ -142,7 +219,7 @@ impl ComputedStates for InGame {
     }
 }
 
-#[derive(SubStates, Debug, Hash, PartialEq, Eq, Clone, Default)]
+#[derive(SubStates, Debug, Hash, PartialEq, Eq, Clone, Default, Serialize, Deserialize)]
 #[source(InGame = InGame)]
 pub enum GameStage {
     #[default]
@@ -165,18 +242,19 @@ impl std::fmt::Display for GameStage {
 
 /// This state represents whether or not bodies and ships are loaded in game.
 /// For server, is is automatically the case, but for a client a system is loaded only if one is connected to a server,
-/// or if the singleplayer or explore modes have been launched
+/// or if the singleplayer or explore modes have been launched. It is entered once all the
+/// [LoadingPhase]s are done
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub struct Loaded;
 
 impl ComputedStates for Loaded {
-    type SourceStates = ClientMode;
+    type SourceStates = LoadingPhase;
 
     fn compute(sources: Self::SourceStates) -> Option<Self> {
-        info!("computing state : Loaded");
+        throttled!(Level::INFO, "computing state : Loaded");
         match sources {
-            ClientMode::None => None,
-            _ => Some(Loaded),
+            LoadingPhase::Done => Some(Loaded),
+            _ => None,
         }
     }
 }
@@ -193,7 +271,7 @@ impl ComputedStates for Authoritative {
     type SourceStates = Option<ClientMode>;
 
     fn compute(sources: Self::SourceStates) -> Option<Self> {
-        info!("computing state : Authoritative");
+        throttled!(Level::INFO, "computing state : Authoritative");
         match sources {
             Some(ClientMode::Singleplayer) | None => Some(Self),
             _ => None,
@@ -208,6 +286,8 @@ fn clear_loaded(mut commands: Commands, query: Query<Entity, With<ClearOnUnload>
     }
     commands.remove_resource::<BodiesMapping>();
     commands.remove_resource::<ShipsMapping>();
+    commands.remove_resource::<PendingBodies>();
+    commands.remove_resource::<LoadingProgress>();
 }
 
 fn enable_time(mut toggle: ResMut<ToggleTime>) {
@@ -223,7 +303,11 @@ fn disable_time(mut toggle: ResMut<ToggleTime>) {
 mod tests {
     use bevy::{app::App, math::DVec3, state::state::State};
 
-    use crate::{objects::ships::ShipEvent, prelude::*};
+    use crate::{
+        objects::ships::ShipEvent, prelude::*, utils::algebra::circular_orbit_around_body,
+    };
+
+    use super::world_hash;
 
     fn new_app() -> App {
         let mut app = App::new();
@@ -238,9 +322,10 @@ mod tests {
         let mut app = new_app();
 
         app.world_mut().send_event(ShipEvent::Create(ShipInfo {
-            id: ShipID::from("s").unwrap(),
+            id: ShipID::new("s").unwrap(),
             spawn_pos: DVec3::new(1e6, 0., 0.),
             spawn_speed: DVec3::new(0., 1e6, 0.),
+            spawn_orbit: None,
         }));
         app.update();
         let world = app.world_mut();
@@ -257,4 +342,50 @@ mod tests {
             GameStage::Preparation
         );
     }
+
+    /// Spawns ships around the Earth at positions drawn from the seed, and returns the hash of the
+    /// resulting world
+    fn seeded_world_hash(seed: u64) -> u64 {
+        let mut app = App::new();
+        app.add_plugins(
+            ClientPlugin::testing()
+                .in_mode(ClientMode::Singleplayer)
+                .with_seed(seed),
+        );
+        app.update();
+        app.update();
+        let world = app.world_mut();
+        let seed = *world.resource::<WorldSeed>();
+        let earth = world.resource::<BodiesMapping>().0["terre"];
+        let (&Mass(mass), &Position(pos), &Velocity(vel)) = world
+            .query::<(&Mass, &Position, &Velocity)>()
+            .get(world, earth)
+            .unwrap();
+        for i in 0..10 {
+            let id = id_from(&format!("s{i}"));
+            let mut rng = seed.rng(("ship_spawn", id));
+            let (spawn_pos, spawn_speed) =
+                circular_orbit_around_body(1e4, mass, pos, vel, &mut rng);
+            world.send_event(ShipEvent::Create(ShipInfo {
+                id,
+                spawn_pos,
+                spawn_speed,
+                spawn_orbit: None,
+            }));
+        }
+        app.update();
+        world_hash(app.world_mut())
+    }
+
+    #[test]
+    fn test_world_seed() {
+        assert_eq!(seeded_world_hash(42), seeded_world_hash(42));
+        assert_ne!(seeded_world_hash(42), seeded_world_hash(43));
+
+        // Child RNGs of different subsystems are independent
+        let seed = WorldSeed(42);
+        let draw = |tag| rand::Rng::gen::<u64>(&mut seed.rng(tag));
+        assert_eq!(draw("belt"), draw("belt"));
+        assert_ne!(draw("belt"), draw("ship_spawn"));
+    }
 }
@@ -0,0 +1,355 @@
+//! Caps on the number of objects a long-running game accumulates, so that memory stays bounded.
+//!
+//! Each kind of object has a soft cap, above which a warning is logged once, and a hard cap,
+//! above which the server acts: the oldest predictions are culled, the acceleration logs are
+//! truncated, and new ships are refused. The counts are checked every [BUDGET_CHECK_INTERVAL],
+//! without going through the whole world: the entities and ships are counted by the ECS and
+//! [ShipsMapping], the predictions by hooks on their component, and only the lengths of the
+//! acceleration logs are summed.
+//!
+//! The ship histories are written to disk by chunks and don't grow in memory, so they have no cap
+
+use std::time::Duration;
+
+use bevy::{
+    ecs::entity::Entities, prelude::*, time::common_conditions::on_real_timer, utils::HashSet,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    objects::ships::ShipsMapping,
+    physics::{leapfrog::AccelerationLog, predictions::Prediction},
+};
+
+/// Time between two checks of the counts against the caps
+pub const BUDGET_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+pub fn plugin(app: &mut App) {
+    app.world_mut()
+        .register_component_hooks::<Prediction>()
+        .on_add(|mut world, _, _| {
+            if let Some(mut counts) = world.get_resource_mut::<BudgetCounts>() {
+                counts.predictions += 1;
+            }
+        })
+        .on_remove(|mut world, _, _| {
+            if let Some(mut counts) = world.get_resource_mut::<BudgetCounts>() {
+                counts.predictions = counts.predictions.saturating_sub(1);
+            }
+        });
+    app.init_resource::<WorldBudget>()
+        .init_resource::<BudgetCounts>()
+        .add_event::<BudgetWarning>()
+        .add_systems(
+            Update,
+            (count_objects, check_budget, enforce_budget)
+                .chain()
+                .run_if(on_real_timer(BUDGET_CHECK_INTERVAL)),
+        );
+}
+
+/// A kind of object whose number is capped
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BudgetKind {
+    Entities,
+    Ships,
+    /// Points of the predicted trajectories of ships
+    Predictions,
+    /// Entries of the [AccelerationLog] of all ships
+    LogEntries,
+}
+
+impl BudgetKind {
+    pub const ALL: [Self; 4] = [
+        Self::Entities,
+        Self::Ships,
+        Self::Predictions,
+        Self::LogEntries,
+    ];
+}
+
+impl std::fmt::Display for BudgetKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Entities => "entities",
+            Self::Ships => "ships",
+            Self::Predictions => "predictions",
+            Self::LogEntries => "log_entries",
+        })
+    }
+}
+
+impl std::str::FromStr for BudgetKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.to_string() == s)
+            .ok_or_else(|| format!("unknown kind of object {s}"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cap {
+    /// A warning is logged above this number
+    pub soft: usize,
+    /// The number is brought back under the soft cap above this one
+    pub hard: usize,
+}
+
+impl Cap {
+    pub const fn new(soft: usize, hard: usize) -> Self {
+        Self { soft, hard }
+    }
+}
+
+/// Soft and hard caps of each kind of object, adjustable with the `budget` command of the server
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct WorldBudget {
+    pub entities: Cap,
+    pub ships: Cap,
+    pub predictions: Cap,
+    pub log_entries: Cap,
+}
+
+impl Default for WorldBudget {
+    fn default() -> Self {
+        Self {
+            entities: Cap::new(100_000, 200_000),
+            ships: Cap::new(1_000, 2_000),
+            predictions: Cap::new(50_000, 100_000),
+            log_entries: Cap::new(5_000_000, 10_000_000),
+        }
+    }
+}
+
+impl WorldBudget {
+    pub fn cap(&self, kind: BudgetKind) -> Cap {
+        match kind {
+            BudgetKind::Entities => self.entities,
+            BudgetKind::Ships => self.ships,
+            BudgetKind::Predictions => self.predictions,
+            BudgetKind::LogEntries => self.log_entries,
+        }
+    }
+
+    pub fn cap_mut(&mut self, kind: BudgetKind) -> &mut Cap {
+        match kind {
+            BudgetKind::Entities => &mut self.entities,
+            BudgetKind::Ships => &mut self.ships,
+            BudgetKind::Predictions => &mut self.predictions,
+            BudgetKind::LogEntries => &mut self.log_entries,
+        }
+    }
+
+    /// Whether a new ship can be spawned in a world with the given numbers of ships and
+    /// entities, or the kind of object whose hard cap is reached
+    pub fn check_spawn(&self, ships: usize, entities: usize) -> Result<(), BudgetKind> {
+        if ships >= self.ships.hard {
+            Err(BudgetKind::Ships)
+        } else if entities >= self.entities.hard {
+            Err(BudgetKind::Entities)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Number of objects of each kind at the last check, except for the predictions which are
+/// counted as they are spawned and despawned
+#[derive(Resource, Default, Debug, Clone, PartialEq)]
+pub struct BudgetCounts {
+    pub entities: usize,
+    pub ships: usize,
+    pub predictions: usize,
+    pub log_entries: usize,
+    /// Kinds above their soft cap, which were already warned about
+    over_soft_cap: HashSet<BudgetKind>,
+}
+
+impl BudgetCounts {
+    pub fn count(&self, kind: BudgetKind) -> usize {
+        match kind {
+            BudgetKind::Entities => self.entities,
+            BudgetKind::Ships => self.ships,
+            BudgetKind::Predictions => self.predictions,
+            BudgetKind::LogEntries => self.log_entries,
+        }
+    }
+}
+
+/// Sent the first time a kind of object goes above its soft cap, and again only after it went
+/// back under it
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BudgetWarning {
+    pub kind: BudgetKind,
+    pub count: usize,
+    pub cap: usize,
+}
+
+fn count_objects(
+    mut counts: ResMut<BudgetCounts>,
+    entities: &Entities,
+    ships: Option<Res<ShipsMapping>>,
+    logs: Query<&AccelerationLog>,
+) {
+    counts.entities = entities.len() as usize;
+    counts.ships = ships.map_or(0, |ships| ships.0.len());
+    // Only the lengths of the logs are read, not their entries
+    counts.log_entries = logs.iter().map(|log| log.entries.len()).sum();
+}
+
+fn check_budget(
+    mut counts: ResMut<BudgetCounts>,
+    budget: Res<WorldBudget>,
+    mut warnings: EventWriter<BudgetWarning>,
+) {
+    for kind in BudgetKind::ALL {
+        let (count, cap) = (counts.count(kind), budget.cap(kind).soft);
+        if count <= cap {
+            counts.over_soft_cap.remove(&kind);
+        } else if counts.over_soft_cap.insert(kind) {
+            warn!("{count} {kind} in the world, above the soft cap of {cap}");
+            warnings.send(BudgetWarning { kind, count, cap });
+        }
+    }
+}
+
+/// Brings the predictions and the log entries back under their soft cap when they are above
+/// their hard cap. Ships and entities are capped by refusing new ships instead
+fn enforce_budget(
+    mut commands: Commands,
+    counts: Res<BudgetCounts>,
+    budget: Res<WorldBudget>,
+    predictions: Query<(Entity, &Prediction)>,
+    mut logs: Query<&mut AccelerationLog>,
+) {
+    if counts.predictions > budget.predictions.hard {
+        warn!(
+            "{} predictions in the world, above the hard cap of {}: culling the oldest ones",
+            counts.predictions, budget.predictions.hard
+        );
+        // The predictions computed together start at the same simtick
+        let mut generations: Vec<_> = predictions
+            .iter()
+            .map(|(entity, p)| (p.simtick - p.index as u64, entity))
+            .collect();
+        generations.sort_unstable();
+        let excess = counts.predictions - budget.predictions.soft;
+        let Some(&(last_culled, _)) = generations.get(excess.saturating_sub(1)) else {
+            return;
+        };
+        for (_, entity) in generations.iter().take_while(|(g, _)| *g <= last_culled) {
+            commands.entity(*entity).despawn();
+        }
+    }
+    if counts.log_entries > budget.log_entries.hard {
+        warn!(
+            "{} acceleration log entries in the world, above the hard cap of {}: truncating them",
+            counts.log_entries, budget.log_entries.hard
+        );
+        let per_log = budget.log_entries.soft / logs.iter().len().max(1);
+        for mut log in logs.iter_mut() {
+            let excess = log.entries.len().saturating_sub(per_log);
+            log.entries.drain(..excess);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::{math::DVec3, prelude::*, time::TimeUpdateStrategy};
+
+    use crate::{
+        network::{
+            testing::{linked_apps, update_linked, ReceivedDenials},
+            PermissionDenied,
+        },
+        physics::predictions::Prediction,
+        prelude::*,
+    };
+
+    use super::{BudgetCounts, BudgetKind, BudgetWarning, Cap, WorldBudget, BUDGET_CHECK_INTERVAL};
+
+    fn spawn_predictions(app: &mut App, simtick: u64, n: usize) {
+        for index in 0..n {
+            app.world_mut().spawn(Prediction {
+                ship: Entity::PLACEHOLDER,
+                index,
+                simtick: simtick + index as u64,
+            });
+        }
+    }
+
+    #[test]
+    fn test_prediction_budget() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer))
+            .insert_resource(TimeUpdateStrategy::ManualDuration(
+                BUDGET_CHECK_INTERVAL + Duration::from_millis(1),
+            ));
+        app.world_mut().resource_mut::<WorldBudget>().predictions = Cap::new(10, 100);
+        app.update();
+        let mut warnings = 0;
+        let mut check = |app: &mut App| {
+            app.update();
+            warnings += app
+                .world_mut()
+                .resource_mut::<Events<BudgetWarning>>()
+                .drain()
+                .filter(|w| w.kind == BudgetKind::Predictions)
+                .count();
+            warnings
+        };
+
+        spawn_predictions(&mut app, 0, 20);
+        assert_eq!(app.world().resource::<BudgetCounts>().predictions, 20);
+        // Staying above the soft cap only warns once
+        for _ in 0..3 {
+            assert_eq!(check(&mut app), 1);
+        }
+
+        // Above the hard cap, the oldest generations are culled until under the soft cap
+        spawn_predictions(&mut app, 100, 80);
+        spawn_predictions(&mut app, 200, 10);
+        check(&mut app);
+        app.update();
+        let world = app.world_mut();
+        assert_eq!(world.resource::<BudgetCounts>().predictions, 10);
+        assert!(world
+            .query::<&Prediction>()
+            .iter(world)
+            .all(|p| p.simtick >= 200));
+        assert_eq!(check(&mut app), 1);
+    }
+
+    #[test]
+    fn test_ship_budget() {
+        let (mut server, mut clients) = linked_apps(1);
+        server.world_mut().resource_mut::<WorldBudget>().ships = Cap::new(1, 1);
+        update_linked(&mut server, &mut clients, 3);
+        for id in ["first", "second"] {
+            clients[0]
+                .world_mut()
+                .send_event(ShipEvent::Create(ShipInfo {
+                    id: id_from(id),
+                    spawn_pos: DVec3::new(1e9, 0., 0.),
+                    ..default()
+                }));
+            update_linked(&mut server, &mut clients, 2);
+        }
+
+        let ships = &server.world().resource::<ShipsMapping>().0;
+        assert_eq!(ships.len(), 1);
+        assert!(ships.contains_key("first"));
+        assert_eq!(
+            clients[0].world().resource::<ReceivedDenials>().0,
+            vec![PermissionDenied::BudgetExceeded(BudgetKind::Ships)]
+        );
+        // The client removed its copy of the refused ship
+        assert_eq!(clients[0].world().resource::<ShipsMapping>().0.len(), 1);
+    }
+}
@@ -0,0 +1,290 @@
+//! A system is loaded in phases, each doing a bounded amount of work per frame, so that systems
+//! with many bodies (like asteroid belts) load across several updates while a loading screen
+//! shows the progress. Small systems still load entirely in the frame their mode is entered.
+
+use bevy::{
+    app::MainScheduleOrder,
+    ecs::{
+        schedule::{ExecutorKind, ScheduleLabel},
+        system::RunSystemOnce,
+    },
+    prelude::*,
+    state::state::StateTransition,
+};
+
+use crate::{
+    client::{BodiesCheck, ClientMode},
+    objects::bodies::{spawn_pending_bodies, BodiesMapping, PendingBodies},
+};
+
+/// Default number of bodies spawned per frame while loading
+pub const DEFAULT_LOADING_CHUNK_SIZE: usize = 1000;
+
+/// The phases of the loading of a system, in order. The work of a phase is done by the systems
+/// running when it is entered, except for [LoadingPhase::SpawningBodies] whose bodies are
+/// spawned by chunks of [LoadingChunkSize]. [LoadingPhase::ComputingOrbits] goes through all the
+/// bodies at once, which counts for one body each in the budget of the frame, so it waits for the
+/// next frame when they don't fit in what is left of it. [LoadingPhase::SpawningShips] only
+/// creates the empty [crate::prelude::ShipsMapping], the ships being created by the players or
+/// replicated from the server afterwards. [crate::game::Loaded] is entered at
+/// [LoadingPhase::Done]. In multiplayer, the loading waits in [LoadingPhase::ReadingBodies] until
+/// the bodies read are the ones of the server, see [BodiesCheck]
+#[derive(SubStates, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
+#[source(ClientMode = ClientMode::Singleplayer | ClientMode::Multiplayer | ClientMode::Explorer | ClientMode::Server)]
+pub enum LoadingPhase {
+    #[default]
+    ReadingBodies,
+    SpawningBodies,
+    ComputingOrbits,
+    SpawningShips,
+    Done,
+}
+
+impl LoadingPhase {
+    pub fn next(self) -> Self {
+        match self {
+            Self::ReadingBodies => Self::SpawningBodies,
+            Self::SpawningBodies => Self::ComputingOrbits,
+            Self::ComputingOrbits => Self::SpawningShips,
+            Self::SpawningShips | Self::Done => Self::Done,
+        }
+    }
+}
+
+impl std::fmt::Display for LoadingPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::ReadingBodies => "Reading bodies",
+                Self::SpawningBodies => "Spawning bodies",
+                Self::ComputingOrbits => "Computing orbits",
+                Self::SpawningShips => "Spawning ships",
+                Self::Done => "Done",
+            }
+        )
+    }
+}
+
+/// Progress of the loading of a system, which only exists while it loads
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct LoadingProgress {
+    pub phase: LoadingPhase,
+    /// Work done in the current phase, out of `total`
+    pub done: usize,
+    pub total: usize,
+}
+
+impl LoadingProgress {
+    /// Fraction of the whole loading done, each phase counting for the same share
+    pub fn fraction(&self) -> f64 {
+        let phases = LoadingPhase::Done as usize as f64;
+        let in_phase = if self.total == 0 {
+            1.
+        } else {
+            self.done as f64 / self.total as f64
+        };
+        ((self.phase as usize as f64 + in_phase) / phases).min(1.)
+    }
+}
+
+/// Maximum number of bodies spawned per frame while loading
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadingChunkSize(pub usize);
+
+impl Default for LoadingChunkSize {
+    fn default() -> Self {
+        Self(DEFAULT_LOADING_CHUNK_SIZE)
+    }
+}
+
+/// Runs right after [StateTransition], so that the phases entered in a frame do their work in
+/// this same frame
+#[derive(ScheduleLabel, Debug, Hash, PartialEq, Eq, Clone)]
+pub struct LoadingStep;
+
+pub fn plugin(app: &mut App) {
+    let mut schedule = Schedule::new(LoadingStep);
+    schedule.set_executor_kind(ExecutorKind::SingleThreaded);
+    app.add_sub_state::<LoadingPhase>()
+        .init_resource::<LoadingChunkSize>()
+        .add_schedule(schedule)
+        .add_systems(OnEnter(LoadingPhase::ReadingBodies), start_loading)
+        .add_systems(LoadingStep, advance_loading);
+    app.world_mut()
+        .resource_mut::<MainScheduleOrder>()
+        .insert_after(StateTransition, LoadingStep);
+}
+
+fn start_loading(mut commands: Commands) {
+    info!("loading the system");
+    commands.insert_resource(LoadingProgress {
+        phase: LoadingPhase::ReadingBodies,
+        done: 0,
+        total: 1,
+    });
+}
+
+/// Goes through the phases as long as their work fits in the budget of this frame
+fn advance_loading(world: &mut World) {
+    let chunk = world.resource::<LoadingChunkSize>().0.max(1);
+    let mut budget = chunk;
+    while let Some(&phase) = world.get_resource::<State<LoadingPhase>>().map(State::get) {
+        let (done, total) = match phase {
+            LoadingPhase::Done => {
+                world.remove_resource::<LoadingProgress>();
+                return;
+            }
+            LoadingPhase::ReadingBodies if world.contains_resource::<BodiesCheck>() => return,
+            LoadingPhase::SpawningBodies => {
+                budget -= world.run_system_once_with(budget, spawn_pending_bodies);
+                let pending = world.resource::<PendingBodies>();
+                let (remaining, total) = (pending.remaining(), pending.total());
+                if remaining > 0 {
+                    world.insert_resource(LoadingProgress {
+                        phase,
+                        done: total - remaining,
+                        total,
+                    });
+                    return;
+                }
+                (total, total)
+            }
+            _ => (1, 1),
+        };
+        world.insert_resource(LoadingProgress { phase, done, total });
+        let cost = match phase.next() {
+            LoadingPhase::ComputingOrbits => world
+                .get_resource::<BodiesMapping>()
+                .map_or(0, |mapping| mapping.0.len()),
+            _ => 0,
+        };
+        // A phase larger than a whole budget still has to be done in a single frame
+        if cost > budget && budget < chunk {
+            return;
+        }
+        budget = budget.saturating_sub(cost);
+        if phase == LoadingPhase::SpawningBodies {
+            world.remove_resource::<PendingBodies>();
+        }
+        debug!("loading phase {phase} done");
+        world
+            .resource_mut::<NextState<LoadingPhase>>()
+            .set(phase.next());
+        world.run_schedule(StateTransition);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        app::App,
+        state::state::{NextState, State},
+    };
+
+    use crate::{
+        objects::{bodies::body_data::BodyDataBuilder, prelude::BodiesConfig},
+        prelude::*,
+    };
+
+    use super::{LoadingChunkSize, LoadingPhase, LoadingProgress};
+
+    fn belt(count: usize) -> Vec<BodyData> {
+        let sun = id_from("soleil");
+        let asteroids: Vec<_> = (0..count).map(|i| id_from(&format!("a{i}"))).collect();
+        let mut bodies = vec![BodyDataBuilder::new(sun)
+            .with_mass(1.989e30)
+            .with_radius(696_000.)
+            .with_moons(asteroids.clone())
+            .build()];
+        bodies.extend(asteroids.into_iter().enumerate().map(|(i, id)| {
+            BodyDataBuilder::new(id)
+                .orbiting(sun)
+                .with_mass(1e15)
+                .with_radius(10.)
+                .with_semimajor_axis(4e8 + i as f64 * 1e4)
+                .with_mean_anomaly(i as f64)
+                .with_revolution_period(1500.)
+                .build()
+        }));
+        bodies
+    }
+
+    fn belt_app(count: usize, chunk: usize) -> App {
+        let mut app = App::new();
+        app.add_plugins(
+            ClientPlugin::testing()
+                .in_mode(ClientMode::Singleplayer)
+                .with_bodies(BodiesConfig::Custom(belt(count))),
+        );
+        app.insert_resource(LoadingChunkSize(chunk));
+        app
+    }
+
+    fn body_count(app: &mut App) -> usize {
+        let world = app.world_mut();
+        world.query::<&BodyInfo>().iter(world).len()
+    }
+
+    #[test]
+    fn test_chunked_loading() {
+        let mut app = belt_app(5000, 700);
+        let mut fractions = Vec::new();
+        let mut bodies = 0;
+        for _ in 0..20 {
+            app.update();
+            let spawned = body_count(&mut app);
+            assert!(spawned - bodies <= 700, "{spawned} after {bodies}");
+            bodies = spawned;
+            match app.world().get_resource::<LoadingProgress>() {
+                Some(progress) => {
+                    assert_eq!(progress.phase, LoadingPhase::SpawningBodies);
+                    assert_eq!(progress.done, bodies);
+                    assert!(!app.world().contains_resource::<State<Loaded>>());
+                    fractions.push(progress.fraction());
+                }
+                None => break,
+            }
+        }
+        assert_eq!(bodies, 5001);
+        // The orbits of all the bodies are computed on the frame after the last ones are spawned
+        assert_eq!(fractions.len(), 8);
+        assert_eq!(fractions.last(), Some(&0.5));
+        assert!(fractions.windows(2).all(|w| w[0] < w[1]), "{fractions:?}");
+        assert_eq!(
+            app.world().resource::<State<LoadingPhase>>().get(),
+            &LoadingPhase::Done
+        );
+        assert!(app.world().contains_resource::<State<InGame>>());
+        assert!(app.world().contains_resource::<ShipsMapping>());
+        assert_eq!(app.world().resource::<BodiesMapping>().0.len(), 5001);
+    }
+
+    #[test]
+    fn test_small_system_loads_at_once() {
+        let mut app = belt_app(10, 700);
+        app.update();
+        assert!(app.world().contains_resource::<State<Loaded>>());
+        assert!(!app.world().contains_resource::<LoadingProgress>());
+        assert_eq!(body_count(&mut app), 11);
+    }
+
+    #[test]
+    fn test_cancel_loading() {
+        let mut app = belt_app(5000, 700);
+        app.update();
+        app.update();
+        assert!(app.world().contains_resource::<LoadingProgress>());
+        app.world_mut()
+            .resource_mut::<NextState<ClientMode>>()
+            .set(ClientMode::None);
+        app.update();
+        assert_eq!(body_count(&mut app), 0);
+        assert!(!app.world().contains_resource::<LoadingProgress>());
+        assert!(!app.world().contains_resource::<BodiesMapping>());
+        assert!(!app.world().contains_resource::<State<LoadingPhase>>());
+        app.update();
+        assert_eq!(body_count(&mut app), 0);
+    }
+}
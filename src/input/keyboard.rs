@@ -15,12 +15,16 @@ pub struct Keymap {
     pub start_menu: StartMenuKeymap,
     pub fleet_screen: FleetScreenKeymap,
     pub editor: EditorKeymap,
+    pub bodies_editor: BodiesEditorKeymap,
+    pub inspector: InspectorKeymap,
+    pub gui: GuiKeymap,
 }
 
 #[derive(Default, Debug, Serialize, Deserialize, Clone)]
 pub struct ExplorerKeymap {
     pub tree: TreeViewKeymap,
     pub search: SearchViewKeymap,
+    pub waypoints: WaypointsKeymap,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -29,6 +33,10 @@ pub struct StartMenuKeymap {
     pub select_previous: Key,
     pub quit: Key,
     pub validate: Key,
+    pub cycle_options: Key,
+    pub delete_char: Key,
+    /// Cancels the loading of a system
+    pub back: Key,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -43,6 +51,13 @@ pub struct FleetScreenKeymap {
     pub validate_new_ship: Key,
     pub delete_char: Key,
     pub enter_explorer: Key,
+    pub switch_info_tab: Key,
+    pub toggle_profiler: Key,
+    pub toggle_recording: Key,
+    pub export_history: Key,
+    pub follow: Key,
+    pub autopilot: Key,
+    pub inspect: Key,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -52,6 +67,39 @@ pub struct EditorKeymap {
     pub back: Key,
     pub remove_node: Key,
     pub new_node: Key,
+    pub cycle_approach_target: Key,
+    pub plan_plane_change: Key,
+    pub toggle_aerobrake: Key,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BodiesEditorKeymap {
+    pub select_next: Key,
+    pub select_previous: Key,
+    pub cycle_options: Key,
+    pub cycle_options_back: Key,
+    pub delete_char: Key,
+    pub apply: Key,
+    pub save: Key,
+    pub revert: Key,
+    pub back: Key,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InspectorKeymap {
+    pub scroll_down: Key,
+    pub scroll_up: Key,
+    pub enter_search: Key,
+    pub leave_search: Key,
+    pub delete_char: Key,
+    pub back: Key,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GuiKeymap {
+    pub toggle_ecliptic_grid: Key,
+    pub toggle_equatorial_planes: Key,
+    pub toggle_orbit_axes: Key,
 }
 
 impl Keymap {
@@ -94,8 +142,15 @@ pub struct TreeViewKeymap {
     pub back: Key,
     pub speed_up: Key,
     pub slow_down: Key,
+    pub raise_max_speed: Key,
+    pub lower_max_speed: Key,
     pub toggle_time: Key,
     pub toggle_info: Key,
+    pub cycle_labels: Key,
+    pub follow: Key,
+    pub edit_bodies: Key,
+    pub inspect: Key,
+    pub waypoints: Key,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -109,6 +164,19 @@ pub struct SearchViewKeymap {
     pub delete_char: Key,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WaypointsKeymap {
+    pub select_next: Key,
+    pub select_previous: Key,
+    pub cycle_options: Key,
+    pub cycle_options_back: Key,
+    pub delete_char: Key,
+    pub add: Key,
+    pub remove: Key,
+    pub goto: Key,
+    pub back: Key,
+}
+
 impl Default for TreeViewKeymap {
     fn default() -> Self {
         Self {
@@ -129,7 +197,14 @@ impl Default for TreeViewKeymap {
             back: Key::from_str_unchecked("esc"),
             speed_up: Key::from_str_unchecked(">"),
             slow_down: Key::from_str_unchecked("<"),
+            raise_max_speed: Key::from_str_unchecked("]"),
+            lower_max_speed: Key::from_str_unchecked("["),
             toggle_time: Key::from_str_unchecked("t"),
+            cycle_labels: Key::from_str_unchecked("l"),
+            follow: Key::from_str_unchecked("v"),
+            edit_bodies: Key::from_str_unchecked("e"),
+            inspect: Key::from_str_unchecked("c"),
+            waypoints: Key::from_str_unchecked("p"),
         }
     }
 }
@@ -148,6 +223,22 @@ impl Default for SearchViewKeymap {
     }
 }
 
+impl Default for WaypointsKeymap {
+    fn default() -> Self {
+        Self {
+            select_next: Key::from_str_unchecked("down"),
+            select_previous: Key::from_str_unchecked("up"),
+            cycle_options: Key::from_str_unchecked("tab"),
+            cycle_options_back: Key::from_str_unchecked("S backtab"),
+            delete_char: Key::from_str_unchecked("backspace"),
+            add: Key::from_str_unchecked("enter"),
+            remove: Key::from_str_unchecked("C d"),
+            goto: Key::from_str_unchecked("C g"),
+            back: Key::from_str_unchecked("esc"),
+        }
+    }
+}
+
 impl Default for StartMenuKeymap {
     fn default() -> Self {
         Self {
@@ -155,6 +246,9 @@ impl Default for StartMenuKeymap {
             select_previous: Key::from_str_unchecked("up"),
             quit: Key::from_str_unchecked("esc"),
             validate: Key::from_str_unchecked("space"),
+            cycle_options: Key::from_str_unchecked("tab"),
+            delete_char: Key::from_str_unchecked("backspace"),
+            back: Key::from_str_unchecked("esc"),
         }
     }
 }
@@ -172,6 +266,13 @@ impl Default for FleetScreenKeymap {
             validate_new_ship: Key::from_str_unchecked("enter"),
             delete_char: Key::from_str_unchecked("backspace"),
             enter_explorer: Key::from_str_unchecked("e"),
+            switch_info_tab: Key::from_str_unchecked("i"),
+            toggle_profiler: Key::from_str_unchecked("p"),
+            toggle_recording: Key::from_str_unchecked("r"),
+            export_history: Key::from_str_unchecked("x"),
+            follow: Key::from_str_unchecked("f"),
+            autopilot: Key::from_str_unchecked("a"),
+            inspect: Key::from_str_unchecked("c"),
         }
     }
 }
@@ -184,6 +285,48 @@ impl Default for EditorKeymap {
             back: Key::from_str_unchecked("esc"),
             new_node: Key::from_str_unchecked("n"),
             remove_node: Key::from_str_unchecked("backspace"),
+            cycle_approach_target: Key::from_str_unchecked("t"),
+            plan_plane_change: Key::from_str_unchecked("i"),
+            toggle_aerobrake: Key::from_str_unchecked("b"),
+        }
+    }
+}
+
+impl Default for BodiesEditorKeymap {
+    fn default() -> Self {
+        Self {
+            select_next: Key::from_str_unchecked("down"),
+            select_previous: Key::from_str_unchecked("up"),
+            cycle_options: Key::from_str_unchecked("tab"),
+            cycle_options_back: Key::from_str_unchecked("S backtab"),
+            delete_char: Key::from_str_unchecked("backspace"),
+            apply: Key::from_str_unchecked("enter"),
+            save: Key::from_str_unchecked("C s"),
+            revert: Key::from_str_unchecked("C r"),
+            back: Key::from_str_unchecked("esc"),
+        }
+    }
+}
+
+impl Default for InspectorKeymap {
+    fn default() -> Self {
+        Self {
+            scroll_down: Key::from_str_unchecked("down"),
+            scroll_up: Key::from_str_unchecked("up"),
+            enter_search: Key::from_str_unchecked("/"),
+            leave_search: Key::from_str_unchecked("enter"),
+            delete_char: Key::from_str_unchecked("backspace"),
+            back: Key::from_str_unchecked("esc"),
+        }
+    }
+}
+
+impl Default for GuiKeymap {
+    fn default() -> Self {
+        Self {
+            toggle_ecliptic_grid: Key::from_str_unchecked("f2"),
+            toggle_equatorial_planes: Key::from_str_unchecked("f3"),
+            toggle_orbit_axes: Key::from_str_unchecked("f4"),
         }
     }
 }
@@ -3,11 +3,21 @@ use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use bevy_quinnet::shared::channels::{ChannelId, ChannelType, ChannelsConfiguration};
 use serde::{Deserialize, Serialize};
 
+use bevy::math::DVec3;
+
+use crate::game::{budget::BudgetKind, GameStage, WorldSeed};
+use crate::objects::bodies::{body_data::BodyData, BodyID};
 use crate::objects::prelude::CreateShipMsg;
 use crate::objects::prelude::ShipID;
+use crate::objects::ships::trajectory::ManeuverNode;
 use crate::physics::prelude::Position;
 use crate::physics::Velocity;
 use crate::prelude::BodiesConfig;
+use crate::utils::algebra::OrbitSpawnError;
+
+pub mod testing;
+pub mod time_sync;
+pub mod transport;
 
 pub const SERVER_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 5000);
 pub const CLIENT_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0);
@@ -19,20 +29,159 @@ pub enum ServerMessage {
     ToggleTime(bool),
     InitialData(InitialData),
     PeriodicUpdate(PeriodicUpdate),
+    ShipCreateRejected {
+        id: ShipID,
+        reason: ShipRejectionReason,
+    },
+    /// Role of the client, sent once the connection is established and whenever it changes
+    RoleAssigned(Role),
+    /// An action requested by the client was refused
+    Denied(PermissionDenied),
+    ShipRemoved(ShipID),
+    /// A ship created with a generated id was given another id by the server
+    ShipRenamed {
+        from: ShipID,
+        to: ShipID,
+    },
+    ChangeStage(GameStage),
+    /// Answer to [ClientMessage::SandboxManeuver], with the simticks and positions of the samples
+    ManeuverPreview {
+        ship: ShipID,
+        positions: Vec<(u64, Position)>,
+        fuel_remaining: f64,
+    },
+    /// The server closed the connection of the client, with the reason given by the server admin
+    Kicked(String),
+    /// The server refused the client at handshake, see [ClientMessage::Hello]
+    Rejected(String),
+    /// Answer to [ClientMessage::RequestBodies], with the data of all the bodies of the server
+    Bodies(Vec<BodyData>),
+    /// Answer to [ClientMessage::TimeSyncRequest], sent as soon as it is received
+    TimeSyncResponse {
+        client_local_tick: u64,
+        server_tick: u64,
+        server_time_ns: u64,
+    },
+}
+
+/// Why the server refused to create a ship requested by a client
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ShipRejectionReason {
+    AlreadyExists,
+    /// Another ship created along with this one was rejected, and ships created together are
+    /// accepted or rejected as a whole
+    BatchRejected,
+    Denied(PermissionDenied),
+    /// The ship was spawned on an orbit around a body that the server doesn't allow
+    InvalidOrbit(OrbitSpawnError),
+}
+
+impl std::fmt::Display for ShipRejectionReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::AlreadyExists => f.write_str("a ship with this id already exists"),
+            Self::BatchRejected => f.write_str("a ship created along with this one was rejected"),
+            Self::Denied(reason) => reason.fmt(f),
+            Self::InvalidOrbit(error) => error.fmt(f),
+        }
+    }
+}
+
+/// What a client is allowed to do on the server
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Role {
+    /// Can control time, the game stage and every ship
+    Admin,
+    /// Can create ships and control its own ships
+    #[default]
+    Player,
+    /// Can only watch
+    Spectator,
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Admin => "admin",
+            Self::Player => "player",
+            Self::Spectator => "spectator",
+        })
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Self::Admin),
+            "player" => Ok(Self::Player),
+            "spectator" => Ok(Self::Spectator),
+            _ => Err(format!("unknown role {s}")),
+        }
+    }
+}
+
+/// The permission a client lacked to perform an action
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PermissionDenied {
+    /// Spectators can't act on the game
+    Spectator,
+    /// The ship belongs to another client
+    NotOwner(ShipID),
+    /// Only admins can perform this action
+    AdminOnly,
+    /// The server reached the hard cap of its [crate::game::budget::WorldBudget] for this kind of object
+    BudgetExceeded(BudgetKind),
+}
+
+impl std::fmt::Display for PermissionDenied {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Spectator => f.write_str("spectators can't act on the game"),
+            Self::NotOwner(id) => write!(f, "ship {id} belongs to another player"),
+            Self::AdminOnly => f.write_str("only admins can do this"),
+            Self::BudgetExceeded(kind) => write!(f, "the server can't hold more {kind}"),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct PeriodicUpdate {
     pub time: u64,
     pub ships: Vec<(ShipID, Position, Velocity)>,
+    /// Ships outside the [InterestRegion] of the client, sent every few updates only
+    pub coarse_ships: Vec<(ShipID, Position, Velocity)>,
+}
+
+/// Center of the region of the system a client follows closely
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum InterestFocus {
+    Body(BodyID),
+    Position(DVec3),
+}
+
+/// Region of the system whose ships a client gets at the full rate of the periodic updates, see
+/// [ClientMessage::InterestUpdate]. The client always gets its own ships at full rate too
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InterestRegion {
+    pub focus: InterestFocus,
+    /// Distance to the focus (in km) under which ships are in the region
+    pub radius: f64,
+    /// Ships followed closely wherever they are
+    pub always: Vec<ShipID>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct InitialData {
     pub bodies_config: BodiesConfig,
+    /// [crate::objects::bodies::bodies_hash] of the bodies of the server
+    pub bodies_hash: u64,
     pub toggle_time: bool,
+    pub seed: WorldSeed,
 }
 
+#[derive(Clone, Copy)]
 #[repr(u8)]
 pub enum ServerChannel {
     Once,
@@ -54,6 +203,7 @@ impl ServerChannel {
     }
 }
 
+#[derive(Clone, Copy)]
 #[repr(u8)]
 pub enum ClientChannel {
     Once,
@@ -77,5 +227,42 @@ impl ClientChannel {
 
 #[derive(Serialize, Deserialize)]
 pub enum ClientMessage {
+    /// First message of a client, naming the player
+    Hello {
+        name: String,
+    },
     CreateShipMsg(CreateShipMsg),
+    /// Ships created together, such as a constellation, which the server accepts or rejects as a
+    /// whole
+    CreateShips(Vec<CreateShipMsg>),
+    /// Instantaneous change of velocity of a ship (in km/day), like a maneuver node the client
+    /// went through
+    Thrust {
+        id: ShipID,
+        dv: DVec3,
+    },
+    /// Starts or stops the action, which only admins can do
+    ChangeStage(GameStage),
+    /// Asks the server to simulate the ship following the given maneuver nodes (indexed by tick)
+    /// during `preview_ticks` ticks, without changing its actual trajectory
+    SandboxManeuver {
+        ship: ShipID,
+        nodes: Vec<(u64, ManeuverNode)>,
+        preview_ticks: u64,
+    },
+    /// Asks for the data of the bodies of the server, when the client built other bodies
+    RequestBodies,
+    /// Ping measuring the offset between the simticks of the client and the server, see
+    /// [time_sync]
+    TimeSyncRequest {
+        client_local_tick: u64,
+        send_time_ns: u64,
+    },
+    /// Restricts the ships sent at full rate to the client to those of an [InterestRegion]. The
+    /// other ones are only sent every few updates, with less precision
+    InterestUpdate {
+        focus: InterestFocus,
+        radius: f64,
+        always: Vec<ShipID>,
+    },
 }
@@ -0,0 +1,550 @@
+//! A server and its clients in the same process, linked by a [LoopbackServer], to test the
+//! multiplayer game without opening ports.
+//!
+//! The helpers compose into scripted sessions: [spawn_server], then [connect_client] for each
+//! player, [run_ticks] to play, and [assert_synced] to check that everyone agrees on the state
+use std::time::Duration;
+
+use bevy::{ecs::system::RunSystemOnce, prelude::*, time::TimeUpdateStrategy};
+
+use crate::{
+    client::{ActionDenied, ClientMode, ClientPlugin, SyncStatus},
+    game::{world_hash, GameStage},
+    physics::{
+        prelude::{GameTime, ToggleTime},
+        time::STPS,
+    },
+    server::ServerPlugin,
+};
+
+use super::{
+    transport::{
+        ClientMessageTransport, ClientTransport, LoopbackServer, MessageTransport, ServerTransport,
+        TransportKind,
+    },
+    ClientChannel, ClientMessage, PermissionDenied, ServerChannel, ServerMessage,
+};
+
+/// Time elapsed at each update of the linked apps, so that each update runs one physics step
+pub const LINKED_UPDATE_STEP: Duration = Duration::from_nanos((1e9 / STPS) as u64);
+
+/// Updates after which [run_ticks] gives up if the simtick doesn't reach its target
+const MAX_UPDATES_PER_TICK: u64 = 4;
+
+/// Permissions the server denied to a client, in the order they were received
+#[derive(Resource, Default, Debug, Clone, PartialEq, Eq)]
+pub struct ReceivedDenials(pub Vec<PermissionDenied>);
+
+fn record_denials(mut reader: EventReader<ActionDenied>, mut denials: ResMut<ReceivedDenials>) {
+    denials
+        .0
+        .extend(reader.read().map(|ActionDenied(reason)| *reason));
+}
+
+/// A server app on a loopback transport, which clients join with [connect_client]
+pub fn spawn_server() -> App {
+    spawn_server_with(ServerPlugin::testing())
+}
+
+/// A server app built from the given plugin, on a loopback transport
+pub fn spawn_server_with(plugin: ServerPlugin) -> App {
+    let mut server = App::new();
+    server
+        .add_plugins(plugin)
+        .init_resource::<LoopbackServer>()
+        .insert_resource(TimeUpdateStrategy::ManualDuration(LINKED_UPDATE_STEP));
+    server
+}
+
+/// A client app in multiplayer, connected to the server under the given name. It gets the next
+/// client id of the server, and is synced after the next couple of updates
+pub fn connect_client(server: &mut App, name: impl Into<String>) -> App {
+    let mut app = App::new();
+    app.add_plugins(
+        ClientPlugin::testing()
+            .in_mode(ClientMode::Multiplayer)
+            .with_transport(TransportKind::Loopback)
+            .with_player_name(name),
+    )
+    .insert_resource(
+        server
+            .world_mut()
+            .resource_mut::<LoopbackServer>()
+            .connect(),
+    )
+    .insert_resource(TimeUpdateStrategy::ManualDuration(LINKED_UPDATE_STEP))
+    .init_resource::<ReceivedDenials>()
+    .add_systems(Update, record_denials);
+    app
+}
+
+/// Closes the connection of the client, which leaves the game and unloads it
+pub fn disconnect_client(client: &mut App) {
+    client
+        .world_mut()
+        .resource_mut::<NextState<ClientMode>>()
+        .set(ClientMode::None);
+    client.update();
+}
+
+/// Connects a client closed by [disconnect_client] again, as a new client of the server
+pub fn reconnect_client(server: &mut App, client: &mut App) {
+    let link = server
+        .world_mut()
+        .resource_mut::<LoopbackServer>()
+        .connect();
+    client.insert_resource(link);
+    client
+        .world_mut()
+        .resource_mut::<NextState<ClientMode>>()
+        .set(ClientMode::Multiplayer);
+}
+
+/// A server app and `n_clients` client apps in multiplayer, already connected to it.
+/// Client `i` is named `player{i}`, and gets the client id `i + 1` on the server
+pub fn linked_apps(n_clients: usize) -> (App, Vec<App>) {
+    let mut server = spawn_server();
+    let clients = (0..n_clients)
+        .map(|i| connect_client(&mut server, format!("player{i}")))
+        .collect();
+    (server, clients)
+}
+
+/// Updates the server then each client, `n` times
+pub fn update_linked(server: &mut App, clients: &mut [App], n: usize) {
+    for _ in 0..n {
+        server.update();
+        for client in clients.iter_mut() {
+            client.update();
+        }
+    }
+}
+
+/// Updates the server and the clients until the simtick of the server advanced by `ticks`.
+/// Panics if the time doesn't run on the server
+pub fn run_ticks(server: &mut App, clients: &mut [App], ticks: u64) {
+    let simtick = |server: &App| server.world().resource::<GameTime>().simtick;
+    let target = simtick(server) + ticks;
+    for _ in 0..ticks * MAX_UPDATES_PER_TICK {
+        if simtick(server) >= target {
+            return;
+        }
+        update_linked(server, clients, 1);
+    }
+    panic!(
+        "the server is at simtick {} instead of {target}, is the time running?",
+        simtick(server)
+    );
+}
+
+/// Whether every client received the initial data from the server
+pub fn all_synced(clients: &[App]) -> bool {
+    clients.iter().all(|client| {
+        client
+            .world()
+            .get_resource::<State<SyncStatus>>()
+            .is_some_and(|status| *status.get() == SyncStatus::Synced)
+    })
+}
+
+/// Sends a message from the server to all the clients
+pub fn broadcast(server: &mut App, message: ServerMessage) {
+    server
+        .world_mut()
+        .run_system_once(move |mut transport: ServerTransport| {
+            transport
+                .broadcast(ServerChannel::Once, &message)
+                .expect("could not broadcast the message");
+        });
+}
+
+/// Sends a message from the client to the server, as if the player did the matching action
+pub fn send_to_server(client: &mut App, message: ClientMessage) {
+    client
+        .world_mut()
+        .run_system_once(move |mut transport: ClientTransport| {
+            transport
+                .send(ClientChannel::Once, &message)
+                .expect("could not send the message");
+        });
+}
+
+/// Starts or stops the time on the server and the clients, as the `toggle_time` command does
+pub fn set_time_running(server: &mut App, running: bool) {
+    server.world_mut().resource_mut::<ToggleTime>().0 = running;
+    broadcast(server, ServerMessage::ToggleTime(running));
+}
+
+/// Moves every client to the given stage of the game
+pub fn change_stage(server: &mut App, stage: GameStage) {
+    broadcast(server, ServerMessage::ChangeStage(stage));
+}
+
+/// Stops the time, lets the last periodic update reach the clients, and asserts that the server
+/// and every client have the same [world_hash], which is returned. The time stays stopped
+pub fn assert_synced(server: &mut App, clients: &mut [App]) -> u64 {
+    set_time_running(server, false);
+    update_linked(server, clients, 3);
+    let expected = world_hash(server.world_mut());
+    for (i, client) in clients.iter_mut().enumerate() {
+        assert_eq!(
+            world_hash(client.world_mut()),
+            expected,
+            "client {i} is not in the same state as the server"
+        );
+    }
+    expected
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{math::DVec3, prelude::*};
+
+    use crate::{
+        client::{BodiesCheck, ClientMode, ClientRole, SyncStatus},
+        game::{loading::LoadingPhase, Loaded, WorldSeed},
+        network::{time_sync::ClockSync, ClientMessage, Role},
+        objects::{
+            bodies::PendingBodies,
+            ships::{trajectory::VelocityUpdate, SpawnOrbit},
+        },
+        physics::{
+            prelude::{GameTime, Position, ToggleTime, Velocity},
+            time::{MaxSimSpeed, TickRateTracker, SIMTICKS_PER_TICK, STPS},
+        },
+        prelude::{
+            id_from, BodiesConfig, BodiesMapping, BodyType, CreateShipMsg, ShipEvent, ShipInfo,
+            ShipsMapping,
+        },
+        server::{ClientNames, ServerPlugin, ShipOwners, SPEED_LIMIT_BROADCAST_DELAY},
+    };
+
+    use super::{
+        assert_synced, connect_client, linked_apps, send_to_server, set_time_running,
+        spawn_server_with, update_linked,
+    };
+
+    #[test]
+    fn test_full_sync() {
+        let (mut server, mut clients) = linked_apps(2);
+        server.insert_resource(WorldSeed(42));
+        update_linked(&mut server, &mut clients, 3);
+
+        for client in &clients {
+            assert_eq!(
+                client.world().resource::<State<SyncStatus>>().get(),
+                &SyncStatus::Synced
+            );
+            assert_eq!(client.world().resource::<WorldSeed>(), &WorldSeed(42));
+            assert_eq!(client.world().resource::<ClientRole>().0, Role::Player);
+        }
+        // The clients introduced themselves after receiving the initial data
+        let names = &server.world().resource::<ClientNames>().0;
+        assert_eq!(names.get(&1).map(String::as_str), Some("player0"));
+        assert_eq!(names.get(&2).map(String::as_str), Some("player1"));
+    }
+
+    #[test]
+    fn test_max_sim_speed_holds_clients() {
+        let (mut server, mut clients) = linked_apps(2);
+        update_linked(&mut server, &mut clients, 3);
+        server
+            .world_mut()
+            .resource_mut::<MaxSimSpeed>()
+            .ticks_per_second = Some(1);
+        set_time_running(&mut server, true);
+
+        // Each update runs a simtick, so the limit is reached within the second
+        update_linked(&mut server, &mut clients, 2 * SIMTICKS_PER_TICK as usize);
+        assert!(server.world().resource::<TickRateTracker>().throttling());
+        assert!(server.world().resource::<ToggleTime>().0);
+        let held = server.world().resource::<GameTime>().simtick;
+        update_linked(&mut server, &mut clients, broadcast_delay_updates());
+        assert_eq!(server.world().resource::<GameTime>().simtick, held);
+        for client in &clients {
+            assert!(!client.world().resource::<ToggleTime>().0);
+        }
+
+        // Releasing the limit resumes the time of the clients
+        server
+            .world_mut()
+            .resource_mut::<MaxSimSpeed>()
+            .ticks_per_second = None;
+        update_linked(&mut server, &mut clients, broadcast_delay_updates());
+        for client in &clients {
+            assert!(client.world().resource::<ToggleTime>().0);
+        }
+    }
+
+    /// Updates after which a hold or a release of the [MaxSimSpeed] reaches the clients
+    fn broadcast_delay_updates() -> usize {
+        (SPEED_LIMIT_BROADCAST_DELAY.as_secs_f64() * STPS).ceil() as usize + 2
+    }
+
+    #[test]
+    fn test_max_sim_speed_flapping() {
+        let (mut server, mut clients) = linked_apps(1);
+        update_linked(&mut server, &mut clients, 3);
+        // The simulation runs at a simtick per update, a bit faster than the limit, so it is held
+        // for a few updates every second
+        server
+            .world_mut()
+            .resource_mut::<MaxSimSpeed>()
+            .ticks_per_second = Some(6);
+        set_time_running(&mut server, true);
+        let mut flips = 0;
+        let mut running = true;
+        for _ in 0..3 * STPS as usize {
+            update_linked(&mut server, &mut clients, 1);
+            let client_running = clients[0].world().resource::<ToggleTime>().0;
+            if client_running != running {
+                flips += 1;
+                running = client_running;
+            }
+        }
+        assert!(
+            server
+                .world()
+                .resource::<TickRateTracker>()
+                .ticks_per_second()
+                > 5.
+        );
+        assert_eq!(flips, 0, "the time of the client flipped {flips} times");
+    }
+
+    #[test]
+    fn test_ship_spawn_broadcast() {
+        let (mut server, mut clients) = linked_apps(2);
+        update_linked(&mut server, &mut clients, 3);
+
+        // Both clients spawn the same ship, only the first one to reach the server keeps it, and
+        // the other client replaces its own by the copy of the server
+        let id = id_from("s");
+        for client in clients.iter_mut() {
+            client.world_mut().send_event(ShipEvent::Create(ShipInfo {
+                id,
+                spawn_pos: DVec3::new(1e6, 0., 0.),
+                spawn_speed: DVec3::new(0., 1e6, 0.),
+                spawn_orbit: None,
+            }));
+        }
+        update_linked(&mut server, &mut clients, 3);
+
+        assert!(server
+            .world()
+            .resource::<ShipsMapping>()
+            .0
+            .contains_key(&id));
+        assert_eq!(server.world().resource::<ShipOwners>().0.get(&id), Some(&1));
+        for client in &clients {
+            assert_eq!(client.world().resource::<ShipsMapping>().0.len(), 1);
+        }
+
+        // The state of the ship on the server reaches every client with the periodic updates
+        let world = server.world_mut();
+        let entity = world.resource::<ShipsMapping>().0[&id];
+        world.get_mut::<Position>(entity).unwrap().0 = DVec3::new(2e6, 0., 0.);
+        update_linked(&mut server, &mut clients, 1);
+        for client in &clients {
+            let world = client.world();
+            let entity = world.resource::<ShipsMapping>().0[&id];
+            assert_eq!(
+                world.get::<Position>(entity).unwrap().0,
+                DVec3::new(2e6, 0., 0.)
+            );
+        }
+    }
+
+    #[test]
+    fn test_thrust_forwarded() {
+        let (mut server, mut clients) = linked_apps(1);
+        update_linked(&mut server, &mut clients, 3);
+        let id = id_from("s");
+        clients[0]
+            .world_mut()
+            .send_event(ShipEvent::Create(ShipInfo {
+                id,
+                spawn_pos: DVec3::new(1e6, 0., 0.),
+                spawn_speed: DVec3::new(0., 1e6, 0.),
+                spawn_orbit: None,
+            }));
+        update_linked(&mut server, &mut clients, 3);
+
+        // A thrust applied on the client, e.g. by a maneuver node, reaches the server
+        let entity = server.world().resource::<ShipsMapping>().0[&id];
+        let before = server.world().get::<Velocity>(entity).unwrap().0;
+        clients[0].world_mut().send_event(VelocityUpdate {
+            ship_id: id,
+            thrust: DVec3::new(0., 0., 1e3),
+        });
+        update_linked(&mut server, &mut clients, 2);
+        let after = server.world().get::<Velocity>(entity).unwrap().0;
+        assert_eq!(after - before, DVec3::new(0., 0., 1e3));
+    }
+
+    #[test]
+    fn test_late_client_gets_ships() {
+        let (mut server, mut clients) = linked_apps(1);
+        update_linked(&mut server, &mut clients, 3);
+        let id = id_from("s");
+        clients[0]
+            .world_mut()
+            .send_event(ShipEvent::Create(ShipInfo {
+                id,
+                spawn_pos: DVec3::new(1e6, 0., 0.),
+                spawn_speed: DVec3::new(0., 1e6, 0.),
+                spawn_orbit: None,
+            }));
+        update_linked(&mut server, &mut clients, 3);
+
+        // A client joining later learns about the ships created before, and doesn't send them
+        // back to the server as its own
+        clients.push(connect_client(&mut server, "late"));
+        update_linked(&mut server, &mut clients, 5);
+        assert!(clients[1]
+            .world()
+            .resource::<ShipsMapping>()
+            .0
+            .contains_key(&id));
+        assert_eq!(server.world().resource::<ShipOwners>().0.get(&id), Some(&1));
+        assert_eq!(server.world().resource::<ShipsMapping>().0.len(), 1);
+    }
+
+    #[test]
+    fn test_time_sync() {
+        let (mut server, mut clients) = linked_apps(1);
+        update_linked(&mut server, &mut clients, 5);
+
+        let clock = clients[0].world().resource::<ClockSync>();
+        assert!(clock.round_trip_ns.is_some());
+        // The client updates right after the server, so it is at most a few simticks behind
+        let local = clients[0].world().resource::<GameTime>().simtick;
+        let server_tick = server.world().resource::<GameTime>().simtick;
+        let error = clock.estimated_server_tick(local) as i64 - server_tick as i64;
+        assert!(error.abs() <= 2, "{error}");
+    }
+
+    #[test]
+    fn test_bodies_mismatch() {
+        let mut server = spawn_server_with(
+            ServerPlugin::testing().with_bodies(BodiesConfig::SmallestBodyType(BodyType::Moon)),
+        );
+        let mut clients = vec![connect_client(&mut server, "player0")];
+        // The client first reads the planets of its own config, and waits for the server to
+        // spawn them
+        clients[0].update();
+        let client = clients[0].world();
+        assert_eq!(client.resource::<PendingBodies>().remaining(), 9);
+        assert!(client.resource::<BodiesMapping>().0.is_empty());
+        assert_eq!(
+            client.resource::<State<LoadingPhase>>().get(),
+            &LoadingPhase::ReadingBodies
+        );
+
+        update_linked(&mut server, &mut clients, 5);
+        let client = clients[0].world();
+        assert!(!client.contains_resource::<BodiesCheck>());
+        assert_eq!(
+            client.resource::<State<ClientMode>>().get(),
+            &ClientMode::Multiplayer
+        );
+        let bodies = &client.resource::<BodiesMapping>().0;
+        assert!(bodies.len() > 9);
+        assert!(bodies.contains_key("lune"));
+        assert_eq!(
+            bodies.len(),
+            server.world().resource::<BodiesMapping>().0.len()
+        );
+        assert!(client.contains_resource::<State<Loaded>>());
+        assert_synced(&mut server, &mut clients);
+    }
+
+    #[test]
+    fn test_constellation_rejected_as_a_whole() {
+        let (mut server, mut clients) = linked_apps(2);
+        update_linked(&mut server, &mut clients, 3);
+        let ship = |id: &str, x: f64| {
+            ShipEvent::Create(ShipInfo {
+                id: id_from(id),
+                spawn_pos: DVec3::new(x, 0., 0.),
+                spawn_speed: DVec3::new(0., 1e6, 0.),
+                spawn_orbit: None,
+            })
+        };
+        // The ships created during the same update form a constellation, one of which conflicts
+        // with the ship the first client creates at the same time, before the server is told
+        clients[0].world_mut().send_event(ship("s", 1e6));
+        for (id, x) in [("c1", 2e6), ("c2", 3e6), ("s", 4e6)] {
+            clients[1].world_mut().send_event(ship(id, x));
+        }
+        update_linked(&mut server, &mut clients, 3);
+
+        let position = |app: &App| {
+            let world = app.world();
+            let ships = &world.resource::<ShipsMapping>().0;
+            assert_eq!(ships.len(), 1);
+            world.get::<Position>(ships["s"]).unwrap().0
+        };
+        let server_pos = position(&server);
+        assert_eq!(server.world().resource::<ShipOwners>().0.get("s"), Some(&1));
+        assert!(server_pos.distance(DVec3::new(1e6, 0., 0.)) < 1e5);
+        // The second client dropped its whole constellation, and got the ship of the server
+        for client in &clients {
+            assert_eq!(position(client), server_pos);
+        }
+    }
+
+    #[test]
+    fn test_unknown_spawn_host() {
+        let (mut server, mut clients) = linked_apps(1);
+        update_linked(&mut server, &mut clients, 3);
+        // Only ids cross the network: a body the server doesn't know is ignored
+        let info = ShipInfo {
+            id: id_from("s"),
+            spawn_pos: DVec3::new(1e9, 0., 0.),
+            spawn_orbit: Some(SpawnOrbit {
+                host: id_from("vulcain"),
+                altitude: 100.,
+            }),
+            ..default()
+        };
+        send_to_server(
+            &mut clients[0],
+            ClientMessage::CreateShipMsg(CreateShipMsg {
+                pos: Position(info.spawn_pos),
+                info,
+                acceleration: default(),
+                velocity: default(),
+                generated_id: false,
+            }),
+        );
+        update_linked(&mut server, &mut clients, 3);
+        for app in [&server, &clients[0]] {
+            assert!(app.world().resource::<ShipsMapping>().0.contains_key("s"));
+        }
+    }
+
+    #[test]
+    fn test_unknown_orbit_host_on_client() {
+        let (mut server, mut clients) = linked_apps(1);
+        update_linked(&mut server, &mut clients, 3);
+        // A body the client doesn't know is skipped, and the ship is created without its orbit
+        clients[0]
+            .world_mut()
+            .send_event(ShipEvent::Create(ShipInfo {
+                id: id_from("s"),
+                spawn_pos: DVec3::new(1e9, 0., 0.),
+                spawn_orbit: Some(SpawnOrbit {
+                    host: id_from("vulcain"),
+                    altitude: 100.,
+                }),
+                ..default()
+            }));
+        update_linked(&mut server, &mut clients, 3);
+        for app in [&server, &clients[0]] {
+            let world = app.world();
+            let ship = world.resource::<ShipsMapping>().0["s"];
+            assert_eq!(world.get::<ShipInfo>(ship).unwrap().spawn_orbit, None);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! Estimation of the simtick of the server by the clients, from periodic pings, so that the time
+//! they display progresses smoothly instead of jumping at each update from the server.
+//!
+//! The offset between the clocks is computed as in NTP: a ping sent at local tick `t0` and
+//! answered when the server is at tick `t1` comes back at local tick `t3`, so the server is ahead
+//! by `t1 - (t0 + t3) / 2` if the trip took as long both ways. Among the last samples, the one
+//! with the shortest round trip is trusted, since it is the least affected by queuing delays
+use std::{collections::VecDeque, time::Duration};
+
+use bevy::prelude::*;
+
+use crate::{
+    client::{ClientMode, SyncStatus},
+    physics::time::{GameTime, SimStepSize, TimeUpdate, ToggleTime, STPS},
+};
+
+use super::{
+    transport::{ClientMessageTransport, ClientTransport},
+    ClientChannel, ClientMessage,
+};
+
+/// Time between two pings of a client
+pub const TIME_SYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Number of samples among which the one with the shortest round trip is kept
+const SAMPLES: usize = 8;
+
+/// Fraction of the difference with the estimated server tick that the displayed tick catches up
+/// at each physics update
+const SLEW_RATE: f64 = 0.1;
+
+/// The displayed tick jumps to the estimated server tick when they differ by more than this many
+/// physics updates, instead of catching up slowly
+const SNAP_UPDATES: f64 = 2. * STPS;
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<ClockSync>()
+        .add_systems(
+            OnEnter(ClientMode::Multiplayer),
+            |mut commands: Commands| commands.insert_resource(ClockSync::default()),
+        )
+        .add_systems(
+            Update,
+            send_time_sync
+                .run_if(in_state(ClientMode::Multiplayer))
+                .run_if(in_state(SyncStatus::Synced)),
+        )
+        .add_systems(FixedUpdate, update_displayed_tick.after(TimeUpdate));
+}
+
+/// A measure of the offset between the clocks of the client and the server
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SyncSample {
+    offset_ticks: i64,
+    round_trip_ns: u64,
+}
+
+/// Estimation of the difference between the local simtick and the one of the server
+#[derive(Resource, Default, Debug, Clone)]
+pub struct ClockSync {
+    /// Number of simticks to add to the local simtick to get the one of the server
+    pub clock_offset_ticks: i64,
+    /// Round trip of the sample the offset comes from, None until a ping was answered
+    pub round_trip_ns: Option<u64>,
+    samples: VecDeque<SyncSample>,
+    /// Local tick and time (in ns) of the last ping, which is waiting for its answer
+    pending: Option<(u64, u64)>,
+    last_ping_ns: Option<u64>,
+    displayed_tick: f64,
+}
+
+/// Offset between the clocks from a ping sent at local tick `sent`, answered by the server at
+/// tick `server`, and received at local tick `received`
+pub fn ntp_offset(sent: u64, server: u64, received: u64) -> i64 {
+    let midpoint = (sent as i128 + received as i128) / 2;
+    (server as i128 - midpoint) as i64
+}
+
+impl ClockSync {
+    pub fn estimated_server_tick(&self, local_tick: u64) -> u64 {
+        local_tick.saturating_add_signed(self.clock_offset_ticks)
+    }
+
+    /// Simtick to show to the player, which follows the estimated server tick without jumps
+    pub fn displayed_tick(&self) -> u64 {
+        self.displayed_tick.round() as u64
+    }
+
+    /// Records a ping sent at the given local tick and time (in ns)
+    fn ping(&mut self, local_tick: u64, time_ns: u64) {
+        self.pending = Some((local_tick, time_ns));
+        self.last_ping_ns = Some(time_ns);
+    }
+
+    fn should_ping(&self, time_ns: u64) -> bool {
+        self.last_ping_ns
+            .is_none_or(|last| time_ns >= last + TIME_SYNC_INTERVAL.as_nanos() as u64)
+    }
+
+    /// Handles the answer of the server to the ping sent at `client_local_tick`. Answers to
+    /// older pings are ignored, since their send time is forgotten
+    pub fn answer(
+        &mut self,
+        client_local_tick: u64,
+        server_tick: u64,
+        local_tick: u64,
+        time_ns: u64,
+    ) {
+        let Some((sent_tick, sent_ns)) = self
+            .pending
+            .take_if(|(sent_tick, _)| *sent_tick == client_local_tick)
+        else {
+            return;
+        };
+        if self.samples.len() == SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(SyncSample {
+            offset_ticks: ntp_offset(sent_tick, server_tick, local_tick),
+            round_trip_ns: time_ns.saturating_sub(sent_ns),
+        });
+        let best = self
+            .samples
+            .iter()
+            .min_by_key(|s| s.round_trip_ns)
+            .copied()
+            .unwrap();
+        self.clock_offset_ticks = best.offset_ticks;
+        self.round_trip_ns = Some(best.round_trip_ns);
+    }
+
+    /// Moves the displayed tick along with the local clock, and towards the estimated server tick
+    fn advance_display(&mut self, local_tick: u64, step: u64, running: bool) {
+        if running {
+            self.displayed_tick += step as f64;
+        }
+        let error = self.estimated_server_tick(local_tick) as f64 - self.displayed_tick;
+        if error.abs() > SNAP_UPDATES * step.max(1) as f64 {
+            self.displayed_tick += error;
+        } else {
+            self.displayed_tick += error * SLEW_RATE;
+        }
+    }
+}
+
+fn send_time_sync(
+    mut transport: ClientTransport,
+    mut clock: ResMut<ClockSync>,
+    time: Res<GameTime>,
+    real_time: Res<Time<Real>>,
+) {
+    let now = real_time.elapsed().as_nanos() as u64;
+    if !clock.should_ping(now) {
+        return;
+    }
+    clock.ping(time.simtick, now);
+    transport
+        .send(
+            ClientChannel::Once,
+            &ClientMessage::TimeSyncRequest {
+                client_local_tick: time.simtick,
+                send_time_ns: now,
+            },
+        )
+        .unwrap_or_else(|e| error!("could not send message to the server: {e}"));
+}
+
+fn update_displayed_tick(
+    mut clock: ResMut<ClockSync>,
+    time: Res<GameTime>,
+    step: Res<SimStepSize>,
+    toggle: Res<ToggleTime>,
+) {
+    clock.advance_display(time.simtick, step.0, toggle.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ntp_offset, ClockSync};
+
+    #[test]
+    fn test_clock_sync() {
+        // Sent at 100, the server was at 160 halfway, received at 120
+        assert_eq!(ntp_offset(100, 160, 120), 50);
+        assert_eq!(ntp_offset(100, 90, 120), -20);
+
+        let mut clock = ClockSync::default();
+        clock.ping(100, 0);
+        clock.answer(100, 160, 120, 40);
+        assert_eq!(clock.clock_offset_ticks, 50);
+        assert_eq!(clock.estimated_server_tick(200), 250);
+        // A slower round trip doesn't replace a faster one
+        clock.ping(200, 1000);
+        clock.answer(200, 300, 220, 2000);
+        assert_eq!(clock.clock_offset_ticks, 50);
+        assert_eq!(clock.round_trip_ns, Some(40));
+        clock.ping(300, 3000);
+        clock.answer(300, 305, 310, 3010);
+        assert_eq!(clock.clock_offset_ticks, 0);
+        // Unexpected answers are ignored
+        clock.answer(400, 1000, 410, 4000);
+        assert_eq!(clock.clock_offset_ticks, 0);
+    }
+
+    #[test]
+    fn test_displayed_tick() {
+        let mut clock = ClockSync {
+            clock_offset_ticks: 10,
+            ..Default::default()
+        };
+        // Far from the server, the display jumps to it
+        clock.advance_display(1000, 1, true);
+        assert_eq!(clock.displayed_tick(), 1010);
+        // Then follows the local clock, catching up with changes of the offset gradually
+        clock.clock_offset_ticks = 20;
+        let mut previous = clock.displayed_tick();
+        for local in 1001..1100 {
+            clock.advance_display(local, 1, true);
+            let displayed = clock.displayed_tick();
+            assert!(displayed > previous && displayed - previous <= 3);
+            previous = displayed;
+        }
+        assert_eq!(previous, 1099 + 20);
+    }
+}
@@ -0,0 +1,397 @@
+//! How messages go between the server and its clients. The game systems only use the
+//! [ServerTransport] and [ClientTransport] parameters, which go through quinnet in the game, and
+//! through in-process queues ([LoopbackServer] and [LoopbackClient]) in the tests
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_quinnet::{
+    client::QuinnetClient,
+    server::QuinnetServer,
+    shared::{error::QuinnetError, ClientId},
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{ClientChannel, ClientMessage, ServerChannel, ServerMessage};
+
+/// Which transport the server or the client uses
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportKind {
+    /// QUIC connections, over the network
+    #[default]
+    Quinnet,
+    /// In-process queues, linked by [super::testing::linked_apps]
+    Loopback,
+}
+
+/// Server side of a transport
+pub trait MessageTransport {
+    /// The clients currently connected
+    fn clients(&self) -> Vec<ClientId>;
+
+    fn send_to(
+        &mut self,
+        client: ClientId,
+        channel: ServerChannel,
+        message: &ServerMessage,
+    ) -> Result<(), QuinnetError>;
+
+    /// Sends a [ServerMessage] already serialized with bincode, so that a message sent to many
+    /// clients is only serialized once. The payload is dropped if it can't be sent
+    fn send_payload_to(&mut self, client: ClientId, channel: ServerChannel, payload: Vec<u8>);
+
+    fn broadcast(
+        &mut self,
+        channel: ServerChannel,
+        message: &ServerMessage,
+    ) -> Result<(), QuinnetError>;
+
+    /// The next message received from the client, if any
+    fn try_receive(&mut self, client: ClientId) -> Option<ClientMessage>;
+
+    fn disconnect(&mut self, client: ClientId) -> Result<(), QuinnetError>;
+}
+
+/// Client side of a transport, mirroring [MessageTransport]
+pub trait ClientMessageTransport {
+    fn send(&mut self, channel: ClientChannel, message: &ClientMessage)
+        -> Result<(), QuinnetError>;
+
+    /// The next message received from the server, if any
+    fn try_receive(&mut self) -> Option<ServerMessage>;
+
+    /// Closes the connection to the server
+    fn disconnect(&mut self) -> Result<(), QuinnetError>;
+}
+
+impl MessageTransport for QuinnetServer {
+    fn clients(&self) -> Vec<ClientId> {
+        self.get_endpoint()
+            .map(|endpoint| endpoint.clients())
+            .unwrap_or_default()
+    }
+
+    fn send_to(
+        &mut self,
+        client: ClientId,
+        channel: ServerChannel,
+        message: &ServerMessage,
+    ) -> Result<(), QuinnetError> {
+        self.get_endpoint()
+            .ok_or(QuinnetError::EndpointAlreadyClosed)?
+            .send_message_on(client, channel, message)
+    }
+
+    fn send_payload_to(&mut self, client: ClientId, channel: ServerChannel, payload: Vec<u8>) {
+        if let Some(endpoint) = self.get_endpoint() {
+            endpoint.try_send_payload_on(client, channel, payload);
+        }
+    }
+
+    fn broadcast(
+        &mut self,
+        channel: ServerChannel,
+        message: &ServerMessage,
+    ) -> Result<(), QuinnetError> {
+        self.get_endpoint()
+            .ok_or(QuinnetError::EndpointAlreadyClosed)?
+            .broadcast_message_on(channel, message)
+    }
+
+    fn try_receive(&mut self, client: ClientId) -> Option<ClientMessage> {
+        self.get_endpoint_mut()?
+            .try_receive_message_from::<ClientMessage>(client)
+            .map(|(_, message)| message)
+    }
+
+    fn disconnect(&mut self, client: ClientId) -> Result<(), QuinnetError> {
+        self.get_endpoint_mut()
+            .ok_or(QuinnetError::EndpointAlreadyClosed)?
+            .disconnect_client(client)
+    }
+}
+
+impl ClientMessageTransport for QuinnetClient {
+    fn send(
+        &mut self,
+        channel: ClientChannel,
+        message: &ClientMessage,
+    ) -> Result<(), QuinnetError> {
+        self.get_connection()
+            .ok_or(QuinnetError::ConnectionClosed)?
+            .send_message_on(channel, message)
+    }
+
+    fn try_receive(&mut self) -> Option<ServerMessage> {
+        self.get_connection_mut()?
+            .try_receive_message::<ServerMessage>()
+            .map(|(_, message)| message)
+    }
+
+    fn disconnect(&mut self) -> Result<(), QuinnetError> {
+        self.close_all_connections()
+    }
+}
+
+/// Serialized messages going one way, in the order they were sent
+type Queue = Arc<Mutex<VecDeque<Vec<u8>>>>;
+
+/// Both ways between the server and a client. Every channel is ordered and reliable
+#[derive(Clone, Default)]
+struct LoopbackLink {
+    to_server: Queue,
+    to_client: Queue,
+    connected: Arc<AtomicBool>,
+}
+
+impl LoopbackLink {
+    fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    fn push<T: Serialize>(queue: &Queue, message: &T) -> Result<(), QuinnetError> {
+        let payload = bincode::serialize(message).map_err(|_| QuinnetError::Serialization)?;
+        queue.lock().unwrap().push_back(payload);
+        Ok(())
+    }
+
+    fn pop<T: DeserializeOwned>(queue: &Queue) -> Option<T> {
+        loop {
+            let payload = queue.lock().unwrap().pop_front()?;
+            match bincode::deserialize(&payload) {
+                Ok(message) => return Some(message),
+                Err(e) => error!("could not deserialize message: {e}"),
+            }
+        }
+    }
+}
+
+/// Server end of in-process links to clients living in other worlds of the same process
+#[derive(Resource, Default)]
+pub struct LoopbackServer {
+    links: BTreeMap<ClientId, LoopbackLink>,
+    next_id: ClientId,
+}
+
+impl LoopbackServer {
+    /// Connects a new client, whose end of the link must be inserted in its world
+    pub fn connect(&mut self) -> LoopbackClient {
+        self.next_id += 1;
+        let link = LoopbackLink::default();
+        link.connected.store(true, Ordering::Relaxed);
+        self.links.insert(self.next_id, link.clone());
+        LoopbackClient { link }
+    }
+
+    fn link(&self, client: ClientId) -> Result<&LoopbackLink, QuinnetError> {
+        self.links
+            .get(&client)
+            .filter(|link| link.is_connected())
+            .ok_or(QuinnetError::UnknownClient(client))
+    }
+}
+
+impl MessageTransport for LoopbackServer {
+    fn clients(&self) -> Vec<ClientId> {
+        self.links
+            .iter()
+            .filter(|(_, link)| link.is_connected())
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    fn send_to(
+        &mut self,
+        client: ClientId,
+        _channel: ServerChannel,
+        message: &ServerMessage,
+    ) -> Result<(), QuinnetError> {
+        LoopbackLink::push(&self.link(client)?.to_client, message)
+    }
+
+    fn send_payload_to(&mut self, client: ClientId, _channel: ServerChannel, payload: Vec<u8>) {
+        if let Ok(link) = self.link(client) {
+            link.to_client.lock().unwrap().push_back(payload);
+        }
+    }
+
+    fn broadcast(
+        &mut self,
+        channel: ServerChannel,
+        message: &ServerMessage,
+    ) -> Result<(), QuinnetError> {
+        let payload = bincode::serialize(message).map_err(|_| QuinnetError::Serialization)?;
+        for client in self.clients() {
+            self.send_payload_to(client, channel, payload.clone());
+        }
+        Ok(())
+    }
+
+    fn try_receive(&mut self, client: ClientId) -> Option<ClientMessage> {
+        LoopbackLink::pop(&self.link(client).ok()?.to_server)
+    }
+
+    fn disconnect(&mut self, client: ClientId) -> Result<(), QuinnetError> {
+        let link = self
+            .links
+            .remove(&client)
+            .ok_or(QuinnetError::UnknownClient(client))?;
+        if link.connected.swap(false, Ordering::Relaxed) {
+            Ok(())
+        } else {
+            Err(QuinnetError::ClientAlreadyDisconnected(client))
+        }
+    }
+}
+
+/// Client end of an in-process link to a [LoopbackServer]
+#[derive(Resource)]
+pub struct LoopbackClient {
+    link: LoopbackLink,
+}
+
+impl LoopbackClient {
+    pub fn is_connected(&self) -> bool {
+        self.link.is_connected()
+    }
+}
+
+impl ClientMessageTransport for LoopbackClient {
+    fn send(
+        &mut self,
+        _channel: ClientChannel,
+        message: &ClientMessage,
+    ) -> Result<(), QuinnetError> {
+        if !self.is_connected() {
+            return Err(QuinnetError::ConnectionClosed);
+        }
+        LoopbackLink::push(&self.link.to_server, message)
+    }
+
+    fn try_receive(&mut self) -> Option<ServerMessage> {
+        // Messages sent before the server closed the connection are still delivered
+        LoopbackLink::pop(&self.link.to_client)
+    }
+
+    fn disconnect(&mut self) -> Result<(), QuinnetError> {
+        if self.link.connected.swap(false, Ordering::Relaxed) {
+            Ok(())
+        } else {
+            Err(QuinnetError::ConnectionAlreadyClosed)
+        }
+    }
+}
+
+/// The transport of the server, whichever is in use
+#[derive(SystemParam)]
+pub struct ServerTransport<'w> {
+    quinnet: Option<ResMut<'w, QuinnetServer>>,
+    loopback: Option<ResMut<'w, LoopbackServer>>,
+}
+
+impl ServerTransport<'_> {
+    fn get(&self) -> Option<&dyn MessageTransport> {
+        match (&self.loopback, &self.quinnet) {
+            (Some(loopback), _) => Some(loopback.as_ref()),
+            (None, Some(quinnet)) => Some(quinnet.as_ref()),
+            (None, None) => None,
+        }
+    }
+
+    fn get_mut(&mut self) -> Option<&mut dyn MessageTransport> {
+        match (&mut self.loopback, &mut self.quinnet) {
+            (Some(loopback), _) => Some(loopback.as_mut()),
+            (None, Some(quinnet)) => Some(quinnet.as_mut()),
+            (None, None) => None,
+        }
+    }
+}
+
+impl MessageTransport for ServerTransport<'_> {
+    fn clients(&self) -> Vec<ClientId> {
+        self.get()
+            .map(|transport| transport.clients())
+            .unwrap_or_default()
+    }
+
+    fn send_to(
+        &mut self,
+        client: ClientId,
+        channel: ServerChannel,
+        message: &ServerMessage,
+    ) -> Result<(), QuinnetError> {
+        self.get_mut()
+            .ok_or(QuinnetError::EndpointAlreadyClosed)?
+            .send_to(client, channel, message)
+    }
+
+    fn send_payload_to(&mut self, client: ClientId, channel: ServerChannel, payload: Vec<u8>) {
+        if let Some(transport) = self.get_mut() {
+            transport.send_payload_to(client, channel, payload);
+        }
+    }
+
+    fn broadcast(
+        &mut self,
+        channel: ServerChannel,
+        message: &ServerMessage,
+    ) -> Result<(), QuinnetError> {
+        self.get_mut()
+            .ok_or(QuinnetError::EndpointAlreadyClosed)?
+            .broadcast(channel, message)
+    }
+
+    fn try_receive(&mut self, client: ClientId) -> Option<ClientMessage> {
+        self.get_mut()?.try_receive(client)
+    }
+
+    fn disconnect(&mut self, client: ClientId) -> Result<(), QuinnetError> {
+        self.get_mut()
+            .ok_or(QuinnetError::EndpointAlreadyClosed)?
+            .disconnect(client)
+    }
+}
+
+/// The transport of the client, whichever is in use
+#[derive(SystemParam)]
+pub struct ClientTransport<'w> {
+    quinnet: Option<ResMut<'w, QuinnetClient>>,
+    loopback: Option<ResMut<'w, LoopbackClient>>,
+}
+
+impl ClientTransport<'_> {
+    fn get_mut(&mut self) -> Option<&mut dyn ClientMessageTransport> {
+        match (&mut self.loopback, &mut self.quinnet) {
+            (Some(loopback), _) => Some(loopback.as_mut()),
+            (None, Some(quinnet)) => Some(quinnet.as_mut()),
+            (None, None) => None,
+        }
+    }
+}
+
+impl ClientMessageTransport for ClientTransport<'_> {
+    fn send(
+        &mut self,
+        channel: ClientChannel,
+        message: &ClientMessage,
+    ) -> Result<(), QuinnetError> {
+        self.get_mut()
+            .ok_or(QuinnetError::ConnectionClosed)?
+            .send(channel, message)
+    }
+
+    fn try_receive(&mut self) -> Option<ServerMessage> {
+        self.get_mut()?.try_receive()
+    }
+
+    fn disconnect(&mut self) -> Result<(), QuinnetError> {
+        self.get_mut()
+            .ok_or(QuinnetError::ConnectionAlreadyClosed)?
+            .disconnect()
+    }
+}
@@ -3,16 +3,20 @@ use bevy::prelude::SystemSet;
 pub mod bodies;
 pub mod id;
 pub mod ships;
+pub mod waypoints;
 
 pub mod prelude {
 
     pub use super::bodies::{
         bodies_config::BodiesConfig,
-        body_data::{BodyData, BodyType},
-        BodiesMapping, BodyID, BodyInfo, PrimaryBody,
+        body_data::{BodyData, BodyDataBuilder, BodyType},
+        Atmosphere, BodiesChanged, BodiesMapping, BodyID, BodyInfo, PrimaryBody, RingSystem,
     };
+    #[cfg(test)]
     pub use super::id::id_from;
+    pub use super::id::IdGenerator;
     pub use super::ships::{CreateShipMsg, ShipEvent, ShipID, ShipInfo, ShipsMapping};
+    pub use super::waypoints::{WaypointID, WaypointKind, WaypointPositions, Waypoints};
 }
 
 #[derive(SystemSet, Debug, Clone, Copy, Hash, PartialEq, Eq)]
@@ -1,22 +1,34 @@
 //! A "Body" is a celestial body whose position is entirely determined by the
 //! current simtick, following orbital mechanics.
-use arrayvec::ArrayString;
-use bevy::{prelude::*, utils::HashMap};
+use std::path::PathBuf;
+
+use bevy::{math::DVec3, prelude::*, utils::HashMap};
 use bodies_config::BodiesConfig;
-use body_data::BodyData;
-use main_bodies::read_main_bodies;
+use body_data::{bodies_orbiting, detect_hierarchy_cycles, BodyData};
+use lookup::{BodyLookupError, BodyNames};
+use serde_json::Value;
 
-use crate::game::{ClearOnUnload, Loaded};
+use crate::game::{loading::LoadingPhase, ClearOnUnload, GameFiles};
 use crate::physics::prelude::*;
+use crate::utils::hash::hash;
+use crate::utils::persist::{write_versioned, Encoding, Migration, PersistError, Versioned};
 
-use super::id::MAX_ID_LENGTH;
-use super::ObjectsUpdate;
+use super::{id::sanitize_legacy_id, ObjectsUpdate};
 
 pub mod bodies_config;
 pub mod body_data;
+pub mod lookup;
 mod main_bodies;
+pub mod validation;
+
+pub use main_bodies::read_main_bodies;
+pub use validation::{validate, ValidationIssue};
+
+pub use super::id::BodyID;
 
-pub type BodyID = ArrayString<MAX_ID_LENGTH>;
+/// File of the game files in which the bodies edited in game are saved, so that the bundled
+/// bodies are never overwritten
+pub const USER_BODIES_PATH: &str = "user_bodies.json";
 // #[derive(Serialize, Deserialize)]
 // pub(crate) struct BodyID(u64);
 
@@ -41,57 +53,339 @@ pub struct PrimaryBody;
 #[derive(Component, Debug, Clone)]
 pub struct BodyInfo(pub BodyData);
 
+/// Thickness of the planetary rings, above and below the equatorial plane of their body (in km)
+pub const RING_THICKNESS_KM: f64 = 1.;
+
+/// Planetary rings, in the equatorial plane of the body
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct RingSystem {
+    pub inner_radius_km: f64,
+    pub outer_radius_km: f64,
+    pub surface_density_kg_m2: f64,
+}
+
+impl RingSystem {
+    /// The rings of a main body, if they are massive enough to be simulated.
+    /// Only the main rings of Saturn (from the C ring to the A ring) qualify
+    pub fn of_main_body(id: &str) -> Option<Self> {
+        match id {
+            "saturne" => Some(Self {
+                inner_radius_km: 74_658.,
+                outer_radius_km: 136_775.,
+                surface_density_kg_m2: 400.,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Whether a position relative to the body (in km) is inside the rings, given the normal of
+    /// the ring plane
+    pub fn contains(&self, rel_pos: DVec3, normal: DVec3) -> bool {
+        let height = rel_pos.dot(normal);
+        let radius = (rel_pos - height * normal).length();
+        height.abs() <= RING_THICKNESS_KM
+            && (self.inner_radius_km..=self.outer_radius_km).contains(&radius)
+    }
+}
+
+/// Exponential atmosphere of a body
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Atmosphere {
+    /// Distance from the center of the body at which the surface density is measured (at the
+    /// 1 bar level for the giant planets, in km)
+    pub radius_km: f64,
+    pub surface_density_kg_m3: f64,
+    /// Altitude difference over which the density is divided by e (in km)
+    pub scale_height_km: f64,
+}
+
+impl Atmosphere {
+    /// The atmosphere of a main body, if it is thick enough to brake a ship
+    pub fn of_main_body(id: &str) -> Option<Self> {
+        let (radius_km, surface_density_kg_m3, scale_height_km) = match id {
+            "venus" => (6_051.8, 65., 15.9),
+            "terre" => (6_371., 1.225, 8.5),
+            "mars" => (3_389.5, 0.020, 11.1),
+            "jupiter" => (69_911., 0.16, 27.),
+            "saturne" => (58_232., 0.19, 59.5),
+            "uranus" => (25_362., 0.42, 27.7),
+            "neptune" => (24_622., 0.45, 19.7),
+            "titan" => (2_574.7, 5.3, 21.),
+            _ => return None,
+        };
+        Some(Self {
+            radius_km,
+            surface_density_kg_m3,
+            scale_height_km,
+        })
+    }
+
+    /// Density at the given altitude above `radius_km` (in kg/km³)
+    pub fn density(&self, altitude_km: f64) -> f64 {
+        self.surface_density_kg_m3 * 1e9 * (-altitude_km / self.scale_height_km).exp()
+    }
+}
+
+/// Entities of the bodies by id, and the names they can be looked up by
 #[derive(Resource)]
-pub struct BodiesMapping(pub HashMap<BodyID, Entity>);
+pub struct BodiesMapping(pub HashMap<BodyID, Entity>, BodyNames);
+
+impl BodiesMapping {
+    /// Id of the body a user meant by `query`, matched against the ids, names and aliases of the
+    /// bodies, see [lookup]
+    pub fn lookup(&self, query: &str) -> Result<BodyID, BodyLookupError> {
+        self.1.lookup(query)
+    }
+
+    /// Id of the body whose id, name or alias is `query`, ignoring the case, see [lookup]
+    pub fn lookup_exact(&self, query: &str) -> Option<BodyID> {
+        self.1.lookup_exact(query)
+    }
+
+    /// Entity of the body a user meant by `query`, see [BodiesMapping::lookup]
+    pub fn resolve(&self, query: &str) -> Option<Entity> {
+        self.lookup(query)
+            .ok()
+            .and_then(|id| self.0.get(&id).copied())
+    }
+
+    /// Adds a body spawned after the others, or replaces the entity of a body
+    pub fn insert(&mut self, data: &BodyData, entity: Entity) {
+        self.1.insert(data);
+        self.0.insert(data.id, entity);
+    }
+
+    /// Forgets a body that no longer exists, and returns its entity
+    pub fn remove(&mut self, id: BodyID) -> Option<Entity> {
+        self.1.remove(id);
+        self.0.remove(&id)
+    }
+}
+
+/// Sent when the data of the bodies changed at runtime, so that the quantities derived from it
+/// (like the Hill radii) are computed again
+#[derive(Event, Debug, Clone, Copy)]
+pub struct BodiesChanged;
 
 pub struct BodiesPlugin;
 
 impl Plugin for BodiesPlugin {
     fn build(&self, app: &mut App) {
-        info!("loading BodiesPlugin");
-        info!("adding system OnEnter(Loaded) : build_system.in_set(ObjectsUpdate)");
-        app.add_systems(OnEnter(Loaded), build_system.in_set(ObjectsUpdate));
+        debug!("loading BodiesPlugin");
+        debug!("adding system OnEnter(LoadingPhase::ReadingBodies) : build_system.in_set(ObjectsUpdate)");
+        app.add_event::<BodiesChanged>().add_systems(
+            OnEnter(LoadingPhase::ReadingBodies),
+            build_system.in_set(ObjectsUpdate),
+        );
     }
 }
 
+/// Reads the bodies of the system, which are then spawned chunk by chunk while the system loads,
+/// see [crate::game::loading]
 pub fn build_system(mut commands: Commands, config: Res<BodiesConfig>) {
     info!("building system");
-    let bodies: Vec<_> = read_main_bodies()
-        .expect("Failed to read bodies")
+    let bodies = match config.as_ref() {
+        BodiesConfig::Custom(bodies) => bodies.clone(),
+        config => {
+            let bodies = read_main_bodies().expect("Failed to read bodies");
+            validation::report_issues(&validate(&bodies));
+            bodies
+                .into_iter()
+                .filter(config.clone().into_filter())
+                .collect()
+        }
+    };
+    commands.insert_resource(PendingBodies::new(bodies));
+    commands.insert_resource(BodiesMapping(HashMap::new(), BodyNames::default()));
+}
+
+/// Bodies read but not spawned yet
+#[derive(Resource, Debug, Default)]
+pub struct PendingBodies {
+    bodies: Vec<BodyData>,
+    primary_body: Option<BodyID>,
+    total: usize,
+}
+
+impl PendingBodies {
+    /// The bodies whose hosts form a cycle are skipped, since their positions could never be
+    /// computed from the primary body, and so are the bodies orbiting them
+    pub fn new(mut bodies: Vec<BodyData>) -> Self {
+        let mut skipped = detect_hierarchy_cycles(&bodies);
+        for id in &skipped {
+            error!("body {id} is part of a cycle of host bodies, it is skipped");
+        }
+        for (id, host) in bodies_orbiting(&bodies, &skipped) {
+            error!("body {id} orbits {host}, which is skipped, so it is skipped too");
+            skipped.push(id);
+        }
+        bodies.retain(|data| !skipped.contains(&data.id));
+        let primary_body = bodies
+            .iter()
+            .find(|data| data.host_body.is_none())
+            .map(|data| data.id);
+        if primary_body.is_none() {
+            warn!("no primary body found, the system will be empty");
+            bodies.clear();
+        }
+        Self {
+            total: bodies.len(),
+            bodies,
+            primary_body,
+        }
+    }
+
+    /// Bodies not spawned yet
+    pub fn bodies(&self) -> &[BodyData] {
+        &self.bodies
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bodies.len()
+    }
+
+    /// Number of bodies to spawn, including the ones already spawned
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Spawns at most `max` of the bodies and adds them to the mapping, and returns how many were
+    /// spawned
+    pub fn spawn_chunk(
+        &mut self,
+        commands: &mut Commands,
+        mapping: &mut BodiesMapping,
+        max: usize,
+    ) -> usize {
+        let count = max.min(self.bodies.len());
+        for data in self.bodies.drain(..count) {
+            let id = data.id;
+            mapping.1.insert(&data);
+            let mut entity = commands.spawn((
+                Position::default(),
+                EllipticalOrbit::from(&data),
+                Mass(data.mass),
+                BodyInfo(data),
+                Velocity::default(),
+                ClearOnUnload,
+            ));
+            if Some(id) == self.primary_body {
+                entity.insert(PrimaryBody);
+            }
+            if let Some(rings) = RingSystem::of_main_body(&id) {
+                entity.insert(rings);
+            }
+            mapping.0.insert(id, entity.id());
+        }
+        count
+    }
+}
+
+/// Spawns at most `max` of the [PendingBodies], and returns how many were spawned
+pub fn spawn_pending_bodies(
+    In(max): In<usize>,
+    mut commands: Commands,
+    pending: Option<ResMut<PendingBodies>>,
+    mapping: Option<ResMut<BodiesMapping>>,
+) -> usize {
+    let (Some(mut pending), Some(mut mapping)) = (pending, mapping) else {
+        return 0;
+    };
+    pending.spawn_chunk(&mut commands, &mut mapping, max)
+}
+
+/// Spawns the bodies and their mapping at once, see [PendingBodies::new] for the skipped bodies
+pub fn spawn_bodies(commands: &mut Commands, bodies: Vec<BodyData>) {
+    let mut mapping = BodiesMapping(HashMap::new(), BodyNames::default());
+    PendingBodies::new(bodies).spawn_chunk(commands, &mut mapping, usize::MAX);
+    commands.insert_resource(mapping);
+}
+
+/// Checksum of the bodies of a system: their ids, their hosts and the data their positions are
+/// computed from. A client whose bodies have another hash than the ones of the server would
+/// compute other positions, see [crate::client::BodiesCheck]
+pub fn bodies_hash<'a>(bodies: impl IntoIterator<Item = &'a BodyData>) -> u64 {
+    let mut keys: Vec<_> = bodies
         .into_iter()
-        .filter(config.clone().into_filter())
+        .map(|data| {
+            (
+                data.id,
+                data.host_body,
+                [
+                    data.semimajor_axis,
+                    data.eccentricity,
+                    data.inclination,
+                    data.long_asc_node,
+                    data.arg_periapsis,
+                    data.initial_mean_anomaly,
+                    data.revolution_period,
+                    data.mass,
+                ]
+                .map(f64::to_bits),
+            )
+        })
         .collect();
-    let primary_body = bodies
-        .iter()
-        .find(|data| data.host_body.is_none())
-        .expect("no primary body found")
-        .id;
-    let mut id_mapping = HashMap::new();
-    for data in bodies {
-        let id = data.id;
-        let mut entity = commands.spawn((
-            Position::default(),
-            EllipticalOrbit::from(&data),
-            Mass(data.mass),
-            BodyInfo(data),
-            Velocity::default(),
-            ClearOnUnload,
-        ));
-        if id == primary_body {
-            entity.insert(PrimaryBody);
+    keys.sort_unstable_by_key(|(id, ..)| *id);
+    hash(&keys)
+}
+
+impl Versioned for Vec<BodyData> {
+    const FORMAT: &'static str = "solar4x-bodies";
+    const VERSION: u32 = 2;
+    const ENCODING: Encoding = Encoding::Json;
+    const MIGRATIONS: &'static [(u32, Migration)] = &[(1, valid_body_ids)];
+}
+
+/// Version 2 validates the ids of the bodies: the ones that contain whitespace or characters that
+/// are not printable ASCII are sanitized
+fn valid_body_ids(payload: &mut Value) -> Result<(), String> {
+    let bodies = payload.as_array_mut().ok_or("no list of bodies")?;
+    for body in bodies {
+        let body = body.as_object_mut().ok_or("invalid body")?;
+        for field in ["id", "host_body"] {
+            if let Some(Value::String(id)) = body.get_mut(field) {
+                *id = sanitize_legacy_id(id);
+            }
+        }
+        if let Some(Value::Array(ids)) = body.get_mut("orbiting_bodies") {
+            for id in ids {
+                if let Value::String(id) = id {
+                    *id = sanitize_legacy_id(id);
+                }
+            }
         }
-        id_mapping.insert(id, entity.id());
     }
-    commands.insert_resource(BodiesMapping(id_mapping));
+    Ok(())
+}
+
+/// Writes the bodies to the user bodies file, and returns its path
+pub fn save_user_bodies(files: &GameFiles, bodies: &[BodyData]) -> Result<PathBuf, PersistError> {
+    let path = files.root.join(USER_BODIES_PATH);
+    write_versioned(&path, &bodies.to_vec())?;
+    Ok(path)
 }
 
 #[cfg(test)]
 mod tests {
-    use bevy::{app::App, ecs::query::With};
+    use bevy::{
+        app::App,
+        ecs::{
+            entity::Entity,
+            query::With,
+            system::{Commands, RunSystemOnce},
+        },
+    };
 
-    use crate::prelude::*;
+    use crate::{
+        physics::{
+            influence::setup_hill_spheres,
+            orbit::{update_global, update_local, update_system_size},
+        },
+        prelude::*,
+        utils::persist::from_versioned_str,
+    };
 
-    use super::BodyInfo;
+    use super::{lookup::BodyLookupError, spawn_bodies, BodyData, BodyInfo};
 
     #[test]
     fn test_build_system() {
@@ -119,4 +413,108 @@ mod tests {
             id_from("soleil")
         )
     }
+
+    #[test]
+    fn test_resolve() {
+        let mut app = App::new();
+        app.add_plugins(
+            ClientPlugin::testing()
+                .in_mode(ClientMode::Explorer)
+                .with_bodies(BodiesConfig::SmallestBodyType(BodyType::DwarfPlanet)),
+        );
+        app.update();
+        let mapping = app.world().resource::<BodiesMapping>();
+        let earth = mapping.0["terre"];
+        for query in ["Earth", " terre ", "TERRE", "La Terre"] {
+            assert_eq!(mapping.resolve(query), Some(earth), "{query}");
+        }
+        // The moons Mab and Margaret also start with "ma"
+        match mapping.lookup("ma") {
+            Err(BodyLookupError::Ambiguous(_, ids)) => {
+                assert!(ids.contains(&id_from("mars")), "{ids:?}");
+                assert!(ids.contains(&id_from("makemake")), "{ids:?}");
+            }
+            other => panic!("{other:?}"),
+        }
+        assert_eq!(mapping.resolve("ma"), None);
+        assert_eq!(
+            mapping.lookup("Satrun"),
+            Err(BodyLookupError::Unknown(
+                "Satrun".into(),
+                Some("Saturn".into())
+            ))
+        );
+    }
+
+    #[test]
+    fn test_cyclic_hierarchy() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Explorer));
+        app.update();
+        let world = app.world_mut();
+        let bodies = world
+            .query::<&BodyInfo>()
+            .iter(world)
+            .map(|BodyInfo(data)| data.clone())
+            .collect::<Vec<_>>();
+        let mut bodies: Vec<_> = bodies
+            .into_iter()
+            .filter(|data| [id_from("soleil"), id_from("terre")].contains(&data.id))
+            .collect();
+        bodies[0].orbiting_bodies = vec![id_from("terre")];
+        // The Moon and Phobos orbit each other
+        let mut moon = bodies[1].clone();
+        moon.id = id_from("lune");
+        moon.host_body = Some(id_from("phobos"));
+        moon.orbiting_bodies = vec![id_from("phobos")];
+        let mut phobos = moon.clone();
+        phobos.id = id_from("phobos");
+        phobos.host_body = Some(id_from("lune"));
+        phobos.orbiting_bodies = vec![id_from("lune")];
+        // Deimos orbits the cycle, so its position can't be computed either
+        let mut deimos = phobos.clone();
+        deimos.id = id_from("deimos");
+        deimos.host_body = Some(id_from("phobos"));
+        deimos.orbiting_bodies = vec![];
+        bodies.extend([moon, phobos, deimos]);
+
+        let entities: Vec<_> = world
+            .query_filtered::<Entity, With<BodyInfo>>()
+            .iter(world)
+            .collect();
+        for e in entities {
+            world.despawn(e);
+        }
+        world.run_system_once(move |mut commands: Commands| {
+            spawn_bodies(&mut commands, bodies.clone())
+        });
+        world.run_system_once(update_local);
+        world.run_system_once(update_global);
+        world.run_system_once(update_system_size);
+        world.run_system_once(setup_hill_spheres);
+        let mapping = &world.resource::<BodiesMapping>().0;
+        assert_eq!(mapping.len(), 2);
+        assert!(!mapping.contains_key("lune"));
+        assert!(!mapping.contains_key("deimos"));
+        app.update();
+    }
+
+    #[test]
+    fn test_legacy_user_bodies() {
+        let mut body = serde_json::to_value(BodyData {
+            id: id_from("lune"),
+            host_body: Some(id_from("terre")),
+            orbiting_bodies: vec![id_from("lune")],
+            ..Default::default()
+        })
+        .unwrap();
+        body["id"] = "petite lune".into();
+        body["host_body"] = " terre ".into();
+        body["orbiting_bodies"] = serde_json::json!(["a b", "c"]);
+        let v1 = serde_json::json!({ "format": "solar4x-bodies", "version": 1, "payload": [body] });
+        let bodies: Vec<BodyData> = from_versioned_str(&v1.to_string()).unwrap();
+        assert_eq!(bodies[0].id, id_from("petite_lune"));
+        assert_eq!(bodies[0].host_body, Some(id_from("terre")));
+        assert_eq!(bodies[0].orbiting_bodies, [id_from("a_b"), id_from("c")]);
+    }
 }
@@ -8,8 +8,13 @@ use super::{
 
 #[derive(Resource, Clone, Serialize, Deserialize)]
 pub enum BodiesConfig {
+    /// Bodies of this type or of a larger one
     SmallestBodyType(BodyType),
+    /// Bodies of these types only
+    BodyTypes(Vec<BodyType>),
     IDs(Vec<BodyID>),
+    /// These bodies instead of the bundled ones
+    Custom(Vec<BodyData>),
 }
 
 impl Default for BodiesConfig {
@@ -24,7 +29,66 @@ impl BodiesConfig {
             BodiesConfig::SmallestBodyType(body_type) => {
                 Box::new(move |data: &BodyData| data.body_type <= body_type)
             }
+            BodiesConfig::BodyTypes(types) => {
+                Box::new(move |data: &BodyData| types.contains(&data.body_type))
+            }
             BodiesConfig::IDs(v) => Box::new(move |data: &BodyData| v.contains(&data.id)),
+            BodiesConfig::Custom(bodies) => {
+                Box::new(move |data: &BodyData| bodies.iter().any(|body| body.id == data.id))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::objects::{
+        bodies::{body_data::BodyType, main_bodies::read_main_bodies, BodyID},
+        id::id_from,
+    };
+
+    use super::BodiesConfig;
+
+    fn filtered(config: BodiesConfig) -> Vec<(BodyID, BodyType)> {
+        let mut filter = config.into_filter();
+        read_main_bodies()
+            .unwrap()
+            .into_iter()
+            .filter(|data| filter(data))
+            .map(|data| (data.id, data.body_type))
+            .collect()
+    }
+
+    fn contains(bodies: &[(BodyID, BodyType)], id: &str) -> bool {
+        bodies.iter().any(|(i, _)| *i == id_from(id))
+    }
+
+    #[test]
+    fn test_smallest_body_type() {
+        let bodies = filtered(BodiesConfig::SmallestBodyType(BodyType::DwarfPlanet));
+        for id in ["soleil", "terre", "lune", "pluton", "ceres"] {
+            assert!(contains(&bodies, id), "{id}");
         }
+        for id in ["halley", "vesta", "hector", "chiron", "arrokoth"] {
+            assert!(!contains(&bodies, id), "{id}");
+        }
+        assert!(bodies.iter().all(|(_, t)| *t <= BodyType::DwarfPlanet));
+
+        let all = filtered(BodiesConfig::SmallestBodyType(BodyType::Comet));
+        assert_eq!(all.len(), 365);
+    }
+
+    #[test]
+    fn test_body_types() {
+        let bodies = filtered(BodiesConfig::BodyTypes(vec![
+            BodyType::Trojan,
+            BodyType::Centaur,
+        ]));
+        assert!(contains(&bodies, "hector"));
+        assert!(contains(&bodies, "chiron"));
+        assert!(!contains(&bodies, "soleil"));
+        assert!(bodies
+            .iter()
+            .all(|(_, t)| matches!(t, BodyType::Trojan | BodyType::Centaur)));
     }
 }
@@ -1,9 +1,12 @@
 use std::fmt::Display;
 
+use bevy::utils::HashMap;
 use serde::{Deserialize, Serialize};
 
 use super::BodyID;
 
+/// Kind of a body, from the largest to the smallest. The small bodies of the Sun are further
+/// sorted by the region of their orbit
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default, PartialOrd)]
 pub enum BodyType {
     Star,
@@ -12,7 +15,14 @@ pub enum BodyType {
     Moon,
     #[serde(alias = "Dwarf Planet")]
     DwarfPlanet,
+    /// Small body of the inner system, mostly in the main belt
     Asteroid,
+    /// Small body sharing the orbit of Jupiter, around one of its Lagrange points
+    Trojan,
+    /// Small body orbiting between Jupiter and Neptune
+    Centaur,
+    /// Small body orbiting beyond Neptune
+    TransNeptunian,
     Comet,
 }
 
@@ -24,15 +34,21 @@ impl Display for BodyType {
             Self::Moon => "Moon",
             Self::Asteroid => "Asteroid",
             Self::DwarfPlanet => "Dwarf Planet",
+            Self::Trojan => "Trojan",
+            Self::Centaur => "Centaur",
+            Self::TransNeptunian => "Trans-Neptunian Object",
             Self::Comet => "Comet",
         })
     }
 }
 
-#[derive(PartialEq, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
 pub struct BodyData {
     pub id: BodyID,
     pub name: String,
+    /// Other names users can type to look the body up, like its name in other languages
+    #[serde(default)]
+    pub aliases: Vec<String>,
     pub body_type: BodyType,
     pub host_body: Option<BodyID>,
     pub orbiting_bodies: Vec<BodyID>,
@@ -53,7 +69,249 @@ pub struct BodyData {
     pub revolution_period: f64,
     // Time required to rotate around itself (in earth hours)
     pub rotation_period: f64,
+    // Angle between the rotation axis and the normal of the orbital plane (in degrees)
+    pub axial_tilt: f64,
 
     pub radius: f64,
     pub mass: f64,
 }
+
+/// Builder of [BodyData] for bodies that aren't read from a file, like the ones of the tests.
+/// Unless set, the body is a planet named after its id, with the mass and radius of the Earth,
+/// on a circular orbit in the reference plane. Its revolution period is zero, which keeps it
+/// still at its initial mean anomaly
+#[derive(Debug, Clone)]
+pub struct BodyDataBuilder(BodyData);
+
+impl BodyDataBuilder {
+    pub fn new(id: BodyID) -> Self {
+        Self(BodyData {
+            id,
+            name: id.to_string(),
+            mass: 5.972e24,
+            radius: 6_371.,
+            ..Default::default()
+        })
+    }
+
+    pub fn named(mut self, name: impl Into<String>) -> Self {
+        self.0.name = name.into();
+        self
+    }
+
+    pub fn with_type(mut self, body_type: BodyType) -> Self {
+        self.0.body_type = body_type;
+        self
+    }
+
+    pub fn orbiting(mut self, host: BodyID) -> Self {
+        self.0.host_body = Some(host);
+        self
+    }
+
+    /// Bodies orbiting this one, which must be [BodyDataBuilder::orbiting] it
+    pub fn with_moons(mut self, moons: impl IntoIterator<Item = BodyID>) -> Self {
+        self.0.orbiting_bodies = moons.into_iter().collect();
+        self
+    }
+
+    /// Mass (in kg)
+    pub fn with_mass(mut self, m: f64) -> Self {
+        self.0.mass = m;
+        self
+    }
+
+    /// Mean radius (in km)
+    pub fn with_radius(mut self, r: f64) -> Self {
+        self.0.radius = r;
+        self
+    }
+
+    /// Semimajor axis (in km)
+    pub fn with_semimajor_axis(mut self, a: f64) -> Self {
+        self.0.semimajor_axis = a;
+        self
+    }
+
+    pub fn with_eccentricity(mut self, e: f64) -> Self {
+        self.0.eccentricity = e;
+        self
+    }
+
+    /// Inclination (in degrees)
+    pub fn with_inclination(mut self, i: f64) -> Self {
+        self.0.inclination = i;
+        self
+    }
+
+    /// Initial mean anomaly (in degrees)
+    pub fn with_mean_anomaly(mut self, m: f64) -> Self {
+        self.0.initial_mean_anomaly = m;
+        self
+    }
+
+    /// Time to complete a revolution around the host (in days)
+    pub fn with_revolution_period(mut self, days: f64) -> Self {
+        self.0.revolution_period = days;
+        self
+    }
+
+    /// Time to rotate around itself (in hours)
+    pub fn with_rotation_period(mut self, hours: f64) -> Self {
+        self.0.rotation_period = hours;
+        self
+    }
+
+    /// The data of the body, with the periapsis and apoapsis of its orbit
+    pub fn build(self) -> BodyData {
+        let BodyData {
+            semimajor_axis: a,
+            eccentricity: e,
+            ..
+        } = self.0;
+        BodyData {
+            periapsis: a * (1. - e),
+            apoapsis: a * (1. + e),
+            ..self.0
+        }
+    }
+}
+
+/// Finds the bodies whose hosts loop back to them (A orbits B which orbits A), with a depth-first
+/// search following the hosts: bodies being explored are gray and explored ones are black, so
+/// reaching a gray body closes a cycle. The bodies that merely orbit a cycle are not included
+pub fn detect_hierarchy_cycles(bodies: &[BodyData]) -> Vec<BodyID> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Color {
+        White,
+        Gray,
+        Black,
+    }
+    let hosts: HashMap<BodyID, Option<BodyID>> =
+        bodies.iter().map(|b| (b.id, b.host_body)).collect();
+    let mut colors: HashMap<BodyID, Color> = bodies.iter().map(|b| (b.id, Color::White)).collect();
+    let mut cycles = Vec::new();
+    for body in bodies {
+        let mut path = Vec::new();
+        let mut current = Some(body.id);
+        while let Some(id) = current {
+            match colors.get(&id) {
+                Some(Color::White) => {
+                    colors.insert(id, Color::Gray);
+                    path.push(id);
+                    current = hosts[&id];
+                }
+                Some(Color::Gray) => {
+                    // The path goes back to this body, every body after it is in the cycle
+                    let start = path.iter().position(|b| *b == id).unwrap();
+                    cycles.extend_from_slice(&path[start..]);
+                    break;
+                }
+                // Already explored, or an unknown host
+                Some(Color::Black) | None => break,
+            }
+        }
+        for id in path {
+            colors.insert(id, Color::Black);
+        }
+    }
+    cycles
+}
+
+/// Finds the bodies that orbit one of the `skipped` bodies, directly or through other hosts, and
+/// the host each of them orbits. The skipped bodies themselves are not included
+pub fn bodies_orbiting(bodies: &[BodyData], skipped: &[BodyID]) -> Vec<(BodyID, BodyID)> {
+    let mut orbiting: Vec<(BodyID, BodyID)> = Vec::new();
+    let is_skipped = |id: &BodyID, orbiting: &[(BodyID, BodyID)]| {
+        skipped.contains(id) || orbiting.iter().any(|(b, _)| b == id)
+    };
+    loop {
+        let before = orbiting.len();
+        for body in bodies {
+            if let Some(host) = body.host_body {
+                if !is_skipped(&body.id, &orbiting) && is_skipped(&host, &orbiting) {
+                    orbiting.push((body.id, host));
+                }
+            }
+        }
+        if orbiting.len() == before {
+            return orbiting;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::objects::prelude::id_from;
+
+    use super::{bodies_orbiting, detect_hierarchy_cycles, BodyData, BodyDataBuilder, BodyType};
+
+    fn body(id: &str, host: Option<&str>) -> BodyData {
+        let body = BodyDataBuilder::new(id_from(id));
+        match host {
+            Some(host) => body.orbiting(id_from(host)),
+            None => body,
+        }
+        .build()
+    }
+
+    #[test]
+    fn test_builder() {
+        let moon = BodyDataBuilder::new(id_from("moon"))
+            .with_type(BodyType::Moon)
+            .orbiting(id_from("planet"))
+            .with_semimajor_axis(1e5)
+            .with_eccentricity(0.5)
+            .with_mass(1e22)
+            .build();
+        assert_eq!(moon.name, "moon");
+        assert_eq!(moon.host_body, Some(id_from("planet")));
+        assert_eq!((moon.periapsis, moon.apoapsis), (5e4, 1.5e5));
+        assert_eq!(moon.mass, 1e22);
+        assert_eq!(moon.inclination, 0.);
+        let planet = BodyDataBuilder::new(id_from("planet"))
+            .named("Planet")
+            .with_moons([moon.id])
+            .build();
+        assert_eq!(planet.body_type, BodyType::Planet);
+        assert_eq!(planet.orbiting_bodies, vec![id_from("moon")]);
+        assert!(planet.host_body.is_none());
+    }
+
+    #[test]
+    fn test_detect_hierarchy_cycles() {
+        let mut bodies = vec![
+            body("sun", None),
+            body("a", Some("b")),
+            body("planet", Some("sun")),
+            body("b", Some("a")),
+            body("moon", Some("a")),
+            body("self", Some("self")),
+        ];
+        let mut cycles = detect_hierarchy_cycles(&bodies);
+        cycles.sort();
+        assert_eq!(cycles, ["a", "b", "self"].map(id_from));
+        bodies.truncate(3);
+        bodies[1].host_body = Some(id_from("planet"));
+        assert!(detect_hierarchy_cycles(&bodies).is_empty());
+    }
+
+    #[test]
+    fn test_bodies_orbiting() {
+        let bodies = vec![
+            body("station", Some("moon")),
+            body("sun", None),
+            body("a", Some("b")),
+            body("b", Some("a")),
+            body("moon", Some("a")),
+            body("planet", Some("sun")),
+        ];
+        let cycles = detect_hierarchy_cycles(&bodies);
+        let mut orbiting = bodies_orbiting(&bodies, &cycles);
+        orbiting.sort();
+        assert_eq!(
+            orbiting,
+            [("moon", "a"), ("station", "moon")].map(|(b, h)| (id_from(b), id_from(h)))
+        );
+    }
+}
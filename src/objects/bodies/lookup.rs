@@ -0,0 +1,199 @@
+//! Lookup of bodies from the names users type, which are rarely the exact ids: the ids of the
+//! bundled bodies are in French ("terre", "soleil") while their names are in English.
+//!
+//! A query is matched, ignoring the case and the surrounding whitespace, against the ids, names
+//! and aliases of the bodies: first exactly, then as a prefix of them
+
+use std::fmt::Display;
+
+use super::{body_data::BodyData, BodyID};
+
+/// Largest edit distance between an unknown name and a known one for the known one to be
+/// suggested
+const MAX_SUGGESTION_DISTANCE: usize = 3;
+
+fn normalize(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BodyLookupError {
+    /// Several bodies have a name starting with the query, sorted by id
+    Ambiguous(String, Vec<BodyID>),
+    /// No body has a name starting with the query. The suggestion is the closest known name
+    Unknown(String, Option<String>),
+}
+
+impl std::error::Error for BodyLookupError {}
+
+impl Display for BodyLookupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ambiguous(query, ids) => {
+                let ids: Vec<_> = ids.iter().map(BodyID::as_str).collect();
+                write!(f, "\"{query}\" could be any of {}", ids.join(", "))
+            }
+            Self::Unknown(query, Some(suggestion)) => {
+                write!(
+                    f,
+                    "no body named \"{query}\", did you mean \"{suggestion}\"?"
+                )
+            }
+            Self::Unknown(query, None) => write!(f, "no body named \"{query}\""),
+        }
+    }
+}
+
+/// Names of the bodies as typed in the data, with their normalized form
+#[derive(Debug, Clone, Default)]
+pub struct BodyNames(Vec<(String, String, BodyID)>);
+
+impl BodyNames {
+    pub fn insert(&mut self, data: &BodyData) {
+        for name in [data.id.as_str(), &data.name]
+            .into_iter()
+            .chain(data.aliases.iter().map(String::as_str))
+        {
+            let normalized = normalize(name);
+            if !normalized.is_empty()
+                && !self
+                    .0
+                    .iter()
+                    .any(|(n, _, id)| *n == normalized && *id == data.id)
+            {
+                self.0.push((normalized, name.trim().to_owned(), data.id));
+            }
+        }
+    }
+
+    /// Forgets the names of a body that no longer exists
+    pub fn remove(&mut self, id: BodyID) {
+        self.0.retain(|(.., name_id)| *name_id != id);
+    }
+
+    /// Id of the only body with a name equal to the query, without looking for the names starting
+    /// with it
+    pub fn lookup_exact(&self, query: &str) -> Option<BodyID> {
+        let normalized = normalize(query);
+        let mut ids = self
+            .0
+            .iter()
+            .filter(|(name, ..)| *name == normalized)
+            .map(|(.., id)| *id);
+        let id = ids.next()?;
+        ids.all(|other| other == id).then_some(id)
+    }
+
+    /// Id of the only body with a name equal to the query, or else starting with it
+    pub fn lookup(&self, query: &str) -> Result<BodyID, BodyLookupError> {
+        let normalized = normalize(query);
+        let query = query.trim().to_owned();
+        if normalized.is_empty() {
+            return Err(BodyLookupError::Unknown(query, None));
+        }
+        let matching = |matches: &dyn Fn(&str) -> bool| {
+            let mut ids: Vec<_> = self
+                .0
+                .iter()
+                .filter(|(name, ..)| matches(name))
+                .map(|(.., id)| *id)
+                .collect();
+            ids.sort();
+            ids.dedup();
+            ids
+        };
+        for ids in [
+            matching(&|name| name == normalized),
+            matching(&|name| name.starts_with(&normalized)),
+        ] {
+            match ids.as_slice() {
+                [] => {}
+                [id] => return Ok(*id),
+                _ => return Err(BodyLookupError::Ambiguous(query, ids)),
+            }
+        }
+        let suggestion = self
+            .0
+            .iter()
+            .map(|(name, original, _)| (edit_distance(name, &normalized), original))
+            .filter(|(distance, _)| *distance <= MAX_SUGGESTION_DISTANCE)
+            .min()
+            .map(|(_, original)| original.clone());
+        Err(BodyLookupError::Unknown(query, suggestion))
+    }
+}
+
+/// Levenshtein distance between two strings, counted in characters
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // Distances from the prefix of `a` read so far to each prefix of `b`
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::objects::{bodies::body_data::BodyData, id::id_from};
+
+    use super::{edit_distance, BodyLookupError, BodyNames};
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("", "mars"), 4);
+        assert_eq!(edit_distance("mars", "mars"), 0);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("jupyter", "jupiter"), 1);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let mut names = BodyNames::default();
+        for (id, name, aliases) in [
+            ("terre", "Earth", vec!["La Terre"]),
+            ("mars", "Mars", vec![]),
+            ("makemake", "136472 Makemake", vec!["2005 FY9"]),
+            ("mercure", "Mercury", vec!["Mercure"]),
+        ] {
+            names.insert(&BodyData {
+                id: id_from(id),
+                name: name.into(),
+                aliases: aliases.into_iter().map(String::from).collect(),
+                ..Default::default()
+            });
+        }
+        for query in ["Earth", " terre ", "TERRE", "la terre", "ear"] {
+            assert_eq!(names.lookup(query), Ok(id_from("terre")), "{query}");
+        }
+        assert_eq!(names.lookup("mars"), Ok(id_from("mars")));
+        assert_eq!(names.lookup_exact("la terre"), Some(id_from("terre")));
+        assert_eq!(names.lookup_exact("ear"), None);
+        assert_eq!(names.lookup("2005"), Ok(id_from("makemake")));
+        assert_eq!(
+            names.lookup("ma"),
+            Err(BodyLookupError::Ambiguous(
+                "ma".into(),
+                vec![id_from("makemake"), id_from("mars")]
+            ))
+        );
+        assert_eq!(
+            names.lookup("Jupyter"),
+            Err(BodyLookupError::Unknown("Jupyter".into(), None))
+        );
+        assert_eq!(
+            names.lookup("eatrh"),
+            Err(BodyLookupError::Unknown(
+                "eatrh".into(),
+                Some("Earth".into())
+            ))
+        );
+    }
+}
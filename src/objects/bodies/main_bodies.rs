@@ -1,11 +1,8 @@
-use std::{fs::File, io::Read};
+use std::{fs::File, io::Read, ops::Range};
 
 use serde::{de::Visitor, Deserialize, Deserializer};
 
-use crate::{
-    objects::id::{id_from, MAX_ID_LENGTH},
-    utils::de::deserialize_options,
-};
+use crate::{physics::AU, utils::de::deserialize_options};
 
 use super::{
     body_data::{BodyData, BodyType},
@@ -16,18 +13,19 @@ const ID_PREFIX: &str = "https://api.le-systeme-solaire.net/rest/bodies/";
 const MAIN_OBJECT_FILE_PATH: &str = "main_objects.json";
 const SUN_ID: &str = "soleil";
 
-#[derive(PartialEq, Debug, Clone)]
-pub struct MainBodyID(pub String);
+/// Ids of the dwarf planets the data lists as asteroids
+const DWARF_PLANETS: [&str; 1] = ["ceres"];
+/// Semimajor axes of the orbits of the Jupiter trojans (in AU)
+const TROJANS_AU: Range<f64> = 5.05..5.35;
+/// Semimajor axis of the orbit of Neptune (in AU)
+const NEPTUNE_AU: f64 = 30.1;
 
-impl From<&str> for MainBodyID {
-    fn from(value: &str) -> Self {
-        Self(value.to_owned())
-    }
-}
+#[derive(PartialEq, Debug, Clone)]
+pub struct MainBodyID(pub BodyID);
 
 impl From<MainBodyID> for BodyID {
     fn from(value: MainBodyID) -> Self {
-        id_from(&value.0)
+        value.0
     }
 }
 
@@ -67,8 +65,8 @@ impl<'de> Visitor<'de> for IDVisitor {
 
 fn strip_id_prefix(s: &str) -> Option<MainBodyID> {
     s.strip_prefix(ID_PREFIX)
-        .map(MainBodyID::from)
-        .filter(|s| s.0.len() < MAX_ID_LENGTH)
+        .and_then(|s| BodyID::new(s).ok())
+        .map(MainBodyID)
 }
 
 impl<'de> Deserialize<'de> for MainBodyID {
@@ -87,6 +85,11 @@ pub struct MainBodyData {
     pub id: MainBodyID,
     #[serde(rename(deserialize = "englishName"))]
     pub name: String,
+    /// Name in French, the language of the ids
+    #[serde(rename(deserialize = "name"))]
+    pub local_name: String,
+    #[serde(default)]
+    pub alternative_name: String,
     pub body_type: BodyType,
     #[serde(alias = "aroundPlanet")]
     pub host_body: Option<MainBodyID>,
@@ -114,6 +117,8 @@ pub struct MainBodyData {
     // Time required to rotate around itself (in earth hours)
     #[serde(alias = "sideralRotation")]
     pub rotation_period: f64,
+    #[serde(default)]
+    pub axial_tilt: f64,
 
     #[serde(alias = "meanRadius")]
     radius: f64,
@@ -121,12 +126,44 @@ pub struct MainBodyData {
     mass: Mass,
 }
 
+impl MainBodyData {
+    /// The data lists all the small bodies of the Sun as asteroids: they are sorted by the
+    /// semimajor axis of their orbit, and the dwarf planets among them are set apart
+    fn refined_body_type(&self) -> BodyType {
+        let around_sun = self
+            .host_body
+            .as_ref()
+            .is_none_or(|host| host.0.as_str() == SUN_ID);
+        if self.body_type != BodyType::Asteroid || !around_sun {
+            return self.body_type;
+        }
+        let semimajor_axis = self.semimajor_axis as f64 / AU;
+        if DWARF_PLANETS.contains(&self.id.0.as_str()) {
+            BodyType::DwarfPlanet
+        } else if TROJANS_AU.contains(&semimajor_axis) {
+            BodyType::Trojan
+        } else if semimajor_axis >= NEPTUNE_AU {
+            BodyType::TransNeptunian
+        } else if semimajor_axis >= TROJANS_AU.end {
+            BodyType::Centaur
+        } else {
+            BodyType::Asteroid
+        }
+    }
+}
+
 impl From<MainBodyData> for BodyData {
     fn from(value: MainBodyData) -> Self {
+        let body_type = value.refined_body_type();
+        let aliases = [value.local_name, value.alternative_name]
+            .into_iter()
+            .filter(|alias| !alias.is_empty() && *alias != value.name)
+            .collect();
         Self {
             id: value.id.into(),
             name: value.name,
-            body_type: value.body_type,
+            aliases,
+            body_type,
             host_body: value.host_body.map(Into::<BodyID>::into),
             orbiting_bodies: value
                 .orbiting_bodies
@@ -143,6 +180,7 @@ impl From<MainBodyData> for BodyData {
             apoapsis: value.apoapsis as f64,
             revolution_period: value.revolution_period,
             rotation_period: value.rotation_period,
+            axial_tilt: value.axial_tilt,
             radius: value.radius,
             mass: value.mass.into(),
         }
@@ -175,19 +213,20 @@ pub fn read_main_bodies() -> std::io::Result<Vec<BodyData>> {
 }
 
 fn fix_bodies(mut bodies: Vec<MainBodyData>) -> std::io::Result<Vec<MainBodyData>> {
+    let sun = MainBodyID(SUN_ID.parse().map_err(std::io::Error::other)?);
     bodies
         .iter_mut()
-        .find(|data| data.id == SUN_ID.into())
+        .find(|data| data.id == sun)
         .ok_or(std::io::Error::other("no sun"))?
         .orbiting_bodies = bodies
         .iter()
-        .filter(|data| data.host_body.is_none() && data.id != SUN_ID.into())
+        .filter(|data| data.host_body.is_none() && data.id != sun)
         .map(|planet| planet.id.clone())
         .collect();
     bodies
         .iter_mut()
-        .filter(|data| data.host_body.is_none() && data.id != SUN_ID.into())
-        .for_each(|body| body.host_body = Some(SUN_ID.into()));
+        .filter(|data| data.host_body.is_none() && data.id != sun)
+        .for_each(|body| body.host_body = Some(sun.clone()));
     Ok(bodies)
 }
 
@@ -195,6 +234,7 @@ fn fix_bodies(mut bodies: Vec<MainBodyData>) -> std::io::Result<Vec<MainBodyData
 mod tests {
 
     use super::*;
+    use crate::objects::id::id_from;
     use serde_json::from_str;
 
     #[test]
@@ -251,6 +291,7 @@ mod tests {
             BodyData {
                 id: id_from("lune"),
                 name: "Moon".into(),
+                aliases: vec!["La Lune".into()],
                 body_type: BodyType::Moon,
                 host_body: Some(id_from("terre")),
                 orbiting_bodies: Vec::new(),
@@ -264,6 +305,7 @@ mod tests {
                 apoapsis: 405500.,
                 revolution_period: 27.32170,
                 rotation_period: 655.72800,
+                axial_tilt: 6.68,
                 radius: 1737.,
                 mass: 7.346e22
             }
@@ -273,7 +315,7 @@ mod tests {
     #[test]
     fn test_read_main_bodies() {
         let bodies = read_main_bodies().unwrap();
-        assert_eq!(bodies.len(), 366);
+        assert_eq!(bodies.len(), 365);
     }
 
     #[test]
@@ -292,6 +334,25 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_small_bodies() {
+        let bodies = read_main_bodies().unwrap();
+        let body_type = |id: &str| {
+            bodies
+                .iter()
+                .find(|data| data.id == id_from(id))
+                .unwrap()
+                .body_type
+        };
+        assert_eq!(body_type("ceres"), BodyType::DwarfPlanet);
+        assert_eq!(body_type("vesta"), BodyType::Asteroid);
+        assert_eq!(body_type("hector"), BodyType::Trojan);
+        assert_eq!(body_type("chiron"), BodyType::Centaur);
+        assert_eq!(body_type("arrokoth"), BodyType::TransNeptunian);
+        assert_eq!(body_type("halley"), BodyType::Comet);
+        assert_eq!(body_type("pluton"), BodyType::DwarfPlanet);
+    }
+
     #[test]
     fn test_id_single() {
         let id: MainBodyID = from_str(
@@ -300,7 +361,7 @@ mod tests {
         "#,
         )
         .unwrap();
-        assert_eq!(id, "terre".into());
+        assert_eq!(id, MainBodyID(id_from("terre")));
     }
 
     #[test]
@@ -313,6 +374,6 @@ mod tests {
         }"#,
         )
         .unwrap();
-        assert_eq!(id, "terre".into());
+        assert_eq!(id, MainBodyID(id_from("terre")));
     }
 }
@@ -0,0 +1,337 @@
+//! Consistency checks of the body data, so that a broken bodies file is reported when it is
+//! loaded instead of surfacing later as a body stuck at the origin
+
+use std::{collections::HashSet, fmt::Display};
+
+use bevy::{log::warn, utils::HashMap};
+
+use crate::physics::influence::hill_distance_factor;
+
+use super::{
+    body_data::{detect_hierarchy_cycles, BodyData},
+    read_main_bodies, BodyID,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The data can be loaded, but will probably not behave as intended
+    Warning,
+    /// The bodies won't be simulated correctly
+    Error,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidationIssue {
+    pub body: BodyID,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl ValidationIssue {
+    fn error(body: BodyID, message: String) -> Self {
+        Self {
+            body,
+            severity: Severity::Error,
+            message,
+        }
+    }
+
+    fn warning(body: BodyID, message: String) -> Self {
+        Self {
+            body,
+            severity: Severity::Warning,
+            message,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl Display for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{severity}: {}: {}", self.body, self.message)
+    }
+}
+
+/// Smallest Hill radius of a body, at periapsis, the same way as in
+/// [crate::physics::influence::setup_hill_spheres]
+fn hill_radius(body: &BodyData, host: &BodyData) -> f64 {
+    (body.semimajor_axis * (1. - body.eccentricity) * hill_distance_factor(body.mass, host.mass))
+        .max(body.radius)
+}
+
+/// Checks that the bodies form a single system whose hierarchy and orbits make sense.
+/// Orbits must be closed, since they are all simulated as ellipses.
+///
+/// The issues are sorted by body, in the order of the list
+pub fn validate(bodies: &[BodyData]) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    let by_id: HashMap<BodyID, &BodyData> = bodies.iter().map(|b| (b.id, b)).collect();
+    let mut seen = HashSet::new();
+    for body in bodies {
+        if !seen.insert(body.id) {
+            issues.push(ValidationIssue::error(
+                body.id,
+                "several bodies have this id".into(),
+            ));
+        }
+    }
+
+    let roots: Vec<_> = bodies.iter().filter(|b| b.host_body.is_none()).collect();
+    match &roots[..] {
+        [] => {
+            if let Some(body) = bodies.first() {
+                issues.push(ValidationIssue::error(
+                    body.id,
+                    "no body is without host, the system has no root".into(),
+                ));
+            }
+        }
+        [_] => {}
+        [first, others @ ..] => issues.extend(others.iter().map(|b| {
+            ValidationIssue::error(
+                b.id,
+                format!("has no host, but {} is already the root", first.id),
+            )
+        })),
+    }
+
+    let cycles = detect_hierarchy_cycles(bodies);
+    for body in bodies {
+        let id = body.id;
+        if body.mass < 0. {
+            issues.push(ValidationIssue::error(
+                id,
+                format!("negative mass ({} kg)", body.mass),
+            ));
+        }
+        if body.radius < 0. {
+            issues.push(ValidationIssue::error(
+                id,
+                format!("negative radius ({} km)", body.radius),
+            ));
+        }
+
+        for child in &body.orbiting_bodies {
+            match by_id.get(child) {
+                None => issues.push(ValidationIssue::error(
+                    id,
+                    format!("orbiting body {child} does not exist"),
+                )),
+                Some(c) if c.host_body != Some(id) => issues.push(ValidationIssue::error(
+                    id,
+                    format!(
+                        "lists {child} as orbiting body, but its host is {}",
+                        c.host_body.map_or("none".to_owned(), |h| h.to_string())
+                    ),
+                )),
+                Some(_) => {}
+            }
+        }
+
+        let Some(host_id) = body.host_body else {
+            continue;
+        };
+        let Some(host) = by_id.get(&host_id) else {
+            issues.push(ValidationIssue::error(
+                id,
+                format!("host body {host_id} does not exist"),
+            ));
+            continue;
+        };
+        if !host.orbiting_bodies.contains(&id) {
+            issues.push(ValidationIssue::error(
+                id,
+                format!("host body {host_id} does not list it as orbiting body"),
+            ));
+        }
+
+        // Following the hosts must end at the root
+        if cycles.contains(&id) {
+            let mut cycle = vec![id.to_string()];
+            let mut current = host_id;
+            while current != id {
+                cycle.push(current.to_string());
+                match by_id[&current].host_body {
+                    Some(h) => current = h,
+                    None => break,
+                }
+            }
+            cycle.push(id.to_string());
+            issues.push(ValidationIssue::error(
+                id,
+                format!("cycle in the host chain: {}", cycle.join(" -> ")),
+            ));
+        }
+
+        if !(0. ..1.).contains(&body.eccentricity) {
+            issues.push(ValidationIssue::error(
+                id,
+                format!("eccentricity {} is not in [0, 1)", body.eccentricity),
+            ));
+        }
+        if body.semimajor_axis <= body.radius + host.radius {
+            issues.push(ValidationIssue::error(
+                id,
+                format!(
+                    "semimajor axis ({} km) is within the sum of the radii with {host_id} ({} km)",
+                    body.semimajor_axis,
+                    body.radius + host.radius
+                ),
+            ));
+        }
+        if let Some(grandhost) = host.host_body.and_then(|h| by_id.get(&h)) {
+            let apoapsis = body.semimajor_axis * (1. + body.eccentricity);
+            let hill = hill_radius(host, grandhost);
+            if apoapsis > hill {
+                issues.push(ValidationIssue::warning(
+                    id,
+                    format!(
+                        "apoapsis ({apoapsis:.0} km) is beyond the Hill radius of {host_id} ({hill:.0} km)"
+                    ),
+                ));
+            }
+        }
+    }
+    issues
+}
+
+/// Logs the issues at warning level, returning whether any of them is an error
+pub fn report_issues(issues: &[ValidationIssue]) -> bool {
+    for issue in issues {
+        warn!("invalid bodies: {issue}");
+    }
+    issues.iter().any(ValidationIssue::is_error)
+}
+
+/// Validates the bundled bodies and prints the issues with a summary, returning whether any of
+/// them is an error
+pub fn check_main_bodies() -> std::io::Result<bool> {
+    let bodies = read_main_bodies()?;
+    let issues = validate(&bodies);
+    for issue in &issues {
+        println!("{issue}");
+    }
+    let errors = issues.iter().filter(|i| i.is_error()).count();
+    println!(
+        "checked {} bodies: {} errors, {} warnings",
+        bodies.len(),
+        errors,
+        issues.len() - errors
+    );
+    Ok(errors > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::objects::prelude::{id_from, BodyData, BodyDataBuilder};
+
+    use super::{read_main_bodies, validate, Severity, ValidationIssue};
+
+    fn body(id: &str, host: Option<&str>, children: &[&str], a: f64) -> BodyData {
+        let mut body = BodyDataBuilder::new(id_from(id))
+            .with_moons(children.iter().map(|c| id_from(c)))
+            .with_semimajor_axis(a)
+            .with_mass(1e22)
+            .with_radius(100.)
+            .build();
+        body.host_body = host.map(id_from);
+        body
+    }
+
+    fn system() -> Vec<BodyData> {
+        let mut sun = body("sun", None, &["planet"], 0.);
+        sun.mass = 1e30;
+        vec![
+            sun,
+            body("planet", Some("sun"), &["moon"], 1e8),
+            body("moon", Some("planet"), &[], 1e4),
+        ]
+    }
+
+    fn issues_of(issues: &[ValidationIssue], id: &str) -> Vec<(Severity, String)> {
+        issues
+            .iter()
+            .filter(|i| i.body == id_from(id))
+            .map(|i| (i.severity, i.message.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_system() {
+        assert_eq!(validate(&system()), Vec::new());
+    }
+
+    #[test]
+    fn test_main_bodies() {
+        let issues = validate(&read_main_bodies().unwrap());
+        let errors: Vec<_> = issues.iter().filter(|i| i.is_error()).collect();
+        assert!(errors.is_empty(), "{errors:?}");
+    }
+
+    #[test]
+    fn test_broken_hierarchy() {
+        // Typo in the host
+        let mut bodies = system();
+        bodies[2].host_body = Some(id_from("plnet"));
+        let issues = validate(&bodies);
+        let moon = issues_of(&issues, "moon");
+        assert_eq!(moon.len(), 1);
+        assert!(moon[0].1.contains("plnet does not exist"));
+        assert!(issues_of(&issues, "planet")[0]
+            .1
+            .contains("its host is plnet"));
+
+        // Second root
+        let mut bodies = system();
+        bodies.push(body("rogue", None, &[], 1e6));
+        let issues = validate(&bodies);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].body, id_from("rogue"));
+
+        // The hosts loop back
+        let mut bodies = system();
+        bodies[0].host_body = Some(id_from("moon"));
+        bodies[2].orbiting_bodies.push(id_from("sun"));
+        let issues = validate(&bodies);
+        assert!(issues[0].is_error() && issues[0].message.contains("no root"));
+        assert_eq!(
+            issues
+                .iter()
+                .filter(|i| i.message.contains("cycle"))
+                .count(),
+            3
+        );
+        assert!(issues_of(&issues, "planet")
+            .iter()
+            .any(|(_, m)| m.contains("cycle") && m.contains("planet -> sun -> moon -> planet")));
+    }
+
+    #[test]
+    fn test_invalid_orbits() {
+        let mut bodies = system();
+        bodies[1].mass = -1.;
+        bodies[1].eccentricity = 1.2;
+        bodies[2].semimajor_axis = 150.;
+        let issues = validate(&bodies);
+        let planet = issues_of(&issues, "planet");
+        assert_eq!(planet.len(), 2);
+        assert!(planet[0].1.contains("negative mass"));
+        assert!(planet[1].1.contains("eccentricity"));
+        assert!(issues_of(&issues, "moon")[0].1.contains("sum of the radii"));
+
+        // Moon escaping its planet
+        let mut bodies = system();
+        bodies[2].semimajor_axis = 1e7;
+        let issues = validate(&bodies);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].severity, Severity::Warning);
+        assert!(issues[0].message.contains("Hill radius of planet"));
+    }
+}
@@ -1,7 +1,17 @@
+//! Ids of the ships and bodies. They are short ASCII strings without whitespace, so that they can
+//! be typed in the console and split from its other arguments
+
+use std::{borrow::Borrow, ops::Deref, str::FromStr};
+
 use arrayvec::ArrayString;
+use bevy::prelude::Resource;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 pub const MAX_ID_LENGTH: usize = 32;
 
+/// Number of characters needed to write any u64, along with the separator
+const COUNTER_LENGTH: usize = 21;
+
 // #[derive(Default)]
 // pub(super) struct NumberIncrementer(u64);
 
@@ -25,6 +35,303 @@ pub const MAX_ID_LENGTH: usize = 32;
 //     }
 // }
 
-pub fn id_from(s: &str) -> ArrayString<MAX_ID_LENGTH> {
-    ArrayString::from(s).unwrap()
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdError {
+    Empty,
+    TooLong(usize),
+    /// Whitespace, or a character which is not printable ASCII
+    InvalidChar(char),
+}
+
+impl std::error::Error for IdError {}
+
+impl std::fmt::Display for IdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => write!(f, "an id can't be empty"),
+            Self::TooLong(len) => write!(
+                f,
+                "an id is at most {MAX_ID_LENGTH} characters long, not {len}"
+            ),
+            Self::InvalidChar(c) if c.is_whitespace() => {
+                write!(f, "an id can't contain whitespace")
+            }
+            Self::InvalidChar(c) => write!(
+                f,
+                "an id can only contain printable ASCII characters, not {c:?}"
+            ),
+        }
+    }
+}
+
+fn validate(s: &str) -> Result<ArrayString<MAX_ID_LENGTH>, IdError> {
+    if s.is_empty() {
+        return Err(IdError::Empty);
+    }
+    if let Some(c) = s.chars().find(|c| !c.is_ascii_graphic()) {
+        return Err(IdError::InvalidChar(c));
+    }
+    ArrayString::from(s).map_err(|_| IdError::TooLong(s.len()))
+}
+
+/// Replaces the characters ids can't contain anymore with underscores, so that the ids written
+/// by older versions of the game can still be read
+pub fn sanitize_legacy_id(s: &str) -> String {
+    s.trim()
+        .chars()
+        .map(|c| if c.is_ascii_graphic() { c } else { '_' })
+        .collect()
+}
+
+macro_rules! validated_id {
+    ($(#[$meta:meta])* $name:ident, $placeholder:literal) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(ArrayString<MAX_ID_LENGTH>);
+
+        impl $name {
+            pub fn new(s: &str) -> Result<Self, IdError> {
+                validate(s).map(Self)
+            }
+
+            /// Reads an id written by an older version of the game, see [sanitize_legacy_id]
+            pub fn from_legacy(s: &str) -> Result<Self, IdError> {
+                Self::new(&sanitize_legacy_id(s))
+            }
+
+            pub fn as_str(&self) -> &str {
+                self.0.as_str()
+            }
+        }
+
+        /// A placeholder for the structs needing a default value before their id is set. It
+        /// contains whitespace, so that it can't be mistaken for a valid id
+        impl Default for $name {
+            fn default() -> Self {
+                Self(ArrayString::from($placeholder).unwrap())
+            }
+        }
+
+        impl Deref for $name {
+            type Target = str;
+
+            fn deref(&self) -> &str {
+                self.as_str()
+            }
+        }
+
+        impl Borrow<str> for $name {
+            fn borrow(&self) -> &str {
+                self.as_str()
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                self.as_str()
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = IdError;
+
+            fn from_str(s: &str) -> Result<Self, IdError> {
+                Self::new(s)
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl std::fmt::Debug for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                std::fmt::Debug::fmt(self.as_str(), f)
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(self.as_str())
+            }
+        }
+
+        /// Malformed ids sent by a client or read from a file are rejected here
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let s = String::deserialize(deserializer)?;
+                Self::new(&s).map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
+
+validated_id!(
+    /// Id of a ship, chosen by its owner or generated by an [IdGenerator]
+    ///
+    /// ```
+    /// # use rust_space_trading::objects::ships::ShipID;
+    /// fn follow(_: ShipID) {}
+    /// follow(ShipID::new("probe-1").unwrap());
+    /// ```
+    ///
+    /// A body id can't be used in its place:
+    ///
+    /// ```compile_fail
+    /// # use rust_space_trading::objects::{bodies::BodyID, ships::ShipID};
+    /// fn follow(_: ShipID) {}
+    /// follow(BodyID::new("terre").unwrap());
+    /// ```
+    ShipID,
+    "unnamed ship"
+);
+
+validated_id!(
+    /// Id of a body, read along with its data
+    BodyID,
+    "unnamed body"
+);
+
+/// A ship or body id from a literal, for tests
+///
+/// Panics if `s` is not a valid id
+#[cfg(test)]
+pub fn id_from<T: FromStr<Err = IdError>>(s: &str) -> T {
+    s.parse()
+        .unwrap_or_else(|e| panic!("invalid id {s:?}: {e}"))
+}
+
+/// Generates readable unique ids ("probe-1", "probe-2"...) for ships created without an id.
+/// The counter starts past the ids of the trajectories on disk, see [IdGenerator::skip_past]
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IdGenerator {
+    prefix: String,
+    counter: u64,
+}
+
+impl Default for IdGenerator {
+    fn default() -> Self {
+        Self::with_prefix("probe")
+    }
+}
+
+impl IdGenerator {
+    /// The prefix is truncated so that the generated ids always fit in [MAX_ID_LENGTH], and its
+    /// characters that ids can't contain are replaced
+    pub fn with_prefix(prefix: &str) -> Self {
+        Self {
+            prefix: sanitize_legacy_id(prefix)
+                .chars()
+                .take(MAX_ID_LENGTH - COUNTER_LENGTH)
+                .collect(),
+            counter: 1,
+        }
+    }
+
+    /// Generates the next id that is not taken
+    pub fn generate(&mut self, taken: impl Fn(&ShipID) -> bool) -> ShipID {
+        loop {
+            let id = ShipID::new(&format!("{}-{}", self.prefix, self.counter))
+                .expect("the prefix only contains valid characters");
+            self.counter += 1;
+            if !taken(&id) {
+                return id;
+            }
+        }
+    }
+
+    /// Whether the id could have been generated by this generator
+    pub fn is_generated(&self, id: &str) -> bool {
+        self.number(id).is_some_and(|n| n < self.counter)
+    }
+
+    /// Moves the counter past an id of the generated form, so that an id used before the
+    /// generator was created, like that of a trajectory file, is never generated again
+    pub fn skip_past(&mut self, id: &str) {
+        if let Some(n) = self.number(id) {
+            self.counter = self.counter.max(n.saturating_add(1));
+        }
+    }
+
+    /// Number of an id of the form "prefix-number"
+    fn number(&self, id: &str) -> Option<u64> {
+        id.strip_prefix(&self.prefix)
+            .and_then(|rest| rest.strip_prefix('-'))
+            .and_then(|n| n.parse::<u64>().ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::objects::{bodies::BodyID, ships::ShipID};
+
+    use super::{id_from, IdError, IdGenerator};
+
+    #[test]
+    fn test_id_generator() {
+        let mut generator = IdGenerator::default();
+        assert_eq!(generator.generate(|_| false), id_from("probe-1"));
+        // Ids chosen by the user are skipped
+        let taken = [id_from("probe-2"), id_from("probe-3")];
+        assert_eq!(
+            generator.generate(|id| taken.contains(id)),
+            id_from("probe-4")
+        );
+        assert!(generator.is_generated("probe-4"));
+        assert!(!generator.is_generated("probe-5"));
+        assert!(!generator.is_generated("probe"));
+
+        // The counter survives a save and load cycle
+        let saved = bincode::serialize(&generator).unwrap();
+        let mut loaded: IdGenerator = bincode::deserialize(&saved).unwrap();
+        assert_eq!(loaded.generate(|_| false), id_from("probe-5"));
+
+        // Past the ids in use before a restart
+        let mut generator = IdGenerator::default();
+        generator.skip_past("probe-12");
+        generator.skip_past("probe-3");
+        generator.skip_past("probe");
+        generator.skip_past("other-40");
+        assert_eq!(generator.generate(|_| false), id_from("probe-13"));
+
+        let mut generator = IdGenerator::with_prefix(&"x".repeat(40));
+        assert_eq!(generator.generate(|_| false).len(), 11 + 2);
+        let mut generator = IdGenerator::with_prefix("cargo ship");
+        assert_eq!(generator.generate(|_| false), id_from("cargo_ship-1"));
+    }
+
+    #[test]
+    fn test_validation() {
+        assert_eq!(ShipID::new("probe-1").unwrap().as_str(), "probe-1");
+        assert_eq!(ShipID::new(""), Err(IdError::Empty));
+        assert_eq!(ShipID::new("cargo ship"), Err(IdError::InvalidChar(' ')));
+        assert_eq!(ShipID::new("tab\there"), Err(IdError::InvalidChar('\t')));
+        assert_eq!(BodyID::new("lune\n"), Err(IdError::InvalidChar('\n')));
+        assert_eq!(BodyID::new("étoile"), Err(IdError::InvalidChar('é')));
+        assert_eq!(ShipID::new(&"x".repeat(33)), Err(IdError::TooLong(33)));
+        assert!(ShipID::new(&"x".repeat(32)).is_ok());
+        assert_eq!("mars".parse::<BodyID>(), BodyID::new("mars"));
+        assert_eq!(
+            ShipID::from_legacy(" cargo ship "),
+            ShipID::new("cargo_ship")
+        );
+        assert_eq!(ShipID::from_legacy("  "), Err(IdError::Empty));
+        // The placeholders are not valid ids
+        assert!(ShipID::new(&ShipID::default()).is_err());
+        assert!(BodyID::new(&BodyID::default()).is_err());
+    }
+
+    #[test]
+    fn test_deserialize_validates() {
+        let id: ShipID = serde_json::from_str("\"probe-1\"").unwrap();
+        assert_eq!(id, id_from("probe-1"));
+        assert!(serde_json::from_str::<ShipID>("\"\"").is_err());
+        assert!(serde_json::from_str::<ShipID>("\"two words\"").is_err());
+        let saved = bincode::serialize(&id).unwrap();
+        assert_eq!(bincode::deserialize::<ShipID>(&saved).unwrap(), id);
+        let invalid = bincode::serialize("no\0pe").unwrap();
+        assert!(bincode::deserialize::<ShipID>(&invalid).is_err());
+    }
 }
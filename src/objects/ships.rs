@@ -1,22 +1,30 @@
 //! A "Ship" is an object whose movement is governed by the gravitationnal
 //! attraction of the celestial bodies, along with custom trajectories
 
-use arrayvec::ArrayString;
+use std::f64::consts::TAU;
+
 use bevy::{math::DVec3, prelude::*, utils::HashMap};
-use bevy_quinnet::client::QuinnetClient;
 use serde::{Deserialize, Serialize};
 
-use crate::game::{ClearOnUnload, Loaded};
-use crate::network::{ClientChannel, ClientMessage};
+use crate::game::{loading::LoadingPhase, ClearOnUnload};
+use crate::network::transport::{ClientMessageTransport, ClientTransport};
+use crate::network::{ClientChannel, ClientMessage, ShipRejectionReason};
+use crate::physics::gravity::body_frame;
 use crate::physics::influence::HillRadius;
-use crate::physics::leapfrog::get_acceleration;
+use crate::physics::leapfrog::{get_acceleration, LeapfrogUpdate};
 use crate::physics::prelude::*;
+use crate::physics::PhysicsUpdate;
 use crate::prelude::ClientMode;
+use crate::utils::algebra::surface_relative_velocity;
 
-use super::id::MAX_ID_LENGTH;
-use super::prelude::{BodiesMapping, BodyInfo, PrimaryBody};
+use super::id::IdGenerator;
+use super::prelude::{BodiesMapping, BodyID, BodyInfo, PrimaryBody};
 use super::ObjectsUpdate;
 
+pub mod autopilot;
+pub mod history;
+pub mod navigation;
+pub mod proximity;
 pub mod trajectory;
 
 // pub(crate) struct ShipID(u64);
@@ -40,29 +48,116 @@ pub struct ShipsPlugin;
 
 impl Plugin for ShipsPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(trajectory::plugin)
-            .add_event::<ShipEvent>()
-            .add_systems(Update, handle_ship_events.in_set(ObjectsUpdate))
-            .add_systems(OnEnter(Loaded), create_ships.in_set(ObjectsUpdate));
+        app.add_plugins((
+            trajectory::plugin,
+            autopilot::plugin,
+            history::plugin,
+            navigation::plugin,
+            proximity::plugin,
+        ))
+        .add_event::<ShipEvent>()
+        .init_resource::<IdGenerator>()
+        .register_type::<ShipInfo>()
+        .register_type::<SurfaceRelativeVelocity>()
+        .add_systems(Update, handle_ship_events.in_set(ObjectsUpdate))
+        .add_systems(
+            FixedUpdate,
+            update_surface_velocity
+                .after(LeapfrogUpdate)
+                .in_set(PhysicsUpdate),
+        )
+        .add_systems(
+            OnEnter(LoadingPhase::SpawningShips),
+            create_ships.in_set(ObjectsUpdate),
+        );
     }
 }
 
-pub type ShipID = ArrayString<MAX_ID_LENGTH>;
+pub use super::id::ShipID;
 
-#[derive(Component, Clone, Default, PartialEq, Serialize, Deserialize, Debug, Copy)]
+#[derive(Component, Clone, Default, PartialEq, Serialize, Deserialize, Debug, Copy, Reflect)]
+#[reflect(Component)]
 pub struct ShipInfo {
+    #[reflect(ignore)]
     pub id: ShipID,
     pub spawn_pos: DVec3,
     pub spawn_speed: DVec3,
+    /// Set when the ship was spawned on a circular orbit instead of raw coordinates
+    #[reflect(ignore)]
+    #[serde(default)]
+    pub spawn_orbit: Option<SpawnOrbit>,
+}
+
+/// Circular orbit around a body on which a ship was spawned, so that the server can check it
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, Debug)]
+pub struct SpawnOrbit {
+    pub host: BodyID,
+    /// Altitude above the surface of the host (in km)
+    pub altitude: f64,
 }
 
+/// Velocity of the ship relative to the ground of its main influencer (in km/day), see
+/// [surface_relative_velocity]
+#[derive(Component, Default, Debug, Clone, Copy, PartialEq, Reflect)]
+#[reflect(Component)]
+pub struct SurfaceRelativeVelocity(pub DVec3);
+
 #[derive(Resource, Default)]
 pub struct ShipsMapping(pub HashMap<ShipID, Entity>);
 
 #[derive(Event)]
 pub enum ShipEvent {
     Create(ShipInfo),
+    /// A ship that exists on the server but not on this client, because another client created
+    /// it or because it was created before this client connected
+    Replicate(ShipInfo),
     Remove(ShipID),
+    /// The server refused the creation of a ship that was optimistically created locally
+    Rejected(ShipID, ShipRejectionReason),
+    /// The server gave another id to a ship created locally with a generated id
+    Renamed {
+        from: ShipID,
+        to: ShipID,
+    },
+}
+
+#[allow(clippy::type_complexity)]
+fn update_surface_velocity(
+    mut commands: Commands,
+    mut ships: Query<
+        (
+            Entity,
+            &Position,
+            &Velocity,
+            &Influenced,
+            Option<&mut SurfaceRelativeVelocity>,
+        ),
+        With<ShipInfo>,
+    >,
+    bodies: Query<(&Position, &Velocity, &BodyInfo, &EllipticalOrbit)>,
+    time: Res<GameTime>,
+) {
+    for (entity, pos, vel, influence, surface_vel) in ships.iter_mut() {
+        let Some((body_pos, body_vel, info, orbit)) =
+            influence.main_influencer.and_then(|e| bodies.get(e).ok())
+        else {
+            continue;
+        };
+        let data = &info.0;
+        let spin_axis = body_frame(info, orbit, time.time()).z_axis;
+        let rate = if data.rotation_period == 0. {
+            0.
+        } else {
+            TAU * 24. / data.rotation_period
+        };
+        let v = surface_relative_velocity(vel.0, body_vel.0, spin_axis, rate, pos.0, body_pos.0);
+        match surface_vel {
+            Some(mut surface_vel) => surface_vel.0 = v,
+            None => {
+                commands.entity(entity).insert(SurfaceRelativeVelocity(v));
+            }
+        }
+    }
 }
 
 fn create_ships(mut commands: Commands) {
@@ -75,6 +170,9 @@ pub struct CreateShipMsg {
     pub acceleration: Acceleration,
     pub pos: Position,
     pub velocity: Velocity,
+    /// Whether the id was generated by the [IdGenerator] of the client, in which case the server
+    /// can change it instead of rejecting the ship if it is already taken
+    pub generated_id: bool,
     // pub transform: TransformBundle,
     // pub clear_on_unload: ClearOnUnload,
 }
@@ -83,23 +181,43 @@ fn handle_ship_events(
     mut commands: Commands,
     mut reader: EventReader<ShipEvent>,
     mut ships: ResMut<ShipsMapping>,
-    mut client: Option<ResMut<QuinnetClient>>,
+    mut transport: ClientTransport,
     client_mode: Option<Res<State<ClientMode>>>,
     bodies: Query<(&Position, &HillRadius, &BodyInfo)>,
     mapping: Res<BodiesMapping>,
     main_body: Query<&BodyInfo, With<PrimaryBody>>,
+    mut infos: Query<&mut ShipInfo>,
+    generator: Res<IdGenerator>,
 ) {
     let multiplayer = in_state(ClientMode::Multiplayer)(client_mode);
+    let mut created = Vec::new();
     for event in reader.read() {
         match event {
-            ShipEvent::Create(info) => {
+            ShipEvent::Create(info) | ShipEvent::Replicate(info) => {
+                if ships.0.contains_key(&info.id) {
+                    warn!("ship {} already exists", info.id);
+                    continue;
+                }
+                // Only body ids cross the network, and the bodies of the client may lack some
+                let mut info = *info;
+                if let Some(orbit) = info
+                    .spawn_orbit
+                    .filter(|orbit| !mapping.0.contains_key(&orbit.host))
+                {
+                    warn!(
+                        "ship {} orbits the unknown body {}, its spawn orbit is skipped",
+                        info.id, orbit.host
+                    );
+                    info.spawn_orbit = None;
+                }
                 let pos = Position(info.spawn_pos);
                 let influence =
                     Influenced::new(&pos, &bodies, mapping.as_ref(), main_body.single().0.id);
-                ships.0.entry(info.id).or_insert({
+                ships.0.insert(
+                    info.id,
                     commands
                         .spawn((
-                            info.clone(),
+                            info,
                             Acceleration::new(get_acceleration(
                                 info.spawn_pos,
                                 bodies
@@ -112,34 +230,51 @@ fn handle_ship_events(
                             TransformBundle::from_transform(Transform::from_xyz(0., 0., 1.)),
                             ClearOnUnload,
                         ))
-                        .id()
-                });
-                if multiplayer {
-                    let msg = CreateShipMsg {
-                        info: info.clone(),
+                        .id(),
+                );
+                if multiplayer && matches!(event, ShipEvent::Create(_)) {
+                    created.push(CreateShipMsg {
+                        info,
                         acceleration: Acceleration::new(get_acceleration(
                             info.spawn_pos,
                             bodies
                                 .iter_many(&influence.influencers)
                                 .map(|(p, _, i)| (p.0, i.0.mass)),
                         )),
-                        pos: pos,
+                        pos,
                         velocity: Velocity(info.spawn_speed),
-                    };
-                    match client {
-                        Some(ref mut client) => client
-                            .connection_mut()
-                            .send_message_on(ClientChannel::Once, ClientMessage::CreateShipMsg(msg))
-                            .unwrap(),
-                        None => (),
-                    }
+                        generated_id: generator.is_generated(&info.id),
+                    });
                 };
             }
-            ShipEvent::Remove(id) => {
+            ShipEvent::Remove(id) | ShipEvent::Rejected(id, _) => {
                 if let Some(e) = ships.0.remove(id) {
                     commands.entity(e).despawn()
                 }
             }
+            ShipEvent::Renamed { from, to } => {
+                if let Some(e) = ships.0.remove(from) {
+                    // The server's copy may have been replicated before the renaming arrived
+                    if ships.0.contains_key(to) {
+                        commands.entity(e).despawn();
+                        continue;
+                    }
+                    ships.0.insert(*to, e);
+                    if let Ok(mut info) = infos.get_mut(e) {
+                        info.id = *to;
+                    }
+                }
+            }
         }
     }
+    // The ships created during the same update, like the ships of a constellation, are sent
+    // together so that the server accepts or rejects them as a whole
+    let message = match created.len() {
+        0 => return,
+        1 => ClientMessage::CreateShipMsg(created.remove(0)),
+        _ => ClientMessage::CreateShips(created),
+    };
+    transport
+        .send(ClientChannel::Once, &message)
+        .unwrap_or_else(|e| error!("could not send the created ships to the server: {e}"));
 }
@@ -0,0 +1,719 @@
+//! Autopilots automatically plan maneuver nodes for a ship, depending on its situation
+
+use std::{
+    error::Error,
+    f64::consts::{FRAC_PI_2, PI},
+};
+
+use bevy::{
+    math::{DMat3, DVec3},
+    prelude::*,
+};
+
+use crate::{
+    game::Authoritative,
+    objects::prelude::{BodiesMapping, BodyID, BodyInfo, ShipID, ShipsMapping},
+    physics::{
+        influence::{SOIEntryEvent, SOIExitEvent},
+        maneuver::{cw_propagate, cw_transfer_burn, edelbaum_dv},
+        prelude::*,
+        time::{SimStepSize, TickEvent, GAMETIME_PER_SIMTICK, SIMTICKS_PER_TICK},
+        G, SECONDS_PER_DAY,
+    },
+    utils::algebra::global_to_orbital_matrix,
+};
+
+use super::trajectory::{CurrentTrajectory, ManeuverNode, TrajectoryUpdate};
+
+/// Distance to the target under which the final approach is computed with the
+/// Clohessy-Wiltshire equations (in km)
+pub const PROXIMITY_OPS_RANGE: f64 = 10.;
+
+/// Farther from the target than this fraction of its orbital radius, the Clohessy-Wiltshire
+/// equations are too far off to close in, and the ship has to be brought closer beforehand
+pub const MAX_CLOSING_RANGE_RATIO: f64 = 0.02;
+
+/// Number of closing transfers after which an autopilot still out of range gives up
+const MAX_CLOSING_TRANSFERS: usize = 5;
+
+/// Point behind the target, in its Hill frame, towards which a ship out of range closes in
+const HOLDING_POINT: DVec3 = DVec3::new(0., -PROXIMITY_OPS_RANGE / 2., 0.);
+
+/// Duration of a tick (in days)
+const TICK_DURATION: f64 = SIMTICKS_PER_TICK as f64 * GAMETIME_PER_SIMTICK;
+
+/// Most maneuver nodes a low-thrust spiral is split into, longer spirals thrust less often
+pub const MAX_SPIRAL_NODES: usize = 20_000;
+
+pub fn plugin(app: &mut App) {
+    app.add_systems(
+        FixedUpdate,
+        run_autopilots
+            .in_set(TrajectoryUpdate)
+            .run_if(on_event::<TickEvent>())
+            .run_if(in_state(Authoritative)),
+    );
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AutopilotKind {
+    /// Brings the ship to a fixed offset from another ship, in the Hill frame of the target
+    /// (radial, along-track, orbit normal)
+    ProximityOps {
+        target_ship: ShipID,
+        final_offset_km: DVec3,
+    },
+    /// Spirals from the current orbit of the ship to the orbit of a body around the same host,
+    /// with a continuous thrust of the given acceleration (see [SpiralPlan])
+    LowThrustSpiral {
+        target_body: BodyID,
+        thrust_accel_km_s2: f64,
+    },
+}
+
+impl std::fmt::Display for AutopilotKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ProximityOps { target_ship, .. } => write!(f, "proximity ops with {target_ship}"),
+            Self::LowThrustSpiral { target_body, .. } => write!(f, "spiral to {target_body}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AutopilotPhase {
+    /// Waiting for the next burns to plan
+    #[default]
+    Approach,
+    /// The burns bringing the ship in range of the target are queued, the second one happening at
+    /// the given tick
+    Closing {
+        arrival_tick: u64,
+    },
+    /// The final approach burns are queued, the second one happening at the given tick
+    FinalApproach {
+        arrival_tick: u64,
+    },
+    /// The nodes of a low-thrust spiral are queued, the last one happening at the given tick
+    Spiral {
+        end_tick: u64,
+    },
+    Done,
+}
+
+impl std::fmt::Display for AutopilotPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Approach => "approach",
+            Self::Closing { .. } => "closing",
+            Self::FinalApproach { .. } => "final approach",
+            Self::Spiral { .. } => "spiral",
+            Self::Done => "done",
+        })
+    }
+}
+
+#[derive(Component, Debug, Clone)]
+pub struct Autopilot {
+    pub kind: AutopilotKind,
+    pub phase: AutopilotPhase,
+    /// Tick at which the queued nodes are dropped and planned again, as the ship crosses the
+    /// boundary of a sphere of influence
+    pub correction_tick: Option<u64>,
+    /// Number of transfers made to get in range of the target
+    pub closing_transfers: usize,
+}
+
+impl Autopilot {
+    pub fn new(kind: AutopilotKind) -> Self {
+        Self {
+            kind,
+            phase: AutopilotPhase::default(),
+            correction_tick: None,
+            closing_transfers: 0,
+        }
+    }
+}
+
+/// Position and velocity of a chaser relative to a target (on a circular orbit around a host body),
+/// expressed in the Hill frame of the target
+struct HillState {
+    /// Converts vectors from the Hill frame to the global frame
+    to_global: DMat3,
+    mean_motion: f64,
+    rel_pos: DVec3,
+    rel_vel: DVec3,
+}
+
+impl HillState {
+    fn new(
+        (host_pos, host_vel, host_mass): (DVec3, DVec3, f64),
+        (target_pos, target_vel): (DVec3, DVec3),
+        (chaser_pos, chaser_vel): (DVec3, DVec3),
+    ) -> Self {
+        let (r, v) = (target_pos - host_pos, target_vel - host_vel);
+        let radial = r.normalize();
+        let normal = r.cross(v).normalize();
+        let to_global = DMat3::from_cols(radial, normal.cross(radial), normal);
+        let mean_motion = (G * host_mass / r.length().powi(3)).sqrt();
+        let to_hill = to_global.transpose();
+        let rel_pos = to_hill * (chaser_pos - target_pos);
+        // Velocity in the rotating frame, which rotates at the mean motion around the normal
+        let rel_vel = to_hill * (chaser_vel - target_vel) - mean_motion * DVec3::Z.cross(rel_pos);
+        Self {
+            to_global,
+            mean_motion,
+            rel_pos,
+            rel_vel,
+        }
+    }
+}
+
+/// Computes the two impulses of the final approach towards `offset` (in the Hill frame) starting
+/// `delay` days from now and lasting `dt` days, in the Hill frame
+fn proximity_ops_burns(state: &HillState, offset: DVec3, delay: f64, dt: f64) -> [DVec3; 2] {
+    let n = state.mean_motion;
+    let (pos, vel) = cw_propagate(state.rel_pos, state.rel_vel, n, delay);
+    let first = cw_transfer_burn(pos, vel, n, dt, offset);
+    let (_, arrival_vel) = cw_propagate(pos, vel + first, n, dt);
+    [first, -arrival_vel]
+}
+
+/// Low-thrust transfer between two circular orbits around the same body, following Edelbaum's
+/// control law: the thrust keeps a constant magnitude, and its yaw out of the orbital plane
+/// changes steadily so that the inclination change is spread over the whole transfer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpiralPlan {
+    /// In km/s
+    pub delta_v: f64,
+    /// Duration of the transfer (in days)
+    pub duration: f64,
+    /// In km/s², as the other quantities of the control law
+    thrust_accel: f64,
+    initial_speed: f64,
+    initial_yaw: f64,
+    /// Of the host body, in km³/s²
+    gm: f64,
+    /// Side of the orbital plane towards which the out-of-plane thrust starts, to tilt the orbit
+    /// towards the target plane
+    normal_sign: f64,
+}
+
+impl SpiralPlan {
+    /// Plans the transfer from a circular orbit where the ship goes at `v1` to one where it goes
+    /// at `v2` (in km/s) around a body whose `gm` is in km³/s², changing the inclination by
+    /// `delta_i_rad`
+    pub fn new(v1: f64, v2: f64, delta_i_rad: f64, thrust_accel_km_s2: f64, gm: f64) -> Self {
+        let delta_v = edelbaum_dv(v1, v2, delta_i_rad);
+        let (sin, cos) = (FRAC_PI_2 * delta_i_rad).sin_cos();
+        Self {
+            delta_v,
+            duration: delta_v / thrust_accel_km_s2 / SECONDS_PER_DAY,
+            thrust_accel: thrust_accel_km_s2,
+            initial_speed: v1,
+            initial_yaw: sin.atan2(v1 / v2 - cos),
+            gm,
+            normal_sign: 1.,
+        }
+    }
+
+    /// Circular speed (in km/s) and yaw of the thrust out of the orbital plane (in radians),
+    /// `t` seconds after the start of the transfer
+    pub fn state_at(&self, t: f64) -> (f64, f64) {
+        let (v0, f) = (self.initial_speed, self.thrust_accel);
+        let (sin, cos) = self.initial_yaw.sin_cos();
+        let speed = (v0 * v0 - 2. * v0 * f * t * cos + f * f * t * t)
+            .max(0.)
+            .sqrt();
+        (speed, (v0 * sin).atan2(v0 * cos - f * t))
+    }
+
+    /// Approximates the continuous thrust with a node at every apsis, that is every half orbit,
+    /// each one giving the impulse accumulated since the previous one. The out-of-plane part of
+    /// the thrust switches sides every half orbit so that the plane changes the same way.
+    ///
+    /// The orbit is assumed to stay circular, with a period derived from the circular speed. The
+    /// first node happens at `start_tick`
+    pub fn nodes(&self, start_tick: u64, origin: BodyID) -> Vec<(u64, ManeuverNode)> {
+        let duration = self.duration * SECONDS_PER_DAY;
+        let tick = TICK_DURATION * SECONDS_PER_DAY;
+        let mut nodes = Vec::new();
+        let mut t = 0.;
+        let mut sign = self.normal_sign;
+        while t < duration {
+            let (speed, _) = self.state_at(t);
+            let half_period = PI * self.gm / speed.powi(3);
+            // An odd number of half orbits keeps the nodes on alternate sides of the orbit
+            let half_orbits = (tick / half_period)
+                .max(duration / (MAX_SPIRAL_NODES as f64 * half_period))
+                .ceil() as u64
+                | 1;
+            let dt = (half_orbits as f64 * half_period).min(duration - t);
+            let (_, yaw) = self.state_at(t + dt / 2.);
+            let impulse = self.thrust_accel * dt * SECONDS_PER_DAY;
+            nodes.push((
+                start_tick + (t / tick).round() as u64,
+                ManeuverNode::impulsive(
+                    format!("Spiral {}", nodes.len() + 1),
+                    // Forward and down axes of the orbital frame
+                    impulse * DVec3::new(yaw.cos(), 0., sign * yaw.sin()),
+                    origin,
+                ),
+            ));
+            sign = -sign;
+            t += dt;
+        }
+        nodes
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpiralError {
+    NoHost,
+    UnknownTarget(BodyID),
+    /// The target doesn't orbit the main influencer of the ship
+    DifferentHost(BodyID),
+    InvalidThrust(f64),
+}
+
+impl Error for SpiralError {}
+
+impl std::fmt::Display for SpiralError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpiralError::NoHost => write!(f, "The ship doesn't orbit any body"),
+            SpiralError::UnknownTarget(id) => write!(f, "There is no body with id \"{}\"", id),
+            SpiralError::DifferentHost(id) => {
+                write!(f, "\"{}\" doesn't orbit the same body as the ship", id)
+            }
+            SpiralError::InvalidThrust(a) => {
+                write!(f, "The thrust acceleration must be positive (got {})", a)
+            }
+        }
+    }
+}
+
+/// Plans the spiral of a ship at `pos` going at `vel` from its orbit around its main influencer
+/// to the orbit of `target_body`, returning it along with the id of the host
+pub fn plan_spiral(
+    (pos, vel): (DVec3, DVec3),
+    influence: &Influenced,
+    target_body: BodyID,
+    thrust_accel_km_s2: f64,
+    bodies: &Query<(&Position, &Velocity, &Mass, &BodyInfo, &EllipticalOrbit)>,
+    mapping: &BodiesMapping,
+) -> Result<(SpiralPlan, BodyID), SpiralError> {
+    if thrust_accel_km_s2.is_nan() || thrust_accel_km_s2 <= 0. {
+        return Err(SpiralError::InvalidThrust(thrust_accel_km_s2));
+    }
+    let (&Position(host_pos), &Velocity(host_vel), &Mass(host_mass), BodyInfo(host), _) = influence
+        .main_influencer
+        .and_then(|e| bodies.get(e).ok())
+        .ok_or(SpiralError::NoHost)?;
+    let (.., BodyInfo(target), orbit) = mapping
+        .0
+        .get(&target_body)
+        .and_then(|&e| bodies.get(e).ok())
+        .ok_or(SpiralError::UnknownTarget(target_body))?;
+    if target.host_body != Some(host.id) {
+        return Err(SpiralError::DifferentHost(target_body));
+    }
+    let (rel_pos, rel_vel) = (pos - host_pos, vel - host_vel);
+    let normal = rel_pos.cross(rel_vel).normalize_or_zero();
+    let target_normal = orbit
+        .local_pos
+        .cross(orbit.local_speed)
+        .try_normalize()
+        .unwrap_or(DVec3::Z);
+    // The maneuver functions work in km/s
+    let gm = G * host_mass / (SECONDS_PER_DAY * SECONDS_PER_DAY);
+    let (v1, v2) = (
+        (gm / rel_pos.length()).sqrt(),
+        (gm / orbit.semimajor_axis).sqrt(),
+    );
+    let mut plan = SpiralPlan::new(
+        v1,
+        v2,
+        normal.angle_between(target_normal),
+        thrust_accel_km_s2,
+        gm,
+    );
+    // Thrusting down (against the angular momentum) turns the momentum along -r x h
+    if rel_pos.cross(normal).dot(target_normal) > 0. {
+        plan.normal_sign = -1.;
+    }
+    Ok((plan, host.id))
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn run_autopilots(
+    mut commands: Commands,
+    mut autopilots: Query<(
+        Entity,
+        &mut Autopilot,
+        &Position,
+        &Velocity,
+        &Influenced,
+        Option<&mut CurrentTrajectory>,
+    )>,
+    coords: Query<(&Position, &Velocity)>,
+    bodies: Query<(&Position, &Velocity, &Mass, &BodyInfo, &EllipticalOrbit)>,
+    ships: Res<ShipsMapping>,
+    mapping: Res<BodiesMapping>,
+    time: Res<GameTime>,
+    step: Res<SimStepSize>,
+    mut exits: EventReader<SOIExitEvent>,
+    mut entries: EventReader<SOIEntryEvent>,
+) {
+    // The plans made around the previous host don't hold once the ship leaves its Hill sphere,
+    // while those made before entering a new one ignore its pull
+    let boundaries = exits
+        .read()
+        .map(|e| (e.ship, e.exit_tick + 1))
+        .chain(entries.read().map(|e| (e.ship, e.entry_tick)));
+    for (ship, tick) in boundaries {
+        let Some(mut autopilot) = ships
+            .0
+            .get(&ship)
+            .and_then(|&e| autopilots.get_mut(e).ok())
+            .map(|(_, autopilot, ..)| autopilot)
+        else {
+            continue;
+        };
+        if matches!(
+            autopilot.phase,
+            AutopilotPhase::Closing { .. }
+                | AutopilotPhase::FinalApproach { .. }
+                | AutopilotPhase::Spiral { .. }
+        ) {
+            autopilot.correction_tick =
+                Some(autopilot.correction_tick.map_or(tick, |t| t.min(tick)));
+        }
+    }
+
+    for (entity, mut autopilot, &Position(pos), &Velocity(vel), influence, mut trajectory) in
+        autopilots.iter_mut()
+    {
+        if autopilot.correction_tick.is_some_and(|t| t <= time.tick()) {
+            autopilot.correction_tick = None;
+            if let Some(trajectory) = trajectory.as_mut() {
+                trajectory.remove_nodes_from(time.tick() + 1);
+            }
+            if autopilot.phase != AutopilotPhase::Done {
+                autopilot.phase = AutopilotPhase::Approach;
+            }
+        }
+        match autopilot.phase {
+            AutopilotPhase::Done => continue,
+            AutopilotPhase::Closing { arrival_tick } => {
+                if time.tick() > arrival_tick {
+                    autopilot.phase = AutopilotPhase::Approach;
+                }
+                continue;
+            }
+            AutopilotPhase::FinalApproach {
+                arrival_tick: end_tick,
+            }
+            | AutopilotPhase::Spiral { end_tick } => {
+                if time.tick() > end_tick {
+                    autopilot.phase = AutopilotPhase::Done;
+                }
+                continue;
+            }
+            AutopilotPhase::Approach => {}
+        }
+        let (target_ship, final_offset_km) = match autopilot.kind {
+            AutopilotKind::ProximityOps {
+                target_ship,
+                final_offset_km,
+            } => (target_ship, final_offset_km),
+            AutopilotKind::LowThrustSpiral {
+                target_body,
+                thrust_accel_km_s2,
+            } => {
+                let nodes = match plan_spiral(
+                    (pos, vel),
+                    influence,
+                    target_body,
+                    thrust_accel_km_s2,
+                    &bodies,
+                    &mapping,
+                ) {
+                    Ok((plan, host)) => plan.nodes(time.tick() + 1, host),
+                    Err(e) => {
+                        warn!("could not plan the spiral: {e}");
+                        autopilot.phase = AutopilotPhase::Done;
+                        continue;
+                    }
+                };
+                let end_tick = nodes.last().map_or(time.tick(), |(tick, _)| *tick);
+                queue_nodes(&mut commands, entity, trajectory, nodes);
+                autopilot.phase = AutopilotPhase::Spiral { end_tick };
+                continue;
+            }
+        };
+        let Some((&Position(target_pos), &Velocity(target_vel))) =
+            ships.0.get(&target_ship).and_then(|&e| coords.get(e).ok())
+        else {
+            continue;
+        };
+        let Some((&Position(host_pos), &Velocity(host_vel), &Mass(host_mass), BodyInfo(host), _)) =
+            influence.main_influencer.and_then(|e| bodies.get(e).ok())
+        else {
+            continue;
+        };
+        // The bodies have already moved to the current simtick, while the ships are still a step
+        // behind, until their own update
+        let ship_host_pos = host_pos - host_vel * step.0 as f64 * GAMETIME_PER_SIMTICK;
+        // Out of range, the ship first closes in on a holding point behind the target, as many
+        // times as needed for the errors of the linearized equations to shrink
+        let closing = (target_pos - pos).length() > PROXIMITY_OPS_RANGE;
+        if closing {
+            let give_up = if (target_pos - pos).length()
+                > MAX_CLOSING_RANGE_RATIO * (target_pos - ship_host_pos).length()
+            {
+                Some("too far to close in")
+            } else if autopilot.closing_transfers >= MAX_CLOSING_TRANSFERS {
+                Some("still out of range after the closing transfers")
+            } else {
+                None
+            };
+            if let Some(reason) = give_up {
+                warn!("stopping the proximity ops with {target_ship}: {reason}");
+                autopilot.phase = AutopilotPhase::Done;
+                continue;
+            }
+            autopilot.closing_transfers += 1;
+        }
+        let (offset, name) = if closing {
+            (HOLDING_POINT, "C-W closing")
+        } else {
+            (final_offset_km, "C-W approach")
+        };
+
+        let state = HillState::new(
+            (ship_host_pos, host_vel, host_mass),
+            (target_pos, target_vel),
+            (pos, vel),
+        );
+        // A quarter of an orbit, rounded to a whole number of ticks
+        let transfer_ticks = ((PI / (2. * state.mean_motion)) / TICK_DURATION)
+            .round()
+            .max(1.) as u64;
+        let first_tick = time.tick() + 1;
+        let arrival_tick = first_tick + transfer_ticks;
+        let burns = proximity_ops_burns(
+            &state,
+            offset,
+            (first_tick * SIMTICKS_PER_TICK - time.simtick) as f64 * GAMETIME_PER_SIMTICK,
+            transfer_ticks as f64 * TICK_DURATION,
+        );
+
+        // The chaser is close to the target, so its orbital frame around the host body rotates
+        // along with the Hill frame of the target. The burns are turned back into global vectors
+        // with the position of the host body read at the same point of the update
+        let to_orbital = global_to_orbital_matrix(host_pos, host_vel, pos, vel) * state.to_global;
+        let nodes = [first_tick, arrival_tick]
+            .into_iter()
+            .zip(burns)
+            .enumerate()
+            .map(|(i, (tick, burn))| {
+                (
+                    tick,
+                    ManeuverNode::impulsive(
+                        format!("{name} {}/2", i + 1),
+                        to_orbital * burn,
+                        host.id,
+                    ),
+                )
+            });
+        queue_nodes(&mut commands, entity, trajectory, nodes);
+        autopilot.phase = if closing {
+            AutopilotPhase::Closing { arrival_tick }
+        } else {
+            AutopilotPhase::FinalApproach { arrival_tick }
+        };
+    }
+}
+
+/// Adds nodes to the trajectory of a ship, creating it if needed
+fn queue_nodes(
+    commands: &mut Commands,
+    entity: Entity,
+    trajectory: Option<Mut<CurrentTrajectory>>,
+    nodes: impl IntoIterator<Item = (u64, ManeuverNode)>,
+) {
+    match trajectory {
+        Some(mut trajectory) => trajectory.insert_nodes(nodes),
+        None => {
+            let mut trajectory = CurrentTrajectory::new(default());
+            trajectory.insert_nodes(nodes);
+            commands.entity(entity).insert(trajectory);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{math::DVec3, prelude::*};
+
+    use crate::{
+        objects::ships::trajectory::CurrentTrajectory,
+        physics::{maneuver::cw_propagate, time::SIMTICKS_PER_TICK, SECONDS_PER_DAY},
+        prelude::*,
+        utils::algebra::circular_orbit_around_body,
+    };
+
+    use super::{
+        proximity_ops_burns, Autopilot, AutopilotKind, AutopilotPhase, HillState, SpiralPlan,
+    };
+
+    #[test]
+    fn test_proximity_ops_burns() {
+        let earth_mass = 5.972e24;
+        let (target_pos, target_vel) = circular_orbit_around_body(
+            1e4,
+            earth_mass,
+            DVec3::ZERO,
+            DVec3::ZERO,
+            &mut rand::thread_rng(),
+        );
+        let chaser_pos = target_pos - 5. * target_vel.normalize();
+        // The chaser drifts along with the rotation of the Hill frame, like a co-orbiting object
+        let mean_motion = target_vel.length() / target_pos.length();
+        let chaser_vel = target_vel + 5. * mean_motion * target_pos.normalize();
+        let state = HillState::new(
+            (DVec3::ZERO, DVec3::ZERO, earth_mass),
+            (target_pos, target_vel),
+            (chaser_pos, chaser_vel),
+        );
+        // The chaser is behind the target on the same orbit
+        assert!((state.rel_pos - DVec3::new(0., -5., 0.)).length() < 1e-6);
+        assert!(state.rel_vel.length() < 1e-3);
+        let [first, second] = proximity_ops_burns(&state, DVec3::ZERO, 0., 0.01);
+        // Both burns stay in the orbital plane
+        assert!(first.length() > 0.);
+        assert!(first.z.abs() < 1e-6 && second.z.abs() < 1e-6);
+
+        // Offsets off the along-track axis, where the chaser has to end up too
+        let n = state.mean_motion;
+        let (delay, dt) = (0.001, 0.01);
+        for offset in [DVec3::new(0.3, 0., 0.), DVec3::new(0., 0., -0.2)] {
+            let [first, second] = proximity_ops_burns(&state, offset, delay, dt);
+            let (pos, vel) = cw_propagate(state.rel_pos, state.rel_vel, n, delay);
+            let (arrival, arrival_vel) = cw_propagate(pos, vel + first, n, dt);
+            assert!((arrival - offset).length() < 1e-9, "{arrival} {offset}");
+            assert!((arrival_vel + second).length() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_spiral_plan() {
+        // From a low orbit to a geostationary one, out of Cape Canaveral, at 1 mm/s²
+        let gm: f64 = 398600.;
+        let (v1, v2) = ((gm / 6678.).sqrt(), (gm / 42164.).sqrt());
+        let accel = 1e-6;
+        let plan = SpiralPlan::new(v1, v2, 28.5f64.to_radians(), accel, gm);
+        assert!((5.9..6.).contains(&plan.delta_v), "{}", plan.delta_v);
+        let duration = plan.duration * SECONDS_PER_DAY;
+        assert!((duration * accel - plan.delta_v).abs() < 1e-9);
+        let (end_speed, _) = plan.state_at(duration);
+        assert!((end_speed - v2).abs() < 1e-6, "{end_speed} {v2}");
+
+        let nodes = plan.nodes(10, id_from("terre"));
+        assert_eq!(nodes[0].0, 10);
+        assert!(nodes.windows(2).all(|w| w[0].0 < w[1].0));
+        let thrusts: Vec<_> = nodes.iter().map(|(_, n)| n.thrust().unwrap()).collect();
+        let total: f64 = thrusts.iter().map(|t| t.length()).sum();
+        assert!((total / SECONDS_PER_DAY - plan.delta_v).abs() < 1e-6);
+        // Prograde thrust raises the orbit, while the out-of-plane thrust switches sides
+        assert!(thrusts[0].x > 0. && thrusts[0].y == 0.);
+        assert!(thrusts[0].z * thrusts[1].z < 0.);
+    }
+
+    #[test]
+    fn test_proximity_ops_autopilot() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer));
+        app.update();
+        let world = app.world_mut();
+        let earth = world.resource::<BodiesMapping>().0["terre"];
+        let (&Position(earth_pos), &Velocity(earth_vel), &Mass(earth_mass)) = world
+            .query::<(&Position, &Velocity, &Mass)>()
+            .get(world, earth)
+            .unwrap();
+        let (pos, vel) = circular_orbit_around_body(
+            1e4,
+            earth_mass,
+            earth_pos,
+            earth_vel,
+            &mut rand::thread_rng(),
+        );
+        for (id, offset) in [
+            ("target", 0.),
+            ("chaser", 5.),
+            ("far", 50.),
+            ("distant", 1000.),
+        ] {
+            world.send_event(ShipEvent::Create(ShipInfo {
+                id: id_from(id),
+                spawn_pos: pos - offset * (vel - earth_vel).normalize(),
+                spawn_speed: vel,
+                spawn_orbit: None,
+            }));
+        }
+        app.update();
+        let mapping = app.world().resource::<ShipsMapping>().0.clone();
+        for id in ["chaser", "far", "distant"] {
+            app.world_mut()
+                .entity_mut(mapping[id])
+                .insert(Autopilot::new(AutopilotKind::ProximityOps {
+                    target_ship: id_from("target"),
+                    final_offset_km: DVec3::new(0., -0.1, 0.),
+                }));
+        }
+        app.world_mut()
+            .resource_mut::<NextState<GameStage>>()
+            .set(GameStage::Action);
+        app.update();
+        while app.world().resource::<GameTime>().simtick < SIMTICKS_PER_TICK {
+            app.update();
+        }
+
+        let world = app.world_mut();
+        let chaser = mapping["chaser"];
+        assert!(matches!(
+            world.get::<Autopilot>(chaser).unwrap().phase,
+            AutopilotPhase::FinalApproach { .. }
+        ));
+        assert!(world.get::<CurrentTrajectory>(chaser).is_some());
+        // Out of range, the ship closes in first, while too far it gives up
+        let far = mapping["far"];
+        assert!(matches!(
+            world.get::<Autopilot>(far).unwrap().phase,
+            AutopilotPhase::Closing { .. }
+        ));
+        assert!(world.get::<CurrentTrajectory>(far).is_some());
+        let distant = mapping["distant"];
+        assert_eq!(
+            world.get::<Autopilot>(distant).unwrap().phase,
+            AutopilotPhase::Done
+        );
+        assert!(world.get::<CurrentTrajectory>(distant).is_none());
+
+        // Once the closing transfer is over, the ship is in range for the final approach
+        while app.world().resource::<GameTime>().simtick < 20 * SIMTICKS_PER_TICK {
+            app.update();
+        }
+        let autopilot = app.world().get::<Autopilot>(far).unwrap();
+        assert!(
+            matches!(
+                autopilot.phase,
+                AutopilotPhase::FinalApproach { .. } | AutopilotPhase::Done
+            ),
+            "{}",
+            autopilot.phase
+        );
+        assert_eq!(autopilot.closing_transfers, 1);
+    }
+}
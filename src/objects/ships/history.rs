@@ -0,0 +1,342 @@
+//! Recording of the actual path of ships, for post-flight analysis.
+//!
+//! Samples are appended to JSON-lines chunks of [CHUNK_SAMPLES] samples under
+//! `history/<ship_id>/`, so that memory use stays constant however long the recording lasts.
+
+use std::{
+    fs::{create_dir_all, read_dir, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    ops::RangeBounds,
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    ecs::component::{ComponentHooks, StorageType},
+    math::DVec3,
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game::GameFiles,
+    physics::{leapfrog::LeapfrogUpdate, prelude::*, time::TickEvent, PhysicsUpdate},
+    prelude::exit_on_error_if_app,
+};
+
+use super::{ShipID, ShipInfo, ShipsMapping};
+
+pub const HISTORY_PATH: &str = "history";
+
+/// Number of samples stored in each chunk of a history log
+pub const CHUNK_SAMPLES: usize = 256;
+
+/// Number of ticks between two samples when not specified
+pub const DEFAULT_RECORD_INTERVAL: u64 = 10;
+
+/// Name of the file written when a log is finalized
+const END_FILE: &str = "end.json";
+
+pub fn plugin(app: &mut App) {
+    app.add_event::<HistoryEvent>()
+        .add_systems(
+            FixedUpdate,
+            record_history
+                .pipe(exit_on_error_if_app)
+                .after(LeapfrogUpdate)
+                .in_set(PhysicsUpdate)
+                .run_if(on_event::<TickEvent>()),
+        )
+        .add_systems(Update, handle_history_events.pipe(exit_on_error_if_app));
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct HistorySample {
+    pub simtick: u64,
+    pub position: DVec3,
+    pub velocity: DVec3,
+}
+
+/// Records a sample of the position and velocity of the ship every `interval_ticks` ticks.
+///
+/// Since the physics don't run while time is paused, neither does the recording.
+/// Removing the component (or the ship) finalizes the log, which is kept on disk
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordHistory {
+    pub interval_ticks: u64,
+    /// Index of the chunk being written
+    chunk: usize,
+    /// Number of samples already in the chunk being written
+    chunk_len: usize,
+}
+
+impl Component for RecordHistory {
+    const STORAGE_TYPE: StorageType = StorageType::Table;
+
+    fn register_component_hooks(hooks: &mut ComponentHooks) {
+        hooks.on_remove(|world, entity, _| {
+            let (Some(files), Some(info)) = (
+                world.get_resource::<GameFiles>(),
+                world.get::<ShipInfo>(entity),
+            ) else {
+                return;
+            };
+            let dir = history_dir(&files.history, info.id);
+            if let Err(e) = finalize_log(&dir, world.get::<RecordHistory>(entity).unwrap()) {
+                error!("could not finalize the history of ship {}: {e}", info.id);
+            }
+        });
+    }
+}
+
+impl RecordHistory {
+    /// Starts a new recording, resuming after the chunks already in `dir` if any
+    pub fn resume(dir: &Path, interval_ticks: u64) -> Self {
+        Self {
+            interval_ticks: interval_ticks.max(1),
+            chunk: chunk_paths(dir).len(),
+            chunk_len: 0,
+        }
+    }
+
+    fn append(&mut self, dir: &Path, sample: &HistorySample) -> std::io::Result<()> {
+        create_dir_all(dir)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(chunk_name(self.chunk)))?;
+        writeln!(file, "{}", serde_json::to_string(sample)?)?;
+        self.chunk_len += 1;
+        if self.chunk_len == CHUNK_SAMPLES {
+            self.chunk += 1;
+            self.chunk_len = 0;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Event, Debug, Clone)]
+pub enum HistoryEvent {
+    Start { ship: ShipID, interval_ticks: u64 },
+    Stop(ShipID),
+    Export { ship: ShipID, format: HistoryFormat },
+}
+
+fn chunk_name(chunk: usize) -> String {
+    format!("chunk-{chunk:06}.jsonl")
+}
+
+/// Paths of the chunks of a log, in the order they were written
+fn chunk_paths(dir: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<_> = read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("chunk-") && n.ends_with(".jsonl"))
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+pub fn history_dir(root: &Path, ship: ShipID) -> PathBuf {
+    root.join(ship.as_str())
+}
+
+fn finalize_log(dir: &Path, history: &RecordHistory) -> std::io::Result<()> {
+    create_dir_all(dir)?;
+    let file = File::create(dir.join(END_FILE))?;
+    serde_json::to_writer(file, history)?;
+    Ok(())
+}
+
+/// Merges all the recorded samples of a ship with a simtick in `range` into a single file,
+/// and returns its path
+pub fn export_history(
+    files: &GameFiles,
+    ship: ShipID,
+    format: HistoryFormat,
+    range: impl RangeBounds<u64>,
+) -> std::io::Result<PathBuf> {
+    let dir = history_dir(&files.history, ship);
+    let mut samples = Vec::new();
+    for path in chunk_paths(&dir) {
+        for line in BufReader::new(File::open(path)?).lines() {
+            let sample: HistorySample = serde_json::from_str(&line?)?;
+            if range.contains(&sample.simtick) {
+                samples.push(sample);
+            }
+        }
+    }
+    let path = match format {
+        HistoryFormat::Csv => dir.join("export.csv"),
+        HistoryFormat::Json => dir.join("export.json"),
+    };
+    let mut file = File::create(&path)?;
+    match format {
+        HistoryFormat::Csv => {
+            writeln!(
+                file,
+                "simtick,x (km),y (km),z (km),vx (km/day),vy (km/day),vz (km/day)"
+            )?;
+            for HistorySample {
+                simtick,
+                position: p,
+                velocity: v,
+            } in samples
+            {
+                writeln!(
+                    file,
+                    "{simtick},{},{},{},{},{},{}",
+                    p.x, p.y, p.z, v.x, v.y, v.z
+                )?;
+            }
+        }
+        HistoryFormat::Json => serde_json::to_writer(
+            file,
+            &serde_json::json!({
+                "ship": ship.as_str(),
+                "units": {"simtick": "simtick", "position": "km", "velocity": "km/day"},
+                "samples": samples,
+            }),
+        )?,
+    }
+    Ok(path)
+}
+
+fn record_history(
+    mut recorders: Query<(&ShipInfo, &Position, &Velocity, &mut RecordHistory)>,
+    files: Res<GameFiles>,
+    time: Res<GameTime>,
+) -> color_eyre::Result<()> {
+    for (info, pos, vel, mut history) in recorders.iter_mut() {
+        if time.tick() % history.interval_ticks != 0 {
+            continue;
+        }
+        let sample = HistorySample {
+            simtick: time.simtick,
+            position: pos.0,
+            velocity: vel.0,
+        };
+        history.append(&history_dir(&files.history, info.id), &sample)?;
+    }
+    Ok(())
+}
+
+fn handle_history_events(
+    mut commands: Commands,
+    mut reader: EventReader<HistoryEvent>,
+    files: Res<GameFiles>,
+    mapping: Option<Res<ShipsMapping>>,
+) -> color_eyre::Result<()> {
+    for event in reader.read() {
+        let ship = match event {
+            HistoryEvent::Start { ship, .. }
+            | HistoryEvent::Stop(ship)
+            | HistoryEvent::Export { ship, .. } => *ship,
+        };
+        let Some(&entity) = mapping.as_ref().and_then(|m| m.0.get(&ship)) else {
+            warn!("no ship with id {ship}");
+            continue;
+        };
+        match event {
+            HistoryEvent::Start { interval_ticks, .. } => {
+                let dir = history_dir(&files.history, ship);
+                commands
+                    .entity(entity)
+                    .insert(RecordHistory::resume(&dir, *interval_ticks));
+            }
+            HistoryEvent::Stop(_) => {
+                commands.entity(entity).remove::<RecordHistory>();
+            }
+            HistoryEvent::Export { format, .. } => {
+                let path = export_history(&files, ship, *format, 0..)?;
+                info!("exported the history of ship {ship} to {}", path.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+
+    use bevy::{math::DVec3, prelude::*};
+
+    use crate::{
+        game::GameFiles,
+        physics::time::{TickEvent, SIMTICKS_PER_TICK},
+        prelude::*,
+    };
+
+    use super::{
+        export_history, history_dir, record_history, HistoryFormat, RecordHistory, END_FILE,
+    };
+
+    #[test]
+    fn test_record_history() {
+        let root = tempfile::tempdir().unwrap();
+        let files = GameFiles::new(root.path()).unwrap();
+        let dir = history_dir(&files.history, id_from("s"));
+        let mut app = App::new();
+        app.add_event::<TickEvent>()
+            .init_resource::<GameTime>()
+            .insert_resource(files)
+            .add_systems(
+                Update,
+                record_history
+                    .pipe(|r: In<color_eyre::Result<()>>| r.0.unwrap())
+                    .run_if(on_event::<TickEvent>()),
+            );
+        let ship = app
+            .world_mut()
+            .spawn((
+                ShipInfo {
+                    id: id_from("s"),
+                    ..default()
+                },
+                Position(DVec3::new(1e5, 0., 0.)),
+                Velocity(DVec3::new(0., 1e3, 0.)),
+                RecordHistory::resume(&dir, 10),
+            ))
+            .id();
+        for _ in 0..1000 {
+            app.world_mut().resource_mut::<GameTime>().simtick += SIMTICKS_PER_TICK;
+            app.world_mut().send_event(TickEvent);
+            app.update();
+        }
+
+        let files = app.world().resource::<GameFiles>();
+        let path = export_history(files, id_from("s"), HistoryFormat::Csv, ..).unwrap();
+        let csv = read_to_string(path).unwrap();
+        let mut lines = csv.lines();
+        assert!(lines.next().unwrap().contains("(km/day)"));
+        let ticks: Vec<u64> = lines
+            .map(|line| {
+                let values: Vec<_> = line.split(',').collect();
+                assert_eq!(values.len(), 7);
+                assert!(values[1..]
+                    .iter()
+                    .all(|v| v.parse::<f64>().unwrap().is_finite()));
+                values[0].parse().unwrap()
+            })
+            .collect();
+        assert_eq!(ticks.len(), 100);
+        assert!(ticks.windows(2).all(|w| w[0] < w[1]));
+
+        // Removing the ship finalizes the log instead of deleting it
+        app.world_mut().despawn(ship);
+        assert!(dir.join(END_FILE).exists());
+        assert_eq!(RecordHistory::resume(&dir, 10).chunk, 1);
+    }
+}
@@ -0,0 +1,146 @@
+//! Positioning of ships from the ranges to the radio beacons around them, as an alternative to
+//! trusting the propagation of their orbit.
+//!
+//! The ranges are the actual distances between the ships and the beacons, so the fixes only
+//! differ from the positions of the ships by the errors of the trilateration
+
+use bevy::{math::DVec3, prelude::*};
+
+use crate::{
+    physics::{leapfrog::LeapfrogUpdate, prelude::*, sgp4::SGP4System, PhysicsUpdate},
+    utils::algebra::trilaterate_near,
+};
+
+use super::ShipInfo;
+
+/// Distance up to which ships pick up the signal of beacons (in km)
+pub const BEACON_SENSOR_RANGE: f64 = 2e6;
+
+/// Number of beacons in range needed to compute a fix
+pub const MIN_BEACONS: usize = 3;
+
+pub fn plugin(app: &mut App) {
+    app.register_type::<RadioBeacon>()
+        .register_type::<NavigationFix>()
+        .add_systems(
+            FixedUpdate,
+            update_navigation_fixes
+                .after(LeapfrogUpdate)
+                .after(SGP4System)
+                .in_set(PhysicsUpdate),
+        );
+}
+
+/// A beacon ships can measure their distance to, on a station or any other object with a position
+#[derive(Component, Debug, Clone, Copy, PartialEq, Reflect)]
+#[reflect(Component)]
+pub struct RadioBeacon {
+    pub frequency_mhz: f64,
+}
+
+/// Position of a ship trilaterated from the beacons in its sensor range, removed when there are
+/// less than [MIN_BEACONS] of them
+#[derive(Component, Debug, Clone, Copy, PartialEq, Reflect)]
+#[reflect(Component)]
+pub struct NavigationFix {
+    pub pos: DVec3,
+    /// Root mean square of the differences between the measured ranges and the distances from
+    /// the fix to the beacons (in km)
+    pub uncertainty_km: f64,
+}
+
+impl NavigationFix {
+    /// Fix from `(beacon position, range)` pairs. With beacons in a single plane, the side of the
+    /// plane is the one of `propagated`, the position the ship thinks it has
+    pub fn new(beacons: &[(DVec3, f64)], propagated: DVec3) -> Option<Self> {
+        let pos = trilaterate_near(beacons, propagated)?;
+        let squares: f64 = beacons
+            .iter()
+            .map(|(beacon, range)| (pos.distance(*beacon) - range).powi(2))
+            .sum();
+        Some(Self {
+            pos,
+            uncertainty_km: (squares / beacons.len() as f64).sqrt(),
+        })
+    }
+}
+
+fn update_navigation_fixes(
+    mut commands: Commands,
+    mut ships: Query<(Entity, &Position, Option<&mut NavigationFix>), With<ShipInfo>>,
+    beacons: Query<(Entity, &Position), With<RadioBeacon>>,
+) {
+    for (entity, pos, fix) in ships.iter_mut() {
+        let ranges: Vec<_> = beacons
+            .iter()
+            .filter(|(beacon, _)| *beacon != entity)
+            .map(|(_, beacon_pos)| (beacon_pos.0, beacon_pos.0.distance(pos.0)))
+            .filter(|(_, range)| *range <= BEACON_SENSOR_RANGE)
+            .collect();
+        let new_fix = (ranges.len() >= MIN_BEACONS)
+            .then(|| NavigationFix::new(&ranges, pos.0))
+            .flatten();
+        match (fix, new_fix) {
+            (Some(mut fix), Some(new_fix)) => *fix = new_fix,
+            (None, Some(new_fix)) => {
+                commands.entity(entity).insert(new_fix);
+            }
+            (Some(_), None) => {
+                commands.entity(entity).remove::<NavigationFix>();
+            }
+            (None, None) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{app::FixedMain, math::DVec3, prelude::*};
+
+    use crate::prelude::*;
+
+    use super::{NavigationFix, RadioBeacon, BEACON_SENSOR_RANGE};
+
+    #[test]
+    fn test_navigation_fix() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer));
+        app.update();
+        let pos = DVec3::new(1e9, 0., 0.);
+        app.world_mut().send_event(ShipEvent::Create(ShipInfo {
+            id: id_from("s"),
+            spawn_pos: pos,
+            spawn_speed: DVec3::ZERO,
+            spawn_orbit: None,
+        }));
+        app.update();
+        let ship = app.world().resource::<ShipsMapping>().0["s"];
+        let mut spawn_beacon = |offset: DVec3| {
+            app.world_mut()
+                .spawn((
+                    RadioBeacon {
+                        frequency_mhz: 8400.,
+                    },
+                    Position(pos + offset),
+                ))
+                .id()
+        };
+        spawn_beacon(DVec3::new(1e4, 0., 0.));
+        spawn_beacon(DVec3::new(0., 2e4, 0.));
+        let far = spawn_beacon(DVec3::new(0., 0., 2. * BEACON_SENSOR_RANGE));
+        FixedMain::run_fixed_main(app.world_mut());
+        // The third beacon is out of range
+        assert_eq!(app.world().get::<NavigationFix>(ship), None);
+
+        app.world_mut().get_mut::<Position>(far).unwrap().0 = pos + DVec3::new(-1e4, -1e4, 5e3);
+        FixedMain::run_fixed_main(app.world_mut());
+        let fix = *app.world().get::<NavigationFix>(ship).unwrap();
+        let ship_pos = app.world().get::<Position>(ship).unwrap().0;
+        assert!(fix.pos.distance(ship_pos) < 1e-3);
+        assert!(fix.uncertainty_km < 1e-3);
+
+        app.world_mut().entity_mut(far).despawn();
+        FixedMain::run_fixed_main(app.world_mut());
+        assert_eq!(app.world().get::<NavigationFix>(ship), None);
+    }
+}
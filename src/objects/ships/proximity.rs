@@ -0,0 +1,136 @@
+//! Alerts when two ships come close to each other, found with the [SpatialIndex] rather than by
+//! comparing every pair of ships
+
+use bevy::{prelude::*, utils::HashSet};
+
+use crate::physics::{
+    spatial::{SpatialIndex, SpatialUpdate},
+    Position,
+};
+
+use super::{ShipID, ShipInfo};
+
+/// Factor of the alert distance two ships must get apart by for their encounter to end, so that
+/// ships staying around the alert distance don't trigger alerts repeatedly
+const ENCOUNTER_END_FACTOR: f64 = 2.;
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<ProximityAlertDistance>()
+        .init_resource::<CloseEncounters>()
+        .add_event::<ShipAlert>()
+        .add_systems(FixedUpdate, detect_close_approaches.after(SpatialUpdate));
+}
+
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub enum ShipAlert {
+    /// Two ships came within the [ProximityAlertDistance] of each other, at the given distance
+    /// (in km). Sent once per encounter
+    CloseApproach(ShipID, ShipID, f64),
+}
+
+/// Distance under which two ships raise a [ShipAlert::CloseApproach] (in km)
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct ProximityAlertDistance(pub f64);
+
+impl Default for ProximityAlertDistance {
+    fn default() -> Self {
+        Self(10.)
+    }
+}
+
+/// Pairs of ships that already raised an alert and didn't get apart since
+#[derive(Resource, Debug, Default)]
+pub struct CloseEncounters(HashSet<(Entity, Entity)>);
+
+fn detect_close_approaches(
+    index: Res<SpatialIndex>,
+    distance: Res<ProximityAlertDistance>,
+    ships: Query<(Entity, &Position, &ShipInfo)>,
+    mut encounters: ResMut<CloseEncounters>,
+    mut alerts: EventWriter<ShipAlert>,
+) {
+    encounters
+        .0
+        .retain(|(a, b)| match (ships.get(*a), ships.get(*b)) {
+            (Ok((_, a, _)), Ok((_, b, _))) => {
+                a.0.distance(b.0) <= distance.0 * ENCOUNTER_END_FACTOR
+            }
+            _ => false,
+        });
+    for (entity, pos, info) in ships.iter() {
+        for other in index.query_radius(pos.0, distance.0) {
+            // Each pair is seen from both ships
+            if other <= entity {
+                continue;
+            }
+            let Ok((_, other_pos, other_info)) = ships.get(other) else {
+                continue;
+            };
+            if encounters.0.insert((entity, other)) {
+                let d = pos.0.distance(other_pos.0);
+                info!(
+                    "ships {} and {} are {d:.3} km apart",
+                    info.id, other_info.id
+                );
+                alerts.send(ShipAlert::CloseApproach(info.id, other_info.id, d));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{app::FixedMain, math::DVec3, prelude::*};
+
+    use crate::prelude::*;
+
+    use super::ShipAlert;
+
+    #[test]
+    fn test_close_approach() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer));
+        app.update();
+        let pos = DVec3::new(1e9, 0., 0.);
+        for (id, offset) in [("a", 0.), ("b", 5.), ("c", 1e4)] {
+            app.world_mut().send_event(ShipEvent::Create(ShipInfo {
+                id: id_from(id),
+                spawn_pos: pos + DVec3::new(0., offset, 0.),
+                spawn_speed: DVec3::ZERO,
+                spawn_orbit: None,
+            }));
+        }
+        app.update();
+        let alerts = |app: &mut App| {
+            FixedMain::run_fixed_main(app.world_mut());
+            app.world_mut()
+                .resource_mut::<Events<ShipAlert>>()
+                .drain()
+                .map(|ShipAlert::CloseApproach(a, b, _)| {
+                    let mut pair = [a, b];
+                    pair.sort();
+                    pair
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(alerts(&mut app), vec![[id_from("a"), id_from("b")]]);
+        // The encounter goes on, so there is no new alert
+        assert!(alerts(&mut app).is_empty());
+
+        let ships = app.world().resource::<ShipsMapping>().0.clone();
+        let move_ship = |app: &mut App, id: &str, offset: f64| {
+            app.world_mut().get_mut::<Position>(ships[id]).unwrap().0 =
+                pos + DVec3::new(0., offset, 0.);
+        };
+        // Getting apart but not enough to end the encounter
+        move_ship(&mut app, "b", 15.);
+        assert!(alerts(&mut app).is_empty());
+        move_ship(&mut app, "b", 5.);
+        assert!(alerts(&mut app).is_empty());
+        // A new encounter
+        move_ship(&mut app, "b", 1e3);
+        assert!(alerts(&mut app).is_empty());
+        move_ship(&mut app, "b", 1e4 + 1.);
+        assert_eq!(alerts(&mut app), vec![[id_from("b"), id_from("c")]]);
+    }
+}
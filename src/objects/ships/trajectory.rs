@@ -1,7 +1,6 @@
 use std::{
     collections::{btree_map, BTreeMap},
-    fs::{read_dir, remove_file, File},
-    io::{Read, Write},
+    fs::{read_dir, remove_file},
     iter::Peekable,
     path::{Path, PathBuf},
     sync::Arc,
@@ -11,17 +10,27 @@ use vectorize;
 
 use bevy::{math::DVec3, prelude::*};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::sync::Mutex;
 
 use crate::{
+    client::ClientMode,
     game::{Authoritative, GameFiles},
-    objects::prelude::{BodiesMapping, BodyID},
-    physics::{prelude::*, time::TickEvent},
+    objects::prelude::{Atmosphere, BodiesMapping, BodyData, BodyID, BodyInfo},
+    physics::{
+        maneuver::{aerobrake_outcome, time_to_periapsis},
+        prelude::*,
+        time::{SimStepSize, GAMETIME_PER_SIMTICK},
+        G,
+    },
     prelude::{exit_on_error_if_app, GameStage},
-    utils::algebra::orbital_to_global_matrix,
+    utils::{
+        algebra::orbital_to_global_matrix,
+        persist::{read_versioned, write_versioned, Encoding, Migration, PersistError, Versioned},
+    },
 };
 
-use super::{ShipID, ShipInfo, ShipsMapping};
+use super::{IdGenerator, ShipID, ShipInfo, ShipsMapping};
 
 pub const TRAJECTORIES_PATH: &str = "trajectories";
 
@@ -32,20 +41,26 @@ pub fn plugin(app: &mut App) {
         .add_systems(
             FixedUpdate,
             (
-                follow_trajectory.run_if(on_event::<TickEvent>()),
+                // Every update, as the aerobraking nodes wait for the periapsis
+                follow_trajectory,
                 handle_thrusts,
             )
                 .chain()
                 .in_set(TrajectoryUpdate),
         )
+        // In multiplayer, the clients follow the trajectories of their ships and send the
+        // thrusts to the server
         .add_systems(
             OnEnter(GameStage::Action),
-            dispatch_trajectories.run_if(in_state(Authoritative)),
+            dispatch_trajectories
+                .run_if(in_state(Authoritative).or_else(in_state(ClientMode::Multiplayer))),
         )
         .add_systems(
             OnEnter(GameStage::Preparation),
-            remove_old_nodes.run_if(in_state(Authoritative)),
+            remove_old_nodes
+                .run_if(in_state(Authoritative).or_else(in_state(ClientMode::Multiplayer))),
         )
+        .add_systems(Startup, seed_id_generator)
         .add_systems(Update, handle_trajectory_event.pipe(exit_on_error_if_app));
 }
 
@@ -55,10 +70,79 @@ pub struct TrajectoryUpdate;
 #[derive(Component, Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ManeuverNode {
     pub name: String,
-    pub thrust: DVec3,
+    pub kind: ManeuverKind,
     pub origin: BodyID,
 }
 
+impl ManeuverNode {
+    /// A burn of the given thrust, in the orbital frame of the ship relative to `origin`
+    pub fn impulsive(name: impl Into<String>, thrust: DVec3, origin: BodyID) -> Self {
+        Self {
+            name: name.into(),
+            kind: ManeuverKind::Impulsive(thrust),
+            origin,
+        }
+    }
+
+    /// Thrust of the node if it is a burn
+    pub fn thrust(&self) -> Option<DVec3> {
+        match self.kind {
+            ManeuverKind::Impulsive(thrust) => Some(thrust),
+            ManeuverKind::Aerobrake { .. } => None,
+        }
+    }
+
+    /// Adds to the thrust of the node if it is a burn
+    pub fn add_thrust(&mut self, thrust: DVec3) {
+        if let ManeuverKind::Impulsive(t) = &mut self.kind {
+            *t += thrust;
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ManeuverKind {
+    /// Instantaneous burn, in the orbital frame of the ship relative to the origin of the node
+    /// (forward, right and down, in km/day)
+    Impulsive(DVec3),
+    /// Pass through the atmosphere of a body at the next periapsis of the orbit, which slows the
+    /// ship down without spending any fuel
+    Aerobrake { body: BodyID },
+}
+
+/// What a ship waiting for an aerobraking pass does during the next `dt` days
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AerobrakeStep {
+    /// The periapsis is not reached yet
+    Wait,
+    /// Velocity change at the periapsis, in km/day
+    Brake(DVec3),
+    /// The pass cannot happen, for the given reason
+    Skip(&'static str),
+}
+
+/// Next step of the aerobraking pass of a ship at `rel_pos` going at `rel_speed` relative to
+/// `body` (in km and km/day), through its atmosphere at the periapsis of the osculating orbit. The
+/// speed lost at periapsis is taken off against the velocity once the periapsis is less than `dt`
+/// days away, or was passed less than `dt` days ago
+pub fn aerobrake_step(rel_pos: DVec3, rel_speed: DVec3, body: &BodyData, dt: f64) -> AerobrakeStep {
+    let Some(atmosphere) = Atmosphere::of_main_body(&body.id) else {
+        return AerobrakeStep::Skip("no atmosphere");
+    };
+    let gm = G * body.mass;
+    let Some(outcome) = aerobrake_outcome(rel_pos, rel_speed, gm, &atmosphere) else {
+        return AerobrakeStep::Skip("the periapsis is above the atmosphere");
+    };
+    let brake = AerobrakeStep::Brake(-outcome.delta_v * rel_speed.normalize_or_zero());
+    match time_to_periapsis(rel_pos, rel_speed, gm) {
+        Some(t) if t < dt => brake,
+        // Passed during the last step, going back in time leads to the periapsis
+        _ if time_to_periapsis(rel_pos, -rel_speed, gm).is_some_and(|t| t < dt) => brake,
+        None => AerobrakeStep::Skip("the ship is leaving the body"),
+        Some(_) => AerobrakeStep::Wait,
+    }
+}
+
 /// A succession of maneuver nodes sorted by order of time, with a single node per server tick
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Trajectory {
@@ -66,6 +150,49 @@ pub struct Trajectory {
     pub nodes: BTreeMap<u64, ManeuverNode>,
 }
 
+impl Versioned for Trajectory {
+    const FORMAT: &'static str = "solar4x-trajectory";
+    const VERSION: u32 = 3;
+    const ENCODING: Encoding = Encoding::Toml;
+    const MIGRATIONS: &'static [(u32, Migration)] =
+        &[(1, nodes_with_kind), (2, aerobrake_without_altitude)];
+}
+
+/// Version 2 added aerobraking nodes: the `thrust` of the nodes became a `kind` of maneuver
+fn nodes_with_kind(payload: &mut Value) -> Result<(), String> {
+    let nodes = payload
+        .get_mut("nodes")
+        .and_then(Value::as_array_mut)
+        .ok_or("no list of nodes")?;
+    for entry in nodes {
+        // The nodes are stored as [tick, node] pairs
+        let node = entry
+            .get_mut(1)
+            .and_then(Value::as_object_mut)
+            .ok_or("invalid node")?;
+        let thrust = node.remove("thrust").ok_or("node without thrust")?;
+        node.insert("kind".into(), serde_json::json!({ "Impulsive": thrust }));
+    }
+    Ok(())
+}
+
+/// Version 3 brakes at the periapsis of the orbit, instead of a declared altitude
+fn aerobrake_without_altitude(payload: &mut Value) -> Result<(), String> {
+    let nodes = payload
+        .get_mut("nodes")
+        .and_then(Value::as_array_mut)
+        .ok_or("no list of nodes")?;
+    for entry in nodes {
+        if let Some(aerobrake) = entry
+            .pointer_mut("/1/kind/Aerobrake")
+            .and_then(Value::as_object_mut)
+        {
+            aerobrake.remove("periapsis_alt_km");
+        }
+    }
+    Ok(())
+}
+
 /// A trajectory taken by an object, storing a peekable queue of all remaining maneuver nodes
 #[derive(Component, Debug)]
 pub struct CurrentTrajectory {
@@ -78,6 +205,22 @@ impl CurrentTrajectory {
             queue: trajectory.nodes.into_iter().peekable(),
         }
     }
+
+    /// Adds nodes to the remaining ones, replacing those already planned at the same ticks
+    pub fn insert_nodes(&mut self, nodes: impl IntoIterator<Item = (u64, ManeuverNode)>) {
+        let mut remaining: BTreeMap<_, _> =
+            std::mem::replace(&mut self.queue, BTreeMap::new().into_iter().peekable()).collect();
+        remaining.extend(nodes);
+        self.queue = remaining.into_iter().peekable();
+    }
+
+    /// Removes the remaining nodes planned at or after `tick`
+    pub fn remove_nodes_from(&mut self, tick: u64) {
+        let mut remaining: BTreeMap<_, _> =
+            std::mem::replace(&mut self.queue, BTreeMap::new().into_iter().peekable()).collect();
+        remaining.split_off(&tick);
+        self.queue = remaining.into_iter().peekable();
+    }
 }
 
 #[derive(Event, Debug, Clone)]
@@ -107,8 +250,7 @@ pub struct VelocityUpdate {
 #[derive(Debug)]
 pub enum TrajectoryError {
     Io(std::io::Error),
-    De(toml::de::Error),
-    Ser(toml::ser::Error),
+    Persist(PersistError),
 }
 
 impl From<std::io::Error> for TrajectoryError {
@@ -117,15 +259,12 @@ impl From<std::io::Error> for TrajectoryError {
     }
 }
 
-impl From<toml::de::Error> for TrajectoryError {
-    fn from(value: toml::de::Error) -> Self {
-        Self::De(value)
-    }
-}
-
-impl From<toml::ser::Error> for TrajectoryError {
-    fn from(value: toml::ser::Error) -> Self {
-        Self::Ser(value)
+impl From<PersistError> for TrajectoryError {
+    fn from(value: PersistError) -> Self {
+        match value {
+            PersistError::Io(err) => Self::Io(err),
+            err => Self::Persist(err),
+        }
     }
 }
 
@@ -133,8 +272,7 @@ impl std::fmt::Display for TrajectoryError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             TrajectoryError::Io(err) => write!(f, "Error when reading trajectory: {}", err),
-            TrajectoryError::De(err) => write!(f, "Error when deserializing trajectory: {}", err),
-            TrajectoryError::Ser(err) => write!(f, "Error when serializing trajectory: {}", err),
+            TrajectoryError::Persist(err) => write!(f, "Invalid trajectory: {}", err),
         }
     }
 }
@@ -142,10 +280,7 @@ impl std::fmt::Display for TrajectoryError {
 impl std::error::Error for TrajectoryError {}
 
 fn read_trajectory(path: impl AsRef<Path>) -> Result<Trajectory, TrajectoryError> {
-    let mut file = File::open(&path)?;
-    let mut buf = String::new();
-    file.read_to_string(&mut buf)?;
-    Ok(toml::from_str::<Trajectory>(&buf)?)
+    Ok(read_versioned(path)?)
 }
 
 fn build_path(dir: impl AsRef<Path>, id: ShipID) -> PathBuf {
@@ -160,25 +295,50 @@ pub fn read_ship_trajectory(
 }
 
 pub fn write_trajectory(path: impl AsRef<Path>, t: &Trajectory) -> Result<(), TrajectoryError> {
-    let s = toml::to_string_pretty(t)?;
-    Ok(File::create(path)?.write_all(s.as_bytes())?)
+    Ok(write_versioned(path, t)?)
 }
 
 fn follow_trajectory(
     mut velocity_events: EventWriter<VelocityUpdate>,
     mapping: Res<BodiesMapping>,
     coords: Query<(&Position, &Velocity)>,
+    bodies: Query<&BodyInfo>,
     mut trajectories: Query<(Entity, &mut CurrentTrajectory, &ShipInfo)>,
     time: Res<GameTime>,
+    step: Res<SimStepSize>,
 ) {
+    let dt = step.0 as f64 * GAMETIME_PER_SIMTICK;
     let events = Arc::new(Mutex::new(Vec::new()));
     trajectories.par_iter_mut().for_each(|(e, mut t, info)| {
         if let Some((tick, n)) = t.queue.peek() {
             if *tick <= time.tick() {
-                if let Some(origin) = mapping.0.get(&n.origin) {
-                    let (&Position(o_pos), &Velocity(o_speed)) = coords.get(*origin).unwrap();
-                    let (&Position(pos), &Velocity(speed)) = coords.get(e).unwrap();
-                    let thrust = orbital_to_global_matrix(o_pos, o_speed, pos, speed) * n.thrust;
+                let (&Position(pos), &Velocity(speed)) = coords.get(e).unwrap();
+                let thrust = match n.kind {
+                    ManeuverKind::Impulsive(thrust) => mapping.0.get(&n.origin).map(|origin| {
+                        let (&Position(o_pos), &Velocity(o_speed)) = coords.get(*origin).unwrap();
+                        orbital_to_global_matrix(o_pos, o_speed, pos, speed) * thrust
+                    }),
+                    ManeuverKind::Aerobrake { body } => {
+                        let Some(body) = mapping.0.get(&body) else {
+                            t.queue.next();
+                            return;
+                        };
+                        let (&Position(b_pos), &Velocity(b_speed)) = coords.get(*body).unwrap();
+                        let data = &bodies.get(*body).unwrap().0;
+                        // The body has already moved to the current simtick, while the ship is
+                        // still a step behind
+                        match aerobrake_step(pos - b_pos + b_speed * dt, speed - b_speed, data, dt)
+                        {
+                            AerobrakeStep::Wait => return,
+                            AerobrakeStep::Brake(change) => Some(change),
+                            AerobrakeStep::Skip(reason) => {
+                                warn!("ship {} cannot aerobrake at {}: {reason}", info.id, data.id);
+                                None
+                            }
+                        }
+                    }
+                };
+                if let Some(thrust) = thrust {
                     events.lock().unwrap().push(VelocityUpdate {
                         ship_id: info.id,
                         thrust,
@@ -211,15 +371,30 @@ pub fn dispatch_trajectories(
     if let Ok(dir) = read_dir(&dir.trajectories) {
         for entry in dir.flatten() {
             let path = entry.path();
-            if let Ok(traj) = read_trajectory(&path) {
-                if let Some(e) = path
-                    .file_name()
-                    .and_then(|s| s.to_str())
-                    .and_then(|s| ShipID::from(s).ok())
-                    .and_then(|id| mapping.0.get(&id))
-                {
-                    commands.entity(*e).insert(CurrentTrajectory::new(traj));
+            match read_trajectory(&path) {
+                Ok(traj) => {
+                    if let Some(e) = path
+                        .file_name()
+                        .and_then(|s| s.to_str())
+                        .and_then(|s| ShipID::from_legacy(s).ok())
+                        .and_then(|id| mapping.0.get(&id))
+                    {
+                        commands.entity(*e).insert(CurrentTrajectory::new(traj));
+                    }
                 }
+                Err(err) => warn!("skipping trajectory {}: {err}", path.display()),
+            }
+        }
+    }
+}
+
+/// The trajectories outlive the ships, so the ids of their files must not be generated again for
+/// new ships, which would follow them
+fn seed_id_generator(mut generator: ResMut<IdGenerator>, dir: Res<GameFiles>) {
+    if let Ok(dir) = read_dir(&dir.trajectories) {
+        for entry in dir.flatten() {
+            if let Some(name) = entry.file_name().to_str() {
+                generator.skip_past(name);
             }
         }
     }
@@ -285,7 +460,12 @@ mod tests {
         state::state::NextState,
     };
 
-    use crate::{objects::ships::ShipEvent, physics::time::SIMTICKS_PER_TICK, prelude::*};
+    use crate::{
+        objects::ships::ShipEvent,
+        physics::{time::SIMTICKS_PER_TICK, SECONDS_PER_DAY},
+        prelude::*,
+        utils::persist::{from_versioned_str, migrate_file},
+    };
 
     use super::*;
 
@@ -300,11 +480,7 @@ mod tests {
         Trajectory {
             nodes: BTreeMap::from([(
                 1,
-                ManeuverNode {
-                    name: "1".to_owned(),
-                    thrust: DVec3::new(1e4, 0., 0.),
-                    origin: id_from("soleil"),
-                },
+                ManeuverNode::impulsive("1", DVec3::new(1e4, 0., 0.), id_from("soleil")),
             )]),
         }
     }
@@ -314,7 +490,7 @@ mod tests {
         let mut app = new_app();
         let trajectory = new_trajectory();
         app.world_mut().send_event(TrajectoryEvent::Create {
-            ship: ShipID::from("s")?,
+            ship: ShipID::new("s")?,
             trajectory: trajectory.clone(),
         });
         app.update();
@@ -331,6 +507,61 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_seed_id_generator() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer));
+        let path = app.world().resource::<GameFiles>().trajectories.clone();
+        write_trajectory(path.join("probe-7"), &new_trajectory()).unwrap();
+        app.update();
+        let mut generator = app.world_mut().resource_mut::<IdGenerator>();
+        assert_eq!(generator.generate(|_| false), id_from("probe-8"));
+    }
+
+    #[test]
+    fn test_legacy_trajectory() {
+        // Written before the aerobraking nodes, without an envelope
+        let v1 = r#"
+nodes = [
+    [1, { name = "1", thrust = [10000.0, 0.0, 0.0], origin = "soleil" }],
+    [5, { name = "2", thrust = [0.0, -5.0, 2.0], origin = "terre" }],
+]
+"#;
+        let traj: Trajectory = from_versioned_str(v1).unwrap();
+        assert_eq!(traj.nodes[&1], new_trajectory().nodes[&1]);
+        assert_eq!(traj.nodes[&5].thrust(), Some(DVec3::new(0., -5., 2.)));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("s");
+        std::fs::write(&path, v1).unwrap();
+        assert_eq!(migrate_file::<Trajectory>(&path).unwrap(), 1);
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.contains("format = \"solar4x-trajectory\""));
+        assert_eq!(read_trajectory(&path).unwrap().nodes, traj.nodes);
+
+        let newer = content.replace("version = 3", "version = 4");
+        let error = from_versioned_str::<Trajectory>(&newer).unwrap_err();
+        assert!(error.to_string().contains("created by a newer version"));
+
+        // Aerobraking at a declared altitude
+        let v2 = r#"
+format = "solar4x-trajectory"
+version = 2
+
+[payload]
+nodes = [
+    [7, { name = "3", kind = { Aerobrake = { periapsis_alt_km = 90.0, body = "terre" } }, origin = "terre" }],
+]
+"#;
+        let traj: Trajectory = from_versioned_str(v2).unwrap();
+        assert_eq!(
+            traj.nodes[&7].kind,
+            ManeuverKind::Aerobrake {
+                body: id_from("terre")
+            }
+        );
+    }
+
     #[test]
     fn test_dispatch_trajectory() {
         let mut app = new_app();
@@ -339,6 +570,7 @@ mod tests {
             id,
             spawn_pos: DVec3::new(1e6, 0., 0.),
             spawn_speed: DVec3::new(0., 1e6, 0.),
+            spawn_orbit: None,
         }));
         let trajectory = new_trajectory();
         app.world_mut().send_event(TrajectoryEvent::Create {
@@ -367,6 +599,7 @@ mod tests {
             id,
             spawn_pos: DVec3::new(0., 0., 1e10),
             spawn_speed: DVec3::new(0., 1e4, 0.),
+            spawn_orbit: None,
         }));
         let trajectory = new_trajectory();
         app.world_mut().send_event(TrajectoryEvent::Create {
@@ -392,4 +625,125 @@ mod tests {
             .single(app.world());
         assert!((ship_speed.0 - DVec3::new(0., 2e4, 0.)).length() < 10.);
     }
+
+    #[test]
+    fn test_aerobrake_node() {
+        let mut app = new_app();
+        let earth = id_from("terre");
+        let earth_coords = |app: &mut App| {
+            let world = app.world_mut();
+            let e = world.resource::<BodiesMapping>().0[&earth];
+            let entity = world.entity(e);
+            (
+                entity.get::<Position>().unwrap().0,
+                entity.get::<Velocity>().unwrap().0,
+            )
+        };
+        let (earth_pos, earth_speed) = earth_coords(&mut app);
+        // Hyperbolic approach with a periapsis 90km above the ground, 15 simticks away
+        let gm = 398600. * SECONDS_PER_DAY * SECONDS_PER_DAY;
+        let periapsis = 6371. + 90.;
+        let (mut pos, mut speed) = (
+            DVec3::new(periapsis, 0., 0.),
+            DVec3::new(
+                0.,
+                (16. + 2. * 398600. / periapsis).sqrt() * SECONDS_PER_DAY,
+                0.,
+            ),
+        );
+        let dt = -1e-6;
+        for _ in 0..15_000 {
+            speed += -gm * pos / pos.length().powi(3) * dt / 2.;
+            pos += speed * dt;
+            speed += -gm * pos / pos.length().powi(3) * dt / 2.;
+        }
+        let id = id_from("s");
+        app.world_mut().send_event(ShipEvent::Create(ShipInfo {
+            id,
+            spawn_pos: earth_pos + pos,
+            spawn_speed: earth_speed + speed,
+            spawn_orbit: None,
+        }));
+        let node = ManeuverNode {
+            name: "aerobrake".into(),
+            kind: ManeuverKind::Aerobrake { body: earth },
+            origin: earth,
+        };
+        app.world_mut().send_event(TrajectoryEvent::Create {
+            ship: id,
+            trajectory: Trajectory {
+                nodes: BTreeMap::from([(1, node.clone())]),
+            },
+        });
+        app.update();
+        app.world_mut()
+            .resource_mut::<NextState<GameStage>>()
+            .set(GameStage::Action);
+        app.update();
+        let ship_coords = |app: &mut App| {
+            let world = app.world_mut();
+            let (pos, speed) = world
+                .query_filtered::<(&Position, &Velocity), With<ShipInfo>>()
+                .single(world);
+            (pos.0, speed.0)
+        };
+        let remaining = |app: &mut App| {
+            let world = app.world_mut();
+            let mut query = world.query::<&mut CurrentTrajectory>();
+            query.single_mut(world).queue.peek().is_some()
+        };
+        let mut simtick = 0;
+        while simtick < SIMTICKS_PER_TICK {
+            app.update();
+            simtick = app.world().resource::<GameTime>().simtick;
+        }
+        // The node waits for the periapsis
+        let mut before = (DVec3::ZERO, DVec3::ZERO);
+        while remaining(&mut app) {
+            assert!(simtick < 3 * SIMTICKS_PER_TICK, "the pass was missed");
+            let (earth_pos, earth_speed) = earth_coords(&mut app);
+            let (pos, speed) = ship_coords(&mut app);
+            before = (pos - earth_pos, speed - earth_speed);
+            FixedMain::run_fixed_main(app.world_mut());
+            simtick = app.world().resource::<GameTime>().simtick;
+        }
+        assert!(simtick > SIMTICKS_PER_TICK + 5, "{simtick}");
+        // Braking around the periapsis, against the velocity, by a fraction of the speed
+        assert!((before.0.length() - periapsis).abs() < 1000.);
+        let events = app.world().resource::<Events<VelocityUpdate>>();
+        let thrust = events.iter_current_update_events().next().unwrap().thrust;
+        assert!(thrust.normalize().dot(before.1.normalize()) < -0.99);
+        let dv = thrust.length() / SECONDS_PER_DAY;
+        assert!((0.1..5.).contains(&dv), "{dv}");
+    }
+
+    #[test]
+    fn test_aerobrake_skipped_above_atmosphere() {
+        let earth = BodyData {
+            id: id_from("terre"),
+            mass: 5.97e24,
+            ..Default::default()
+        };
+        let gm = G * earth.mass;
+        let (pos, speed) = (DVec3::new(6371. + 500., 0., 0.), DVec3::new(0., 1e6, 0.));
+        assert_eq!(
+            aerobrake_step(pos, speed, &earth, 1.),
+            AerobrakeStep::Skip("the periapsis is above the atmosphere")
+        );
+        let periapsis = 6371. + 90.;
+        let speed = DVec3::new(0., (2.5 * gm / periapsis).sqrt(), 0.);
+        let pos = DVec3::new(periapsis, 0., 0.);
+        assert!(matches!(
+            aerobrake_step(pos - speed * 1e-3, speed, &earth, 1e-4),
+            AerobrakeStep::Wait
+        ));
+        assert!(matches!(
+            aerobrake_step(pos - speed * 1e-5, speed, &earth, 1e-4),
+            AerobrakeStep::Brake(_)
+        ));
+        assert_eq!(
+            aerobrake_step(pos + speed * 1e-3, speed, &earth, 1e-4),
+            AerobrakeStep::Skip("the ship is leaving the body")
+        );
+    }
 }
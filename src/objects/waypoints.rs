@@ -0,0 +1,506 @@
+//! Named points of the world, shared between the screens and the console. A waypoint is either a
+//! fixed position, a position following a body, or the position of a ship at a future simtick.
+//!
+//! The waypoints are kept in the game files so that they survive restarts. They are local to each
+//! instance: the waypoints of the server are not sent to its clients
+
+use std::{collections::BTreeMap, io, path::Path};
+
+use arrayvec::ArrayString;
+use bevy::{math::DVec3, prelude::*};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    game::{GameFiles, Loaded},
+    physics::{predictions::Prediction, Position},
+    utils::persist::{
+        read_versioned, write_versioned, Encoding, Migration, PersistError, Versioned,
+    },
+};
+
+use super::{
+    bodies::lookup::BodyLookupError,
+    id::{sanitize_legacy_id, MAX_ID_LENGTH},
+    prelude::{BodiesMapping, BodyID, ShipEvent, ShipID, ShipsMapping},
+    ObjectsUpdate,
+};
+
+/// Name of the file of the waypoints, in the game files
+pub const WAYPOINTS_FILE: &str = "waypoints.json";
+
+pub type WaypointID = ArrayString<MAX_ID_LENGTH>;
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<Waypoints>()
+        .init_resource::<WaypointPositions>()
+        .add_systems(Startup, load_waypoints)
+        .add_systems(Update, resolve_waypoints.in_set(ObjectsUpdate))
+        .add_systems(
+            Update,
+            save_waypoints
+                .run_if(waypoints_edited)
+                .after(resolve_waypoints),
+        )
+        .add_systems(OnExit(Loaded), clear_positions);
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum WaypointKind {
+    /// A fixed position (in km)
+    Fixed(DVec3),
+    /// A position relative to a body (in km), which moves along with it
+    Body { body: BodyID, offset: DVec3 },
+    /// The position of a ship at a given simtick, read from its predictions
+    Ship { ship: ShipID, simtick: u64 },
+}
+
+impl std::fmt::Display for WaypointKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Fixed(pos) => write!(f, "fixed at {pos}"),
+            Self::Body { body, offset } => write!(f, "{offset} from {body}"),
+            Self::Ship { ship, simtick } => write!(f, "ship {ship} at simtick {simtick}"),
+        }
+    }
+}
+
+/// Waypoints by name
+#[derive(Resource, Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct Waypoints(pub BTreeMap<WaypointID, WaypointKind>);
+
+impl Waypoints {
+    /// Reads the waypoints at `path`, which are empty if the file doesn't exist
+    pub fn load(path: &Path) -> Result<Self, PersistError> {
+        match read_versioned(path) {
+            Err(PersistError::Io(e)) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            result => result,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), PersistError> {
+        write_versioned(path, self)
+    }
+
+    /// Adds the waypoint `name`, replacing the one that had the same name
+    pub fn insert(&mut self, name: &str, kind: WaypointKind) -> Result<WaypointID, WaypointError> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(WaypointError::MissingField("name"));
+        }
+        let id = WaypointID::from(name).map_err(|_| WaypointError::NameTooLong)?;
+        self.0.insert(id, kind);
+        Ok(id)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&WaypointKind> {
+        self.0.get(&WaypointID::from(name.trim()).ok()?)
+    }
+}
+
+impl Versioned for Waypoints {
+    const FORMAT: &'static str = "solar4x-waypoints";
+    const VERSION: u32 = 2;
+    const ENCODING: Encoding = Encoding::Json;
+    const MIGRATIONS: &'static [(u32, Migration)] = &[(1, valid_object_ids)];
+}
+
+/// Version 2 validates the ids of ships and bodies: the ones that contain whitespace or
+/// characters that are not printable ASCII are sanitized
+fn valid_object_ids(payload: &mut Value) -> Result<(), String> {
+    let waypoints = payload.as_object_mut().ok_or("invalid waypoints")?;
+    for kind in waypoints.values_mut() {
+        for (variant, field) in [("Ship", "ship"), ("Body", "body")] {
+            if let Some(Value::String(id)) = kind.get_mut(variant).and_then(|v| v.get_mut(field)) {
+                *id = sanitize_legacy_id(id);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Current position of each waypoint (in km). A waypoint whose object doesn't exist yet has no
+/// position
+#[derive(Resource, Default, Debug, Clone)]
+pub struct WaypointPositions(pub BTreeMap<WaypointID, DVec3>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WaypointError {
+    NameTooLong,
+    /// The kind is not one of "fixed", "body" or "ship"
+    UnknownKind(String),
+    MissingField(&'static str),
+    InvalidNumber(String),
+    UnknownBody(BodyLookupError),
+    UnknownShip(String),
+}
+
+impl std::error::Error for WaypointError {}
+
+impl std::fmt::Display for WaypointError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NameTooLong => write!(
+                f,
+                "the name of a waypoint is at most {MAX_ID_LENGTH} characters long"
+            ),
+            Self::UnknownKind(kind) => write!(
+                f,
+                "unknown kind of waypoint \"{kind}\", expected fixed, body or ship"
+            ),
+            Self::MissingField(field) => write!(f, "missing {field}"),
+            Self::InvalidNumber(value) => write!(f, "\"{value}\" is not a valid number"),
+            Self::UnknownBody(e) => write!(f, "{e}"),
+            Self::UnknownShip(id) => write!(f, "no ship with id \"{id}\""),
+        }
+    }
+}
+
+/// Reads a waypoint of the given kind ("fixed", "body" or "ship") from its fields, which are the
+/// coordinates of a fixed waypoint, the body and the optional offset of a body waypoint, or the
+/// ship and the simtick of a ship waypoint. Blank fields are missing
+pub fn parse_waypoint(
+    kind: &str,
+    fields: &[&str],
+    bodies: &BodiesMapping,
+    ships: &ShipsMapping,
+) -> Result<WaypointKind, WaypointError> {
+    let field = |i: usize, name| {
+        fields
+            .get(i)
+            .map(|f| f.trim())
+            .filter(|f| !f.is_empty())
+            .ok_or(WaypointError::MissingField(name))
+    };
+    let vector = |start: usize| -> Result<DVec3, WaypointError> {
+        let mut coords = ["x", "y", "z"].into_iter().enumerate().map(|(i, name)| {
+            let value = field(start + i, name)?;
+            value
+                .parse::<f64>()
+                .map_err(|_| WaypointError::InvalidNumber(value.into()))
+        });
+        Ok(DVec3::new(
+            coords.next().unwrap()?,
+            coords.next().unwrap()?,
+            coords.next().unwrap()?,
+        ))
+    };
+    match kind.trim() {
+        "fixed" => Ok(WaypointKind::Fixed(vector(0)?)),
+        "body" => {
+            let body = bodies
+                .lookup(field(0, "body")?)
+                .map_err(WaypointError::UnknownBody)?;
+            let offset = if (1..4).all(|i| field(i, "").is_err()) {
+                DVec3::ZERO
+            } else {
+                vector(1)?
+            };
+            Ok(WaypointKind::Body { body, offset })
+        }
+        "ship" => {
+            let id = field(0, "ship")?;
+            let ship = ShipID::new(id)
+                .ok()
+                .filter(|ship| ships.0.contains_key(ship))
+                .ok_or_else(|| WaypointError::UnknownShip(id.into()))?;
+            let simtick = field(1, "simtick")?;
+            let simtick = simtick
+                .parse()
+                .map_err(|_| WaypointError::InvalidNumber(simtick.into()))?;
+            Ok(WaypointKind::Ship { ship, simtick })
+        }
+        kind => Err(WaypointError::UnknownKind(kind.into())),
+    }
+}
+
+fn load_waypoints(mut waypoints: ResMut<Waypoints>, files: Res<GameFiles>) {
+    match Waypoints::load(&files.root.join(WAYPOINTS_FILE)) {
+        // Not an edit, so it isn't saved back
+        Ok(loaded) => *waypoints.bypass_change_detection() = loaded,
+        Err(e) => warn!("could not read the waypoints: {e}"),
+    }
+}
+
+fn waypoints_edited(waypoints: Res<Waypoints>) -> bool {
+    waypoints.is_changed() && !waypoints.is_added()
+}
+
+fn save_waypoints(waypoints: Res<Waypoints>, files: Res<GameFiles>) {
+    if let Err(e) = waypoints.save(&files.root.join(WAYPOINTS_FILE)) {
+        warn!("could not save the waypoints: {e}");
+    }
+}
+
+/// The objects are despawned, so their last positions must not turn the waypoints into fixed ones
+/// when they are not spawned again right away
+fn clear_positions(mut positions: ResMut<WaypointPositions>) {
+    positions.0.clear();
+}
+
+/// Updates the positions of the waypoints. A waypoint whose object disappears becomes a fixed one,
+/// at its last position
+fn resolve_waypoints(
+    mut waypoints: ResMut<Waypoints>,
+    mut positions: ResMut<WaypointPositions>,
+    mut ship_events: EventReader<ShipEvent>,
+    bodies: Res<BodiesMapping>,
+    ships: Res<ShipsMapping>,
+    objects: Query<&Position, Without<Prediction>>,
+    predictions: Query<(&Prediction, &Position)>,
+) {
+    for event in ship_events.read() {
+        if let ShipEvent::Renamed { from, to } = event {
+            for kind in waypoints.0.values_mut() {
+                if let WaypointKind::Ship { ship, .. } = kind {
+                    if ship == from {
+                        *ship = *to;
+                    }
+                }
+            }
+        }
+    }
+    positions.0.retain(|name, _| waypoints.0.contains_key(name));
+    let mut degraded = Vec::new();
+    for (name, kind) in waypoints.0.iter() {
+        let pos = match *kind {
+            WaypointKind::Fixed(pos) => Some(pos),
+            WaypointKind::Body { body, offset } => bodies
+                .0
+                .get(&body)
+                .and_then(|&e| objects.get(e).ok())
+                .map(|p| p.0 + offset),
+            // The prediction closest to the simtick, or the current position of the ship while it
+            // has no prediction
+            WaypointKind::Ship { ship, simtick } => ships.0.get(&ship).and_then(|&e| {
+                predictions
+                    .iter()
+                    .filter(|(p, _)| p.ship == e)
+                    .min_by_key(|(p, _)| p.simtick.abs_diff(simtick))
+                    .map(|(_, pos)| pos.0)
+                    .or_else(|| objects.get(e).ok().map(|p| p.0))
+            }),
+        };
+        match (pos, positions.0.get(name)) {
+            (Some(pos), _) => {
+                positions.0.insert(*name, pos);
+            }
+            (None, Some(&last)) => degraded.push((*name, last)),
+            (None, None) => {}
+        }
+    }
+    for (name, last) in degraded {
+        warn!(
+            "the object of waypoint {name} ({}) disappeared, it is now fixed at {last}",
+            waypoints.0[&name]
+        );
+        waypoints.0.insert(name, WaypointKind::Fixed(last));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{math::DVec3, prelude::*};
+
+    use crate::{
+        game::GameFiles,
+        objects::bodies::lookup::BodyLookupError,
+        physics::{predictions::Prediction, Position},
+        prelude::*,
+    };
+
+    use super::{
+        parse_waypoint, WaypointError, WaypointKind, WaypointPositions, Waypoints, WAYPOINTS_FILE,
+    };
+
+    fn new_app() -> App {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer));
+        app.update();
+        app.update();
+        app.world_mut().send_event(ShipEvent::Create(ShipInfo {
+            id: id_from("s"),
+            spawn_pos: DVec3::new(1e9, 0., 0.),
+            ..Default::default()
+        }));
+        app.update();
+        app
+    }
+
+    fn position(app: &App, name: &str) -> Option<DVec3> {
+        app.world()
+            .resource::<WaypointPositions>()
+            .0
+            .get(name)
+            .copied()
+    }
+
+    #[test]
+    fn test_resolve_waypoints() {
+        let mut app = new_app();
+        let world = app.world_mut();
+        let ship = world.resource::<ShipsMapping>().0["s"];
+        let earth = world.resource::<BodiesMapping>().0["terre"];
+        let offset = DVec3::new(1e4, 0., 0.);
+        let mut waypoints = world.resource_mut::<Waypoints>();
+        for (name, kind) in [
+            ("fixed", WaypointKind::Fixed(DVec3::ONE)),
+            (
+                "orbit",
+                WaypointKind::Body {
+                    body: id_from("terre"),
+                    offset,
+                },
+            ),
+            (
+                "rendezvous",
+                WaypointKind::Ship {
+                    ship: id_from("s"),
+                    simtick: 100,
+                },
+            ),
+        ] {
+            waypoints.insert(name, kind).unwrap();
+        }
+        app.update();
+        assert_eq!(position(&app, "fixed"), Some(DVec3::ONE));
+        let earth_pos = app.world().get::<Position>(earth).unwrap().0;
+        assert_eq!(position(&app, "orbit"), Some(earth_pos + offset));
+        // Without predictions, the ship waypoint is at the ship
+        let ship_pos = app.world().get::<Position>(ship).unwrap().0;
+        assert_eq!(position(&app, "rendezvous"), Some(ship_pos));
+
+        // The prediction closest to the simtick is used
+        for (index, simtick) in [(0, 10), (1, 90), (2, 200)] {
+            app.world_mut().spawn((
+                Prediction {
+                    ship,
+                    index,
+                    simtick,
+                },
+                Position(DVec3::splat(simtick as f64)),
+            ));
+        }
+        app.update();
+        assert_eq!(position(&app, "rendezvous"), Some(DVec3::splat(90.)));
+    }
+
+    #[test]
+    fn test_degraded_waypoint() {
+        let mut app = new_app();
+        let kind = WaypointKind::Ship {
+            ship: id_from("s"),
+            simtick: 0,
+        };
+        app.world_mut()
+            .resource_mut::<Waypoints>()
+            .insert("rendezvous", kind)
+            .unwrap();
+        app.update();
+        let last = position(&app, "rendezvous").unwrap();
+
+        app.world_mut().send_event(ShipEvent::Remove(id_from("s")));
+        app.update();
+        app.update();
+        let waypoints = app.world().resource::<Waypoints>();
+        assert_eq!(
+            waypoints.get("rendezvous"),
+            Some(&WaypointKind::Fixed(last))
+        );
+        assert_eq!(position(&app, "rendezvous"), Some(last));
+        // The change is saved
+        let path = app
+            .world()
+            .resource::<GameFiles>()
+            .root
+            .join(WAYPOINTS_FILE);
+        assert_eq!(&Waypoints::load(&path).unwrap(), waypoints);
+    }
+
+    #[test]
+    fn test_legacy_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(WAYPOINTS_FILE);
+        std::fs::write(
+            &path,
+            r#"{
+                "rendezvous": { "Ship": { "ship": "cargo ship", "simtick": 3 } },
+                "orbit": { "Body": { "body": "terre", "offset": [1.0, 0.0, 0.0] } }
+            }"#,
+        )
+        .unwrap();
+        let waypoints = Waypoints::load(&path).unwrap();
+        assert_eq!(
+            waypoints.get("rendezvous"),
+            Some(&WaypointKind::Ship {
+                ship: id_from("cargo_ship"),
+                simtick: 3
+            })
+        );
+        assert_eq!(
+            waypoints.get("orbit"),
+            Some(&WaypointKind::Body {
+                body: id_from("terre"),
+                offset: DVec3::X
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_waypoint() {
+        let app = new_app();
+        let (bodies, ships) = (
+            app.world().resource::<BodiesMapping>(),
+            app.world().resource::<ShipsMapping>(),
+        );
+        let parse = |kind, fields: &[&str]| parse_waypoint(kind, fields, bodies, ships);
+        assert_eq!(
+            parse("fixed", &["1", "2", "3"]),
+            Ok(WaypointKind::Fixed(DVec3::new(1., 2., 3.)))
+        );
+        assert_eq!(
+            parse("fixed", &["1", " ", "3"]),
+            Err(WaypointError::MissingField("y"))
+        );
+        assert_eq!(
+            parse("fixed", &["1", "two", "3"]),
+            Err(WaypointError::InvalidNumber("two".into()))
+        );
+        // Bodies are looked up by name, and the offset is optional
+        assert_eq!(
+            parse("body", &["Earth"]),
+            Ok(WaypointKind::Body {
+                body: id_from("terre"),
+                offset: DVec3::ZERO
+            })
+        );
+        assert_eq!(
+            parse("body", &["terre", "", "", ""]),
+            parse("body", &["terre"])
+        );
+        assert!(matches!(
+            parse("body", &["vulcan"]),
+            Err(WaypointError::UnknownBody(BodyLookupError::Unknown(..)))
+        ));
+        assert_eq!(
+            parse("ship", &["s", "42"]),
+            Ok(WaypointKind::Ship {
+                ship: id_from("s"),
+                simtick: 42
+            })
+        );
+        assert_eq!(
+            parse("ship", &["t", "42"]),
+            Err(WaypointError::UnknownShip("t".into()))
+        );
+        assert_eq!(
+            parse("comet", &[]),
+            Err(WaypointError::UnknownKind("comet".into()))
+        );
+        let mut waypoints = Waypoints::default();
+        assert_eq!(
+            waypoints.insert(&"a".repeat(100), WaypointKind::Fixed(DVec3::ZERO)),
+            Err(WaypointError::NameTooLong)
+        );
+    }
+}
@@ -3,54 +3,76 @@ use influence::InfluenceUpdate;
 use leapfrog::LeapfrogUpdate;
 use orbit::OrbitsUpdate;
 use serde::{Deserialize, Serialize};
-use time::{TimeUpdate, ToggleTime};
+use sgp4::SGP4System;
+use time::{time_running, TimeUpdate};
 
 use crate::{objects::ships::trajectory::TrajectoryUpdate, server::CommandSet};
 
+pub mod gravity;
 pub mod influence;
+pub mod inspiral;
 pub mod leapfrog;
+pub mod maneuver;
+pub mod optimizer;
 pub mod orbit;
 pub mod predictions;
+pub mod rk4;
+pub mod sgp4;
+pub mod spatial;
 pub mod time;
 
-const SECONDS_PER_DAY: f64 = 24. * 3600.;
+pub const SECONDS_PER_DAY: f64 = 24. * 3600.;
 
 /// Gravitationnal constant in km3kg-1d-2
 pub const G: f64 = 6.6743e-11 * SECONDS_PER_DAY * SECONDS_PER_DAY * 1e-9;
 
+/// Astronomical unit in km
+pub const AU: f64 = 149_597_870.7;
+
 pub(crate) mod prelude {
     pub use super::{
         influence::Influenced,
-        leapfrog::Acceleration,
+        leapfrog::{Acceleration, AccelerationLog},
         orbit::{EllipticalOrbit, SystemSize},
         predictions::Prediction,
-        time::{GameTime, ToggleTime},
+        time::{GameTime, PhysicsRate, ToggleTime},
         Mass, Position, Velocity,
     };
 }
 
-#[derive(Component, Default, Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Component, Default, Debug, Clone, Copy, Serialize, Deserialize, Reflect)]
+#[reflect(Component)]
 pub struct Position(pub DVec3);
 
-#[derive(Component, Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[derive(Component, Debug, Default, Clone, Copy, Serialize, Deserialize, Reflect)]
+#[reflect(Component)]
 pub struct Velocity(pub DVec3);
 
-#[derive(Component, Clone, Copy)]
+#[derive(Component, Clone, Copy, Default, Reflect)]
+#[reflect(Component)]
 pub struct Mass(pub f64);
 
 pub struct PhysicsPlugin;
 
 impl Plugin for PhysicsPlugin {
     fn build(&self, app: &mut App) {
-        info!("loading PhysicsPlugin");
-        info!("adding plugins : orbit::plugin , inflence::plugin, leapfrog::plugin, time::plugin");
+        debug!("loading PhysicsPlugin");
+        debug!("adding plugins : orbit::plugin , inflence::plugin, leapfrog::plugin, time::plugin");
         app.add_plugins((
             orbit::plugin,
+            gravity::plugin,
+            maneuver::plugin,
             influence::plugin,
+            inspiral::plugin,
             leapfrog::plugin,
+            sgp4::plugin,
+            spatial::plugin,
             time::plugin,
-        ));
-        info!("configuring sets : (TimeUpdate,OrbitsUpdate,InfluenceUpdate,TrajectoryUpdate,LeapfrogUpdate,).chain().in_set(PhysicsUpdate).run_if(resource_equals(ToggleTime(true)))");
+        ))
+        .register_type::<Position>()
+        .register_type::<Velocity>()
+        .register_type::<Mass>();
+        debug!("configuring sets : (TimeUpdate,OrbitsUpdate,InfluenceUpdate,TrajectoryUpdate,LeapfrogUpdate,).chain().in_set(PhysicsUpdate).run_if(time_running)");
         app.configure_sets(
             FixedUpdate,
             (
@@ -59,11 +81,12 @@ impl Plugin for PhysicsPlugin {
                 InfluenceUpdate,
                 TrajectoryUpdate,
                 LeapfrogUpdate,
+                SGP4System,
                 CommandSet,
             )
                 .chain()
                 .in_set(PhysicsUpdate)
-                .run_if(resource_equals(ToggleTime(true))),
+                .run_if(time_running),
         );
     }
 }
@@ -0,0 +1,334 @@
+//! Gravity fields of non-spherical bodies.
+//!
+//! The coefficients of a body are read from `gamefiles/gravity/<body_id>.shc`, a file with one
+//! `n m Cnm Snm` line per coefficient, fully normalized. Bodies without such a file are point
+//! masses whatever the [GravityModel].
+
+use std::{
+    f64::consts::TAU,
+    fs::read_to_string,
+    io::{Error, ErrorKind},
+    path::Path,
+};
+
+use bevy::{
+    math::{DMat3, DVec3},
+    prelude::*,
+};
+
+use crate::{
+    game::{loading::LoadingPhase, GameFiles},
+    objects::prelude::BodyInfo,
+    utils::algebra::spin_axis_direction,
+};
+
+use super::orbit::{EllipticalOrbit, OrbitsUpdate};
+
+pub const GRAVITY_PATH: &str = "gravity";
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<GravityModel>().add_systems(
+        OnEnter(LoadingPhase::ComputingOrbits),
+        load_gravity_fields.before(OrbitsUpdate),
+    );
+}
+
+/// How the gravity of bodies with known coefficients is computed
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq)]
+pub enum GravityModel {
+    #[default]
+    PointMass,
+    /// Only the oblateness of bodies, which dominates the other terms
+    J2Only,
+    SphericalHarmonics {
+        max_degree: u32,
+    },
+}
+
+/// Fully normalized coefficients `(Cnm, Snm)` of the gravity field of a body, indexed by degree
+/// then order
+#[derive(Component, Debug, Clone, Default, PartialEq)]
+pub struct GravityCoefficients(pub Vec<Vec<(f64, f64)>>);
+
+impl GravityCoefficients {
+    pub fn degree(&self) -> usize {
+        self.0.len().saturating_sub(1)
+    }
+
+    pub fn get(&self, n: usize, m: usize) -> (f64, f64) {
+        self.0
+            .get(n)
+            .and_then(|row| row.get(m))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Unnormalized coefficients, as used by the recursion in [harmonics_perturbation]
+    fn unnormalized(&self, n: usize, m: usize) -> (f64, f64) {
+        let (c, s) = self.get(n, m);
+        let factorial_ratio: f64 = (n - m + 1..=n + m).map(|k| 1. / k as f64).product();
+        let norm = ((if m == 0 { 1. } else { 2. }) * (2 * n + 1) as f64 * factorial_ratio).sqrt();
+        (c * norm, s * norm)
+    }
+
+    pub fn j2(&self) -> f64 {
+        -self.unnormalized(2, 0).0
+    }
+}
+
+pub fn load_gravity_coefficients(path: &Path) -> Result<GravityCoefficients, Error> {
+    let mut coefficients = GravityCoefficients::default();
+    for line in read_to_string(path)?.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |e: String| Error::new(ErrorKind::InvalidData, format!("{line:?}: {e}"));
+        let values: Vec<_> = line.split_whitespace().collect();
+        let [n, m, c, s] = values[..] else {
+            return Err(invalid("expected 4 values".into()));
+        };
+        let (n, m): (usize, usize) = (
+            n.parse().map_err(|e| invalid(format!("{e}")))?,
+            m.parse().map_err(|e| invalid(format!("{e}")))?,
+        );
+        if m > n {
+            return Err(invalid("the order can't exceed the degree".into()));
+        }
+        let (c, s): (f64, f64) = (
+            c.parse().map_err(|e| invalid(format!("{e}")))?,
+            s.parse().map_err(|e| invalid(format!("{e}")))?,
+        );
+        if coefficients.0.len() <= n {
+            coefficients.0.resize_with(n + 1, Default::default);
+        }
+        let row = &mut coefficients.0[n];
+        row.resize(n + 1, (0., 0.));
+        row[m] = (c, s);
+    }
+    for (n, row) in coefficients.0.iter_mut().enumerate() {
+        row.resize(n + 1, (0., 0.));
+    }
+    Ok(coefficients)
+}
+
+/// What is needed to compute the gravity of a body beyond its point mass
+pub struct GravityField<'a> {
+    pub coefficients: &'a GravityCoefficients,
+    /// Reference radius of the coefficients (in km)
+    pub radius: f64,
+    /// Axes of the body-fixed frame in the global frame, the third one being the spin axis
+    pub frame: DMat3,
+}
+
+impl GravityModel {
+    /// Acceleration caused by the field of a body on top of its point mass, at `rel_pos` from it
+    pub fn perturbation(&self, rel_pos: DVec3, gm: f64, field: &GravityField) -> DVec3 {
+        match self {
+            Self::PointMass => DVec3::ZERO,
+            Self::J2Only => j2_perturbation(
+                rel_pos,
+                field.frame.z_axis,
+                gm,
+                field.radius,
+                field.coefficients.j2(),
+            ),
+            Self::SphericalHarmonics { max_degree } => {
+                field.frame
+                    * harmonics_perturbation(
+                        field.frame.transpose() * rel_pos,
+                        gm,
+                        field.radius,
+                        field.coefficients,
+                        *max_degree as usize,
+                    )
+            }
+        }
+    }
+}
+
+/// Axes of the frame rotating with a body, whose spin axis is `axis`, after `days` days
+pub fn body_fixed_frame(axis: DVec3, rotation_period_hours: f64, days: f64) -> DMat3 {
+    let angle = if rotation_period_hours == 0. {
+        0.
+    } else {
+        TAU * days * 24. / rotation_period_hours
+    };
+    let x0 = axis.any_orthonormal_vector();
+    let x = x0 * angle.cos() + axis.cross(x0) * angle.sin();
+    DMat3::from_cols(x, axis.cross(x), axis)
+}
+
+/// Acceleration caused by the oblateness of a body of spin axis `axis`
+pub fn j2_perturbation(rel_pos: DVec3, axis: DVec3, gm: f64, radius: f64, j2: f64) -> DVec3 {
+    let r2 = rel_pos.length_squared();
+    let z = rel_pos.dot(axis);
+    -1.5 * j2 * gm * radius * radius / (r2 * r2 * r2.sqrt())
+        * ((1. - 5. * z * z / r2) * rel_pos + 2. * z * axis)
+}
+
+/// Acceleration caused by the terms of degree 2 to `max_degree` of the field, at `rel_pos` given
+/// in the body-fixed frame.
+///
+/// See Montenbruck & Gill, Satellite Orbits, section 3.2.5: the solid harmonics `V` and `W` are
+/// computed with the recursion of the associated Legendre polynomials
+pub fn harmonics_perturbation(
+    rel_pos: DVec3,
+    gm: f64,
+    radius: f64,
+    coefficients: &GravityCoefficients,
+    max_degree: usize,
+) -> DVec3 {
+    let n_max = max_degree.min(coefficients.degree());
+    if n_max < 2 {
+        return DVec3::ZERO;
+    }
+    let r2 = rel_pos.length_squared();
+    let DVec3 { x, y, z } = rel_pos * radius / r2;
+    let rho = radius * radius / r2;
+    let size = n_max + 2;
+    let mut v = vec![vec![0.; size + 1]; size + 1];
+    let mut w = vec![vec![0.; size + 1]; size + 1];
+    v[0][0] = radius / r2.sqrt();
+    for m in 0..size {
+        if m > 0 {
+            let k = (2 * m - 1) as f64;
+            v[m][m] = k * (x * v[m - 1][m - 1] - y * w[m - 1][m - 1]);
+            w[m][m] = k * (x * w[m - 1][m - 1] + y * v[m - 1][m - 1]);
+        }
+        if m + 1 < size {
+            let k = (2 * m + 1) as f64;
+            v[m + 1][m] = k * z * v[m][m];
+            w[m + 1][m] = k * z * w[m][m];
+        }
+        for n in m + 2..size {
+            let (a, b, d) = ((2 * n - 1) as f64, (n + m - 1) as f64, (n - m) as f64);
+            v[n][m] = (a * z * v[n - 1][m] - b * rho * v[n - 2][m]) / d;
+            w[n][m] = (a * z * w[n - 1][m] - b * rho * w[n - 2][m]) / d;
+        }
+    }
+    let mut acc = DVec3::ZERO;
+    for n in 2..=n_max {
+        for m in 0..=n {
+            let (c, s) = coefficients.unnormalized(n, m);
+            if m == 0 {
+                acc -= c * DVec3::new(v[n + 1][1], w[n + 1][1], (n + 1) as f64 * v[n + 1][0]);
+            } else {
+                let f = ((n - m + 1) * (n - m + 2)) as f64;
+                acc.x += 0.5
+                    * (-c * v[n + 1][m + 1] - s * w[n + 1][m + 1]
+                        + f * (c * v[n + 1][m - 1] + s * w[n + 1][m - 1]));
+                acc.y += 0.5
+                    * (-c * w[n + 1][m + 1]
+                        + s * v[n + 1][m + 1]
+                        + f * (-c * w[n + 1][m - 1] + s * v[n + 1][m - 1]));
+                acc.z += (n - m + 1) as f64 * (-c * v[n + 1][m] - s * w[n + 1][m]);
+            }
+        }
+    }
+    acc * gm / (radius * radius)
+}
+
+/// Axes of the body-fixed frame of a body
+pub fn body_frame(info: &BodyInfo, orbit: &EllipticalOrbit, days: f64) -> DMat3 {
+    let axis = spin_axis_direction(
+        info.0.axial_tilt.to_radians(),
+        orbit.long_asc_node.to_radians(),
+        orbit.inclination.to_radians(),
+    );
+    body_fixed_frame(axis, info.0.rotation_period, days)
+}
+
+fn load_gravity_fields(
+    mut commands: Commands,
+    files: Res<GameFiles>,
+    bodies: Query<(Entity, &BodyInfo)>,
+) {
+    for (entity, info) in bodies.iter() {
+        let path = files.gravity.join(format!("{}.shc", info.0.id));
+        if !path.exists() {
+            continue;
+        }
+        match load_gravity_coefficients(&path) {
+            Ok(coefficients) => {
+                commands.entity(entity).insert(coefficients);
+            }
+            Err(e) => error!("could not load the gravity field of {}: {e}", info.0.id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::write, io::ErrorKind};
+
+    use bevy::math::{DMat3, DVec3};
+
+    use super::{
+        body_fixed_frame, harmonics_perturbation, j2_perturbation, load_gravity_coefficients,
+        GravityCoefficients, GravityField, GravityModel,
+    };
+
+    const EARTH_GM: f64 = 398600.4418 * 86400. * 86400.;
+    const EARTH_RADIUS: f64 = 6378.137;
+    const EARTH_J2: f64 = 1.08263e-3;
+
+    #[test]
+    fn test_harmonics_match_j2() {
+        let coefficients = GravityCoefficients(vec![
+            vec![(1., 0.)],
+            vec![(0., 0.); 2],
+            vec![(-EARTH_J2 / 5f64.sqrt(), 0.), (0., 0.), (0., 0.)],
+        ]);
+        assert!((coefficients.j2() - EARTH_J2).abs() < 1e-15);
+        for pos in [
+            DVec3::new(7000., 0., 0.),
+            DVec3::new(3000., -4000., 5000.),
+            DVec3::new(0., 100., 8000.),
+        ] {
+            let j2 = j2_perturbation(pos, DVec3::Z, EARTH_GM, EARTH_RADIUS, EARTH_J2);
+            let harmonics = harmonics_perturbation(pos, EARTH_GM, EARTH_RADIUS, &coefficients, 10);
+            assert!((j2 - harmonics).length() < 1e-9 * j2.length());
+        }
+
+        // Both models agree whatever the orientation of the body
+        let frame = body_fixed_frame(DVec3::new(1., 2., 3.).normalize(), 23.93, 0.3);
+        assert!((frame * frame.transpose()).abs_diff_eq(DMat3::IDENTITY, 1e-12));
+        let field = GravityField {
+            coefficients: &coefficients,
+            radius: EARTH_RADIUS,
+            frame,
+        };
+        let pos = DVec3::new(-5000., 2000., 4000.);
+        let j2 = GravityModel::J2Only.perturbation(pos, EARTH_GM, &field);
+        let harmonics =
+            GravityModel::SphericalHarmonics { max_degree: 4 }.perturbation(pos, EARTH_GM, &field);
+        assert!((j2 - harmonics).length() < 1e-9 * j2.length());
+        assert_eq!(
+            GravityModel::PointMass.perturbation(pos, EARTH_GM, &field),
+            DVec3::ZERO
+        );
+    }
+
+    #[test]
+    fn test_load_gravity_coefficients() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("terre.shc");
+        write(
+            &path,
+            "# n m Cnm Snm\n0 0 1.0 0.0\n2 0 -4.84165e-4 0.0\n2 2 2.43938e-6 -1.40027e-6\n",
+        )
+        .unwrap();
+        let coefficients = load_gravity_coefficients(&path).unwrap();
+        assert_eq!(coefficients.degree(), 2);
+        assert_eq!(coefficients.get(2, 2), (2.43938e-6, -1.40027e-6));
+        assert_eq!(coefficients.get(1, 1), (0., 0.));
+        assert!((coefficients.j2() - EARTH_J2).abs() < 1e-8);
+
+        write(&path, "2 3 0.0 0.0\n").unwrap();
+        assert_eq!(
+            load_gravity_coefficients(&path).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+}
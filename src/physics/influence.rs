@@ -1,37 +1,113 @@
-use bevy::{math::DVec3, prelude::*};
+use bevy::{log::Level, math::DVec3, prelude::*};
 use serde::{Deserialize, Serialize};
 
-use crate::game::Loaded;
+use crate::game::loading::LoadingPhase;
 use crate::objects::prelude::*;
+use crate::throttled;
 
 use crate::objects::bodies::BodyID;
 
-use super::time::TickEvent;
-use super::Position;
+use super::orbit::EllipticalOrbit;
+use crate::utils::algebra::osculating_elements;
+
+use super::time::{GameTime, TickEvent, GAMETIME_PER_SIMTICK, SIMTICKS_PER_TICK};
+use super::{Mass, Position, Velocity, G};
+
+/// Bodies on orbits more eccentric than this have their Hill radius computed from their current
+/// distance to their host at each update, instead of once from their periapsis
+pub const VARYING_HILL_ECCENTRICITY: f64 = 0.05;
 
 pub fn plugin(app: &mut App) {
-    info!("loading inflence::plugin");
-    info!("adding system OnEnter(loaded) : setup_jill_spheres.in_set(InfluenceUpdate)");
-    app.add_systems(OnEnter(Loaded), setup_hill_spheres.in_set(InfluenceUpdate));
-    info!(
+    debug!("loading inflence::plugin");
+    debug!("adding system OnEnter(LoadingPhase::ComputingOrbits) : setup_jill_spheres.in_set(InfluenceUpdate)");
+    app.add_systems(
+        OnEnter(LoadingPhase::ComputingOrbits),
+        (setup_hill_spheres, update_hill_radii)
+            .chain()
+            .in_set(InfluenceUpdate),
+    )
+    .register_type::<Influenced>();
+    debug!(
         "adding system FixedUpdate : update_influence.in_set(InfluenceUpdate).run_if(on_event::<TickEvent>()),"
     );
     app.add_systems(
         FixedUpdate,
-        update_influence
-            .in_set(InfluenceUpdate)
-            .run_if(on_event::<TickEvent>()),
-    );
+        (
+            setup_hill_spheres.run_if(on_event::<BodiesChanged>()),
+            update_hill_radii,
+            update_influence.run_if(on_event::<TickEvent>()),
+            predict_soi_transitions
+                .in_set(SOITransitionSystem)
+                .run_if(on_event::<TickEvent>()),
+        )
+            .chain()
+            .in_set(InfluenceUpdate),
+    )
+    .add_event::<SOIExitEvent>()
+    .add_event::<SOIEntryEvent>();
 }
 
 #[derive(SystemSet, Debug, PartialEq, Eq, Hash, Clone)]
 pub struct InfluenceUpdate;
 
-#[derive(Component, Clone, Copy)]
+/// Predicts when the ships leave the spheres of influence of their main influencers, once their
+/// influencers are up to date
+#[derive(SystemSet, Debug, PartialEq, Eq, Hash, Clone)]
+pub struct SOITransitionSystem;
+
+/// Sent when a ship on an escape trajectory is predicted to leave the Hill sphere of its main
+/// influencer, and again whenever the prediction changes
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub struct SOIExitEvent {
+    pub ship: ShipID,
+    pub body: BodyID,
+    /// Tick during which the ship crosses the Hill sphere
+    pub exit_tick: u64,
+}
+
+/// Sent when a ship enters the Hill sphere of a body
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub struct SOIEntryEvent {
+    pub ship: ShipID,
+    pub body: BodyID,
+    pub entry_tick: u64,
+}
+
+/// Influencers of a ship at the previous tick, along with the last predicted exit of the Hill
+/// sphere of its main influencer
+#[derive(Component, Default, Debug, Clone)]
+pub struct SOITracker {
+    influencers: Vec<Entity>,
+    exit: Option<(Entity, u64)>,
+}
+
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
 pub struct HillRadius(pub f64);
 
+/// Marks the bodies on eccentric orbits, whose Hill radius follows their distance to their host
+#[derive(Component, Clone, Copy, Debug)]
+pub struct VaryingHillRadius {
+    /// Ratio of the Hill radius to the distance to the host
+    distance_factor: f64,
+    /// Radius of the body, below which the Hill radius never goes
+    min_radius: f64,
+}
+
+impl VaryingHillRadius {
+    pub fn at_distance(&self, distance: f64) -> f64 {
+        (distance * self.distance_factor).max(self.min_radius)
+    }
+}
+
+/// Ratio of the Hill radius of a body of mass `mass` orbiting a host of mass `host_mass` to its
+/// distance to the host
+pub fn hill_distance_factor(mass: f64, host_mass: f64) -> f64 {
+    (mass / (3. * (host_mass + mass))).powf(1. / 3.)
+}
+
 /// Component storing the bodies that influence the object's trajectory
-#[derive(Component, Default, Debug, Serialize, Deserialize, Clone)]
+#[derive(Component, Default, Debug, Serialize, Deserialize, Clone, Reflect)]
+#[reflect(Component)]
 pub struct Influenced {
     pub main_influencer: Option<Entity>,
     pub influencers: Vec<Entity>,
@@ -44,7 +120,7 @@ impl Influenced {
         mapping: &BodiesMapping,
         main_body: BodyID,
     ) -> Self {
-        debug!("new Influenced");
+        throttled!(Level::DEBUG, "new Influenced");
         // if an object is not in a bodie's sphere of influence, it is not in its children's either
         fn influencers_rec(
             body: BodyID,
@@ -53,7 +129,7 @@ impl Influenced {
             object_pos: &DVec3,
             influences: &mut Vec<(Entity, f64)>,
         ) {
-            debug!("influencers_rec");
+            throttled!(Level::DEBUG, "influencers_rec");
             if let Some(e) = mapping.0.get(&body) {
                 let (Position(body_pos), HillRadius(hill_radius), BodyInfo(data)) =
                     query.get(*e).unwrap();
@@ -80,42 +156,69 @@ impl Influenced {
     }
 }
 
-fn setup_hill_spheres(
+/// Computes the Hill radii of all the bodies from their orbits and masses. The radii of the bodies
+/// on eccentric orbits are then kept up to date by [update_hill_radii]
+pub fn setup_hill_spheres(
     mut commands: Commands,
     query: Query<&BodyInfo>,
     primary: Query<(Entity, &BodyInfo), With<PrimaryBody>>,
     mapping: Res<BodiesMapping>,
 ) {
     debug!("setting up hill spheres");
-    let mut queue = vec![(primary.single().1 .0.id, 0.)];
+    let Ok((primary, BodyInfo(primary_data))) = primary.get_single() else {
+        return;
+    };
+    let mut queue = vec![(primary_data.id, 0.)];
     let mut i = 0;
     while i < queue.len() {
         let (id, parent_mass) = queue[i];
         if let Some(entity) = mapping.0.get(&id) {
             if let Ok(BodyInfo(data)) = query.get(*entity) {
-                let radius = (data.semimajor_axis
-                    * (1. - data.eccentricity)
-                    * (data.mass / (3. * (parent_mass + data.mass))).powf(1. / 3.))
-                .max(data.radius);
-                commands.entity(*entity).insert(HillRadius(radius));
+                let varying = VaryingHillRadius {
+                    distance_factor: hill_distance_factor(data.mass, parent_mass),
+                    min_radius: data.radius,
+                };
+                let radius = varying.at_distance(data.semimajor_axis * (1. - data.eccentricity));
+                let mut entity = commands.entity(*entity);
+                entity.insert(HillRadius(radius));
+                if data.eccentricity > VARYING_HILL_ECCENTRICITY {
+                    entity.insert(varying);
+                } else {
+                    entity.remove::<VaryingHillRadius>();
+                }
                 queue.extend(data.orbiting_bodies.iter().map(|c| (*c, data.mass)));
             }
         }
         i += 1;
     }
     commands
-        .entity(primary.single().0)
-        .insert(HillRadius(f64::INFINITY));
+        .entity(primary)
+        .insert(HillRadius(f64::INFINITY))
+        .remove::<VaryingHillRadius>();
+}
+
+/// Computes the Hill radii of the bodies on eccentric orbits from their current distance to their
+/// host
+pub fn update_hill_radii(
+    mut bodies: Query<(&EllipticalOrbit, &VaryingHillRadius, &mut HillRadius)>,
+) {
+    for (orbit, varying, mut hill) in bodies.iter_mut() {
+        hill.0 = varying.at_distance(orbit.local_pos.length());
+    }
 }
 
-fn update_influence(
+/// Recomputes the bodies influencing each object from their positions and Hill radii
+pub fn update_influence(
     mut influenced: Query<(&Position, &mut Influenced)>,
     bodies: Query<(&Position, &HillRadius, &BodyInfo)>,
     mapping: Res<BodiesMapping>,
     main_body: Query<&BodyInfo, With<PrimaryBody>>,
 ) {
-    debug!("updating influence");
-    let main_body = main_body.single().0.id;
+    throttled!(Level::DEBUG, "updating influence");
+    let Ok(BodyInfo(main_body)) = main_body.get_single() else {
+        return;
+    };
+    let main_body = main_body.id;
     influenced
         .par_iter_mut()
         .for_each(|(object_pos, mut influence)| {
@@ -123,11 +226,252 @@ fn update_influence(
         });
 }
 
+/// Time (in days) until an object at `rel_pos` going at `rel_vel` relative to a body whose `gm`
+/// is in km³/d² gets `radius` km away from it, if it is on a hyperbolic trajectory.
+///
+/// The hyperbolic excess velocity gives the semi-major axis of the hyperbola, on which the
+/// hyperbolic anomalies of the current and exit positions follow from their distances
+pub fn hyperbolic_exit_time(rel_pos: DVec3, rel_vel: DVec3, gm: f64, radius: f64) -> Option<f64> {
+    let r = rel_pos.length();
+    let v_inf_squared = rel_vel.length_squared() - 2. * gm / r;
+    if v_inf_squared <= 0. || !radius.is_finite() {
+        return None;
+    }
+    let a = gm / v_inf_squared;
+    let eccentricity = osculating_elements(rel_pos, rel_vel, gm).eccentricity;
+    let anomaly = |distance: f64| ((distance / a + 1.) / eccentricity).max(1.).acosh();
+    // Inbound objects have a negative anomaly
+    let current = anomaly(r).copysign(rel_pos.dot(rel_vel));
+    let exit = anomaly(radius.max(r));
+    let mean_anomaly = |f: f64| eccentricity * f.sinh() - f;
+    let mean_motion = (gm / a.powi(3)).sqrt();
+    Some((mean_anomaly(exit) - mean_anomaly(current)) / mean_motion)
+}
+
+/// Number of ticks by which a predicted exit has to move to be sent again, given the ticks
+/// remaining until the previous prediction
+fn exit_tolerance(remaining: u64) -> u64 {
+    (remaining / 100).max(1)
+}
+
+/// Sends [SOIEntryEvent]s for the Hill spheres the ships entered since the last tick, and
+/// [SOIExitEvent]s for the ships on escape trajectories from their main influencer
+#[allow(clippy::type_complexity)]
+pub fn predict_soi_transitions(
+    mut commands: Commands,
+    mut ships: Query<(
+        Entity,
+        &ShipInfo,
+        &Position,
+        &Velocity,
+        &Influenced,
+        Option<&mut SOITracker>,
+    )>,
+    bodies: Query<(&Position, &Velocity, &Mass, &HillRadius, &BodyInfo)>,
+    time: Res<GameTime>,
+    mut exits: EventWriter<SOIExitEvent>,
+    mut entries: EventWriter<SOIEntryEvent>,
+) {
+    let tick = time.tick();
+    for (entity, info, &Position(pos), &Velocity(vel), influence, tracker) in ships.iter_mut() {
+        let mut inserted = None;
+        let tracker = match tracker {
+            Some(tracker) => tracker.into_inner(),
+            // Ships seen for the first time entered none of their current Hill spheres
+            None => inserted.insert(SOITracker {
+                influencers: influence.influencers.clone(),
+                exit: None,
+            }),
+        };
+        for &body in &influence.influencers {
+            if !tracker.influencers.contains(&body) {
+                if let Ok((.., BodyInfo(data))) = bodies.get(body) {
+                    entries.send(SOIEntryEvent {
+                        ship: info.id,
+                        body: data.id,
+                        entry_tick: tick,
+                    });
+                }
+            }
+        }
+        tracker.influencers.clone_from(&influence.influencers);
+
+        let exit = influence.main_influencer.and_then(|main| {
+            let (&Position(body_pos), &Velocity(body_vel), &Mass(mass), &HillRadius(radius), _) =
+                bodies.get(main).ok()?;
+            let days = hyperbolic_exit_time(pos - body_pos, vel - body_vel, G * mass, radius)?;
+            let simticks = time.simtick as f64 + days / GAMETIME_PER_SIMTICK;
+            Some((main, (simticks / SIMTICKS_PER_TICK as f64) as u64))
+        });
+        // Small drifts of the prediction keep the previous one, so that they add up
+        let changed = match (tracker.exit, exit) {
+            (Some((old_body, old_tick)), Some((body, exit_tick))) => {
+                old_body != body
+                    || old_tick.abs_diff(exit_tick) >= exit_tolerance(old_tick.saturating_sub(tick))
+            }
+            _ => true,
+        };
+        if changed {
+            if let Some((BodyInfo(data), exit_tick)) =
+                exit.and_then(|(body, t)| Some((bodies.get(body).ok()?.4, t)))
+            {
+                exits.send(SOIExitEvent {
+                    ship: info.id,
+                    body: data.id,
+                    exit_tick,
+                });
+            }
+            tracker.exit = exit;
+        }
+
+        if let Some(tracker) = inserted {
+            commands.entity(entity).insert(tracker);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use bevy::app::App;
+    use bevy::{app::App, ecs::system::RunSystemOnce, math::DVec3, prelude::*};
+
+    use crate::{
+        objects::bodies::spawn_bodies,
+        physics::{
+            orbit::{update_global, update_local},
+            time::{GAMETIME_PER_SIMTICK, SIMTICKS_PER_TICK},
+            G,
+        },
+        prelude::*,
+        utils::algebra::circular_orbit_around_body,
+    };
+
+    use super::{
+        hill_distance_factor, hyperbolic_exit_time, predict_soi_transitions, setup_hill_spheres,
+        update_hill_radii, update_influence, HillRadius, SOIEntryEvent, SOIExitEvent,
+    };
+
+    /// An app with the Moon on an orbit of eccentricity 0.5, at periapsis at time 0
+    fn eccentric_moon_app() -> App {
+        let mut app = App::new();
+        app.add_plugins(
+            ClientPlugin::testing()
+                .with_bodies(BodiesConfig::SmallestBodyType(BodyType::Moon))
+                .in_mode(ClientMode::Singleplayer),
+        );
+        app.update();
+        let world = app.world_mut();
+        let moon = world.resource::<BodiesMapping>().0["lune"];
+        let mut entity = world.entity_mut(moon);
+        let mut info = entity.get_mut::<BodyInfo>().unwrap();
+        info.0.eccentricity = 0.5;
+        info.0.initial_mean_anomaly = 0.;
+        let orbit = EllipticalOrbit::from(&info.0);
+        entity.insert(orbit);
+        world.run_system_once(setup_hill_spheres);
+        app
+    }
+
+    /// Moves the bodies to the given simtick, and returns the Hill radius and the distance to the
+    /// Earth of the Moon
+    fn moon_at(app: &mut App, simtick: u64) -> (f64, f64) {
+        let world = app.world_mut();
+        world.resource_mut::<GameTime>().simtick = simtick;
+        world.run_system_once(update_local);
+        world.run_system_once(update_global);
+        world.run_system_once(update_hill_radii);
+        let mapping = &world.resource::<BodiesMapping>().0;
+        let (moon, earth) = (mapping["lune"], mapping["terre"]);
+        let pos = |e: Entity| world.get::<Position>(e).unwrap().0;
+        (
+            world.get::<HillRadius>(moon).unwrap().0,
+            pos(moon).distance(pos(earth)),
+        )
+    }
+
+    fn half_period_simticks(app: &mut App) -> u64 {
+        let world = app.world_mut();
+        let moon = world.resource::<BodiesMapping>().0["lune"];
+        let period = world
+            .get::<EllipticalOrbit>(moon)
+            .unwrap()
+            .revolution_period;
+        (period / 2. / GAMETIME_PER_SIMTICK).round() as u64
+    }
+
+    #[test]
+    fn test_eccentric_hill_radius() {
+        let mut app = eccentric_moon_app();
+        let (periapsis, _) = moon_at(&mut app, 0);
+        let half_period = half_period_simticks(&mut app);
+        let (apoapsis, _) = moon_at(&mut app, half_period);
+        // (1 + e) / (1 - e)
+        assert!(
+            (apoapsis / periapsis - 3.).abs() < 1e-4,
+            "{}",
+            apoapsis / periapsis
+        );
+
+        // Near-circular orbits keep the value at periapsis
+        let world = app.world_mut();
+        let earth = world.resource::<BodiesMapping>().0["terre"];
+        let earth_hill = world.get::<HillRadius>(earth).unwrap().0;
+        moon_at(&mut app, half_period / 2);
+        let world = app.world_mut();
+        assert_eq!(world.get::<HillRadius>(earth).unwrap().0, earth_hill);
+    }
+
+    #[test]
+    fn test_influence_follows_hill_radius() {
+        let mut app = eccentric_moon_app();
+        let (periapsis_hill, _) = moon_at(&mut app, 0);
+        let id = id_from("s");
+        app.world_mut().send_event(ShipEvent::Create(ShipInfo {
+            id,
+            spawn_pos: DVec3::new(1e9, 0., 0.),
+            spawn_speed: DVec3::ZERO,
+            spawn_orbit: None,
+        }));
+        app.update();
+        let world = app.world_mut();
+        let mapping = &world.resource::<BodiesMapping>().0;
+        let (moon, earth) = (mapping["lune"], mapping["terre"]);
+        let ship = world.resource::<ShipsMapping>().0[&id];
+        let factor = {
+            let mass = |e: Entity| world.get::<Mass>(e).unwrap().0;
+            hill_distance_factor(mass(moon), mass(earth))
+        };
 
-    use crate::{prelude::*, utils::algebra::circular_orbit_around_body};
+        // A ship twice as far from the Moon as its Hill radius at periapsis, which is inside the
+        // Hill sphere around apoapsis only
+        let distance = 2. * periapsis_hill;
+        let half_period = half_period_simticks(&mut app);
+        let mut switches = 0;
+        let mut was_inside = None;
+        for i in 0..=40 {
+            let simtick = half_period * i / 20;
+            let (_, moon_distance) = moon_at(&mut app, simtick);
+            let world = app.world_mut();
+            let moon_pos = world.get::<Position>(moon).unwrap().0;
+            world.get_mut::<Position>(ship).unwrap().0 = moon_pos + DVec3::Z * distance;
+            world.run_system_once(update_influence);
+
+            // Brute force reference, from the distances between the bodies
+            let inside = distance < moon_distance * factor;
+            let influence = world.get::<Influenced>(ship).unwrap();
+            let expected = if inside { moon } else { earth };
+            assert_eq!(
+                influence.main_influencer,
+                Some(expected),
+                "simtick {simtick}"
+            );
+            if was_inside.is_some_and(|w| w != inside) {
+                switches += 1;
+            }
+            was_inside = Some(inside);
+        }
+        // Entering the Hill sphere on the way to apoapsis, and leaving it on the way back
+        assert_eq!(switches, 2);
+    }
     #[test]
     fn test_influence() {
         let mut app = App::new();
@@ -139,18 +483,20 @@ mod tests {
         app.update();
         let world = app.world_mut();
         let mapping = &world.resource::<BodiesMapping>().0;
-        let moon = mapping[&id_from("lune")];
-        let earth = mapping[&id_from("terre")];
-        let sun = mapping[&id_from("soleil")];
+        let moon = mapping["lune"];
+        let earth = mapping["terre"];
+        let sun = mapping["soleil"];
         let (mass, pos, speed) = world
             .query::<(&Mass, &Position, &Velocity)>()
             .get(world, moon)
             .unwrap();
-        let (spawn_pos, spawn_speed) = circular_orbit_around_body(100., mass.0, pos.0, speed.0);
+        let (spawn_pos, spawn_speed) =
+            circular_orbit_around_body(100., mass.0, pos.0, speed.0, &mut rand::thread_rng());
         world.send_event(ShipEvent::Create(ShipInfo {
             id: id_from("s"),
             spawn_pos,
             spawn_speed,
+            spawn_orbit: None,
         }));
         app.update();
         let world = app.world_mut();
@@ -162,4 +508,126 @@ mod tests {
         assert_eq!(influenced.main_influencer, Some(moon));
         assert_eq!(influenced.influencers.len(), 3);
     }
+
+    #[test]
+    fn test_hyperbolic_exit_time() {
+        let gm = 1.;
+        let (mut pos, mut vel) = (DVec3::new(1., 0., 0.), DVec3::new(-0.3, 1.6, 0.));
+        let radius = 10.;
+        let expected = hyperbolic_exit_time(pos, vel, gm, radius).unwrap();
+        // Leapfrog integration of the two-body problem
+        let accel = |p: DVec3| -gm * p / p.length().powi(3);
+        let dt = 1e-5;
+        let mut t = 0.;
+        while pos.length() < radius {
+            vel += accel(pos) * dt / 2.;
+            pos += vel * dt;
+            vel += accel(pos) * dt / 2.;
+            t += dt;
+        }
+        assert!((t - expected).abs() < 1e-3 * expected, "{t} {expected}");
+
+        // Bound orbits never leave
+        assert!(hyperbolic_exit_time(DVec3::X, DVec3::Y, gm, radius).is_none());
+        assert!(hyperbolic_exit_time(pos, vel, gm, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn test_soi_transitions() {
+        let mut app = eccentric_moon_app();
+        let world = app.world_mut();
+        let moon = world.resource::<BodiesMapping>().0["lune"];
+        let (&Position(moon_pos), &Velocity(moon_vel), &Mass(moon_mass), &HillRadius(hill)) = world
+            .query::<(&Position, &Velocity, &Mass, &HillRadius)>()
+            .get(world, moon)
+            .unwrap();
+        // Twice the escape speed
+        let escape_speed = (2. * G * moon_mass / 1e4).sqrt();
+        world.send_event(ShipEvent::Create(ShipInfo {
+            id: id_from("s"),
+            spawn_pos: moon_pos + DVec3::X * 1e4,
+            spawn_speed: moon_vel + DVec3::Y * 2. * escape_speed,
+            spawn_orbit: None,
+        }));
+        app.update();
+        let world = app.world_mut();
+        let ship = world.resource::<ShipsMapping>().0["s"];
+        world.run_system_once(update_influence);
+        world.run_system_once(predict_soi_transitions);
+        let exits: Vec<_> = world
+            .resource_mut::<Events<SOIExitEvent>>()
+            .drain()
+            .collect();
+        assert_eq!(exits.len(), 1);
+        assert_eq!(exits[0].body, id_from("lune"));
+        let days = hyperbolic_exit_time(
+            DVec3::X * 1e4,
+            DVec3::Y * 2. * escape_speed,
+            G * moon_mass,
+            hill,
+        )
+        .unwrap();
+        let time = world.resource::<GameTime>();
+        let expected =
+            (time.simtick as f64 + days / GAMETIME_PER_SIMTICK) as u64 / SIMTICKS_PER_TICK;
+        assert!(exits[0].exit_tick.abs_diff(expected) <= 1);
+        // The ship didn't enter its current Hill spheres
+        assert!(world.resource::<Events<SOIEntryEvent>>().is_empty());
+
+        // The same prediction isn't sent again
+        world.run_system_once(predict_soi_transitions);
+        assert!(world.resource::<Events<SOIExitEvent>>().is_empty());
+
+        // Leaving the Hill sphere of the Moon, then coming back
+        for (distance, entered) in [(2. * hill, false), (hill / 2., true)] {
+            world.get_mut::<Position>(ship).unwrap().0 = moon_pos + DVec3::X * distance;
+            world.run_system_once(update_influence);
+            world.run_system_once(predict_soi_transitions);
+            let entries: Vec<_> = world
+                .resource_mut::<Events<SOIEntryEvent>>()
+                .drain()
+                .collect();
+            assert_eq!(entries.len(), entered as usize);
+            if entered {
+                assert_eq!(entries[0].body, id_from("lune"));
+            }
+        }
+    }
+
+    #[test]
+    fn test_hill_radius() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Explorer));
+        app.update();
+        let world = app.world_mut();
+        let bodies: Vec<_> = world
+            .query_filtered::<Entity, With<BodyInfo>>()
+            .iter(world)
+            .collect();
+        for entity in bodies {
+            world.despawn(entity);
+        }
+        let star = BodyDataBuilder::new(id_from("star"))
+            .with_type(BodyType::Star)
+            .with_mass(2e30)
+            .with_moons([id_from("planet")])
+            .build();
+        let planet = BodyDataBuilder::new(id_from("planet"))
+            .orbiting(star.id)
+            .with_semimajor_axis(1.5e8)
+            .with_mass(6e24)
+            .build();
+        world.run_system_once(move |mut commands: Commands| {
+            spawn_bodies(&mut commands, vec![star.clone(), planet.clone()])
+        });
+        world.run_system_once(setup_hill_spheres);
+
+        let mapping = &world.resource::<BodiesMapping>().0;
+        let hill = |id: &str| world.get::<HillRadius>(mapping[id]).unwrap().0;
+        assert_eq!(hill("star"), f64::INFINITY);
+        let expected = 1.5e8 * hill_distance_factor(6e24, 2e30);
+        assert!((hill("planet") - expected).abs() < 1e-6 * expected);
+        // About 1.5 million km for the Earth
+        assert!((1.4e6..1.6e6).contains(&hill("planet")));
+    }
 }
@@ -0,0 +1,339 @@
+//! Orbital decay of compact binaries through the emission of gravitational waves.
+//!
+//! See https://en.wikipedia.org/wiki/Gravitational_wave#Binaries, the orbits are assumed circular.
+//! A [BinaryPair] tracks the separation of two bodies, which shrinks at each physics step until
+//! they merge into a single body. The bodies keep following their orbits in the meantime: the
+//! separation of the pair only drives the inspiral.
+
+use bevy::prelude::*;
+
+use crate::{
+    game::ClearOnUnload,
+    objects::prelude::{BodiesChanged, BodiesMapping, BodyID, BodyInfo, PrimaryBody, RingSystem},
+};
+
+use super::{
+    orbit::{OrbitsUpdate, SimulationTier},
+    prelude::*,
+    time::{SimStepSize, GAMETIME_PER_SIMTICK},
+    G, SECONDS_PER_DAY,
+};
+
+/// Speed of light in km/day
+pub const C: f64 = 299_792.458 * SECONDS_PER_DAY;
+
+/// Mass combination that drives the inspiral of a binary (in kg)
+pub fn chirp_mass(m1: f64, m2: f64) -> f64 {
+    (m1 * m2).powf(0.6) / (m1 + m2).powf(0.2)
+}
+
+/// Radius of the event horizon of a non-rotating body of the given mass (in km)
+pub fn schwarzschild_radius(mass: f64) -> f64 {
+    2. * G * mass / (C * C)
+}
+
+/// Power radiated as gravitational waves by a binary with the given separation (in kg.km²/day³)
+pub fn gw_energy_loss_rate(m1: f64, m2: f64, separation: f64) -> f64 {
+    -32. / 5. * G.powi(4) * (m1 * m2).powi(2) * (m1 + m2) / (C.powi(5) * separation.powi(5))
+}
+
+/// Rate of change of the separation of a binary (in km/day), obtained from the energy loss rate
+/// and the orbital energy `-G.m1.m2 / 2r`
+pub fn gw_separation_rate(m1: f64, m2: f64, separation: f64) -> f64 {
+    2. * separation.powi(2) / (G * m1 * m2) * gw_energy_loss_rate(m1, m2, separation)
+}
+
+/// Time left before the two bodies merge (in days)
+pub fn time_to_merger(m1: f64, m2: f64, separation: f64) -> f64 {
+    5. / 256. * C.powi(5) * separation.powi(4) / (G.powi(3) * m1 * m2 * (m1 + m2))
+}
+
+pub fn plugin(app: &mut App) {
+    app.add_event::<MergerEvent>()
+        .configure_sets(
+            FixedUpdate,
+            GWDecaySystem
+                .in_set(OrbitsUpdate)
+                .run_if(resource_exists::<BodiesMapping>),
+        )
+        .add_systems(
+            FixedUpdate,
+            (add_inspirals, decay_binaries, merge_binaries)
+                .chain()
+                .in_set(GWDecaySystem),
+        );
+}
+
+/// Shrinks the separation of the binary pairs, and merges the ones that got too close
+#[derive(SystemSet, Debug, PartialEq, Eq, Hash, Clone)]
+pub struct GWDecaySystem;
+
+/// Two bodies orbiting each other closely enough for their gravitational waves to matter
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct BinaryPair {
+    pub bodies: (BodyID, BodyID),
+    /// Distance between the two bodies (in km)
+    pub separation: f64,
+}
+
+/// Added to the [BinaryPair]s whose bodies are spawned
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct GWInspiral {
+    /// See [chirp_mass] (in kg)
+    pub chirp_mass: f64,
+}
+
+/// Sent when the two bodies of a pair merge, before they are replaced by the merged body
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub struct MergerEvent {
+    pub bodies: (BodyID, BodyID),
+    /// Mass of the merged body (in kg)
+    pub product_mass: f64,
+}
+
+/// Masses of the two bodies of a pair, if they both exist
+fn pair_masses(
+    pair: &BinaryPair,
+    mapping: &BodiesMapping,
+    masses: &Query<&Mass>,
+) -> Option<(f64, f64)> {
+    let mass = |id| {
+        mapping
+            .0
+            .get(&id)
+            .and_then(|e| masses.get(*e).ok())
+            .map(|m| m.0)
+    };
+    Some((mass(pair.bodies.0)?, mass(pair.bodies.1)?))
+}
+
+fn add_inspirals(
+    mut commands: Commands,
+    pairs: Query<(Entity, &BinaryPair), Without<GWInspiral>>,
+    mapping: Res<BodiesMapping>,
+    masses: Query<&Mass>,
+) {
+    for (entity, pair) in pairs.iter() {
+        if let Some((m1, m2)) = pair_masses(pair, &mapping, &masses) {
+            commands.entity(entity).insert(GWInspiral {
+                chirp_mass: chirp_mass(m1, m2),
+            });
+        }
+    }
+}
+
+/// Merging happens once the bodies are closer than the event horizon of the merged body
+fn decay_binaries(
+    mut commands: Commands,
+    mut pairs: Query<(Entity, &mut BinaryPair), With<GWInspiral>>,
+    mapping: Res<BodiesMapping>,
+    masses: Query<&Mass>,
+    step: Res<SimStepSize>,
+    mut mergers: EventWriter<MergerEvent>,
+) {
+    let dt = step.0 as f64 * GAMETIME_PER_SIMTICK;
+    for (entity, mut pair) in pairs.iter_mut() {
+        let Some((m1, m2)) = pair_masses(&pair, &mapping, &masses) else {
+            continue;
+        };
+        let rate = gw_separation_rate(m1, m2, pair.separation);
+        pair.separation = (pair.separation + rate * dt).max(0.);
+        if pair.separation < 2. * schwarzschild_radius(m1 + m2) {
+            mergers.send(MergerEvent {
+                bodies: pair.bodies,
+                product_mass: m1 + m2,
+            });
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// Replaces the two bodies of a merger by a body with their total mass. It keeps the id, the orbit
+/// and the position of the host of the pair (or of the heavier body if neither hosts the other),
+/// and the satellites of both bodies
+#[allow(clippy::type_complexity)]
+fn merge_binaries(
+    mut commands: Commands,
+    mut mergers: EventReader<MergerEvent>,
+    mut mapping: ResMut<BodiesMapping>,
+    mut bodies: Query<(
+        &mut BodyInfo,
+        &Position,
+        &Velocity,
+        Option<&SimulationTier>,
+        Has<PrimaryBody>,
+    )>,
+    mut bodies_changed: EventWriter<BodiesChanged>,
+) {
+    for event in mergers.read() {
+        let (a, b) = event.bodies;
+        let (Some(&entity_a), Some(&entity_b)) = (mapping.0.get(&a), mapping.0.get(&b)) else {
+            continue;
+        };
+        let Ok([(info_a, ..), (info_b, ..)]) = bodies.get_many([entity_a, entity_b]) else {
+            continue;
+        };
+        let a_is_main = if info_b.0.host_body == Some(a) {
+            true
+        } else if info_a.0.host_body == Some(b) {
+            false
+        } else {
+            info_a.0.mass >= info_b.0.mass
+        };
+        let (main, other) = if a_is_main { (a, b) } else { (b, a) };
+        let (main_entity, other_entity) = (mapping.0[&main], mapping.0[&other]);
+        let other_data = bodies.get(other_entity).unwrap().0 .0.clone();
+        let (info, &pos, &velocity, tier, primary) = bodies.get(main_entity).unwrap();
+        let mut data = info.0.clone();
+        let tier = tier.copied();
+        data.mass = event.product_mass;
+        data.orbiting_bodies.retain(|id| *id != other);
+        data.orbiting_bodies
+            .extend(other_data.orbiting_bodies.iter().copied());
+
+        // The satellites of the other body now orbit the merged body, and its host loses it
+        for satellite in &other_data.orbiting_bodies {
+            if let Some((mut info, ..)) = mapping
+                .0
+                .get(satellite)
+                .and_then(|e| bodies.get_mut(*e).ok())
+            {
+                info.0.host_body = Some(main);
+            }
+        }
+        if let Some((mut info, ..)) = other_data
+            .host_body
+            .filter(|host| *host != main)
+            .and_then(|host| mapping.0.get(&host))
+            .and_then(|e| bodies.get_mut(*e).ok())
+        {
+            info.0.orbiting_bodies.retain(|id| *id != other);
+        }
+
+        commands.entity(main_entity).despawn();
+        commands.entity(other_entity).despawn();
+        mapping.remove(other);
+        let mut entity = commands.spawn((
+            pos,
+            EllipticalOrbit::from(&data),
+            Mass(data.mass),
+            velocity,
+            ClearOnUnload,
+        ));
+        if let Some(tier) = tier {
+            entity.insert(tier);
+        }
+        if primary {
+            entity.insert(PrimaryBody);
+        }
+        if let Some(rings) = RingSystem::of_main_body(&main) {
+            entity.insert(rings);
+        }
+        mapping.insert(&data, entity.id());
+        entity.insert(BodyInfo(data));
+        bodies_changed.send(BodiesChanged);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{app::FixedMain, prelude::*};
+
+    use crate::prelude::*;
+
+    use super::{
+        chirp_mass, gw_separation_rate, schwarzschild_radius, time_to_merger, BinaryPair,
+        GWInspiral, MergerEvent,
+    };
+
+    const SUN_MASS: f64 = 1.989e30;
+
+    #[test]
+    fn test_inspiral() {
+        assert!((schwarzschild_radius(SUN_MASS) - 2.95).abs() < 0.01);
+        assert!((chirp_mass(SUN_MASS, SUN_MASS) - 0.8706 * SUN_MASS).abs() < 1e-3 * SUN_MASS);
+
+        // Two neutron stars 1000 km apart: the separation shrinks faster as they get closer,
+        // and the integration agrees with the closed form time to merger
+        let (m1, m2) = (1.4 * SUN_MASS, 1.4 * SUN_MASS);
+        let mut separation = 1000.;
+        let expected = time_to_merger(m1, m2, separation);
+        let dt = expected / 1e5;
+        let mut t = 0.;
+        while separation > 4. * schwarzschild_radius(m1 + m2) {
+            separation += gw_separation_rate(m1, m2, separation) * dt;
+            t += dt;
+        }
+        assert!((t - expected).abs() / expected < 0.01);
+    }
+
+    #[test]
+    fn test_merger() {
+        let mut app = App::new();
+        app.add_plugins(
+            ClientPlugin::testing()
+                .with_bodies(BodiesConfig::SmallestBodyType(BodyType::Moon))
+                .in_mode(ClientMode::Singleplayer),
+        );
+        app.update();
+        app.world_mut()
+            .resource_mut::<NextState<GameStage>>()
+            .set(GameStage::Action);
+        app.update();
+        let world = app.world_mut();
+        let body = |world: &World, id: &str| {
+            world
+                .resource::<BodiesMapping>()
+                .0
+                .get(&id_from::<BodyID>(id))
+                .copied()
+        };
+        let (earth, moon) = (body(world, "terre").unwrap(), body(world, "lune").unwrap());
+        let mass = |world: &World, e| world.get::<Mass>(e).unwrap().0;
+        let (m1, m2) = (mass(world, earth), mass(world, moon));
+        let earth_pos = world.get::<Position>(earth).unwrap().0;
+        let pair = world
+            .spawn(BinaryPair {
+                bodies: (id_from("terre"), id_from("lune")),
+                separation: 1.,
+            })
+            .id();
+
+        // Far apart, the pair barely shrinks
+        FixedMain::run_fixed_main(app.world_mut());
+        FixedMain::run_fixed_main(app.world_mut());
+        let world = app.world_mut();
+        assert_eq!(
+            world.get::<GWInspiral>(pair).unwrap().chirp_mass,
+            chirp_mass(m1, m2)
+        );
+        let separation = world.get::<BinaryPair>(pair).unwrap().separation;
+        assert!(separation < 1. && separation > 1. - 1e-6);
+        assert!(world.resource::<Events<MergerEvent>>().is_empty());
+
+        // Close enough, they merge into the Earth
+        world.get_mut::<BinaryPair>(pair).unwrap().separation = 1e-4;
+        FixedMain::run_fixed_main(world);
+        assert!(world.get_entity(pair).is_none());
+        let mergers: Vec<_> = world
+            .resource_mut::<Events<MergerEvent>>()
+            .drain()
+            .collect();
+        assert_eq!(
+            mergers,
+            vec![MergerEvent {
+                bodies: (id_from("terre"), id_from("lune")),
+                product_mass: m1 + m2,
+            }]
+        );
+        assert!(world.get_entity(earth).is_none() && world.get_entity(moon).is_none());
+        assert_eq!(body(world, "lune"), None);
+        let merged = body(world, "terre").unwrap();
+        assert_eq!(mass(world, merged), m1 + m2);
+        let info = &world.get::<BodyInfo>(merged).unwrap().0;
+        assert_eq!(info.mass, m1 + m2);
+        assert!(!info.orbiting_bodies.contains(&id_from("lune")));
+        assert!(world.get::<Position>(merged).unwrap().0.distance(earth_pos) < 1e5);
+        assert!(world.resource::<BodiesMapping>().lookup("lune").is_err());
+    }
+}
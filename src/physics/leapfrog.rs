@@ -1,37 +1,70 @@
-use bevy::{math::DVec3, prelude::*};
+use std::{collections::VecDeque, f64::consts::PI};
+
+use bevy::{log::Level, math::DVec3, prelude::*};
 use serde::{Deserialize, Serialize};
 
 use super::{
     super::prelude::ClientMode,
+    gravity::{body_frame, GravityCoefficients, GravityField, GravityModel},
     prelude::*,
-    time::{SimStepSize, GAMETIME_PER_SIMTICK},
-    G,
+    sgp4::TLEData,
+    time::{time_running, SimStepSize, GAMETIME_PER_SIMTICK},
+    G, SECONDS_PER_DAY,
+};
+use crate::{
+    game::InGame,
+    objects::prelude::{BodyID, RingSystem},
+    prelude::BodyInfo,
+    throttled,
+    utils::algebra::spin_axis_direction,
 };
-use crate::game::InGame;
+
+/// Maximum number of entries kept in an [AccelerationLog]
+pub const MAX_ACCELERATION_LOG_ENTRIES: usize = 10_000;
 
 // See https://en.wikipedia.org/wiki/Leapfrog_integration#Algorithm
 pub fn plugin(app: &mut App) {
-    info!("loading leapfrog::plugin");
-    info!("configuring sets FixedUpdate : LeapfrogUpdate.run_if(resource_equals(ToggleTime(true))).run_if(in_state(InGame)),");
+    debug!("loading leapfrog::plugin");
+    debug!("configuring sets FixedUpdate : LeapfrogUpdate.run_if(time_running).run_if(in_state(InGame)),");
     app.configure_sets(
         FixedUpdate,
         LeapfrogUpdate
-            .run_if(resource_equals(ToggleTime(true)))
+            .run_if(time_running)
             .run_if(in_state(InGame).or_else(in_state(ClientMode::Server))),
     );
-    info!("adding systems FixedUpdate :  (update_position, update_acceleration, update_velocity).chain().in_set(LeapfrogUpdate),");
-    app.add_systems(
-        FixedUpdate,
-        (update_position, update_acceleration, update_velocity)
-            .chain()
-            .in_set(LeapfrogUpdate),
-    );
+    debug!("adding systems FixedUpdate :  (update_position, update_acceleration, update_velocity).chain().in_set(LeapfrogUpdate),");
+    app.init_resource::<IntegrationMethod>()
+        .add_systems(
+            FixedUpdate,
+            (update_position, update_acceleration, update_velocity)
+                .chain()
+                .in_set(LeapfrogUpdate),
+        )
+        .register_type::<Acceleration>();
 }
 
 #[derive(SystemSet, Debug, PartialEq, Eq, Hash, Clone)]
 pub struct LeapfrogUpdate;
 
-#[derive(Component, Debug, Default, Serialize, Deserialize)]
+/// How the trajectories of the ships are computed
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IntegrationMethod {
+    /// Integration of the gravity of the bodies influencing the ships
+    #[default]
+    Leapfrog,
+    /// The ships with a [TLEData] follow the SGP4 analytic model, the others are integrated
+    SGP4,
+}
+
+impl IntegrationMethod {
+    /// Whether the leapfrog integration moves a ship, knowing whether it has a TLE
+    pub fn integrates(&self, has_tle: bool) -> bool {
+        !(has_tle && *self == Self::SGP4)
+    }
+}
+
+#[derive(Component, Debug, Default, Serialize, Deserialize, Reflect)]
+#[reflect(Component)]
 pub struct Acceleration {
     pub current: DVec3,
     pub previous: DVec3,
@@ -39,7 +72,7 @@ pub struct Acceleration {
 
 impl Acceleration {
     pub fn new(acc: DVec3) -> Self {
-        debug!("new_acceleration");
+        throttled!(Level::DEBUG, "new_acceleration");
         Self {
             current: acc,
             ..Default::default()
@@ -47,37 +80,119 @@ impl Acceleration {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AccelerationEntry {
+    pub simtick: u64,
+    pub body_id: BodyID,
+    pub contribution_km_s2: f64,
+}
+
+/// The most recent contributions of each influencer to the acceleration of a ship
+#[derive(Component, Debug, Default)]
+pub struct AccelerationLog {
+    pub entries: VecDeque<AccelerationEntry>,
+}
+
+impl AccelerationLog {
+    pub fn push(&mut self, entry: AccelerationEntry) {
+        if self.entries.len() >= MAX_ACCELERATION_LOG_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+}
+
+#[allow(clippy::type_complexity)]
 fn update_acceleration(
-    mut gravity_bound: Query<(&Position, &mut Acceleration, &Influenced)>,
-    bodies: Query<(&Position, &Mass)>,
+    mut gravity_bound: Query<(
+        &Position,
+        &mut Acceleration,
+        &Influenced,
+        Option<&mut AccelerationLog>,
+        Has<TLEData>,
+    )>,
+    bodies: Query<(
+        &Position,
+        &Mass,
+        &BodyInfo,
+        &EllipticalOrbit,
+        Option<&RingSystem>,
+        Option<&GravityCoefficients>,
+    )>,
+    game_time: Res<GameTime>,
+    model: Res<GravityModel>,
+    method: Res<IntegrationMethod>,
 ) {
-    debug!("updating accelaration");
-    gravity_bound
-        .par_iter_mut()
-        .for_each(|(object_pos, mut acceleration, influenced)| {
+    throttled!(Level::DEBUG, "updating accelaration");
+    let days = game_time.time();
+    gravity_bound.par_iter_mut().for_each(
+        |(object_pos, mut acceleration, influenced, mut log, has_tle)| {
+            if !method.integrates(has_tle) {
+                return;
+            }
             acceleration.previous = acceleration.current;
-            acceleration.current = get_acceleration(
-                object_pos.0,
-                bodies
-                    .iter_many(&influenced.influencers)
-                    .map(|(p, m)| (p.0, m.0)),
-            );
-        });
+            let mut acc = DVec3::ZERO;
+            for (body_pos, mass, info, orbit, rings, coefficients) in
+                bodies.iter_many(&influenced.influencers)
+            {
+                let mut contribution = get_contribution(object_pos.0, body_pos.0, mass.0);
+                if let Some(coefficients) = coefficients {
+                    let field = GravityField {
+                        coefficients,
+                        radius: info.0.radius,
+                        frame: body_frame(info, orbit, days),
+                    };
+                    contribution +=
+                        model.perturbation(object_pos.0 - body_pos.0, G * mass.0, &field);
+                }
+                if let Some(rings) = rings {
+                    let normal = spin_axis_direction(
+                        info.0.axial_tilt.to_radians(),
+                        orbit.long_asc_node.to_radians(),
+                        orbit.inclination.to_radians(),
+                    );
+                    contribution += get_ring_contribution(object_pos.0 - body_pos.0, normal, rings);
+                }
+                if let Some(log) = log.as_mut() {
+                    log.push(AccelerationEntry {
+                        simtick: game_time.simtick,
+                        body_id: info.0.id,
+                        contribution_km_s2: contribution.length()
+                            / (SECONDS_PER_DAY * SECONDS_PER_DAY),
+                    });
+                }
+                acc += contribution;
+            }
+            acceleration.current = acc;
+        },
+    );
 }
 
 fn update_position(
-    mut query: Query<(&mut Position, &Velocity, &Acceleration)>,
+    mut query: Query<(&mut Position, &Velocity, &Acceleration, Has<TLEData>)>,
     step: Res<SimStepSize>,
+    method: Res<IntegrationMethod>,
 ) {
-    debug!("updating position");
-    query.par_iter_mut().for_each(|(mut pos, speed, acc)| {
-        pos.0 += get_dx(speed.0, acc.current, GAMETIME_PER_SIMTICK * step.0 as f64)
-    });
+    throttled!(Level::DEBUG, "updating position");
+    query
+        .par_iter_mut()
+        .for_each(|(mut pos, speed, acc, has_tle)| {
+            if method.integrates(has_tle) {
+                pos.0 += get_dx(speed.0, acc.current, GAMETIME_PER_SIMTICK * step.0 as f64)
+            }
+        });
 }
 
-fn update_velocity(mut query: Query<(&mut Velocity, &Acceleration)>, step: Res<SimStepSize>) {
-    debug!("updating velocity");
-    query.par_iter_mut().for_each(|(mut speed, acc)| {
+fn update_velocity(
+    mut query: Query<(&mut Velocity, &Acceleration, Has<TLEData>)>,
+    step: Res<SimStepSize>,
+    method: Res<IntegrationMethod>,
+) {
+    throttled!(Level::DEBUG, "updating velocity");
+    query.par_iter_mut().for_each(|(mut speed, acc, has_tle)| {
+        if !method.integrates(has_tle) {
+            return;
+        }
         speed.0 += get_dv(
             acc.previous,
             acc.current,
@@ -91,36 +206,118 @@ pub fn get_acceleration(
     object_pos: DVec3,
     influencers: impl Iterator<Item = (DVec3, f64)>,
 ) -> DVec3 {
-    debug!("getting acceleration");
+    throttled!(Level::DEBUG, "getting acceleration");
     let mut acc = DVec3::ZERO;
     for (body_pos, mass) in influencers {
-        let r = object_pos - body_pos;
-        let dist = r.length();
-        acc -= r * G * mass / (dist.powi(3));
+        acc += get_contribution(object_pos, body_pos, mass);
     }
     acc
 }
 
+/// Computes the acceleration caused by a single body
+pub fn get_contribution(object_pos: DVec3, body_pos: DVec3, mass: f64) -> DVec3 {
+    let r = object_pos - body_pos;
+    let dist = r.length();
+    -r * G * mass / (dist.powi(3))
+}
+
+/// Computes the acceleration caused by a ring system, approximated as an infinite thin disk:
+/// objects inside the rings are pulled towards the ring plane, whose normal is given
+pub fn get_ring_contribution(rel_pos: DVec3, normal: DVec3, rings: &RingSystem) -> DVec3 {
+    let height = rel_pos.dot(normal);
+    if height == 0. || !rings.contains(rel_pos, normal) {
+        return DVec3::ZERO;
+    }
+    // Surface density in kg/km2
+    let density = rings.surface_density_kg_m2 * 1e6;
+    -2. * PI * G * density * height.signum() * normal
+}
+
 pub fn get_dx(speed: DVec3, acc: DVec3, dt: f64) -> DVec3 {
-    debug!("getting dx");
+    throttled!(Level::DEBUG, "getting dx");
     (speed + acc * dt / 2.) * dt
 }
 
 pub fn get_dv(previous_acc: DVec3, acc: DVec3, dt: f64) -> DVec3 {
-    debug!("getting dv");
+    throttled!(Level::DEBUG, "getting dv");
     (previous_acc + acc) * dt / 2.
 }
 
 #[cfg(test)]
 mod tests {
-    use std::f64::consts::PI;
-
     use bevy::app::FixedMain;
 
     use super::*;
 
     use crate::{prelude::*, utils::algebra::circular_orbit_around_body};
 
+    #[test]
+    fn test_acceleration_log() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer));
+        app.update();
+        let world = app.world_mut();
+        let earth = world.resource::<BodiesMapping>().0["terre"];
+        let (&mass, &earth_pos, &earth_speed) = world
+            .query::<(&Mass, &Position, &Velocity)>()
+            .get(world, earth)
+            .unwrap();
+        let (spawn_pos, spawn_speed) = circular_orbit_around_body(
+            1e5,
+            mass.0,
+            earth_pos.0,
+            earth_speed.0,
+            &mut rand::thread_rng(),
+        );
+        app.world_mut().send_event(ShipEvent::Create(ShipInfo {
+            id: id_from("s"),
+            spawn_pos,
+            spawn_speed,
+            spawn_orbit: None,
+        }));
+        app.update();
+        // The ships only log their perturbations when asked to
+        let ship = app.world().resource::<ShipsMapping>().0["s"];
+        app.world_mut()
+            .entity_mut(ship)
+            .insert(AccelerationLog::default());
+        app.world_mut()
+            .resource_mut::<NextState<GameStage>>()
+            .set(GameStage::Action);
+        app.update();
+        for _ in 0..10 {
+            FixedMain::run_fixed_main(app.world_mut());
+        }
+        let world = app.world_mut();
+        let simtick = world.resource::<GameTime>().simtick;
+        let (log, influenced) = world
+            .query::<(&AccelerationLog, &Influenced)>()
+            .single(world);
+        assert_eq!(
+            log.entries.iter().filter(|e| e.simtick == simtick).count(),
+            influenced.influencers.len()
+        );
+        let earth_entry = log
+            .entries
+            .iter()
+            .rev()
+            .find(|e| e.body_id == id_from("terre"))
+            .unwrap();
+        let expected = G * mass.0 / 1e10 / (SECONDS_PER_DAY * SECONDS_PER_DAY);
+        assert!((earth_entry.contribution_km_s2 - expected).abs() / expected < 1e-2);
+
+        let mut log = AccelerationLog::default();
+        for simtick in 0..MAX_ACCELERATION_LOG_ENTRIES as u64 + 5 {
+            log.push(AccelerationEntry {
+                simtick,
+                body_id: id_from("terre"),
+                contribution_km_s2: 0.,
+            });
+        }
+        assert_eq!(log.entries.len(), MAX_ACCELERATION_LOG_ENTRIES);
+        assert_eq!(log.entries.front().unwrap().simtick, 5);
+    }
+
     #[test]
     fn test_leapfrog() {
         let mut app = App::new();
@@ -128,17 +325,23 @@ mod tests {
         app.update();
         let world = app.world_mut();
         let mapping = &world.resource::<BodiesMapping>().0;
-        let earth = mapping[&id_from("terre")];
+        let earth = mapping["terre"];
         let (&mass, &spawn_earth_pos, &spawn_earth_speed) = world
             .query::<(&Mass, &mut Position, &Velocity)>()
             .get(world, earth)
             .unwrap();
-        let (spawn_pos, spawn_speed) =
-            circular_orbit_around_body(1e5, mass.0, spawn_earth_pos.0, spawn_earth_speed.0);
+        let (spawn_pos, spawn_speed) = circular_orbit_around_body(
+            1e5,
+            mass.0,
+            spawn_earth_pos.0,
+            spawn_earth_speed.0,
+            &mut rand::thread_rng(),
+        );
         app.world_mut().send_event(ShipEvent::Create(ShipInfo {
             id: id_from("s"),
             spawn_pos,
             spawn_speed,
+            spawn_orbit: None,
         }));
         app.update();
         let period = 2. * PI * (1e5_f64).powf(3. / 2.) / (G * mass.0).sqrt();
@@ -160,4 +363,40 @@ mod tests {
         // dbg!(pos - earth_pos.0);
         assert!(((spawn_pos - spawn_earth_pos.0) - (pos - earth_pos.0)).length() < 2e4);
     }
+
+    #[test]
+    fn test_ring_contribution() {
+        let rings = RingSystem::of_main_body("saturne").unwrap();
+        let normal = DVec3::Z;
+        let above = get_ring_contribution(DVec3::new(1e5, 0., 0.5), normal, &rings);
+        let below = get_ring_contribution(DVec3::new(0., -1e5, -0.5), normal, &rings);
+        assert!(above.z < 0. && below.z > 0.);
+        assert_eq!(above, -below);
+        assert_eq!((above.x, above.y), (0., 0.));
+        let expected = 2. * PI * G * rings.surface_density_kg_m2 * 1e6;
+        assert!((above.length() - expected).abs() < 1e-12);
+        // Outside of the rings, either too high or too far
+        for pos in [
+            DVec3::new(1e5, 0., 10.),
+            DVec3::new(5e4, 0., 0.5),
+            DVec3::new(2e5, 0., 0.5),
+        ] {
+            assert_eq!(get_ring_contribution(pos, normal, &rings), DVec3::ZERO);
+        }
+    }
+
+    #[test]
+    fn test_rings_spawned() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Explorer));
+        app.update();
+        app.update();
+        let world = app.world_mut();
+        let ringed: Vec<_> = world
+            .query_filtered::<&BodyInfo, With<RingSystem>>()
+            .iter(world)
+            .map(|info| info.0.id)
+            .collect();
+        assert_eq!(ringed, vec![id_from("saturne")]);
+    }
 }
@@ -0,0 +1,792 @@
+use bevy::{
+    math::{DMat3, DVec3},
+    prelude::*,
+};
+
+use crate::{
+    objects::prelude::{Atmosphere, BodyInfo, PrimaryBody, ShipInfo},
+    physics::{
+        gravity::{body_frame, GravityCoefficients},
+        leapfrog::LeapfrogUpdate,
+        prelude::*,
+        PhysicsUpdate, AU, G, SECONDS_PER_DAY,
+    },
+    utils::algebra::osculating_elements,
+};
+
+pub fn plugin(app: &mut App) {
+    app.register_type::<StationKeepingReport>().add_systems(
+        FixedUpdate,
+        update_station_keeping
+            .after(LeapfrogUpdate)
+            .in_set(PhysicsUpdate),
+    );
+}
+
+// See https://en.wikipedia.org/wiki/Clohessy%E2%80%93Wiltshire_equations
+// Relative positions and velocities are expressed in the Hill frame of the target:
+// x is radial (pointing away from the host body), y is along-track and z is the orbit normal.
+// Velocities are measured in this rotating frame.
+
+/// Blocks of the state transition matrix of the Clohessy-Wiltshire equations after a time `t`,
+/// in the order (position from position, position from velocity, velocity from position,
+/// velocity from velocity)
+fn cw_state_transition(mean_motion: f64, t: f64) -> [DMat3; 4] {
+    let n = mean_motion;
+    let (s, c) = (n * t).sin_cos();
+    let rr = DMat3::from_cols(
+        DVec3::new(4. - 3. * c, 6. * (s - n * t), 0.),
+        DVec3::new(0., 1., 0.),
+        DVec3::new(0., 0., c),
+    );
+    let rv = DMat3::from_cols(
+        DVec3::new(s / n, -2. * (1. - c) / n, 0.),
+        DVec3::new(2. * (1. - c) / n, (4. * s - 3. * n * t) / n, 0.),
+        DVec3::new(0., 0., s / n),
+    );
+    let vr = DMat3::from_cols(
+        DVec3::new(3. * n * s, -6. * n * (1. - c), 0.),
+        DVec3::ZERO,
+        DVec3::new(0., 0., -n * s),
+    );
+    let vv = DMat3::from_cols(
+        DVec3::new(c, -2. * s, 0.),
+        DVec3::new(2. * s, 4. * c - 3., 0.),
+        DVec3::new(0., 0., c),
+    );
+    [rr, rv, vr, vv]
+}
+
+/// Propagates the relative position and velocity of a chaser around a target on a circular orbit
+/// with the given mean motion (in radians per day), after a time `t` (in days)
+pub fn cw_propagate(rel_pos: DVec3, rel_vel: DVec3, mean_motion: f64, t: f64) -> (DVec3, DVec3) {
+    let [rr, rv, vr, vv] = cw_state_transition(mean_motion, t);
+    (rr * rel_pos + rv * rel_vel, vr * rel_pos + vv * rel_vel)
+}
+
+/// Computes the impulse (in km/day) that brings a chaser from its position relative to a target
+/// to the target itself after `dt` days, following the Clohessy-Wiltshire linearized equations.
+///
+/// The velocity of the chaser on arrival is given by [cw_propagate], and cancelling it with a
+/// second impulse completes the rendezvous
+pub fn cw_rendezvous_burn(rel_pos: DVec3, rel_vel: DVec3, mean_motion: f64, dt: f64) -> DVec3 {
+    cw_transfer_burn(rel_pos, rel_vel, mean_motion, dt, DVec3::ZERO)
+}
+
+/// Computes the impulse (in km/day) that brings a chaser to `arrival_pos` relative to the target
+/// after `dt` days. The equations are not invariant by translation, so an offset from the target
+/// can't be handled by moving the origin of the relative position
+pub fn cw_transfer_burn(
+    rel_pos: DVec3,
+    rel_vel: DVec3,
+    mean_motion: f64,
+    dt: f64,
+    arrival_pos: DVec3,
+) -> DVec3 {
+    let [rr, rv, _, _] = cw_state_transition(mean_motion, dt);
+    let required_vel = rv.inverse() * (arrival_pos - rr * rel_pos);
+    required_vel - rel_vel
+}
+
+/// Delta-v (in km/s) needed to rotate the velocity of a ship going at `v_km_s` by
+/// `delta_i_rad`, which is cheapest where the ship is the slowest
+pub fn plane_change_dv(v_km_s: f64, delta_i_rad: f64) -> f64 {
+    2. * v_km_s * (delta_i_rad / 2.).sin().abs()
+}
+
+/// Delta-v needed to turn a velocity by `angle` while changing its magnitude from `from` to `to`
+fn turning_burn(from: f64, to: f64, angle: f64) -> f64 {
+    (from * from + to * to - 2. * from * to * angle.cos())
+        .max(0.)
+        .sqrt()
+}
+
+/// Speeds at the first and second burns on the transfer ellipse between radii `r1` and `r2`
+fn transfer_speeds(r1: f64, r2: f64, gm: f64) -> (f64, f64) {
+    let a = (r1 + r2) / 2.;
+    (
+        (gm * (2. / r1 - 1. / a)).sqrt(),
+        (gm * (2. / r2 - 1. / a)).sqrt(),
+    )
+}
+
+/// Delta-v (in km/s) of a low-thrust spiral between circular orbits where the ship goes at `v1`
+/// and `v2`, changing the inclination by `delta_i_rad` on the way (Edelbaum's approximation).
+/// The thrust is continuous, so the plane change is spread over the whole spiral
+pub fn edelbaum_dv(v1: f64, v2: f64, delta_i_rad: f64) -> f64 {
+    turning_burn(v1, v2, std::f64::consts::FRAC_PI_2 * delta_i_rad)
+}
+
+/// Delta-v of the two burns of a Hohmann transfer from a circular orbit of radius `r1` (in km)
+/// where the ship goes at `v1` to a circular orbit of radius `r2` where it goes at `v2`, when
+/// `first_rad` of the plane change is done at the first burn and `second_rad` at the second one
+fn hohmann_plane_change_burns(
+    (v1, v2): (f64, f64),
+    (first_rad, second_rad): (f64, f64),
+    (r1, r2): (f64, f64),
+    gm: f64,
+) -> (f64, f64) {
+    let (transfer_1, transfer_2) = transfer_speeds(r1, r2, gm);
+    (
+        turning_burn(v1, transfer_1, first_rad),
+        turning_burn(transfer_2, v2, second_rad),
+    )
+}
+
+/// Delta-v (in km/s) of a Hohmann transfer which also does the whole plane change of
+/// `delta_i_rad` at its second burn, at the apoapsis of the transfer when raising the orbit.
+/// See [combined_plane_change_dv] for the units
+pub fn apoapsis_plane_change_dv(
+    v1: f64,
+    v2: f64,
+    delta_i_rad: f64,
+    r1: f64,
+    r2: f64,
+    gm: f64,
+) -> f64 {
+    let (first, second) = hohmann_plane_change_burns((v1, v2), (0., delta_i_rad), (r1, r2), gm);
+    first + second
+}
+
+/// Delta-v (in km/s) of the two burns of a Hohmann transfer from a circular orbit of radius `r1`
+/// where the ship goes at `v1` to a circular orbit of radius `r2` where it goes at `v2`, which
+/// also changes the plane of the orbit by `delta_i_rad`. Radii are in km and `gm` in km³/s².
+///
+/// Turning the velocity is cheaper where it is small, so most of the plane change happens at the
+/// second burn when raising the orbit, but doing a small part of it at the first burn is cheaper
+/// still. The returned tuple holds the delta-v of the first and second burns, and the part of
+/// the plane change done at the first burn (in radians), split to minimize the total delta-v
+pub fn combined_plane_change_dv(
+    v1: f64,
+    v2: f64,
+    delta_i_rad: f64,
+    r1: f64,
+    r2: f64,
+    gm: f64,
+) -> (f64, f64, f64) {
+    const SAMPLES: usize = 100;
+    let total = |first: f64| {
+        let (a, b) =
+            hohmann_plane_change_burns((v1, v2), (first, delta_i_rad - first), (r1, r2), gm);
+        a + b
+    };
+    // Coarse search first, since the total is not always unimodal (e.g. when r1 = r2)
+    let step = delta_i_rad / SAMPLES as f64;
+    let best = (0..=SAMPLES)
+        .min_by(|&i, &j| total(i as f64 * step).total_cmp(&total(j as f64 * step)))
+        .unwrap_or_default();
+    // Then golden-section search around the best sample
+    let ratio = (5f64.sqrt() - 1.) / 2.;
+    let (mut low, mut high) = (
+        (best.saturating_sub(1)) as f64 * step,
+        ((best + 1).min(SAMPLES)) as f64 * step,
+    );
+    for _ in 0..50 {
+        let (x1, x2) = (high - ratio * (high - low), low + ratio * (high - low));
+        if total(x1) < total(x2) {
+            high = x2;
+        } else {
+            low = x1;
+        }
+    }
+    let first = [(low + high) / 2., best as f64 * step]
+        .into_iter()
+        .min_by(|a, b| total(*a).total_cmp(&total(*b)))
+        .unwrap_or_default();
+    let (a, b) = hohmann_plane_change_burns((v1, v2), (first, delta_i_rad - first), (r1, r2), gm);
+    (a, b, first)
+}
+
+/// Time until a ship crosses a plane, and its distance to its host when it does
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeCrossing {
+    /// In days
+    pub delay: f64,
+    /// In km
+    pub radius: f64,
+}
+
+/// Finds when a ship at `rel_pos` going at `rel_speed` relative to its host (in km and km/day)
+/// next crosses the plane of normal `plane_normal`, upwards at the ascending node and downwards
+/// at the descending node. This is the same line of nodes as the one given by
+/// [EllipticalOrbit::long_asc_node] when the plane is the ecliptic.
+///
+/// Returns the ascending and descending node crossings, or None if the orbit is open or lies in
+/// the plane
+pub fn node_crossings(
+    rel_pos: DVec3,
+    rel_speed: DVec3,
+    gm: f64,
+    plane_normal: DVec3,
+) -> Option<(NodeCrossing, NodeCrossing)> {
+    let h = rel_pos.cross(rel_speed);
+    let node = plane_normal.cross(h);
+    if node.length() <= 1e-9 * h.length() * plane_normal.length() {
+        return None;
+    }
+    let (r, v) = (rel_pos.length(), rel_speed.length());
+    let a = 1. / (2. / r - v * v / gm);
+    let eccentricity = ((v * v - gm / r) * rel_pos - rel_pos.dot(rel_speed) * rel_speed) / gm;
+    let mut e = eccentricity.length();
+    if a <= 0. || e >= 1. {
+        return None;
+    }
+    // On circular orbits, angles are measured from the ship instead of the periapsis
+    let p_axis = if e > 1e-9 {
+        eccentricity / e
+    } else {
+        e = 0.;
+        rel_pos / r
+    };
+    let q_axis = h.normalize().cross(p_axis);
+    let true_anomaly = |d: DVec3| d.dot(q_axis).atan2(d.dot(p_axis));
+    let mean_anomaly = |nu: f64| {
+        let ecc_anomaly =
+            2. * ((1. - e).sqrt() * (nu / 2.).sin()).atan2((1. + e).sqrt() * (nu / 2.).cos());
+        ecc_anomaly - e * ecc_anomaly.sin()
+    };
+    let mean_motion = (gm / a.powi(3)).sqrt();
+    let current = mean_anomaly(true_anomaly(rel_pos));
+    let crossing = |d: DVec3| {
+        let nu = true_anomaly(d);
+        NodeCrossing {
+            delay: (mean_anomaly(nu) - current).rem_euclid(2. * std::f64::consts::PI) / mean_motion,
+            radius: a * (1. - e * e) / (1. + e * nu.cos()),
+        }
+    };
+    Some((crossing(node), crossing(-node)))
+}
+
+/// Drag coefficient times the cross-section over the mass of a ship braking in an atmosphere
+/// (in km²/kg), since ships don't describe their surfaces yet
+pub const AEROBRAKING_BALLISTIC_COEFFICIENT: f64 = 2.2e-8;
+
+/// Speed lost by a ship passing through the periapsis of its orbit at `entry_v`, `periapsis_alt`
+/// (in km) above the reference radius of the atmosphere. The whole pass is approximated as an
+/// impulse at periapsis: the air met along the orbit near periapsis amounts to the density there
+/// times √(2π·r·H), and the speed decays exponentially with it.
+///
+/// The delta-v is in the same unit as `entry_v`
+pub fn aerobraking_delta_v(entry_v: f64, periapsis_alt: f64, atmosphere: &Atmosphere) -> f64 {
+    let radius = atmosphere.radius_km + periapsis_alt;
+    let column = atmosphere.density(periapsis_alt)
+        * (2. * std::f64::consts::PI * radius * atmosphere.scale_height_km).sqrt();
+    entry_v * (1. - (-0.5 * AEROBRAKING_BALLISTIC_COEFFICIENT * column).exp())
+}
+
+/// Time until an object at `rel_pos` going at `rel_speed` relative to a body of gravitational
+/// parameter `gm` passes the periapsis of its osculating orbit (in days, with km and km/day).
+///
+/// Returns None on an escape trajectory past its periapsis
+pub fn time_to_periapsis(rel_pos: DVec3, rel_speed: DVec3, gm: f64) -> Option<f64> {
+    let elements = osculating_elements(rel_pos, rel_speed, gm);
+    let (r, a, e) = (
+        rel_pos.length(),
+        elements.semimajor_axis,
+        elements.eccentricity,
+    );
+    let approaching = rel_pos.dot(rel_speed) < 0.;
+    if let Some(period) = elements.period {
+        // The eccentric anomaly is negative before the periapsis
+        let cos_anomaly = if e > 1e-12 {
+            ((1. - r / a) / e).clamp(-1., 1.)
+        } else {
+            1.
+        };
+        let anomaly = if approaching {
+            -cos_anomaly.acos()
+        } else {
+            cos_anomaly.acos()
+        };
+        let mean_anomaly = anomaly - e * anomaly.sin();
+        Some(
+            (-mean_anomaly).rem_euclid(2. * std::f64::consts::PI) / (2. * std::f64::consts::PI)
+                * period,
+        )
+    } else if approaching {
+        // Hyperbolic anomaly, with a negative semi-major axis
+        let anomaly = -((1. - r / a) / e).max(1.).acosh();
+        let mean_anomaly = e * anomaly.sinh() - anomaly;
+        Some(-mean_anomaly / (gm / (-a).powi(3)).sqrt())
+    } else {
+        None
+    }
+}
+
+/// Number of scale heights above the reference radius of an atmosphere over which the air is too
+/// thin to brake a ship
+pub const AEROBRAKING_MAX_SCALE_HEIGHTS: f64 = 20.;
+
+/// Planned result of an aerobraking pass
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AerobrakeOutcome {
+    /// Altitude of the periapsis above the reference radius of the atmosphere (in km)
+    pub periapsis_alt: f64,
+    /// Speed at periapsis before the pass
+    pub entry_v: f64,
+    pub delta_v: f64,
+    /// Altitude of the apoapsis after the pass (in km), or None if the orbit stays open
+    pub apoapsis_alt: Option<f64>,
+}
+
+/// Aerobraking pass at the periapsis of the osculating orbit of a ship at `rel_pos` going at
+/// `rel_speed` relative to the body (in km and km/day). The speed at periapsis is found by
+/// conservation of energy, so the pass doesn't need to be the next one.
+///
+/// Returns None if the periapsis is above the atmosphere, see [AEROBRAKING_MAX_SCALE_HEIGHTS]
+pub fn aerobrake_outcome(
+    rel_pos: DVec3,
+    rel_speed: DVec3,
+    gm: f64,
+    atmosphere: &Atmosphere,
+) -> Option<AerobrakeOutcome> {
+    let periapsis = osculating_elements(rel_pos, rel_speed, gm).periapsis;
+    let periapsis_alt = periapsis - atmosphere.radius_km;
+    if periapsis_alt > AEROBRAKING_MAX_SCALE_HEIGHTS * atmosphere.scale_height_km {
+        return None;
+    }
+    let entry_v = (rel_speed.length_squared() + 2. * gm * (1. / periapsis - 1. / rel_pos.length()))
+        .max(0.)
+        .sqrt();
+    let delta_v = aerobraking_delta_v(entry_v, periapsis_alt, atmosphere);
+    let exit_v = entry_v - delta_v;
+    let a = 1. / (2. / periapsis - exit_v * exit_v / gm);
+    Some(AerobrakeOutcome {
+        periapsis_alt,
+        entry_v,
+        delta_v,
+        apoapsis_alt: (a > 0.).then_some(2. * a - periapsis - atmosphere.radius_km),
+    })
+}
+
+/// Days in a year, the period over which station-keeping budgets are estimated
+const DAYS_PER_YEAR: f64 = 365.25;
+
+/// Solar radiation pressure at 1 AU (in N/m²)
+const SOLAR_PRESSURE_1AU: f64 = 4.56e-6;
+
+/// Margin kept on top of the estimated station-keeping budget, as a fraction of it
+pub const STATION_KEEPING_RESERVE: f64 = 0.1;
+
+/// Exponential atmosphere of a body, and drag properties of the ship flying through it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DragParams {
+    /// Drag coefficient times the cross-section over the mass of the ship (in km²/kg)
+    pub ballistic_coefficient: f64,
+    /// Density of the atmosphere at `reference_radius` from the center of the body (in kg/km³)
+    pub reference_density: f64,
+    /// Distance from the center of the body (in km)
+    pub reference_radius: f64,
+    /// Altitude difference over which the density is divided by e (in km)
+    pub scale_height: f64,
+}
+
+/// Sunlight pushing on a ship
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SRPParams {
+    /// Between 1 for a black body and 2 for a perfect mirror
+    pub reflectivity: f64,
+    /// Cross-section over the mass of the ship (in km²/kg)
+    pub area_to_mass: f64,
+    /// Distance between the ship and the Sun (in km)
+    pub sun_distance: f64,
+}
+
+impl SRPParams {
+    /// Small satellite of 0.01 m²/kg, since ships don't describe their surfaces yet
+    pub fn typical(sun_distance: f64) -> Self {
+        Self {
+            reflectivity: 1.3,
+            area_to_mass: 1e-8,
+            sun_distance,
+        }
+    }
+}
+
+/// Delta-v needed each year to counter each perturbation (in km/s)
+#[derive(Debug, Clone, Copy, PartialEq, Default, Reflect)]
+pub struct StationKeepingBudget {
+    pub drag: f64,
+    pub srp: f64,
+    pub j2: f64,
+}
+
+impl StationKeepingBudget {
+    pub fn total(&self) -> f64 {
+        self.drag + self.srp + self.j2
+    }
+}
+
+/// Estimates the delta-v needed each year to counter each perturbation of an orbit of semi-major
+/// axis `a_km`, eccentricity `e` and inclination `i_rad` (relative to the equator of the host).
+///
+/// - drag is compensated as it slows the ship down at periapsis, where the air is the densest
+/// - the radiation pressure pumps the eccentricity at a rate of 3/2·a/(n·a), and correcting the
+///   eccentricity costs n·a/2 per unit
+/// - the oblateness of the host makes the periapsis precess, which costs v·e/2 per radian to
+///   undo. `j2` is the J₂ coefficient of the host times the square of its radius (in km²), since
+///   both only appear together
+pub fn station_keeping_breakdown(
+    a_km: f64,
+    e: f64,
+    i_rad: f64,
+    drag_params: Option<&DragParams>,
+    srp_params: Option<&SRPParams>,
+    j2: Option<f64>,
+    gm: f64,
+) -> StationKeepingBudget {
+    let n = (gm / a_km.powi(3)).sqrt();
+    let drag = drag_params.map_or(0., |d| {
+        let periapsis = a_km * (1. - e);
+        let density =
+            d.reference_density * (-(periapsis - d.reference_radius) / d.scale_height).exp();
+        let speed_squared = gm * (2. / periapsis - 1. / a_km);
+        0.5 * density * speed_squared * d.ballistic_coefficient
+    });
+    let srp = srp_params.map_or(0., |s| {
+        let pressure = SOLAR_PRESSURE_1AU * (AU / s.sun_distance).powi(2);
+        // From m/s² to km/day²
+        let acceleration = pressure
+            * s.reflectivity
+            * s.area_to_mass
+            * 1e6
+            * 1e-3
+            * SECONDS_PER_DAY
+            * SECONDS_PER_DAY;
+        0.75 * acceleration
+    });
+    let j2 = j2.map_or(0., |j2| {
+        let p = a_km * (1. - e * e);
+        let cos_i = i_rad.cos();
+        let apsidal_rate = 0.75 * n * j2 / (p * p) * (5. * cos_i * cos_i - 1.);
+        n * a_km * e / 2. * apsidal_rate.abs()
+    });
+    let yearly = |dv: f64| dv * DAYS_PER_YEAR / SECONDS_PER_DAY;
+    StationKeepingBudget {
+        drag: yearly(drag),
+        srp: yearly(srp),
+        j2: yearly(j2),
+    }
+}
+
+/// Total delta-v needed each year to keep an orbit in place (in km/s), see
+/// [station_keeping_breakdown]
+pub fn station_keeping_dv_budget(
+    a_km: f64,
+    e: f64,
+    i_rad: f64,
+    drag_params: Option<&DragParams>,
+    srp_params: Option<&SRPParams>,
+    j2: Option<f64>,
+    gm: f64,
+) -> f64 {
+    station_keeping_breakdown(a_km, e, i_rad, drag_params, srp_params, j2, gm).total()
+}
+
+/// Yearly station-keeping budget of a ship around its main influencer, updated when its orbit
+/// changes significantly.
+///
+/// Bodies don't have atmospheres yet, so drag is left out of the estimate
+#[derive(Component, Debug, Clone, Copy, PartialEq, Reflect)]
+#[reflect(Component)]
+pub struct StationKeepingReport {
+    /// Estimate including the reserve (in km/s)
+    pub annual_dv_km_s: f64,
+    pub reserve_fraction: f64,
+    pub breakdown: StationKeepingBudget,
+    /// Semi-major axis, eccentricity and inclination the report was computed for
+    elements: (f64, f64, f64),
+}
+
+impl StationKeepingReport {
+    fn new(breakdown: StationKeepingBudget, elements: (f64, f64, f64)) -> Self {
+        Self {
+            annual_dv_km_s: breakdown.total() * (1. + STATION_KEEPING_RESERVE),
+            reserve_fraction: STATION_KEEPING_RESERVE,
+            breakdown,
+            elements,
+        }
+    }
+
+    /// Whether the orbit changed enough since the report was computed to update it
+    fn is_outdated(&self, (a, e, i): (f64, f64, f64)) -> bool {
+        let (old_a, old_e, old_i) = self.elements;
+        ((a - old_a) / old_a).abs() > 1e-3 || (e - old_e).abs() > 1e-3 || (i - old_i).abs() > 1e-3
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn update_station_keeping(
+    mut commands: Commands,
+    ships: Query<
+        (
+            Entity,
+            &Position,
+            &Velocity,
+            &Influenced,
+            Option<&StationKeepingReport>,
+        ),
+        With<ShipInfo>,
+    >,
+    bodies: Query<(
+        &BodyInfo,
+        &Position,
+        &Velocity,
+        &EllipticalOrbit,
+        Option<&GravityCoefficients>,
+    )>,
+    primary: Query<&Position, With<PrimaryBody>>,
+    time: Res<GameTime>,
+) {
+    let sun = primary.get_single().map_or(DVec3::ZERO, |p| p.0);
+    for (entity, pos, vel, influence, report) in ships.iter() {
+        let Some((info, host_pos, host_vel, orbit, coefficients)) =
+            influence.main_influencer.and_then(|e| bodies.get(e).ok())
+        else {
+            continue;
+        };
+        let (rel_pos, rel_vel) = (pos.0 - host_pos.0, vel.0 - host_vel.0);
+        let data = &info.0;
+        let gm = G * data.mass;
+        let osculating = osculating_elements(rel_pos, rel_vel, gm);
+        if osculating.period.is_none() {
+            continue;
+        }
+        let spin_axis = body_frame(info, orbit, time.time()).z_axis;
+        let inclination = rel_pos.cross(rel_vel).angle_between(spin_axis);
+        let elements = (
+            osculating.semimajor_axis,
+            osculating.eccentricity,
+            inclination,
+        );
+        if report.is_some_and(|r| !r.is_outdated(elements)) {
+            continue;
+        }
+        let breakdown = station_keeping_breakdown(
+            elements.0,
+            elements.1,
+            elements.2,
+            None,
+            Some(&SRPParams::typical(pos.0.distance(sun).max(1.))),
+            coefficients.map(|c| c.j2() * data.radius * data.radius),
+            gm,
+        );
+        commands
+            .entity(entity)
+            .insert(StationKeepingReport::new(breakdown, elements));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use bevy::math::DVec3;
+
+    use super::{
+        aerobrake_outcome, aerobraking_delta_v, apoapsis_plane_change_dv, combined_plane_change_dv,
+        cw_propagate, cw_rendezvous_burn, edelbaum_dv, node_crossings, plane_change_dv,
+        station_keeping_breakdown, time_to_periapsis, DragParams, SRPParams,
+    };
+    use crate::{
+        objects::prelude::Atmosphere,
+        physics::{AU, G},
+    };
+
+    #[test]
+    fn test_cw_propagate() {
+        let n = 2. * PI / 0.0625;
+        // An object at rest on the same orbit stays at the same place
+        let (pos, vel) = cw_propagate(DVec3::new(0., -5., 0.), DVec3::ZERO, n, 0.02);
+        assert!((pos - DVec3::new(0., -5., 0.)).length() < 1e-9);
+        assert!(vel.length() < 1e-9);
+        // An out of plane offset oscillates with the orbital period
+        let (pos, _) = cw_propagate(DVec3::new(0., 0., 1.), DVec3::ZERO, n, 0.0625);
+        assert!((pos - DVec3::new(0., 0., 1.)).length() < 1e-9);
+    }
+
+    #[test]
+    fn test_cw_rendezvous_burn() {
+        let n = 2. * PI / 0.0625;
+        let dt = 0.0625 / 4.;
+        let (rel_pos, rel_vel) = (DVec3::new(1., -8., 0.5), DVec3::new(0., 10., -3.));
+        let burn = cw_rendezvous_burn(rel_pos, rel_vel, n, dt);
+        let (pos, vel) = cw_propagate(rel_pos, rel_vel + burn, n, dt);
+        assert!(pos.length() < 1e-9);
+        assert!(vel.length() > 0.);
+    }
+
+    #[test]
+    fn test_station_keeping() {
+        const EARTH_MASS: f64 = 5.972e24;
+        const EARTH_RADIUS: f64 = 6378.;
+        let gm = G * EARTH_MASS;
+
+        // A space station at 400 km needs a few tens of m/s each year against drag
+        let drag = DragParams {
+            ballistic_coefficient: 2.2e-8,
+            reference_density: 2.8e-3,
+            reference_radius: EARTH_RADIUS + 400.,
+            scale_height: 60.,
+        };
+        let budget = station_keeping_breakdown(
+            EARTH_RADIUS + 400.,
+            0.,
+            0.9,
+            Some(&drag),
+            None,
+            Some(1.0826e-3 * EARTH_RADIUS * EARTH_RADIUS),
+            gm,
+        );
+        assert!((0.01..0.2).contains(&budget.drag), "{budget:?}");
+        // Circular orbits have no periapsis to keep in place
+        assert_eq!(budget.j2, 0.);
+        assert_eq!(budget.srp, 0.);
+
+        // A geostationary satellite needs a few m/s against radiation pressure
+        let budget = station_keeping_breakdown(
+            42164.,
+            1e-4,
+            0.,
+            None,
+            Some(&SRPParams::typical(AU)),
+            None,
+            gm,
+        );
+        assert!((1e-3..1e-2).contains(&budget.srp), "{budget:?}");
+        assert_eq!(budget.total(), budget.srp);
+
+        // The periapsis of an eccentric orbit doesn't precess at the critical inclination
+        let molniya = |i: f64| {
+            station_keeping_breakdown(
+                26600.,
+                0.74,
+                i,
+                None,
+                None,
+                Some(1.0826e-3 * EARTH_RADIUS * EARTH_RADIUS),
+                gm,
+            )
+            .j2
+        };
+        assert!(molniya((1f64 / 5.).sqrt().acos()) < 1e-9);
+        assert!(molniya(0.5) > 0.1);
+    }
+
+    #[test]
+    fn test_plane_change() {
+        assert!((plane_change_dv(7.8, PI / 3.) - 7.8).abs() < 1e-9);
+        assert_eq!(plane_change_dv(7.8, 0.), 0.);
+
+        // From a low orbit to a geostationary one, out of Cape Canaveral
+        let gm: f64 = 398600.;
+        let (r1, r2) = (6678., 42164.);
+        let (v1, v2) = ((gm / r1).sqrt(), (gm / r2).sqrt());
+        let delta_i = 28.5f64.to_radians();
+        let (first, second, split) = combined_plane_change_dv(v1, v2, delta_i, r1, r2, gm);
+        let at_apoapsis = apoapsis_plane_change_dv(v1, v2, delta_i, r1, r2, gm);
+        assert!(first + second <= at_apoapsis);
+        assert!(
+            (4.2..4.35).contains(&(first + second)),
+            "{}",
+            first + second
+        );
+        assert!((0.5f64.to_radians()..5f64.to_radians()).contains(&split));
+        // Without plane change, this is a plain Hohmann transfer
+        let (first, second, split) = combined_plane_change_dv(v1, v2, 0., r1, r2, gm);
+        assert!((first + second - 3.89).abs() < 0.01);
+        assert_eq!(split, 0.);
+        // On the same orbit, the plane change is done in one burn
+        let (first, second, _) = combined_plane_change_dv(v1, v1, delta_i, r1, r1, gm);
+        assert!((first + second - plane_change_dv(v1, delta_i)).abs() < 1e-6);
+
+        // Spiraling up costs more than the impulsive transfer
+        let spiral = edelbaum_dv(v1, v2, delta_i);
+        assert!((5.9..6.).contains(&spiral), "{spiral}");
+        assert!((edelbaum_dv(v1, v2, 0.) - (v1 - v2)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_aerobraking() {
+        let earth = Atmosphere::of_main_body("terre").unwrap();
+        assert!(Atmosphere::of_main_body("lune").is_none());
+        // Deeper passes brake more, and the air is too thin to matter higher up
+        let deep = aerobraking_delta_v(11., 80., &earth);
+        let shallow = aerobraking_delta_v(11., 100., &earth);
+        assert!(deep > shallow && deep < 11.);
+        assert!(aerobraking_delta_v(11., 200., &earth) < 1e-3);
+
+        // Arriving from Mars at 4 km/s, in km and km/s, at the periapsis
+        let gm = 398600.;
+        let arrival = |alt: f64| {
+            let periapsis = earth.radius_km + alt;
+            (
+                DVec3::new(periapsis, 0., 0.),
+                DVec3::new(0., (16. + 2. * gm / periapsis).sqrt(), 0.),
+            )
+        };
+        let (pos, speed) = arrival(100.);
+        let outcome = aerobrake_outcome(pos, speed, gm, &earth).unwrap();
+        assert!((outcome.periapsis_alt - 100.).abs() < 1e-6);
+        let escape = (2. * gm / (earth.radius_km + 100.)).sqrt();
+        assert!(outcome.entry_v > escape && outcome.entry_v - outcome.delta_v < escape);
+        assert!(outcome.apoapsis_alt.is_some_and(|a| a > 100.));
+        // Too high to be captured, then above the atmosphere
+        let (pos, speed) = arrival(150.);
+        let outcome = aerobrake_outcome(pos, speed, gm, &earth).unwrap();
+        assert_eq!(outcome.apoapsis_alt, None);
+        let (pos, speed) = arrival(300.);
+        assert_eq!(aerobrake_outcome(pos, speed, gm, &earth), None);
+    }
+
+    #[test]
+    fn test_time_to_periapsis() {
+        let gm = 398600.;
+        // At the apoapsis of an orbit, half a period away from the periapsis
+        let (periapsis, apoapsis) = (7000., 9000.);
+        let a: f64 = (periapsis + apoapsis) / 2.;
+        let speed = (gm * (2. / apoapsis - 1. / a)).sqrt();
+        let period = 2. * PI * (a.powi(3) / gm).sqrt();
+        let t = time_to_periapsis(
+            DVec3::new(-apoapsis, 0., 0.),
+            DVec3::new(0., -speed, 0.),
+            gm,
+        );
+        assert!((t.unwrap() - period / 2.).abs() < 1e-6, "{t:?}");
+        // Right after the periapsis, a whole period away
+        let speed = (gm * (2. / periapsis - 1. / a)).sqrt();
+        let (pos, vel) = (DVec3::new(periapsis, 0., 0.), DVec3::new(0., speed, 0.));
+        let t = time_to_periapsis(pos + vel * 1e-3, vel, gm).unwrap();
+        assert!(t > period * 0.99 && t < period, "{t}");
+
+        // On an escape trajectory, going back 100s from the periapsis
+        let vel = DVec3::new(0., (2.5 * gm / periapsis).sqrt(), 0.);
+        let (mut p, mut v) = (pos, vel);
+        let dt = -0.01;
+        for _ in 0..10000 {
+            v += -gm * p / p.length().powi(3) * dt / 2.;
+            p += v * dt;
+            v += -gm * p / p.length().powi(3) * dt / 2.;
+        }
+        let before = time_to_periapsis(p, v, gm).unwrap();
+        assert!((before - 100.).abs() < 1e-3, "{before}");
+        assert_eq!(time_to_periapsis(pos + vel, vel, gm), None);
+    }
+
+    #[test]
+    fn test_node_crossings() {
+        let gm = G * 5.972e24;
+        let radius = 7000.;
+        let speed = (gm / radius).sqrt();
+        let period = 2. * PI * radius / speed;
+        let (s, c) = (PI / 6.).sin_cos();
+        // Highest point of an orbit inclined around the X axis, which is the line of nodes
+        let pos = radius * DVec3::new(0., c, s);
+        let vel = speed * DVec3::new(-1., 0., 0.);
+        let (ascending, descending) = node_crossings(pos, vel, gm, DVec3::Z).unwrap();
+        assert!((descending.delay - period / 4.).abs() < 1e-9 * period);
+        assert!((ascending.delay - 3. * period / 4.).abs() < 1e-9 * period);
+        assert!((ascending.radius - radius).abs() < 1e-6);
+        assert!(node_crossings(DVec3::X * radius, DVec3::Y * speed, gm, DVec3::Z).is_none());
+    }
+}
@@ -0,0 +1,371 @@
+//! Search of maneuver sequences meeting trajectory constraints with a genetic algorithm.
+//!
+//! Each chromosome is a list of burns, given in the orbital frame of the central body like the
+//! thrust of [ManeuverNode]s, at most one per tick. Chromosomes are evaluated by integrating the
+//! trajectory of the ship around the central body alone with a leapfrog, one step per simtick.
+
+use bevy::{math::DVec3, utils::HashMap};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    objects::{prelude::BodyID, ships::trajectory::ManeuverNode},
+    utils::algebra::orbital_to_global_matrix,
+};
+
+use super::{
+    leapfrog::{get_dv, get_dx},
+    time::{GAMETIME_PER_SIMTICK, SIMTICKS_PER_TICK},
+    Position, Velocity,
+};
+
+/// Positions of bodies relative to the central body, sampled every tick from the start of the
+/// optimization
+pub type Ephemerides = HashMap<BodyID, Vec<DVec3>>;
+
+/// Position of a body at a simtick counted from the start of the optimization, interpolated
+/// between the ticks of its ephemeris
+fn ephemeris_at(ephemeris: &[DVec3], simtick: u64) -> Option<DVec3> {
+    let tick = (simtick / SIMTICKS_PER_TICK) as usize;
+    let before = *ephemeris.get(tick)?;
+    let fraction = (simtick % SIMTICKS_PER_TICK) as f64 / SIMTICKS_PER_TICK as f64;
+    match ephemeris.get(tick + 1) {
+        Some(after) => Some(before.lerp(*after, fraction)),
+        None => (fraction == 0.).then_some(before),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrajectoryConstraint {
+    /// Pass within `tolerance_km` of the body before the end of the horizon
+    ReachBody {
+        body: BodyID,
+        tolerance_km: f64,
+    },
+    MinimizeDV,
+    /// Without a propellant model the final mass only depends on the total delta-v through the
+    /// rocket equation, so this is equivalent to [TrajectoryConstraint::MinimizeDV]
+    MaximizeFinalMass,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeneticConfig {
+    pub population_size: usize,
+    pub generations: usize,
+    /// Probability that two parents are crossed instead of copying the first one
+    pub crossover_rate: f64,
+    /// Probability that each burn is perturbed, and that a burn is added or removed
+    pub mutation_rate: f64,
+    pub max_burns: usize,
+    /// Number of ticks over which the trajectories are simulated
+    pub horizon_ticks: u64,
+    pub seed: u64,
+}
+
+impl Default for GeneticConfig {
+    fn default() -> Self {
+        Self {
+            population_size: 50,
+            generations: 100,
+            crossover_rate: 0.8,
+            mutation_rate: 0.2,
+            max_burns: 3,
+            horizon_ticks: 100,
+            seed: 0,
+        }
+    }
+}
+
+/// A burn of a chromosome, at a tick counted from the start of the optimization
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Gene {
+    tick: u64,
+    thrust: DVec3,
+}
+
+type Chromosome = Vec<Gene>;
+
+/// Best maneuvers found by [optimize_trajectory]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ManeuverSequence {
+    /// Ticks of the burns, counted from the start of the optimization, and thrusts in the orbital
+    /// frame of the central body
+    pub burns: Vec<(u64, DVec3)>,
+    pub delta_v: f64,
+    /// Closest approach to each body of a [TrajectoryConstraint::ReachBody] constraint
+    pub closest_approaches: Vec<(BodyID, f64)>,
+    /// Value of the cost function, lower is better
+    pub cost: f64,
+}
+
+impl ManeuverSequence {
+    /// Maneuver nodes of the burns, relative to the central body and starting at tick `start`
+    pub fn nodes(
+        &self,
+        origin: BodyID,
+        start: u64,
+    ) -> impl Iterator<Item = (u64, ManeuverNode)> + '_ {
+        self.burns
+            .iter()
+            .enumerate()
+            .map(move |(i, &(tick, thrust))| {
+                (
+                    start + tick,
+                    ManeuverNode::impulsive(format!("optimized-{}", i + 1), thrust, origin),
+                )
+            })
+    }
+}
+
+/// Simulates the chromosome and returns its cost along with the closest approaches
+fn evaluate(
+    chromosome: &Chromosome,
+    (Position(start_pos), Velocity(start_vel)): &(Position, Velocity),
+    targets: &[TrajectoryConstraint],
+    gm: f64,
+    horizon_ticks: u64,
+    ephemerides: &Ephemerides,
+) -> (f64, f64, Vec<(BodyID, f64)>) {
+    let accel = |p: DVec3| -p * gm / p.length().powi(3);
+    let dt = GAMETIME_PER_SIMTICK;
+    let (mut pos, mut vel) = (*start_pos, *start_vel);
+    let mut acc = accel(pos);
+    let mut delta_v = 0.;
+    let mut closest: Vec<_> = targets
+        .iter()
+        .filter_map(|t| match t {
+            TrajectoryConstraint::ReachBody { body, .. } => Some((*body, f64::INFINITY)),
+            _ => None,
+        })
+        .collect();
+    let mut burns = chromosome.iter().peekable();
+    for simtick in 0..horizon_ticks * SIMTICKS_PER_TICK {
+        if simtick % SIMTICKS_PER_TICK == 0 {
+            while let Some(gene) = burns.next_if(|g| g.tick * SIMTICKS_PER_TICK <= simtick) {
+                vel += orbital_to_global_matrix(DVec3::ZERO, DVec3::ZERO, pos, vel) * gene.thrust;
+                delta_v += gene.thrust.length();
+            }
+        }
+        for (body, distance) in closest.iter_mut() {
+            if let Some(body_pos) = ephemerides.get(body).and_then(|e| ephemeris_at(e, simtick)) {
+                *distance = distance.min(pos.distance(body_pos));
+            }
+        }
+        pos += get_dx(vel, acc, dt);
+        let previous = acc;
+        acc = accel(pos);
+        vel += get_dv(previous, acc, dt);
+    }
+    // Costs are made dimensionless with the initial speed and the tolerances
+    let speed_scale = start_vel.length().max(f64::EPSILON);
+    let cost = targets
+        .iter()
+        .map(|t| match t {
+            TrajectoryConstraint::ReachBody { body, tolerance_km } => {
+                let distance = closest.iter().find(|(b, _)| b == body).unwrap().1;
+                ((distance - tolerance_km) / tolerance_km).max(0.)
+            }
+            TrajectoryConstraint::MinimizeDV | TrajectoryConstraint::MaximizeFinalMass => {
+                delta_v / speed_scale
+            }
+        })
+        .sum();
+    (cost, delta_v, closest)
+}
+
+fn random_gene(rng: &mut StdRng, horizon_ticks: u64, thrust_scale: f64) -> Gene {
+    Gene {
+        tick: rng.gen_range(0..horizon_ticks.max(1)),
+        thrust: DVec3::new(
+            rng.gen_range(-1. ..=1.),
+            rng.gen_range(-1. ..=1.),
+            rng.gen_range(-1. ..=1.),
+        ) * thrust_scale,
+    }
+}
+
+/// Sorts the burns by tick and keeps a single burn per tick, like in a [Trajectory](crate::objects::ships::trajectory::Trajectory)
+fn normalize(chromosome: &mut Chromosome, max_burns: usize) {
+    chromosome.sort_by_key(|g| g.tick);
+    chromosome.dedup_by_key(|g| g.tick);
+    chromosome.truncate(max_burns);
+}
+
+fn tournament<'a>(rng: &mut StdRng, scored: &'a [(f64, Chromosome)]) -> &'a Chromosome {
+    (0..3)
+        .map(|_| &scored[rng.gen_range(0..scored.len())])
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, c)| c)
+        .unwrap()
+}
+
+fn crossover(rng: &mut StdRng, a: &Chromosome, b: &Chromosome, max_burns: usize) -> Chromosome {
+    let cut = rng.gen_range(0..=a.len());
+    let cut_b = rng.gen_range(0..=b.len());
+    let mut child: Chromosome = a[..cut].iter().chain(&b[cut_b..]).copied().collect();
+    normalize(&mut child, max_burns);
+    child
+}
+
+fn mutate(
+    rng: &mut StdRng,
+    chromosome: &mut Chromosome,
+    config: &GeneticConfig,
+    thrust_scale: f64,
+) {
+    let horizon = config.horizon_ticks.max(1);
+    for gene in chromosome.iter_mut() {
+        if rng.gen_bool(config.mutation_rate) {
+            gene.thrust += random_gene(rng, horizon, thrust_scale * 0.1).thrust;
+            let shift = rng.gen_range(-((horizon / 20) as i64)..=(horizon / 20) as i64);
+            gene.tick = gene.tick.saturating_add_signed(shift).min(horizon - 1);
+        }
+    }
+    if rng.gen_bool(config.mutation_rate) {
+        if chromosome.len() < config.max_burns && rng.gen_bool(0.5) {
+            chromosome.push(random_gene(rng, horizon, thrust_scale));
+        } else if !chromosome.is_empty() {
+            chromosome.remove(rng.gen_range(0..chromosome.len()));
+        }
+    }
+    normalize(chromosome, config.max_burns);
+}
+
+/// Searches the burns that best satisfy `targets` for a ship starting at `start` relative to a
+/// central body of gravitational parameter `gm` (in km³/day²).
+///
+/// The positions of the bodies of [TrajectoryConstraint::ReachBody] constraints are read from
+/// `ephemerides`, and bodies missing from it are never reached. The optimizer runs away from the
+/// world in a background task, so it can't look the bodies up by their ids like the rest of the
+/// physics does: their positions have to be sampled beforehand
+pub fn optimize_trajectory(
+    start: &(Position, Velocity),
+    targets: &[TrajectoryConstraint],
+    gm: f64,
+    config: GeneticConfig,
+    ephemerides: &Ephemerides,
+) -> ManeuverSequence {
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let thrust_scale = start.1 .0.length() * 0.3;
+    let score = |c: Chromosome| {
+        let (cost, ..) = evaluate(&c, start, targets, gm, config.horizon_ticks, ephemerides);
+        (cost, c)
+    };
+    let mut population: Vec<(f64, Chromosome)> = std::iter::once(Vec::new())
+        .chain((1..config.population_size.max(2)).map(|_| {
+            let mut c: Chromosome = (0..rng.gen_range(1..=config.max_burns.max(1)))
+                .map(|_| random_gene(&mut rng, config.horizon_ticks, thrust_scale))
+                .collect();
+            normalize(&mut c, config.max_burns);
+            c
+        }))
+        .map(score)
+        .collect();
+    for _ in 0..config.generations {
+        population.sort_by(|a, b| a.0.total_cmp(&b.0));
+        // The best chromosome is always kept
+        let mut next = vec![population[0].clone()];
+        while next.len() < population.len() {
+            let a = tournament(&mut rng, &population);
+            let mut child = if rng.gen_bool(config.crossover_rate) {
+                let b = tournament(&mut rng, &population);
+                crossover(&mut rng, a, b, config.max_burns)
+            } else {
+                a.clone()
+            };
+            mutate(&mut rng, &mut child, &config, thrust_scale);
+            next.push(score(child));
+        }
+        population = next;
+    }
+    let (_, best) = population
+        .into_iter()
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .unwrap();
+    let (cost, delta_v, closest_approaches) =
+        evaluate(&best, start, targets, gm, config.horizon_ticks, ephemerides);
+    ManeuverSequence {
+        burns: best.iter().map(|g| (g.tick, g.thrust)).collect(),
+        delta_v,
+        closest_approaches,
+        cost,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use bevy::{math::DVec3, utils::HashMap};
+
+    use crate::physics::{
+        time::{GAMETIME_PER_SIMTICK, SIMTICKS_PER_TICK},
+        Position, Velocity, G,
+    };
+    use crate::prelude::id_from;
+
+    use super::{ephemeris_at, optimize_trajectory, GeneticConfig, TrajectoryConstraint};
+
+    #[test]
+    fn test_ephemeris_at() {
+        let ephemeris = [DVec3::ZERO, DVec3::X * 10.];
+        assert_eq!(ephemeris_at(&ephemeris, 0), Some(DVec3::ZERO));
+        assert_eq!(
+            ephemeris_at(&ephemeris, SIMTICKS_PER_TICK / 2),
+            Some(DVec3::X * 5.)
+        );
+        assert_eq!(
+            ephemeris_at(&ephemeris, SIMTICKS_PER_TICK),
+            Some(DVec3::X * 10.)
+        );
+        assert_eq!(ephemeris_at(&ephemeris, SIMTICKS_PER_TICK + 1), None);
+    }
+
+    #[test]
+    fn test_optimize_trajectory() {
+        let gm = G * 5.972e24;
+        let (r1, r2) = (1e4, 2e4);
+        let start = (
+            Position(DVec3::new(r1, 0., 0.)),
+            Velocity(DVec3::new(0., (gm / r1).sqrt(), 0.)),
+        );
+        // Target on a circular orbit, placed so that a Hohmann transfer meets it
+        let transfer_time = PI * (((r1 + r2) / 2.).powi(3) / gm).sqrt();
+        let n2 = (gm / r2.powi(3)).sqrt();
+        let phase = PI - n2 * transfer_time;
+        let horizon_ticks =
+            (1.5 * transfer_time / (GAMETIME_PER_SIMTICK * SIMTICKS_PER_TICK as f64)) as u64;
+        let ephemeris = (0..=horizon_ticks)
+            .map(|t| {
+                let angle = phase + n2 * (t * SIMTICKS_PER_TICK) as f64 * GAMETIME_PER_SIMTICK;
+                r2 * DVec3::new(angle.cos(), angle.sin(), 0.)
+            })
+            .collect();
+        let target = id_from("target");
+        let ephemerides = HashMap::from_iter([(target, ephemeris)]);
+        let tolerance_km = 2000.;
+        let targets = [
+            TrajectoryConstraint::ReachBody {
+                body: target,
+                tolerance_km,
+            },
+            TrajectoryConstraint::MinimizeDV,
+        ];
+        let config = GeneticConfig {
+            population_size: 40,
+            generations: 40,
+            max_burns: 2,
+            horizon_ticks,
+            seed: 1,
+            ..Default::default()
+        };
+
+        let sequence = optimize_trajectory(&start, &targets, gm, config, &ephemerides);
+        assert!(sequence.closest_approaches[0].1 < tolerance_km);
+        assert!(!sequence.burns.is_empty());
+        // Optimizing again with the same seed gives the same maneuvers
+        assert_eq!(
+            sequence,
+            optimize_trajectory(&start, &targets, gm, config, &ephemerides)
+        );
+    }
+}
@@ -6,35 +6,147 @@ use bevy::{
 };
 
 use crate::{
-    game::Loaded,
+    game::{loading::LoadingPhase, Loaded},
     objects::prelude::*,
     physics::prelude::*,
     utils::algebra::{mod_180, rotate},
 };
 
-use super::time::GameTime;
+use super::{
+    time::{GameTime, SimStepSize, TickEvent, GAMETIME_PER_SIMTICK, SIMTICKS_PER_TICK},
+    PhysicsUpdate, AU,
+};
 
 pub fn plugin(app: &mut App) {
-    info!("loading orbit::plugin");
-    info!("adding system OnEnter(Loaded) : (update_local, update_global, insert_system_size).chain().in_set(OrbitsUpdate),");
-    app.add_systems(
-        OnEnter(Loaded),
-        (update_local, update_global, insert_system_size)
-            .chain()
-            .in_set(OrbitsUpdate),
+    debug!("loading orbit::plugin");
+    debug!("adding system OnEnter(LoadingPhase::ComputingOrbits) : (classify_bodies, update_local, update_global).chain().in_set(OrbitsUpdate),");
+    app.init_resource::<SystemSize>()
+        .register_type::<SimulationTier>()
+        .add_systems(
+            OnEnter(LoadingPhase::ComputingOrbits),
+            (classify_bodies, update_local, update_global)
+                .chain()
+                .in_set(OrbitsUpdate),
+        )
+        // The size of the system is only known once everything is loaded
+        .add_systems(OnEnter(Loaded), update_system_size.in_set(OrbitsUpdate));
+    debug!(
+        "adding system FixedUpdate : (update_local, update_global, update_system_size).chain().in_set(OrbitsUpdate),"
     );
-    info!(
-        "adding system FixedUpdate : (update_local, update_global).chain().in_set(OrbitsUpdate),"
-    );
-    app.add_systems(
+    app.configure_sets(
+        FixedUpdate,
+        (
+            InnerOrbitsUpdate.run_if(tier_due(SimulationTier::Inner)),
+            MiddleOrbitsUpdate.run_if(tier_due(SimulationTier::Middle)),
+            OuterOrbitsUpdate.run_if(tier_due(SimulationTier::Outer)),
+        )
+            .in_set(OrbitsUpdate),
+    )
+    .add_systems(
         FixedUpdate,
-        (update_local, update_global).chain().in_set(OrbitsUpdate),
+        (
+            classify_bodies,
+            (
+                update_tier_local(SimulationTier::Inner).in_set(InnerOrbitsUpdate),
+                update_tier_local(SimulationTier::Middle).in_set(MiddleOrbitsUpdate),
+                update_tier_local(SimulationTier::Outer).in_set(OuterOrbitsUpdate),
+            ),
+            update_global,
+            update_system_size.run_if(resource_exists_and_changed::<BodiesMapping>),
+        )
+            .chain()
+            .in_set(OrbitsUpdate),
     );
+    app.add_event::<ConjunctionCycleEvent>()
+        .init_resource::<SynodicMonitor>()
+        .add_systems(
+            FixedUpdate,
+            monitor_synodic_cycles
+                .after(OrbitsUpdate)
+                .in_set(PhysicsUpdate)
+                .run_if(on_event::<TickEvent>()),
+        );
 }
 
 #[derive(SystemSet, Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub struct OrbitsUpdate;
 
+/// Update of the orbits of the [SimulationTier::Inner] bodies, at each physics step
+#[derive(SystemSet, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct InnerOrbitsUpdate;
+
+/// Update of the orbits of the [SimulationTier::Middle] bodies
+#[derive(SystemSet, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct MiddleOrbitsUpdate;
+
+/// Update of the orbits of the [SimulationTier::Outer] bodies
+#[derive(SystemSet, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct OuterOrbitsUpdate;
+
+/// Bodies on larger orbits (in AU) are [SimulationTier::Middle]
+pub const INNER_TIER_MAX_AU: f64 = 2.;
+
+/// Bodies on larger orbits (in AU) are [SimulationTier::Outer]
+pub const MIDDLE_TIER_MAX_AU: f64 = 10.;
+
+/// How often the orbit of a body is propagated. Bodies on large orbits move slowly, so their
+/// positions on their orbits are only computed every few simticks, and extrapolated from their
+/// velocity in between. The moons are on small orbits, and follow their host when it moves
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Reflect)]
+#[reflect(Component)]
+pub enum SimulationTier {
+    #[default]
+    Inner,
+    Middle,
+    Outer,
+}
+
+impl SimulationTier {
+    /// Tier of a body on an orbit of the given semimajor axis (in km)
+    pub fn of(semimajor_axis: f64) -> Self {
+        match semimajor_axis / AU {
+            a if a < INNER_TIER_MAX_AU => Self::Inner,
+            a if a < MIDDLE_TIER_MAX_AU => Self::Middle,
+            _ => Self::Outer,
+        }
+    }
+
+    /// Number of simticks between two updates of the bodies of the tier
+    pub fn period(&self) -> u64 {
+        match self {
+            Self::Inner => 1,
+            Self::Middle => 10,
+            Self::Outer => 100,
+        }
+    }
+
+    /// Last simtick at which the bodies of the tier were propagated, at or before `simtick`
+    pub fn last_update(&self, simtick: u64) -> u64 {
+        simtick - simtick % self.period()
+    }
+}
+
+/// Whether the last physics step crossed a multiple of the period of the tier. It only depends on
+/// the simtick, so that the server, the clients and reloaded games update the same bodies
+fn tier_due(tier: SimulationTier) -> impl Fn(Res<GameTime>, Res<SimStepSize>) -> bool {
+    move |time: Res<GameTime>, step: Res<SimStepSize>| {
+        let period = tier.period();
+        time.simtick / period != time.simtick.saturating_sub(step.0.max(1)) / period
+    }
+}
+
+/// Gives a [SimulationTier] to the bodies that don't have one yet
+pub fn classify_bodies(
+    mut commands: Commands,
+    bodies: Query<(Entity, &BodyInfo), Without<SimulationTier>>,
+) {
+    for (entity, BodyInfo(data)) in bodies.iter() {
+        commands
+            .entity(entity)
+            .insert(SimulationTier::of(data.semimajor_axis));
+    }
+}
+
 #[derive(Component, Default, Clone, Debug)]
 pub struct EllipticalOrbit {
     pub eccentricity: f64,
@@ -116,6 +228,14 @@ impl EllipticalOrbit {
         self.local_pos = rotate(self.orbital_position, o, O, I);
         self.local_speed = rotate(self.orbital_velocity, o, O, I);
     }
+
+    /// Position and velocity with respect to the host body at the given time (in days), leaving
+    /// the orbit untouched
+    pub fn state_at(&self, time: f64) -> (DVec3, DVec3) {
+        let mut orbit = self.clone();
+        orbit.update_pos(time);
+        (orbit.local_pos, orbit.local_speed)
+    }
 }
 
 impl From<&BodyData> for EllipticalOrbit {
@@ -135,54 +255,451 @@ impl From<&BodyData> for EllipticalOrbit {
     }
 }
 
-pub fn update_local(mut orbits: Query<&mut EllipticalOrbit>, time: Res<GameTime>) {
+/// Propagates the orbits of all the bodies, at the last update of their tier
+pub fn update_local(
+    mut orbits: Query<(&mut EllipticalOrbit, Option<&SimulationTier>)>,
+    time: Res<GameTime>,
+) {
     //debug!("update_local");
-    orbits
-        .par_iter_mut()
-        .for_each(|mut o| o.update_pos(time.time()));
+    orbits.par_iter_mut().for_each(|(mut o, tier)| {
+        let simtick = tier.copied().unwrap_or_default().last_update(time.simtick);
+        o.update_pos(simtick as f64 * GAMETIME_PER_SIMTICK);
+    });
 }
 
+/// [update_local] for the bodies of a single tier, the bodies not classified yet being
+/// [SimulationTier::Inner]
+#[allow(clippy::type_complexity)]
+fn update_tier_local(
+    tier: SimulationTier,
+) -> impl FnMut(Query<(&mut EllipticalOrbit, Option<&SimulationTier>)>, Res<GameTime>) {
+    move |mut orbits, time| {
+        let simtick = tier.last_update(time.simtick);
+        orbits.par_iter_mut().for_each(|(mut o, body_tier)| {
+            if body_tier.copied().unwrap_or_default() == tier {
+                o.update_pos(simtick as f64 * GAMETIME_PER_SIMTICK);
+            }
+        });
+    }
+}
+
+/// Computes the global positions of the bodies from the positions on their orbits, which are
+/// extrapolated from their velocity since the last update of their tier. The positions only
+/// depend on the simtick, whatever updates the world went through
+#[allow(clippy::type_complexity)]
 pub fn update_global(
-    mut query: Query<(&mut Position, &mut Velocity, &EllipticalOrbit, &BodyInfo)>,
-    primary: Query<&BodyInfo, With<PrimaryBody>>,
+    mut query: Query<(
+        &mut Position,
+        &mut Velocity,
+        &EllipticalOrbit,
+        &BodyInfo,
+        Option<&SimulationTier>,
+    )>,
     mapping: Res<BodiesMapping>,
+    time: Res<GameTime>,
 ) {
     //debug!("update_global");
-    let mut queue = vec![(primary.single().0.id, (DVec3::ZERO, DVec3::ZERO))];
+    let roots: Vec<_> = query
+        .iter()
+        .filter(|(.., BodyInfo(data), _)| {
+            data.host_body
+                .map_or(true, |host| !mapping.0.contains_key(&host))
+        })
+        .map(|(.., BodyInfo(data), _)| data.id)
+        .collect();
+    // Going down from the roots, the hosts are placed before their satellites
+    let mut queue: Vec<_> = roots
+        .into_iter()
+        .map(|id| (id, (DVec3::ZERO, DVec3::ZERO)))
+        .collect();
     let mut i = 0;
     while i < queue.len() {
         let (id, (parent_pos, parent_velocity)) = queue[i];
-        if let Some(entity) = mapping.0.get(&id) {
-            if let Ok((mut world_pos, mut world_velocity, orbit, info)) = query.get_mut(*entity) {
-                let pos = parent_pos + orbit.local_pos;
-                let velocity = parent_velocity + orbit.local_speed;
-                world_pos.0 = pos;
-                world_velocity.0 = velocity;
-                queue.extend(info.0.orbiting_bodies.iter().map(|c| (*c, (pos, velocity))));
+        i += 1;
+        let Some(Ok((mut world_pos, mut world_velocity, orbit, info, tier))) =
+            mapping.0.get(&id).map(|&e| query.get_mut(e))
+        else {
+            continue;
+        };
+        let elapsed = (time.simtick - tier.copied().unwrap_or_default().last_update(time.simtick))
+            as f64
+            * GAMETIME_PER_SIMTICK;
+        let pos = parent_pos + orbit.local_pos + orbit.local_speed * elapsed;
+        let velocity = parent_velocity + orbit.local_speed;
+        world_pos.0 = pos;
+        world_velocity.0 = velocity;
+        queue.extend(info.0.orbiting_bodies.iter().map(|c| (*c, (pos, velocity))));
+    }
+}
+
+/// Time between two conjunctions of bodies with the orbital periods `t1_days` and `t2_days` (in
+/// days), which is infinite if the periods are equal
+pub fn synodic_period(t1_days: f64, t2_days: f64) -> f64 {
+    (t1_days * t2_days).abs() / (t1_days - t2_days).abs()
+}
+
+/// Angle between the reference direction and the mean position of the body on its orbit (in
+/// degrees)
+pub fn mean_longitude(orbit: &EllipticalOrbit) -> f64 {
+    orbit.long_asc_node + orbit.arg_periapsis + orbit.mean_anomaly
+}
+
+/// Sent each time two watched bodies complete a synodic cycle, that is when they are in
+/// conjunction
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConjunctionCycleEvent {
+    pub body_a: BodyID,
+    pub body_b: BodyID,
+    pub next_conjunction_tick: u64,
+}
+
+/// Two bodies whose conjunctions are watched, which should orbit the same host
+#[derive(Debug, Clone, PartialEq)]
+pub struct SynodicPair {
+    pub body_a: BodyID,
+    pub body_b: BodyID,
+    /// Angle between the mean longitudes of the bodies accumulated since it was first observed
+    /// (in synodic cycles), conjunctions happen when it is an integer
+    cycles: Option<f64>,
+    /// Last observed angle between the mean longitudes (in degrees)
+    last_angle: f64,
+    pub next_conjunction_tick: Option<u64>,
+}
+
+impl SynodicPair {
+    pub fn new(body_a: BodyID, body_b: BodyID) -> Self {
+        Self {
+            body_a,
+            body_b,
+            cycles: None,
+            last_angle: 0.,
+            next_conjunction_tick: None,
+        }
+    }
+
+    /// Records the angle between the mean longitudes at `tick`, given the orbital periods of the
+    /// bodies, and returns whether a synodic cycle was completed since the last observation
+    fn observe(&mut self, angle: f64, periods: (f64, f64), tick: u64) -> bool {
+        let (completed, cycles) = match self.cycles {
+            None => (false, angle.rem_euclid(360.) / 360.),
+            Some(cycles) => {
+                let delta = (angle - self.last_angle + 180.).rem_euclid(360.) - 180.;
+                let new = cycles + delta / 360.;
+                (new.floor() != cycles.floor(), new)
             }
+        };
+        self.cycles = Some(cycles);
+        self.last_angle = angle;
+
+        // Fraction of the current cycle left, in the direction the angle moves
+        let rate = 1. / periods.0 - 1. / periods.1;
+        let fraction = cycles.rem_euclid(1.);
+        let left = match rate {
+            r if r > 0. => 1. - fraction,
+            r if r < 0. && fraction > 0. => fraction,
+            r if r < 0. => 1.,
+            _ => {
+                self.next_conjunction_tick = None;
+                return completed;
+            }
+        };
+        let days_per_tick = GAMETIME_PER_SIMTICK * SIMTICKS_PER_TICK as f64;
+        let days = left * synodic_period(periods.0, periods.1);
+        self.next_conjunction_tick = Some(tick + (days / days_per_tick).ceil() as u64);
+        completed
+    }
+}
+
+/// Pairs of bodies whose conjunctions are predicted, see [ConjunctionCycleEvent]
+#[derive(Resource, Debug, Clone)]
+pub struct SynodicMonitor {
+    pub pairs: Vec<SynodicPair>,
+}
+
+impl Default for SynodicMonitor {
+    fn default() -> Self {
+        let id = |s: &str| BodyID::new(s).expect("the ids of the main bodies are valid");
+        Self {
+            pairs: vec![SynodicPair::new(id("terre"), id("mars"))],
         }
-        i += 1;
     }
 }
 
-#[derive(Resource)]
+impl SynodicMonitor {
+    pub fn watch(&mut self, body_a: BodyID, body_b: BodyID) {
+        self.pairs.push(SynodicPair::new(body_a, body_b));
+    }
+
+    /// Next conjunctions of the body, with the other body of each pair
+    pub fn conjunctions_of(&self, body: BodyID) -> impl Iterator<Item = (BodyID, u64)> + '_ {
+        self.pairs.iter().filter_map(move |pair| {
+            let other = match body {
+                id if id == pair.body_a => pair.body_b,
+                id if id == pair.body_b => pair.body_a,
+                _ => return None,
+            };
+            pair.next_conjunction_tick.map(|tick| (other, tick))
+        })
+    }
+}
+
+fn monitor_synodic_cycles(
+    mut monitor: ResMut<SynodicMonitor>,
+    mut events: EventWriter<ConjunctionCycleEvent>,
+    orbits: Query<&EllipticalOrbit>,
+    mapping: Res<BodiesMapping>,
+    time: Res<GameTime>,
+) {
+    for pair in monitor.pairs.iter_mut() {
+        let (Some(a), Some(b)) = (
+            mapping
+                .0
+                .get(&pair.body_a)
+                .and_then(|&e| orbits.get(e).ok()),
+            mapping
+                .0
+                .get(&pair.body_b)
+                .and_then(|&e| orbits.get(e).ok()),
+        ) else {
+            continue;
+        };
+        if a.revolution_period == 0. || b.revolution_period == 0. {
+            continue;
+        }
+        let angle = mean_longitude(a) - mean_longitude(b);
+        let periods = (a.revolution_period, b.revolution_period);
+        if pair.observe(angle, periods, time.tick()) {
+            events.send(ConjunctionCycleEvent {
+                body_a: pair.body_a,
+                body_b: pair.body_b,
+                next_conjunction_tick: pair.next_conjunction_tick.unwrap_or_default(),
+            });
+        }
+    }
+}
+
+/// Upper bound of the distance between the primary body and any other body (in kilometers)
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
 pub struct SystemSize(pub f64);
 
-pub fn insert_system_size(mut commands: Commands, body_positions: Query<&mut Position>) {
-    debug!("insert_system_size");
-    let system_size = body_positions
+impl Default for SystemSize {
+    fn default() -> Self {
+        Self(AU)
+    }
+}
+
+impl SystemSize {
+    /// Size of the system in astronomical units
+    pub fn au(&self) -> f64 {
+        self.0 / AU
+    }
+}
+
+/// Farthest distance a body can reach from its host (in kilometers)
+fn max_host_distance(data: &BodyData) -> f64 {
+    data.apoapsis
+        .max(data.semimajor_axis * (1. + data.eccentricity))
+}
+
+/// Computes the size of the system by summing the apoapsis distances along the chain of host
+/// bodies, so that the result doesn't depend on where the bodies currently are on their orbits
+pub fn update_system_size(
+    mut system_size: ResMut<SystemSize>,
+    bodies: Query<&BodyInfo>,
+    mapping: Res<BodiesMapping>,
+) {
+    debug!("update_system_size");
+    let max_distance = |data: &BodyData| {
+        let mut distance = 0.;
+        let mut current = Some(data);
+        // Bounded by the number of bodies in case the hierarchy contains a cycle
+        for _ in 0..=mapping.0.len() {
+            let Some(data) = current else {
+                break;
+            };
+            if data.host_body.is_some() {
+                distance += max_host_distance(data);
+            }
+            current = data
+                .host_body
+                .and_then(|id| mapping.0.get(&id))
+                .and_then(|&e| bodies.get(e).ok())
+                .map(|info| &info.0);
+        }
+        distance
+    };
+    match bodies
         .iter()
-        .map(|pos| pos.0.length())
+        .map(|BodyInfo(data)| max_distance(data))
         .max_by(|a, b| a.total_cmp(b))
-        .unwrap();
-    commands.insert_resource(SystemSize(system_size));
+    {
+        Some(size) if size > 0. => system_size.0 = size,
+        _ => {
+            warn!(
+                "could not compute the size of the system, using {} km instead",
+                SystemSize::default().0
+            );
+            *system_size = SystemSize::default();
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use bevy::app::App;
+    use bevy::{
+        app::{App, FixedMain},
+        ecs::{system::RunSystemOnce, world::World},
+        math::DVec3,
+        prelude::*,
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::{prelude::*, utils::algebra::circular_orbit_around_body};
+
+    use super::{
+        synodic_period, update_global, update_local, SimulationTier, SynodicPair, SystemSize,
+    };
+
+    #[test]
+    fn test_simulation_tiers() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Explorer));
+        app.update();
+        let world = app.world_mut();
+        let mapping = world.resource::<BodiesMapping>().0.clone();
+        let bodies = ["terre", "jupiter", "neptune"].map(|id| mapping[id]);
+        let tiers = bodies.map(|e| *world.get::<SimulationTier>(e).unwrap());
+        use SimulationTier::*;
+        assert_eq!(tiers, [Inner, Middle, Outer]);
+
+        let positions = |world: &World| bodies.map(|e| world.get::<Position>(e).unwrap().0);
+        let orbits =
+            |world: &World| bodies.map(|e| world.get::<EllipticalOrbit>(e).unwrap().local_pos);
+        while !app
+            .world()
+            .resource::<GameTime>()
+            .simtick
+            .is_multiple_of(100)
+        {
+            FixedMain::run_fixed_main(app.world_mut());
+        }
+        let mut updates = [0; 3];
+        for _ in 0..100 {
+            let (before, orbits_before) = (positions(app.world()), orbits(app.world()));
+            FixedMain::run_fixed_main(app.world_mut());
+            let world = app.world();
+            let time = world.resource::<GameTime>().time();
+            for (i, (&after, orbit_after)) in positions(world).iter().zip(orbits(world)).enumerate()
+            {
+                updates[i] += usize::from(orbits_before[i] != orbit_after);
+                // Between two updates, the bodies move along their velocity and stay close to
+                // where their orbit puts them
+                assert_ne!(before[i], after);
+                let exact = world
+                    .get::<EllipticalOrbit>(bodies[i])
+                    .unwrap()
+                    .state_at(time)
+                    .0;
+                assert!(after.distance(exact) < 1., "{i}: {}", after.distance(exact));
+            }
+        }
+        // A hundred simticks after a multiple of 100, the outer bodies being propagated at the
+        // last one only
+        assert_eq!(updates, [100, 10, 1]);
+    }
 
-    use crate::prelude::*;
+    fn ship_around_jupiter(tiers: bool) -> App {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer));
+        app.update();
+        let world = app.world_mut();
+        if !tiers {
+            let bodies: Vec<_> = world
+                .resource::<BodiesMapping>()
+                .0
+                .values()
+                .copied()
+                .collect();
+            for entity in bodies {
+                world.entity_mut(entity).insert(SimulationTier::Inner);
+            }
+        }
+        let jupiter = world.resource::<BodiesMapping>().0["jupiter"];
+        let (&mass, &pos, &speed) = world
+            .query::<(&Mass, &Position, &Velocity)>()
+            .get(world, jupiter)
+            .unwrap();
+        let (spawn_pos, spawn_speed) =
+            circular_orbit_around_body(1e5, mass.0, pos.0, speed.0, &mut StdRng::seed_from_u64(0));
+        world.send_event(ShipEvent::Create(ShipInfo {
+            id: id_from("s"),
+            spawn_pos,
+            spawn_speed,
+            spawn_orbit: None,
+        }));
+        app.update();
+        app.world_mut()
+            .resource_mut::<NextState<GameStage>>()
+            .set(GameStage::Action);
+        app.update();
+        app
+    }
+
+    fn ship_from_jupiter(app: &mut App) -> DVec3 {
+        let world = app.world_mut();
+        let jupiter = world.resource::<BodiesMapping>().0["jupiter"];
+        let jupiter_pos = world.get::<Position>(jupiter).unwrap().0;
+        let ship_pos = world
+            .query_filtered::<&Position, With<ShipInfo>>()
+            .single(world)
+            .0;
+        ship_pos - jupiter_pos
+    }
+
+    #[test]
+    fn test_low_orbit_around_middle_tier_body() {
+        let mut tiered = ship_around_jupiter(true);
+        let mut reference = ship_around_jupiter(false);
+        let jupiter = tiered.world().resource::<BodiesMapping>().0["jupiter"];
+        assert_eq!(
+            tiered.world().get::<SimulationTier>(jupiter),
+            Some(&SimulationTier::Middle)
+        );
+        // About 5 revolutions of the ship, ending on an update of Jupiter
+        let end = (tiered.world().resource::<GameTime>().simtick / 100 + 10) * 100;
+        for app in [&mut tiered, &mut reference] {
+            while app.world().resource::<GameTime>().simtick < end {
+                FixedMain::run_fixed_main(app.world_mut());
+            }
+        }
+        let (pos, reference_pos) = (
+            ship_from_jupiter(&mut tiered),
+            ship_from_jupiter(&mut reference),
+        );
+        assert!((pos.length() - 1e5).abs() < 1e3, "{}", pos.length());
+        assert!(
+            pos.distance(reference_pos) < 10.,
+            "{}",
+            pos.distance(reference_pos)
+        );
+    }
+
+    #[test]
+    fn test_circular_orbit() {
+        let data = BodyDataBuilder::new(id_from("planet"))
+            .orbiting(id_from("sun"))
+            .with_semimajor_axis(1e6)
+            .with_revolution_period(100.)
+            .build();
+        let orbit = EllipticalOrbit::from(&data);
+        let (start, start_speed) = orbit.state_at(0.);
+        assert!(start.distance(DVec3::new(1e6, 0., 0.)) < 1e-6);
+        let speed = 2. * std::f64::consts::PI * 1e6 / 100.;
+        assert!((start_speed.length() - speed).abs() < 1e-6);
+        // Half a revolution later, on the other side of the host
+        let (opposite, _) = orbit.state_at(50.);
+        assert!(opposite.distance(DVec3::new(-1e6, 0., 0.)) < 1e-3);
+    }
 
     #[test]
     fn test_update_local() {
@@ -201,6 +718,27 @@ mod tests {
         assert!((earth_speed / 24. - 107200.).abs() <= 20000.);
     }
 
+    #[test]
+    fn test_interpolated_position() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Explorer));
+        app.update();
+        let world = app.world_mut();
+        let mut query = world.query::<(&EllipticalOrbit, &BodyInfo)>();
+        let (orbit, _) = query
+            .iter(world)
+            .find(|(_, BodyInfo(data))| data.id == id_from("terre"))
+            .unwrap();
+        let time = GameTime { simtick: 1000 };
+        let before = orbit.state_at(time.time()).0;
+        let after = orbit.state_at(GameTime { simtick: 1001 }.time()).0;
+        let between = orbit.state_at(time.interpolated(0.5)).0;
+        let step = before.distance(after);
+        assert!(before.distance(between) < step);
+        assert!(after.distance(between) < step);
+        assert!((before.distance(between) + between.distance(after) - step).abs() < 1e-6 * step);
+    }
+
     #[test]
     fn test_update_global() {
         let mut app = App::new();
@@ -221,4 +759,91 @@ mod tests {
         assert!(min <= moon_length);
         assert!(moon_length <= max)
     }
+
+    #[test]
+    fn test_positions_from_time() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Explorer));
+        app.update();
+        for _ in 0..137 {
+            FixedMain::run_fixed_main(app.world_mut());
+        }
+        let world = app.world_mut();
+        let bodies: Vec<_> = world
+            .query_filtered::<Entity, With<EllipticalOrbit>>()
+            .iter(world)
+            .collect();
+        let positions = |world: &World| {
+            bodies
+                .iter()
+                .map(|&e| world.get::<Position>(e).unwrap().0)
+                .collect::<Vec<_>>()
+        };
+        let expected = positions(world);
+
+        // Computing the positions at once gives the same result as the updates at each step
+        for &e in &bodies {
+            world.get_mut::<Position>(e).unwrap().0 = DVec3::ZERO;
+        }
+        world.run_system_once(update_local);
+        world.run_system_once(update_global);
+        assert_eq!(positions(world), expected);
+    }
+
+    fn system_size(config: BodiesConfig) -> SystemSize {
+        let mut app = App::new();
+        app.add_plugins(
+            ClientPlugin::testing()
+                .in_mode(ClientMode::Explorer)
+                .with_bodies(config),
+        );
+        app.update();
+        app.update();
+        *app.world().resource::<SystemSize>()
+    }
+
+    #[test]
+    fn test_system_size() {
+        let size = system_size(BodiesConfig::SmallestBodyType(BodyType::Moon));
+        assert!(4537039826. < size.0);
+        assert!(30. < size.au());
+
+        let size = system_size(BodiesConfig::IDs(vec![id_from("soleil"), id_from("terre")]));
+        assert!((size.0 - 152097701.).abs() < 1e6);
+        assert!((size.au() - 1.).abs() < 0.05);
+
+        assert_eq!(
+            system_size(BodiesConfig::IDs(Vec::new())),
+            SystemSize::default()
+        );
+    }
+
+    #[test]
+    fn test_synodic_period() {
+        assert!((synodic_period(365.256, 686.98) - 779.9).abs() < 0.1);
+        assert_eq!(
+            synodic_period(686.98, 365.256),
+            synodic_period(365.256, 686.98)
+        );
+
+        // Two bodies starting 90° apart, observed every tick (of 0.01 days) for 3 synodic
+        // periods: the conjunctions happen at the predicted ticks
+        let (t1, t2) = (10., 15.);
+        let syn = synodic_period(t1, t2);
+        let mut pair = SynodicPair::new(id_from("a"), id_from("b"));
+        let mut predicted = None;
+        let mut conjunctions = Vec::new();
+        for tick in 0..(3. * syn * 100.) as u64 {
+            let days = tick as f64 / 100.;
+            let angle = 90. + 360. * days / t1 - 360. * days / t2;
+            if pair.observe(angle, (t1, t2), tick) {
+                conjunctions.push((tick, predicted));
+            }
+            predicted = pair.next_conjunction_tick;
+        }
+        assert_eq!(conjunctions.len(), 3);
+        for (tick, predicted) in conjunctions {
+            assert!(tick.abs_diff(predicted.unwrap()) <= 1);
+        }
+    }
 }
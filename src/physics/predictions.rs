@@ -1,9 +1,12 @@
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, ops::Range};
 
 use bevy::{ecs::system::QueryLens, math::DVec3, prelude::*, utils::HashMap};
 
 use crate::{
-    objects::{prelude::*, ships::trajectory::ManeuverNode},
+    objects::{
+        prelude::*,
+        ships::trajectory::{aerobrake_step, AerobrakeStep, ManeuverKind, ManeuverNode},
+    },
     physics::prelude::*,
     utils::algebra::orbital_to_global_matrix,
 };
@@ -83,6 +86,8 @@ impl PredictionStart {
         ));
         let mut acc = self.acc;
         let mut previous_acc;
+        // Body of the aerobraking pass the ship is waiting for
+        let mut aerobrake = None;
         for i in 1..number + 1 {
             let simtick = self.simtick + i as u64;
             let bodies_coords = get_bodies_coordinates(
@@ -98,10 +103,19 @@ impl PredictionStart {
             if simtick % SIMTICKS_PER_TICK == 0 {
                 if let Some(node) = nodes.get(&(simtick / SIMTICKS_PER_TICK)) {
                     // For now, the origin body must be simulated
-                    if let Some(node_origin) = mapping.get(&node.origin) {
-                        if let Some(&(origin_pos, origin_speed, _)) = map.get(node_origin) {
-                            speed += orbital_to_global_matrix(origin_pos, origin_speed, pos, speed)
-                                * node.thrust;
+                    match node.kind {
+                        ManeuverKind::Impulsive(thrust) => {
+                            if let Some(&(origin_pos, origin_speed, _)) =
+                                mapping.get(&node.origin).and_then(|e| map.get(e))
+                            {
+                                speed +=
+                                    orbital_to_global_matrix(origin_pos, origin_speed, pos, speed)
+                                        * thrust;
+                            }
+                        }
+                        // Waits for the periapsis, from this simtick on
+                        ManeuverKind::Aerobrake { body } => {
+                            aerobrake = mapping.get(&body).copied();
                         }
                     }
                 }
@@ -144,6 +158,25 @@ impl PredictionStart {
                 }
             }
 
+            if let Some(body) = aerobrake {
+                // The ship is still a simtick behind the bodies
+                match map.get(&body).map(|&(body_pos, body_speed, _)| {
+                    aerobrake_step(
+                        pos - body_pos + body_speed * dt,
+                        speed - body_speed,
+                        &bodies.get(body).unwrap().1 .0,
+                        dt,
+                    )
+                }) {
+                    Some(AerobrakeStep::Wait) => {}
+                    Some(AerobrakeStep::Brake(change)) => {
+                        speed += change;
+                        aerobrake = None;
+                    }
+                    Some(AerobrakeStep::Skip(_)) | None => aerobrake = None,
+                }
+            }
+
             pos += get_dx(speed, acc, dt);
             let ref_coords = reference.and_then(|r| map.get(&r).cloned()).unwrap_or((
                 DVec3::ZERO,
@@ -226,12 +259,196 @@ pub fn get_bodies_coordinates(
         .collect()
 }
 
+/// Number of samples used to bracket the local minima of the distance in [closest_approach]
+const CLOSEST_APPROACH_SAMPLES: u64 = 200;
+
+/// Inverse of the golden ratio
+const INV_PHI: f64 = 0.618_033_988_749_895;
+
+/// Finds the minimum of `dist` between `lo` and `hi` (included) with a golden-section search,
+/// assuming it is unimodal there
+fn golden_section(dist: &impl Fn(u64) -> f64, mut lo: u64, mut hi: u64, tol: u64) -> (u64, f64) {
+    let tol = tol.max(2);
+    while hi - lo > tol {
+        let step = ((hi - lo) as f64 * INV_PHI).round() as u64;
+        let (c, d) = (hi - step, lo + step);
+        if c >= d {
+            break;
+        }
+        if dist(c) < dist(d) {
+            hi = d;
+        } else {
+            lo = c;
+        }
+    }
+    [lo, (lo + hi) / 2, hi]
+        .into_iter()
+        .map(|t| (t, dist(t)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap()
+}
+
+/// Local minima of the distance between two position functions in the window, in order of time.
+///
+/// The window is sampled coarsely to bracket the minima, which are then refined up to `tol_ticks`
+fn local_minima(
+    a: impl Fn(u64) -> DVec3,
+    b: impl Fn(u64) -> DVec3,
+    window: Range<u64>,
+    tol_ticks: u64,
+) -> Vec<(u64, f64)> {
+    let dist = |t| (a(t) - b(t)).length();
+    if window.end <= window.start + 1 {
+        return vec![(window.start, dist(window.start))];
+    }
+    let last = window.end - 1;
+    let step = ((last - window.start) / CLOSEST_APPROACH_SAMPLES).max(1);
+    let mut samples: Vec<_> = (window.start..=last)
+        .step_by(step as usize)
+        .map(|t| (t, dist(t)))
+        .collect();
+    if samples.last().is_some_and(|(t, _)| *t != last) {
+        samples.push((last, dist(last)));
+    }
+    let mut minima = Vec::new();
+    for i in 0..samples.len() {
+        let previous = i.checked_sub(1).map(|j| samples[j]);
+        let next = samples.get(i + 1).copied();
+        let (t, d) = samples[i];
+        if previous.map_or(true, |p| d <= p.1) && next.map_or(true, |n| d < n.1) {
+            let lo = previous.map_or(t, |p| p.0);
+            let hi = next.map_or(t, |n| n.0);
+            minima.push(golden_section(&dist, lo, hi, tol_ticks));
+        }
+    }
+    minima
+}
+
+/// Finds the tick (within the window) at which the two objects whose positions are given
+/// by `a` and `b` are the closest, along with the distance at that tick.
+///
+/// All the local minima are refined, so the global minimum is returned even if the objects
+/// get close several times
+pub fn closest_approach(
+    a: impl Fn(u64) -> DVec3,
+    b: impl Fn(u64) -> DVec3,
+    window: Range<u64>,
+    tol_ticks: u64,
+) -> (u64, f64) {
+    local_minima(a, b, window, tol_ticks)
+        .into_iter()
+        .min_by(|x, y| x.1.total_cmp(&y.1))
+        .unwrap()
+}
+
+/// Finds the first local minimum of the distance between the two objects that is under `threshold`
+pub fn first_approach_below(
+    a: impl Fn(u64) -> DVec3,
+    b: impl Fn(u64) -> DVec3,
+    window: Range<u64>,
+    tol_ticks: u64,
+    threshold: f64,
+) -> Option<(u64, f64)> {
+    local_minima(a, b, window, tol_ticks)
+        .into_iter()
+        .find(|(_, d)| *d < threshold)
+}
+
+/// Shortens the window so that it ends before `horizon`, returning whether it was clamped
+pub fn clamp_window(window: Range<u64>, horizon: u64) -> (Range<u64>, bool) {
+    if window.end > horizon {
+        (window.start..horizon.max(window.start + 1), true)
+    } else {
+        (window, false)
+    }
+}
+
+/// Builds a position function from predictions, the first one being at simtick `start`.
+///
+/// Positions outside of the predictions are clamped to the first or last one
+pub fn prediction_positions(start: u64, positions: &[DVec3]) -> impl Fn(u64) -> DVec3 + '_ {
+    move |simtick| {
+        let i = (simtick.saturating_sub(start) as usize).min(positions.len().saturating_sub(1));
+        positions.get(i).copied().unwrap_or_default()
+    }
+}
+
+/// Computes the position and velocity of a body at a given simtick from its orbit and the ones
+/// of its hosts
+pub fn body_state_at(
+    body: Entity,
+    bodies: &Query<(&EllipticalOrbit, &BodyInfo)>,
+    mapping: &HashMap<BodyID, Entity>,
+    simtick: u64,
+) -> (DVec3, DVec3) {
+    let Ok((orbit, BodyInfo(data))) = bodies.get(body) else {
+        return (DVec3::ZERO, DVec3::ZERO);
+    };
+    let mut orbit = orbit.clone();
+    orbit.update_pos(simtick as f64 * GAMETIME_PER_SIMTICK);
+    let (host_pos, host_speed) = data
+        .host_body
+        .and_then(|id| mapping.get(&id))
+        .map_or((DVec3::ZERO, DVec3::ZERO), |&host| {
+            body_state_at(host, bodies, mapping, simtick)
+        });
+    (host_pos + orbit.local_pos, host_speed + orbit.local_speed)
+}
+
+/// Orbits of a body and of its hosts, to compute its positions away from the world with
+/// [chain_position]
+pub fn orbit_chain(
+    body: Entity,
+    bodies: &Query<(&EllipticalOrbit, &BodyInfo)>,
+    mapping: &HashMap<BodyID, Entity>,
+) -> Vec<EllipticalOrbit> {
+    let mut chain = Vec::new();
+    let mut next = Some(body);
+    while let Some((orbit, BodyInfo(data))) = next.and_then(|e| bodies.get(e).ok()) {
+        chain.push(orbit.clone());
+        next = data.host_body.and_then(|id| mapping.get(&id).copied());
+    }
+    chain
+}
+
+/// Position at a given simtick of the body whose [orbit_chain] is `chain`, like [body_state_at]
+pub fn chain_position(chain: &mut [EllipticalOrbit], simtick: u64) -> DVec3 {
+    chain
+        .iter_mut()
+        .map(|orbit| {
+            orbit.update_pos(simtick as f64 * GAMETIME_PER_SIMTICK);
+            orbit.local_pos
+        })
+        .sum()
+}
+
+/// Builds the position function of a body, in the referential used by predictions
+/// (see [PredictionStart::compute_predictions]) that starts at simtick `start`
+pub fn body_positions<'a>(
+    body: Entity,
+    reference: Option<Entity>,
+    start: u64,
+    bodies: &'a Query<(&EllipticalOrbit, &BodyInfo)>,
+    mapping: &'a HashMap<BodyID, Entity>,
+) -> Box<dyn Fn(u64) -> DVec3 + 'a> {
+    let initial_ref = reference.map_or(DVec3::ZERO, |r| body_state_at(r, bodies, mapping, start).0);
+    Box::new(move |simtick| {
+        let (pos, _) = body_state_at(body, bodies, mapping, simtick);
+        let ref_pos = reference.map_or(DVec3::ZERO, |r| {
+            body_state_at(r, bodies, mapping, simtick).0
+        });
+        pos - ref_pos + initial_ref
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use bevy::{ecs::system::SystemState, prelude::*};
 
     use crate::{
-        physics::leapfrog::get_acceleration, prelude::*, utils::algebra::circular_orbit_around_body,
+        physics::{leapfrog::get_acceleration, G},
+        prelude::*,
+        utils::algebra::circular_orbit_around_body,
     };
 
     use super::*;
@@ -243,13 +460,19 @@ mod tests {
         app.update();
         let world = app.world_mut();
         let mapping = &world.resource::<BodiesMapping>().0;
-        let earth = *mapping.get(&id_from("terre")).unwrap();
-        let sun = *mapping.get(&id_from("soleil")).unwrap();
+        let earth = *mapping.get("terre").unwrap();
+        let sun = *mapping.get("soleil").unwrap();
         let (&mass, &earth_pos, &earth_speed) = world
             .query::<(&Mass, &Position, &Velocity)>()
             .get(world, earth)
             .unwrap();
-        let (pos, speed) = circular_orbit_around_body(1e5, mass.0, earth_pos.0, earth_speed.0);
+        let (pos, speed) = circular_orbit_around_body(
+            1e5,
+            mass.0,
+            earth_pos.0,
+            earth_speed.0,
+            &mut rand::thread_rng(),
+        );
         let influencers = vec![sun, earth];
         let influence = Influenced {
             main_influencer: Some(earth),
@@ -286,4 +509,103 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_closest_approach_crossing_orbits() {
+        // Two circular orbits in orthogonal planes, crossing every half period with a radius
+        // difference of 100 km
+        let (radius, gap, period) = (1e4, 100., 1000.);
+        let angle = |t: u64| 2. * std::f64::consts::PI * t as f64 / period;
+        let a = |t| radius * DVec3::new(angle(t).cos(), angle(t).sin(), 0.);
+        let b = |t| (radius + gap) * DVec3::new(angle(t).cos(), 0., angle(t).sin());
+        let (simtick, distance) = closest_approach(a, b, 10..900, 1);
+        assert!(simtick.abs_diff(500) <= 1);
+        assert!((distance - gap).abs() < 1.);
+        // The first crossing is outside the window, the next one is the first under the threshold
+        let (simtick, _) = first_approach_below(a, b, 10..1200, 1, 200.).unwrap();
+        assert!(simtick.abs_diff(500) <= 1);
+        assert!(first_approach_below(a, b, 10..400, 1, 200.).is_none());
+        assert_eq!(clamp_window(10..1200, 800), (10..800, true));
+    }
+
+    #[test]
+    fn test_closest_approach_moon_transfer() {
+        let mut app = App::new();
+        app.add_plugins(
+            ClientPlugin::testing()
+                .with_bodies(BodiesConfig::SmallestBodyType(BodyType::Moon))
+                .in_mode(ClientMode::Singleplayer),
+        );
+        app.update();
+        let world = app.world_mut();
+        let mapping = &world.resource::<BodiesMapping>().0;
+        let (earth, moon, sun) = (mapping["terre"], mapping["lune"], mapping["soleil"]);
+        #[allow(clippy::type_complexity)]
+        let mut system_state: SystemState<(
+            Res<BodiesMapping>,
+            Query<(&EllipticalOrbit, &BodyInfo, &HillRadius)>,
+            Query<(&Position, &Mass)>,
+        )> = SystemState::new(world);
+        let (mapping, mut bodies, query) = system_state.get(world);
+        let earth_mass = query.get(earth).unwrap().1 .0;
+        let mut lens = bodies.transmute_lens::<(&EllipticalOrbit, &BodyInfo)>();
+        let orbits = lens.query();
+
+        // Hohmann transfer from a circular orbit to the position of the Moon at arrival
+        let mu = G * earth_mass;
+        let r0 = 5e4;
+        let moon_at = |simtick| {
+            let (moon_pos, moon_speed) = body_state_at(moon, &orbits, &mapping.0, simtick);
+            let (earth_pos, earth_speed) = body_state_at(earth, &orbits, &mapping.0, simtick);
+            (moon_pos - earth_pos, moon_speed - earth_speed)
+        };
+        let transfer_time = |r1: f64| {
+            let a = (r0 + r1) / 2.;
+            (std::f64::consts::PI * (a.powi(3) / mu).sqrt() / GAMETIME_PER_SIMTICK) as u64
+        };
+        // The distance of the Moon at arrival depends on the duration of the transfer
+        let r1 = moon_at(transfer_time(moon_at(0).0.length())).0.length();
+        let (transfer, a) = (transfer_time(r1), (r0 + r1) / 2.);
+        let (arrival_pos, arrival_speed) = moon_at(transfer);
+        let direction = arrival_pos.normalize();
+        let normal = arrival_pos.cross(arrival_speed).normalize();
+        let (earth_pos, earth_speed) = body_state_at(earth, &orbits, &mapping.0, 0);
+        let pos = earth_pos - r0 * direction;
+        let speed =
+            earth_speed + (mu * (2. / r0 - 1. / a)).sqrt() * normal.cross(-direction).normalize();
+
+        let influencers = vec![sun, earth];
+        let influence = Influenced {
+            main_influencer: Some(earth),
+            influencers: influencers.clone(),
+        };
+        let window = 0..transfer * 3 / 2;
+        let predictions: Vec<_> = PredictionStart {
+            pos,
+            speed,
+            simtick: 0,
+            acc: get_acceleration(pos, query.iter_many(&influencers).map(|(p, m)| (p.0, m.0))),
+        }
+        .compute_predictions(
+            window.end as usize,
+            &influence,
+            None,
+            &mut bodies.as_query_lens(),
+            &mapping.0,
+            &BTreeMap::new(),
+        )
+        .into_iter()
+        .map(|(p, _)| p)
+        .collect();
+        let mut lens = bodies.transmute_lens::<(&EllipticalOrbit, &BodyInfo)>();
+        let orbits = lens.query();
+        let (simtick, distance) = closest_approach(
+            prediction_positions(1, &predictions),
+            body_positions(moon, None, 0, &orbits, &mapping.0),
+            window,
+            1,
+        );
+        assert!(simtick.abs_diff(transfer) < transfer / 5);
+        assert!(distance < 6e4);
+    }
 }
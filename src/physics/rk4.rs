@@ -0,0 +1,56 @@
+use bevy::math::DVec3;
+
+// See https://en.wikipedia.org/wiki/Runge%E2%80%93Kutta_methods
+// This is not used by the simulation, it serves as a reference to validate the leapfrog integrator
+
+/// Computes one classic 4th-order Runge-Kutta step from the object's position and velocity,
+/// given a function returning the acceleration at a position.
+///
+/// Returns the new position and velocity
+pub fn rk4_step(
+    pos: DVec3,
+    vel: DVec3,
+    accel_fn: impl Fn(DVec3) -> DVec3,
+    dt: f64,
+) -> (DVec3, DVec3) {
+    let k1_x = vel;
+    let k1_v = accel_fn(pos);
+    let k2_x = vel + k1_v * dt / 2.;
+    let k2_v = accel_fn(pos + k1_x * dt / 2.);
+    let k3_x = vel + k2_v * dt / 2.;
+    let k3_v = accel_fn(pos + k2_x * dt / 2.);
+    let k4_x = vel + k3_v * dt;
+    let k4_v = accel_fn(pos + k3_x * dt);
+    (
+        pos + (k1_x + 2. * k2_x + 2. * k3_x + k4_x) * dt / 6.,
+        vel + (k1_v + 2. * k2_v + 2. * k3_v + k4_v) * dt / 6.,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use bevy::math::DVec3;
+
+    use crate::physics::{leapfrog::get_acceleration, G};
+
+    use super::rk4_step;
+
+    #[test]
+    fn test_rk4_circular_orbit() {
+        let mass = 5.972e24;
+        let radius = 1e4;
+        let speed = (G * mass / radius).sqrt();
+        let period = 2. * PI * radius / speed;
+        let steps = 10000;
+        let dt = period / steps as f64;
+        let (mut pos, mut vel) = (DVec3::new(radius, 0., 0.), DVec3::new(0., speed, 0.));
+        let accel = |p| get_acceleration(p, std::iter::once((DVec3::ZERO, mass)));
+        for _ in 0..steps {
+            (pos, vel) = rk4_step(pos, vel, accel, dt);
+        }
+        assert!((pos - DVec3::new(radius, 0., 0.)).length() < 1e-3);
+        assert!((vel - DVec3::new(0., speed, 0.)).length() < 1e-3);
+    }
+}
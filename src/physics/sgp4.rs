@@ -0,0 +1,763 @@
+//! SGP4 analytic propagation of Earth satellites from their two-line element sets (TLE).
+//!
+//! With [IntegrationMethod::SGP4], the ships with a [TLEData] are moved by the SGP4 model instead
+//! of the leapfrog integration, so that real satellites follow their published orbits. This is
+//! the near-Earth model of Vallado's revision of Spacetrack Report #3, with the WGS72 constants
+//! used to fit the published TLEs. The deep-space part (SDP4), for orbits longer than 225 minutes,
+//! is not implemented: such TLEs are refused, and their ships are integrated like the others. So
+//! are the ships whose orbit decayed, once SGP4 can't propagate them anymore.
+//!
+//! The server attaches TLEs to ships with its `tle` command, and selects the model with its
+//! `integration` command. The TLEs are placed in the game time by the [SimulationEpoch], the date
+//! of the simtick 0, set with the `epoch` command.
+//!
+//! SGP4 gives positions in the TEME frame, centered on the Earth and aligned with its equator.
+//! Its axes are turned to align its pole with the spin axis of the Earth of the game.
+
+use std::{f64::consts::TAU, fmt::Display};
+
+use arrayvec::ArrayString;
+use bevy::{
+    math::{DQuat, DVec3},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client::ClientMode,
+    game::InGame,
+    objects::prelude::{BodiesMapping, BodyInfo},
+    utils::algebra::spin_axis_direction,
+};
+
+use super::{leapfrog::IntegrationMethod, prelude::*, SECONDS_PER_DAY};
+
+/// Body around which the orbits described by TLEs are
+pub const TLE_HOST: &str = "terre";
+
+/// Julian date of the J2000 epoch
+pub const J2000: f64 = 2_451_545.;
+
+/// Length of a line of a TLE
+const TLE_LINE_LENGTH: usize = 69;
+
+// WGS72 constants, with which the TLEs are fitted
+const EARTH_RADIUS: f64 = 6378.135;
+const MU: f64 = 398_600.8;
+const J2: f64 = 0.001_082_616;
+const J3: f64 = -0.000_002_538_81;
+const J4: f64 = -0.000_001_655_97;
+const J3OJ2: f64 = J3 / J2;
+const X2O3: f64 = 2. / 3.;
+const MINUTES_PER_DAY: f64 = 1440.;
+
+/// Orbits with longer periods need the deep-space model (in minutes)
+const DEEP_SPACE_PERIOD: f64 = 225.;
+
+/// Square root of the gravitational parameter of the Earth, in Earth radii^1.5 per minute
+fn xke() -> f64 {
+    60. / (EARTH_RADIUS.powi(3) / MU).sqrt()
+}
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<SimulationEpoch>()
+        .configure_sets(
+            FixedUpdate,
+            SGP4System
+                .run_if(resource_equals(IntegrationMethod::SGP4))
+                .run_if(in_state(InGame).or_else(in_state(ClientMode::Server))),
+        )
+        .add_systems(
+            FixedUpdate,
+            (init_propagators, propagate_satellites)
+                .chain()
+                .in_set(SGP4System),
+        );
+}
+
+/// Computes the positions and velocities of the ships propagated by SGP4
+#[derive(SystemSet, Debug, PartialEq, Eq, Hash, Clone)]
+pub struct SGP4System;
+
+/// Julian date of the simtick 0, which places the epochs of the TLEs in the game time. It is
+/// J2000 unless the server sets it
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct SimulationEpoch(pub f64);
+
+impl SimulationEpoch {
+    /// Julian date at the given game time (in days)
+    pub fn date(&self, time: f64) -> f64 {
+        self.0 + time
+    }
+}
+
+impl Default for SimulationEpoch {
+    fn default() -> Self {
+        Self(J2000)
+    }
+}
+
+/// Two-line element set of a satellite, as published by NORAD
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TLEData {
+    pub line1: ArrayString<70>,
+    pub line2: ArrayString<70>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sgp4Error {
+    /// A line doesn't have the expected length or number
+    Line(u8),
+    /// The checksum (last digit) of a line doesn't match its content
+    Checksum(u8),
+    /// A field couldn't be read
+    Field(&'static str),
+    /// The orbit needs the deep-space model, whose period is given (in minutes)
+    DeepSpace(f64),
+    /// The elements don't describe an orbit anymore at the given time, e.g. because the
+    /// satellite decayed (in minutes since the epoch)
+    Decayed(f64),
+}
+
+impl Display for Sgp4Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Line(n) => write!(f, "line {n} is not a TLE line {n}"),
+            Self::Checksum(n) => write!(f, "wrong checksum on line {n}"),
+            Self::Field(name) => write!(f, "could not read the {name}"),
+            Self::DeepSpace(period) => write!(
+                f,
+                "orbit period of {period:.0} minutes needs the deep-space model (SDP4), which is not supported"
+            ),
+            Self::Decayed(t) => write!(f, "the orbit decayed {t:.0} minutes after the epoch"),
+        }
+    }
+}
+
+/// Sum of the digits of the line, counting minus signs as 1, modulo 10
+fn checksum(line: &str) -> u32 {
+    line.chars()
+        .map(|c| match c {
+            '-' => 1,
+            c => c.to_digit(10).unwrap_or(0),
+        })
+        .sum::<u32>()
+        % 10
+}
+
+/// Field in the columns `start..end` of a line (counted from 1 like in the TLE specification)
+fn field(line: &str, start: usize, end: usize, name: &'static str) -> Result<f64, Sgp4Error> {
+    line.get(start - 1..end)
+        .and_then(|s| s.trim().parse().ok())
+        .ok_or(Sgp4Error::Field(name))
+}
+
+/// Field with an implied leading decimal point, and optionally an exponent: " 12345-3" is
+/// 0.12345e-3
+fn implied_decimal(
+    line: &str,
+    start: usize,
+    end: usize,
+    name: &'static str,
+) -> Result<f64, Sgp4Error> {
+    let s = line.get(start - 1..end).ok_or(Sgp4Error::Field(name))?;
+    let (sign, digits) = match s.trim_start().strip_prefix('-') {
+        Some(rest) => (-1., rest),
+        None => (1., s.trim_start().trim_start_matches('+')),
+    };
+    let (mantissa, exponent) = match digits.rfind(['-', '+']) {
+        Some(i) if i > 0 => (&digits[..i], &digits[i..]),
+        _ => (digits, "0"),
+    };
+    let mantissa: f64 = format!("0.{}", mantissa.trim())
+        .parse()
+        .map_err(|_| Sgp4Error::Field(name))?;
+    let exponent: i32 = exponent.parse().map_err(|_| Sgp4Error::Field(name))?;
+    Ok(sign * mantissa * 10f64.powi(exponent))
+}
+
+/// Julian date of the given fractional day of the year (January 1st at 0h being day 1)
+pub fn julian_date(year: i32, day_of_year: f64) -> f64 {
+    // Julian date of January 1st at 0h, valid from 1901 to 2099
+    let january_first = 367. * year as f64 - ((7 * year) / 4) as f64 + 31. + 1_721_013.5;
+    january_first + day_of_year - 1.
+}
+
+/// Mean elements of a TLE, in radians and radians per minute
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeanElements {
+    /// Julian date of the epoch
+    pub epoch: f64,
+    pub bstar: f64,
+    pub inclination: f64,
+    pub right_ascension: f64,
+    pub eccentricity: f64,
+    pub argument_of_perigee: f64,
+    pub mean_anomaly: f64,
+    pub mean_motion: f64,
+}
+
+impl TLEData {
+    pub fn new(line1: &str, line2: &str) -> Result<Self, Sgp4Error> {
+        let line = |s: &str, n| ArrayString::from(s.trim_end()).map_err(|_| Sgp4Error::Line(n));
+        Ok(Self {
+            line1: line(line1, 1)?,
+            line2: line(line2, 2)?,
+        })
+    }
+
+    /// Reads a TLE in the two line format, or in the three line format whose first line is the
+    /// name of the satellite
+    pub fn from_text(text: &str) -> Result<Self, Sgp4Error> {
+        let lines: Vec<_> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+        match lines[..] {
+            [line1, line2] | [_, line1, line2] => Self::new(line1, line2),
+            _ => Err(Sgp4Error::Line(1)),
+        }
+    }
+
+    pub fn elements(&self) -> Result<MeanElements, Sgp4Error> {
+        for (n, line) in [(1, &self.line1), (2, &self.line2)] {
+            if line.len() != TLE_LINE_LENGTH || !line.starts_with(&format!("{n} ")) {
+                return Err(Sgp4Error::Line(n));
+            }
+            if line[68..].parse() != Ok(checksum(&line[..68])) {
+                return Err(Sgp4Error::Checksum(n));
+            }
+        }
+        let (l1, l2) = (self.line1.as_str(), self.line2.as_str());
+        let two_digit_year = field(l1, 19, 20, "epoch year")? as i32;
+        let year = if two_digit_year < 57 {
+            2000 + two_digit_year
+        } else {
+            1900 + two_digit_year
+        };
+        let angle = |start, end, name| field(l2, start, end, name).map(f64::to_radians);
+        Ok(MeanElements {
+            epoch: julian_date(year, field(l1, 21, 32, "epoch day")?),
+            bstar: implied_decimal(l1, 54, 61, "drag term")?,
+            inclination: angle(9, 16, "inclination")?,
+            right_ascension: angle(18, 25, "right ascension of the ascending node")?,
+            eccentricity: implied_decimal(l2, 27, 33, "eccentricity")?,
+            argument_of_perigee: angle(35, 42, "argument of perigee")?,
+            mean_anomaly: angle(44, 51, "mean anomaly")?,
+            mean_motion: field(l2, 53, 63, "mean motion")? * TAU / MINUTES_PER_DAY,
+        })
+    }
+}
+
+/// The SGP4 model initialized from a TLE, which gives the state of the satellite at any time
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct Sgp4 {
+    elements: MeanElements,
+    /// Mean motion, with the Kozai mean motion of the TLE converted to the Brouwer one
+    mean_motion: f64,
+    simple: bool,
+    aycof: f64,
+    con41: f64,
+    cc1: f64,
+    cc4: f64,
+    cc5: f64,
+    d2: f64,
+    d3: f64,
+    d4: f64,
+    delmo: f64,
+    eta: f64,
+    argpdot: f64,
+    omgcof: f64,
+    sinmao: f64,
+    t2cof: f64,
+    t3cof: f64,
+    t4cof: f64,
+    t5cof: f64,
+    x1mth2: f64,
+    x7thm1: f64,
+    mdot: f64,
+    nodedot: f64,
+    xlcof: f64,
+    xmcof: f64,
+    nodecf: f64,
+}
+
+impl Sgp4 {
+    pub fn new(elements: MeanElements) -> Result<Self, Sgp4Error> {
+        let MeanElements {
+            bstar,
+            inclination,
+            eccentricity: ecco,
+            argument_of_perigee: argpo,
+            mean_anomaly: mo,
+            mean_motion: no_kozai,
+            ..
+        } = elements;
+        let xke = xke();
+
+        // Brouwer mean motion and semi-major axis
+        let omeosq = 1. - ecco * ecco;
+        let rteosq = omeosq.sqrt();
+        let (sinio, cosio) = inclination.sin_cos();
+        let cosio2 = cosio * cosio;
+        let ak = (xke / no_kozai).powf(X2O3);
+        let d1 = 0.75 * J2 * (3. * cosio2 - 1.) / (rteosq * omeosq);
+        let del = d1 / (ak * ak);
+        let adel = ak * (1. - del * del - del * (1. / 3. + 134. * del * del / 81.));
+        let del = d1 / (adel * adel);
+        let no = no_kozai / (1. + del);
+        let period = TAU / no;
+        if period >= DEEP_SPACE_PERIOD {
+            return Err(Sgp4Error::DeepSpace(period));
+        }
+        let ao = (xke / no).powf(X2O3);
+        let po = ao * omeosq;
+        let con42 = 1. - 5. * cosio2;
+        let con41 = -con42 - 2. * cosio2;
+        let posq = po * po;
+        let rp = ao * (1. - ecco);
+
+        // Atmospheric density parameters, adjusted for low perigees
+        let ss = 78. / EARTH_RADIUS + 1.;
+        let mut sfour = ss;
+        let mut qzms24 = ((120. - 78.) / EARTH_RADIUS).powi(4);
+        let perigee = (rp - 1.) * EARTH_RADIUS;
+        if perigee < 156. {
+            let s = if perigee < 98. { 20. } else { perigee - 78. };
+            qzms24 = ((120. - s) / EARTH_RADIUS).powi(4);
+            sfour = s / EARTH_RADIUS + 1.;
+        }
+        let simple = rp < 220. / EARTH_RADIUS + 1.;
+
+        let pinvsq = 1. / posq;
+        let tsi = 1. / (ao - sfour);
+        let eta = ao * ecco * tsi;
+        let etasq = eta * eta;
+        let eeta = ecco * eta;
+        let psisq = (1. - etasq).abs();
+        let coef = qzms24 * tsi.powi(4);
+        let coef1 = coef / psisq.powf(3.5);
+        let cc2 = coef1
+            * no
+            * (ao * (1. + 1.5 * etasq + eeta * (4. + etasq))
+                + 0.375 * J2 * tsi / psisq * con41 * (8. + 3. * etasq * (8. + etasq)));
+        let cc1 = bstar * cc2;
+        let cc3 = if ecco > 1e-4 {
+            -2. * coef * tsi * J3OJ2 * no * sinio / ecco
+        } else {
+            0.
+        };
+        let x1mth2 = 1. - cosio2;
+        let cc4 = 2.
+            * no
+            * coef1
+            * ao
+            * omeosq
+            * (eta * (2. + 0.5 * etasq) + ecco * (0.5 + 2. * etasq)
+                - J2 * tsi / (ao * psisq)
+                    * (-3. * con41 * (1. - 2. * eeta + etasq * (1.5 - 0.5 * eeta))
+                        + 0.75 * x1mth2 * (2. * etasq - eeta * (1. + etasq)) * (2. * argpo).cos()));
+        let cc5 = 2. * coef1 * ao * omeosq * (1. + 2.75 * (etasq + eeta) + eeta * etasq);
+
+        // Secular rates of the mean anomaly, argument of perigee and node
+        let cosio4 = cosio2 * cosio2;
+        let temp1 = 1.5 * J2 * pinvsq * no;
+        let temp2 = 0.5 * temp1 * J2 * pinvsq;
+        let temp3 = -0.46875 * J4 * pinvsq * pinvsq * no;
+        let mdot = no
+            + 0.5 * temp1 * rteosq * con41
+            + 0.0625 * temp2 * rteosq * (13. - 78. * cosio2 + 137. * cosio4);
+        let argpdot = -0.5 * temp1 * con42
+            + 0.0625 * temp2 * (7. - 114. * cosio2 + 395. * cosio4)
+            + temp3 * (3. - 36. * cosio2 + 49. * cosio4);
+        let xhdot1 = -temp1 * cosio;
+        let nodedot =
+            xhdot1 + (0.5 * temp2 * (4. - 19. * cosio2) + 2. * temp3 * (3. - 7. * cosio2)) * cosio;
+        let omgcof = bstar * cc3 * argpo.cos();
+        let xmcof = if ecco > 1e-4 {
+            -X2O3 * coef * bstar / eeta
+        } else {
+            0.
+        };
+        let nodecf = 3.5 * omeosq * xhdot1 * cc1;
+        let t2cof = 1.5 * cc1;
+        // Avoids a division by zero for an inclination of 180°
+        let xlcof = -0.25 * J3OJ2 * sinio * (3. + 5. * cosio) / (1. + cosio).abs().max(1.5e-12);
+        let aycof = -0.5 * J3OJ2 * sinio;
+        let delmo = (1. + eta * mo.cos()).powi(3);
+        let x7thm1 = 7. * cosio2 - 1.;
+
+        let (mut d2, mut d3, mut d4, mut t3cof, mut t4cof, mut t5cof) = (0., 0., 0., 0., 0., 0.);
+        if !simple {
+            let cc1sq = cc1 * cc1;
+            d2 = 4. * ao * tsi * cc1sq;
+            let temp = d2 * tsi * cc1 / 3.;
+            d3 = (17. * ao + sfour) * temp;
+            d4 = 0.5 * temp * ao * tsi * (221. * ao + 31. * sfour) * cc1;
+            t3cof = d2 + 2. * cc1sq;
+            t4cof = 0.25 * (3. * d3 + cc1 * (12. * d2 + 10. * cc1sq));
+            t5cof =
+                0.2 * (3. * d4 + 12. * cc1 * d3 + 6. * d2 * d2 + 15. * cc1sq * (2. * d2 + cc1sq));
+        }
+
+        Ok(Self {
+            elements,
+            mean_motion: no,
+            simple,
+            aycof,
+            con41,
+            cc1,
+            cc4,
+            cc5,
+            d2,
+            d3,
+            d4,
+            delmo,
+            eta,
+            argpdot,
+            omgcof,
+            sinmao: mo.sin(),
+            t2cof,
+            t3cof,
+            t4cof,
+            t5cof,
+            x1mth2,
+            x7thm1,
+            mdot,
+            nodedot,
+            xlcof,
+            xmcof,
+            nodecf,
+        })
+    }
+
+    pub fn epoch(&self) -> f64 {
+        self.elements.epoch
+    }
+
+    /// Position (in km) and velocity (in km/s) in the TEME frame, `t` minutes after the epoch
+    pub fn propagate(&self, t: f64) -> Result<(DVec3, DVec3), Sgp4Error> {
+        let MeanElements {
+            bstar,
+            inclination,
+            right_ascension: nodeo,
+            eccentricity: ecco,
+            argument_of_perigee: argpo,
+            mean_anomaly: mo,
+            ..
+        } = self.elements;
+        let xke = xke();
+
+        // Secular gravity and atmospheric drag
+        let xmdf = mo + self.mdot * t;
+        let argpdf = argpo + self.argpdot * t;
+        let nodedf = nodeo + self.nodedot * t;
+        let t2 = t * t;
+        let nodem = nodedf + self.nodecf * t2;
+        let mut argpm = argpdf;
+        let mut mm = xmdf;
+        let mut tempa = 1. - self.cc1 * t;
+        let mut tempe = bstar * self.cc4 * t;
+        let mut templ = self.t2cof * t2;
+        if !self.simple {
+            let delomg = self.omgcof * t;
+            let delm = self.xmcof * ((1. + self.eta * xmdf.cos()).powi(3) - self.delmo);
+            mm = xmdf + delomg + delm;
+            argpm = argpdf - delomg - delm;
+            let t3 = t2 * t;
+            let t4 = t3 * t;
+            tempa -= self.d2 * t2 + self.d3 * t3 + self.d4 * t4;
+            tempe += bstar * self.cc5 * (mm.sin() - self.sinmao);
+            templ += self.t3cof * t3 + t4 * (self.t4cof + t * self.t5cof);
+        }
+        let am = (xke / self.mean_motion).powf(X2O3) * tempa * tempa;
+        let nm = xke / am.powf(1.5);
+        let mut em = ecco - tempe;
+        if !(-0.001..1.).contains(&em) || am < 0.95 {
+            return Err(Sgp4Error::Decayed(t));
+        }
+        em = em.max(1e-6);
+        mm += self.mean_motion * templ;
+        let xlm = mm + argpm + nodem;
+        let nodem = nodem.rem_euclid(TAU);
+        let argpm = argpm.rem_euclid(TAU);
+        let xlm = xlm.rem_euclid(TAU);
+        let mm = (xlm - argpm - nodem).rem_euclid(TAU);
+
+        // Long period periodics
+        let (sinip, cosip) = inclination.sin_cos();
+        let axnl = em * argpm.cos();
+        let temp = 1. / (am * (1. - em * em));
+        let aynl = em * argpm.sin() + temp * self.aycof;
+        let xl = mm + argpm + nodem + temp * self.xlcof * axnl;
+
+        // Kepler's equation
+        let u = (xl - nodem).rem_euclid(TAU);
+        let mut eo1 = u;
+        let (mut sineo1, mut coseo1) = eo1.sin_cos();
+        for _ in 0..10 {
+            (sineo1, coseo1) = eo1.sin_cos();
+            let step =
+                (u - aynl * coseo1 + axnl * sineo1 - eo1) / (1. - coseo1 * axnl - sineo1 * aynl);
+            let step = step.clamp(-0.95, 0.95);
+            eo1 += step;
+            if step.abs() < 1e-12 {
+                break;
+            }
+        }
+
+        // Short period periodics
+        let ecose = axnl * coseo1 + aynl * sineo1;
+        let esine = axnl * sineo1 - aynl * coseo1;
+        let el2 = axnl * axnl + aynl * aynl;
+        let pl = am * (1. - el2);
+        if pl < 0. {
+            return Err(Sgp4Error::Decayed(t));
+        }
+        let rl = am * (1. - ecose);
+        let rdotl = am.sqrt() * esine / rl;
+        let rvdotl = pl.sqrt() / rl;
+        let betal = (1. - el2).sqrt();
+        let temp = esine / (1. + betal);
+        let sinu = am / rl * (sineo1 - aynl - axnl * temp);
+        let cosu = am / rl * (coseo1 - axnl + aynl * temp);
+        let su = sinu.atan2(cosu);
+        let sin2u = 2. * cosu * sinu;
+        let cos2u = 1. - 2. * sinu * sinu;
+        let temp = 1. / pl;
+        let temp1 = 0.5 * J2 * temp;
+        let temp2 = temp1 * temp;
+        let mrt = rl * (1. - 1.5 * temp2 * betal * self.con41) + 0.5 * temp1 * self.x1mth2 * cos2u;
+        let su = su - 0.25 * temp2 * self.x7thm1 * sin2u;
+        let xnode = nodem + 1.5 * temp2 * cosip * sin2u;
+        let xinc = inclination + 1.5 * temp2 * cosip * sinip * cos2u;
+        let mvt = rdotl - nm * temp1 * self.x1mth2 * sin2u / xke;
+        let rvdot = rvdotl + nm * temp1 * (self.x1mth2 * cos2u + 1.5 * self.con41) / xke;
+        if mrt < 1. {
+            return Err(Sgp4Error::Decayed(t));
+        }
+
+        // Orientation vectors
+        let (sinsu, cossu) = su.sin_cos();
+        let (snod, cnod) = xnode.sin_cos();
+        let (sini, cosi) = xinc.sin_cos();
+        let xmx = -snod * cosi;
+        let xmy = cnod * cosi;
+        let u = DVec3::new(
+            xmx * sinsu + cnod * cossu,
+            xmy * sinsu + snod * cossu,
+            sini * sinsu,
+        );
+        let v = DVec3::new(
+            xmx * cossu - cnod * sinsu,
+            xmy * cossu - snod * sinsu,
+            sini * cossu,
+        );
+        let km_per_s = EARTH_RADIUS * xke / 60.;
+        Ok((mrt * u * EARTH_RADIUS, (mvt * u + rvdot * v) * km_per_s))
+    }
+}
+
+/// Rotation from the TEME frame to the frame of the game, which turns the pole of the TEME frame
+/// to the spin axis of the host
+fn teme_rotation(host: &BodyInfo, orbit: &EllipticalOrbit) -> DQuat {
+    let axis = spin_axis_direction(
+        host.0.axial_tilt.to_radians(),
+        orbit.long_asc_node.to_radians(),
+        orbit.inclination.to_radians(),
+    );
+    DQuat::from_rotation_arc(DVec3::Z, axis)
+}
+
+/// Initializes the model of the ships whose TLE was added or changed. Ships with an invalid TLE
+/// lose it, and are integrated like the others
+fn init_propagators(mut commands: Commands, ships: Query<(Entity, &TLEData), Changed<TLEData>>) {
+    for (entity, tle) in ships.iter() {
+        match tle.elements().and_then(Sgp4::new) {
+            Ok(model) => {
+                commands.entity(entity).insert(model);
+            }
+            Err(e) => {
+                warn!("invalid TLE {tle:?}: {e}");
+                commands.entity(entity).remove::<(TLEData, Sgp4)>();
+            }
+        }
+    }
+}
+
+fn propagate_satellites(
+    mut commands: Commands,
+    mut ships: Query<(Entity, &Sgp4, &mut Position, &mut Velocity), With<TLEData>>,
+    bodies: Query<(&Position, &Velocity, &BodyInfo, &EllipticalOrbit), Without<TLEData>>,
+    mapping: Res<BodiesMapping>,
+    time: Res<GameTime>,
+    epoch: Res<SimulationEpoch>,
+) {
+    let Some((host_pos, host_velocity, info, orbit)) =
+        mapping.0.get(TLE_HOST).and_then(|e| bodies.get(*e).ok())
+    else {
+        return;
+    };
+    let rotation = teme_rotation(info, orbit);
+    let date = epoch.date(time.time());
+    for (entity, model, mut pos, mut velocity) in ships.iter_mut() {
+        let minutes = (date - model.epoch()) * MINUTES_PER_DAY;
+        match model.propagate(minutes) {
+            Ok((r, v)) => {
+                pos.0 = host_pos.0 + rotation * r;
+                velocity.0 = host_velocity.0 + rotation * v * SECONDS_PER_DAY;
+            }
+            Err(e) => {
+                warn!("could not propagate a TLE, integrating the ship instead: {e}");
+                commands.entity(entity).remove::<(TLEData, Sgp4)>();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{app::FixedMain, math::DVec3, prelude::*};
+
+    use crate::{
+        physics::{
+            leapfrog::IntegrationMethod,
+            sgp4::{julian_date, SimulationEpoch, TLEData, TLE_HOST},
+        },
+        prelude::*,
+    };
+
+    use super::{Sgp4, Sgp4Error};
+
+    /// Vanguard 1, a test case of Vallado's "Revisiting Spacetrack Report #3"
+    fn vanguard() -> TLEData {
+        TLEData::new(
+            "1 00005U 58002B   00179.78495062  .00000023  00000-0  28098-4 0  4753",
+            "2 00005  34.2682 348.7242 1859667 331.7664  19.3264 10.82419157413667",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_sgp4() {
+        let elements = vanguard().elements().unwrap();
+        assert!((elements.eccentricity - 0.1859667).abs() < 1e-12);
+        assert!((elements.bstar - 0.28098e-4).abs() < 1e-12);
+        assert!((elements.epoch - 2_451_723.28495062).abs() < 1e-8);
+
+        // Reference states from the verification results of Vallado
+        let model = Sgp4::new(elements).unwrap();
+        for (t, pos, vel) in [
+            (
+                0.,
+                DVec3::new(7022.46529266, -1400.08296755, 0.03995155),
+                DVec3::new(1.893841015, 6.405893759, 4.534807250),
+            ),
+            (
+                360.,
+                DVec3::new(-7154.03120202, -3783.17682504, -3536.19412294),
+                DVec3::new(4.741887409, -4.151817765, -2.093935425),
+            ),
+        ] {
+            let (p, v) = model.propagate(t).unwrap();
+            assert!(p.distance(pos) < 1e-3, "{p} instead of {pos} at {t}");
+            assert!(v.distance(vel) < 1e-6, "{v} instead of {vel} at {t}");
+        }
+    }
+
+    #[test]
+    fn test_tle_errors() {
+        let tle = vanguard();
+        let mut wrong = tle;
+        wrong.line2 = tle
+            .line2
+            .replace("34.2682", "34.2683")
+            .as_str()
+            .try_into()
+            .unwrap();
+        assert_eq!(wrong.elements(), Err(Sgp4Error::Checksum(2)));
+        let swapped = TLEData::new(&tle.line2, &tle.line1).unwrap();
+        assert_eq!(swapped.elements(), Err(Sgp4Error::Line(1)));
+        // The three line format starts with the name of the satellite
+        let named = format!("VANGUARD 1\n{}\n{}\n", tle.line1, tle.line2);
+        assert_eq!(TLEData::from_text(&named), Ok(tle));
+        assert_eq!(TLEData::from_text(&tle.line1), Err(Sgp4Error::Line(1)));
+        // A geostationary satellite needs the deep-space model
+        let geo = TLEData::new(
+            "1 28884U 05041A   20003.35307101 -.00000281  00000-0  00000-0 0  9997",
+            "2 28884   0.0164 261.1722 0002431 200.4004 162.3458  1.00272469 52685",
+        )
+        .unwrap();
+        assert!(matches!(
+            geo.elements().and_then(Sgp4::new),
+            Err(Sgp4Error::DeepSpace(_))
+        ));
+        assert_eq!(julian_date(2000, 1.5), 2_451_545.);
+    }
+
+    #[test]
+    fn test_sgp4_ships() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer));
+        app.insert_resource(IntegrationMethod::SGP4);
+        app.update();
+        let tle = vanguard();
+        // The game starts at the epoch of the TLE
+        let epoch = tle.elements().unwrap().epoch;
+        app.insert_resource(SimulationEpoch(epoch));
+        let world = app.world_mut();
+        for id in ["tle", "free"] {
+            world.send_event(ShipEvent::Create(ShipInfo {
+                id: id_from(id),
+                spawn_pos: DVec3::new(1e9, 0., 0.),
+                spawn_speed: DVec3::ZERO,
+                spawn_orbit: None,
+            }));
+        }
+        app.update();
+        let world = app.world_mut();
+        let ship = |world: &World, id| world.resource::<ShipsMapping>().0[id];
+        let tle_ship = ship(world, "tle");
+        world.entity_mut(tle_ship).insert(tle);
+        world
+            .resource_mut::<NextState<GameStage>>()
+            .set(GameStage::Action);
+        app.update();
+        for _ in 0..100 {
+            FixedMain::run_fixed_main(app.world_mut());
+        }
+
+        let world = app.world_mut();
+        let earth = world.resource::<BodiesMapping>().0[TLE_HOST];
+        let earth_pos = world.get::<Position>(earth).unwrap().0;
+        let time = world.resource::<GameTime>().time();
+        let (expected, _) = Sgp4::new(tle.elements().unwrap())
+            .unwrap()
+            .propagate(time * 1440.)
+            .unwrap();
+        let pos = world.get::<Position>(tle_ship).unwrap().0;
+        // The rotation to the frame of the game keeps the distances
+        assert!(((pos - earth_pos).length() - expected.length()).abs() < 1e-3);
+        // The other ship is still integrated, and falls towards the Sun
+        let free = world.get::<Position>(ship(world, "free")).unwrap().0;
+        assert!(free.x < 1e9);
+
+        // Once its orbit decayed, the satellite is integrated instead
+        let mut elements = tle.elements().unwrap();
+        elements.bstar = 0.5;
+        let model = Sgp4::new(elements).unwrap();
+        let year = 365.;
+        assert!(matches!(
+            model.propagate(year * 1440.),
+            Err(Sgp4Error::Decayed(_))
+        ));
+        world.entity_mut(tle_ship).insert(model);
+        world.insert_resource(SimulationEpoch(epoch + year - time));
+        FixedMain::run_fixed_main(app.world_mut());
+        let world = app.world_mut();
+        assert!(world.get::<TLEData>(tle_ship).is_none());
+        assert!(world.get::<Sgp4>(tle_ship).is_none());
+        let pos = world.get::<Position>(tle_ship).unwrap().0;
+        for _ in 0..10 {
+            FixedMain::run_fixed_main(app.world_mut());
+        }
+        assert_ne!(app.world().get::<Position>(tle_ship).unwrap().0, pos);
+    }
+}
@@ -0,0 +1,441 @@
+//! Index of the positions of the ships, to find the ships around a point without going through
+//! all of them.
+//!
+//! Positions span many orders of magnitude: ships orbit a few thousand km from the center of a
+//! planet which is itself 1e9 km from the origin. A uniform grid sized from the [SystemSize]
+//! would put all the ships around a planet in a single cell, and a grid sized for them would
+//! need billions of cells. The index is an octree instead: the root is a cube centered on the
+//! origin containing the system and all the ships, and a cell is split in eight as long as it
+//! holds more than [LEAF_CAPACITY] ships, down to [MIN_CELL_KM]. Cells are only as small as the
+//! ships in them require, so the depth of the tree depends on how close the ships are to each
+//! other rather than on how far they are from the origin: about log8(n) levels for n ships
+//! spread evenly, plus log2 of the size of the system over the size of a cluster.
+//!
+//! The tree is rebuilt at each fixed update, in O(n log n)
+
+use std::{cmp::Ordering, collections::BinaryHeap, ops::Range};
+
+use bevy::{
+    math::{DVec2, DVec3},
+    prelude::*,
+};
+
+use crate::objects::ships::ShipInfo;
+
+use super::{orbit::SystemSize, PhysicsUpdate, Position};
+
+/// Number of ships above which a cell is split
+pub const LEAF_CAPACITY: usize = 8;
+
+/// Size of the smallest cells (in km). Ships closer than this to each other stay in the same cell
+pub const MIN_CELL_KM: f64 = 1.;
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<SpatialIndex>().add_systems(
+        FixedUpdate,
+        rebuild_ship_index
+            .in_set(SpatialUpdate)
+            .after(PhysicsUpdate),
+    );
+}
+
+/// Rebuilding of the [SpatialIndex], after the ships moved
+#[derive(SystemSet, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct SpatialUpdate;
+
+#[derive(Debug, Clone)]
+struct Cell {
+    center: DVec3,
+    half_size: f64,
+    /// Indices of the points in the cell, which are contiguous
+    points: Range<usize>,
+    /// Indices of the non-empty children, empty for a leaf
+    children: Range<usize>,
+}
+
+impl Cell {
+    /// Distance from the point to the nearest point of the cell
+    fn distance_to(&self, point: DVec3) -> f64 {
+        ((point - self.center).abs() - self.half_size)
+            .max(DVec3::ZERO)
+            .length()
+    }
+
+    /// Distance from the point of the ecliptic plane to the nearest point of the cell projected on
+    /// it
+    fn distance_in_plane(&self, point: DVec2) -> f64 {
+        ((point - self.center.xy()).abs() - self.half_size)
+            .max(DVec2::ZERO)
+            .length()
+    }
+
+    /// Distance from the point to the farthest point of the cell
+    fn farthest_from(&self, point: DVec3) -> f64 {
+        ((point - self.center).abs() + self.half_size).length()
+    }
+
+    /// Index of the child containing the point, with a bit per axis
+    fn octant(&self, point: DVec3) -> usize {
+        usize::from(point.x >= self.center.x)
+            | usize::from(point.y >= self.center.y) << 1
+            | usize::from(point.z >= self.center.z) << 2
+    }
+
+    fn child_center(&self, octant: usize) -> DVec3 {
+        let sign = |bit: usize| if octant & bit != 0 { 1. } else { -1. };
+        self.center + DVec3::new(sign(1), sign(2), sign(4)) * self.half_size / 2.
+    }
+}
+
+/// Octree of the positions of the ships at the last fixed update, see the [module](self) docs
+#[derive(Resource, Debug, Clone, Default)]
+pub struct SpatialIndex {
+    cells: Vec<Cell>,
+    points: Vec<(Entity, DVec3)>,
+}
+
+impl SpatialIndex {
+    /// Index of the points, with a root cell at least as large as `min_half_size` (in km)
+    pub fn build(points: impl IntoIterator<Item = (Entity, DVec3)>, min_half_size: f64) -> Self {
+        let points: Vec<_> = points.into_iter().collect();
+        // The points on the upper faces of the root belong to it
+        let half_size = points
+            .iter()
+            .map(|(_, p)| p.abs().max_element())
+            .fold(min_half_size.max(MIN_CELL_KM), f64::max)
+            * 2.;
+        let mut index = Self {
+            cells: vec![Cell {
+                center: DVec3::ZERO,
+                half_size,
+                points: 0..points.len(),
+                children: 0..0,
+            }],
+            points,
+        };
+        let mut to_split = vec![0];
+        while let Some(cell) = to_split.pop() {
+            to_split.extend(index.split(cell));
+        }
+        index
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Splits a cell with too many points, and returns its children
+    fn split(&mut self, cell: usize) -> Range<usize> {
+        let parent = self.cells[cell].clone();
+        if parent.points.len() <= LEAF_CAPACITY || parent.half_size * 2. <= MIN_CELL_KM {
+            return 0..0;
+        }
+        let points = &mut self.points[parent.points.clone()];
+        points.sort_unstable_by_key(|(_, p)| parent.octant(*p));
+        let first_child = self.cells.len();
+        let mut start = 0;
+        for octant in 0..8 {
+            let end = points.partition_point(|(_, p)| parent.octant(*p) <= octant);
+            if end > start {
+                self.cells.push(Cell {
+                    center: parent.child_center(octant),
+                    half_size: parent.half_size / 2.,
+                    points: parent.points.start + start..parent.points.start + end,
+                    children: 0..0,
+                });
+            }
+            start = end;
+        }
+        let children = first_child..self.cells.len();
+        self.cells[cell].children = children.clone();
+        children
+    }
+
+    /// Entities within `radius` of `center`, and the number of positions that were compared to
+    /// the radius. Cells entirely inside the sphere are taken without comparing their positions
+    fn radius_candidates(&self, center: DVec3, radius: f64) -> (Vec<Entity>, usize) {
+        let mut found = Vec::new();
+        let mut compared = 0;
+        let mut to_visit = if self.points.is_empty() {
+            Vec::new()
+        } else {
+            vec![0]
+        };
+        while let Some(cell) = to_visit.pop() {
+            let cell = &self.cells[cell];
+            let points = &self.points[cell.points.clone()];
+            if cell.distance_to(center) > radius {
+                continue;
+            } else if cell.farthest_from(center) <= radius {
+                found.extend(points.iter().map(|(e, _)| *e));
+            } else if cell.children.is_empty() {
+                compared += points.len();
+                found.extend(
+                    points
+                        .iter()
+                        .filter(|(_, p)| p.distance(center) <= radius)
+                        .map(|(e, _)| *e),
+                );
+            } else {
+                to_visit.extend(cell.children.clone());
+            }
+        }
+        (found, compared)
+    }
+
+    /// Entities within `radius` (in km) of `center`, in no particular order
+    pub fn query_radius(&self, center: DVec3, radius: f64) -> impl Iterator<Item = Entity> {
+        self.radius_candidates(center, radius).0.into_iter()
+    }
+
+    /// The `k` entities nearest to `center` with their distances, nearest first. The cells are
+    /// visited by increasing distance, until `k` positions are nearer than all the cells left
+    pub fn nearest(&self, center: DVec3, k: usize) -> Vec<(Entity, f64)> {
+        self.nearest_by(k, |cell| cell.distance_to(center), |p| p.distance(center))
+    }
+
+    /// [SpatialIndex::nearest] with the distances in the ecliptic plane, as on the space map
+    pub fn nearest_in_plane(&self, center: DVec2, k: usize) -> Vec<(Entity, f64)> {
+        self.nearest_by(
+            k,
+            |cell| cell.distance_in_plane(center),
+            |p| p.xy().distance(center),
+        )
+    }
+
+    /// The `k` entities nearest to a point, given the lower bound of the distance to the
+    /// positions of a cell and the distance to a position
+    fn nearest_by(
+        &self,
+        k: usize,
+        cell_distance: impl Fn(&Cell) -> f64,
+        point_distance: impl Fn(DVec3) -> f64,
+    ) -> Vec<(Entity, f64)> {
+        let mut nearest = Vec::with_capacity(k.min(self.points.len()));
+        if self.points.is_empty() || k == 0 {
+            return nearest;
+        }
+        let mut queue = BinaryHeap::from([Candidate {
+            distance: cell_distance(&self.cells[0]),
+            kind: CandidateKind::Cell(0),
+        }]);
+        while let Some(Candidate { distance, kind }) = queue.pop() {
+            match kind {
+                CandidateKind::Point(entity) => {
+                    nearest.push((entity, distance));
+                    if nearest.len() == k {
+                        break;
+                    }
+                }
+                CandidateKind::Cell(cell) => {
+                    let cell = &self.cells[cell];
+                    if cell.children.is_empty() {
+                        queue.extend(self.points[cell.points.clone()].iter().map(|(e, p)| {
+                            Candidate {
+                                distance: point_distance(*p),
+                                kind: CandidateKind::Point(*e),
+                            }
+                        }));
+                    } else {
+                        queue.extend(cell.children.clone().map(|child| Candidate {
+                            distance: cell_distance(&self.cells[child]),
+                            kind: CandidateKind::Cell(child),
+                        }));
+                    }
+                }
+            }
+        }
+        nearest
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CandidateKind {
+    Cell(usize),
+    Point(Entity),
+}
+
+/// Cell or position to visit in [SpatialIndex::nearest], the nearest being the greatest
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Candidate {
+    distance: f64,
+    kind: CandidateKind,
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.distance.total_cmp(&self.distance)
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn rebuild_ship_index(
+    mut index: ResMut<SpatialIndex>,
+    ships: Query<(Entity, &Position), With<ShipInfo>>,
+    system_size: Option<Res<SystemSize>>,
+) {
+    *index = SpatialIndex::build(
+        ships.iter().map(|(e, p)| (e, p.0)),
+        system_size.map_or(0., |s| s.0),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        math::{DVec2, DVec3, Vec3Swizzles},
+        prelude::Entity,
+    };
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::SpatialIndex;
+
+    fn brute_force(points: &[(Entity, DVec3)], center: DVec3, radius: f64) -> Vec<Entity> {
+        let mut found: Vec<_> = points
+            .iter()
+            .filter(|(_, p)| p.distance(center) <= radius)
+            .map(|(e, _)| *e)
+            .collect();
+        found.sort();
+        found
+    }
+
+    fn query(index: &SpatialIndex, center: DVec3, radius: f64) -> Vec<Entity> {
+        let mut found: Vec<_> = index.query_radius(center, radius).collect();
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn test_dynamic_range() {
+        // Two clusters of ships, around a planet near the origin and one far away
+        let mut rng = StdRng::seed_from_u64(0);
+        let near = DVec3::new(1e3, 0., 0.);
+        let far = DVec3::new(1e9, -1e8, 1e7);
+        let points: Vec<_> = (0..200)
+            .map(|i| {
+                let offset = DVec3::new(rng.gen(), rng.gen(), rng.gen()) * 10.;
+                let center = if i % 2 == 0 { near } else { far };
+                (Entity::from_raw(i), center + offset)
+            })
+            .collect();
+        let index = SpatialIndex::build(points.iter().copied(), 5e9);
+        assert_eq!(index.len(), 200);
+        for center in [near, far] {
+            let (found, compared) = index.radius_candidates(center, 20.);
+            assert_eq!(found.len(), 100);
+            assert!(compared <= 100, "{compared}");
+            assert_eq!(
+                query(&index, center + 5., 3.),
+                brute_force(&points, center + 5., 3.)
+            );
+        }
+        // The cells around the clusters are small, but not degenerate
+        let smallest = index
+            .cells
+            .iter()
+            .map(|c| c.half_size)
+            .fold(f64::MAX, f64::min);
+        assert!((0.5..10.).contains(&smallest), "{smallest}");
+        assert!(index.cells.len() < 200);
+
+        let nearest = index.nearest(far + 5., 3);
+        let mut expected: Vec<_> = points
+            .iter()
+            .map(|(e, p)| (*e, p.distance(far + 5.)))
+            .collect();
+        expected.sort_by(|a, b| a.1.total_cmp(&b.1));
+        assert_eq!(nearest, expected[..3]);
+        assert_eq!(index.nearest(near, 500).len(), 200);
+        assert!(SpatialIndex::default().nearest(near, 1).is_empty());
+    }
+
+    #[test]
+    fn test_sublinear_queries() {
+        const SHIPS: usize = 10_000;
+        let mut rng = StdRng::seed_from_u64(1);
+        // Distances from the origin spread evenly in log scale, from 1e3 to 1e9 km
+        let points: Vec<_> = (0..SHIPS)
+            .map(|i| {
+                let direction = DVec3::new(
+                    rng.gen_range(-1. ..1.),
+                    rng.gen_range(-1. ..1.),
+                    rng.gen_range(-1. ..1.),
+                )
+                .normalize();
+                let distance = 10f64.powf(rng.gen_range(3. ..9.));
+                (Entity::from_raw(i as u32), direction * distance)
+            })
+            .collect();
+        let index = SpatialIndex::build(points.iter().copied(), 5e9);
+
+        let mut compared = 0;
+        let queries = 100;
+        for (_, center) in points.iter().step_by(SHIPS / queries) {
+            // A tenth of the distance to the origin, which is a few km for the nearest ships
+            let radius = center.length() * 0.1;
+            let (mut found, n) = index.radius_candidates(*center, radius);
+            found.sort();
+            assert_eq!(found, brute_force(&points, *center, radius));
+            compared += n;
+        }
+        // The brute force compares every position at each query
+        let average = compared / queries;
+        assert!(
+            average < SHIPS / 20,
+            "{average} positions compared per query"
+        );
+
+        for (_, center) in points.iter().step_by(SHIPS / 10) {
+            let nearest = index.nearest(*center, 5);
+            let mut expected: Vec<_> = points
+                .iter()
+                .map(|(e, p)| (*e, p.distance(*center)))
+                .collect();
+            expected.sort_by(|a, b| a.1.total_cmp(&b.1));
+            assert_eq!(nearest, expected[..5]);
+        }
+    }
+
+    #[test]
+    fn test_nearest_in_plane() {
+        // A ship right above the point is nearer on the map than one beside it in the plane
+        let above = (Entity::from_raw(0), DVec3::new(0., 0., 1e6));
+        let beside = (Entity::from_raw(1), DVec3::new(1e3, 0., 0.));
+        let index = SpatialIndex::build([above, beside], 5e9);
+        assert_eq!(index.nearest(DVec3::ZERO, 1), vec![(beside.0, 1e3)]);
+        assert_eq!(
+            index.nearest_in_plane(DVec2::ZERO, 2),
+            vec![(above.0, 0.), (beside.0, 1e3)]
+        );
+
+        let mut rng = StdRng::seed_from_u64(2);
+        let points: Vec<_> = (0..1000)
+            .map(|i| {
+                let pos = DVec3::new(rng.gen(), rng.gen(), rng.gen()) * 2e6 - 1e6;
+                (Entity::from_raw(i), pos)
+            })
+            .collect();
+        let index = SpatialIndex::build(points.iter().copied(), 5e9);
+        for (_, center) in points.iter().step_by(100) {
+            let center = center.xy() + 10.;
+            let mut expected: Vec<_> = points
+                .iter()
+                .map(|(e, p)| (*e, p.xy().distance(center)))
+                .collect();
+            expected.sort_by(|a, b| a.1.total_cmp(&b.1));
+            assert_eq!(index.nearest_in_plane(center, 5), expected[..5]);
+        }
+    }
+}
@@ -1,6 +1,8 @@
-use bevy::prelude::*;
+use std::{collections::VecDeque, time::Duration};
 
-use crate::utils::Direction2;
+use bevy::{ecs::component::Tick, log::Level, prelude::*};
+
+use crate::{game::Authoritative, prelude::ClientMode, throttled, utils::Direction2};
 
 /// Number of server updates (ticks) per real time second
 // pub const TPS: f32 = 1.;
@@ -13,38 +15,71 @@ pub const GAMETIME_PER_SIMTICK: f64 = 1e-3;
 
 pub const SIMTICKS_PER_TICK: u64 = 10;
 
+/// Highest limit set by raising the [MaxSimSpeed] step by step, above which the speed is unlimited
+pub const MAX_SIM_SPEED_STEP_LIMIT: u64 = 1024;
+
 pub fn plugin(app: &mut App) {
-    info!("loading time::plugin");
-    info!("inserting resource time");
+    debug!("loading time::plugin");
+    debug!("inserting resource time");
     app.insert_resource(Time::<Fixed>::from_hz(STPS));
-    info!("initialising resource ToggleTime");
+    debug!("initialising resource ToggleTime");
     app.init_resource::<ToggleTime>();
-    info!("initialising resource GameTime");
+    debug!("initialising resource GameTime");
     app.init_resource::<GameTime>();
-    info!("initialising resource SimStepSize");
+    debug!("initialising resource SimStepSize");
     app.init_resource::<SimStepSize>();
-    info!("adding event TimeEvent");
+    debug!("initialising resource PhysicsRate");
+    app.init_resource::<PhysicsRate>();
+    debug!("initialising resource StepPacing");
+    app.init_resource::<StepPacing>();
+    debug!("initialising resource MaxSimSpeed");
+    app.init_resource::<MaxSimSpeed>();
+    debug!("initialising resource TickRateTracker");
+    app.init_resource::<TickRateTracker>();
+    debug!("adding event TimeEvent");
     app.add_event::<TimeEvent>();
-    info!("adding event TickEvent");
+    debug!("adding event TickEvent");
     app.add_event::<TickEvent>();
-    info!(
-        "adding systems FixedUpdate : (update_simtick, update_tick).in_set(TimeUpdate),
+    debug!(
+        "adding systems FixedUpdate : (pace_step_size, update_simtick, update_tick).chain().in_set(TimeUpdate),
 "
     );
     app.add_systems(
         FixedUpdate,
-        (update_simtick, update_tick).in_set(TimeUpdate),
+        (pace_step_size, update_simtick, update_tick)
+            .chain()
+            .in_set(TimeUpdate),
     );
-    info!("adding systems update : handle_time_events");
+    debug!("adding systems update : handle_time_events");
     app.add_systems(Update, handle_time_events);
+    debug!("adding systems Update : limit_sim_speed.after(handle_time_events)");
+    app.add_systems(
+        Update,
+        limit_sim_speed
+            .after(handle_time_events)
+            .run_if(in_state(Authoritative).or_else(in_state(ClientMode::Server))),
+    );
+    debug!("adding systems PreUpdate : apply_physics_rate");
+    app.add_systems(
+        PreUpdate,
+        apply_physics_rate.run_if(resource_changed::<PhysicsRate>),
+    );
 }
 
 #[derive(SystemSet, Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub struct TimeUpdate;
 
+/// Whether the players let the time run. The simulation can still be held by [limit_sim_speed],
+/// see [time_running]
 #[derive(Resource, PartialEq, Default)]
 pub struct ToggleTime(pub bool);
 
+/// Run condition of the simulation: the players let the time run and it isn't held back by the
+/// [MaxSimSpeed]
+pub fn time_running(toggle: Res<ToggleTime>, tracker: Res<TickRateTracker>) -> bool {
+    toggle.0 && !tracker.throttling
+}
+
 /// The elapsed time in game (stores simulation ticks)
 #[derive(Resource, Default, Debug, Clone, Copy)]
 pub struct GameTime {
@@ -53,14 +88,30 @@ pub struct GameTime {
 
 impl GameTime {
     pub fn time(&self) -> f64 {
-        debug!("time");
+        throttled!(Level::DEBUG, "time");
         self.simtick as f64 * GAMETIME_PER_SIMTICK
     }
 
     pub fn tick(&self) -> u64 {
-        debug!("tick");
+        throttled!(Level::DEBUG, "tick");
         self.simtick / SIMTICKS_PER_TICK
     }
+
+    /// Game time (in days) `fixed_overstep` simticks after the last fixed update, to smooth what
+    /// is rendered between two of them. See [interpolation_overstep]
+    pub fn interpolated(&self, fixed_overstep: f64) -> f64 {
+        (self.simtick as f64 + fixed_overstep.max(0.)) * GAMETIME_PER_SIMTICK
+    }
+}
+
+/// Number of simticks the simulation would have advanced since the last fixed update, from the
+/// fixed timestep overstep. It stays at zero while time is paused, and never goes past the next
+/// update when fixed updates are catching up
+pub fn interpolation_overstep(fixed: &Time<Fixed>, step: &SimStepSize, running: bool) -> f64 {
+    if !running {
+        return 0.;
+    }
+    fixed.overstep_fraction_f64().clamp(0., 1.) * step.0 as f64
 }
 
 /// The number of simticks that are added at each update
@@ -74,6 +125,128 @@ impl Default for SimStepSize {
     }
 }
 
+/// The number of updates (simulation ticks) that are run per real time second.
+///
+/// Changing it keeps the game speed (`SimStepSize × PhysicsRate`, in simticks per second) constant,
+/// by scaling the number of simticks per update accordingly. When it isn't a whole number, the
+/// [StepPacing] alternates between the step sizes around it, so that the game time advances at the
+/// same rate on average. Rates at which an update would last less than
+/// [MIN_SIMTICKS_PER_UPDATE] are refused, see [max_physics_rate].
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub struct PhysicsRate(pub f64);
+
+/// Number of simticks each update should advance, which may be fractional after a change of the
+/// [PhysicsRate]. The [SimStepSize] of each update is its whole part, plus the fractions
+/// accumulated over the previous updates
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Default)]
+pub struct StepPacing {
+    pub simticks_per_update: f64,
+    remainder: f64,
+    /// Last change of the [SimStepSize] by the players taken into account
+    seen_change: Option<Tick>,
+}
+
+impl StepPacing {
+    /// Replaces the pacing by the step size when the players changed it since the last call
+    fn follow(&mut self, step: u64, changed: Tick) {
+        if self.seen_change != Some(changed) {
+            self.simticks_per_update = step as f64;
+            self.remainder = 0.;
+            self.seen_change = Some(changed);
+        }
+    }
+
+    /// Number of simticks the next updates advance on average, given the current step size and
+    /// its last change, without taking that change into account
+    pub fn current(&self, step: u64, changed: Tick) -> f64 {
+        if self.seen_change == Some(changed) {
+            self.simticks_per_update
+        } else {
+            step as f64
+        }
+    }
+}
+
+impl Default for PhysicsRate {
+    fn default() -> Self {
+        Self(STPS)
+    }
+}
+
+/// Cap on the number of ticks run per real time second, whatever the step size and the rate of
+/// the physics. None if the speed is unlimited
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Default)]
+pub struct MaxSimSpeed {
+    pub ticks_per_second: Option<u64>,
+}
+
+impl MaxSimSpeed {
+    /// Doubles or halves the limit. Raising it past [MAX_SIM_SPEED_STEP_LIMIT] removes it, and
+    /// lowering it from no limit starts from there
+    pub fn step(&mut self, direction: Direction2) {
+        self.ticks_per_second = match (direction, self.ticks_per_second) {
+            (Direction2::Up, Some(max)) if max * 2 <= MAX_SIM_SPEED_STEP_LIMIT => Some(max * 2),
+            (Direction2::Up, _) => None,
+            (Direction2::Down, Some(max)) => Some((max / 2).max(1)),
+            (Direction2::Down, None) => Some(MAX_SIM_SPEED_STEP_LIMIT),
+        };
+    }
+}
+
+/// Simticks reached over the last real time second, to measure the speed of the simulation
+#[derive(Resource, Default, Debug)]
+pub struct TickRateTracker {
+    /// Real time of each frame with the simtick at that time, oldest first
+    samples: VecDeque<(Duration, u64)>,
+    /// Whether the simulation is held by [limit_sim_speed]
+    throttling: bool,
+}
+
+impl TickRateTracker {
+    fn record(&mut self, now: Duration, simtick: u64) {
+        self.samples.push_back((now, simtick));
+        while self
+            .samples
+            .front()
+            .is_some_and(|(t, _)| now.saturating_sub(*t) > Duration::from_secs(1))
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Whether the simulation is held because it ran faster than the [MaxSimSpeed]
+    pub fn throttling(&self) -> bool {
+        self.throttling
+    }
+
+    /// Number of ticks run over the last real time second
+    pub fn ticks_per_second(&self) -> f64 {
+        match (self.samples.front(), self.samples.back()) {
+            (Some((_, first)), Some((_, last))) => {
+                last.saturating_sub(*first) as f64 / SIMTICKS_PER_TICK as f64
+            }
+            _ => 0.,
+        }
+    }
+}
+
+/// Fewest simticks an update can advance: the simtick is the smallest step of the simulation, so
+/// the [PhysicsRate] can't be raised above the game speed
+pub const MIN_SIMTICKS_PER_UPDATE: f64 = 1.;
+
+/// Highest physics rate that keeps the game speed of `simticks_per_update` at `rate` updates per
+/// second (in Hz)
+pub fn max_physics_rate(simticks_per_update: f64, rate: f64) -> f64 {
+    simticks_per_update * rate / MIN_SIMTICKS_PER_UPDATE
+}
+
+/// Computes the number of simticks per update that keeps the game speed constant when changing
+/// the physics rate, or None if an update would last less than [MIN_SIMTICKS_PER_UPDATE]
+pub fn rescaled_step_size(simticks_per_update: f64, old_rate: f64, new_rate: f64) -> Option<f64> {
+    let rescaled = simticks_per_update * old_rate / new_rate;
+    (rescaled >= MIN_SIMTICKS_PER_UPDATE - 1e-9).then_some(rescaled.max(MIN_SIMTICKS_PER_UPDATE))
+}
+
 #[derive(Event, Default)]
 pub struct TickEvent;
 
@@ -87,26 +260,82 @@ pub enum TimeEvent {
     ///
     /// This does not change simulation outcome, but leads to heavier CPU load.
     ChangeUpdateRate(Direction2),
+    /// Raise or lower the [MaxSimSpeed]
+    ChangeMaxSpeed(Direction2),
     ToggleTime,
 }
 
-fn update_tick(mut writer: EventWriter<TickEvent>, game_time: Res<GameTime>) {
-    debug!("update_tick");
-    if game_time.simtick % SIMTICKS_PER_TICK == 0 {
+/// Sends a [TickEvent] when the last update crossed the start of a tick, whatever the step size
+fn update_tick(
+    mut writer: EventWriter<TickEvent>,
+    game_time: Res<GameTime>,
+    step: Res<SimStepSize>,
+) {
+    throttled!(Level::DEBUG, "update_tick");
+    let previous = game_time.simtick.saturating_sub(step.0);
+    if step.0 > 0 && game_time.simtick / SIMTICKS_PER_TICK != previous / SIMTICKS_PER_TICK {
         writer.send_default();
     }
 }
 
 fn update_simtick(mut game_time: ResMut<GameTime>, step: Res<SimStepSize>) {
-    debug!("update_simtick");
+    throttled!(Level::DEBUG, "update_simtick");
     game_time.simtick += step.0;
 }
 
+/// Sets the [SimStepSize] of the update from the [StepPacing]. A step size set by the players
+/// replaces the pacing, so the writes of this system don't count as changes
+fn pace_step_size(mut step: ResMut<SimStepSize>, mut pacing: ResMut<StepPacing>) {
+    pacing.follow(step.0, step.last_changed());
+    let advance = pacing.simticks_per_update + pacing.remainder;
+    // Rounding errors shouldn't delay the longer steps
+    let whole = (advance + 1e-9).floor();
+    pacing.remainder = (advance - whole).max(0.);
+    step.bypass_change_detection().0 = whole as u64;
+}
+
+fn apply_physics_rate(
+    mut rate: ResMut<PhysicsRate>,
+    mut time: ResMut<Time<Fixed>>,
+    step_size: Res<SimStepSize>,
+    mut pacing: ResMut<StepPacing>,
+) {
+    let old_rate = 1. / time.timestep().as_secs_f64();
+    pacing.follow(step_size.0, step_size.last_changed());
+    let rescaled = if rate.0 <= 0. || !rate.0.is_finite() {
+        warn!("invalid physics rate {}, keeping {old_rate} Hz", rate.0);
+        None
+    } else {
+        let rescaled = rescaled_step_size(pacing.simticks_per_update, old_rate, rate.0);
+        if rescaled.is_none() {
+            warn!(
+                "physics rate {} Hz is above the highest rate of {} Hz at this game speed, \
+                keeping {old_rate} Hz",
+                rate.0,
+                max_physics_rate(pacing.simticks_per_update, old_rate)
+            );
+        }
+        rescaled
+    };
+    let Some(simticks_per_update) = rescaled else {
+        rate.bypass_change_detection().0 = old_rate;
+        return;
+    };
+    info!(
+        "physics rate set to {} Hz, {simticks_per_update} simticks per update",
+        rate.0
+    );
+    time.set_timestep_hz(rate.0);
+    pacing.simticks_per_update = simticks_per_update;
+    pacing.remainder = 0.;
+}
+
 fn handle_time_events(
     mut reader: EventReader<TimeEvent>,
     mut toggle_time: ResMut<ToggleTime>,
     mut time: ResMut<Time<Virtual>>,
     mut step_size: ResMut<SimStepSize>,
+    mut max_speed: ResMut<MaxSimSpeed>,
 ) {
     use TimeEvent::*;
     for event in reader.read() {
@@ -120,9 +349,277 @@ fn handle_time_events(
             }
             ChangeStepSize(d) => match d {
                 Direction2::Up => step_size.0 *= 2,
-                Direction2::Down => step_size.0 /= 2,
+                Direction2::Down => step_size.0 = (step_size.0 / 2).max(1),
             },
+            ChangeMaxSpeed(d) => max_speed.step(*d),
             ToggleTime => toggle_time.0 = !toggle_time.0,
         }
     }
 }
+
+/// Holds the simulation for the next frame when more ticks than the [MaxSimSpeed] were run over
+/// the last second, and releases it at the first frame under the limit. The [ToggleTime] of the
+/// players is left as is, so that pausing or resuming while held isn't undone
+fn limit_sim_speed(
+    max_speed: Res<MaxSimSpeed>,
+    mut tracker: ResMut<TickRateTracker>,
+    real_time: Res<Time<Real>>,
+    game_time: Res<GameTime>,
+) {
+    tracker.record(real_time.elapsed(), game_time.simtick);
+    tracker.throttling = max_speed
+        .ticks_per_second
+        .is_some_and(|max| tracker.ticks_per_second() > max as f64);
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        app::FixedMain, ecs::system::RunSystemOnce, math::DVec3, prelude::*,
+        time::TimeUpdateStrategy,
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_max_sim_speed_step() {
+        let mut max = MaxSimSpeed::default();
+        max.step(Direction2::Down);
+        assert_eq!(max.ticks_per_second, Some(MAX_SIM_SPEED_STEP_LIMIT));
+        max.step(Direction2::Down);
+        assert_eq!(max.ticks_per_second, Some(MAX_SIM_SPEED_STEP_LIMIT / 2));
+        max.step(Direction2::Up);
+        max.step(Direction2::Up);
+        assert_eq!(max.ticks_per_second, None);
+        max.ticks_per_second = Some(1);
+        max.step(Direction2::Down);
+        assert_eq!(max.ticks_per_second, Some(1));
+    }
+
+    #[test]
+    fn test_limit_sim_speed() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer));
+        app.update();
+        app.world_mut().resource_mut::<ToggleTime>().0 = true;
+        app.world_mut()
+            .resource_mut::<MaxSimSpeed>()
+            .ticks_per_second = Some(2);
+        app.update();
+        let start = app.world().resource::<GameTime>().simtick;
+        run_until(&mut app, start + 3 * SIMTICKS_PER_TICK);
+        app.update();
+        assert!(app.world().resource::<TickRateTracker>().ticks_per_second() >= 3.);
+        // Held as long as the last second is above the limit, without pausing for the players
+        let held = app.world().resource::<GameTime>().simtick;
+        for _ in 0..3 {
+            assert!(app.world().resource::<TickRateTracker>().throttling());
+            assert!(app.world().resource::<ToggleTime>().0);
+            app.update();
+        }
+        assert_eq!(app.world().resource::<GameTime>().simtick, held);
+
+        // A pause of the players while held is kept once the ticks run fast are more than a
+        // second old
+        app.world_mut().send_event(TimeEvent::ToggleTime);
+        app.update();
+        app.world_mut()
+            .resource_mut::<TickRateTracker>()
+            .samples
+            .clear();
+        app.update();
+        assert!(!app.world().resource::<TickRateTracker>().throttling());
+        assert!(!app.world().resource::<ToggleTime>().0);
+        assert_eq!(app.world().resource::<GameTime>().simtick, held);
+
+        // Without limit, the time is never held
+        app.world_mut().resource_mut::<ToggleTime>().0 = true;
+        app.world_mut()
+            .resource_mut::<MaxSimSpeed>()
+            .ticks_per_second = None;
+        run_until(&mut app, start + 10 * SIMTICKS_PER_TICK);
+        app.update();
+        assert!(!app.world().resource::<TickRateTracker>().throttling());
+    }
+
+    #[test]
+    fn test_rescaled_step_size() {
+        assert_eq!(rescaled_step_size(1., 64., 16.), Some(4.));
+        assert_eq!(rescaled_step_size(4., 16., 64.), Some(1.));
+        assert_eq!(rescaled_step_size(1., 64., 20.), Some(3.2));
+        assert_eq!(rescaled_step_size(1., 64., 240.), None);
+        assert_eq!(rescaled_step_size(5., 64., 240.), Some(4. / 3.));
+        // Up to a simtick per update
+        assert_eq!(max_physics_rate(5., 64.), 320.);
+        assert_eq!(rescaled_step_size(5., 64., 320.), Some(1.));
+        assert_eq!(rescaled_step_size(5., 64., 320.1), None);
+    }
+
+    #[test]
+    fn test_step_pacing() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer));
+        app.update();
+        app.world_mut().resource_mut::<ToggleTime>().0 = true;
+        app.world_mut().insert_resource(PhysicsRate(20.));
+        app.world_mut().run_system_once(apply_physics_rate);
+        let start = app.world().resource::<GameTime>().simtick;
+        let mut steps = Vec::new();
+        let mut ticks = 0;
+        for _ in 0..10 {
+            FixedMain::run_fixed_main(app.world_mut());
+            steps.push(app.world().resource::<SimStepSize>().0);
+            ticks += app
+                .world_mut()
+                .resource_mut::<Events<TickEvent>>()
+                .drain()
+                .count();
+        }
+        assert_eq!(steps, [3, 3, 3, 3, 4, 3, 3, 3, 3, 4]);
+        let end = app.world().resource::<GameTime>().simtick;
+        assert_eq!(end - start, 32);
+        // The ticks are sent even when the simticks skip their start
+        assert_eq!(
+            ticks as u64,
+            end / SIMTICKS_PER_TICK - start / SIMTICKS_PER_TICK
+        );
+
+        // The step size set by the players replaces the pacing
+        app.world_mut().resource_mut::<SimStepSize>().0 = 5;
+        FixedMain::run_fixed_main(app.world_mut());
+        FixedMain::run_fixed_main(app.world_mut());
+        assert_eq!(app.world().resource::<GameTime>().simtick, end + 10);
+
+        // Faster than the game speed, an update would last less than a simtick
+        app.world_mut().insert_resource(PhysicsRate(1000.));
+        app.world_mut().run_system_once(apply_physics_rate);
+        assert_eq!(app.world().resource::<PhysicsRate>().0, 20.);
+        assert_eq!(app.world().resource::<StepPacing>().simticks_per_update, 5.);
+    }
+
+    /// Simticks run over a second of real time, in updates of 50 ms
+    fn simticks_in_a_second(app: &mut App) -> u64 {
+        let start = app.world().resource::<GameTime>().simtick;
+        for _ in 0..20 {
+            app.update();
+        }
+        app.world().resource::<GameTime>().simtick - start
+    }
+
+    #[test]
+    fn test_physics_rate_keeps_wall_clock_speed() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer))
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_millis(
+                50,
+            )));
+        app.update();
+        app.world_mut().resource_mut::<ToggleTime>().0 = true;
+        simticks_in_a_second(&mut app);
+        let before = simticks_in_a_second(&mut app);
+        assert!(before.abs_diff(STPS as u64) <= 1, "{before}");
+        for hz in [20., 16., 240., 64.] {
+            app.world_mut().insert_resource(PhysicsRate(hz));
+            app.update();
+            let after = simticks_in_a_second(&mut app);
+            assert!(after.abs_diff(before) <= 1, "{hz} Hz: {after} {before}");
+        }
+    }
+
+    fn new_app(info: ShipInfo) -> App {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer));
+        app.update();
+        app.world_mut().send_event(ShipEvent::Create(info));
+        app.update();
+        app.world_mut()
+            .resource_mut::<NextState<GameStage>>()
+            .set(GameStage::Action);
+        app.update();
+        app
+    }
+
+    fn run_until(app: &mut App, simtick: u64) {
+        while app.world().resource::<GameTime>().simtick < simtick {
+            FixedMain::run_fixed_main(app.world_mut());
+        }
+    }
+
+    fn relative_ship_pos(app: &mut App) -> DVec3 {
+        let world = app.world_mut();
+        let earth = world.resource::<BodiesMapping>().0["terre"];
+        let earth_pos = world.query::<&Position>().get(world, earth).unwrap().0;
+        let ship_pos = world
+            .query_filtered::<&Position, With<ShipInfo>>()
+            .single(world)
+            .0;
+        ship_pos - earth_pos
+    }
+
+    fn game_speed(app: &App) -> f64 {
+        app.world().resource::<StepPacing>().simticks_per_update
+            / app
+                .world()
+                .resource::<Time<Fixed>>()
+                .timestep()
+                .as_secs_f64()
+    }
+
+    #[test]
+    fn test_change_physics_rate() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer));
+        app.update();
+        let world = app.world_mut();
+        let earth = world.resource::<BodiesMapping>().0["terre"];
+        let (&mass, &earth_pos, &earth_speed) = world
+            .query::<(&Mass, &Position, &Velocity)>()
+            .get(world, earth)
+            .unwrap();
+        let (spawn_pos, spawn_speed) = crate::utils::algebra::circular_orbit_around_body(
+            1e5,
+            mass.0,
+            earth_pos.0,
+            earth_speed.0,
+            &mut StdRng::seed_from_u64(0),
+        );
+        let info = ShipInfo {
+            id: id_from("s"),
+            spawn_pos,
+            spawn_speed,
+            spawn_orbit: None,
+        };
+
+        let mut reference = new_app(info);
+        let mut app_16 = new_app(info);
+        let mut app_20 = new_app(info);
+        let speed = game_speed(&reference);
+        assert!((speed - STPS).abs() < 1e-6);
+
+        for app in [&mut reference, &mut app_16, &mut app_20] {
+            run_until(app, 600);
+        }
+        app_16.world_mut().insert_resource(PhysicsRate(16.));
+        app_20.world_mut().insert_resource(PhysicsRate(20.));
+        app_16.world_mut().run_system_once(apply_physics_rate);
+        app_20.world_mut().run_system_once(apply_physics_rate);
+
+        // 16 Hz runs 4 simticks per update, 20 Hz alternates between 3 and 4 simticks
+        assert!((game_speed(&app_16) - speed).abs() < 1e-6);
+        assert!((game_speed(&app_20) - speed).abs() < 1e-6);
+
+        // About one sim-day later (a multiple of both step patterns), the trajectories are still
+        // close
+        let end = 600 + 1008;
+        for app in [&mut reference, &mut app_16, &mut app_20] {
+            run_until(app, end);
+            assert_eq!(app.world().resource::<GameTime>().simtick, end);
+        }
+        let reference_pos = relative_ship_pos(&mut reference);
+        assert!((relative_ship_pos(&mut app_16) - reference_pos).length() < 100.);
+        assert!((relative_ship_pos(&mut app_20) - reference_pos).length() < 100.);
+    }
+}
@@ -1,15 +1,50 @@
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr};
 use std::result::Result::Ok;
 
 use crate::client::ClientMode;
-use crate::game::ClearOnUnload;
-use crate::network::PeriodicUpdate;
+use crate::game::budget::{BudgetCounts, BudgetKind, Cap, WorldBudget};
+use crate::game::{world_hash, ClearOnUnload, GameFiles, WorldSeed};
+use crate::network::{InterestFocus, InterestRegion, PeriodicUpdate};
+use crate::objects::bodies::validation::check_main_bodies;
+use crate::objects::bodies::{bodies_hash, body_data::BodyData, USER_BODIES_PATH};
+use crate::objects::ships::autopilot::Autopilot;
+use crate::objects::ships::history::{HistoryEvent, DEFAULT_RECORD_INTERVAL};
+use crate::objects::ships::navigation::RadioBeacon;
+use crate::objects::ships::trajectory::{
+    ManeuverNode, Trajectory, TrajectoryEvent, TRAJECTORIES_PATH,
+};
+use crate::objects::waypoints::{
+    parse_waypoint, WaypointID, WaypointKind, WaypointPositions, Waypoints, WAYPOINTS_FILE,
+};
+use crate::physics::gravity::GravityModel;
 use crate::physics::influence::HillRadius;
-use crate::physics::time::{SimStepSize, ToggleTime};
-use crate::physics::{PhysicsUpdate, Position, Velocity};
+use crate::physics::leapfrog::{get_acceleration, get_dv, get_dx, IntegrationMethod};
+use crate::physics::maneuver::StationKeepingReport;
+use crate::physics::optimizer::{
+    optimize_trajectory, GeneticConfig, ManeuverSequence, TrajectoryConstraint,
+};
+use crate::physics::predictions::{chain_position, orbit_chain, PredictionStart};
+use crate::physics::rk4::rk4_step;
+use crate::physics::sgp4::{Sgp4, SimulationEpoch, TLEData};
+use crate::physics::time::{
+    max_physics_rate, MaxSimSpeed, PhysicsRate, SimStepSize, StepPacing, TickRateTracker,
+    ToggleTime, GAMETIME_PER_SIMTICK, SIMTICKS_PER_TICK,
+};
+use crate::physics::{Mass, PhysicsUpdate, Position, Velocity, G};
 use crate::prelude::{
-    Acceleration, BodiesMapping, BodyInfo, Influenced, PrimaryBody, ShipID, ShipInfo, ShipsMapping,
+    Acceleration, AccelerationLog, BodiesMapping, BodyID, BodyInfo, CreateShipMsg, EllipticalOrbit,
+    IdGenerator, Influenced, PrimaryBody, ShipID, ShipInfo, ShipsMapping,
 };
+use crate::utils::algebra::{check_spawn_altitude, osculating_elements};
+use crate::utils::log::{InitialLogLevel, LogFilter};
+use crate::utils::persist::{
+    backup_path, migrate_file, read_header, read_versioned, write_versioned, Encoding,
+    PersistError, Versioned,
+};
+use crate::utils::table::Table;
+use bevy::ecs::entity::Entities;
+use bevy::log::Level;
+use bevy::math::DVec3;
 use bevy::prelude::*;
 use bevy::tasks::block_on;
 use bevy::tasks::{poll_once, AsyncComputeTaskPool, Task};
@@ -21,7 +56,12 @@ use bevy_quinnet::{
     },
     shared::ClientId,
 };
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::io::{self, BufRead};
+use std::path::Path;
+use std::time::Duration;
 pub mod prelude {
     pub use super::{ServerNetworkInfo, ServerPlugin};
 }
@@ -30,7 +70,11 @@ pub struct CommandSet;
 
 use crate::{
     game::GamePlugin,
-    network::{ClientMessage, InitialData, ServerChannel, ServerMessage},
+    network::{
+        transport::{LoopbackServer, MessageTransport, ServerTransport, TransportKind},
+        ClientMessage, InitialData, PermissionDenied, Role, ServerChannel, ServerMessage,
+        ShipRejectionReason,
+    },
     prelude::{BodiesConfig, GameTime},
     utils::ecs::exit_on_error_if_app,
 };
@@ -38,35 +82,152 @@ use crate::{
 pub struct ServerPlugin {
     pub server_address: ServerNetworkInfo,
     pub config: BodiesConfig,
+    pub physics_rate: PhysicsRate,
+    pub seed: WorldSeed,
+    /// Level at which the server starts logging, which can be changed with the `log_level` command
+    pub log_level: Level,
+    pub transport: TransportKind,
+    pub testing: bool,
+}
+
+impl ServerPlugin {
+    /// A server without console, whose clients are linked in memory with
+    /// [crate::network::testing::linked_apps]
+    pub fn testing() -> Self {
+        Self {
+            server_address: ServerNetworkInfo(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
+            config: BodiesConfig::default(),
+            physics_rate: PhysicsRate::default(),
+            seed: WorldSeed::default(),
+            log_level: Level::WARN,
+            transport: TransportKind::Loopback,
+            testing: true,
+        }
+    }
+
+    pub fn with_physics_rate(self, hz: f64) -> Self {
+        Self {
+            physics_rate: PhysicsRate(hz),
+            ..self
+        }
+    }
+
+    pub fn with_seed(self, seed: u64) -> Self {
+        Self {
+            seed: WorldSeed(seed),
+            ..self
+        }
+    }
+
+    pub fn with_bodies(self, config: BodiesConfig) -> Self {
+        Self { config, ..self }
+    }
 }
 
 impl Plugin for ServerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins((GamePlugin::default(), QuinnetServerPlugin::default()))
+        match self.transport {
+            TransportKind::Quinnet => {
+                app.add_plugins(QuinnetServerPlugin::default())
+                    .add_systems(Startup, start_endpoint.pipe(exit_on_error_if_app));
+            }
+            TransportKind::Loopback => {
+                app.init_resource::<LoopbackServer>();
+            }
+        }
+        if !self.testing {
+            app.add_systems(Update, (handle_stdin, read_stdin));
+        }
+        app.insert_resource(InitialLogLevel(self.log_level))
+            .add_plugins(GamePlugin {
+                testing: self.testing,
+            })
             .add_event::<ClientConnectionEvent>()
+            .add_event::<SandboxRequest>()
+            .init_resource::<PendingSandboxes>()
+            .add_event::<KickEvent>()
             .insert_state(ClientMode::Server)
             .insert_resource(TaskCommand::default())
             .insert_state(Reading::default())
             .insert_state(Command::default())
-            .add_systems(Update, (handle_stdin, read_stdin))
-            .add_systems(FixedUpdate, handle_client_messages.in_set(PhysicsUpdate))
+            .add_systems(
+                FixedUpdate,
+                (handle_client_messages, run_maneuver_sandboxes)
+                    .chain()
+                    .in_set(PhysicsUpdate),
+            )
             .add_systems(OnExit(Command::None), handle_command.in_set(CommandSet))
             .add_systems(OnEnter(Command::TestSetPos), test_set_pos)
+            .add_systems(OnEnter(Command::CompareIntegrators), compare_integrators)
+            .add_systems(OnEnter(Command::PerturbationLog), perturbation_log)
+            .add_systems(OnEnter(Command::PerturbationSummary), perturbation_summary)
+            .add_systems(OnEnter(Command::TickRate), set_tick_rate)
+            .add_systems(OnEnter(Command::SetMaxSpeed), set_max_speed_command)
+            .add_systems(OnEnter(Command::BandwidthStats), bandwidth_stats)
+            .add_systems(OnEnter(Command::Role), role_command)
+            .add_systems(OnEnter(Command::MaxPlayers), max_players_command)
+            .add_systems(OnEnter(Command::Record), record_command)
+            .add_systems(OnEnter(Command::Seed), seed_command)
+            .add_systems(OnEnter(Command::HashWorld), hash_world_command)
+            .add_systems(OnEnter(Command::Optimize), optimize_command)
+            .add_systems(OnEnter(Command::Kick), kick_command)
+            .add_systems(OnEnter(Command::Ban), ban_command)
+            .add_systems(OnEnter(Command::Unban), unban_command)
+            .add_systems(OnEnter(Command::Bans), bans_command)
+            .add_systems(OnEnter(Command::StationKeeping), station_keeping_command)
+            .add_systems(OnEnter(Command::LogLevel), log_level_command)
+            .add_systems(OnEnter(Command::CheckBodies), check_bodies_command)
+            .add_systems(OnEnter(Command::Migrate), migrate_command)
+            .add_systems(OnEnter(Command::Budget), budget_command)
+            .add_systems(OnEnter(Command::Waypoint), waypoint_command)
+            .add_systems(OnEnter(Command::FleetReport), fleet_report_command)
+            .add_systems(
+                OnEnter(Command::FleetReportInterval),
+                fleet_report_interval_command,
+            )
+            .add_systems(OnEnter(Command::Integration), integration_command)
+            .add_systems(OnEnter(Command::Tle), tle_command)
+            .add_systems(OnEnter(Command::Epoch), epoch_command)
+            .add_systems(OnEnter(Command::Gravity), gravity_command)
+            .add_systems(OnEnter(Command::Beacon), beacon_command)
             .insert_resource(self.server_address.clone())
             .insert_resource(self.config.clone())
+            .insert_resource(self.physics_rate)
+            .insert_resource(self.seed)
             .insert_resource(Clients::default())
             .insert_resource(PeriodicUpdatesTimer(Timer::from_seconds(
                 1. / 60.,
                 TimerMode::Repeating,
             )))
+            .init_resource::<BandwidthTracker>()
+            .init_resource::<ClientRoles>()
+            .init_resource::<MaxPlayers>()
+            .init_resource::<ShipOwners>()
+            .init_resource::<ClientNames>()
+            .init_resource::<ClientInterests>()
+            .init_resource::<FleetReportInterval>()
+            .init_resource::<OptimizationTasks>()
+            .init_resource::<PerClientBandwidthLimitBytesPerSec>()
+            .insert_resource(BandwidthWindowTimer(Timer::from_seconds(
+                1.,
+                TimerMode::Repeating,
+            )))
             .insert_resource(Arguments(String::new()))
-            .add_systems(Startup, start_endpoint.pipe(exit_on_error_if_app))
+            .add_systems(Startup, load_ban_list.pipe(exit_on_error_if_app))
             .add_systems(
                 Update,
                 (
-                    update_clients,
-                    handle_connection_events.pipe(exit_on_error_if_app),
+                    (
+                        kick_clients,
+                        update_clients,
+                        handle_connection_events.pipe(exit_on_error_if_app),
+                    )
+                        .chain(),
+                    reset_bandwidth_window,
                     send_periodic_updates,
+                    poll_optimizations,
+                    write_fleet_log,
+                    broadcast_time_running,
                 ),
             );
     }
@@ -78,7 +239,7 @@ pub struct ServerNetworkInfo(pub IpAddr, pub u16);
 #[derive(Resource, Default)]
 struct Clients(Vec<ClientId>);
 
-#[derive(Event)]
+#[derive(Event, Debug, PartialEq, Eq)]
 enum ClientConnectionEvent {
     Connected(ClientId),
     Disconnected(ClientId),
@@ -87,6 +248,234 @@ enum ClientConnectionEvent {
 #[derive(Resource)]
 struct PeriodicUpdatesTimer(Timer);
 
+/// Ships outside the [InterestRegion] of a client are sent once every this many periodic updates
+pub const COARSE_UPDATE_PERIOD: u64 = 10;
+
+/// Region followed by each client, see [ClientMessage::InterestUpdate]. Clients that never sent
+/// one get every ship at full rate
+#[derive(Resource, Default, Debug)]
+pub struct ClientInterests(pub HashMap<ClientId, InterestRegion>);
+
+/// Bytes of periodic updates sent to each client in the current 1-second window
+#[derive(Resource, Default, Debug)]
+pub struct BandwidthTracker(pub HashMap<ClientId, usize>);
+
+impl BandwidthTracker {
+    /// Records that `bytes` are sent to the client, unless it would exceed the limit.
+    /// Returns whether the bytes can be sent.
+    pub fn try_consume(&mut self, client: ClientId, bytes: usize, limit: usize) -> bool {
+        let used = self.0.entry(client).or_default();
+        if *used + bytes > limit {
+            false
+        } else {
+            *used += bytes;
+            true
+        }
+    }
+}
+
+/// Maximum number of bytes of periodic updates sent to a single client per second
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct PerClientBandwidthLimitBytesPerSec(pub usize);
+
+impl Default for PerClientBandwidthLimitBytesPerSec {
+    fn default() -> Self {
+        Self(1_000_000)
+    }
+}
+
+#[derive(Resource)]
+struct BandwidthWindowTimer(Timer);
+
+/// Role of each connected client
+#[derive(Resource, Default, Debug)]
+pub struct ClientRoles(pub HashMap<ClientId, Role>);
+
+impl ClientRoles {
+    /// Role given to a newly connected client: a player, unless there are already `max_players`
+    /// clients that are not spectators
+    fn role_for_new_client(&self, max_players: MaxPlayers) -> Role {
+        let players = self
+            .0
+            .values()
+            .filter(|role| **role != Role::Spectator)
+            .count();
+        match max_players.0 {
+            Some(max) if players >= max => Role::Spectator,
+            _ => Role::Player,
+        }
+    }
+}
+
+/// Maximum number of clients that can join as players, the next ones join as spectators
+#[derive(Resource, Default, Debug, Clone, Copy)]
+pub struct MaxPlayers(pub Option<usize>);
+
+/// Client that created each ship
+#[derive(Resource, Default, Debug)]
+pub struct ShipOwners(pub HashMap<ShipID, ClientId>);
+
+/// Name given by each connected client at handshake, see [ClientMessage::Hello]
+#[derive(Resource, Default, Debug)]
+pub struct ClientNames(pub HashMap<ClientId, String>);
+
+impl ClientNames {
+    /// Clients that gave the name `name`
+    fn clients_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = ClientId> + 'a {
+        self.0
+            .iter()
+            .filter(move |(_, n)| *n == name)
+            .map(|(client, _)| *client)
+    }
+}
+
+/// Name of the file of the ban list, in the game files
+pub const BAN_LIST_FILE: &str = "banlist.json";
+
+/// Reason sent to banned players when they try to join
+const BANNED_REASON: &str = "banned";
+
+/// Names of the players that can't join the server, kept on disk so that bans survive restarts
+#[derive(Resource, Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct BanList {
+    pub names: BTreeSet<String>,
+}
+
+impl BanList {
+    /// Reads the ban list at `path`, which is empty if the file doesn't exist
+    pub fn load(path: &Path) -> Result<Self, PersistError> {
+        match read_versioned(path) {
+            Err(PersistError::Io(e)) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            result => result,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), PersistError> {
+        write_versioned(path, self)
+    }
+
+    pub fn is_banned(&self, name: &str) -> bool {
+        self.names.contains(name)
+    }
+}
+
+impl Versioned for BanList {
+    const FORMAT: &'static str = "solar4x-banlist";
+    const VERSION: u32 = 1;
+    const ENCODING: Encoding = Encoding::Json;
+}
+
+/// Closes the connection of a client, after sending it `message` (which tells it why)
+#[derive(Event)]
+struct KickEvent {
+    client: ClientId,
+    message: ServerMessage,
+}
+
+/// Number of positions sent back for a maneuver preview
+const PREVIEW_SAMPLES: u64 = 50;
+
+/// Maximum number of ticks of a maneuver preview, to bound the time spent computing it
+const MAX_PREVIEW_TICKS: u64 = 25_000;
+
+/// Number of ticks of maneuver previews computed at each update at most. The other previews wait
+/// for the next updates
+const PREVIEW_TICKS_PER_UPDATE: u64 = MAX_PREVIEW_TICKS;
+
+/// A maneuver preview requested by a client
+#[derive(Event, Clone)]
+struct SandboxRequest {
+    client: ClientId,
+    ship: ShipID,
+    nodes: BTreeMap<u64, ManeuverNode>,
+    preview_ticks: u64,
+}
+
+/// Maneuver previews waiting for their turn, oldest first. A client editing a maneuver sends a
+/// request at each change, so a new request replaces the waiting one of the same client for the
+/// same ship
+#[derive(Resource, Default)]
+struct PendingSandboxes(VecDeque<SandboxRequest>);
+
+impl PendingSandboxes {
+    fn push(&mut self, request: SandboxRequest) {
+        match self
+            .0
+            .iter_mut()
+            .find(|r| (r.client, r.ship) == (request.client, request.ship))
+        {
+            Some(waiting) => *waiting = request,
+            None => self.0.push_back(request),
+        }
+    }
+
+    /// Takes the oldest requests whose previews fit in [PREVIEW_TICKS_PER_UPDATE]
+    fn take_batch(&mut self) -> Vec<SandboxRequest> {
+        let mut budget = PREVIEW_TICKS_PER_UPDATE;
+        let mut batch = Vec::new();
+        while let Some(request) = self.0.front() {
+            if request.preview_ticks > budget {
+                break;
+            }
+            budget -= request.preview_ticks;
+            batch.extend(self.0.pop_front());
+        }
+        batch
+    }
+}
+
+/// An action requested by a client, which requires some permissions
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    Identify,
+    CreateShip,
+    ControlShip(ShipID),
+    ChangeStage,
+    SyncTime,
+    SyncBodies,
+    FollowRegion,
+}
+
+impl From<&ClientMessage> for Action {
+    fn from(message: &ClientMessage) -> Self {
+        match message {
+            ClientMessage::Hello { .. } => Self::Identify,
+            ClientMessage::CreateShipMsg(_) | ClientMessage::CreateShips(_) => Self::CreateShip,
+            ClientMessage::Thrust { id, .. } | ClientMessage::SandboxManeuver { ship: id, .. } => {
+                Self::ControlShip(*id)
+            }
+            ClientMessage::ChangeStage(_) => Self::ChangeStage,
+            ClientMessage::TimeSyncRequest { .. } => Self::SyncTime,
+            ClientMessage::RequestBodies => Self::SyncBodies,
+            ClientMessage::InterestUpdate { .. } => Self::FollowRegion,
+        }
+    }
+}
+
+/// Checks whether a client with the given role can perform an action.
+/// Admins can do anything, players can create ships and control the ships they created,
+/// and spectators can't do anything
+fn check_permission(
+    role: Role,
+    action: Action,
+    client: ClientId,
+    owners: &ShipOwners,
+) -> Result<(), PermissionDenied> {
+    match (role, action) {
+        (Role::Admin, _)
+        | (_, Action::Identify | Action::SyncTime | Action::SyncBodies | Action::FollowRegion) => {
+            Ok(())
+        }
+        (_, Action::ChangeStage) => Err(PermissionDenied::AdminOnly),
+        (Role::Spectator, _) => Err(PermissionDenied::Spectator),
+        (Role::Player, Action::CreateShip) => Ok(()),
+        (Role::Player, Action::ControlShip(id)) => match owners.0.get(&id) {
+            Some(owner) if *owner == client => Ok(()),
+            _ => Err(PermissionDenied::NotOwner(id)),
+        },
+    }
+}
+
 fn start_endpoint(
     mut server: ResMut<QuinnetServer>,
     network_info: Res<ServerNetworkInfo>,
@@ -101,12 +490,41 @@ fn start_endpoint(
     Ok(())
 }
 
+fn load_ban_list(mut commands: Commands, files: Res<GameFiles>) -> color_eyre::Result<()> {
+    let ban_list = BanList::load(&files.root.join(BAN_LIST_FILE))?;
+    info!("{} banned players", ban_list.names.len());
+    commands.insert_resource(ban_list);
+    Ok(())
+}
+
+/// Sends their reason to the kicked clients and closes their connection, going through the same
+/// path as a client leaving by itself
+fn kick_clients(
+    mut kicks: EventReader<KickEvent>,
+    mut transport: ServerTransport,
+    mut clients: ResMut<Clients>,
+    mut writer: EventWriter<ClientConnectionEvent>,
+) {
+    for KickEvent { client, message } in kicks.read() {
+        transport
+            .send_to(*client, ServerChannel::Once, message)
+            .unwrap_or_else(|e| error!("could not send message to client {client}: {e}"));
+        transport
+            .disconnect(*client)
+            .unwrap_or_else(|e| error!("could not disconnect client {client}: {e}"));
+        if clients.0.contains(client) {
+            clients.0.retain(|c| c != client);
+            writer.send(ClientConnectionEvent::Disconnected(*client));
+        }
+    }
+}
+
 fn update_clients(
     mut clients: ResMut<Clients>,
-    server: ResMut<QuinnetServer>,
+    transport: ServerTransport,
     mut writer: EventWriter<ClientConnectionEvent>,
 ) {
-    let updated_clients = server.endpoint().clients();
+    let updated_clients = transport.clients();
     for client in &updated_clients {
         if !clients.0.contains(client) {
             writer.send(ClientConnectionEvent::Connected(*client));
@@ -120,89 +538,564 @@ fn update_clients(
     clients.0 = updated_clients;
 }
 
+#[allow(clippy::too_many_arguments)]
 fn handle_connection_events(
     mut reader: EventReader<ClientConnectionEvent>,
-    mut server: ResMut<QuinnetServer>,
-    time_toggle: Res<ToggleTime>,
+    mut transport: ServerTransport,
+    (time_toggle, tick_rate): (Res<ToggleTime>, Res<TickRateTracker>),
     bodies_config: Res<BodiesConfig>,
+    mut tracker: ResMut<BandwidthTracker>,
+    mut roles: ResMut<ClientRoles>,
+    (mut names, mut interests): (ResMut<ClientNames>, ResMut<ClientInterests>),
+    max_players: Res<MaxPlayers>,
+    seed: Res<WorldSeed>,
+    bodies: Query<&BodyInfo>,
 ) -> color_eyre::Result<()> {
-    let endpoint = server.endpoint_mut();
     for event in reader.read() {
         match event {
             ClientConnectionEvent::Connected(id) => {
                 info!("Client connected with id {id}");
-                endpoint.send_message_on(
+                transport.send_to(
                     *id,
                     ServerChannel::Once,
-                    ServerMessage::InitialData(InitialData {
+                    &ServerMessage::InitialData(InitialData {
                         bodies_config: bodies_config.clone(),
-                        toggle_time: time_toggle.0,
+                        bodies_hash: bodies_hash(bodies.iter().map(|BodyInfo(data)| data)),
+                        toggle_time: time_toggle.0 && !tick_rate.throttling(),
+                        seed: *seed,
                     }),
-                )?
+                )?;
+                let role = roles.role_for_new_client(*max_players);
+                info!("Client {id} joined as {role}");
+                roles.0.insert(*id, role);
+                transport.send_to(*id, ServerChannel::Once, &ServerMessage::RoleAssigned(role))?
             }
             ClientConnectionEvent::Disconnected(id) => {
                 info!("Client disconnected with id {id}");
+                tracker.0.remove(id);
+                roles.0.remove(id);
+                names.0.remove(id);
+                interests.0.remove(id);
             }
         }
     }
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn handle_client_messages(
-    mut server: ResMut<QuinnetServer>,
+    mut transport: ServerTransport,
     mut ships: ResMut<ShipsMapping>,
     mut command: Commands,
-    bodies: Query<(&Position, &HillRadius, &BodyInfo)>,
+    (bodies, all_bodies): (Query<(&Position, &HillRadius, &BodyInfo)>, Query<&BodyInfo>),
     main_body: Query<&BodyInfo, With<PrimaryBody>>,
     mapping: Res<BodiesMapping>,
+    roles: Res<ClientRoles>,
+    mut owners: ResMut<ShipOwners>,
+    mut velocities: Query<&mut Velocity>,
+    mut sandbox_requests: EventWriter<SandboxRequest>,
+    mut generator: ResMut<IdGenerator>,
+    (mut names, mut interests): (ResMut<ClientNames>, ResMut<ClientInterests>),
+    (ban_list, budget, entities): (Res<BanList>, Res<WorldBudget>, &Entities),
+    mut kicks: EventWriter<KickEvent>,
+    (time, real_time): (Res<GameTime>, Res<Time<Real>>),
 ) {
-    let mut endpoint = server.endpoint_mut();
-    for client_id in endpoint.clients() {
-        while let Some(message) = endpoint.try_receive_message_from::<ClientMessage>(client_id) {
-            match message.1 {
-                ClientMessage::CreateShipMsg(msg) => {
-                    ships.0.entry(msg.info.id).or_insert({
-                        let alpha = main_body.single().0.id;
-                        println!("{:#?}", alpha);
-                        let influence = Influenced::new(&msg.pos, &bodies, mapping.as_ref(), alpha);
-                        command
-                            .spawn((
-                                msg.info.clone(),
-                                msg.acceleration,
-                                influence,
-                                msg.pos,
-                                msg.velocity,
-                                TransformBundle::from_transform(Transform::from_xyz(0., 0., 1.)),
-                                ClearOnUnload,
-                            ))
-                            .id()
+    let mut requests = Vec::new();
+    for client_id in transport.clients() {
+        let role = roles.0.get(&client_id).copied().unwrap_or_default();
+        while let Some(message) = transport.try_receive(client_id) {
+            if !names.0.contains_key(&client_id) && !matches!(message, ClientMessage::Hello { .. })
+            {
+                warn!("Ignored request from client {client_id} before its handshake");
+                continue;
+            }
+            if let Err(reason) = check_permission(role, (&message).into(), client_id, &owners) {
+                warn!("Denied request from client {client_id}: {reason}");
+                let denied_ships = match &message {
+                    ClientMessage::CreateShipMsg(msg) => vec![msg.info.id],
+                    ClientMessage::CreateShips(batch) => {
+                        batch.iter().map(|msg| msg.info.id).collect()
+                    }
+                    _ => Vec::new(),
+                };
+                for id in denied_ships {
+                    transport
+                        .send_to(
+                            client_id,
+                            ServerChannel::Once,
+                            &ServerMessage::ShipCreateRejected {
+                                id,
+                                reason: ShipRejectionReason::Denied(reason),
+                            },
+                        )
+                        .unwrap_or_else(|e| {
+                            error!("could not send message to client {client_id}: {e}")
+                        });
+                }
+                transport
+                    .send_to(
+                        client_id,
+                        ServerChannel::Once,
+                        &ServerMessage::Denied(reason),
+                    )
+                    .unwrap_or_else(|e| {
+                        error!("could not send message to client {client_id}: {e}")
+                    });
+                continue;
+            }
+            match message {
+                ClientMessage::Hello { name } => {
+                    if ban_list.is_banned(&name) {
+                        info!("Rejected banned player {name} (client {client_id})");
+                        kicks.send(KickEvent {
+                            client: client_id,
+                            message: ServerMessage::Rejected(BANNED_REASON.into()),
+                        });
+                        break;
+                    }
+                    info!("Client {client_id} is player {name}");
+                    names.0.insert(client_id, name);
+                }
+                ClientMessage::CreateShipMsg(_) | ClientMessage::CreateShips(_) => {
+                    let batch = match message {
+                        ClientMessage::CreateShipMsg(msg) => vec![msg],
+                        ClientMessage::CreateShips(batch) => batch,
+                        _ => unreachable!(),
+                    };
+                    match check_spawn_orbits(batch, &bodies, &mapping) {
+                        Ok(batch) => requests.push((client_id, batch)),
+                        Err(rejections) => {
+                            for (id, reason) in rejections {
+                                warn!("Rejected ship {id} from client {client_id}: {reason}");
+                                transport
+                                    .send_to(
+                                        client_id,
+                                        ServerChannel::Once,
+                                        &ServerMessage::ShipCreateRejected { id, reason },
+                                    )
+                                    .unwrap_or_else(|e| {
+                                        error!("could not send message to client {client_id}: {e}")
+                                    });
+                            }
+                        }
+                    }
+                }
+                ClientMessage::Thrust { id, dv } => {
+                    if let Some(mut velocity) =
+                        ships.0.get(&id).and_then(|e| velocities.get_mut(*e).ok())
+                    {
+                        velocity.0 += dv;
+                    }
+                }
+                ClientMessage::SandboxManeuver {
+                    ship,
+                    nodes,
+                    preview_ticks,
+                } => {
+                    sandbox_requests.send(SandboxRequest {
+                        client: client_id,
+                        ship,
+                        nodes: nodes.into_iter().collect(),
+                        preview_ticks: preview_ticks.min(MAX_PREVIEW_TICKS),
                     });
                 }
+                ClientMessage::ChangeStage(stage) => {
+                    let _ = transport
+                        .broadcast(ServerChannel::Once, &ServerMessage::ChangeStage(stage));
+                }
+                ClientMessage::RequestBodies => {
+                    info!("Client {client_id} built other bodies, sending the ones of the server");
+                    let data = all_bodies.iter().map(|BodyInfo(data)| data.clone());
+                    transport
+                        .send_to(
+                            client_id,
+                            ServerChannel::Once,
+                            &ServerMessage::Bodies(data.collect()),
+                        )
+                        .unwrap_or_else(|e| {
+                            error!("could not send message to client {client_id}: {e}")
+                        });
+                }
+                ClientMessage::TimeSyncRequest {
+                    client_local_tick, ..
+                } => transport
+                    .send_to(
+                        client_id,
+                        ServerChannel::Once,
+                        &ServerMessage::TimeSyncResponse {
+                            client_local_tick,
+                            server_tick: time.simtick,
+                            server_time_ns: real_time.elapsed().as_nanos() as u64,
+                        },
+                    )
+                    .unwrap_or_else(|e| {
+                        error!("could not send message to client {client_id}: {e}")
+                    }),
+                ClientMessage::InterestUpdate {
+                    focus,
+                    radius,
+                    always,
+                } => {
+                    interests.0.insert(
+                        client_id,
+                        InterestRegion {
+                            focus,
+                            radius,
+                            always,
+                        },
+                    );
+                }
+            }
+        }
+    }
+    let (accepted, rejected, renamed) = resolve_ship_creations(&ships, requests, &mut generator);
+    for (client_id, from, to) in renamed {
+        info!("Renamed ship {from} from client {client_id} to {to}");
+        transport
+            .send_to(
+                client_id,
+                ServerChannel::Once,
+                &ServerMessage::ShipRenamed { from, to },
+            )
+            .unwrap_or_else(|e| error!("could not send message to client {client_id}: {e}"));
+    }
+    for (client_id, id, reason) in rejected {
+        warn!("Rejected ship {id} from client {client_id}: {reason}");
+        transport
+            .send_to(
+                client_id,
+                ServerChannel::Once,
+                &ServerMessage::ShipCreateRejected { id, reason },
+            )
+            .unwrap_or_else(|e| error!("could not send message to client {client_id}: {e}"));
+    }
+    for (client_id, msg) in accepted {
+        if let Err(kind) = budget.check_spawn(ships.0.len(), entities.len() as usize) {
+            let reason = PermissionDenied::BudgetExceeded(kind);
+            warn!(
+                "Refused ship {} from client {client_id}: {reason}",
+                msg.info.id
+            );
+            for message in [
+                ServerMessage::ShipCreateRejected {
+                    id: msg.info.id,
+                    reason: ShipRejectionReason::Denied(reason),
+                },
+                ServerMessage::Denied(reason),
+            ] {
+                transport
+                    .send_to(client_id, ServerChannel::Once, &message)
+                    .unwrap_or_else(|e| {
+                        error!("could not send message to client {client_id}: {e}")
+                    });
+            }
+            continue;
+        }
+        let influence =
+            Influenced::new(&msg.pos, &bodies, mapping.as_ref(), main_body.single().0.id);
+        let entity = command
+            .spawn((
+                msg.info.clone(),
+                msg.acceleration,
+                influence,
+                msg.pos,
+                msg.velocity,
+                TransformBundle::from_transform(Transform::from_xyz(0., 0., 1.)),
+                ClearOnUnload,
+            ))
+            .id();
+        ships.0.insert(msg.info.id, entity);
+        owners.0.insert(msg.info.id, client_id);
+    }
+}
+
+/// Simulates the requested maneuvers from a copy of the state of each ship, and sends the
+/// resulting positions to the client that asked for them. At most [PREVIEW_TICKS_PER_UPDATE] are
+/// computed per update, see [PendingSandboxes]
+#[allow(clippy::too_many_arguments)]
+fn run_maneuver_sandboxes(
+    mut requests: EventReader<SandboxRequest>,
+    mut pending: ResMut<PendingSandboxes>,
+    mut transport: ServerTransport,
+    ships: Res<ShipsMapping>,
+    query: Query<(&Position, &Velocity, &Acceleration, &Influenced)>,
+    mut bodies: Query<(&EllipticalOrbit, &BodyInfo, &HillRadius)>,
+    mapping: Res<BodiesMapping>,
+    game_time: Res<GameTime>,
+) {
+    for request in requests.read() {
+        pending.push(request.clone());
+    }
+    for request in pending.take_batch() {
+        let Some((pos, speed, acc, influence)) =
+            ships.0.get(&request.ship).and_then(|e| query.get(*e).ok())
+        else {
+            continue;
+        };
+        let start = PredictionStart {
+            pos: pos.0,
+            speed: speed.0,
+            acc: acc.current,
+            simtick: game_time.simtick,
+        };
+        let predictions = start.compute_predictions(
+            (request.preview_ticks * SIMTICKS_PER_TICK) as usize,
+            influence,
+            None,
+            &mut bodies.as_query_lens(),
+            &mapping.0,
+            &request.nodes,
+        );
+        let client = request.client;
+        transport
+            .send_to(
+                client,
+                ServerChannel::Once,
+                &ServerMessage::ManeuverPreview {
+                    ship: request.ship,
+                    positions: sample_preview(start.simtick, &predictions, request.preview_ticks),
+                    // Ships don't carry fuel yet, so maneuvers are free
+                    fuel_remaining: f64::INFINITY,
+                },
+            )
+            .unwrap_or_else(|e| error!("could not send message to client {client}: {e}"));
+    }
+}
+
+/// Keeps about [PREVIEW_SAMPLES] evenly spaced predictions (one every `preview_ticks / 50` ticks),
+/// along with their simtick
+fn sample_preview(
+    start: u64,
+    predictions: &[(DVec3, DVec3)],
+    preview_ticks: u64,
+) -> Vec<(u64, Position)> {
+    let step = (preview_ticks / PREVIEW_SAMPLES).max(1) * SIMTICKS_PER_TICK;
+    // The prediction at index i is at simtick start + i + 1
+    (step..=predictions.len() as u64)
+        .step_by(step as usize)
+        .map(|offset| (start + offset, Position(predictions[offset as usize - 1].0)))
+        .collect()
+}
+
+/// Applies the checks of the client to ships spawned on a circular orbit around a body, ships
+/// spawned from raw coordinates are accepted whatever their state
+fn check_spawn_orbit(
+    msg: &CreateShipMsg,
+    bodies: &Query<(&Position, &HillRadius, &BodyInfo)>,
+    mapping: &BodiesMapping,
+) -> Result<(), ShipRejectionReason> {
+    let Some(orbit) = msg.info.spawn_orbit else {
+        return Ok(());
+    };
+    let Some((_, HillRadius(hill), BodyInfo(data))) =
+        mapping.0.get(&orbit.host).and_then(|&e| bodies.get(e).ok())
+    else {
+        warn!(
+            "ship {} orbits the unknown body {}, its orbit is not checked",
+            msg.info.id, orbit.host
+        );
+        return Ok(());
+    };
+    check_spawn_altitude(orbit.altitude, data.radius, *hill)
+        .map_err(ShipRejectionReason::InvalidOrbit)
+}
+
+/// Checks the spawn orbits of a batch of ships, which is rejected as a whole if any of them is
+/// invalid, with the reason of each ship
+fn check_spawn_orbits(
+    batch: Vec<CreateShipMsg>,
+    bodies: &Query<(&Position, &HillRadius, &BodyInfo)>,
+    mapping: &BodiesMapping,
+) -> Result<Vec<CreateShipMsg>, Vec<(ShipID, ShipRejectionReason)>> {
+    let checks: Vec<_> = batch
+        .iter()
+        .map(|msg| check_spawn_orbit(msg, bodies, mapping))
+        .collect();
+    if checks.iter().all(Result::is_ok) {
+        return Ok(batch);
+    }
+    Err(batch
+        .iter()
+        .zip(checks)
+        .map(|(msg, check)| {
+            let reason = check.err().unwrap_or(ShipRejectionReason::BatchRejected);
+            (msg.info.id, reason)
+        })
+        .collect())
+}
+
+/// Splits ship creation requests (batches of ships sent by a client) between the ones that can be
+/// spawned and the ones that conflict with an existing ship or an earlier request.
+///
+/// Conflicting ships whose id was generated by the client get a new id from the server's
+/// [IdGenerator] instead, and are returned along with their former id.
+/// Otherwise a batch is rejected as a whole if any of its ships conflicts, and the client that
+/// sent it is told about every ship of the batch, under the id it gave them
+#[allow(clippy::type_complexity)]
+fn resolve_ship_creations(
+    ships: &ShipsMapping,
+    requests: Vec<(ClientId, Vec<CreateShipMsg>)>,
+    generator: &mut IdGenerator,
+) -> (
+    Vec<(ClientId, CreateShipMsg)>,
+    Vec<(ClientId, ShipID, ShipRejectionReason)>,
+    Vec<(ClientId, ShipID, ShipID)>,
+) {
+    let mut accepted: Vec<(ClientId, CreateShipMsg)> = Vec::new();
+    let mut rejected = Vec::new();
+    let mut renamed = Vec::new();
+    for (client_id, mut batch) in requests {
+        let ids: Vec<_> = batch.iter().map(|msg| msg.info.id).collect();
+        let mut conflicts = Vec::new();
+        let mut renames = Vec::new();
+        for i in 0..batch.len() {
+            let id = batch[i].info.id;
+            let taken = |id: &ShipID| {
+                ships.0.contains_key(id)
+                    || accepted.iter().any(|(_, m)| m.info.id == *id)
+                    || batch[..i].iter().any(|m| m.info.id == *id)
+            };
+            if !taken(&id) {
+                continue;
             }
+            if batch[i].generated_id {
+                let new_id = generator
+                    .generate(|id| taken(id) || batch[i + 1..].iter().any(|m| m.info.id == *id));
+                batch[i].info.id = new_id;
+                renames.push((client_id, id, new_id));
+            } else {
+                conflicts.push(id);
+            }
+        }
+        if conflicts.is_empty() {
+            accepted.extend(batch.into_iter().map(|msg| (client_id, msg)));
+            renamed.extend(renames);
+        } else {
+            rejected.extend(ids.into_iter().map(|id| {
+                let reason = if conflicts.contains(&id) {
+                    ShipRejectionReason::AlreadyExists
+                } else {
+                    ShipRejectionReason::BatchRejected
+                };
+                (client_id, id, reason)
+            }));
         }
     }
+    (accepted, rejected, renamed)
+}
+
+fn reset_bandwidth_window(
+    mut timer: ResMut<BandwidthWindowTimer>,
+    time: Res<Time>,
+    mut tracker: ResMut<BandwidthTracker>,
+) {
+    timer.0.tick(time.delta());
+    if timer.0.finished() {
+        tracker.0.values_mut().for_each(|bytes| *bytes = 0);
+    }
+}
+
+/// Splits the ships between those the client follows at full rate (its own ships and those of its
+/// [InterestRegion]) and the other ones, which are only sent on coarse updates. Without a region,
+/// or with a focus on an unknown body, every ship is sent at full rate
+fn interest_update(
+    simtick: u64,
+    ships: &[(ShipID, Position, Velocity)],
+    interest: Option<&InterestRegion>,
+    client: ClientId,
+    coarse: bool,
+    owners: &ShipOwners,
+    body_position: impl Fn(BodyID) -> Option<DVec3>,
+) -> PeriodicUpdate {
+    let center = interest.and_then(|region| match region.focus {
+        InterestFocus::Body(id) => body_position(id),
+        InterestFocus::Position(pos) => Some(pos),
+    });
+    let (Some(region), Some(center)) = (interest, center) else {
+        return PeriodicUpdate {
+            time: simtick,
+            ships: ships.to_vec(),
+            coarse_ships: Vec::new(),
+        };
+    };
+    let (followed, others): (Vec<_>, Vec<_>) = ships.iter().partition(|(id, pos, _)| {
+        owners.0.get(id) == Some(&client)
+            || region.always.contains(id)
+            || pos.0.distance(center) <= region.radius
+    });
+    PeriodicUpdate {
+        time: simtick,
+        ships: followed,
+        coarse_ships: if coarse { others } else { Vec::new() },
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn send_periodic_updates(
     mut timer: ResMut<PeriodicUpdatesTimer>,
     time: Res<Time>,
-    mut server: ResMut<QuinnetServer>,
+    mut transport: ServerTransport,
     game_time: Res<GameTime>,
     query: Query<(&ShipInfo, &Position, &Velocity)>,
+    clients: Res<Clients>,
+    mut tracker: ResMut<BandwidthTracker>,
+    limit: Res<PerClientBandwidthLimitBytesPerSec>,
+    (interests, owners): (Res<ClientInterests>, Res<ShipOwners>),
+    (mapping, positions): (Res<BodiesMapping>, Query<&Position, With<BodyInfo>>),
+    mut broadcasts: Local<u64>,
 ) {
     timer.0.tick(time.delta());
     if timer.0.finished() {
-        let mut alpha = Vec::<(ShipID, Position, Velocity)>::new();
-        for (id, pos, velocity) in query.iter() {
-            alpha.push((id.id, *pos, *velocity));
-        }
-        server.endpoint_mut().try_broadcast_message_on(
-            ServerChannel::PeriodicUpdates,
-            ServerMessage::PeriodicUpdate(PeriodicUpdate {
-                time: game_time.simtick,
-                ships: alpha,
-            }), //ServerMessage::UpdateTime(game_time.simtick),
-        );
+        let ships: Vec<_> = query
+            .iter()
+            .map(|(info, pos, velocity)| (info.id, *pos, *velocity))
+            .collect();
+        let coarse = broadcasts.is_multiple_of(COARSE_UPDATE_PERIOD);
+        *broadcasts += 1;
+        let body_position = |id| {
+            mapping
+                .0
+                .get(&id)
+                .and_then(|&e| positions.get(e).ok())
+                .map(|p| p.0)
+        };
+        // Clients without a region all get the same update, serialized once
+        let mut full_payload = None;
+        for &client in &clients.0 {
+            let interest = interests.0.get(&client);
+            let payload = match (interest, &full_payload) {
+                (None, Some(payload)) => Vec::clone(payload),
+                _ => {
+                    let update = interest_update(
+                        game_time.simtick,
+                        &ships,
+                        interest,
+                        client,
+                        coarse,
+                        &owners,
+                        body_position,
+                    );
+                    match bincode::serialize(&ServerMessage::PeriodicUpdate(update)) {
+                        Ok(payload) => payload,
+                        Err(error) => {
+                            error!("could not serialize periodic update: {error}");
+                            return;
+                        }
+                    }
+                }
+            };
+            if interest.is_none() && full_payload.is_none() {
+                full_payload = Some(payload.clone());
+            }
+            if tracker.try_consume(client, payload.len(), limit.0) {
+                transport.send_payload_to(client, ServerChannel::PeriodicUpdates, payload);
+            } else {
+                debug!(
+                    "skipping periodic update for client {client}: bandwidth limit of {} bytes per second reached",
+                    limit.0
+                );
+            }
+        }
     }
 }
 #[derive(Resource)]
@@ -236,6 +1129,35 @@ enum Command {
     GetBodysData,
     Test,
     TestSetPos,
+    CompareIntegrators,
+    PerturbationLog,
+    PerturbationSummary,
+    TickRate,
+    SetMaxSpeed,
+    BandwidthStats,
+    Role,
+    MaxPlayers,
+    Record,
+    Seed,
+    HashWorld,
+    Optimize,
+    Kick,
+    Ban,
+    Unban,
+    Bans,
+    StationKeeping,
+    LogLevel,
+    CheckBodies,
+    Migrate,
+    Budget,
+    Waypoint,
+    FleetReport,
+    FleetReportInterval,
+    Integration,
+    Tle,
+    Epoch,
+    Gravity,
+    Beacon,
 }
 
 #[derive(Resource)]
@@ -303,6 +1225,35 @@ fn handle_stdin(
                 "get_bodys_data" => next_command.set(Command::GetBodysData),
                 "test" => next_command.set(Command::Test),
                 "test_set_pos" => next_command.set(Command::TestSetPos),
+                "compare_integrators" => next_command.set(Command::CompareIntegrators),
+                "perturbation_log" => next_command.set(Command::PerturbationLog),
+                "perturbation_summary" => next_command.set(Command::PerturbationSummary),
+                "tick_rate" => next_command.set(Command::TickRate),
+                "set_max_speed" => next_command.set(Command::SetMaxSpeed),
+                "bandwidth_stats" => next_command.set(Command::BandwidthStats),
+                "role" => next_command.set(Command::Role),
+                "max_players" => next_command.set(Command::MaxPlayers),
+                "record" => next_command.set(Command::Record),
+                "seed" => next_command.set(Command::Seed),
+                "hash_world" => next_command.set(Command::HashWorld),
+                "optimize" => next_command.set(Command::Optimize),
+                "kick" => next_command.set(Command::Kick),
+                "ban" => next_command.set(Command::Ban),
+                "unban" => next_command.set(Command::Unban),
+                "bans" => next_command.set(Command::Bans),
+                "station_keeping" => next_command.set(Command::StationKeeping),
+                "log_level" => next_command.set(Command::LogLevel),
+                "check_bodies" => next_command.set(Command::CheckBodies),
+                "migrate" => next_command.set(Command::Migrate),
+                "budget" => next_command.set(Command::Budget),
+                "waypoint" => next_command.set(Command::Waypoint),
+                "fleet_report" => next_command.set(Command::FleetReport),
+                "fleet_report_interval" => next_command.set(Command::FleetReportInterval),
+                "integration" => next_command.set(Command::Integration),
+                "tle" => next_command.set(Command::Tle),
+                "epoch" => next_command.set(Command::Epoch),
+                "gravity" => next_command.set(Command::Gravity),
+                "beacon" => next_command.set(Command::Beacon),
                 _ => next_command.set(Command::None),
             }
             next_state.set(Reading::NotReading);
@@ -316,7 +1267,6 @@ fn handle_command(
     command: Res<State<Command>>,
     mut next_state: ResMut<NextState<Command>>,
     mut toggle_time: ResMut<ToggleTime>,
-    mut server: ResMut<QuinnetServer>,
     mut sim_step_size: ResMut<SimStepSize>,
     mut arg: ResMut<Arguments>,
     ships: Res<ShipsMapping>,
@@ -326,13 +1276,44 @@ fn handle_command(
 ) {
     match command.get() {
         Command::Help => help_command(),
-        Command::TimeStart => toggle_time_command(toggle_time, server),
+        Command::TimeStart => toggle_time_command(toggle_time),
         Command::TimeScale => set_time_scale(sim_step_size, arg),
         Command::ListShips => list_ships_command(ships),
         Command::GetShipData => get_ship_data(ships, arg, query),
         Command::GetBodysData => get_bodys_data(bodies),
         Command::Test => test(pos_query_mut),
         //Command::TestSetPos => test_set_pos(pos_query_mut, ships, arg),
+        // These commands are handled by their own systems
+        Command::TestSetPos
+        | Command::CompareIntegrators
+        | Command::PerturbationLog
+        | Command::PerturbationSummary
+        | Command::TickRate
+        | Command::SetMaxSpeed
+        | Command::BandwidthStats
+        | Command::Role
+        | Command::MaxPlayers
+        | Command::Record
+        | Command::Seed
+        | Command::HashWorld
+        | Command::Optimize
+        | Command::Kick
+        | Command::Ban
+        | Command::Unban
+        | Command::Bans
+        | Command::StationKeeping
+        | Command::LogLevel
+        | Command::CheckBodies
+        | Command::Migrate
+        | Command::Budget
+        | Command::Waypoint
+        | Command::FleetReport
+        | Command::FleetReportInterval
+        | Command::Integration
+        | Command::Tle
+        | Command::Epoch
+        | Command::Gravity
+        | Command::Beacon => {}
         _ => println!("Command is not implemented"),
     }
     next_state.set(Command::None);
@@ -344,21 +1325,76 @@ fn help_command() {
     help : print the list of all available command
     toggle_time : start the simulation or pause it if already started
     time_scale : set the timescale to first argument, if no argument print current timescale (stepsize)
+    tick_rate : set the number of physics updates per second to first argument while keeping the game speed, at most one per simtick, if no argument print current rate
+    set_max_speed [N|none] : pause the time whenever more than N ticks ran during the last real second, if no argument print the current limit and speed
     list_ships : print the list of ships
+    bandwidth_stats : print the bytes sent to each client during the current second
+    role [set CLIENT_ID ROLE] : set the role (admin, player or spectator) of a client, if no argument print the role of each client
+    max_players [N|none] : set the number of clients that can join as players before the next ones join as spectators, if no argument print the current limit
+    kick CLIENT_ID|NAME [REASON] : close the connection of a client, or of all the clients of a player
+    ban CLIENT_ID|NAME : kick a player and prevent them from joining again, even after a restart
+    unban NAME : allow a banned player to join again
+    bans : print the list of banned players
     get_ship_data ID : print the data of the ship with id ID
+    record ID on|off [K] : start (sampling every K ticks, default 10) or stop recording the history of the ship with id ID
+    seed [VALUE] : set the seed of the random generation (applies to content generated afterwards), if no argument print the current seed
+    log_level [LEVEL [TARGET]] : set the level (error, warn, info, debug or trace) of the logs, only for TARGET if given, if no argument print the current filter
+    check_bodies : check the consistency of the bundled bodies data, and print the issues
+    migrate PATH : rewrite a saved file (trajectory, user bodies, ban list or waypoints) in the current version of its format, keeping a copy of the original in PATH.bak
+    budget [set KIND HARD [SOFT]] : set the caps on the number of objects of a kind (entities, ships, predictions or log_entries), if no argument print the number of objects of each kind and their caps
+    hash_world : print a checksum of the state of the simulation, to compare two servers
+    optimize ID TARGET N : search in the background, over N generations, the burns bringing the ship with id ID to the body or waypoint TARGET (id or name), and add them to its trajectory
+    waypoint add NAME fixed X Y Z|body BODY [DX DY DZ]|ship ID SIMTICK : add a waypoint at a fixed position, relative to a body, or where a ship will be at a simtick (replacing the waypoint with the same name)
+    waypoint remove NAME : remove a waypoint
+    waypoint [list] : print the waypoints and their positions
+    fleet_report : print the state, orbit, autopilot and owner of every ship
+    fleet_report_interval [SECONDS|off] : write the fleet report to fleet_log_TIMESTAMP.txt in the game directory every SECONDS seconds, if no argument print the current interval
+    integration [leapfrog|sgp4] : set how the ships are moved, sgp4 moving the ships with a TLE along it, if no argument print the current method
+    tle ID PATH : attach to the ship with id ID the TLE read from the file PATH (in the two or three line format), which is used with the sgp4 integration
+    epoch [DATE] : set the Julian date of the simtick 0, which places the TLEs in the game time, if no argument print the current date and the one of the simtick 0
+    beacon ID [FREQUENCY|off] : make the ship with id ID a radio beacon emitting at FREQUENCY MHz, which the other ships use for their navigation fixes, or stop it with off
+    gravity [point|j2|harmonics DEGREE] : set how the gravity of the bodies with known coefficients is computed, if no argument print the current model
     get_bodies_data : print data of all bodys
+    compare_integrators ID N : integrate the ship with id ID for N ticks using both leapfrog and rk4, and print their divergence
+    perturbation_log ID [N|off] : print the N (default 10) largest acceleration contributions logged for the ship with id ID, starting to log them if they were not, or stop logging them with off
+    perturbation_summary ID : print the total acceleration contribution of each body on the ship with id ID over the last 1000 simticks, starting to log them if they were not
+    station_keeping ID : print the delta-v needed each year by the ship with id ID to stay on its orbit, by perturbation
     test
     test_set_pos"
     );
 }
 
-fn toggle_time_command(mut toggle_time: ResMut<ToggleTime>, mut server: ResMut<QuinnetServer>) {
+fn toggle_time_command(mut toggle_time: ResMut<ToggleTime>) {
     println!("toggling time");
     toggle_time.0 = !toggle_time.0;
-    let _ = server.endpoint_mut().broadcast_message_on(
-        ServerChannel::Once,
-        ServerMessage::ToggleTime(toggle_time.0),
-    );
+}
+
+/// Time for which the [MaxSimSpeed] must keep holding or releasing the simulation before the
+/// clients are told. At the limit, the simulation is held and released every few frames, which
+/// the clients don't need to follow: the periodic updates bring them to the simtick of the server
+pub const SPEED_LIMIT_BROADCAST_DELAY: Duration = Duration::from_millis(100);
+
+/// Tells the clients whether the simulation runs, as soon as the players toggle the time, and once
+/// the [MaxSimSpeed] held or released it for [SPEED_LIMIT_BROADCAST_DELAY]
+fn broadcast_time_running(
+    toggle_time: Res<ToggleTime>,
+    tick_rate: Res<TickRateTracker>,
+    real_time: Res<Time<Real>>,
+    mut transport: ServerTransport,
+    mut sent: Local<Option<bool>>,
+    mut throttling_since: Local<Option<(bool, Duration)>>,
+) {
+    let (throttling, now) = (tick_rate.throttling(), real_time.elapsed());
+    let since = match *throttling_since {
+        Some((state, since)) if state == throttling => since,
+        _ => throttling_since.insert((throttling, now)).1,
+    };
+    let settled = toggle_time.is_changed() || now - since >= SPEED_LIMIT_BROADCAST_DELAY;
+    let running = toggle_time.0 && !throttling;
+    if *sent != Some(running) && settled {
+        let _ = transport.broadcast(ServerChannel::Once, &ServerMessage::ToggleTime(running));
+        *sent = Some(running);
+    }
 }
 
 fn set_time_scale(mut sim_step_size: ResMut<SimStepSize>, mut arguments: ResMut<Arguments>) {
@@ -367,6 +1403,7 @@ fn set_time_scale(mut sim_step_size: ResMut<SimStepSize>, mut arguments: ResMut<
         Some(arg1) => {
             let tmp: Result<u64, _> = arg1.parse();
             match tmp {
+                Ok(0) => println!("timescale must be positive"),
                 Ok(tmp) => sim_step_size.0 = tmp,
                 Err(error) => println!("timescale is a u64, Error : {}", error),
             }
@@ -376,59 +1413,900 @@ fn set_time_scale(mut sim_step_size: ResMut<SimStepSize>, mut arguments: ResMut<
     println!("Current timescale = {}", sim_step_size.0)
 }
 
-fn list_ships_command(ships: Res<ShipsMapping>) {
-    println!("ships list : {:?}", ships.0.keys())
+fn set_tick_rate(
+    mut rate: ResMut<PhysicsRate>,
+    arguments: Res<Arguments>,
+    sim_step_size: Res<SimStepSize>,
+    pacing: Res<StepPacing>,
+) {
+    let simticks_per_update = pacing.current(sim_step_size.0, sim_step_size.last_changed());
+    let max = max_physics_rate(simticks_per_update, rate.0);
+    match arguments.0.split_whitespace().next().map(str::parse::<f64>) {
+        Some(Ok(hz)) if hz > max => println!(
+            "tick rate must be at most {} Hz at this game speed, updates can't last less than a simtick",
+            max
+        ),
+        Some(Ok(hz)) if hz > 0. && hz.is_finite() => rate.0 = hz,
+        Some(Ok(hz)) => println!("tick rate must be positive, got {}", hz),
+        Some(Err(error)) => println!("tick rate is a f64, Error : {}", error),
+        None => println!(
+            "Current tick rate = {} Hz, timescale = {}",
+            rate.0, sim_step_size.0
+        ),
+    }
 }
 
-fn get_ship_data(
-    ships: Res<ShipsMapping>,
-    arguments: ResMut<Arguments>,
-    query: Query<(&Position, &Velocity, &Acceleration, &Influenced)>,
+fn set_max_speed_command(
+    arguments: Res<Arguments>,
+    mut max_speed: ResMut<MaxSimSpeed>,
+    tracker: Res<TickRateTracker>,
 ) {
-    let mut arg = arguments.0.split_whitespace();
-    match arg.next() {
-        Some(arg1) => {
-            let tmp: Result<ShipID, _> = arg1.parse();
-            match tmp {
-                Ok(tmp) => {
-                    match ships.0.get(&tmp) {
-                        Some(thing) => println!("data : {:#?}", query.get(*thing)),
-                        None => {
-                            println!("wrong ID");
-                        }
-                    };
-                }
-                Err(error) => println!("not an id, Error : {}", error),
+    match arguments.0.split_whitespace().next() {
+        Some("none") => max_speed.ticks_per_second = None,
+        Some(arg) => match arg.parse::<u64>() {
+            Ok(0) => println!("max speed must be positive"),
+            Ok(max) => max_speed.ticks_per_second = Some(max),
+            Err(error) => println!("max speed is a u64, Error : {}", error),
+        },
+        None => {}
+    }
+    match max_speed.ticks_per_second {
+        Some(max) => println!("Current max speed = {} ticks per second", max),
+        None => println!("No limit on the speed of the simulation"),
+    }
+    println!(
+        "Current speed = {:.1} ticks per second",
+        tracker.ticks_per_second()
+    );
+}
+
+fn bandwidth_stats(
+    tracker: Res<BandwidthTracker>,
+    limit: Res<PerClientBandwidthLimitBytesPerSec>,
+    clients: Res<Clients>,
+) {
+    println!("bandwidth limit : {} bytes per second per client", limit.0);
+    for client in &clients.0 {
+        println!(
+            "client {} : {} bytes",
+            client,
+            tracker.0.get(client).copied().unwrap_or_default()
+        );
+    }
+}
+
+fn role_command(
+    arguments: Res<Arguments>,
+    mut roles: ResMut<ClientRoles>,
+    mut transport: ServerTransport,
+) {
+    let mut args = arguments.0.split_whitespace();
+    match (args.next(), args.next(), args.next()) {
+        (None, ..) => {
+            for (client, role) in &roles.0 {
+                println!("client {} : {}", client, role);
             }
         }
-        None => {}
+        (Some("set"), Some(client), Some(role)) => {
+            let client = match client.parse::<ClientId>() {
+                Ok(client) if roles.0.contains_key(&client) => client,
+                Ok(client) => return println!("no client with id {}", client),
+                Err(error) => return println!("client id is a u64, Error : {}", error),
+            };
+            let role = match role.parse::<Role>() {
+                Ok(role) => role,
+                Err(error) => return println!("{}", error),
+            };
+            roles.0.insert(client, role);
+            println!("client {} is now {}", client, role);
+            transport
+                .send_to(
+                    client,
+                    ServerChannel::Once,
+                    &ServerMessage::RoleAssigned(role),
+                )
+                .unwrap_or_else(|e| error!("could not send message to client {client}: {e}"));
+        }
+        _ => println!("usage : role set CLIENT_ID ROLE"),
     }
 }
 
-fn get_bodys_data(bodies: Query<(&Position, &HillRadius, &BodyInfo)>) {
-    for (i, (pos, hill, bodyinfo)) in bodies.iter().enumerate() {
-        println!("{} - {:#?}", i, bodyinfo)
+fn max_players_command(arguments: Res<Arguments>, mut max_players: ResMut<MaxPlayers>) {
+    match arguments.0.split_whitespace().next() {
+        Some("none") => max_players.0 = None,
+        Some(arg) => match arg.parse::<usize>() {
+            Ok(max) => max_players.0 = Some(max),
+            Err(error) => println!("max players is a usize, Error : {}", error),
+        },
+        None => {}
+    }
+    match max_players.0 {
+        Some(max) => println!("Current max players = {}", max),
+        None => println!("No limit on the number of players"),
     }
 }
 
-fn test(query: Query<(&Position, &ShipInfo, Entity)>) {
-    let mut alpha = Vec::<(ShipID, Position)>::new();
-    for (a, b, c) in query.iter() {
-        alpha.push((b.id, *a));
-        //println!("{:#?} , {:#?} , {:#?}", *a, *b, c)
+/// Clients designated by `arg`, which is either the id of a connected client or a player name
+fn resolve_clients(arg: &str, clients: &Clients, names: &ClientNames) -> Vec<ClientId> {
+    match arg.parse::<ClientId>() {
+        Ok(client) if clients.0.contains(&client) => vec![client],
+        _ => names.clients_named(arg).collect(),
     }
-    println!("{:#?}", alpha);
-    println!("test")
 }
 
-fn test_set_pos(
-    mut query: Query<(&mut Position, &ShipInfo, Entity)>,
-    ships: Res<ShipsMapping>,
-    arguments: ResMut<Arguments>,
+fn kick_command(
+    arguments: Res<Arguments>,
+    clients: Res<Clients>,
+    names: Res<ClientNames>,
+    mut kicks: EventWriter<KickEvent>,
 ) {
-    let mut argument = arguments.0.split_whitespace();
-    let id = match argument.next() {
-        Some(arg) => ships.0.get(arg),
+    let mut args = arguments.0.split_whitespace();
+    let Some(target) = args.next() else {
+        return println!("usage : kick CLIENT_ID|NAME [REASON]");
+    };
+    let reason = args.collect::<Vec<_>>().join(" ");
+    let reason = if reason.is_empty() {
+        "kicked by the server admin".to_owned()
+    } else {
+        reason
+    };
+    let targets = resolve_clients(target, &clients, &names);
+    if targets.is_empty() {
+        return println!("no client with id or name {}", target);
+    }
+    for client in targets {
+        println!("kicking client {}", client);
+        kicks.send(KickEvent {
+            client,
+            message: ServerMessage::Kicked(reason.clone()),
+        });
+    }
+}
+
+fn ban_command(
+    arguments: Res<Arguments>,
+    clients: Res<Clients>,
+    names: Res<ClientNames>,
+    mut ban_list: ResMut<BanList>,
+    files: Res<GameFiles>,
+    mut kicks: EventWriter<KickEvent>,
+) {
+    let Some(target) = arguments.0.split_whitespace().next() else {
+        return println!("usage : ban CLIENT_ID|NAME");
+    };
+    let name = match target.parse::<ClientId>() {
+        Ok(client) if clients.0.contains(&client) => match names.0.get(&client) {
+            Some(name) => name.clone(),
+            None => return println!("client {} didn't give its name yet", client),
+        },
+        _ => target.to_owned(),
+    };
+    ban_list.names.insert(name.clone());
+    if let Err(error) = ban_list.save(&files.root.join(BAN_LIST_FILE)) {
+        println!("could not save the ban list, Error : {}", error);
+    }
+    println!("banned {}", name);
+    for client in names.clients_named(&name) {
+        kicks.send(KickEvent {
+            client,
+            message: ServerMessage::Kicked(BANNED_REASON.into()),
+        });
+    }
+}
+
+fn unban_command(arguments: Res<Arguments>, mut ban_list: ResMut<BanList>, files: Res<GameFiles>) {
+    let Some(name) = arguments.0.split_whitespace().next() else {
+        return println!("usage : unban NAME");
+    };
+    if !ban_list.names.remove(name) {
+        return println!("{} is not banned", name);
+    }
+    if let Err(error) = ban_list.save(&files.root.join(BAN_LIST_FILE)) {
+        println!("could not save the ban list, Error : {}", error);
+    }
+    println!("unbanned {}", name);
+}
+
+fn bans_command(ban_list: Res<BanList>) {
+    if ban_list.names.is_empty() {
+        println!("No banned players");
+    }
+    for name in &ban_list.names {
+        println!("{}", name);
+    }
+}
+
+fn seed_command(arguments: Res<Arguments>, mut seed: ResMut<WorldSeed>) {
+    if let Some(arg) = arguments.0.split_whitespace().next() {
+        match arg.parse() {
+            Ok(value) => seed.0 = value,
+            Err(error) => println!("seed is a u64, Error : {}", error),
+        }
+    }
+    println!("Current seed = {}", seed.0);
+}
+
+fn log_level_command(arguments: Res<Arguments>, filter: Option<ResMut<LogFilter>>) {
+    let Some(mut filter) = filter else {
+        return println!("the log filter can't be changed");
+    };
+    let mut args = arguments.0.split_whitespace();
+    if let Some(level) = args.next() {
+        match level.parse() {
+            Ok(level) => {
+                if let Err(e) = filter.set_level(level, args.next()) {
+                    println!("could not change the log filter: {e}");
+                }
+            }
+            Err(e) => println!("invalid level {level}: {e}"),
+        }
+    }
+    println!("Current log filter = {}", filter.directives());
+}
+
+fn budget_command(
+    arguments: Res<Arguments>,
+    mut budget: ResMut<WorldBudget>,
+    counts: Res<BudgetCounts>,
+) {
+    let mut args = arguments.0.split_whitespace();
+    match args.next() {
+        Some("set") => {
+            let (Some(kind), Some(hard)) = (args.next(), args.next()) else {
+                return println!("usage : budget set KIND HARD [SOFT]");
+            };
+            let kind = match kind.parse::<BudgetKind>() {
+                Ok(kind) => kind,
+                Err(e) => return println!("{e}"),
+            };
+            let (hard, soft) = match (hard.parse::<usize>(), args.next().map(str::parse::<usize>)) {
+                (Ok(hard), None) => (hard, budget.cap(kind).soft.min(hard)),
+                (Ok(hard), Some(Ok(soft))) if soft <= hard => (hard, soft),
+                (Ok(_), Some(Ok(_))) => {
+                    return println!("the soft cap can't be above the hard cap")
+                }
+                (Err(e), _) | (_, Some(Err(e))) => return println!("caps are usizes, Error : {e}"),
+            };
+            *budget.cap_mut(kind) = Cap { soft, hard };
+        }
+        Some(arg) => {
+            return println!("unknown argument {arg}, usage : budget [set KIND HARD [SOFT]]")
+        }
+        None => {}
+    }
+    println!("kind : count / soft cap / hard cap (counted every few seconds)");
+    for kind in BudgetKind::ALL {
+        let cap = budget.cap(kind);
+        println!(
+            "{kind} : {} / {} / {}",
+            counts.count(kind),
+            cap.soft,
+            cap.hard
+        );
+    }
+}
+
+fn waypoint_command(
+    arguments: Res<Arguments>,
+    mut waypoints: ResMut<Waypoints>,
+    positions: Res<WaypointPositions>,
+    bodies: Res<BodiesMapping>,
+    ships: Res<ShipsMapping>,
+) {
+    let mut args = arguments.0.split_whitespace();
+    match args.next() {
+        Some("add") => {
+            let (Some(name), Some(kind)) = (args.next(), args.next()) else {
+                return println!(
+                    "usage : waypoint add NAME fixed X Y Z|body BODY [DX DY DZ]|ship ID SIMTICK"
+                );
+            };
+            let fields: Vec<_> = args.collect();
+            match parse_waypoint(kind, &fields, &bodies, &ships)
+                .and_then(|kind| waypoints.insert(name, kind))
+            {
+                Ok(name) => println!("added waypoint {name} ({})", waypoints.0[&name]),
+                Err(e) => println!("could not add the waypoint: {e}"),
+            }
+        }
+        Some("remove") => {
+            let Some(name) = args.next() else {
+                return println!("usage : waypoint remove NAME");
+            };
+            match WaypointID::from(name)
+                .ok()
+                .and_then(|n| waypoints.0.remove(&n))
+            {
+                Some(_) => println!("removed waypoint {name}"),
+                None => println!("no waypoint named {name}"),
+            }
+        }
+        Some("list") | None => {
+            if waypoints.0.is_empty() {
+                println!("No waypoints");
+            }
+            for (name, kind) in &waypoints.0 {
+                match positions.0.get(name) {
+                    Some(pos) => println!("{name} : {kind}, at {pos}"),
+                    None => println!("{name} : {kind}, not resolved yet"),
+                }
+            }
+        }
+        Some(arg) => println!("unknown argument {arg}, usage : waypoint [add|remove|list]"),
+    }
+}
+
+fn check_bodies_command() {
+    if let Err(e) = check_main_bodies() {
+        println!("could not read the bodies: {e}");
+    }
+}
+
+/// Format of a saved file, guessed from its location if it was written before the files had an
+/// envelope
+fn persisted_format(path: &Path) -> Result<String, PersistError> {
+    if let (Some(format), _) = read_header(path)? {
+        return Ok(format);
+    }
+    let name = path.file_name().and_then(|n| n.to_str());
+    let dir = path
+        .parent()
+        .and_then(|p| p.file_name())
+        .and_then(|n| n.to_str());
+    match (name, dir) {
+        (Some(USER_BODIES_PATH), _) => Ok(<Vec<BodyData>>::FORMAT.into()),
+        (Some(BAN_LIST_FILE), _) => Ok(BanList::FORMAT.into()),
+        (Some(WAYPOINTS_FILE), _) => Ok(Waypoints::FORMAT.into()),
+        (_, Some(TRAJECTORIES_PATH)) => Ok(Trajectory::FORMAT.into()),
+        _ => Err(PersistError::Parse("unknown kind of file".into())),
+    }
+}
+
+/// Migrates a saved file of any format, returns its format and the version it was in
+fn migrate_persisted_file(path: &Path) -> Result<(String, u32), PersistError> {
+    let format = persisted_format(path)?;
+    let version = match format.as_str() {
+        Trajectory::FORMAT => migrate_file::<Trajectory>(path)?,
+        <Vec<BodyData>>::FORMAT => migrate_file::<Vec<BodyData>>(path)?,
+        BanList::FORMAT => migrate_file::<BanList>(path)?,
+        Waypoints::FORMAT => migrate_file::<Waypoints>(path)?,
+        _ => return Err(PersistError::Parse(format!("unknown format {format}"))),
+    };
+    Ok((format, version))
+}
+
+fn migrate_command(arguments: Res<Arguments>) {
+    let path = Path::new(arguments.0.trim());
+    if path.as_os_str().is_empty() {
+        return println!("usage : migrate PATH");
+    }
+    match migrate_persisted_file(path) {
+        Ok((format, version)) if backup_path(path).exists() => println!(
+            "migrated {} from version {version} of the {format} format, the original is in {}",
+            path.display(),
+            backup_path(path).display()
+        ),
+        Ok((format, _)) => println!(
+            "{} is already in the current version of the {format} format",
+            path.display()
+        ),
+        Err(e) => println!("could not migrate {}: {e}", path.display()),
+    }
+}
+
+fn hash_world_command(world: &mut World) {
+    println!("World hash = {:016x}", world_hash(world));
+}
+
+fn record_command(
+    arguments: Res<Arguments>,
+    ships: Res<ShipsMapping>,
+    mut history: EventWriter<HistoryEvent>,
+) {
+    let mut args = arguments.0.split_whitespace();
+    let (Some(id), Some(state)) = (args.next(), args.next()) else {
+        return println!("usage : record ID on|off [K]");
+    };
+    let Some(&ship) = ships.0.get_key_value(id).map(|(ship, _)| ship) else {
+        return println!("no ship with id {}", id);
+    };
+    match state {
+        "on" => {
+            let interval_ticks = match args.next().map(str::parse::<u64>) {
+                None => DEFAULT_RECORD_INTERVAL,
+                Some(Ok(k)) if k > 0 => k,
+                _ => return println!("K is a positive number of ticks"),
+            };
+            history.send(HistoryEvent::Start {
+                ship,
+                interval_ticks,
+            });
+            println!("recording ship {} every {} ticks", ship, interval_ticks);
+        }
+        "off" => {
+            history.send(HistoryEvent::Stop(ship));
+            println!("stopped recording ship {}", ship);
+        }
+        _ => println!("usage : record ID on|off [K]"),
+    }
+}
+
+fn integration_command(arguments: Res<Arguments>, mut method: ResMut<IntegrationMethod>) {
+    match arguments.0.split_whitespace().next() {
+        None => {}
+        Some("leapfrog") => *method = IntegrationMethod::Leapfrog,
+        Some("sgp4") => *method = IntegrationMethod::SGP4,
+        Some(_) => return println!("usage : integration [leapfrog|sgp4]"),
+    }
+    println!("integration method : {:?}", *method);
+}
+
+/// Attaches a TLE to a ship. The TLEs that SGP4 can't propagate, like the ones of the orbits
+/// needing the deep-space model, are refused here rather than dropped once the ship follows them
+fn tle_command(
+    mut commands: Commands,
+    arguments: Res<Arguments>,
+    ships: Res<ShipsMapping>,
+    method: Res<IntegrationMethod>,
+    epoch: Res<SimulationEpoch>,
+    time: Res<GameTime>,
+) {
+    let mut args = arguments.0.split_whitespace();
+    let (Some(id), Some(path)) = (args.next(), args.next()) else {
+        return println!("usage : tle ID PATH");
+    };
+    let Some(&ship) = ships.0.get(id) else {
+        return println!("no ship with id {}", id);
+    };
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => return println!("could not read {path}: {e}"),
+    };
+    let tle = match TLEData::from_text(&text) {
+        Ok(tle) => tle,
+        Err(e) => return println!("could not read the TLE of {path}: {e}"),
+    };
+    let model = match tle.elements().and_then(Sgp4::new) {
+        Ok(model) => model,
+        Err(e) => return println!("the TLE of {path} is refused: {e}"),
+    };
+    commands.entity(ship).insert(tle);
+    let days = model.epoch() - epoch.date(time.time());
+    println!("ship {id} follows the TLE of {path}, whose epoch is {days:.1} days from now");
+    if *method != IntegrationMethod::SGP4 {
+        println!("the ship is integrated until the sgp4 integration is selected with the integration command");
+    }
+}
+
+fn epoch_command(
+    arguments: Res<Arguments>,
+    mut epoch: ResMut<SimulationEpoch>,
+    time: Res<GameTime>,
+) {
+    if let Some(arg) = arguments.0.split_whitespace().next() {
+        match arg.parse::<f64>() {
+            Ok(date) if date.is_finite() => epoch.0 = date,
+            _ => return println!("usage : epoch [DATE], DATE being a Julian date"),
+        }
+    }
+    println!(
+        "Julian date : {:.5}, at the simtick 0 : {:.5}",
+        epoch.date(time.time()),
+        epoch.0
+    );
+}
+
+fn gravity_command(arguments: Res<Arguments>, mut model: ResMut<GravityModel>) {
+    let mut args = arguments.0.split_whitespace();
+    match (args.next(), args.next().map(str::parse::<u32>)) {
+        (None, _) => {}
+        (Some("point"), None) => *model = GravityModel::PointMass,
+        (Some("j2"), None) => *model = GravityModel::J2Only,
+        (Some("harmonics"), Some(Ok(max_degree))) if max_degree >= 2 => {
+            *model = GravityModel::SphericalHarmonics { max_degree }
+        }
+        _ => {
+            return println!("usage : gravity [point|j2|harmonics DEGREE], DEGREE being at least 2")
+        }
+    }
+    println!("gravity model : {:?}", *model);
+}
+
+fn beacon_command(mut commands: Commands, arguments: Res<Arguments>, ships: Res<ShipsMapping>) {
+    let mut args = arguments.0.split_whitespace();
+    let (Some(id), Some(arg)) = (args.next(), args.next()) else {
+        return println!("usage : beacon ID [FREQUENCY|off]");
+    };
+    let Some(&ship) = ships.0.get(id) else {
+        return println!("no ship with id {}", id);
+    };
+    if arg == "off" {
+        commands.entity(ship).remove::<RadioBeacon>();
+        return println!("ship {id} is no longer a beacon");
+    }
+    match arg.parse::<f64>() {
+        Ok(frequency_mhz) if frequency_mhz > 0. => {
+            commands.entity(ship).insert(RadioBeacon { frequency_mhz });
+            println!("ship {id} is a beacon emitting at {frequency_mhz} MHz");
+        }
+        _ => println!("usage : beacon ID [FREQUENCY|off], FREQUENCY being positive"),
+    }
+}
+
+/// Longest horizon searched by the optimizer (in ticks), so that the ephemeris and the
+/// simulations of the chromosomes stay bounded
+const MAX_OPTIMIZATION_HORIZON_TICKS: u64 = 30_000;
+
+/// Trajectory optimizations running in the background, see [optimize_command]
+#[derive(Resource, Default)]
+struct OptimizationTasks(Vec<OptimizationTask>);
+
+struct OptimizationTask {
+    ship: ShipID,
+    origin: BodyID,
+    start_tick: u64,
+    task: Task<ManeuverSequence>,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn optimize_command(
+    arguments: Res<Arguments>,
+    ships: Res<ShipsMapping>,
+    mapping: Res<BodiesMapping>,
+    query: Query<(&Position, &Velocity, &Influenced)>,
+    bodies: Query<(&Position, &Velocity, &Mass, &BodyInfo)>,
+    orbits: Query<(&EllipticalOrbit, &BodyInfo)>,
+    time: Res<GameTime>,
+    seed: Res<WorldSeed>,
+    mut tasks: ResMut<OptimizationTasks>,
+    (waypoints, positions): (Res<Waypoints>, Res<WaypointPositions>),
+) {
+    let mut args = arguments.0.split_whitespace();
+    let (Some(id), Some(target), Some(generations)) = (args.next(), args.next(), args.next())
+    else {
+        return println!("usage : optimize ID TARGET N");
+    };
+    let Some((ship, &entity)) = ships.0.get_key_value(id) else {
+        return println!("no ship with id {}", id);
+    };
+    // The exact names of bodies come first, so that a waypoint can't hide the body with the
+    // same name, but a waypoint can't be hidden by a body whose name merely starts with its own
+    let waypoint = waypoints.get(target).copied();
+    let (target, target_entity) = match (mapping.lookup_exact(target), waypoint) {
+        (Some(id), _) => (id, Some(mapping.0[&id])),
+        // The optimizer reads the positions of the waypoint from its ephemeris, under its name
+        (None, Some(_)) => match BodyID::new(target) {
+            Ok(id) => (id, None),
+            Err(error) => return println!("{}", error),
+        },
+        (None, None) => match mapping.lookup(target) {
+            Ok(id) => (id, Some(mapping.0[&id])),
+            Err(error) => return println!("{}", error),
+        },
+    };
+    if target_entity.is_none() && !positions.0.contains_key(target.as_str()) {
+        return println!("waypoint {} has no position yet", target);
+    }
+    let generations = match generations.parse() {
+        Ok(generations) => generations,
+        Err(error) => return println!("number of generations is a usize, Error : {}", error),
+    };
+    let Ok((pos, vel, influenced)) = query.get(entity) else {
+        return println!("ship has no physical state");
+    };
+    let Some((central, (c_pos, c_vel, c_mass, c_info))) = influenced
+        .main_influencer
+        .and_then(|e| bodies.get(e).ok().map(|b| (e, b)))
+    else {
+        return println!("ship is not orbiting any body");
+    };
+    if Some(central) == target_entity {
+        return println!("ship is already orbiting {}", target);
+    }
+    let gm = G * c_mass.0;
+    let start = (Position(pos.0 - c_pos.0), Velocity(vel.0 - c_vel.0));
+    // The positions of the target are computed from the orbits of the target and its hosts, plus
+    // an offset for the waypoints, so that the ephemeris is built in the background task
+    let (mut target_chain, offset) = match (target_entity, waypoint) {
+        (Some(entity), _) => (orbit_chain(entity, &orbits, &mapping.0), DVec3::ZERO),
+        (None, Some(WaypointKind::Body { body, offset })) => match mapping.0.get(&body) {
+            Some(&entity) => (orbit_chain(entity, &orbits, &mapping.0), offset),
+            None => (Vec::new(), offset),
+        },
+        // The other waypoints are points of space
+        (None, _) => (Vec::new(), positions.0[target.as_str()]),
+    };
+    let mut central_chain = orbit_chain(central, &orbits, &mapping.0);
+    let target_distance = match target_entity {
+        Some(entity) => bodies
+            .get(entity)
+            .map_or(0., |(p, ..)| p.0.distance(c_pos.0)),
+        None => positions.0[target.as_str()].distance(c_pos.0),
+    };
+    // Half the orbital period of the target, which is longer than any transfer towards it
+    let horizon_days = std::f64::consts::PI * (target_distance.powi(3) / gm).sqrt();
+    let horizon_ticks =
+        ((horizon_days / (GAMETIME_PER_SIMTICK * SIMTICKS_PER_TICK as f64)) as u64).max(1);
+    if horizon_ticks > MAX_OPTIMIZATION_HORIZON_TICKS {
+        println!(
+            "transfers towards {} may take {} ticks, only the first {} are searched",
+            target, horizon_ticks, MAX_OPTIMIZATION_HORIZON_TICKS
+        );
+    }
+    let horizon_ticks = horizon_ticks.min(MAX_OPTIMIZATION_HORIZON_TICKS);
+    let targets = [
+        TrajectoryConstraint::ReachBody {
+            body: target,
+            tolerance_km: target_distance * 0.01,
+        },
+        TrajectoryConstraint::MinimizeDV,
+    ];
+    let config = GeneticConfig {
+        generations,
+        horizon_ticks,
+        seed: seed.rng(("optimizer", *ship)).gen(),
+        ..Default::default()
+    };
+    let start_simtick = time.simtick;
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        let ephemeris = (0..=horizon_ticks)
+            .map(|t| {
+                let simtick = start_simtick + t * SIMTICKS_PER_TICK;
+                chain_position(&mut target_chain, simtick) + offset
+                    - chain_position(&mut central_chain, simtick)
+            })
+            .collect();
+        let ephemerides = HashMap::from_iter([(target, ephemeris)]);
+        optimize_trajectory(&start, &targets, gm, config, &ephemerides)
+    });
+    println!(
+        "optimizing the trajectory of ship {} towards {} over {} ticks",
+        ship, target, horizon_ticks
+    );
+    tasks.0.push(OptimizationTask {
+        ship: *ship,
+        origin: c_info.0.id,
+        start_tick: time.tick(),
+        task,
+    });
+}
+
+fn poll_optimizations(
+    mut tasks: ResMut<OptimizationTasks>,
+    mut trajectories: EventWriter<TrajectoryEvent>,
+) {
+    tasks.0.retain_mut(|optimization| {
+        let Some(sequence) = block_on(poll_once(&mut optimization.task)) else {
+            return true;
+        };
+        println!(
+            "optimized trajectory of ship {} : {} burns, total dv = {:.3} km/day, closest approaches = {:?}",
+            optimization.ship,
+            sequence.burns.len(),
+            sequence.delta_v,
+            sequence.closest_approaches
+        );
+        for (tick, node) in sequence.nodes(optimization.origin, optimization.start_tick) {
+            trajectories.send(TrajectoryEvent::AddNode {
+                ship: optimization.ship,
+                node,
+                tick,
+            });
+        }
+        false
+    });
+}
+
+/// Writes the fleet report to a file every few seconds, see [write_fleet_log]
+#[derive(Resource, Default, Debug)]
+struct FleetReportInterval(Option<Timer>);
+
+/// Builds the table of the ships of the fleet report, sorted by id. The orbital elements are
+/// relative to the main influencer of each ship
+#[allow(clippy::type_complexity)]
+fn fleet_report(
+    ships: &Query<(
+        &ShipInfo,
+        &Position,
+        &Velocity,
+        &Influenced,
+        Option<&Autopilot>,
+    )>,
+    bodies: &Query<(&Position, &Velocity, &Mass), With<BodyInfo>>,
+    owners: &ShipOwners,
+) -> Table {
+    let mut table = Table::new([
+        "id",
+        "position (km)",
+        "velocity (km/day)",
+        "SMA (km)",
+        "eccentricity",
+        "inclination (°)",
+        "fuel_remaining_kg",
+        "docked_at",
+        "autopilot_mode",
+        "owner",
+    ]);
+    let mut ships: Vec<_> = ships.iter().collect();
+    ships.sort_unstable_by_key(|(info, ..)| info.id);
+    let vector = |v: DVec3| format!("[{:.3e}, {:.3e}, {:.3e}]", v.x, v.y, v.z);
+    for (info, &Position(pos), &Velocity(vel), influence, autopilot) in ships {
+        let elements = influence
+            .main_influencer
+            .and_then(|e| bodies.get(e).ok())
+            .map(|(&Position(host_pos), &Velocity(host_vel), &Mass(mass))| {
+                let (r, v) = (pos - host_pos, vel - host_vel);
+                let elements = osculating_elements(r, v, G * mass);
+                let inclination = r.cross(v).angle_between(DVec3::Z).to_degrees();
+                [
+                    format!("{:.0}", elements.semimajor_axis),
+                    format!("{:.4}", elements.eccentricity),
+                    format!("{:.2}", inclination),
+                ]
+            })
+            .unwrap_or_else(|| ["-".into(), "-".into(), "-".into()]);
+        let [sma, eccentricity, inclination] = elements;
+        table.push_row([
+            info.id.to_string(),
+            vector(pos),
+            vector(vel),
+            sma,
+            eccentricity,
+            inclination,
+            // Ships don't carry fuel nor dock yet
+            "-".into(),
+            "None".into(),
+            autopilot.map_or("None".into(), |a| format!("{} ({})", a.kind, a.phase)),
+            owners
+                .0
+                .get(&info.id)
+                .map_or("Singleplayer".into(), |client| client.to_string()),
+        ]);
+    }
+    table
+}
+
+#[allow(clippy::type_complexity)]
+fn fleet_report_command(
+    ships: Query<(
+        &ShipInfo,
+        &Position,
+        &Velocity,
+        &Influenced,
+        Option<&Autopilot>,
+    )>,
+    bodies: Query<(&Position, &Velocity, &Mass), With<BodyInfo>>,
+    owners: Res<ShipOwners>,
+    time: Res<GameTime>,
+) {
+    let table = fleet_report(&ships, &bodies, &owners);
+    println!(
+        "fleet report at simtick {} : {} ships",
+        time.simtick,
+        table.len()
+    );
+    print!("{table}");
+}
+
+fn fleet_report_interval_command(
+    arguments: Res<Arguments>,
+    mut interval: ResMut<FleetReportInterval>,
+) {
+    match arguments.0.split_whitespace().next() {
+        None => {}
+        Some("off") => interval.0 = None,
+        Some(arg) => match arg.parse::<f32>() {
+            Ok(seconds) if seconds > 0. => {
+                interval.0 = Some(Timer::from_seconds(seconds, TimerMode::Repeating))
+            }
+            _ => return println!("the interval is a positive number of seconds, or off"),
+        },
+    }
+    match &interval.0 {
+        Some(timer) => println!(
+            "Writing the fleet report every {} seconds",
+            timer.duration().as_secs_f32()
+        ),
+        None => println!("The fleet report isn't written periodically"),
+    }
+}
+
+/// Writes the fleet report to `fleet_log_<timestamp>.txt` in the game directory at each interval
+/// given with the `fleet_report_interval` command, the timestamp being in seconds since the Unix
+/// epoch
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn write_fleet_log(
+    mut interval: ResMut<FleetReportInterval>,
+    time: Res<Time>,
+    ships: Query<(
+        &ShipInfo,
+        &Position,
+        &Velocity,
+        &Influenced,
+        Option<&Autopilot>,
+    )>,
+    bodies: Query<(&Position, &Velocity, &Mass), With<BodyInfo>>,
+    owners: Res<ShipOwners>,
+    game_time: Res<GameTime>,
+    files: Res<GameFiles>,
+) {
+    let Some(timer) = interval.0.as_mut() else {
+        return;
+    };
+    if !timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let path = files.root.join(format!("fleet_log_{timestamp}.txt"));
+    let table = fleet_report(&ships, &bodies, &owners);
+    let report = format!(
+        "fleet report at simtick {} : {} ships\n{table}",
+        game_time.simtick,
+        table.len()
+    );
+    if let Err(e) = std::fs::write(&path, report) {
+        error!(
+            "could not write the fleet report to {}: {e}",
+            path.display()
+        );
+    }
+}
+
+fn list_ships_command(ships: Res<ShipsMapping>) {
+    println!("ships list : {:?}", ships.0.keys())
+}
+
+fn get_ship_data(
+    ships: Res<ShipsMapping>,
+    arguments: ResMut<Arguments>,
+    query: Query<(&Position, &Velocity, &Acceleration, &Influenced)>,
+) {
+    let mut arg = arguments.0.split_whitespace();
+    match arg.next() {
+        Some(arg1) => {
+            let tmp: Result<ShipID, _> = arg1.parse();
+            match tmp {
+                Ok(tmp) => {
+                    match ships.0.get(&tmp) {
+                        Some(thing) => println!("data : {:#?}", query.get(*thing)),
+                        None => {
+                            println!("wrong ID");
+                        }
+                    };
+                }
+                Err(error) => println!("not an id, Error : {}", error),
+            }
+        }
+        None => {}
+    }
+}
+
+fn get_bodys_data(bodies: Query<(&Position, &HillRadius, &BodyInfo)>) {
+    for (i, (pos, hill, bodyinfo)) in bodies.iter().enumerate() {
+        println!("{} - {:#?}", i, bodyinfo)
+    }
+}
+
+fn test(query: Query<(&Position, &ShipInfo, Entity)>) {
+    let mut alpha = Vec::<(ShipID, Position)>::new();
+    for (a, b, c) in query.iter() {
+        alpha.push((b.id, *a));
+        //println!("{:#?} , {:#?} , {:#?}", *a, *b, c)
+    }
+    println!("{:#?}", alpha);
+    println!("test")
+}
+
+fn test_set_pos(
+    mut query: Query<(&mut Position, &ShipInfo, Entity)>,
+    ships: Res<ShipsMapping>,
+    arguments: ResMut<Arguments>,
+) {
+    let mut argument = arguments.0.split_whitespace();
+    let id = match argument.next() {
+        Some(arg) => ships.0.get(arg),
         None => {
             println!("wrong ID");
             None
@@ -484,3 +2362,796 @@ fn test_set_pos(
         None => (),
     }
 }
+
+fn compare_integrators(
+    ships: Res<ShipsMapping>,
+    arguments: Res<Arguments>,
+    query: Query<(&Position, &Velocity, &Influenced)>,
+    bodies: Query<(&Position, &Mass)>,
+    step: Res<SimStepSize>,
+) {
+    let mut arg = arguments.0.split_whitespace();
+    let Some(entity) = arg.next().and_then(|id| ships.0.get(id)) else {
+        println!("wrong ID");
+        return;
+    };
+    let n_ticks: u64 = match arg.next().map(str::parse) {
+        Some(Ok(n)) => n,
+        Some(Err(error)) => {
+            println!("number of ticks is a u64, Error : {}", error);
+            return;
+        }
+        None => {
+            println!("missing number of ticks");
+            return;
+        }
+    };
+    let Ok((pos, vel, influenced)) = query.get(*entity) else {
+        println!("ship has no physical state");
+        return;
+    };
+    // Influencers are frozen at their current positions so that only the integrators differ
+    let influencers: Vec<_> = bodies
+        .iter_many(&influenced.influencers)
+        .map(|(p, m)| (p.0, m.0))
+        .collect();
+    let accel = |p| get_acceleration(p, influencers.iter().copied());
+    let dt = GAMETIME_PER_SIMTICK * step.0 as f64;
+
+    let (mut lf_pos, mut lf_vel) = (pos.0, vel.0);
+    let mut lf_acc = accel(lf_pos);
+    let (mut rk_pos, mut rk_vel) = (pos.0, vel.0);
+    let report_every = (n_ticks / 10).max(1);
+    println!(
+        "{:>10} {:>20} {:>20}",
+        "tick", "position diff (km)", "velocity diff (km/d)"
+    );
+    for tick in 1..=n_ticks {
+        lf_pos += get_dx(lf_vel, lf_acc, dt);
+        let previous = lf_acc;
+        lf_acc = accel(lf_pos);
+        lf_vel += get_dv(previous, lf_acc, dt);
+        (rk_pos, rk_vel) = rk4_step(rk_pos, rk_vel, accel, dt);
+        if tick % report_every == 0 || tick == n_ticks {
+            println!(
+                "{:>10} {:>20.6} {:>20.6}",
+                tick,
+                (lf_pos - rk_pos).length(),
+                (lf_vel - rk_vel).length()
+            );
+        }
+    }
+}
+
+/// Finds the log of the ship whose id is the first argument. The ships don't log their
+/// perturbations by default, so a log is started if the ship has none
+fn find_perturbation_log<'a>(
+    commands: &mut Commands,
+    ships: &ShipsMapping,
+    id: Option<&str>,
+    query: &'a Query<&AccelerationLog>,
+) -> Option<&'a AccelerationLog> {
+    let Some((id, &entity)) = id.and_then(|id| ships.0.get_key_value(id)) else {
+        println!("wrong ID");
+        return None;
+    };
+    let log = query.get(entity).ok();
+    if log.is_none() {
+        commands.entity(entity).insert(AccelerationLog::default());
+        println!("the perturbations of ship {id} are logged from now on");
+    }
+    log
+}
+
+fn perturbation_log(
+    mut commands: Commands,
+    ships: Res<ShipsMapping>,
+    arguments: Res<Arguments>,
+    query: Query<&AccelerationLog>,
+) {
+    let mut arg = arguments.0.split_whitespace();
+    let id = arg.next();
+    let n = match arg.next() {
+        Some("off") => {
+            if let Some(&entity) = id.and_then(|id| ships.0.get(id)) {
+                commands.entity(entity).remove::<AccelerationLog>();
+            }
+            return;
+        }
+        Some(n) => match n.parse() {
+            Ok(n) => n,
+            Err(error) => {
+                println!("number of entries is a usize, Error : {}", error);
+                return;
+            }
+        },
+        None => 10,
+    };
+    let Some(log) = find_perturbation_log(&mut commands, &ships, id, &query) else {
+        return;
+    };
+    let mut entries: Vec<_> = log.entries.iter().collect();
+    entries.sort_by(|a, b| b.contribution_km_s2.total_cmp(&a.contribution_km_s2));
+    println!("{:>10} {:>32} {:>20}", "simtick", "body", "acc (km/s2)");
+    for entry in entries.into_iter().take(n) {
+        println!(
+            "{:>10} {:>32} {:>20.6e}",
+            entry.simtick, entry.body_id, entry.contribution_km_s2
+        );
+    }
+}
+
+/// Number of simticks taken into account by the perturbation summary
+const PERTURBATION_SUMMARY_SIMTICKS: u64 = 1000;
+
+fn perturbation_summary(
+    mut commands: Commands,
+    ships: Res<ShipsMapping>,
+    arguments: Res<Arguments>,
+    query: Query<&AccelerationLog>,
+    game_time: Res<GameTime>,
+) {
+    let id = arguments.0.split_whitespace().next();
+    let Some(log) = find_perturbation_log(&mut commands, &ships, id, &query) else {
+        return;
+    };
+    let start = game_time
+        .simtick
+        .saturating_sub(PERTURBATION_SUMMARY_SIMTICKS);
+    let mut totals: Vec<(BodyID, f64)> = Vec::new();
+    for entry in log.entries.iter().filter(|e| e.simtick > start) {
+        match totals.iter_mut().find(|(id, _)| *id == entry.body_id) {
+            Some((_, total)) => *total += entry.contribution_km_s2,
+            None => totals.push((entry.body_id, entry.contribution_km_s2)),
+        }
+    }
+    totals.sort_by(|a, b| b.1.total_cmp(&a.1));
+    println!("{:>32} {:>20}", "body", "total acc (km/s2)");
+    for (id, total) in totals {
+        println!("{:>32} {:>20.6e}", id, total);
+    }
+}
+
+fn station_keeping_command(
+    ships: Res<ShipsMapping>,
+    arguments: Res<Arguments>,
+    query: Query<&StationKeepingReport>,
+) {
+    let Some(entity) = arguments
+        .0
+        .split_whitespace()
+        .next()
+        .and_then(|id| ships.0.get(id))
+    else {
+        println!("wrong ID");
+        return;
+    };
+    let Ok(report) = query.get(*entity) else {
+        println!("no station-keeping estimate, the ship is not on a closed orbit");
+        return;
+    };
+    let breakdown = report.breakdown;
+    println!("{:>16} {:>16}", "perturbation", "dv/year (m/s)");
+    println!("{:>16} {:>16.3}", "drag", breakdown.drag * 1e3);
+    println!("{:>16} {:>16.3}", "radiation", breakdown.srp * 1e3);
+    println!("{:>16} {:>16.3}", "J2", breakdown.j2 * 1e3);
+    println!(
+        "{:>16} {:>16.3}",
+        format!("reserve ({:.0}%)", report.reserve_fraction * 100.),
+        breakdown.total() * report.reserve_fraction * 1e3
+    );
+    println!("{:>16} {:>16.3}", "total", report.annual_dv_km_s * 1e3);
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        ecs::{entity::Entity, system::RunSystemOnce},
+        math::DVec3,
+        prelude::*,
+        utils::default,
+    };
+
+    use crate::{
+        client::{ClientMode, ClientPlugin, DisconnectReason, LastServerUpdate},
+        game::GameFiles,
+        network::{
+            testing::{connect_client, linked_apps, send_to_server, spawn_server, update_linked},
+            transport::{ClientMessageTransport, LoopbackServer},
+            ClientMessage, InterestFocus, PermissionDenied, Role, ServerMessage,
+            ShipRejectionReason,
+        },
+        objects::ships::{
+            autopilot::{Autopilot, AutopilotKind},
+            navigation::RadioBeacon,
+        },
+        physics::{
+            gravity::GravityModel,
+            leapfrog::{AccelerationLog, IntegrationMethod},
+            sgp4::{SimulationEpoch, TLEData},
+            time::{PhysicsRate, SimStepSize},
+            Mass, Position, Velocity,
+        },
+        prelude::{
+            id_from, BodiesMapping, CreateShipMsg, IdGenerator, ShipEvent, ShipInfo, ShipsMapping,
+        },
+        utils::algebra::circular_orbit_around_body,
+    };
+
+    use super::{
+        backup_path, ban_command, beacon_command, check_permission, epoch_command, fleet_report,
+        gravity_command, integration_command, kick_clients, migrate_persisted_file,
+        perturbation_log, read_header, resolve_ship_creations, sample_preview, set_tick_rate,
+        tle_command, Action, Arguments, BanList, BandwidthTracker, BandwidthWindowTimer,
+        ClientConnectionEvent, ClientInterests, ClientNames, ClientRoles, Clients, KickEvent,
+        MaxPlayers, PendingSandboxes, SandboxRequest, ServerPlugin, ShipOwners, Trajectory,
+        Versioned, BANNED_REASON, BAN_LIST_FILE, COARSE_UPDATE_PERIOD, MAX_PREVIEW_TICKS,
+        PREVIEW_SAMPLES, TRAJECTORIES_PATH,
+    };
+
+    fn create_msg(id: &str) -> CreateShipMsg {
+        CreateShipMsg {
+            info: ShipInfo {
+                id: id_from(id),
+                ..default()
+            },
+            acceleration: default(),
+            pos: default(),
+            velocity: default(),
+            generated_id: false,
+        }
+    }
+
+    #[test]
+    fn test_bandwidth_tracker() {
+        let mut tracker = BandwidthTracker::default();
+        assert!(tracker.try_consume(1, 600, 1000));
+        assert!(!tracker.try_consume(1, 600, 1000));
+        assert!(tracker.try_consume(2, 600, 1000));
+        assert!(tracker.try_consume(1, 400, 1000));
+        assert_eq!(tracker.0[&1], 1000);
+        tracker.0.values_mut().for_each(|bytes| *bytes = 0);
+        assert!(tracker.try_consume(1, 600, 1000));
+    }
+
+    #[test]
+    fn test_fleet_report() {
+        let mut app = App::new();
+        app.add_plugins(ClientPlugin::testing().in_mode(ClientMode::Singleplayer));
+        app.update();
+        let world = app.world_mut();
+        let earth = world.resource::<BodiesMapping>().0["terre"];
+        let (&Position(earth_pos), &Velocity(earth_vel), &Mass(earth_mass)) = world
+            .query::<(&Position, &Velocity, &Mass)>()
+            .get(world, earth)
+            .unwrap();
+        let (spawn_pos, spawn_speed) = circular_orbit_around_body(
+            1e4,
+            earth_mass,
+            earth_pos,
+            earth_vel,
+            &mut rand::thread_rng(),
+        );
+        for id in ["b", "a"] {
+            world.send_event(ShipEvent::Create(ShipInfo {
+                id: id_from(id),
+                spawn_pos,
+                spawn_speed,
+                spawn_orbit: None,
+            }));
+        }
+        app.update();
+        let world = app.world_mut();
+        let a = world.resource::<ShipsMapping>().0["a"];
+        world
+            .entity_mut(a)
+            .insert(Autopilot::new(AutopilotKind::LowThrustSpiral {
+                target_body: id_from("lune"),
+                thrust_accel_km_s2: 1e-6,
+            }));
+        let mut owners = ShipOwners::default();
+        owners.0.insert(id_from("b"), 3);
+        let report = world.run_system_once(move |ships: Query<_>, bodies: Query<_, _>| {
+            fleet_report(&ships, &bodies, &owners).to_string()
+        });
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("id "));
+        // Sorted by id, with the orbit around the Earth
+        for (line, id) in lines[2..].iter().zip(["a", "b"]) {
+            let cells: Vec<_> = line.split("  ").filter(|c| !c.is_empty()).collect();
+            assert_eq!(cells[0].trim(), id);
+            let sma: f64 = cells[3].trim().parse().unwrap();
+            assert!((sma - 1e4).abs() < 1., "{sma}");
+        }
+        assert!(lines[2].contains("spiral to lune (approach)"));
+        assert!(lines[2].ends_with("Singleplayer"));
+        assert!(lines[3].ends_with('3'));
+    }
+
+    /// Bytes of periodic updates sent to all the clients during `updates` updates
+    fn periodic_bytes(server: &mut App, clients: &mut [App], updates: usize) -> usize {
+        // Far from the end of the bandwidth window
+        server
+            .world_mut()
+            .resource_mut::<BandwidthWindowTimer>()
+            .0
+            .reset();
+        server
+            .world_mut()
+            .resource_mut::<BandwidthTracker>()
+            .0
+            .clear();
+        update_linked(server, clients, updates);
+        server
+            .world()
+            .resource::<BandwidthTracker>()
+            .0
+            .values()
+            .sum()
+    }
+
+    #[test]
+    fn test_interest_regions() {
+        let (mut server, mut clients) = linked_apps(2);
+        update_linked(&mut server, &mut clients, 3);
+        let body_pos = |server: &App, id: &str| {
+            let world = server.world();
+            let entity = world.resource::<BodiesMapping>().0[id];
+            world.get::<Position>(entity).unwrap().0
+        };
+        let (earth, jupiter) = (body_pos(&server, "terre"), body_pos(&server, "jupiter"));
+        // Each player owns a ship in the region of the other one, and a ship far from both
+        let far = DVec3::new(0., 0., 1e10);
+        let ships = [
+            (0, "own0", jupiter + DVec3::X * 1e5),
+            (0, "far0", far),
+            (1, "own1", earth + DVec3::X * 1e5),
+            (1, "far1", far + DVec3::X * 1e5),
+        ];
+        for (client, id, pos) in ships {
+            let mut msg = create_msg(id);
+            msg.pos = Position(pos);
+            send_to_server(&mut clients[client], ClientMessage::CreateShipMsg(msg));
+        }
+        update_linked(&mut server, &mut clients, 3);
+        let baseline = periodic_bytes(&mut server, &mut clients, 5);
+
+        for (client, body) in [(0, "terre"), (1, "jupiter")] {
+            send_to_server(
+                &mut clients[client],
+                ClientMessage::InterestUpdate {
+                    focus: InterestFocus::Body(id_from(body)),
+                    radius: 1e6,
+                    always: Vec::new(),
+                },
+            );
+        }
+        update_linked(&mut server, &mut clients, COARSE_UPDATE_PERIOD as usize + 2);
+        assert_eq!(server.world().resource::<ClientInterests>().0.len(), 2);
+
+        for (client, coarse) in [(0, "far1"), (1, "far0")] {
+            let world = clients[client].world();
+            let mapping = &world.resource::<ShipsMapping>().0;
+            assert_eq!(mapping.len(), 4);
+            for (id, &entity) in mapping {
+                let update = world.get::<LastServerUpdate>(entity).unwrap();
+                assert_eq!(
+                    update.coarse,
+                    id.as_str() == coarse,
+                    "client {client}, {id}"
+                );
+            }
+        }
+        assert!(periodic_bytes(&mut server, &mut clients, 5) < baseline);
+    }
+
+    #[test]
+    fn test_duplicate_ship_creation() {
+        let mut ships = ShipsMapping::default();
+        // Two clients create the same ship during the same tick
+        let mut generator = IdGenerator::default();
+        let (accepted, rejected, _) = resolve_ship_creations(
+            &ships,
+            vec![(1, vec![create_msg("s")]), (2, vec![create_msg("s")])],
+            &mut generator,
+        );
+        assert_eq!(accepted.len(), 1);
+        assert_eq!(
+            rejected,
+            vec![(2, id_from("s"), ShipRejectionReason::AlreadyExists)]
+        );
+
+        // A batch with a single conflict is rejected as a whole
+        ships.0.insert(id_from("s"), Entity::PLACEHOLDER);
+        let (accepted, rejected, _) = resolve_ship_creations(
+            &ships,
+            vec![(1, vec![create_msg("a"), create_msg("s")])],
+            &mut generator,
+        );
+        assert!(accepted.is_empty());
+        assert_eq!(
+            rejected,
+            vec![
+                (1, id_from("a"), ShipRejectionReason::BatchRejected),
+                (1, id_from("s"), ShipRejectionReason::AlreadyExists)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_generated_ship_ids() {
+        let mut ships = ShipsMapping::default();
+        ships.0.insert(id_from("probe-1"), Entity::PLACEHOLDER);
+        // Two clients generated the same ids, which are renamed instead of rejected
+        let generated = |id| CreateShipMsg {
+            generated_id: true,
+            ..create_msg(id)
+        };
+        let mut generator = IdGenerator::default();
+        let (accepted, rejected, renamed) = resolve_ship_creations(
+            &ships,
+            vec![
+                (1, vec![generated("probe-1"), generated("probe-2")]),
+                (2, vec![generated("probe-2")]),
+            ],
+            &mut generator,
+        );
+        assert!(rejected.is_empty());
+        let ids: Vec<_> = accepted.iter().map(|(_, m)| m.info.id).collect();
+        assert_eq!(
+            ids,
+            vec![id_from("probe-3"), id_from("probe-2"), id_from("probe-4")]
+        );
+        assert_eq!(
+            renamed,
+            vec![
+                (1, id_from("probe-1"), id_from("probe-3")),
+                (2, id_from("probe-2"), id_from("probe-4"))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_permissions() {
+        let mut owners = ShipOwners::default();
+        owners.0.insert(id_from("mine"), 1);
+        owners.0.insert(id_from("other"), 2);
+        let check = |role, action| check_permission(role, action, 1, &owners);
+
+        // Spectators
+        assert_eq!(
+            check(Role::Spectator, Action::CreateShip),
+            Err(PermissionDenied::Spectator)
+        );
+        assert_eq!(
+            check(Role::Spectator, Action::ControlShip(id_from("mine"))),
+            Err(PermissionDenied::Spectator)
+        );
+        assert_eq!(check(Role::Spectator, Action::SyncBodies), Ok(()));
+
+        // Players
+        assert_eq!(check(Role::Player, Action::CreateShip), Ok(()));
+        assert_eq!(
+            check(Role::Player, Action::ControlShip(id_from("mine"))),
+            Ok(())
+        );
+        assert_eq!(
+            check(Role::Player, Action::ControlShip(id_from("other"))),
+            Err(PermissionDenied::NotOwner(id_from("other")))
+        );
+        assert_eq!(
+            check(Role::Player, Action::ChangeStage),
+            Err(PermissionDenied::AdminOnly)
+        );
+
+        // Admins
+        for action in [Action::ChangeStage, Action::ControlShip(id_from("other"))] {
+            assert_eq!(check(Role::Admin, action), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_max_players() {
+        let mut roles = ClientRoles::default();
+        let max_players = MaxPlayers(Some(2));
+        roles.0.insert(1, Role::Admin);
+        roles.0.insert(2, Role::Spectator);
+        assert_eq!(roles.role_for_new_client(max_players), Role::Player);
+        roles.0.insert(3, Role::Player);
+        assert_eq!(roles.role_for_new_client(max_players), Role::Spectator);
+        assert_eq!(roles.role_for_new_client(MaxPlayers(None)), Role::Player);
+    }
+
+    #[test]
+    fn test_sample_preview() {
+        let predictions: Vec<_> = (1..=1000)
+            .map(|i| (DVec3::splat(i as f64), DVec3::ZERO))
+            .collect();
+        // 100 ticks of 10 simticks, sampled every 2 ticks
+        let samples = sample_preview(500, &predictions, 100);
+        assert_eq!(samples.len() as u64, PREVIEW_SAMPLES);
+        assert_eq!(samples[0].0, 520);
+        assert_eq!(samples[0].1 .0, DVec3::splat(20.));
+        assert_eq!(samples.last().unwrap().0, 1500);
+        // Short previews are sampled every tick
+        assert_eq!(sample_preview(0, &predictions[..30], 3).len(), 3);
+    }
+
+    #[test]
+    fn test_pending_sandboxes() {
+        let request = |client, ship, preview_ticks| SandboxRequest {
+            client,
+            ship: id_from(ship),
+            nodes: default(),
+            preview_ticks,
+        };
+        let mut pending = PendingSandboxes::default();
+        pending.push(request(1, "a", MAX_PREVIEW_TICKS));
+        pending.push(request(2, "a", 10));
+        pending.push(request(2, "b", 10));
+        // The last request of a client for a ship replaces the one waiting
+        pending.push(request(1, "a", MAX_PREVIEW_TICKS / 2));
+        pending.push(request(1, "b", MAX_PREVIEW_TICKS));
+        let batches: Vec<Vec<_>> = std::iter::from_fn(|| {
+            let batch = pending.take_batch();
+            (!batch.is_empty()).then(|| {
+                batch
+                    .iter()
+                    .map(|r| (r.client, r.ship.to_string(), r.preview_ticks))
+                    .collect()
+            })
+        })
+        .collect();
+        let long = MAX_PREVIEW_TICKS;
+        assert_eq!(
+            batches,
+            vec![
+                vec![
+                    (1, "a".into(), long / 2),
+                    (2, "a".into(), 10),
+                    (2, "b".into(), 10)
+                ],
+                vec![(1, "b".into(), long)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ban_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(BAN_LIST_FILE);
+        let mut ban_list = BanList::load(&path).unwrap();
+        assert!(!ban_list.is_banned("eve"));
+        ban_list.names.insert("eve".into());
+        ban_list.save(&path).unwrap();
+
+        // The ban survives a restart of the server
+        let ban_list = BanList::load(&path).unwrap();
+        assert!(ban_list.is_banned("eve"));
+        assert!(!ban_list.is_banned("bob"));
+    }
+
+    #[test]
+    fn test_ban_survives_restart() {
+        let (mut server, mut clients) = linked_apps(1);
+        update_linked(&mut server, &mut clients, 3);
+        server.insert_resource(Arguments("player0".into()));
+        server.world_mut().run_system_once(ban_command);
+        update_linked(&mut server, &mut clients, 2);
+        assert_eq!(
+            clients[0].world().resource::<State<ClientMode>>().get(),
+            &ClientMode::None
+        );
+
+        // A new server reads the ban list saved in the same game files at startup
+        let root = server.world().resource::<GameFiles>().root.clone();
+        let mut restarted = spawn_server();
+        restarted.insert_resource(GameFiles::new(root).unwrap());
+        let mut clients = vec![
+            connect_client(&mut restarted, "player0"),
+            connect_client(&mut restarted, "player1"),
+        ];
+        update_linked(&mut restarted, &mut clients, 5);
+        let banned = clients[0].world();
+        assert_eq!(
+            banned.resource::<State<ClientMode>>().get(),
+            &ClientMode::None
+        );
+        assert_eq!(banned.resource::<DisconnectReason>().0, BANNED_REASON);
+        assert_eq!(
+            clients[1].world().resource::<State<ClientMode>>().get(),
+            &ClientMode::Multiplayer
+        );
+        let names = &restarted.world().resource::<ClientNames>().0;
+        assert_eq!(names.values().collect::<Vec<_>>(), vec!["player1"]);
+    }
+
+    #[test]
+    fn test_migrate_persisted_file() {
+        let dir = tempfile::tempdir().unwrap();
+        // Ban lists written before the envelope are recognized by their name
+        let path = dir.path().join(BAN_LIST_FILE);
+        std::fs::write(&path, r#"{ "names": ["eve"] }"#).unwrap();
+        let (format, version) = migrate_persisted_file(&path).unwrap();
+        assert_eq!((&format[..], version), (BanList::FORMAT, 1));
+        assert!(backup_path(&path).exists());
+        assert!(BanList::load(&path).unwrap().is_banned("eve"));
+
+        let trajectories = dir.path().join(TRAJECTORIES_PATH);
+        std::fs::create_dir(&trajectories).unwrap();
+        let path = trajectories.join("s");
+        std::fs::write(
+            &path,
+            r#"nodes = [[1, { name = "1", thrust = [1.0, 0.0, 0.0], origin = "soleil" }]]"#,
+        )
+        .unwrap();
+        let (format, version) = migrate_persisted_file(&path).unwrap();
+        assert_eq!((&format[..], version), (Trajectory::FORMAT, 1));
+        let (_, version) = read_header(&path).unwrap();
+        assert_eq!(version, Trajectory::VERSION);
+
+        let unknown = dir.path().join("notes.txt");
+        std::fs::write(&unknown, "{}").unwrap();
+        assert!(migrate_persisted_file(&unknown).is_err());
+    }
+
+    #[test]
+    fn test_kick() {
+        let mut server = LoopbackServer::default();
+        let mut kicked = server.connect();
+        let other = server.connect();
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .insert_resource(server)
+            .insert_resource(Clients(vec![1, 2]))
+            .add_event::<KickEvent>()
+            .add_event::<ClientConnectionEvent>()
+            .add_systems(Update, kick_clients);
+        app.world_mut().send_event(KickEvent {
+            client: 1,
+            message: ServerMessage::Kicked("test".into()),
+        });
+        app.update();
+        assert_eq!(app.world().resource::<Clients>().0, vec![2]);
+        let events = app.world().resource::<Events<ClientConnectionEvent>>();
+        assert_eq!(
+            events.iter_current_update_events().collect::<Vec<_>>(),
+            vec![&ClientConnectionEvent::Disconnected(1)]
+        );
+        assert!(matches!(
+            kicked.try_receive(),
+            Some(ServerMessage::Kicked(reason)) if reason == "test"
+        ));
+        assert!(!kicked.is_connected());
+        assert!(other.is_connected());
+    }
+
+    #[test]
+    fn test_tle_command() {
+        let mut app = App::new();
+        app.add_plugins(ServerPlugin::testing());
+        app.update();
+        for id in ["sat", "geo"] {
+            app.world_mut().send_event(ShipEvent::Create(ShipInfo {
+                id: id_from(id),
+                spawn_pos: DVec3::new(1e9, 0., 0.),
+                ..default()
+            }));
+        }
+        app.update();
+        let dir = tempfile::tempdir().unwrap();
+        for (name, lines) in [
+            (
+                "vanguard.txt",
+                "VANGUARD 1
+1 00005U 58002B   00179.78495062  .00000023  00000-0  28098-4 0  4753
+2 00005  34.2682 348.7242 1859667 331.7664  19.3264 10.82419157413667",
+            ),
+            (
+                "geo.txt",
+                "1 28884U 05041A   20003.35307101 -.00000281  00000-0  00000-0 0  9997
+2 28884   0.0164 261.1722 0002431 200.4004 162.3458  1.00272469 52685",
+            ),
+        ] {
+            std::fs::write(dir.path().join(name), lines).unwrap();
+        }
+        let world = app.world_mut();
+        for (id, file) in [("sat", "vanguard.txt"), ("geo", "geo.txt")] {
+            let path = dir.path().join(file);
+            world.insert_resource(Arguments(format!("{id} {}", path.display())));
+            world.run_system_once(tle_command);
+        }
+        world.insert_resource(Arguments("sgp4".into()));
+        world.run_system_once(integration_command);
+        world.insert_resource(Arguments("2451723.5".into()));
+        world.run_system_once(epoch_command);
+
+        let ships = &world.resource::<ShipsMapping>().0;
+        assert!(world.get::<TLEData>(ships["sat"]).is_some());
+        // The deep-space orbits are refused
+        assert!(world.get::<TLEData>(ships["geo"]).is_none());
+        assert_eq!(
+            *world.resource::<IntegrationMethod>(),
+            IntegrationMethod::SGP4
+        );
+        assert_eq!(world.resource::<SimulationEpoch>().0, 2_451_723.5);
+    }
+
+    #[test]
+    fn test_perturbation_log_opt_in() {
+        let mut world = World::new();
+        let ship = world.spawn_empty().id();
+        let mut ships = ShipsMapping::default();
+        ships.0.insert(id_from("s"), ship);
+        world.insert_resource(ships);
+        for (args, logged) in [("s", true), ("s 5", true), ("s off", false)] {
+            world.insert_resource(Arguments(args.into()));
+            world.run_system_once(perturbation_log);
+            assert_eq!(world.get::<AccelerationLog>(ship).is_some(), logged);
+        }
+    }
+
+    #[test]
+    fn test_beacon_command() {
+        let mut world = World::new();
+        let ship = world.spawn_empty().id();
+        let mut ships = ShipsMapping::default();
+        ships.0.insert(id_from("s"), ship);
+        world.insert_resource(ships);
+        for (args, expected) in [
+            ("s 121.5", Some(121.5)),
+            // Invalid frequencies keep the current beacon
+            ("s -3", Some(121.5)),
+            ("s off", None),
+        ] {
+            world.insert_resource(Arguments(args.into()));
+            world.run_system_once(beacon_command);
+            assert_eq!(
+                world.get::<RadioBeacon>(ship).map(|b| b.frequency_mhz),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_gravity_command() {
+        let mut world = World::new();
+        world.init_resource::<GravityModel>();
+        for (args, expected) in [
+            ("j2", GravityModel::J2Only),
+            (
+                "harmonics 4",
+                GravityModel::SphericalHarmonics { max_degree: 4 },
+            ),
+            // Invalid arguments keep the current model
+            (
+                "harmonics 1",
+                GravityModel::SphericalHarmonics { max_degree: 4 },
+            ),
+            ("newton", GravityModel::SphericalHarmonics { max_degree: 4 }),
+            ("point", GravityModel::PointMass),
+        ] {
+            world.insert_resource(Arguments(args.into()));
+            world.run_system_once(gravity_command);
+            assert_eq!(*world.resource::<GravityModel>(), expected);
+        }
+    }
+
+    #[test]
+    fn test_tick_rate_command() {
+        let mut app = App::new();
+        app.add_plugins(ServerPlugin::testing());
+        app.update();
+        let world = app.world_mut();
+        world.resource_mut::<SimStepSize>().0 = 4;
+        let rate = world.resource::<PhysicsRate>().0;
+        // Updates can't last less than a simtick
+        world.insert_resource(Arguments(format!("{}", rate * 4. + 1.)));
+        world.run_system_once(set_tick_rate);
+        assert_eq!(world.resource::<PhysicsRate>().0, rate);
+        world.insert_resource(Arguments(format!("{}", rate * 4.)));
+        world.run_system_once(set_tick_rate);
+        assert_eq!(world.resource::<PhysicsRate>().0, rate * 4.);
+    }
+}
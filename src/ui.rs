@@ -1,10 +1,13 @@
 use bevy::prelude::*;
 use bevy_ratatui::{event::KeyEvent, RatatuiPlugins};
 
-use crate::input::prelude::Keymap;
+use crate::{input::prelude::Keymap, physics::orbit::SystemSize};
+
+use self::widget::space_map::{MapDisplaySettings, SpaceMap};
 
 pub mod gui;
 pub mod screen;
+pub mod spectate;
 pub mod widget;
 
 pub mod prelude {
@@ -38,9 +41,23 @@ impl Plugin for TuiPlugin {
         } else {
             app.add_plugins(RatatuiPlugins::default());
         }
-        app.add_plugins(screen::plugin)
+        app.add_plugins((screen::plugin, spectate::plugin, widget::profiler::plugin))
             .insert_resource(self.keymap.clone())
+            .init_resource::<MapDisplaySettings>()
             .configure_sets(PostUpdate, (UiUpdate, RenderSet).chain())
+            .add_systems(
+                PostUpdate,
+                widget::space_map::update_system_size
+                    .before(UiUpdate)
+                    .run_if(resource_exists::<SpaceMap>)
+                    .run_if(resource_exists_and_changed::<SystemSize>),
+            )
+            .add_systems(
+                PostUpdate,
+                widget::space_map::update_followed_region
+                    .before(UiUpdate)
+                    .run_if(resource_exists_and_changed::<SpaceMap>),
+            )
             .configure_sets(Update, (InputReading, EventHandling).chain());
     }
 }
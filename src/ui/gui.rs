@@ -1,5 +1,5 @@
 use bevy::{
-    color::palettes::css::{BLACK, DARK_GRAY, GOLD, GREEN, TEAL},
+    color::palettes::css::{BLACK, GOLD, GREEN},
     core_pipeline::bloom::BloomSettings,
     input::{
         common_conditions::input_pressed,
@@ -13,39 +13,57 @@ use bevy::{
 };
 
 use crate::{
-    physics::{influence::HillRadius, orbit::SystemSize},
+    physics::{
+        influence::HillRadius,
+        orbit::SystemSize,
+        spatial::SpatialIndex,
+        time::{interpolation_overstep, SimStepSize, TickRateTracker, GAMETIME_PER_SIMTICK},
+    },
     prelude::*,
     utils::{
-        algebra::{center_to_periapsis_direction, ellipse_half_sizes},
+        algebra::{ellipse_half_sizes, periapsis_direction},
         ui::EllipseBuilder,
     },
 };
 
-use self::editor_gui::CurrentGizmo;
+use self::{
+    editor_gui::CurrentGizmo,
+    textures::{generate_planet_textures, BodyTexture},
+};
 
 use super::{
-    widget::space_map::{SpaceMap, ZOOM_STEP},
+    widget::space_map::{SpaceMap, MIN_ZOOM_LEVEL, ZOOM_STEP},
     RenderSet, UiUpdate,
 };
 
 pub mod editor_gui;
+pub mod overlays;
+pub mod textures;
 
 pub const MAX_HEIGHT: f32 = 100000.;
 const MIN_RADIUS: f32 = 1e-4;
 const SCROLL_SENSITIVITY: f32 = 10.;
+/// Distance from a ship under which a click selects it, in transform coordinates at zoom level 1
+const SHIP_SELECTION_RADIUS: f32 = MAX_HEIGHT / 100.;
+/// Factor applied to the colors of the stars, so that they glow through the bloom
+const STAR_BRIGHTNESS: f32 = 10.;
 pub struct GuiPlugin;
 
 impl Plugin for GuiPlugin {
     fn build(&self, app: &mut App) {
         #[cfg(feature = "debug_display")]
         app.init_resource::<DebugDisplay>();
-        app.add_plugins(editor_gui::plugin)
+        app.add_plugins((editor_gui::plugin, overlays::plugin))
             .insert_resource(ClearColor(Color::Srgba(BLACK)))
             .add_event::<SelectObjectEvent>()
-            .add_systems(Startup, (camera_setup, color_setup))
+            .add_systems(Startup, camera_setup)
             .add_systems(
                 OnEnter(Loaded),
-                (insert_display_components, update_transform)
+                (
+                    generate_planet_textures,
+                    insert_display_components,
+                    update_transform,
+                )
                     .chain()
                     .in_set(GUIUpdate),
             )
@@ -119,69 +137,48 @@ impl SelectionRadius {
     }
 }
 
-#[derive(Resource)]
-pub struct Colors {
-    stars: Handle<StandardMaterial>,
-    planets: Handle<StandardMaterial>,
-    other: Handle<StandardMaterial>,
-}
-
 pub fn camera_setup(mut commands: Commands) {
     commands.spawn((
-        Camera3dBundle {
+        Camera2dBundle {
             camera: Camera {
                 hdr: true,
                 ..default()
             },
-            transform: Transform::from_xyz(0., 0., MAX_HEIGHT).looking_at(Vec3::ZERO, Vec3::Y),
-            projection: Projection::Orthographic(OrthographicProjection {
+            transform: Transform::from_xyz(0., 0., MAX_HEIGHT),
+            projection: OrthographicProjection {
                 far: 2. * MAX_HEIGHT,
                 scaling_mode: ScalingMode::FixedVertical(MAX_HEIGHT),
                 ..default()
-            }),
+            },
             ..default()
         },
         BloomSettings::NATURAL,
     ));
 }
 
-pub fn color_setup(mut commands: Commands, mut materials: ResMut<Assets<StandardMaterial>>) {
-    let colors = Colors {
-        stars: materials.add(StandardMaterial {
-            base_color: Color::Srgba(GOLD),
-            emissive: LinearRgba::from(GOLD) * 50.,
-            ..default()
-        }),
-        planets: materials.add(Color::Srgba(TEAL)),
-        other: materials.add(Color::Srgba(DARK_GRAY)),
-    };
-    commands.insert_resource(colors);
-}
-
+/// Displays the bodies as sprites of their texture, as large as the bodies
 fn insert_display_components(
     mut commands: Commands,
-    bodies: Query<(Entity, &BodyInfo)>,
+    bodies: Query<(Entity, &BodyInfo, &BodyTexture)>,
     ships: Query<Entity, With<ShipInfo>>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    colors: Res<Colors>,
     system_size: Res<SystemSize>,
 ) {
     let scale = MAX_HEIGHT as f64 / system_size.0;
-    bodies.iter().for_each(|(e, BodyInfo(data))| {
-        let material = match data.body_type {
-            BodyType::Star => colors.stars.clone(),
-            BodyType::Planet => colors.planets.clone(),
-            _ => colors.other.clone(),
+    bodies.iter().for_each(|(e, BodyInfo(data), texture)| {
+        let color = match data.body_type {
+            BodyType::Star => Color::LinearRgba(LinearRgba::WHITE * STAR_BRIGHTNESS),
+            _ => Color::WHITE,
         };
         commands.entity(e).insert((
-            PbrBundle {
-                mesh: meshes.add(
-                    Sphere {
-                        radius: MIN_RADIUS.max((data.radius * scale) as f32),
-                    }
-                    .mesh(),
-                ),
-                material,
+            SpriteBundle {
+                texture: texture.0.clone(),
+                sprite: Sprite {
+                    color,
+                    custom_size: Some(Vec2::splat(
+                        2. * MIN_RADIUS.max((data.radius * scale) as f32),
+                    )),
+                    ..default()
+                },
                 ..default()
             },
             SelectionRadius {
@@ -189,21 +186,6 @@ fn insert_display_components(
                 actual_radius: (data.radius * scale) as f32,
             },
         ));
-        if matches!(data.body_type, BodyType::Star) {
-            commands.entity(e).with_children(|builder| {
-                builder.spawn(PointLightBundle {
-                    point_light: PointLight {
-                        intensity: (data.radius * scale * 1e3).powi(3) as f32,
-                        color: Color::WHITE,
-                        shadows_enabled: true,
-                        radius: (data.radius * scale) as f32,
-                        range: MAX_HEIGHT,
-                        ..default()
-                    },
-                    ..default()
-                });
-            });
-        }
     });
     for e in ships.iter() {
         commands.entity(e).insert(TransformBundle::default());
@@ -227,10 +209,12 @@ fn adaptive_translation(
 
 fn zoom_with_scroll(mut events: EventReader<MouseWheel>, mut space_map: ResMut<SpaceMap>) {
     for event in events.read() {
-        space_map.zoom_level *= ZOOM_STEP.powf(match event.unit {
-            MouseScrollUnit::Line => event.y,
-            MouseScrollUnit::Pixel => event.y * SCROLL_SENSITIVITY,
-        } as f64);
+        space_map.zoom_level = (space_map.zoom_level
+            * ZOOM_STEP.powf(match event.unit {
+                MouseScrollUnit::Line => event.y,
+                MouseScrollUnit::Pixel => event.y * SCROLL_SENSITIVITY,
+            } as f64))
+        .max(MIN_ZOOM_LEVEL);
     }
 }
 
@@ -241,6 +225,10 @@ fn pan_when_dragging(mut motions: EventReader<MouseMotion>, mut map: ResMut<Spac
     }
 }
 
+/// Selects the object under the cursor on a click. Ships have no [SelectionRadius], they are
+/// looked up in the [SpatialIndex] by their distance to the clicked point in the ecliptic plane,
+/// whatever their height above it
+#[allow(clippy::too_many_arguments)]
 fn send_select_object_event(
     mut clicks: EventReader<MouseButtonInput>,
     window: Query<&Window, With<PrimaryWindow>>,
@@ -248,6 +236,8 @@ fn send_select_object_event(
     mut writer: EventWriter<SelectObjectEvent>,
     objects: Query<(Entity, &GlobalTransform, &SelectionRadius)>,
     map: Res<SpaceMap>,
+    ships: Res<SpatialIndex>,
+    system_size: Res<SystemSize>,
 ) {
     let (cam, cam_transform) = cam.single();
     for event in clicks.read() {
@@ -257,15 +247,26 @@ fn send_select_object_event(
         ) {
             if let Some(cursor_pos) = window.single().cursor_position() {
                 if let Some(translation) = cam.viewport_to_world_2d(cam_transform, cursor_pos) {
-                    objects
+                    let scale = MAX_HEIGHT as f64 / system_size.0;
+                    let selected = objects
                         .iter()
                         .find(|(_, pos, rad)| {
                             (pos.translation().xy() - translation).length()
                                 < rad.radius(map.zoom_level)
                         })
-                        .map(|(entity, _, _)| {
-                            writer.send(SelectObjectEvent { entity, cursor_pos })
+                        .map(|(entity, _, _)| entity)
+                        .or_else(|| {
+                            let point = translation.as_dvec2() / scale;
+                            let tolerance = SHIP_SELECTION_RADIUS as f64 / map.zoom_level / scale;
+                            ships
+                                .nearest_in_plane(point, 1)
+                                .first()
+                                .filter(|(_, distance)| *distance < tolerance)
+                                .map(|(entity, _)| *entity)
                         });
+                    if let Some(entity) = selected {
+                        writer.send(SelectObjectEvent { entity, cursor_pos });
+                    }
                 }
             }
         }
@@ -274,29 +275,81 @@ fn send_select_object_event(
 
 fn update_camera_pos(
     space_map: Res<SpaceMap>,
-    mut cam: Query<(&mut Transform, &mut Projection)>,
-    positions: Query<&Position>,
+    mut cam: Query<(&mut Transform, &mut OrthographicProjection)>,
+    transforms: Query<&Transform, Without<OrthographicProjection>>,
 ) {
     let scale = MAX_HEIGHT as f64 / space_map.system_size;
     let (mut cam_pos, mut proj) = cam.single_mut();
-    let focus_pos = space_map
+    // Follow the interpolated position of the focus rather than its last simulated one
+    let focus_translation = space_map
         .focus_body
-        .map_or(DVec3::default(), |f| positions.get(f).unwrap().0);
-    cam_pos.translation = ((focus_pos
-        + DVec3::new(space_map.offset_amount.x, space_map.offset_amount.y, 0.))
-        * scale)
-        .as_vec3()
+        .and_then(|f| transforms.get(f).ok())
+        .map_or(Vec3::ZERO, |t| t.translation);
+    cam_pos.translation = focus_translation
+        + (DVec3::new(space_map.offset_amount.x, space_map.offset_amount.y, 0.) * scale).as_vec3()
         + MAX_HEIGHT * Vec3::Z;
-    if let Projection::Orthographic(ortho) = proj.as_mut() {
-        ortho.scale = (1. / space_map.zoom_level) as f32;
-    }
+    proj.scale = (1. / space_map.zoom_level) as f32;
 }
 
-fn update_transform(system_size: Res<SystemSize>, mut query: Query<(&mut Transform, &Position)>) {
+/// Renders objects where they are between two fixed updates: bodies from their orbits, and ships
+/// by extrapolating their last integrated state. The simulated positions are left untouched
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn update_transform(
+    system_size: Res<SystemSize>,
+    mut query: Query<(
+        &mut Transform,
+        &Position,
+        Option<&Velocity>,
+        Option<&Acceleration>,
+        Option<&BodyInfo>,
+    )>,
+    orbits: Query<(&EllipticalOrbit, &BodyInfo)>,
+    mapping: Option<Res<BodiesMapping>>,
+    game_time: Res<GameTime>,
+    fixed: Res<Time<Fixed>>,
+    step: Res<SimStepSize>,
+    toggle: Res<ToggleTime>,
+    tracker: Res<TickRateTracker>,
+) {
     let scale = MAX_HEIGHT as f64 / system_size.0;
-    for (mut transform, Position(pos)) in query.iter_mut() {
-        transform.translation = (*pos * scale).as_vec3();
+    let overstep = interpolation_overstep(&fixed, &step, toggle.0 && !tracker.throttling());
+    let time = game_time.interpolated(overstep);
+    let dt = overstep * GAMETIME_PER_SIMTICK;
+    for (mut transform, Position(pos), velocity, acceleration, info) in query.iter_mut() {
+        let pos = match (info, velocity, acceleration) {
+            (Some(BodyInfo(data)), _, _) => mapping
+                .as_ref()
+                .and_then(|mapping| body_position_at(data.id, time, &orbits, mapping))
+                .unwrap_or(*pos),
+            (None, Some(velocity), Some(acceleration)) => {
+                *pos + velocity.0 * dt + acceleration.current * dt * dt / 2.
+            }
+            _ => *pos,
+        };
+        transform.translation = (pos * scale).as_vec3();
+    }
+}
+
+/// Position of a body at the given time (in days), adding up its position relative to each of
+/// its hosts. None if the hierarchy is broken
+fn body_position_at(
+    id: BodyID,
+    time: f64,
+    orbits: &Query<(&EllipticalOrbit, &BodyInfo)>,
+    mapping: &BodiesMapping,
+) -> Option<DVec3> {
+    let mut pos = DVec3::ZERO;
+    let mut current = Some(id);
+    // Bounded by the number of bodies in case the hierarchy contains a cycle
+    for _ in 0..=mapping.0.len() {
+        let Some(id) = current else {
+            return Some(pos);
+        };
+        let (orbit, BodyInfo(data)) = orbits.get(*mapping.0.get(&id)?).ok()?;
+        pos += orbit.state_at(time).0;
+        current = data.host_body;
     }
+    None
 }
 
 #[allow(non_snake_case)]
@@ -363,10 +416,8 @@ fn draw_gizmos(
                 //         continue;
                 //     }
                 // }
-                let position =
-                    (scale * (peri - a) * center_to_periapsis_direction(o, O, I).normalize())
-                        .as_vec3()
-                        + parent_translation;
+                let position = (scale * (peri - a) * periapsis_direction(o, O, I)).as_vec3()
+                    + parent_translation;
                 let resolution = ((zoom_level * 100.) as usize).min(1000);
                 EllipseBuilder {
                     position,
@@ -1,4 +1,5 @@
 use crate::{
+    client::ManeuverPreview,
     physics::{predictions::Prediction, time::SIMTICKS_PER_TICK},
     prelude::*,
     ui::{
@@ -41,7 +42,11 @@ pub fn plugin(app: &mut App) {
     app.init_resource::<CurrentGizmo>()
         .add_systems(
             PostUpdate,
-            (draw_predictions, draw_maneuver_node)
+            (
+                draw_predictions,
+                draw_maneuver_node,
+                draw_maneuver_preview.run_if(resource_exists::<ManeuverPreview>),
+            )
                 .in_set(RenderSet)
                 .run_if(resource_exists::<EditorContext>),
         )
@@ -119,6 +124,26 @@ fn draw_predictions(
     }
 }
 
+/// Draws the trajectory previewed by the server as a faded line
+fn draw_maneuver_preview(
+    mut gizmos: Gizmos,
+    preview: Res<ManeuverPreview>,
+    context: Res<EditorContext>,
+    system_size: Res<SystemSize>,
+) {
+    if preview.ship != context.ship_info.id {
+        return;
+    }
+    let scale = MAX_HEIGHT as f64 / system_size.0;
+    gizmos.linestrip(
+        preview
+            .positions
+            .iter()
+            .map(|(_, Position(pos))| (*pos * scale).as_vec3()),
+        Color::WHITE.with_alpha(0.3),
+    );
+}
+
 fn spawn_arrows(
     context: Res<EditorContext>,
     space_map: Res<SpaceMap>,
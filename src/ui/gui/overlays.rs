@@ -0,0 +1,243 @@
+use bevy::{
+    color::palettes::css::{LIGHT_BLUE, MAGENTA, ORANGE, PURPLE},
+    prelude::*,
+    window::PrimaryWindow,
+};
+use bevy_ratatui::event::KeyEvent;
+use crossterm::event::KeyEventKind;
+
+use crate::{
+    input::prelude::Keymap,
+    physics::AU,
+    prelude::*,
+    ui::{widget::space_map::SpaceMap, RenderSet},
+    utils::algebra::{
+        ascending_node_direction, orbit_normal, periapsis_direction, spin_axis_direction,
+    },
+};
+
+use super::MAX_HEIGHT;
+
+/// Radii of the distance rings of the ecliptic grid (in AU)
+const ECLIPTIC_RINGS: [f64; 4] = [0.5, 1., 5., 10.];
+
+/// Rings smaller than this on screen (in pixels) are not drawn
+const MIN_RING_PIXELS: f32 = 4.;
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<GuiOverlaySettings>()
+        .add_systems(Startup, spawn_ring_labels)
+        .add_systems(Update, toggle_overlays)
+        .add_systems(
+            PostUpdate,
+            (
+                draw_ecliptic_grid,
+                draw_selected_body_overlays,
+                draw_waypoints,
+            )
+                .in_set(RenderSet)
+                .run_if(resource_exists::<SpaceMap>)
+                .run_if(in_state(Loaded)),
+        );
+}
+
+/// Visibility of the reference geometry drawn on top of the GUI
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq)]
+pub struct GuiOverlaySettings {
+    pub ecliptic_grid: bool,
+    pub equatorial_planes: bool,
+    pub orbit_axes: bool,
+}
+
+#[derive(Component)]
+struct RingLabel(f64);
+
+fn spawn_ring_labels(mut commands: Commands) {
+    for radius in ECLIPTIC_RINGS {
+        commands.spawn((
+            TextBundle::from_section(
+                format!("{radius} AU"),
+                TextStyle {
+                    font_size: 14.,
+                    color: Color::WHITE.with_alpha(0.4),
+                    ..default()
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                ..default()
+            }),
+            Visibility::Hidden,
+            RingLabel(radius),
+        ));
+    }
+}
+
+fn toggle_overlays(
+    mut key_event: EventReader<KeyEvent>,
+    keymap: Res<Keymap>,
+    mut settings: ResMut<GuiOverlaySettings>,
+) {
+    let keymap = &keymap.gui;
+    for KeyEvent(event) in key_event.read() {
+        if event.kind == KeyEventKind::Release {
+            continue;
+        }
+        match event {
+            e if keymap.toggle_ecliptic_grid.matches(e) => {
+                settings.ecliptic_grid = !settings.ecliptic_grid
+            }
+            e if keymap.toggle_equatorial_planes.matches(e) => {
+                settings.equatorial_planes = !settings.equatorial_planes
+            }
+            e if keymap.toggle_orbit_axes.matches(e) => settings.orbit_axes = !settings.orbit_axes,
+            _ => {}
+        }
+    }
+}
+
+/// Number of pixels per transform unit for the current zoom level
+fn pixels_per_unit(window: &Window, zoom_level: f64) -> f32 {
+    window.height() * zoom_level as f32 / MAX_HEIGHT
+}
+
+fn draw_ecliptic_grid(
+    settings: Res<GuiOverlaySettings>,
+    space_map: Res<SpaceMap>,
+    mut gizmos: Gizmos,
+    mut labels: Query<(&mut Style, &mut Visibility, &RingLabel)>,
+    primary: Query<&Transform, With<PrimaryBody>>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    cam: Query<(&Camera, &GlobalTransform)>,
+) {
+    let (Ok(window), Ok((cam, cam_transform)), Ok(center)) =
+        (window.get_single(), cam.get_single(), primary.get_single())
+    else {
+        return;
+    };
+    let scale = MAX_HEIGHT as f64 / space_map.system_size;
+    let pixels_per_unit = pixels_per_unit(window, space_map.zoom_level);
+    for (mut style, mut visibility, RingLabel(radius)) in labels.iter_mut() {
+        let radius = (radius * AU * scale) as f32;
+        *visibility = Visibility::Hidden;
+        if !settings.ecliptic_grid || radius * pixels_per_unit < MIN_RING_PIXELS {
+            continue;
+        }
+        gizmos
+            .circle_2d(
+                center.translation.xy(),
+                radius,
+                Color::WHITE.with_alpha(0.15),
+            )
+            .resolution(128);
+        if let Some(label_pos) =
+            cam.world_to_viewport(cam_transform, center.translation + radius * Vec3::Y)
+        {
+            style.left = Val::Px(label_pos.x);
+            style.top = Val::Px(label_pos.y);
+            *visibility = Visibility::Visible;
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+fn draw_selected_body_overlays(
+    settings: Res<GuiOverlaySettings>,
+    space_map: Res<SpaceMap>,
+    mut gizmos: Gizmos,
+    bodies: Query<(&Transform, &BodyInfo, &EllipticalOrbit)>,
+    mapping: Res<BodiesMapping>,
+) {
+    let Some((transform, info, orbit)) = space_map.selected.and_then(|s| bodies.get(s).ok()) else {
+        return;
+    };
+    let scale = MAX_HEIGHT as f64 / space_map.system_size;
+    let min_size = MAX_HEIGHT as f64 / (50. * space_map.zoom_level);
+    if settings.equatorial_planes {
+        // The selected body and its moons
+        for (transform, BodyInfo(data), orbit) in std::iter::once((transform, info, orbit)).chain(
+            bodies.iter_many(
+                info.0
+                    .orbiting_bodies
+                    .iter()
+                    .filter_map(|id| mapping.0.get(id)),
+            ),
+        ) {
+            let axis = spin_axis_direction(
+                data.axial_tilt.to_radians(),
+                orbit.long_asc_node.to_radians(),
+                orbit.inclination.to_radians(),
+            );
+            let size = (2. * data.radius * scale).max(min_size) as f32;
+            let axis = axis.as_vec3();
+            gizmos.circle(
+                transform.translation,
+                Dir3::new(axis).unwrap_or(Dir3::Z),
+                size,
+                Color::Srgba(LIGHT_BLUE).with_alpha(0.4),
+            );
+            gizmos.line(
+                transform.translation - axis * size * 1.5,
+                transform.translation + axis * size * 1.5,
+                Color::Srgba(LIGHT_BLUE).with_alpha(0.6),
+            );
+        }
+    }
+    if settings.orbit_axes {
+        let Some(host) = info
+            .0
+            .host_body
+            .and_then(|id| mapping.0.get(&id))
+            .and_then(|&e| bodies.get(e).ok())
+        else {
+            return;
+        };
+        let &EllipticalOrbit {
+            semimajor_axis: a,
+            eccentricity: e,
+            inclination: I,
+            long_asc_node: O,
+            arg_periapsis: o,
+            ..
+        } = orbit;
+        let (o, O, I) = (o.to_radians(), O.to_radians(), I.to_radians());
+        let focus = host.0.translation;
+        let radius_at = |true_anomaly: f64| a * (1. - e * e) / (1. + e * true_anomaly.cos());
+
+        // Line of nodes, between the ascending and the descending node
+        let node = ascending_node_direction(O);
+        gizmos.line(
+            focus + (node * radius_at(-o) * scale).as_vec3(),
+            focus - (node * radius_at(std::f64::consts::PI - o) * scale).as_vec3(),
+            Color::Srgba(PURPLE),
+        );
+        // Periapsis direction
+        gizmos.line(
+            focus,
+            focus + (periapsis_direction(o, O, I) * a * (1. - e) * scale).as_vec3(),
+            Color::Srgba(ORANGE),
+        );
+        // Orbit normal, drawn at a fixed screen size
+        gizmos.line(
+            focus,
+            focus + (orbit_normal(O, I) * min_size).as_vec3(),
+            Color::Srgba(PURPLE).with_alpha(0.5),
+        );
+    }
+}
+
+/// Marks each waypoint with a cross of a fixed screen size
+fn draw_waypoints(space_map: Res<SpaceMap>, waypoints: Res<WaypointPositions>, mut gizmos: Gizmos) {
+    let scale = MAX_HEIGHT as f64 / space_map.system_size;
+    let size = (MAX_HEIGHT as f64 / (150. * space_map.zoom_level)) as f32;
+    for pos in waypoints.0.values() {
+        let center = (*pos * scale).as_vec3().xy();
+        for diagonal in [Vec2::ONE, Vec2::new(1., -1.)] {
+            gizmos.line_2d(
+                center - diagonal * size,
+                center + diagonal * size,
+                Color::Srgba(MAGENTA),
+            );
+        }
+    }
+}
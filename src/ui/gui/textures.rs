@@ -0,0 +1,175 @@
+//! Textures of the bodies, generated from a noise heightmap colored according to their surface
+
+use std::f64::consts::PI;
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+use rand::Rng;
+
+use crate::{game::WorldSeed, prelude::*, utils::noise::Perlin};
+
+/// Width and height of the body textures (in pixels)
+pub const TEXTURE_SIZE: u32 = 128;
+
+/// Bodies larger than this are gas giants (in km)
+const GAS_GIANT_MIN_RADIUS: f64 = 15_000.;
+
+/// Bodies less dense than this are mostly made of ice (in g/cm³)
+const ICY_MAX_DENSITY: f64 = 2.5;
+
+#[derive(Component, Debug, Clone)]
+pub struct BodyTexture(pub Handle<Image>);
+
+/// What the surface of a body looks like, which decides the colors of its texture
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SurfaceKind {
+    Star,
+    Rocky,
+    Gaseous,
+    Icy,
+}
+
+impl SurfaceKind {
+    /// Guesses the surface of a body from its size and mean density
+    pub fn of(data: &BodyData) -> Self {
+        // kg/km³ to g/cm³
+        let density = data.mass / (4. / 3. * PI * data.radius.powi(3)) * 1e-12;
+        match data.body_type {
+            BodyType::Star => Self::Star,
+            BodyType::Comet => Self::Icy,
+            _ if data.radius > GAS_GIANT_MIN_RADIUS => Self::Gaseous,
+            _ if data.mass > 0. && density < ICY_MAX_DENSITY => Self::Icy,
+            _ => Self::Rocky,
+        }
+    }
+
+    /// Colors of the lowest and highest points of the heightmap
+    fn palette(&self) -> [[f64; 3]; 2] {
+        match self {
+            Self::Star => [[255., 170., 50.], [255., 250., 220.]],
+            Self::Rocky => [[25., 60., 150.], [90., 160., 80.]],
+            Self::Gaseous => [[185., 110., 55.], [235., 205., 155.]],
+            Self::Icy => [[170., 190., 215.], [250., 250., 255.]],
+        }
+    }
+
+    /// Height in [0, 1] at the given point of the texture (with coordinates in [0, 1])
+    fn height(&self, perlin: &Perlin, x: f64, y: f64) -> f64 {
+        let noise = match self {
+            // Gas giants are banded, so their features are stretched along the equator
+            Self::Gaseous => perlin.fbm(x * 1.5, y * 12., 4),
+            _ => perlin.fbm(x * 4., y * 4., 5),
+        };
+        (noise + 1.) / 2.
+    }
+}
+
+/// Texture of a disk colored by a heightmap, transparent around the disk
+pub fn body_texture(kind: SurfaceKind, seed: u64) -> Image {
+    let perlin = Perlin::new(seed);
+    let [low, high] = kind.palette();
+    let half = TEXTURE_SIZE as f64 / 2.;
+    let mut data = Vec::with_capacity((TEXTURE_SIZE * TEXTURE_SIZE * 4) as usize);
+    for j in 0..TEXTURE_SIZE {
+        for i in 0..TEXTURE_SIZE {
+            let (x, y) = (i as f64 + 0.5, j as f64 + 0.5);
+            let distance = ((x - half).powi(2) + (y - half).powi(2)).sqrt() / half;
+            if distance > 1. {
+                data.extend([0; 4]);
+                continue;
+            }
+            let t = kind.height(&perlin, x / TEXTURE_SIZE as f64, y / TEXTURE_SIZE as f64);
+            // Darker towards the edge of the disk, so that it looks like a sphere
+            let shade = 1. - 0.4 * distance.powi(4);
+            data.extend((0..3).map(|c| ((low[c] + t * (high[c] - low[c])) * shade).round() as u8));
+            data.push(u8::MAX);
+        }
+    }
+    Image::new(
+        Extent3d {
+            width: TEXTURE_SIZE,
+            height: TEXTURE_SIZE,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD,
+    )
+}
+
+/// Generates the texture of each body, which is the same for the same world seed
+pub fn generate_planet_textures(
+    mut commands: Commands,
+    bodies: Query<(Entity, &BodyInfo)>,
+    mut images: ResMut<Assets<Image>>,
+    seed: Res<WorldSeed>,
+) {
+    for (e, BodyInfo(data)) in bodies.iter() {
+        let texture = body_texture(
+            SurfaceKind::of(data),
+            seed.rng(("body_texture", data.id)).gen(),
+        );
+        commands.entity(e).insert(BodyTexture(images.add(texture)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    use super::{body_texture, SurfaceKind, TEXTURE_SIZE};
+
+    fn body(body_type: BodyType, mass: f64, radius: f64) -> BodyData {
+        BodyDataBuilder::new(id_from("body"))
+            .with_type(body_type)
+            .with_mass(mass)
+            .with_radius(radius)
+            .build()
+    }
+
+    #[test]
+    fn test_surface_kind() {
+        let kind = |body_type, mass, radius| SurfaceKind::of(&body(body_type, mass, radius));
+        assert_eq!(kind(BodyType::Star, 1.989e30, 696_342.), SurfaceKind::Star);
+        assert_eq!(kind(BodyType::Planet, 5.972e24, 6_371.), SurfaceKind::Rocky);
+        assert_eq!(
+            kind(BodyType::Planet, 1.898e27, 69_911.),
+            SurfaceKind::Gaseous
+        );
+        // Ganymede
+        assert_eq!(kind(BodyType::Moon, 1.482e23, 2_634.), SurfaceKind::Icy);
+        // Bodies of unknown mass are assumed rocky
+        assert_eq!(kind(BodyType::Asteroid, 0., 10.), SurfaceKind::Rocky);
+    }
+
+    #[test]
+    fn test_body_texture() {
+        let pixel = |image: &bevy::render::texture::Image, i: u32, j: u32| {
+            let start = ((j * TEXTURE_SIZE + i) * 4) as usize;
+            image.data[start..start + 4].to_vec()
+        };
+        let center = TEXTURE_SIZE / 2;
+        let image = body_texture(SurfaceKind::Gaseous, 1);
+        assert_eq!(image.data.len(), (TEXTURE_SIZE * TEXTURE_SIZE * 4) as usize);
+        assert_eq!(pixel(&image, 0, 0)[3], 0);
+        let [r, g, b, a] = pixel(&image, center, center)[..] else {
+            unreachable!()
+        };
+        assert!(r > g && g > b && a == u8::MAX);
+
+        let rocky = body_texture(SurfaceKind::Rocky, 1);
+        let [r, _, b, _] = pixel(&rocky, center, center)[..] else {
+            unreachable!()
+        };
+        assert!(b > r);
+        // The same seed gives the same texture
+        assert_eq!(body_texture(SurfaceKind::Rocky, 1).data, rocky.data);
+        assert_ne!(body_texture(SurfaceKind::Rocky, 2).data, rocky.data);
+    }
+}
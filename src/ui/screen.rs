@@ -1,21 +1,40 @@
 use bevy::prelude::*;
 use bevy_ratatui::{event::KeyEvent, terminal::RatatuiContext};
+use bodies::{BodiesEditorContext, BodiesEditorScreen};
 use editor::{EditorContext, EditorScreen};
 use explorer::{ExplorerContext, ExplorerScreen};
 use fleet::{FleetContext, FleetScreen};
+use inspector::{InspectorContext, InspectorScreen};
+use lobby::LobbyScreen;
 use start::{StartMenu, StartMenuContext};
 
 use crate::{
-    client::ClientMode,
+    client::{ClientMode, ClientRole, PlayerName, ServerNetworkInfo, SyncStatus},
+    game::loading::LoadingProgress,
+    input::prelude::Keymap,
+    network::time_sync::ClockSync,
     objects::ships::ShipID,
+    physics::time::{MaxSimSpeed, TickRateTracker},
     prelude::{exit_on_error_if_app, Loaded},
 };
 
-use super::{widget::space_map::SpaceMap, InputReading, RenderSet};
+use super::{
+    spectate::{banner_area, SpectateBanner, SpectateTarget},
+    widget::{
+        clock::{clock_area, slider_area, GameClock, SpeedSlider},
+        loading::LoadingScreen,
+        profiler::{overlay_area, ProfilerOverlay, ProfilerReport, ProfilerTable},
+        space_map::SpaceMap,
+    },
+    InputReading, RenderSet,
+};
 
+pub mod bodies;
 pub mod editor;
 pub mod explorer;
 pub mod fleet;
+pub mod inspector;
+pub mod lobby;
 pub mod start;
 
 /// A resource storing the current screen
@@ -25,9 +44,14 @@ pub mod start;
 pub enum AppScreen {
     #[default]
     StartMenu,
+    /// Waiting for the system of the server in multiplayer
+    Lobby,
     Explorer,
     Fleet,
     Editor(ShipID),
+    Bodies,
+    /// Reflected components of any entity
+    Inspector(Entity),
 }
 
 #[derive(Resource, Default, Debug)]
@@ -39,6 +63,9 @@ pub fn plugin(app: &mut App) {
         explorer::plugin,
         fleet::plugin,
         editor::plugin,
+        bodies::plugin,
+        inspector::plugin,
+        lobby::plugin,
     ))
     .init_state::<AppScreen>()
     .init_resource::<PreviousScreen>()
@@ -53,13 +80,12 @@ pub fn plugin(app: &mut App) {
             .run_if(state_changed::<AppScreen>),
     )
     .add_systems(
-        OnEnter(ClientMode::Explorer),
-        move |mut next_screen: ResMut<NextState<AppScreen>>| next_screen.set(AppScreen::Explorer),
-    )
-    .add_systems(
-        OnEnter(ClientMode::Singleplayer),
-        move |mut next_screen: ResMut<NextState<AppScreen>>| next_screen.set(AppScreen::Fleet),
+        Update,
+        cancel_loading
+            .in_set(InputReading)
+            .run_if(resource_exists::<LoadingProgress>),
     )
+    .add_systems(OnEnter(Loaded), open_mode_screen)
     .add_systems(
         PostUpdate,
         render
@@ -83,6 +109,31 @@ fn clear_key_events(mut events: ResMut<Events<KeyEvent>>) {
     events.clear();
 }
 
+/// The screen of a mode is opened once the system is loaded, since it needs the bodies
+fn open_mode_screen(mode: Res<State<ClientMode>>, mut next_screen: ResMut<NextState<AppScreen>>) {
+    match mode.get() {
+        ClientMode::Explorer => next_screen.set(AppScreen::Explorer),
+        ClientMode::Singleplayer | ClientMode::Multiplayer => next_screen.set(AppScreen::Fleet),
+        _ => {}
+    }
+}
+
+/// The other inputs are locked while a system loads
+fn cancel_loading(
+    mut key_event: EventReader<KeyEvent>,
+    keymap: Res<Keymap>,
+    mut next_mode: ResMut<NextState<ClientMode>>,
+) {
+    if key_event
+        .read()
+        .any(|KeyEvent(event)| keymap.start_menu.back.matches(event))
+    {
+        info!("loading cancelled");
+        next_mode.set(ClientMode::None);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn render(
     mut ctx: ResMut<RatatuiContext>,
     screen: Res<State<AppScreen>>,
@@ -90,28 +141,88 @@ fn render(
     explorer: Option<ResMut<ExplorerContext>>,
     fleet: Option<ResMut<FleetContext>>,
     editor: Option<ResMut<EditorContext>>,
+    bodies: Option<ResMut<BodiesEditorContext>>,
+    inspector: Option<ResMut<InspectorContext>>,
     space_map: Option<ResMut<SpaceMap>>,
+    (profiler, report): (Res<ProfilerOverlay>, Res<ProfilerReport>),
+    spectate: Res<SpectateTarget>,
+    clock: Res<ClockSync>,
+    (max_speed, tick_rate): (Res<MaxSimSpeed>, Res<TickRateTracker>),
+    loading: Option<Res<LoadingProgress>>,
+    lobby: (
+        Res<ServerNetworkInfo>,
+        Res<PlayerName>,
+        Res<State<SyncStatus>>,
+        Res<ClientRole>,
+    ),
 ) -> color_eyre::Result<()> {
-    ctx.draw(|f| match screen.get() {
-        AppScreen::StartMenu => {
-            f.render_stateful_widget(StartMenu, f.size(), start_menu.unwrap().as_mut())
+    ctx.draw(|f| {
+        if let Some(progress) = &loading {
+            f.render_widget(LoadingScreen(progress), f.size());
+            return;
         }
-        AppScreen::Explorer => {
-            if let Some(mut explorer) = explorer {
-                f.render_stateful_widget(
-                    ExplorerScreen {
-                        map: space_map.unwrap().as_mut(),
+        match screen.get() {
+            AppScreen::StartMenu => {
+                f.render_stateful_widget(StartMenu, f.size(), start_menu.unwrap().as_mut())
+            }
+            AppScreen::Lobby => {
+                let (server, player, status, role) = &lobby;
+                f.render_widget(
+                    LobbyScreen {
+                        server,
+                        player,
+                        status: status.get(),
+                        role,
                     },
                     f.size(),
-                    explorer.as_mut(),
                 )
             }
+            AppScreen::Explorer => {
+                if let Some(mut explorer) = explorer {
+                    f.render_stateful_widget(
+                        ExplorerScreen {
+                            map: space_map.unwrap().as_mut(),
+                        },
+                        f.size(),
+                        explorer.as_mut(),
+                    )
+                }
+            }
+            AppScreen::Fleet => {
+                f.render_stateful_widget(FleetScreen, f.size(), fleet.unwrap().as_mut())
+            }
+            AppScreen::Editor(_) => {
+                f.render_stateful_widget(EditorScreen, f.size(), editor.unwrap().as_mut())
+            }
+            AppScreen::Bodies => {
+                if let Some(mut bodies) = bodies {
+                    f.render_stateful_widget(BodiesEditorScreen, f.size(), bodies.as_mut())
+                }
+            }
+            AppScreen::Inspector(_) => {
+                if let Some(mut inspector) = inspector {
+                    f.render_stateful_widget(InspectorScreen, f.size(), inspector.as_mut())
+                }
+            }
+        }
+        if !matches!(screen.get(), AppScreen::StartMenu | AppScreen::Lobby) {
+            // In multiplayer, the estimated time of the server rather than the last one received
+            let clock = GameClock(clock.displayed_tick());
+            let area = clock_area(f.size(), &clock);
+            f.render_widget(clock, area);
+            if *screen.get() == AppScreen::Explorer {
+                let slider = SpeedSlider {
+                    max: max_speed.ticks_per_second,
+                    current: tick_rate.ticks_per_second(),
+                };
+                f.render_widget(slider, slider_area(area));
+            }
         }
-        AppScreen::Fleet => {
-            f.render_stateful_widget(FleetScreen, f.size(), fleet.unwrap().as_mut())
+        if let Some(target) = &spectate.target {
+            f.render_widget(SpectateBanner(target), banner_area(f.size(), target));
         }
-        AppScreen::Editor(_) => {
-            f.render_stateful_widget(EditorScreen, f.size(), editor.unwrap().as_mut())
+        if profiler.enabled {
+            f.render_widget(ProfilerTable(&report), overlay_area(f.size()));
         }
     })?;
     Ok(())
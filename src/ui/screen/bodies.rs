@@ -0,0 +1,601 @@
+use std::{error::Error, num::ParseFloatError};
+
+use bevy::prelude::*;
+use bevy_ratatui::event::KeyEvent;
+use crossterm::event::{KeyCode, KeyEventKind};
+use ratatui::{
+    layout::{Constraint, Layout},
+    style::Stylize,
+    text::Line,
+    widgets::{Block, List, ListState, Paragraph, StatefulWidget, Widget, Wrap},
+};
+
+use crate::{
+    game::GameFiles,
+    objects::bodies::{body_data::BodyData, save_user_bodies},
+    physics::influence::{setup_hill_spheres, update_hill_radii, update_influence},
+    physics::orbit::{update_global, update_local, update_system_size},
+    prelude::*,
+    utils::list::OptionsList,
+};
+
+pub fn plugin(app: &mut App) {
+    app.add_event::<BodiesEditorEvent>()
+        .add_systems(
+            Update,
+            (
+                read_input.in_set(InputReading),
+                handle_bodies_editor_events.in_set(EventHandling),
+            )
+                .run_if(in_loaded_screen::<BodiesEditorContext>(AppScreen::Bodies)),
+        )
+        .add_systems(
+            Update,
+            (
+                update_local,
+                update_global,
+                update_system_size,
+                setup_hill_spheres,
+                update_hill_radii,
+                update_influence,
+            )
+                .chain()
+                .after(EventHandling)
+                .run_if(in_state(Loaded).and_then(on_event::<BodiesChanged>())),
+        )
+        .add_systems(OnEnter(AppScreen::Bodies), create_screen)
+        .add_systems(OnExit(AppScreen::Bodies), clear_screen);
+}
+
+fn create_screen(mut commands: Commands, bodies: Query<&BodyInfo>) {
+    commands.insert_resource(BodiesEditorContext::new(bodies.iter().map(|b| b.0.clone())));
+}
+
+fn clear_screen(mut commands: Commands) {
+    commands.remove_resource::<BodiesEditorContext>();
+}
+
+#[derive(Event, Debug, Clone, Copy)]
+pub enum BodiesEditorEvent {
+    Select(Direction2),
+    Apply,
+    Save,
+    Revert,
+    Back,
+}
+
+#[derive(Clone, Debug)]
+pub enum BodyEditError {
+    ParseError(ParseFloatError),
+    Negative(&'static str),
+    /// Only elliptical orbits are supported, so the eccentricity must be in [0, 1)
+    UnboundOrbit(f64),
+    Save(String),
+}
+
+impl From<ParseFloatError> for BodyEditError {
+    fn from(value: ParseFloatError) -> Self {
+        Self::ParseError(value)
+    }
+}
+
+impl Error for BodyEditError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BodyEditError::ParseError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for BodyEditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BodyEditError::ParseError(e) => write!(f, "Parsing error while editing body: {}", e),
+            BodyEditError::Negative(field) => write!(f, "The {} can't be negative", field),
+            BodyEditError::UnboundOrbit(e) => write!(
+                f,
+                "Eccentricity {} is not supported, orbits must be elliptical (0 <= e < 1)",
+                e
+            ),
+            BodyEditError::Save(e) => write!(f, "Couldn't save the system: {}", e),
+        }
+    }
+}
+
+/// Editable fields of the selected body
+#[derive(Default, Clone)]
+pub struct BodyFields {
+    mass: String,
+    radius: String,
+    semimajor_axis: String,
+    eccentricity: String,
+    inclination: String,
+    long_asc_node: String,
+    arg_periapsis: String,
+    initial_mean_anomaly: String,
+    selected: usize,
+}
+
+impl OptionsList<8> for BodyFields {
+    fn current_index(&mut self) -> &mut usize {
+        &mut self.selected
+    }
+
+    fn fields_list(&mut self) -> [(&mut String, String); 8] {
+        [
+            (&mut self.mass, "Mass (kg)".into()),
+            (&mut self.radius, "Radius (km)".into()),
+            (&mut self.semimajor_axis, "Semimajor axis (km)".into()),
+            (&mut self.eccentricity, "Eccentricity".into()),
+            (&mut self.inclination, "Inclination (°)".into()),
+            (
+                &mut self.long_asc_node,
+                "Longitude of ascending node (°)".into(),
+            ),
+            (&mut self.arg_periapsis, "Argument of periapsis (°)".into()),
+            (
+                &mut self.initial_mean_anomaly,
+                "Initial mean anomaly (°)".into(),
+            ),
+        ]
+    }
+}
+
+/// Factor by which an orbital period changes when the semimajor axis goes from `a` to `new_a`
+/// and the total mass of the host and the body from `mu` to `new_mu` (Kepler's third law)
+fn period_scale(a: f64, new_a: f64, mu: f64, new_mu: f64) -> f64 {
+    if a <= 0. || new_mu <= 0. {
+        return 1.;
+    }
+    (new_a / a).powf(1.5) * (mu / new_mu).sqrt()
+}
+
+impl BodyFields {
+    pub fn new(data: &BodyData) -> Self {
+        Self {
+            mass: data.mass.to_string(),
+            radius: data.radius.to_string(),
+            semimajor_axis: data.semimajor_axis.to_string(),
+            eccentricity: data.eccentricity.to_string(),
+            inclination: data.inclination.to_string(),
+            long_asc_node: data.long_asc_node.to_string(),
+            arg_periapsis: data.arg_periapsis.to_string(),
+            initial_mean_anomaly: data.initial_mean_anomaly.to_string(),
+            selected: 0,
+        }
+    }
+
+    /// Applies the fields to the data of the body, whose host has the given mass. The derived
+    /// quantities (apsides and period) follow the new elements
+    pub fn to_data(&self, data: &BodyData, host_mass: f64) -> Result<BodyData, BodyEditError> {
+        let positive = |field: &String, name| {
+            let value = field.parse::<f64>()?;
+            if value < 0. {
+                Err(BodyEditError::Negative(name))
+            } else {
+                Ok(value)
+            }
+        };
+        let mass = positive(&self.mass, "mass")?;
+        let radius = positive(&self.radius, "radius")?;
+        let semimajor_axis = positive(&self.semimajor_axis, "semimajor axis")?;
+        let eccentricity = self.eccentricity.parse::<f64>()?;
+        if !(0. ..1.).contains(&eccentricity) {
+            return Err(BodyEditError::UnboundOrbit(eccentricity));
+        }
+        let revolution_period = data.revolution_period
+            * period_scale(
+                data.semimajor_axis,
+                semimajor_axis,
+                host_mass + data.mass,
+                host_mass + mass,
+            );
+        Ok(BodyData {
+            mass,
+            radius,
+            semimajor_axis,
+            eccentricity,
+            inclination: self.inclination.parse()?,
+            long_asc_node: self.long_asc_node.parse()?,
+            arg_periapsis: self.arg_periapsis.parse()?,
+            initial_mean_anomaly: self.initial_mean_anomaly.parse()?,
+            periapsis: semimajor_axis * (1. - eccentricity),
+            apoapsis: semimajor_axis * (1. + eccentricity),
+            revolution_period,
+            ..data.clone()
+        })
+    }
+}
+
+#[derive(Resource)]
+pub struct BodiesEditorContext {
+    list_state: ListState,
+    /// Bodies as they were when the editor was opened, sorted by name
+    original: Vec<BodyData>,
+    pub fields: BodyFields,
+    /// Outcome of the last action, displayed until the next one
+    status: Option<Result<String, BodyEditError>>,
+}
+
+impl ClampedList for BodiesEditorContext {
+    fn list_state(&mut self) -> &mut ListState {
+        &mut self.list_state
+    }
+
+    fn len(&self) -> usize {
+        self.original.len()
+    }
+}
+
+impl BodiesEditorContext {
+    pub fn new(bodies: impl Iterator<Item = BodyData>) -> Self {
+        let mut original: Vec<_> = bodies.collect();
+        original.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut ctx = Self {
+            list_state: ListState::default(),
+            original,
+            fields: BodyFields::default(),
+            status: None,
+        };
+        ctx.select_next();
+        if let Some(data) = ctx.original.first() {
+            ctx.fields = BodyFields::new(data);
+        }
+        ctx
+    }
+
+    pub fn selected_body(&self) -> Option<BodyID> {
+        self.list_state
+            .selected()
+            .and_then(|i| self.original.get(i))
+            .map(|data| data.id)
+    }
+
+    pub fn select_body(&mut self, id: BodyID) {
+        if let Some(i) = self.original.iter().position(|data| data.id == id) {
+            self.list_state.select(Some(i));
+        }
+    }
+}
+
+pub struct BodiesEditorScreen;
+
+fn read_input(
+    mut context: ResMut<BodiesEditorContext>,
+    mut key_event: EventReader<KeyEvent>,
+    keymap: Res<Keymap>,
+    mut internal_event: EventWriter<BodiesEditorEvent>,
+) {
+    use BodiesEditorEvent::*;
+    use Direction2::*;
+    let keymap = &keymap.bodies_editor;
+    for KeyEvent(event) in key_event.read() {
+        if event.kind == KeyEventKind::Release {
+            return;
+        }
+        let fields = &mut context.fields;
+        match event {
+            e if keymap.select_next.matches(e) => {
+                internal_event.send(Select(Down));
+            }
+            e if keymap.select_previous.matches(e) => {
+                internal_event.send(Select(Up));
+            }
+            e if keymap.cycle_options.matches(e) => fields.select_next(),
+            e if keymap.cycle_options_back.matches(e) => fields.select_previous(),
+            e if keymap.delete_char.matches(e) => {
+                fields.selected_field().pop();
+            }
+            e if keymap.apply.matches(e) => {
+                internal_event.send(Apply);
+            }
+            e if keymap.save.matches(e) => {
+                internal_event.send(Save);
+            }
+            e if keymap.revert.matches(e) => {
+                internal_event.send(Revert);
+            }
+            e if keymap.back.matches(e) => {
+                internal_event.send(Back);
+            }
+            crossterm::event::KeyEvent {
+                code: KeyCode::Char(c),
+                ..
+            } => fields.selected_field().push(*c),
+            _ => {}
+        }
+    }
+}
+
+/// Replaces the data of a body, along with the components derived from it
+fn set_body_data(
+    data: BodyData,
+    bodies: &mut Query<(&mut BodyInfo, &mut Mass, &mut EllipticalOrbit)>,
+    mapping: &BodiesMapping,
+) {
+    if let Some(Ok((mut info, mut mass, mut orbit))) =
+        mapping.0.get(&data.id).map(|e| bodies.get_mut(*e))
+    {
+        *orbit = EllipticalOrbit::from(&data);
+        mass.0 = data.mass;
+        info.0 = data;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn handle_bodies_editor_events(
+    mut context: ResMut<BodiesEditorContext>,
+    mut events: EventReader<BodiesEditorEvent>,
+    mut bodies: Query<(&mut BodyInfo, &mut Mass, &mut EllipticalOrbit)>,
+    mapping: Res<BodiesMapping>,
+    files: Res<GameFiles>,
+    mut next_screen: ResMut<NextState<AppScreen>>,
+    mut edited: EventWriter<BodiesChanged>,
+) {
+    for event in events.read() {
+        match event {
+            BodiesEditorEvent::Select(d) => {
+                context.select_adjacent(*d);
+                if let Some(Ok((info, ..))) = context
+                    .selected_body()
+                    .and_then(|id| mapping.0.get(&id))
+                    .map(|e| bodies.get(*e))
+                {
+                    context.fields = BodyFields::new(&info.0);
+                }
+            }
+            BodiesEditorEvent::Apply => {
+                let Some(id) = context.selected_body() else {
+                    continue;
+                };
+                let Some(Ok((BodyInfo(data), ..))) = mapping.0.get(&id).map(|e| bodies.get(*e))
+                else {
+                    continue;
+                };
+                let data = data.clone();
+                let host_mass = data
+                    .host_body
+                    .and_then(|host| mapping.0.get(&host))
+                    .and_then(|e| bodies.get(*e).ok())
+                    .map_or(0., |(_, mass, _)| mass.0);
+                let new_data = match context.fields.to_data(&data, host_mass) {
+                    Ok(new_data) => new_data,
+                    Err(e) => {
+                        context.status = Some(Err(e));
+                        continue;
+                    }
+                };
+                // The periods of the moons depend on the mass of their host
+                if new_data.mass != data.mass {
+                    for child in &data.orbiting_bodies {
+                        if let Some(Ok((mut info, _, mut orbit))) =
+                            mapping.0.get(child).map(|e| bodies.get_mut(*e))
+                        {
+                            let scale = period_scale(
+                                1.,
+                                1.,
+                                data.mass + info.0.mass,
+                                new_data.mass + info.0.mass,
+                            );
+                            info.0.revolution_period *= scale;
+                            orbit.revolution_period = info.0.revolution_period;
+                        }
+                    }
+                }
+                set_body_data(new_data, &mut bodies, &mapping);
+                context.status = Some(Ok(format!("Applied changes to {}", data.name)));
+                edited.send(BodiesChanged);
+            }
+            BodiesEditorEvent::Revert => {
+                for data in context.original.clone() {
+                    set_body_data(data, &mut bodies, &mapping);
+                }
+                if let Some(data) = context
+                    .list_state
+                    .selected()
+                    .and_then(|i| context.original.get(i))
+                    .cloned()
+                {
+                    context.fields = BodyFields::new(&data);
+                }
+                context.status = Some(Ok("Reverted the system".into()));
+                edited.send(BodiesChanged);
+            }
+            BodiesEditorEvent::Save => {
+                let mut data: Vec<_> = context
+                    .original
+                    .iter()
+                    .filter_map(|d| bodies.get(*mapping.0.get(&d.id)?).ok())
+                    .map(|(info, ..)| info.0.clone())
+                    .collect();
+                data.sort_by_key(|d| d.id);
+                context.status = Some(
+                    save_user_bodies(&files, &data)
+                        .map(|path| format!("Saved the system to {}", path.display()))
+                        .map_err(|e| BodyEditError::Save(e.to_string())),
+                );
+            }
+            BodiesEditorEvent::Back => next_screen.set(AppScreen::Explorer),
+        }
+    }
+}
+
+impl StatefulWidget for BodiesEditorScreen {
+    type State = BodiesEditorContext;
+
+    fn render(
+        self,
+        area: ratatui::prelude::Rect,
+        buf: &mut ratatui::prelude::Buffer,
+        state: &mut Self::State,
+    ) {
+        let chunks =
+            Layout::horizontal([Constraint::Percentage(25), Constraint::Fill(1)]).split(area);
+
+        // Body list
+        let entries = state.original.iter().map(|d| d.name.clone());
+        let mut block = Block::bordered()
+            .title_top("Bodies")
+            .title_bottom("enter: apply, C-s: save, C-r: revert");
+        if let Some(status) = &state.status {
+            block = block.title_bottom(
+                match status {
+                    Ok(message) => Line::from(message.clone().green()),
+                    Err(e) => Line::from(e.to_string().red()),
+                }
+                .right_aligned(),
+            );
+        }
+        let list = List::new(entries).highlight_symbol(">").block(block);
+        <List as StatefulWidget>::render(list, chunks[0], buf, &mut state.list_state);
+
+        // Fields of the selected body
+        let mut constraints = [Constraint::Length(3)].repeat(8);
+        constraints.push(Constraint::Fill(1));
+        let fields = Layout::vertical(constraints).split(chunks[1]);
+        for i in 0..8 {
+            state.fields.paragraph(i).render(fields[i], buf);
+        }
+        Paragraph::new(
+            "Changes are applied to the running simulation, and saved to the user bodies file \
+             (the bundled bodies are never overwritten)",
+        )
+        .wrap(Wrap { trim: false })
+        .render(fields[8], buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{math::DVec3, prelude::*};
+
+    use crate::{
+        game::GameFiles,
+        objects::bodies::{body_data::BodyData, USER_BODIES_PATH},
+        physics::influence::HillRadius,
+        prelude::*,
+        utils::persist::read_versioned,
+    };
+
+    use super::{BodiesEditorContext, BodiesEditorEvent, BodyEditError};
+
+    fn new_app() -> App {
+        let mut app = App::new();
+        app.add_plugins((
+            ClientPlugin::testing()
+                .with_bodies(BodiesConfig::SmallestBodyType(BodyType::Moon))
+                .in_mode(ClientMode::Singleplayer),
+            TuiPlugin::testing(),
+        ));
+        app.update();
+        app.update();
+        app.world_mut()
+            .resource_mut::<NextState<AppScreen>>()
+            .set(AppScreen::Bodies);
+        app.update();
+        app
+    }
+
+    fn body(app: &mut App, id: &str) -> (BodyData, EllipticalOrbit, f64, DVec3) {
+        let world = app.world_mut();
+        let entity = world.resource::<BodiesMapping>().0[id];
+        let (info, orbit, hill, pos) = world
+            .query::<(&BodyInfo, &EllipticalOrbit, &HillRadius, &Position)>()
+            .get(world, entity)
+            .unwrap();
+        (info.0.clone(), orbit.clone(), hill.0, pos.0)
+    }
+
+    fn spawn_ship(app: &mut App, id: &str, pos: DVec3) -> Influenced {
+        app.world_mut().send_event(ShipEvent::Create(ShipInfo {
+            id: id_from(id),
+            spawn_pos: pos,
+            ..default()
+        }));
+        app.update();
+        let world = app.world_mut();
+        let entity = world.resource::<ShipsMapping>().0[id];
+        world.get::<Influenced>(entity).unwrap().clone()
+    }
+
+    #[test]
+    fn test_edit_semimajor_axis() {
+        let mut app = new_app();
+        let (data, _, hill, old_pos) = body(&mut app, "terre");
+        let mut ctx = app.world_mut().resource_mut::<BodiesEditorContext>();
+        ctx.select_body(id_from("terre"));
+        ctx.fields = super::BodyFields::new(&data);
+        ctx.fields.semimajor_axis = (2. * data.semimajor_axis).to_string();
+        app.world_mut().send_event(BodiesEditorEvent::Apply);
+        app.update();
+
+        let (new_data, orbit, new_hill, pos) = body(&mut app, "terre");
+        assert_eq!(new_data.semimajor_axis, 2. * data.semimajor_axis);
+        assert_eq!(orbit.semimajor_axis, 2. * data.semimajor_axis);
+        assert!((new_hill / hill - 2.).abs() < 1e-9, "{hill} {new_hill}");
+        assert!(
+            (new_data.revolution_period / data.revolution_period - 2f64.powf(1.5)).abs() < 1e-9
+        );
+        let sun_pos = body(&mut app, "soleil").3;
+        let distance = pos.distance(sun_pos);
+        assert!(
+            (1.9 * data.semimajor_axis..2.1 * data.semimajor_axis).contains(&distance),
+            "{distance}"
+        );
+
+        // Ships spawned at the old position of the Earth are not around it anymore
+        let earth = app.world().resource::<BodiesMapping>().0["terre"];
+        let lost = spawn_ship(&mut app, "lost", old_pos + DVec3::X * 1e4);
+        assert_ne!(lost.main_influencer, Some(earth));
+        let orbiting = spawn_ship(&mut app, "orbiting", pos + DVec3::X * 1e4);
+        assert_eq!(orbiting.main_influencer, Some(earth));
+
+        // The system is saved next to the game files, and the changes can be reverted
+        app.world_mut().send_event(BodiesEditorEvent::Save);
+        app.world_mut().send_event(BodiesEditorEvent::Revert);
+        app.update();
+        let path = app
+            .world()
+            .resource::<GameFiles>()
+            .root
+            .join(USER_BODIES_PATH);
+        let saved: Vec<BodyData> = read_versioned(path).unwrap();
+        let saved_earth = saved.iter().find(|d| d.id == id_from("terre")).unwrap();
+        assert_eq!(saved_earth.semimajor_axis, 2. * data.semimajor_axis);
+        let (reverted, _, reverted_hill, _) = body(&mut app, "terre");
+        assert_eq!(reverted, data);
+        assert_eq!(reverted_hill, hill);
+    }
+
+    #[test]
+    fn test_rejected_edits() {
+        let mut app = new_app();
+        let (data, ..) = body(&mut app, "terre");
+        let (moon, ..) = body(&mut app, "lune");
+        let (_, _, moon_hill, _) = body(&mut app, "lune");
+        let mut ctx = app.world_mut().resource_mut::<BodiesEditorContext>();
+        ctx.select_body(id_from("terre"));
+        ctx.fields = super::BodyFields::new(&data);
+        ctx.fields.eccentricity = "1".into();
+        app.world_mut().send_event(BodiesEditorEvent::Apply);
+        app.update();
+        let ctx = app.world().resource::<BodiesEditorContext>();
+        assert!(matches!(
+            ctx.status,
+            Some(Err(BodyEditError::UnboundOrbit(_)))
+        ));
+        assert_eq!(body(&mut app, "terre").0, data);
+
+        // A heavier Earth makes the Moon orbit faster, and shrinks its Hill sphere
+        let mut ctx = app.world_mut().resource_mut::<BodiesEditorContext>();
+        ctx.fields.eccentricity = data.eccentricity.to_string();
+        ctx.fields.mass = (4. * data.mass).to_string();
+        app.world_mut().send_event(BodiesEditorEvent::Apply);
+        app.update();
+        assert_eq!(body(&mut app, "terre").1.eccentricity, data.eccentricity);
+        let (new_moon, _, new_moon_hill, _) = body(&mut app, "lune");
+        assert!(new_moon.revolution_period < moon.revolution_period);
+        assert!(new_moon_hill < moon_hill);
+    }
+}
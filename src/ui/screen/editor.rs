@@ -9,11 +9,19 @@ use bevy_ratatui::event::KeyEvent;
 use crossterm::event::KeyEventKind;
 use ratatui::{
     layout::{Constraint, Layout},
+    style::Stylize,
+    text::Line,
     widgets::{Block, List, ListState, Paragraph, StatefulWidget, Widget},
 };
 
 use crate::{
-    objects::ships::trajectory::ManeuverNode, physics::time::SIMTICKS_PER_TICK, prelude::*,
+    objects::ships::trajectory::{ManeuverKind, ManeuverNode},
+    physics::{
+        maneuver::AerobrakeOutcome,
+        time::{GAMETIME_PER_SIMTICK, SIMTICKS_PER_TICK},
+        SECONDS_PER_DAY,
+    },
+    prelude::*,
 };
 
 use super::AppScreen;
@@ -24,6 +32,9 @@ pub fn plugin(app: &mut App) {
     app.add_plugins(editor_backend::plugin)
         .add_computed_state::<InEditor>()
         .add_event::<SelectNode>()
+        .add_event::<CycleApproachTarget>()
+        .add_event::<TogglePlaneChangePlanner>()
+        .add_event::<ToggleAerobrake>()
         .add_systems(
             Update,
             (
@@ -74,6 +85,61 @@ pub struct EditorContext {
     temp_predictions: Vec<Entity>,
     /// This field stores the thrust that will be added to a node when we are editing one
     editing_data: Option<DVec3>,
+    /// Object whose closest approach with the ship is computed from the predictions
+    approach_target: Option<ApproachTarget>,
+    approach: Option<ApproachReport>,
+    /// Open when the user plans a change of inclination
+    planner: Option<ManeuverPlanner>,
+    /// Planned outcome of the selected node if it is an aerobraking pass through an atmosphere
+    aerobrake: Option<AerobrakeOutcome>,
+    /// Why the saved trajectory of the ship could not be read, in which case saving overwrites it
+    load_error: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ApproachTarget {
+    Ship(ShipID),
+    Body(BodyID),
+    Waypoint(WaypointID),
+}
+
+impl std::fmt::Display for ApproachTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ship(id) => write!(f, "ship {}", id),
+            Self::Body(id) => write!(f, "body {}", id),
+            Self::Waypoint(name) => write!(f, "waypoint {}", name),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ApproachReport {
+    pub simtick: u64,
+    pub distance: f64,
+    /// First time the ship gets closer than [editor_backend::APPROACH_THRESHOLD]
+    pub first_below: Option<(u64, f64)>,
+    /// Whether the search window was shortened because the predictions are too short
+    pub clamped: bool,
+}
+
+/// Delta-v needed to bring the orbit of the ship in the plane of the approach target if it is a
+/// body, or in the ecliptic otherwise
+#[derive(Clone, Copy, Debug)]
+pub struct ManeuverPlanner {
+    pub target: Option<BodyID>,
+    /// Angle between the orbit of the ship and the target plane (in radians)
+    pub delta_i: f64,
+    /// Plane change alone, at the current speed (in km/s)
+    pub pure_dv: f64,
+    /// Hohmann transfer to the orbit of the target (to the current radius for the ecliptic),
+    /// with the whole plane change at its second burn (in km/s)
+    pub combined_dv: f64,
+    /// Burns of the same transfer with the plane change split optimally between them (in km/s),
+    /// and the part of it done at the first burn (in radians)
+    pub split: (f64, f64, f64),
+    /// Simtick and whether it is at the ascending node, for the slowest node crossing
+    pub burn: Option<(u64, bool)>,
 }
 
 impl EditorContext {
@@ -95,6 +161,11 @@ impl EditorContext {
             predictions: Vec::new(),
             temp_predictions: Vec::new(),
             editing_data: None,
+            approach_target: None,
+            approach: None,
+            planner: None,
+            aerobrake: None,
+            load_error: None,
         }
     }
 
@@ -225,6 +296,9 @@ fn read_input(
     mut key_event: EventReader<KeyEvent>,
     keymap: Res<Keymap>,
     mut internal_event: EventWriter<SelectNode>,
+    mut cycle_target: EventWriter<CycleApproachTarget>,
+    mut toggle_planner: EventWriter<TogglePlaneChangePlanner>,
+    mut aerobrake: EventWriter<ToggleAerobrake>,
     mut next_screen: ResMut<NextState<AppScreen>>,
 ) {
     use Direction2::*;
@@ -239,12 +313,37 @@ fn read_input(
             e if keymap.select_previous.matches(e) => SelectAdjacent(Up),
             e if keymap.back.matches(e) => return next_screen.set(AppScreen::Fleet),
             e if keymap.remove_node.matches(e) => RemoveNode(true),
+            e if keymap.cycle_approach_target.matches(e) => {
+                cycle_target.send(CycleApproachTarget);
+                continue;
+            }
+            e if keymap.plan_plane_change.matches(e) => {
+                toggle_planner.send(TogglePlaneChangePlanner);
+                continue;
+            }
+            e if keymap.toggle_aerobrake.matches(e) => {
+                aerobrake.send(ToggleAerobrake);
+                continue;
+            }
             // e if keymap.new_node.matches(e) => NewNode(None),
             _ => return,
         });
     }
 }
 
+/// Selects the next ship, body or waypoint whose closest approach is computed
+#[derive(Event, Clone, Copy)]
+pub struct CycleApproachTarget;
+
+/// Opens or closes the [ManeuverPlanner]
+#[derive(Event, Clone, Copy)]
+pub struct TogglePlaneChangePlanner;
+
+/// Switches the selected node between a burn and an aerobraking pass at the next periapsis around
+/// the origin of the node
+#[derive(Event, Clone, Copy)]
+pub struct ToggleAerobrake;
+
 #[derive(Event, Clone, Copy)]
 pub enum SelectNode {
     SelectAdjacent(Direction2),
@@ -268,11 +367,7 @@ fn handle_editor_events(
                     .map_or(primary.single().0.id, |e| bodies.get(e).unwrap().0.id);
                 context.select_or_insert(
                     simtick / SIMTICKS_PER_TICK,
-                    ManeuverNode {
-                        name: "Node".into(),
-                        thrust: DVec3::ZERO,
-                        origin,
-                    },
+                    ManeuverNode::impulsive("Node", DVec3::ZERO, origin),
                 );
             }
             SelectNode::RemoveNode(b) => {
@@ -311,17 +406,107 @@ impl StatefulWidget for EditorScreen {
     ) {
         let chunks =
             Layout::horizontal([Constraint::Percentage(30), Constraint::Fill(1)]).split(area);
+        let mut block = Block::bordered().title_top("Maneuver nodes");
+        if let Some(error) = &state.load_error {
+            block = block.title_bottom(Line::from(error.as_str()).red());
+        }
         let list = List::new(state.nodes.values().map(|n| &n.name[..]))
             .highlight_symbol(">")
-            .block(Block::bordered().title_top("Maneuver nodes"));
+            .block(block);
         StatefulWidget::render(list, chunks[0], buf, &mut state.list_state);
 
+        let planner_height = if state.planner.is_some() { 7 } else { 0 };
+        let right = Layout::vertical([
+            Constraint::Fill(1),
+            Constraint::Length(planner_height),
+            Constraint::Length(5),
+        ])
+        .split(chunks[1]);
         if let Some((tick, node)) = state.selected_entry() {
-            Paragraph::new(format!(
-                "Tick: {}\nThrust: {}\nOrigin: {}",
-                tick, node.thrust, node.origin
-            ))
-            .render(chunks[1], buf);
+            let text = match node.kind {
+                ManeuverKind::Impulsive(thrust) => format!(
+                    "Tick: {}\nThrust: {}\nOrigin: {}",
+                    tick, thrust, node.origin
+                ),
+                ManeuverKind::Aerobrake { body } => {
+                    let mut text = format!("Tick: {}\nAerobraking at {}", tick, body);
+                    text += &match state.aerobrake {
+                        None => "\nThe periapsis doesn't pass through an atmosphere".to_owned(),
+                        Some(outcome) => format!(
+                            "\nPeriapsis: {:.0} km\nSpeed at periapsis: {:.3} km/s\nΔv: {:.3} km/s\nResulting apoapsis: {}",
+                            outcome.periapsis_alt,
+                            outcome.entry_v / SECONDS_PER_DAY,
+                            outcome.delta_v / SECONDS_PER_DAY,
+                            outcome
+                                .apoapsis_alt
+                                .map_or("none (still escaping)".to_owned(), |a| format!(
+                                    "{:.0} km",
+                                    a
+                                )),
+                        ),
+                    };
+                    text
+                }
+            };
+            Paragraph::new(text).render(right[0], buf);
+        }
+
+        // Closest approach
+        let text = match (state.approach_target, state.approach) {
+            (None, _) => "No target".to_owned(),
+            (Some(target), None) => format!("Target: {}\nNo prediction", target),
+            (Some(target), Some(approach)) => {
+                let days = |simtick: u64| (simtick - state.simtick) as f64 * GAMETIME_PER_SIMTICK;
+                let mut text = format!(
+                    "Target: {}\nClosest approach: {:.0} km in {:.2} days{}",
+                    target,
+                    approach.distance,
+                    days(approach.simtick),
+                    if approach.clamped {
+                        " (predictions too short)"
+                    } else {
+                        ""
+                    }
+                );
+                if let Some((simtick, distance)) = approach.first_below {
+                    text += &format!(
+                        "\nFirst approach under {} km: {:.0} km in {:.2} days",
+                        editor_backend::APPROACH_THRESHOLD,
+                        distance,
+                        days(simtick)
+                    );
+                }
+                text
+            }
+        };
+        Paragraph::new(text)
+            .block(Block::bordered().title_top("Closest approach"))
+            .render(right[2], buf);
+
+        if let Some(planner) = state.planner {
+            let (first, second, split) = planner.split;
+            let mut text = format!(
+                "Plane: {} (Δi = {:.2}°)\nPure plane change: {:.3} km/s\nCombined at apoapsis: {:.3} km/s\nOptimal split: {:.3} + {:.3} km/s ({:.2}° at first burn)",
+                planner
+                    .target
+                    .map_or("ecliptic".to_owned(), |id| format!("body {id}")),
+                planner.delta_i.to_degrees(),
+                planner.pure_dv,
+                planner.combined_dv,
+                first,
+                second,
+                split.to_degrees(),
+            );
+            if let Some((simtick, ascending)) = planner.burn {
+                text += &format!(
+                    "\nBurn at tick {} ({} node)",
+                    simtick / SIMTICKS_PER_TICK,
+                    if ascending { "ascending" } else { "descending" }
+                );
+            }
+            Paragraph::new(text)
+                .block(Block::bordered().title_top("Plane change"))
+                .render(right[1], buf);
         }
     }
 }
@@ -2,19 +2,41 @@ use std::time::Duration;
 
 use crate::{
     game::GameFiles,
-    objects::ships::trajectory::{read_ship_trajectory, Trajectory, TrajectoryEvent},
+    network::{
+        transport::{ClientMessageTransport, ClientTransport},
+        ClientChannel, ClientMessage,
+    },
+    objects::ships::trajectory::{
+        read_ship_trajectory, ManeuverKind, Trajectory, TrajectoryError, TrajectoryEvent,
+    },
     physics::{
         influence::HillRadius,
-        predictions::{Prediction, PredictionStart},
+        maneuver::{
+            aerobrake_outcome, apoapsis_plane_change_dv, combined_plane_change_dv, node_crossings,
+            plane_change_dv,
+        },
+        predictions::{
+            body_positions, body_state_at, clamp_window, closest_approach, first_approach_below,
+            prediction_positions, Prediction, PredictionStart,
+        },
+        time::{GAMETIME_PER_SIMTICK, SIMTICKS_PER_TICK},
+        G, SECONDS_PER_DAY,
     },
     prelude::*,
     ui::gui::SelectionRadius,
 };
 use bevy::{math::DVec3, prelude::*};
 
-use super::{ClearOnEditorExit, EditorContext};
+use super::{
+    ApproachReport, ApproachTarget, ClearOnEditorExit, CycleApproachTarget, EditorContext,
+    ManeuverPlanner, ToggleAerobrake, TogglePlaneChangePlanner,
+};
 
 pub const PREDICTIONS_NUMBER: usize = 250_000;
+/// Number of simticks after the start of the predictions in which the closest approach is searched
+pub const APPROACH_WINDOW: u64 = 30_000;
+/// Distance under which the first approach with the target is reported (in km)
+pub const APPROACH_THRESHOLD: f64 = 1e3;
 const PREDICTION_DELAY: Duration = Duration::from_millis(200);
 const PREDICTIONS_ADD_STEP: isize = 100;
 const TICK_ADD_STEP: isize = 1;
@@ -26,6 +48,7 @@ pub fn plugin(app: &mut App) {
         .add_event::<ChangePredictionsNumber>()
         .add_event::<ChangeNodeTick>()
         .add_event::<ReloadPredictions>()
+        .add_event::<RecomputeApproach>()
         .init_resource::<PredictionDelay>()
         .init_resource::<NumberOfPredictions>()
         .add_systems(
@@ -76,9 +99,40 @@ pub fn plugin(app: &mut App) {
                 )
                     .chain()
                     .run_if(on_event::<ConfirmThrust>()),
+                (
+                    handle_toggle_aerobrake,
+                    update_temp_predictions,
+                    copy_predictions,
+                )
+                    .chain()
+                    .run_if(on_event::<ToggleAerobrake>()),
             )
                 .run_if(resource_exists::<EditorContext>)
                 .in_set(EventHandling),
+        )
+        .add_systems(
+            Update,
+            request_maneuver_preview
+                .after(handle_confirm_thrust)
+                .after(handle_toggle_aerobrake)
+                .run_if(resource_exists::<EditorContext>)
+                .run_if(on_event::<ConfirmThrust>().or_else(on_event::<ToggleAerobrake>()))
+                .run_if(in_state(ClientMode::Multiplayer)),
+        )
+        .add_systems(
+            Update,
+            (
+                handle_cycle_approach_target,
+                update_closest_approach.run_if(on_event::<RecomputeApproach>()),
+                update_plane_change_planner.run_if(
+                    on_event::<TogglePlaneChangePlanner>()
+                        .or_else(on_event::<CycleApproachTarget>()),
+                ),
+                update_aerobrake_preview,
+            )
+                .chain()
+                .after(EventHandling)
+                .run_if(resource_exists::<EditorContext>),
         );
 }
 
@@ -86,8 +140,14 @@ fn read_nodes(
     mut context: ResMut<EditorContext>,
     gamefiles: Res<GameFiles>,
 ) -> color_eyre::Result<()> {
-    if let Ok(traj) = read_ship_trajectory(&gamefiles.trajectories, context.ship_info.id) {
-        context.nodes = traj.nodes;
+    match read_ship_trajectory(&gamefiles.trajectories, context.ship_info.id) {
+        Ok(traj) => context.nodes = traj.nodes,
+        // A ship without a trajectory has no nodes yet
+        Err(TrajectoryError::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => {
+            warn!("{err}");
+            context.load_error = Some(err.to_string());
+        }
     }
     Ok(())
 }
@@ -164,7 +224,12 @@ fn explicitly_clear_predictions(mut commands: Commands, mut context: ResMut<Edit
     predictions
         .drain(0..)
         .chain(temp_predictions.drain(0..))
-        .for_each(|e| commands.entity(e).despawn());
+        .for_each(|e| {
+            // Predictions may have been culled to stay within the budget of the world
+            if let Some(mut entity) = commands.get_entity(e) {
+                entity.despawn();
+            }
+        });
 }
 
 #[derive(Resource, Debug, Clone, Copy)]
@@ -185,6 +250,11 @@ impl Default for NumberOfPredictions {
 #[derive(Event, Default)]
 pub struct ReloadPredictions;
 
+/// Searches the closest approach again, sent when the predictions of the edited ship are replaced
+/// or when the approach target changes, since the search is too slow to run at each frame
+#[derive(Event, Default)]
+pub struct RecomputeApproach;
+
 fn handle_change_predictions_number(
     mut events: EventReader<ChangePredictionsNumber>,
     mut number: ResMut<NumberOfPredictions>,
@@ -256,7 +326,7 @@ fn handle_confirm_thrust(
     if let Some(thrust) = context.editing_data {
         let ship = context.ship_info.id;
         if let Some((&tick, node)) = context.selected_entry_mut() {
-            node.thrust += thrust;
+            node.add_thrust(thrust);
             traj_event.send(TrajectoryEvent::AddNode {
                 ship,
                 node: node.clone(),
@@ -267,6 +337,77 @@ fn handle_confirm_thrust(
     context.editing_data = None;
 }
 
+/// Turns the selected node into an aerobraking pass around its origin, or back into a burn
+fn handle_toggle_aerobrake(
+    mut context: ResMut<EditorContext>,
+    mut events: EventReader<ToggleAerobrake>,
+    mut traj_event: EventWriter<TrajectoryEvent>,
+) {
+    let ship = context.ship_info.id;
+    let Some((&tick, node)) = context.selected_entry_mut() else {
+        return;
+    };
+    for _ in events.read() {
+        node.kind = match node.kind {
+            ManeuverKind::Aerobrake { .. } => ManeuverKind::Impulsive(DVec3::ZERO),
+            ManeuverKind::Impulsive(_) => ManeuverKind::Aerobrake { body: node.origin },
+        };
+        traj_event.send(TrajectoryEvent::AddNode {
+            ship,
+            node: node.clone(),
+            tick,
+        });
+    }
+}
+
+/// Plans the aerobraking pass of the selected node at the periapsis of the current orbit of the
+/// ship, so that its outcome is shown before the pass happens
+fn update_aerobrake_preview(
+    mut ctx: ResMut<EditorContext>,
+    bodies: Query<(&Position, &Velocity, &BodyInfo)>,
+    mapping: Res<BodiesMapping>,
+) {
+    let outcome = ctx.selected_node().and_then(|node| match node.kind {
+        ManeuverKind::Aerobrake { body } => {
+            let (&Position(b_pos), &Velocity(b_speed), BodyInfo(data)) =
+                bodies.get(*mapping.0.get(&body)?).ok()?;
+            let atmosphere = Atmosphere::of_main_body(&data.id)?;
+            aerobrake_outcome(
+                ctx.pos - b_pos,
+                ctx.speed - b_speed,
+                G * data.mass,
+                &atmosphere,
+            )
+        }
+        ManeuverKind::Impulsive(_) => None,
+    });
+    if ctx.aerobrake != outcome {
+        ctx.aerobrake = outcome;
+    }
+}
+
+/// Asks the server to preview the trajectory of the ship with its edited maneuver nodes
+fn request_maneuver_preview(
+    context: Res<EditorContext>,
+    predictions_number: Res<NumberOfPredictions>,
+    mut transport: ClientTransport,
+) {
+    transport
+        .send(
+            ClientChannel::Once,
+            &ClientMessage::SandboxManeuver {
+                ship: context.ship_info.id,
+                nodes: context
+                    .nodes
+                    .iter()
+                    .map(|(tick, node)| (*tick, node.clone()))
+                    .collect(),
+                preview_ticks: predictions_number.0 as u64 / SIMTICKS_PER_TICK,
+            },
+        )
+        .unwrap_or_else(|e| error!("could not send maneuver preview request: {e}"));
+}
+
 fn handle_update_thrust(
     mut thrust_updates: EventReader<UpdateThrust>,
     mut context: ResMut<EditorContext>,
@@ -321,7 +462,7 @@ fn update_temp_predictions(
     let thrust = ctx.editing_data.unwrap_or_default();
     let mut nodes = ctx.nodes.clone();
     if let Some(tick) = ctx.selected_tick() {
-        nodes.get_mut(&tick).unwrap().thrust += thrust;
+        nodes.get_mut(&tick).unwrap().add_thrust(thrust);
     }
     let reference = space_map.focus_body.or(influence.main_influencer);
     let predictions = start.compute_predictions(
@@ -344,7 +485,9 @@ fn copy_predictions(
     ctx: Res<EditorContext>,
     new_coords: Query<(&Position, &Velocity), With<TempPrediction>>,
     mut coords: Query<(&mut Position, &mut Velocity), Without<TempPrediction>>,
+    mut recompute: EventWriter<RecomputeApproach>,
 ) {
+    recompute.send_default();
     let mut new_coords = new_coords.iter_many(&ctx.temp_predictions);
     let mut iter = coords.iter_many_mut(&ctx.predictions);
     while let (Some((mut pos, mut speed)), Some((new_pos, new_speed))) =
@@ -353,3 +496,232 @@ fn copy_predictions(
         (pos.0, speed.0) = (new_pos.0, new_speed.0);
     }
 }
+
+fn handle_cycle_approach_target(
+    mut events: EventReader<CycleApproachTarget>,
+    mut ctx: ResMut<EditorContext>,
+    ships: Res<ShipsMapping>,
+    bodies: Res<BodiesMapping>,
+    waypoints: Res<Waypoints>,
+    mut recompute: EventWriter<RecomputeApproach>,
+) {
+    for _ in events.read() {
+        recompute.send_default();
+        let mut ship_ids: Vec<_> = ships
+            .0
+            .keys()
+            .filter(|id| **id != ctx.ship_info.id)
+            .copied()
+            .collect();
+        ship_ids.sort();
+        let mut body_ids: Vec<_> = bodies.0.keys().copied().collect();
+        body_ids.sort();
+        let targets: Vec<_> = ship_ids
+            .into_iter()
+            .map(ApproachTarget::Ship)
+            .chain(body_ids.into_iter().map(ApproachTarget::Body))
+            .chain(waypoints.0.keys().copied().map(ApproachTarget::Waypoint))
+            .collect();
+        // Cycling past the last target deselects it
+        ctx.approach_target = match ctx
+            .approach_target
+            .and_then(|t| targets.iter().position(|u| *u == t))
+        {
+            Some(i) => targets.get(i + 1).copied(),
+            None => targets.first().copied(),
+        };
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_closest_approach(
+    mut ctx: ResMut<EditorContext>,
+    predictions: Query<&Position, (With<Prediction>, Without<TempPrediction>)>,
+    ships: Query<(&Position, &Velocity, &Acceleration, &Influenced), Without<Prediction>>,
+    mut bodies: Query<(&EllipticalOrbit, &BodyInfo, &HillRadius)>,
+    ships_mapping: Res<ShipsMapping>,
+    bodies_mapping: Res<BodiesMapping>,
+    space_map: Res<SpaceMap>,
+    gamefiles: Res<GameFiles>,
+    (waypoints, waypoint_positions): (Res<Waypoints>, Res<WaypointPositions>),
+) {
+    ctx.approach = None;
+    let (Some(target), Ok((_, _, _, influence))) = (ctx.approach_target, ships.get(ctx.ship))
+    else {
+        return;
+    };
+    // The first prediction is one simtick after the start
+    let start = ctx.simtick + 1;
+    let positions: Vec<_> = predictions
+        .iter_many(&ctx.predictions)
+        .map(|p| p.0)
+        .collect();
+    if positions.is_empty() {
+        return;
+    }
+    let (window, clamped) = clamp_window(
+        start..start + APPROACH_WINDOW,
+        start + positions.len() as u64,
+    );
+    let ship = prediction_positions(start, &positions);
+    let reference = space_map.focus_body.or(influence.main_influencer);
+    let ((simtick, distance), first_below) = match target {
+        ApproachTarget::Body(id) => {
+            let Some(&body) = bodies_mapping.0.get(&id) else {
+                return;
+            };
+            let mut lens = bodies.transmute_lens::<(&EllipticalOrbit, &BodyInfo)>();
+            let orbits = lens.query();
+            let target = body_positions(body, reference, ctx.simtick, &orbits, &bodies_mapping.0);
+            (
+                closest_approach(&ship, &target, window.clone(), 1),
+                first_approach_below(&ship, &target, window, 1, APPROACH_THRESHOLD),
+            )
+        }
+        ApproachTarget::Ship(id) => {
+            let Some((&Position(pos), &Velocity(speed), acc, influence)) =
+                ships_mapping.0.get(&id).and_then(|&e| ships.get(e).ok())
+            else {
+                return;
+            };
+            let nodes = read_ship_trajectory(&gamefiles.trajectories, id)
+                .map(|t| t.nodes)
+                .unwrap_or_default();
+            let target_positions: Vec<_> = PredictionStart {
+                pos,
+                speed,
+                acc: acc.current,
+                simtick: ctx.simtick,
+            }
+            .compute_predictions(
+                (window.end - start) as usize,
+                influence,
+                reference,
+                &mut bodies.as_query_lens(),
+                &bodies_mapping.0,
+                &nodes,
+            )
+            .into_iter()
+            .map(|(p, _)| p)
+            .collect();
+            let target = prediction_positions(start, &target_positions);
+            (
+                closest_approach(&ship, &target, window.clone(), 1),
+                first_approach_below(&ship, &target, window, 1, APPROACH_THRESHOLD),
+            )
+        }
+        ApproachTarget::Waypoint(name) => {
+            let (Some(&kind), Some(&pos)) =
+                (waypoints.0.get(&name), waypoint_positions.0.get(&name))
+            else {
+                return;
+            };
+            let mut lens = bodies.transmute_lens::<(&EllipticalOrbit, &BodyInfo)>();
+            let orbits = lens.query();
+            let target: Box<dyn Fn(u64) -> DVec3> = match kind {
+                WaypointKind::Body { body, offset } => {
+                    let Some(&body) = bodies_mapping.0.get(&body) else {
+                        return;
+                    };
+                    let positions =
+                        body_positions(body, reference, ctx.simtick, &orbits, &bodies_mapping.0);
+                    Box::new(move |simtick| positions(simtick) + offset)
+                }
+                // The other waypoints are points of space, which move in the referential of the
+                // predictions
+                _ => {
+                    let initial_ref = reference.map_or(DVec3::ZERO, |r| {
+                        body_state_at(r, &orbits, &bodies_mapping.0, ctx.simtick).0
+                    });
+                    let orbits = &orbits;
+                    let mapping = &bodies_mapping.0;
+                    Box::new(move |simtick| {
+                        let ref_pos = reference.map_or(DVec3::ZERO, |r| {
+                            body_state_at(r, orbits, mapping, simtick).0
+                        });
+                        pos - ref_pos + initial_ref
+                    })
+                }
+            };
+            (
+                closest_approach(&ship, &target, window.clone(), 1),
+                first_approach_below(&ship, &target, window, 1, APPROACH_THRESHOLD),
+            )
+        }
+    };
+    ctx.approach = Some(ApproachReport {
+        simtick,
+        distance,
+        first_below,
+        clamped,
+    });
+}
+
+fn update_plane_change_planner(
+    mut ctx: ResMut<EditorContext>,
+    mut toggles: EventReader<TogglePlaneChangePlanner>,
+    ships: Query<(&Position, &Velocity, &Influenced)>,
+    bodies: Query<(&Position, &Velocity, &Mass, &EllipticalOrbit, &BodyInfo)>,
+    bodies_mapping: Res<BodiesMapping>,
+    time: Res<GameTime>,
+) {
+    // Closes an open planner when toggled, and leaves a closed one closed when the target changes
+    let toggled = toggles.read().count() % 2 == 1;
+    if toggled == ctx.planner.is_some() {
+        ctx.planner = None;
+        return;
+    }
+    let Ok((pos, speed, influence)) = ships.get(ctx.ship) else {
+        return;
+    };
+    let Some((host_pos, host_speed, mass, _, host)) =
+        influence.main_influencer.and_then(|e| bodies.get(e).ok())
+    else {
+        return;
+    };
+    let (rel_pos, rel_speed) = (pos.0 - host_pos.0, speed.0 - host_speed.0);
+    let gm = G * mass.0;
+    let r1 = rel_pos.length();
+    let target = match ctx.approach_target {
+        Some(ApproachTarget::Body(id)) => {
+            bodies_mapping.0.get(&id).and_then(|&e| bodies.get(e).ok())
+        }
+        _ => None,
+    };
+    let (normal, r2) = match target {
+        Some((_, _, _, orbit, BodyInfo(data))) => (
+            orbit.local_pos.cross(orbit.local_speed),
+            // A transfer to the orbit of the target only makes sense if it shares our host
+            if data.host_body == Some(host.0.id) {
+                orbit.semimajor_axis
+            } else {
+                r1
+            },
+        ),
+        None => (DVec3::Z, r1),
+    };
+    let normal = normal.try_normalize().unwrap_or(DVec3::Z);
+    let delta_i = rel_pos.cross(rel_speed).angle_between(normal);
+    // The maneuver functions work in km/s
+    let gm_s = gm / (SECONDS_PER_DAY * SECONDS_PER_DAY);
+    let (v1, v2) = ((gm_s / r1).sqrt(), (gm_s / r2).sqrt());
+    let burn = node_crossings(rel_pos, rel_speed, gm, normal).map(|(ascending, descending)| {
+        let (node, is_ascending) = if ascending.radius >= descending.radius {
+            (ascending, true)
+        } else {
+            (descending, false)
+        };
+        (
+            time.simtick + (node.delay / GAMETIME_PER_SIMTICK).round() as u64,
+            is_ascending,
+        )
+    });
+    ctx.planner = Some(ManeuverPlanner {
+        target: target.map(|(.., info)| info.0.id),
+        delta_i,
+        pure_dv: plane_change_dv(rel_speed.length() / SECONDS_PER_DAY, delta_i),
+        combined_dv: apoapsis_plane_change_dv(v1, v2, delta_i, r1, r2, gm_s),
+        split: combined_plane_change_dv(v1, v2, delta_i, r1, r2, gm_s),
+        burn,
+    });
+}
@@ -1,30 +1,52 @@
-use bevy::{prelude::*, utils::HashMap};
+use bevy::{math::DVec3, prelude::*, utils::HashMap};
 use bevy_ratatui::event::KeyEvent;
 use crossterm::event::{KeyCode, KeyEvent as CKeyEvent, KeyEventKind};
 use ratatui::{
-    layout::{Constraint, Layout},
-    widgets::{StatefulWidget, StatefulWidgetRef, WidgetRef},
+    layout::{Alignment, Constraint, Layout},
+    style::Stylize,
+    text::Line,
+    widgets::{
+        Block, Clear, List, ListState, Paragraph, StatefulWidget, StatefulWidgetRef, Widget,
+        WidgetRef,
+    },
 };
 
 use crate::{
-    client::ClientMode,
+    client::{ClientMode, LastServerUpdate},
     game::GameStage,
-    physics::{orbit::SystemSize, time::TimeEvent},
+    network::{
+        transport::{ClientMessageTransport, ClientTransport},
+        ClientChannel, ClientMessage,
+    },
+    physics::{
+        inspiral::{time_to_merger, BinaryPair},
+        orbit::{EllipticalOrbit, SynodicMonitor, SystemSize},
+        time::{TimeEvent, GAMETIME_PER_SIMTICK, SIMTICKS_PER_TICK},
+    },
     ui::{
         gui::SelectObjectEvent,
+        spectate::{FollowTarget, SpectateTarget},
         widget::{
             info::InfoWidget,
             search::{SearchPlugin, SearchState, SearchWidget},
-            space_map::{SpaceMap, SpaceMapWidget},
+            space_map::{MapDisplaySettings, SpaceMap, SpaceMapWidget},
             tree::{TreeState, TreeWidget},
         },
         UiUpdate,
     },
-    utils::list::ClampedList,
+    utils::{
+        algebra::project_onto_plane,
+        list::{select_next_clamp, select_previous_clamp, ClampedList, OptionsList},
+        ui::centered_rect,
+        Direction2,
+    },
+};
+use crate::{
+    input::prelude::Keymap,
+    objects::{prelude::*, waypoints::parse_waypoint},
 };
-use crate::{input::prelude::Keymap, objects::prelude::*};
 use crate::{
-    physics::Position,
+    physics::{Mass, Position},
     ui::{
         prelude::*,
         widget::{
@@ -46,13 +68,21 @@ pub fn plugin(app: &mut App) {
                 read_input.in_set(InputReading),
                 (
                     handle_explorer_events,
+                    handle_waypoints_events,
                     focus_on_select_body.run_if(
                         resource_exists::<Events<SelectObjectEvent>>
                             .and_then(on_event::<SelectObjectEvent>()),
                     ),
                 )
                     .in_set(EventHandling),
-                update_space_map.in_set(UiUpdate),
+                (
+                    update_space_map,
+                    update_conjunctions,
+                    update_inspiral,
+                    update_waypoints_popup,
+                )
+                    .in_set(UiUpdate)
+                    .after(EventHandling),
             )
                 .run_if(in_loaded_screen::<ExplorerContext>(AppScreen::Explorer)),
         )
@@ -94,6 +124,64 @@ pub struct ExplorerContext {
     pub search_state: SearchState,
     pub info: InfoWidget,
     pub space_map: SpaceMapWidget,
+    pub waypoints_popup: Option<WaypointsPopup>,
+}
+
+/// Fields of the popup adding a waypoint, next to the list of the waypoints
+#[derive(Default, Debug)]
+pub struct WaypointsPopup {
+    name: String,
+    /// "fixed", "body" or "ship"
+    kind: String,
+    /// Body or ship the waypoint refers to
+    reference: String,
+    x: String,
+    y: String,
+    z: String,
+    simtick: String,
+    selected: usize,
+    list_state: ListState,
+    /// Name and description of each waypoint
+    entries: Vec<(WaypointID, String)>,
+    /// Why the last waypoint could not be added
+    error: Option<String>,
+}
+
+impl OptionsList<7> for WaypointsPopup {
+    fn current_index(&mut self) -> &mut usize {
+        &mut self.selected
+    }
+
+    fn fields_list(&mut self) -> [(&mut String, String); 7] {
+        [
+            (&mut self.name, "Name".into()),
+            (&mut self.kind, "Kind (fixed, body or ship)".into()),
+            (&mut self.reference, "Body or ship".into()),
+            (&mut self.x, "x (offset for a body)".into()),
+            (&mut self.y, "y".into()),
+            (&mut self.z, "z".into()),
+            (&mut self.simtick, "Simtick (for a ship)".into()),
+        ]
+    }
+}
+
+impl WaypointsPopup {
+    /// The fields read by [parse_waypoint] for the kind of the waypoint
+    fn kind_fields(&self) -> Vec<&str> {
+        match self.kind.trim() {
+            "fixed" => vec![&self.x, &self.y, &self.z],
+            "body" => vec![&self.reference, &self.x, &self.y, &self.z],
+            "ship" => vec![&self.reference, &self.simtick],
+            _ => Vec::new(),
+        }
+    }
+
+    fn selected_waypoint(&self) -> Option<WaypointID> {
+        self.list_state
+            .selected()
+            .and_then(|i| self.entries.get(i))
+            .map(|(name, _)| *name)
+    }
 }
 
 impl ExplorerContext {
@@ -107,13 +195,17 @@ impl ExplorerContext {
             search_state: SearchState::new(infos.into_iter()),
             info: InfoWidget {
                 body_info: primary_data.clone(),
+                conjunctions: Vec::new(),
+                inspiral: None,
             },
             space_map: SpaceMapWidget::default(),
+            waypoints_popup: None,
         }
     }
     fn update_info(&mut self, mapping: &HashMap<BodyID, Entity>, bodies: &Query<&BodyInfo>) {
         let id = self.tree_state.selected_body_id();
-        if let Ok(body_info) = bodies.get(mapping[&id]) {
+        // The body may have merged with another one since the tree was built
+        if let Some(body_info) = mapping.get(&id).and_then(|e| bodies.get(*e).ok()) {
             self.info.body_info = body_info.0.clone();
         }
     }
@@ -126,6 +218,7 @@ pub(crate) enum ExplorerEvent {
     SpaceMap(SpaceMapEvent),
     View(ViewEvent),
     Time(TimeEvent),
+    Waypoints(WaypointsEvent),
 }
 
 impl ExplorerContext {
@@ -141,15 +234,30 @@ impl ExplorerContext {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+pub enum WaypointsEvent {
+    Open,
+    Close,
+    Select(Direction2),
+    /// Adds the waypoint described by the fields of the popup
+    Add,
+    /// Removes the selected waypoint
+    Remove,
+    /// Centers the map on the selected waypoint
+    Goto,
+}
+
 #[derive(Debug, Event)]
 pub enum ViewEvent {
     ChangeSidePaneMode(SidePaneMode),
     ToggleInfo,
+    EditBodies,
+    Inspect,
     Back,
 }
 
 fn read_input(
-    context: Res<ExplorerContext>,
+    mut context: ResMut<ExplorerContext>,
     mut key_event: EventReader<KeyEvent>,
     keymap: Res<Keymap>,
     mut internal_event: EventWriter<ExplorerEvent>,
@@ -162,6 +270,38 @@ fn read_input(
             return;
         }
         let keymap = &keymap.explorer;
+        if let Some(popup) = &mut context.waypoints_popup {
+            let codes = &keymap.waypoints;
+            internal_event.send(ExplorerEvent::Waypoints(match event {
+                e if codes.select_next.matches(e) => WaypointsEvent::Select(Down),
+                e if codes.select_previous.matches(e) => WaypointsEvent::Select(Up),
+                e if codes.add.matches(e) => WaypointsEvent::Add,
+                e if codes.remove.matches(e) => WaypointsEvent::Remove,
+                e if codes.goto.matches(e) => WaypointsEvent::Goto,
+                e if codes.back.matches(e) => WaypointsEvent::Close,
+                e if codes.cycle_options.matches(e) => {
+                    popup.select_next();
+                    continue;
+                }
+                e if codes.cycle_options_back.matches(e) => {
+                    popup.select_previous();
+                    continue;
+                }
+                e if codes.delete_char.matches(e) => {
+                    popup.selected_field().pop();
+                    continue;
+                }
+                CKeyEvent {
+                    code: KeyCode::Char(c),
+                    ..
+                } => {
+                    popup.selected_field().push(*c);
+                    continue;
+                }
+                _ => continue,
+            }));
+            continue;
+        }
         internal_event.send(match context.side_pane_mode {
             SidePaneMode::Tree => {
                 let codes = &keymap.tree;
@@ -182,13 +322,22 @@ fn read_input(
                     e if codes.map_offset_reset.matches(e) => SpaceMap(MapOffsetReset),
                     e if codes.focus.matches(e) => SpaceMap(FocusBody),
                     e if codes.autoscale.matches(e) => SpaceMap(Autoscale),
+                    e if codes.cycle_labels.matches(e) => SpaceMap(CycleLabels),
+                    e if codes.follow.matches(e) => SpaceMap(FollowNextShip),
                     e if codes.enter_search.matches(e) => {
                         View(ChangeSidePaneMode(SidePaneMode::Search))
                     }
                     e if codes.toggle_info.matches(e) => View(ToggleInfo),
+                    e if codes.edit_bodies.matches(e) => View(EditBodies),
+                    e if codes.inspect.matches(e) => View(Inspect),
+                    e if codes.waypoints.matches(e) => {
+                        ExplorerEvent::Waypoints(WaypointsEvent::Open)
+                    }
                     e if codes.back.matches(e) => View(ViewEvent::Back),
                     e if codes.speed_up.matches(e) => Time(ChangeStepSize(Up)),
                     e if codes.slow_down.matches(e) => Time(ChangeStepSize(Down)),
+                    e if codes.raise_max_speed.matches(e) => Time(ChangeMaxSpeed(Up)),
+                    e if codes.lower_max_speed.matches(e) => Time(ChangeMaxSpeed(Down)),
                     e if codes.toggle_time.matches(e) => Time(ToggleTime),
                     _ => return,
                 }
@@ -219,8 +368,9 @@ fn read_input(
 fn handle_explorer_events(
     mut ctx: ResMut<ExplorerContext>,
     mut space_map: ResMut<SpaceMap>,
+    mut display_settings: ResMut<MapDisplaySettings>,
 
-    client_mode: Res<State<ClientMode>>,
+    (client_mode, mut transport): (Res<State<ClientMode>>, ClientTransport),
     mut next_mode: ResMut<NextState<ClientMode>>,
 
     previous_screen: Res<PreviousScreen>,
@@ -234,6 +384,8 @@ fn handle_explorer_events(
     bodies: Query<&BodyInfo>,
     mut time_events: ResMut<Events<TimeEvent>>,
     fuzzy_matcher: Res<SearchMatcher>,
+    mut spectate: ResMut<SpectateTarget>,
+    ships: Option<Res<ShipsMapping>>,
 ) {
     for event in events.read() {
         match event {
@@ -277,12 +429,20 @@ fn handle_explorer_events(
                     MapOffset(d) => space_map.offset(*d),
                     MapOffsetReset => space_map.reset_offset(),
                     FocusBody => {
-                        if let Some(entity) = mapping.0.get(&ctx.tree_state.selected_body_id()) {
+                        let id = ctx.tree_state.selected_body_id();
+                        if let Some(entity) = mapping.0.get(&id) {
                             space_map.focus(*entity);
-                            ctx.tree_state.focus_body(bodies.get(*entity).unwrap().0.id)
+                            ctx.tree_state.focus_body(id);
+                            if spectate.target.is_some() {
+                                spectate.follow(FollowTarget::Body(id));
+                            }
                         }
                     }
                     Autoscale => space_map.autoscale(&mapping.0, &bodies),
+                    CycleLabels => display_settings.labels = display_settings.labels.next(),
+                    FollowNextShip => {
+                        spectate.cycle_ships(ships.iter().flat_map(|s| s.0.keys().copied()))
+                    }
                 }
             }
             ExplorerEvent::View(event) => match *event {
@@ -292,10 +452,28 @@ fn handle_explorer_events(
                 }
 
                 ViewEvent::ToggleInfo => ctx.info_toggle = !ctx.info_toggle,
+                // Only the clients that own the simulation can change the bodies
+                ViewEvent::EditBodies => {
+                    if matches!(
+                        client_mode.get(),
+                        ClientMode::Explorer | ClientMode::Singleplayer
+                    ) {
+                        next_screen.set(AppScreen::Bodies);
+                    }
+                }
+                ViewEvent::Inspect => {
+                    if let Some(&entity) = mapping.0.get(&ctx.selected_body()) {
+                        next_screen.set(AppScreen::Inspector(entity));
+                    }
+                }
                 ViewEvent::Back => match client_mode.get() {
                     ClientMode::Explorer => next_mode.set(ClientMode::None),
                     _ => {
-                        next_screen.set(previous_screen.0);
+                        // The bodies editor and the inspector always come back to the explorer
+                        next_screen.set(match previous_screen.0 {
+                            AppScreen::Bodies | AppScreen::Inspector(_) => AppScreen::Fleet,
+                            screen => screen,
+                        });
                     }
                 },
             },
@@ -307,40 +485,211 @@ fn handle_explorer_events(
                         TimeEvent::ChangeStepSize(d) => {
                             time_events.send(TimeEvent::ChangeUpdateRate(*d));
                         }
-                        TimeEvent::ToggleTime => next_game_stage.set(match game_stage.get() {
-                            GameStage::Preparation => GameStage::Action,
-                            GameStage::Action => GameStage::Preparation,
-                        }),
+                        TimeEvent::ToggleTime => {
+                            let stage = match game_stage.get() {
+                                GameStage::Preparation => GameStage::Action,
+                                GameStage::Action => GameStage::Preparation,
+                            };
+                            // In multiplayer, the server changes the stage of every client
+                            if *client_mode.get() == ClientMode::Multiplayer {
+                                transport
+                                    .send(ClientChannel::Once, &ClientMessage::ChangeStage(stage))
+                                    .unwrap_or_else(|e| {
+                                        error!("could not send message to the server: {e}")
+                                    });
+                            } else {
+                                next_game_stage.set(stage);
+                            }
+                        }
+                        TimeEvent::ChangeMaxSpeed(_) => {
+                            time_events.send(*event);
+                        }
                         _ => {}
                     }
                 } else {
                     time_events.send(*event);
                 }
             }
+            // See handle_waypoints_events
+            ExplorerEvent::Waypoints(_) => {}
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
+fn handle_waypoints_events(
+    mut ctx: ResMut<ExplorerContext>,
+    mut events: EventReader<ExplorerEvent>,
+    mut waypoints: ResMut<Waypoints>,
+    positions: Res<WaypointPositions>,
+    mut space_map: ResMut<SpaceMap>,
+    bodies: Res<BodiesMapping>,
+    ships: Option<Res<ShipsMapping>>,
+    objects: Query<&Position>,
+) {
+    for event in events.read() {
+        let ExplorerEvent::Waypoints(event) = event else {
+            continue;
+        };
+        if matches!(event, WaypointsEvent::Open) {
+            ctx.waypoints_popup = Some(WaypointsPopup::default());
+            continue;
+        }
+        let Some(popup) = &mut ctx.waypoints_popup else {
+            continue;
+        };
+        match event {
+            WaypointsEvent::Open => {}
+            WaypointsEvent::Close => ctx.waypoints_popup = None,
+            WaypointsEvent::Select(Direction2::Down) => {
+                if !popup.entries.is_empty() {
+                    select_next_clamp(&mut popup.list_state, popup.entries.len() - 1)
+                }
+            }
+            WaypointsEvent::Select(Direction2::Up) => {
+                select_previous_clamp(&mut popup.list_state, 0)
+            }
+            WaypointsEvent::Add => {
+                let no_ships = ShipsMapping::default();
+                let ships = ships.as_deref().unwrap_or(&no_ships);
+                popup.error =
+                    match parse_waypoint(&popup.kind, &popup.kind_fields(), &bodies, ships)
+                        .and_then(|kind| waypoints.insert(&popup.name, kind))
+                    {
+                        Ok(_) => None,
+                        Err(e) => Some(e.to_string()),
+                    };
+            }
+            WaypointsEvent::Remove => {
+                if let Some(name) = popup.selected_waypoint() {
+                    waypoints.0.remove(&name);
+                }
+            }
+            WaypointsEvent::Goto => {
+                let Some(pos) = popup
+                    .selected_waypoint()
+                    .and_then(|name| positions.0.get(&name))
+                else {
+                    continue;
+                };
+                let focus = space_map
+                    .focus_body
+                    .and_then(|f| objects.get(f).ok())
+                    .map_or(DVec3::ZERO, |p| p.0);
+                space_map.offset_amount = project_onto_plane(*pos - focus, (DVec3::X, DVec3::Y));
+            }
+        }
+    }
+}
+
+/// Lists the waypoints in the popup
+fn update_waypoints_popup(mut ctx: ResMut<ExplorerContext>, waypoints: Res<Waypoints>) {
+    let Some(popup) = &ctx.waypoints_popup else {
+        return;
+    };
+    let entries: Vec<_> = waypoints
+        .0
+        .iter()
+        .map(|(name, kind)| (*name, kind.to_string()))
+        .collect();
+    if popup.entries != entries {
+        let popup = ctx.waypoints_popup.as_mut().unwrap();
+        popup.entries = entries;
+        if popup.list_state.selected().is_none() || popup.entries.is_empty() {
+            popup
+                .list_state
+                .select((!popup.entries.is_empty()).then_some(0));
+        } else {
+            let last = popup.entries.len() - 1;
+            popup
+                .list_state
+                .select(popup.list_state.selected().map(|i| i.min(last)));
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn update_space_map(
     mut ctx: ResMut<ExplorerContext>,
     mut space_map: ResMut<SpaceMap>,
     query: Query<(Entity, &Position, &BodyInfo)>,
+    rings: Query<(&Position, &BodyInfo, &EllipticalOrbit, &RingSystem)>,
+    ships: Query<(Entity, &Position, &ShipInfo, Option<&LastServerUpdate>)>,
+    waypoints: Res<WaypointPositions>,
+    display_settings: Res<MapDisplaySettings>,
     mapping: Res<BodiesMapping>,
 ) {
     space_map.selected = mapping.0.get(&ctx.selected_body()).cloned();
-    ctx.space_map.update_map(space_map.as_ref(), &query);
+    ctx.space_map.update_map(
+        space_map.as_ref(),
+        &query,
+        &rings,
+        &ships,
+        &waypoints,
+        &display_settings,
+    );
 }
 
+/// Shows the next conjunctions of the selected body, to help planning missions between bodies
+fn update_conjunctions(
+    mut ctx: ResMut<ExplorerContext>,
+    monitor: Res<SynodicMonitor>,
+    mapping: Res<BodiesMapping>,
+    bodies: Query<&BodyInfo>,
+) {
+    let days_per_tick = GAMETIME_PER_SIMTICK * SIMTICKS_PER_TICK as f64;
+    let conjunctions = monitor
+        .conjunctions_of(ctx.info.body_info.id)
+        .filter_map(|(id, tick)| {
+            let name = &bodies.get(*mapping.0.get(&id)?).ok()?.0.name;
+            Some((name.clone(), tick as f64 * days_per_tick))
+        })
+        .collect();
+    if ctx.info.conjunctions != conjunctions {
+        ctx.info.conjunctions = conjunctions;
+    }
+}
+
+/// Shows the countdown before the selected body merges with its companion, if it is in a
+/// [BinaryPair]
+fn update_inspiral(
+    mut ctx: ResMut<ExplorerContext>,
+    pairs: Query<&BinaryPair>,
+    mapping: Res<BodiesMapping>,
+    bodies: Query<(&BodyInfo, &Mass)>,
+) {
+    let id = ctx.info.body_info.id;
+    let inspiral = pairs.iter().find_map(|pair| {
+        let companion = match pair.bodies {
+            (a, b) if a == id => b,
+            (a, b) if b == id => a,
+            _ => return None,
+        };
+        let body = |id| bodies.get(*mapping.0.get(&id)?).ok();
+        let ((_, m1), (info, m2)) = (body(id)?, body(companion)?);
+        let days = time_to_merger(m1.0, m2.0, pair.separation);
+        Some((info.0.name.clone(), pair.separation, days))
+    });
+    if ctx.info.inspiral != inspiral {
+        ctx.info.inspiral = inspiral;
+    }
+}
+
+/// Focuses on the clicked body, or follows the clicked ship
 fn focus_on_select_body(
     mut events: EventReader<SelectObjectEvent>,
     info: Query<&BodyInfo>,
+    ships: Query<&ShipInfo>,
     mut space_map: ResMut<SpaceMap>,
     mut ctx: ResMut<ExplorerContext>,
+    mut spectate: ResMut<SpectateTarget>,
 ) {
     for event in events.read() {
         if let Ok(info) = info.get(event.entity) {
             space_map.focus(event.entity);
             ctx.tree_state.focus_body(info.0.id)
+        } else if let Ok(ship) = ships.get(event.entity) {
+            spectate.follow(FollowTarget::Ship(ship.id));
         }
     }
 }
@@ -373,5 +722,37 @@ impl StatefulWidget for ExplorerScreen<'_> {
         if state.info_toggle {
             state.info.render_ref(chunks[2], buf);
         }
+
+        // Waypoints popup
+        if let Some(popup) = &mut state.waypoints_popup {
+            let area = centered_rect(70, 70, area);
+            Clear.render(area, buf);
+            let chunks = Layout::vertical([Constraint::Length(3), Constraint::Fill(1)]).split(area);
+            Paragraph::new("Waypoints".bold())
+                .alignment(Alignment::Center)
+                .render(chunks[0], buf);
+            let body = Layout::horizontal([Constraint::Percentage(50), Constraint::Fill(1)])
+                .split(chunks[1]);
+
+            // Fields of the new waypoint, with the last error below
+            let mut constraints = [Constraint::Length(3)].repeat(7);
+            constraints.push(Constraint::Fill(1));
+            let fields = Layout::vertical(constraints).split(body[0]);
+            for i in 0..7 {
+                popup.paragraph(i).render(fields[i], buf);
+            }
+            if let Some(error) = &popup.error {
+                Paragraph::new(Line::from(error.clone().red())).render(fields[7], buf);
+            }
+
+            let entries = popup
+                .entries
+                .iter()
+                .map(|(name, description)| format!("{name}: {description}"));
+            let list = List::new(entries)
+                .highlight_symbol(">")
+                .block(Block::bordered().title_top("Saved waypoints"));
+            <List as StatefulWidget>::render(list, body[1], buf, &mut popup.list_state);
+        }
     }
 }
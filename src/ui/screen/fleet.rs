@@ -1,20 +1,46 @@
 use std::{error::Error, num::ParseFloatError};
 
-use arrayvec::CapacityError;
-use bevy::prelude::*;
+use bevy::{math::DVec3, prelude::*};
 use bevy_ratatui::event::KeyEvent;
 use crossterm::event::{KeyCode, KeyEventKind};
 use ratatui::{
     layout::{Alignment, Constraint, Layout},
     style::Stylize,
-    widgets::{Block, Clear, List, ListState, Paragraph, StatefulWidget, Widget},
+    text::Line,
+    widgets::{Block, Clear, List, ListState, Paragraph, StatefulWidget, Tabs, Widget, Wrap},
 };
 
 use crate::{
-    objects::id::MAX_ID_LENGTH,
+    client::ClientRole,
+    game::Authoritative,
+    network::{Role, ShipRejectionReason},
+    objects::{
+        bodies::lookup::BodyLookupError,
+        id::IdError,
+        ships::{
+            autopilot::{plan_spiral, Autopilot, AutopilotKind, SpiralError, SpiralPlan},
+            history::{HistoryEvent, HistoryFormat, RecordHistory, DEFAULT_RECORD_INTERVAL},
+            navigation::{NavigationFix, MIN_BEACONS},
+            SpawnOrbit, SurfaceRelativeVelocity,
+        },
+    },
+    physics::{influence::HillRadius, G, SECONDS_PER_DAY},
     prelude::*,
-    ui::UiUpdate,
-    utils::{algebra::circular_orbit_around_body, list::OptionsList, ui::centered_rect},
+    ui::{
+        screen::inspector::reflected_components,
+        spectate::{FollowTarget, SpectateTarget},
+        widget::profiler::ProfilerOverlay,
+        UiUpdate,
+    },
+    utils::{
+        algebra::{
+            checked_circular_orbit_around_body, circular_orbit_around_body, osculating_elements,
+            OrbitPlane, OrbitSpawnError, OsculatingElements,
+        },
+        hash::hash,
+        list::OptionsList,
+        ui::centered_rect,
+    },
 };
 
 pub fn plugin(app: &mut App) {
@@ -23,18 +49,32 @@ pub fn plugin(app: &mut App) {
             Update,
             (
                 read_input.in_set(InputReading),
-                handle_fleet_events
-                    .pipe(exit_on_error_if_app)
-                    .in_set(EventHandling),
+                (handle_fleet_events, surface_rejected_ships).in_set(EventHandling),
             )
                 .run_if(in_loaded_screen::<FleetContext>(AppScreen::Fleet)),
         )
+        .add_systems(
+            PostUpdate,
+            (
+                select_followed_ship.run_if(resource_changed::<SpectateTarget>),
+                update_ship_systems,
+                update_surface_speed,
+                update_navigation_fix,
+                update_creation_preview,
+                update_autopilot_preview,
+            )
+                .chain()
+                .run_if(in_loaded_screen::<FleetContext>(AppScreen::Fleet))
+                .in_set(UiUpdate),
+        )
         .add_systems(
             PostUpdate,
             update_fleet_context
                 .run_if(state_exists::<GameStage>)
                 .run_if(
-                    state_changed::<GameStage>.or_else(resource_exists_and_changed::<ShipsMapping>),
+                    state_changed::<GameStage>
+                        .or_else(resource_exists_and_changed::<ShipsMapping>)
+                        .or_else(resource_changed::<ClientRole>),
                 )
                 .in_set(UiUpdate),
         )
@@ -49,9 +89,21 @@ fn create_screen(
     mut commands: Commands,
     mut next_screen: ResMut<NextState<AppScreen>>,
     ships: Query<&ShipInfo>,
+    role: Res<ClientRole>,
 ) {
     commands.insert_resource(FleetContext::new(ships.iter().cloned()));
-    next_screen.set(AppScreen::Fleet);
+    // Spectators follow ships on the map instead
+    next_screen.set(match role.0 {
+        Role::Spectator => AppScreen::Explorer,
+        _ => AppScreen::Fleet,
+    });
+}
+
+/// Shows the details of the followed ship
+fn select_followed_ship(mut context: ResMut<FleetContext>, spectate: Res<SpectateTarget>) {
+    if let Some(FollowTarget::Ship(id)) = spectate.target {
+        context.select_ship(id);
+    }
 }
 
 fn clear_screen(mut commands: Commands) {
@@ -63,24 +115,62 @@ pub struct FleetContext {
     list_state: ListState,
     ships: Vec<ShipInfo>,
     popup_context: Option<CreateShipContext>,
+    autopilot_context: Option<AutopilotContext>,
     stage: GameStage,
+    info_tab: InfoTab,
+    /// Name and debug representation of the reflected components of the selected ship
+    systems: Vec<(String, String)>,
+    /// Last error that happened while creating a ship, displayed until the next attempt
+    creation_error: Option<ShipCreationError>,
+    /// Role given by the server, spectators can't create ships
+    role: Role,
+    /// Speed of the selected ship relative to the surface of its main influencer (in km/day)
+    surface_speed: Option<f64>,
+    /// Position of the selected ship trilaterated from radio beacons
+    navigation_fix: Option<NavigationFix>,
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub enum InfoTab {
+    #[default]
+    Info,
+    Systems,
+}
+
+impl InfoTab {
+    fn next(self) -> Self {
+        match self {
+            Self::Info => Self::Systems,
+            Self::Systems => Self::Info,
+        }
+    }
 }
 
 #[allow(clippy::large_enum_variant)]
 #[derive(Event, Clone)]
 pub enum FleetScreenEvent {
     Select(Direction2),
+    SwitchInfoTab,
+    ToggleProfiler,
+    ToggleRecording,
+    ExportHistory,
+    Follow,
     TryNewShip(CreateShipContext),
+    EngageAutopilot(AutopilotKind),
     EditTrajectory,
     EnterExplorer,
+    Inspect,
     Back,
 }
 
 #[derive(Clone, Debug)]
 pub enum ShipCreationError {
     ParseError(ParseFloatError),
-    IDTooLong,
+    InvalidId(IdError),
     ShipAlreadyExists(ShipID),
+    Rejected(ShipID, ShipRejectionReason),
+    InvalidOrbit(OrbitSpawnError),
+    UnknownHost(BodyLookupError),
 }
 
 impl From<ParseFloatError> for ShipCreationError {
@@ -89,9 +179,21 @@ impl From<ParseFloatError> for ShipCreationError {
     }
 }
 
-impl From<CapacityError> for ShipCreationError {
-    fn from(_value: CapacityError) -> Self {
-        Self::IDTooLong
+impl From<OrbitSpawnError> for ShipCreationError {
+    fn from(value: OrbitSpawnError) -> Self {
+        Self::InvalidOrbit(value)
+    }
+}
+
+impl From<BodyLookupError> for ShipCreationError {
+    fn from(value: BodyLookupError) -> Self {
+        Self::UnknownHost(value)
+    }
+}
+
+impl From<IdError> for ShipCreationError {
+    fn from(value: IdError) -> Self {
+        Self::InvalidId(value)
     }
 }
 
@@ -99,6 +201,9 @@ impl Error for ShipCreationError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
             ShipCreationError::ParseError(e) => Some(e),
+            ShipCreationError::InvalidOrbit(e) => Some(e),
+            ShipCreationError::UnknownHost(e) => Some(e),
+            ShipCreationError::InvalidId(e) => Some(e),
             _ => None,
         }
     }
@@ -115,11 +220,12 @@ impl std::fmt::Display for ShipCreationError {
                 "Couldn't create ship with id \"{}\" because it already exists",
                 id
             ),
-            ShipCreationError::IDTooLong => write!(
-                f,
-                "Couldn't create ship because id is too long (max length = {})",
-                MAX_ID_LENGTH
-            ),
+            ShipCreationError::Rejected(id, reason) => {
+                write!(f, "The server rejected ship \"{}\": {}", id, reason)
+            }
+            ShipCreationError::InvalidOrbit(e) => write!(f, "Couldn't create ship: {}", e),
+            ShipCreationError::UnknownHost(e) => write!(f, "Couldn't create ship: {}", e),
+            ShipCreationError::InvalidId(e) => write!(f, "Couldn't create ship: {}", e),
         }
     }
 }
@@ -139,6 +245,9 @@ pub struct CreateShipContext {
     id_text: String,
     host_body: String,
     altitude: String,
+    inclination: String,
+    raan: String,
+    phase: String,
     pos_x: String,
     pos_y: String,
     pos_z: String,
@@ -146,19 +255,104 @@ pub struct CreateShipContext {
     speed_y: String,
     speed_z: String,
     selected: usize,
+    preview: CreationPreview,
+    /// Hash of the fields the preview was computed from
+    previewed_fields: Option<u64>,
+}
+
+/// Quantities derived from the fields of the creation popup, updated as they are typed.
+/// Fields that are empty or can't be parsed yet leave the quantities depending on them unknown
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct CreationPreview {
+    /// Speed (in km/day) and period (in days) of the circular orbit around the host body
+    circular: Option<(f64, f64)>,
+    /// Distance of the raw position from the primary body (in km)
+    distance_to_primary: Option<f64>,
+    /// Norm of the raw velocity (in km/day)
+    speed: Option<f64>,
+    /// Osculating orbit around the body nearest to the raw position, and whether its periapsis is
+    /// under the surface of the body
+    osculating: Option<(BodyID, OsculatingElements, bool)>,
+    /// Why the angles of the plane of the circular orbit are invalid
+    plane_error: Option<String>,
+}
+
+fn format_duration(days: f64) -> String {
+    let minutes = days * 24. * 60.;
+    if minutes < 60. {
+        format!("{minutes:.1} min")
+    } else if days < 1. {
+        format!("{} h {:.0} min", (minutes / 60.) as u64, minutes % 60.)
+    } else {
+        format!("{days:.2} days")
+    }
+}
+
+impl CreationPreview {
+    fn lines(&self) -> Vec<Line<'static>> {
+        const UNKNOWN: &str = "-";
+        let mut lines = Vec::new();
+        if let Some(e) = &self.plane_error {
+            lines.push(Line::from(e.clone().red()));
+        }
+        lines.extend([
+            Line::from(match self.circular {
+                Some((speed, period)) => format!(
+                    "Circular orbit: {:.3} km/s, period {}",
+                    speed / SECONDS_PER_DAY,
+                    format_duration(period)
+                ),
+                None => format!("Circular orbit: {UNKNOWN}"),
+            }),
+            Line::from(format!(
+                "Distance from primary: {}",
+                self.distance_to_primary
+                    .map_or(UNKNOWN.into(), |d| format!("{d:.0} km"))
+            )),
+            Line::from(format!(
+                "Speed: {}",
+                self.speed.map_or(UNKNOWN.into(), |v| format!(
+                    "{:.3} km/s ({v:.0} km/day)",
+                    v / SECONDS_PER_DAY
+                ))
+            )),
+        ]);
+        match &self.osculating {
+            Some((body, elements, crash)) => {
+                lines.push(Line::from(format!(
+                    "Orbit around {body}: periapsis {:.0} km, apoapsis {}",
+                    elements.periapsis,
+                    elements
+                        .apoapsis
+                        .map_or(UNKNOWN.into(), |a| format!("{a:.0} km"))
+                )));
+                if elements.is_escape() {
+                    lines.push(Line::from("escape trajectory".red()));
+                }
+                if *crash {
+                    lines.push(Line::from("sub-surface periapsis (will crash)".red()));
+                }
+            }
+            None => lines.push(Line::from(format!("Orbit: {UNKNOWN}"))),
+        }
+        lines
+    }
 }
 
-impl OptionsList<9> for CreateShipContext {
+impl OptionsList<12> for CreateShipContext {
     fn current_index(&mut self) -> &mut usize {
         &mut self.selected
     }
 
-    fn fields_list(&mut self) -> [(&mut String, String); 9] {
+    fn fields_list(&mut self) -> [(&mut String, String); 12] {
         [
             (&mut self.id_text, "Ship ID".into()),
             // TODO: add search or tree widget instead of plain id
-            (&mut self.host_body, "Host body id".into()),
+            (&mut self.host_body, "Host body (id or name)".into()),
             (&mut self.altitude, "Spawn Altitude".into()),
+            (&mut self.inclination, "Inclination (°)".into()),
+            (&mut self.raan, "RAAN (°)".into()),
+            (&mut self.phase, "Phase (°)".into()),
             (&mut self.pos_x, "Spawn x".into()),
             (&mut self.pos_y, "Spawn y".into()),
             (&mut self.pos_z, "Spawn z".into()),
@@ -170,11 +364,97 @@ impl OptionsList<9> for CreateShipContext {
 }
 
 impl CreateShipContext {
+    /// Orientation of the circular orbit, whose blank angles keep their default
+    fn plane(&self) -> Result<OrbitPlane, ParseFloatError> {
+        let angle = |s: &String| match s.trim() {
+            "" => Ok(None),
+            s => s.parse().map(Some),
+        };
+        Ok(OrbitPlane {
+            inclination: angle(&self.inclination)?,
+            raan: angle(&self.raan)?,
+            phase: angle(&self.phase)?,
+        })
+    }
+
+    fn fields_hash(&self) -> u64 {
+        hash(&[
+            &self.id_text,
+            &self.host_body,
+            &self.altitude,
+            &self.inclination,
+            &self.raan,
+            &self.phase,
+            &self.pos_x,
+            &self.pos_y,
+            &self.pos_z,
+            &self.speed_x,
+            &self.speed_y,
+            &self.speed_z,
+        ])
+    }
+
+    fn compute_preview(
+        &self,
+        bodies: &Query<(&BodyInfo, &Mass, &Position, &Velocity, &HillRadius)>,
+        mapping: &BodiesMapping,
+        primary: Option<DVec3>,
+        seed: &WorldSeed,
+    ) -> CreationPreview {
+        let parse = |s: &String| s.trim().parse::<f64>().ok();
+        let host = mapping
+            .resolve(&self.host_body)
+            .and_then(|e| bodies.get(e).ok());
+        let circular = host.zip(parse(&self.altitude)).map(
+            |((BodyInfo(data), &Mass(m), &Position(p), &Velocity(v), _), altitude)| {
+                let (pos, speed) = circular_orbit_around_body(
+                    data.radius + altitude,
+                    m,
+                    p,
+                    v,
+                    &mut seed.rng("creation_preview"),
+                );
+                let elements = osculating_elements(pos - p, speed - v, G * m);
+                ((speed - v).length(), elements.period.unwrap_or(f64::NAN))
+            },
+        );
+        let raw = |fields: [&String; 3]| -> Option<DVec3> {
+            Some(DVec3::new(
+                parse(fields[0])?,
+                parse(fields[1])?,
+                parse(fields[2])?,
+            ))
+        };
+        let pos = raw([&self.pos_x, &self.pos_y, &self.pos_z]);
+        let speed = raw([&self.speed_x, &self.speed_y, &self.speed_z]);
+        let osculating = pos.zip(speed).and_then(|(pos, speed)| {
+            let (BodyInfo(data), &Mass(m), &Position(p), &Velocity(v), _) = bodies
+                .iter()
+                .min_by(|a, b| pos.distance(a.2 .0).total_cmp(&pos.distance(b.2 .0)))?;
+            let elements = osculating_elements(pos - p, speed - v, G * m);
+            Some((data.id, elements, elements.periapsis < data.radius))
+        });
+        let plane_error = match self.plane() {
+            Ok(plane) => plane.validate().err().map(|e| e.to_string()),
+            Err(e) => Some(format!("invalid angle: {e}")),
+        };
+        CreationPreview {
+            circular,
+            distance_to_primary: pos.zip(primary).map(|(pos, p)| pos.distance(p)),
+            speed: speed.map(|s| s.length()),
+            osculating,
+            plane_error,
+        }
+    }
+
+    /// Creates the info of the new ship, with an id given by `generate_id` if the id field is empty
     fn to_info<'a>(
         &self,
         mut ships: impl Iterator<Item = &'a ShipInfo>,
-        bodies: &Query<(&Mass, &Position, &Velocity)>,
+        bodies: &Query<(&BodyInfo, &Mass, &Position, &Velocity, &HillRadius)>,
         mapping: &BodiesMapping,
+        generate_id: impl FnOnce() -> ShipID,
+        seed: &WorldSeed,
     ) -> Result<ShipInfo, ShipCreationError> {
         let CreateShipContext {
             id_text,
@@ -188,17 +468,45 @@ impl CreateShipContext {
             speed_z,
             ..
         } = self;
-        let (spawn_pos, spawn_speed) =
-            if let Some(body) = BodyID::from(host_body).ok().and_then(|i| mapping.0.get(&i)) {
-                let (Mass(m), Position(p), Velocity(v)) = bodies.get(*body).unwrap();
-                circular_orbit_around_body(altitude.parse()?, *m, *p, *v)
+        let id = if id_text.is_empty() {
+            generate_id()
+        } else {
+            ShipID::new(id_text)?
+        };
+        // An empty host body stands for raw coordinates, but a host body that is not found is an
+        // error rather than a silent switch to them
+        let host = (!host_body.trim().is_empty())
+            .then(|| mapping.lookup(host_body))
+            .transpose()?;
+        let (spawn_pos, spawn_speed, spawn_orbit) =
+            if let Some(body) = host.and_then(|id| mapping.0.get(&id)) {
+                let (BodyInfo(data), Mass(m), Position(p), Velocity(v), HillRadius(hill)) =
+                    bodies.get(*body).unwrap();
+                let mut rng = seed.rng(("ship_spawn", id));
+                let altitude = altitude.parse::<f64>()?;
+                let (pos, speed) = checked_circular_orbit_around_body(
+                    altitude,
+                    data.radius,
+                    *hill,
+                    *m,
+                    *p,
+                    *v,
+                    self.plane()?,
+                    &mut rng,
+                )?;
+                let orbit = SpawnOrbit {
+                    host: data.id,
+                    altitude,
+                };
+                (pos, speed, Some(orbit))
             } else {
+                // Raw coordinates are not checked, to allow any state on purpose
                 (
                     (pos_x.parse()?, pos_y.parse()?, pos_z.parse()?).into(),
                     (speed_x.parse()?, speed_y.parse()?, speed_z.parse()?).into(),
+                    None,
                 )
             };
-        let id = ShipID::from(id_text).map_err(CapacityError::simplify)?;
         if ships.any(|s| s.id == id) {
             Err(ShipCreationError::ShipAlreadyExists(id))
         } else {
@@ -206,11 +514,64 @@ impl CreateShipContext {
                 id,
                 spawn_pos,
                 spawn_speed,
+                spawn_orbit,
             })
         }
     }
 }
 
+/// Fields of the popup engaging a low-thrust spiral autopilot on the selected ship
+#[derive(Default, Clone)]
+pub struct AutopilotContext {
+    target_body: String,
+    thrust_accel: String,
+    selected: usize,
+    /// Spiral planned from the fields, shown before the autopilot is engaged. None while the
+    /// fields can't be parsed
+    preview: Option<Result<SpiralPlan, SpiralError>>,
+    /// Whether the autopilot was refused because this client doesn't run the simulation
+    refused: bool,
+}
+
+impl OptionsList<2> for AutopilotContext {
+    fn current_index(&mut self) -> &mut usize {
+        &mut self.selected
+    }
+
+    fn fields_list(&mut self) -> [(&mut String, String); 2] {
+        [
+            (&mut self.target_body, "Target body id".into()),
+            (&mut self.thrust_accel, "Thrust acceleration (km/s²)".into()),
+        ]
+    }
+}
+
+impl AutopilotContext {
+    fn kind(&self) -> Option<AutopilotKind> {
+        Some(AutopilotKind::LowThrustSpiral {
+            target_body: BodyID::new(self.target_body.trim()).ok()?,
+            thrust_accel_km_s2: self.thrust_accel.parse().ok()?,
+        })
+    }
+
+    fn lines(&self) -> Vec<Line<'static>> {
+        let mut lines = match &self.preview {
+            Some(Ok(plan)) => vec![
+                Line::from(format!("Delta-v: {:.3} km/s", plan.delta_v)),
+                Line::from(format!("Mission time: {}", format_duration(plan.duration))),
+            ],
+            Some(Err(e)) => vec![Line::from(e.to_string().red())],
+            None => vec![Line::from("Delta-v: -"), Line::from("Mission time: -")],
+        };
+        if self.refused {
+            lines.push(Line::from(
+                "Autopilots only run in singleplayer for now".red(),
+            ));
+        }
+        lines
+    }
+}
+
 impl FleetContext {
     pub fn new(ships: impl Iterator<Item = ShipInfo>) -> Self {
         Self {
@@ -219,7 +580,13 @@ impl FleetContext {
         }
     }
     fn selected_ship(&self) -> Option<&ShipInfo> {
-        self.list_state.selected().map(|i| &self.ships[i])
+        self.list_state.selected().and_then(|i| self.ships.get(i))
+    }
+
+    fn select_ship(&mut self, id: ShipID) {
+        if let Some(i) = self.ships.iter().position(|s| s.id == id) {
+            self.list_state.select(Some(i));
+        }
     }
 }
 
@@ -238,6 +605,27 @@ fn read_input(
         if event.kind == KeyEventKind::Release {
             return;
         }
+        if let Some(ctx) = &mut context.autopilot_context {
+            match event {
+                e if keymap.cycle_options.matches(e) => ctx.select_next(),
+                e if keymap.cycle_options_back.matches(e) => ctx.select_previous(),
+                e if keymap.back.matches(e) => context.autopilot_context = None,
+                e if keymap.validate_new_ship.matches(e) => {
+                    if let (Some(kind), Some(Ok(_))) = (ctx.kind(), &ctx.preview) {
+                        internal_event.send(EngageAutopilot(kind));
+                    }
+                }
+                e if keymap.delete_char.matches(e) => {
+                    ctx.selected_field().pop();
+                }
+                crossterm::event::KeyEvent {
+                    code: KeyCode::Char(c),
+                    ..
+                } => ctx.selected_field().push(*c),
+                _ => {}
+            }
+            continue;
+        }
         match &mut context.popup_context {
             None => match event {
                 e if keymap.select_next.matches(e) => {
@@ -249,8 +637,9 @@ fn read_input(
                 e if keymap.edit_trajectory.matches(e) => {
                     internal_event.send(EditTrajectory);
                 }
-                e if keymap.new_ship.matches(e) => {
-                    context.popup_context = Some(CreateShipContext::default())
+                e if keymap.new_ship.matches(e) && context.role != Role::Spectator => {
+                    context.popup_context = Some(CreateShipContext::default());
+                    context.creation_error = None;
                 }
                 e if keymap.back.matches(e) => {
                     internal_event.send(Back);
@@ -258,6 +647,30 @@ fn read_input(
                 e if keymap.enter_explorer.matches(e) => {
                     internal_event.send(EnterExplorer);
                 }
+                e if keymap.switch_info_tab.matches(e) => {
+                    internal_event.send(SwitchInfoTab);
+                }
+                e if keymap.toggle_profiler.matches(e) => {
+                    internal_event.send(ToggleProfiler);
+                }
+                e if keymap.toggle_recording.matches(e) => {
+                    internal_event.send(ToggleRecording);
+                }
+                e if keymap.export_history.matches(e) => {
+                    internal_event.send(ExportHistory);
+                }
+                e if keymap.follow.matches(e) => {
+                    internal_event.send(Follow);
+                }
+                e if keymap.inspect.matches(e) => {
+                    internal_event.send(Inspect);
+                }
+                e if keymap.autopilot.matches(e)
+                    && context.role != Role::Spectator
+                    && context.selected_ship().is_some() =>
+                {
+                    context.autopilot_context = Some(AutopilotContext::default());
+                }
                 _ => {}
             },
             Some(ctx) => match event {
@@ -280,42 +693,131 @@ fn read_input(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn handle_fleet_events(
     mut context: ResMut<FleetContext>,
     mut next_screen: ResMut<NextState<AppScreen>>,
     mut next_mode: ResMut<NextState<ClientMode>>,
     mut events: EventReader<FleetScreenEvent>,
     mut ship_events: EventWriter<ShipEvent>,
-    bodies: Query<(&Mass, &Position, &Velocity)>,
+    bodies: Query<(&BodyInfo, &Mass, &Position, &Velocity, &HillRadius)>,
     mapping: Res<BodiesMapping>,
-) -> color_eyre::eyre::Result<()> {
+    ships: Res<ShipsMapping>,
+    mut generator: ResMut<IdGenerator>,
+    mut profiler: ResMut<ProfilerOverlay>,
+    mut history: EventWriter<HistoryEvent>,
+    recording: Query<(), With<RecordHistory>>,
+    mut spectate: ResMut<SpectateTarget>,
+    seed: Res<WorldSeed>,
+    mut commands: Commands,
+    authoritative: Option<Res<State<Authoritative>>>,
+) {
     for event in events.read() {
         match event {
             FleetScreenEvent::Select(d) => context.select_adjacent(*d),
+            FleetScreenEvent::SwitchInfoTab => context.info_tab = context.info_tab.next(),
+            FleetScreenEvent::ToggleProfiler => profiler.enabled = !profiler.enabled,
             FleetScreenEvent::TryNewShip(ctx) => {
-                let info = ctx.to_info(context.ships.iter(), &bodies, mapping.as_ref())?;
-                context.ships.push(info.clone());
-                ship_events.send(ShipEvent::Create(info.clone()));
-                context.popup_context = None;
+                let generate_id = || {
+                    generator.generate(|id| {
+                        ships.0.contains_key(id) || context.ships.iter().any(|s| s.id == *id)
+                    })
+                };
+                match ctx.to_info(
+                    context.ships.iter(),
+                    &bodies,
+                    mapping.as_ref(),
+                    generate_id,
+                    &seed,
+                ) {
+                    Ok(info) => {
+                        context.ships.push(info.clone());
+                        ship_events.send(ShipEvent::Create(info.clone()));
+                        context.popup_context = None;
+                        context.creation_error = None;
+                    }
+                    Err(e) => context.creation_error = Some(e),
+                }
+            }
+            FleetScreenEvent::EngageAutopilot(kind) => {
+                // Autopilots are run by the simulation, which is not synchronized with the server
+                if authoritative.is_none() {
+                    if let Some(ctx) = &mut context.autopilot_context {
+                        ctx.refused = true;
+                    }
+                    continue;
+                }
+                if let Some(&entity) = context
+                    .selected_ship()
+                    .and_then(|ship| ships.0.get(&ship.id))
+                {
+                    commands.entity(entity).insert(Autopilot::new(kind.clone()));
+                    context.autopilot_context = None;
+                }
             }
             FleetScreenEvent::EditTrajectory => {
                 if let Some(ship) = context.selected_ship() {
                     next_screen.set(AppScreen::Editor(ship.id));
                 }
             }
+            FleetScreenEvent::ToggleRecording => {
+                if let Some(&ShipInfo { id: ship, .. }) = context.selected_ship() {
+                    let recorded = ships.0.get(&ship).is_some_and(|&e| recording.contains(e));
+                    history.send(if recorded {
+                        HistoryEvent::Stop(ship)
+                    } else {
+                        HistoryEvent::Start {
+                            ship,
+                            interval_ticks: DEFAULT_RECORD_INTERVAL,
+                        }
+                    });
+                }
+            }
+            FleetScreenEvent::ExportHistory => {
+                if let Some(ship) = context.selected_ship() {
+                    history.send(HistoryEvent::Export {
+                        ship: ship.id,
+                        format: HistoryFormat::Csv,
+                    });
+                }
+            }
+            FleetScreenEvent::Follow => match (spectate.target, context.selected_ship()) {
+                (Some(FollowTarget::Ship(_)), _) | (_, None) => {
+                    spectate.cycle_ships(ships.0.keys().copied())
+                }
+                (_, Some(ship)) => spectate.follow(FollowTarget::Ship(ship.id)),
+            },
             FleetScreenEvent::Back => next_mode.set(ClientMode::None),
             FleetScreenEvent::EnterExplorer => next_screen.set(AppScreen::Explorer),
+            FleetScreenEvent::Inspect => {
+                if let Some(&entity) = context
+                    .selected_ship()
+                    .and_then(|ship| ships.0.get(&ship.id))
+                {
+                    next_screen.set(AppScreen::Inspector(entity));
+                }
+            }
+        }
+    }
+}
+
+/// Displays the ship creations that were rejected by the server
+fn surface_rejected_ships(mut context: ResMut<FleetContext>, mut events: EventReader<ShipEvent>) {
+    for event in events.read() {
+        if let ShipEvent::Rejected(id, reason) = event {
+            context.creation_error = Some(ShipCreationError::Rejected(*id, *reason));
         }
     }
-    Ok(())
 }
 
 fn update_fleet_context(
     stage: Res<State<GameStage>>,
     ships: Query<&ShipInfo>,
+    role: Res<ClientRole>,
     mut ctx: ResMut<FleetContext>,
 ) {
     ctx.stage = stage.get().clone();
+    ctx.role = role.0;
     ctx.ships.retain(|i| ships.iter().any(|j| i == j));
     let diff = ships
         .iter()
@@ -324,6 +826,127 @@ fn update_fleet_context(
     ctx.ships.extend(diff);
 }
 
+fn update_surface_speed(
+    mut ctx: ResMut<FleetContext>,
+    mapping: Res<ShipsMapping>,
+    velocities: Query<&SurfaceRelativeVelocity>,
+) {
+    let speed = ctx
+        .selected_ship()
+        .and_then(|info| mapping.0.get(&info.id))
+        .and_then(|&e| velocities.get(e).ok())
+        .map(|v| v.0.length());
+    if ctx.surface_speed != speed {
+        ctx.surface_speed = speed;
+    }
+}
+
+fn update_navigation_fix(
+    mut ctx: ResMut<FleetContext>,
+    mapping: Res<ShipsMapping>,
+    fixes: Query<&NavigationFix>,
+) {
+    let fix = ctx
+        .selected_ship()
+        .and_then(|info| mapping.0.get(&info.id))
+        .and_then(|&e| fixes.get(e).ok())
+        .copied();
+    if ctx.navigation_fix != fix {
+        ctx.navigation_fix = fix;
+    }
+}
+
+fn update_creation_preview(
+    mut context: ResMut<FleetContext>,
+    bodies: Query<(&BodyInfo, &Mass, &Position, &Velocity, &HillRadius)>,
+    primary: Query<&Position, With<PrimaryBody>>,
+    mapping: Res<BodiesMapping>,
+    seed: Res<WorldSeed>,
+) {
+    let Some(ctx) = &context.popup_context else {
+        return;
+    };
+    let fields = ctx.fields_hash();
+    if ctx.previewed_fields == Some(fields) {
+        return;
+    }
+    let preview = ctx.compute_preview(
+        &bodies,
+        &mapping,
+        primary.get_single().ok().map(|p| p.0),
+        &seed,
+    );
+    let ctx = context.popup_context.as_mut().unwrap();
+    ctx.preview = preview;
+    ctx.previewed_fields = Some(fields);
+}
+
+/// Plans the spiral of the autopilot popup from the current state of the selected ship
+fn update_autopilot_preview(
+    mut context: ResMut<FleetContext>,
+    ships: Query<(&Position, &Velocity, &Influenced)>,
+    bodies: Query<(&Position, &Velocity, &Mass, &BodyInfo, &EllipticalOrbit)>,
+    ships_mapping: Res<ShipsMapping>,
+    bodies_mapping: Res<BodiesMapping>,
+) {
+    let Some(ctx) = &context.autopilot_context else {
+        return;
+    };
+    let state = context
+        .selected_ship()
+        .and_then(|ship| ships_mapping.0.get(&ship.id))
+        .and_then(|&e| ships.get(e).ok());
+    let preview = match (ctx.kind(), state) {
+        (
+            Some(AutopilotKind::LowThrustSpiral {
+                target_body,
+                thrust_accel_km_s2,
+            }),
+            Some((pos, vel, influence)),
+        ) => Some(
+            plan_spiral(
+                (pos.0, vel.0),
+                influence,
+                target_body,
+                thrust_accel_km_s2,
+                &bodies,
+                &bodies_mapping,
+            )
+            .map(|(plan, _)| plan),
+        ),
+        _ => None,
+    };
+    if ctx.preview != preview {
+        context.autopilot_context.as_mut().unwrap().preview = preview;
+    }
+}
+
+/// Lists the reflected components of the selected ship, so that any new component is displayed
+/// as soon as it derives [Reflect] and is registered
+fn update_ship_systems(world: &mut World) {
+    world.resource_scope(|world, mut ctx: Mut<FleetContext>| {
+        if ctx.info_tab != InfoTab::Systems {
+            return;
+        }
+        ctx.systems.clear();
+        let Some(entity) = ctx
+            .selected_ship()
+            .and_then(|s| world.get_resource::<ShipsMapping>()?.0.get(&s.id).copied())
+        else {
+            return;
+        };
+        let Some(components) = reflected_components(world, entity) else {
+            return;
+        };
+        let mut systems: Vec<_> = components
+            .into_iter()
+            .map(|(name, reflected)| (name, format!("{:?}", reflected)))
+            .collect();
+        systems.sort();
+        ctx.systems = systems;
+    });
+}
+
 impl StatefulWidget for FleetScreen {
     type State = FleetContext;
 
@@ -338,21 +961,67 @@ impl StatefulWidget for FleetScreen {
 
         // Ship list
         let entries = state.ships.iter().map(|s| s.id.to_string());
-        let list = List::new(entries).highlight_symbol(">").block(
-            Block::bordered()
-                .title_top("Ships")
-                .title_bottom(format!("Current stage: {}", state.stage)),
-        );
+        let title = match state.role {
+            Role::Spectator => "Ships (spectating)",
+            _ => "Ships",
+        };
+        let mut block = Block::bordered()
+            .title_top(title)
+            .title_bottom(format!("Current stage: {}", state.stage));
+        if let Some(error) = &state.creation_error {
+            block = block.title_bottom(Line::from(error.to_string().red()).right_aligned());
+        }
+        let list = List::new(entries).highlight_symbol(">").block(block);
         <List as StatefulWidget>::render(list, chunks[0], buf, &mut state.list_state);
 
         // Ship info
         if let Some(info) = state.selected_ship() {
-            Paragraph::new(format!(
-                "ID: {}\nSpawn position: {}\nSpawn velocity: {}",
-                info.id, info.spawn_pos, info.spawn_speed
-            ))
-            .block(Block::bordered().title_top("Ship info"))
-            .render(chunks[1], buf);
+            let block = Block::bordered().title_top("Ship info");
+            let inner = block.inner(chunks[1]);
+            block.render(chunks[1], buf);
+            let info_chunks =
+                Layout::vertical([Constraint::Length(2), Constraint::Fill(1)]).split(inner);
+            Tabs::new(["Info", "Systems"])
+                .select(match state.info_tab {
+                    InfoTab::Info => 0,
+                    InfoTab::Systems => 1,
+                })
+                .render(info_chunks[0], buf);
+            match state.info_tab {
+                InfoTab::Info => {
+                    let mut text = format!(
+                        "ID: {}\nSpawn position: {}\nSpawn velocity: {}",
+                        info.id, info.spawn_pos, info.spawn_speed
+                    );
+                    if let Some(speed) = state.surface_speed {
+                        text.push_str(&format!(
+                            "\nSpeed relative to surface: {:.3} km/s",
+                            speed / SECONDS_PER_DAY
+                        ));
+                    }
+                    text.push_str(&match state.navigation_fix {
+                        Some(fix) => format!(
+                            "\nNavigation fix: {} ± {:.3} km",
+                            fix.pos, fix.uncertainty_km
+                        ),
+                        None => {
+                            format!("\nNavigation fix: less than {MIN_BEACONS} beacons in range")
+                        }
+                    });
+                    Paragraph::new(text)
+                }
+                InfoTab::Systems => Paragraph::new(
+                    state
+                        .systems
+                        .iter()
+                        .map(|(name, value)| {
+                            Line::from(vec![format!("{name}: ").bold(), value.as_str().into()])
+                        })
+                        .collect::<Vec<_>>(),
+                ),
+            }
+            .wrap(Wrap { trim: false })
+            .render(info_chunks[1], buf);
         }
 
         // Ship creation popup
@@ -370,32 +1039,72 @@ impl StatefulWidget for FleetScreen {
             let body = Layout::horizontal([Constraint::Percentage(50), Constraint::Fill(1)])
                 .split(chunks[1]);
 
-            // Left side of options
-            let mut constraints = [Constraint::Percentage(100 / 3)].repeat(3);
+            // Left side of options, with the preview below
+            let mut constraints = [Constraint::Length(3)].repeat(6);
             constraints.push(Constraint::Fill(1));
             let left = Layout::vertical(constraints).split(body[0]);
-            for i in 0..3 {
+            for i in 0..6 {
                 ctx.paragraph(i).render(left[i], buf);
             }
+            Paragraph::new(ctx.preview.lines())
+                .block(Block::bordered().title_top("Preview"))
+                .wrap(Wrap { trim: false })
+                .render(left[6], buf);
 
             // Right side (spawn coordinates)
             let mut constraints = [Constraint::Percentage(100 / 6)].repeat(6);
             constraints.push(Constraint::Fill(1));
             let coords = Layout::vertical(constraints).split(body[1]);
-            for i in 3..9 {
-                ctx.paragraph(i).render(coords[i - 3], buf);
+            for i in 6..12 {
+                ctx.paragraph(i).render(coords[i - 6], buf);
             }
         }
+
+        // Autopilot popup
+        if let Some(ctx) = &mut state.autopilot_context {
+            let popup = centered_rect(50, 40, area);
+            Clear.render(popup, buf);
+            let chunks = Layout::vertical([
+                Constraint::Length(3),
+                Constraint::Length(3),
+                Constraint::Length(3),
+                Constraint::Fill(1),
+            ])
+            .split(popup);
+            Paragraph::new("Low-thrust spiral autopilot".bold())
+                .alignment(Alignment::Center)
+                .render(chunks[0], buf);
+            for i in 0..2 {
+                ctx.paragraph(i).render(chunks[i + 1], buf);
+            }
+            Paragraph::new(ctx.lines())
+                .block(Block::bordered().title_top("Preview"))
+                .wrap(Wrap { trim: false })
+                .render(chunks[3], buf);
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use bevy::{app::App, prelude::default, state::state::NextState};
+    use bevy::prelude::*;
 
     use crate::prelude::*;
 
-    use super::{CreateShipContext, FleetContext, FleetScreenEvent};
+    use crate::{
+        network::ShipRejectionReason,
+        objects::ships::{
+            autopilot::{Autopilot, AutopilotPhase, SpiralError},
+            trajectory::CurrentTrajectory,
+        },
+        physics::time::SIMTICKS_PER_TICK,
+        utils::algebra::OrbitSpawnError,
+    };
+
+    use super::{
+        AutopilotContext, CreateShipContext, FleetContext, FleetScreenEvent, InfoTab,
+        ShipCreationError,
+    };
 
     fn new_app() -> App {
         let mut app = App::new();
@@ -413,15 +1122,259 @@ mod tests {
         let mut app = new_app();
         let popup = CreateShipContext {
             selected: 0,
+            host_body: " Earth".into(),
+            altitude: "1e4".into(),
+            ..Default::default()
+        };
+        app.world_mut()
+            .send_event(FleetScreenEvent::TryNewShip(popup.clone()));
+        app.update();
+        app.update();
+        assert_eq!(app.world().resource::<ShipsMapping>().0.len(), 1);
+
+        // A misspelled host body doesn't fall back to the raw coordinates
+        app.world_mut()
+            .send_event(FleetScreenEvent::TryNewShip(CreateShipContext {
+                host_body: "Eatrh".into(),
+                pos_x: "1e9".into(),
+                pos_y: "0".into(),
+                pos_z: "0".into(),
+                speed_x: "0".into(),
+                speed_y: "0".into(),
+                speed_z: "0".into(),
+                ..popup
+            }));
+        app.update();
+        let error = app
+            .world()
+            .resource::<FleetContext>()
+            .creation_error
+            .clone();
+        assert!(
+            error
+                .as_ref()
+                .is_some_and(|e| e.to_string().contains("did you mean \"Earth\"")),
+            "{error:?}"
+        );
+        assert_eq!(app.world().resource::<ShipsMapping>().0.len(), 1);
+    }
+
+    #[test]
+    fn test_creation_preview() {
+        let mut app = new_app();
+        app.world_mut().resource_mut::<FleetContext>().popup_context = Some(CreateShipContext {
+            host_body: "terre".into(),
+            altitude: "400".into(),
+            pos_x: "1e9".into(),
+            ..Default::default()
+        });
+        app.update();
+        let ctx = app.world().resource::<FleetContext>();
+        let preview = &ctx.popup_context.as_ref().unwrap().preview;
+        let (_, period) = preview.circular.unwrap();
+        assert!((92. ..93.).contains(&(period * 24. * 60.)), "{period}");
+        assert!(preview.lines()[0].to_string().contains(" min"));
+        // Incomplete raw coordinates
+        assert_eq!(preview.osculating, None);
+        assert!(preview.lines()[1].to_string().ends_with('-'));
+
+        // Standing still next to the Earth
+        let world = app.world_mut();
+        let earth = world.resource::<BodiesMapping>().0["terre"];
+        let (&Position(pos), &Velocity(vel)) = world
+            .query::<(&Position, &Velocity)>()
+            .get(world, earth)
+            .unwrap();
+        let mut ctx = world.resource_mut::<FleetContext>();
+        let popup = ctx.popup_context.as_mut().unwrap();
+        for (field, value) in [
+            (&mut popup.pos_x, pos.x + 7000.),
+            (&mut popup.pos_y, pos.y),
+            (&mut popup.pos_z, pos.z),
+            (&mut popup.speed_x, vel.x),
+            (&mut popup.speed_y, vel.y),
+            (&mut popup.speed_z, vel.z),
+        ] {
+            *field = value.to_string();
+        }
+        app.update();
+        let ctx = app.world().resource::<FleetContext>();
+        let preview = &ctx.popup_context.as_ref().unwrap().preview;
+        let (body, _, crash) = preview.osculating.unwrap();
+        assert_eq!(body, id_from("terre"));
+        assert!(crash);
+        assert!(preview
+            .lines()
+            .iter()
+            .any(|l| l.to_string().contains("will crash")));
+    }
+
+    #[test]
+    fn test_autopilot_preview() {
+        let mut app = App::new();
+        app.add_plugins((
+            ClientPlugin::testing()
+                .with_bodies(BodiesConfig::SmallestBodyType(BodyType::Moon))
+                .in_mode(ClientMode::Singleplayer),
+            TuiPlugin::testing(),
+        ));
+        app.update();
+        app.update();
+        app.world_mut()
+            .send_event(FleetScreenEvent::TryNewShip(CreateShipContext {
+                id_text: "s".into(),
+                host_body: "terre".into(),
+                altitude: "400".into(),
+                ..Default::default()
+            }));
+        app.update();
+        app.update();
+        let mut ctx = app.world_mut().resource_mut::<FleetContext>();
+        ctx.list_state.select(Some(0));
+        ctx.autopilot_context = Some(AutopilotContext {
+            target_body: "soleil".into(),
+            thrust_accel: "1e-6".into(),
+            ..Default::default()
+        });
+        app.update();
+        let ctx = app.world().resource::<FleetContext>();
+        let preview = ctx.autopilot_context.as_ref().unwrap().preview;
+        assert!(matches!(preview, Some(Err(SpiralError::DifferentHost(_)))));
+
+        let mut ctx = app.world_mut().resource_mut::<FleetContext>();
+        ctx.autopilot_context.as_mut().unwrap().target_body = "lune".into();
+        app.update();
+        let ctx = app.world().resource::<FleetContext>();
+        let popup = ctx.autopilot_context.as_ref().unwrap();
+        let Some(Ok(plan)) = popup.preview else {
+            panic!("{:?}", popup.preview);
+        };
+        // Raising a low orbit up to the Moon, at 1 mm/s²
+        assert!((6. ..8.).contains(&plan.delta_v), "{}", plan.delta_v);
+        assert!(popup.lines()[1].to_string().contains("days"));
+
+        let kind = popup.kind().unwrap();
+        app.world_mut()
+            .send_event(FleetScreenEvent::EngageAutopilot(kind.clone()));
+        app.update();
+        let world = app.world_mut();
+        let ship = world.resource::<ShipsMapping>().0["s"];
+        assert_eq!(world.get::<Autopilot>(ship).unwrap().kind, kind);
+        assert!(world.resource::<FleetContext>().autopilot_context.is_none());
+
+        // The spiral is planned at the next tick
+        world
+            .resource_mut::<NextState<GameStage>>()
+            .set(GameStage::Action);
+        while app.world().resource::<GameTime>().simtick < SIMTICKS_PER_TICK {
+            app.update();
+        }
+        let world = app.world();
+        assert!(matches!(
+            world.get::<Autopilot>(ship).unwrap().phase,
+            AutopilotPhase::Spiral { .. }
+        ));
+        assert!(world.get::<CurrentTrajectory>(ship).is_some());
+    }
+
+    #[test]
+    fn test_inclined_ship() {
+        let mut app = new_app();
+        let popup = CreateShipContext {
+            id_text: "polar".into(),
             host_body: "terre".into(),
             altitude: "1e4".into(),
+            inclination: "200".into(),
             ..Default::default()
         };
+        app.world_mut().resource_mut::<FleetContext>().popup_context = Some(popup.clone());
+        app.update();
+        let ctx = app.world().resource::<FleetContext>();
+        let preview = &ctx.popup_context.as_ref().unwrap().preview;
+        assert!(preview.lines()[0].to_string().contains("inclination"));
+
+        app.world_mut()
+            .send_event(FleetScreenEvent::TryNewShip(popup.clone()));
+        app.update();
+        assert!(matches!(
+            app.world().resource::<FleetContext>().creation_error,
+            Some(ShipCreationError::InvalidOrbit(
+                OrbitSpawnError::InvalidPlane(_)
+            ))
+        ));
+
         app.world_mut()
-            .send_event(FleetScreenEvent::TryNewShip(popup));
+            .send_event(FleetScreenEvent::TryNewShip(CreateShipContext {
+                inclination: "90".into(),
+                raan: "45".into(),
+                ..popup
+            }));
         app.update();
         app.update();
-        assert_eq!(app.world().resource::<ShipsMapping>().0.len(), 1)
+        let world = app.world_mut();
+        let earth = world.resource::<BodiesMapping>().0["terre"];
+        let ship = world.resource::<ShipsMapping>().0["polar"];
+        let state = |world: &World, e| {
+            (
+                world.get::<Position>(e).unwrap().0,
+                world.get::<Velocity>(e).unwrap().0,
+            )
+        };
+        let ((p, v), (earth_p, earth_v)) = (state(world, ship), state(world, earth));
+        let momentum = (p - earth_p).cross(v - earth_v).normalize();
+        assert!(momentum.z.abs() < 1e-6, "{momentum}");
+    }
+
+    #[test]
+    fn test_generated_ids() {
+        let mut app = new_app();
+        let popup = CreateShipContext {
+            selected: 0,
+            host_body: "terre".into(),
+            altitude: "1e4".into(),
+            ..Default::default()
+        };
+        for _ in 0..100 {
+            app.world_mut()
+                .send_event(FleetScreenEvent::TryNewShip(popup.clone()));
+        }
+        app.update();
+        app.update();
+        assert_eq!(app.world().resource::<ShipsMapping>().0.len(), 100);
+        assert!(app
+            .world()
+            .resource::<ShipsMapping>()
+            .0
+            .contains_key("probe-100"));
+    }
+
+    #[test]
+    fn test_rejected_ship() {
+        let mut app = new_app();
+        app.world_mut().send_event(ShipEvent::Create(ShipInfo {
+            id: id_from("s"),
+            ..default()
+        }));
+        app.update();
+        app.update();
+        let ship = app.world().resource::<ShipsMapping>().0["s"];
+        app.world_mut().send_event(ShipEvent::Rejected(
+            id_from("s"),
+            ShipRejectionReason::AlreadyExists,
+        ));
+        app.update();
+        app.update();
+        assert!(app.world().resource::<ShipsMapping>().0.is_empty());
+        assert!(app.world().get_entity(ship).is_none());
+        let ctx = app.world().resource::<FleetContext>();
+        assert!(ctx.ships.is_empty());
+        assert!(matches!(
+            ctx.creation_error,
+            Some(ShipCreationError::Rejected(
+                _,
+                ShipRejectionReason::AlreadyExists
+            ))
+        ));
     }
 
     #[test]
@@ -445,4 +1398,42 @@ mod tests {
         assert_eq!(ctx.ships.len(), 1);
         assert_eq!(ctx.stage, GameStage::Action);
     }
+
+    #[derive(Component, Reflect, Default)]
+    #[reflect(Component)]
+    struct TestCargo(u32);
+
+    #[test]
+    fn test_ship_systems() {
+        let mut app = new_app();
+        app.register_type::<TestCargo>();
+        app.world_mut().send_event(ShipEvent::Create(ShipInfo {
+            id: id_from("s"),
+            ..default()
+        }));
+        app.update();
+        app.update();
+        let ship = app.world().resource::<ShipsMapping>().0["s"];
+        app.world_mut().entity_mut(ship).insert(TestCargo(12));
+        app.world_mut()
+            .send_event(FleetScreenEvent::Select(Direction2::Down));
+        app.world_mut().send_event(FleetScreenEvent::SwitchInfoTab);
+        app.update();
+        let ctx = app.world().resource::<FleetContext>();
+        assert_eq!(ctx.info_tab, InfoTab::Systems);
+        let names: Vec<_> = ctx.systems.iter().map(|(name, _)| name.as_str()).collect();
+        for name in [
+            "Position",
+            "Velocity",
+            "Acceleration",
+            "Influenced",
+            "TestCargo",
+        ] {
+            assert!(names.contains(&name), "{name} not in {names:?}");
+        }
+        assert!(ctx
+            .systems
+            .iter()
+            .any(|(name, value)| name == "TestCargo" && value.contains("12")));
+    }
 }
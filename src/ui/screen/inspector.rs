@@ -0,0 +1,305 @@
+//! Display of the reflected components of any entity, to debug the state of ships and bodies
+//! without a dedicated server command for each type of data.
+//!
+//! Only the components registered in the [AppTypeRegistry] with [ReflectComponent] are shown
+
+use bevy::prelude::*;
+use bevy_ratatui::event::KeyEvent;
+use crossterm::event::{KeyCode, KeyEventKind};
+use ratatui::{
+    layout::{Constraint, Layout},
+    style::Stylize,
+    text::Line,
+    widgets::{Block, Paragraph, StatefulWidget, Widget, Wrap},
+};
+
+use crate::{prelude::*, ui::UiUpdate};
+
+use super::PreviousScreen;
+
+pub fn plugin(app: &mut App) {
+    app.add_computed_state::<InInspector>()
+        .add_systems(
+            Update,
+            read_input
+                .in_set(InputReading)
+                .run_if(in_state(InInspector))
+                .run_if(resource_exists::<InspectorContext>),
+        )
+        .add_systems(
+            PostUpdate,
+            update_components
+                .run_if(in_state(InInspector))
+                .run_if(resource_exists::<InspectorContext>)
+                .in_set(UiUpdate),
+        )
+        .add_systems(OnEnter(InInspector), create_screen)
+        .add_systems(OnExit(InInspector), clear_screen);
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct InInspector;
+
+impl ComputedStates for InInspector {
+    type SourceStates = AppScreen;
+
+    fn compute(sources: Self::SourceStates) -> Option<Self> {
+        match sources {
+            AppScreen::Inspector(_) => Some(Self),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Resource, Debug, Clone)]
+pub struct InspectorContext {
+    pub entity: Entity,
+    /// Index of the first displayed component, among the ones matching the search
+    pub scroll: usize,
+    pub search: String,
+    /// Whether the typed characters go to the search bar
+    pub searching: bool,
+    /// Short type name and debug representation of the reflected components, sorted by name.
+    /// None if the entity doesn't exist anymore
+    components: Option<Vec<(String, String)>>,
+}
+
+impl InspectorContext {
+    pub fn new(entity: Entity) -> Self {
+        Self {
+            entity,
+            scroll: 0,
+            search: String::new(),
+            searching: false,
+            components: Some(Vec::new()),
+        }
+    }
+
+    /// Components whose type name contains the search, ignoring the case
+    pub fn filtered(&self) -> impl Iterator<Item = &(String, String)> {
+        let search = self.search.to_lowercase();
+        self.components
+            .iter()
+            .flatten()
+            .filter(move |(name, _)| name.to_lowercase().contains(&search))
+    }
+
+    pub fn scroll(&mut self, direction: Direction2) {
+        self.scroll = match direction {
+            Direction2::Up => self.scroll.saturating_sub(1),
+            Direction2::Down => self.scroll + 1,
+        };
+        self.clamp_scroll();
+    }
+
+    fn clamp_scroll(&mut self) {
+        self.scroll = self.scroll.min(self.filtered().count().saturating_sub(1));
+    }
+}
+
+/// Short type names of the reflected components of an entity, with their values
+pub fn reflected_components(world: &World, entity: Entity) -> Option<Vec<(String, &dyn Reflect)>> {
+    let entity_ref = world.get_entity(entity)?;
+    let registry = world.resource::<AppTypeRegistry>().read();
+    Some(
+        world
+            .inspect_entity(entity)
+            .into_iter()
+            .filter_map(|info| {
+                let registration = registry.get(info.type_id()?)?;
+                let reflected = registration
+                    .data::<ReflectComponent>()?
+                    .reflect(entity_ref)?;
+                Some((
+                    registration
+                        .type_info()
+                        .type_path_table()
+                        .short_path()
+                        .to_owned(),
+                    reflected,
+                ))
+            })
+            .collect(),
+    )
+}
+
+fn create_screen(mut commands: Commands, screen: Res<State<AppScreen>>) {
+    if let AppScreen::Inspector(entity) = screen.get() {
+        commands.insert_resource(InspectorContext::new(*entity));
+    }
+}
+
+fn clear_screen(mut commands: Commands) {
+    commands.remove_resource::<InspectorContext>();
+}
+
+/// Reads the components at each frame, so that the values shown are the current ones
+fn update_components(world: &mut World) {
+    world.resource_scope(|world, mut ctx: Mut<InspectorContext>| {
+        ctx.components = reflected_components(world, ctx.entity).map(|components| {
+            let mut components: Vec<_> = components
+                .into_iter()
+                .map(|(name, value)| (name, format!("{value:#?}")))
+                .collect();
+            components.sort();
+            components
+        });
+        ctx.clamp_scroll();
+    });
+}
+
+fn read_input(
+    mut context: ResMut<InspectorContext>,
+    mut key_event: EventReader<KeyEvent>,
+    keymap: Res<Keymap>,
+    previous_screen: Res<PreviousScreen>,
+    mut next_screen: ResMut<NextState<AppScreen>>,
+) {
+    use Direction2::*;
+    let keymap = &keymap.inspector;
+    for KeyEvent(event) in key_event.read() {
+        if event.kind == KeyEventKind::Release {
+            return;
+        }
+        match event {
+            e if keymap.scroll_down.matches(e) => context.scroll(Down),
+            e if keymap.scroll_up.matches(e) => context.scroll(Up),
+            e if context.searching => match e {
+                e if keymap.leave_search.matches(e) => context.searching = false,
+                e if keymap.delete_char.matches(e) => {
+                    context.search.pop();
+                    context.clamp_scroll();
+                }
+                crossterm::event::KeyEvent {
+                    code: KeyCode::Char(c),
+                    ..
+                } => {
+                    context.search.push(*c);
+                    context.clamp_scroll();
+                }
+                _ => {}
+            },
+            e if keymap.enter_search.matches(e) => context.searching = true,
+            e if keymap.back.matches(e) => next_screen.set(match previous_screen.0 {
+                AppScreen::Inspector(_) => AppScreen::Fleet,
+                screen => screen,
+            }),
+            _ => {}
+        }
+    }
+}
+
+pub struct InspectorScreen;
+
+impl StatefulWidget for InspectorScreen {
+    type State = InspectorContext;
+
+    fn render(
+        self,
+        area: ratatui::prelude::Rect,
+        buf: &mut ratatui::prelude::Buffer,
+        state: &mut Self::State,
+    ) {
+        let chunks = Layout::vertical([Constraint::Length(3), Constraint::Fill(1)]).split(area);
+
+        let mut search = Block::bordered().title_top("Search component");
+        if state.searching {
+            search = search.yellow();
+        }
+        Paragraph::new(state.search.as_str())
+            .block(search)
+            .render(chunks[0], buf);
+
+        let block = Block::bordered().title_top(format!("Entity {}", state.entity));
+        let lines: Vec<_> = match &state.components {
+            None => vec![Line::from("The entity doesn't exist anymore".red())],
+            Some(_) => state
+                .filtered()
+                .skip(state.scroll)
+                .flat_map(|(name, value)| {
+                    std::iter::once(Line::from(name.clone().bold()))
+                        .chain(value.lines().map(|l| Line::from(l.to_owned())))
+                })
+                .collect(),
+        };
+        let count = state.filtered().count();
+        Paragraph::new(lines)
+            .block(block.title_bottom(format!(
+                "{}/{count} components",
+                (state.scroll + 1).min(count)
+            )))
+            .wrap(Wrap { trim: false })
+            .render(chunks[1], buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{math::DVec3, prelude::*};
+    use bevy_ratatui::event::KeyEvent;
+    use crossterm::event::{KeyCode, KeyEvent as CrosstermKeyEvent, KeyModifiers};
+
+    use crate::prelude::*;
+
+    use super::InspectorContext;
+
+    fn key(app: &mut App, code: KeyCode) {
+        app.world_mut()
+            .send_event(KeyEvent(CrosstermKeyEvent::new(code, KeyModifiers::NONE)));
+        app.update();
+    }
+
+    #[test]
+    fn test_inspector() {
+        let mut app = App::new();
+        app.add_plugins((
+            ClientPlugin::testing().in_mode(ClientMode::Singleplayer),
+            TuiPlugin::testing(),
+        ));
+        app.update();
+        app.world_mut().send_event(ShipEvent::Create(ShipInfo {
+            id: id_from("s"),
+            spawn_pos: DVec3::new(1e9, 0., 0.),
+            ..default()
+        }));
+        app.update();
+        let ship = app.world().resource::<ShipsMapping>().0["s"];
+        app.world_mut()
+            .resource_mut::<NextState<AppScreen>>()
+            .set(AppScreen::Inspector(ship));
+        app.update();
+        app.update();
+
+        let ctx = app.world().resource::<InspectorContext>();
+        let names: Vec<_> = ctx.filtered().map(|(name, _)| name.as_str()).collect();
+        assert!(names.contains(&"Position"), "{names:?}");
+        assert!(names.contains(&"ShipInfo"), "{names:?}");
+
+        // The search keeps the components whose name contains it
+        key(&mut app, KeyCode::Char('/'));
+        for c in "velo".chars() {
+            key(&mut app, KeyCode::Char(c));
+        }
+        key(&mut app, KeyCode::Enter);
+        let ctx = app.world().resource::<InspectorContext>();
+        assert!(!ctx.searching);
+        assert!(ctx.filtered().all(|(name, _)| name.contains("Velocity")));
+        let (_, velocity) = ctx.filtered().next().unwrap();
+        assert!(velocity.contains("Velocity"), "{velocity}");
+
+        // Scrolling stops at the last component
+        for _ in 0..10 {
+            key(&mut app, KeyCode::Down);
+        }
+        let ctx = app.world().resource::<InspectorContext>();
+        assert_eq!(ctx.scroll, ctx.filtered().count() - 1);
+
+        key(&mut app, KeyCode::Esc);
+        app.update();
+        assert_eq!(
+            app.world().resource::<State<AppScreen>>().get(),
+            &AppScreen::Fleet
+        );
+        assert!(!app.world().contains_resource::<InspectorContext>());
+    }
+}
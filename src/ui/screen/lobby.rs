@@ -0,0 +1,89 @@
+//! Screen shown in multiplayer until the system of the server is loaded, with the server joined
+//! and the state of the connection
+
+use bevy::prelude::*;
+use bevy_ratatui::event::KeyEvent;
+use crossterm::event::KeyEventKind;
+use ratatui::{
+    buffer::Buffer,
+    layout::{Constraint, Layout, Rect},
+    text::Line,
+    widgets::{Block, Paragraph, Widget},
+};
+
+use crate::{
+    client::{ClientRole, PlayerName, ServerNetworkInfo, SyncStatus},
+    game::loading::LoadingProgress,
+    prelude::*,
+};
+
+use super::AppScreen;
+
+/// Width of the box of the lobby, in the middle of the screen
+const LOBBY_WIDTH: u16 = 50;
+
+pub fn plugin(app: &mut App) {
+    app.add_systems(OnEnter(ClientMode::Multiplayer), open_lobby)
+        .add_systems(
+            Update,
+            read_input
+                .in_set(InputReading)
+                .run_if(in_state(AppScreen::Lobby))
+                .run_if(not(resource_exists::<LoadingProgress>)),
+        );
+}
+
+fn open_lobby(mut next_screen: ResMut<NextState<AppScreen>>) {
+    next_screen.set(AppScreen::Lobby);
+}
+
+/// Leaving the lobby closes the connection and goes back to the start menu
+fn read_input(
+    mut key_event: EventReader<KeyEvent>,
+    keymap: Res<Keymap>,
+    mut next_mode: ResMut<NextState<ClientMode>>,
+) {
+    if key_event.read().any(|KeyEvent(event)| {
+        event.kind != KeyEventKind::Release && keymap.start_menu.back.matches(event)
+    }) {
+        next_mode.set(ClientMode::None);
+    }
+}
+
+pub struct LobbyScreen<'a> {
+    pub server: &'a ServerNetworkInfo,
+    pub player: &'a PlayerName,
+    pub status: &'a SyncStatus,
+    pub role: &'a ClientRole,
+}
+
+impl Widget for LobbyScreen<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let [_, center, _] = Layout::vertical([
+            Constraint::Fill(1),
+            Constraint::Length(5),
+            Constraint::Fill(1),
+        ])
+        .areas(area);
+        let [_, center, _] = Layout::horizontal([
+            Constraint::Fill(1),
+            Constraint::Length(LOBBY_WIDTH),
+            Constraint::Fill(1),
+        ])
+        .areas(center);
+        let [lines, help] =
+            Layout::vertical([Constraint::Length(4), Constraint::Length(1)]).areas(center);
+        let ServerNetworkInfo(host, port) = self.server;
+        let status = match self.status {
+            SyncStatus::NotSynced => "Connecting...".to_owned(),
+            SyncStatus::Synced => format!("Joined as {}", self.role.0),
+        };
+        Paragraph::new(vec![
+            Line::from(self.player.0.as_str()).centered(),
+            Line::from(status).centered(),
+        ])
+        .block(Block::bordered().title(format!(" Lobby of {host}:{port} ")))
+        .render(lines, buf);
+        Line::from("esc to leave").centered().render(help, buf);
+    }
+}
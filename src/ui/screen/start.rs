@@ -1,22 +1,53 @@
+use std::{
+    error::Error,
+    io,
+    net::{AddrParseError, IpAddr},
+    num::ParseIntError,
+};
+
 use bevy::{prelude::*, ui::widget};
 use bevy_ratatui::event::KeyEvent;
-use crossterm::event::KeyEventKind;
+use crossterm::event::{KeyCode, KeyEventKind};
 use ratatui::{
     layout::{Constraint, Flex, Layout},
+    style::Stylize,
     text::Line,
     widgets::{List, ListState, Paragraph, StatefulWidget, Widget},
 };
+use serde::{Deserialize, Serialize};
 
-use crate::prelude::*;
+use crate::{
+    client::{DisconnectReason, ServerNetworkInfo},
+    game::{loading::LoadingProgress, GameFiles},
+    prelude::*,
+    utils::{
+        list::OptionsList,
+        persist::{read_versioned, write_versioned, Encoding, PersistError, Versioned},
+    },
+};
 
 use super::AppScreen;
 
-const SCREENS: [(ClientMode, &str); 3] = [
-    (ClientMode::Singleplayer, "Singleplayer"),
-    (ClientMode::Multiplayer, "Multiplayer"),
-    (ClientMode::Explorer, "Explore"),
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum StartMenuOption {
+    Singleplayer,
+    Explorer,
+    Multiplayer,
+    LastServer,
+    Quit,
+}
+
+const OPTIONS: [(StartMenuOption, &str); 5] = [
+    (StartMenuOption::Singleplayer, "Singleplayer"),
+    (StartMenuOption::Explorer, "Explore"),
+    (StartMenuOption::Multiplayer, "Multiplayer"),
+    (StartMenuOption::LastServer, "Connect to last server"),
+    (StartMenuOption::Quit, "Quit"),
 ];
 
+/// Name of the file of the last server the client connected to, in the game files
+pub const LAST_SERVER_FILE: &str = "last_server.json";
+
 pub struct StartMenuPlugin;
 
 #[derive(Event)]
@@ -29,6 +60,88 @@ pub enum StartMenuEvent {
 #[derive(Resource)]
 pub struct StartMenuContext {
     list_state: ListState,
+    /// Address of the server to join in multiplayer
+    address: ServerAddressContext,
+    /// Address of the last server the client connected to, kept in the game files
+    last_server: Option<(IpAddr, u16)>,
+    address_error: Option<AddressError>,
+    /// Why the server closed the connection of the last multiplayer game, if it did
+    disconnect_reason: Option<String>,
+}
+
+/// Address of the last server the client connected to, as saved in the game files
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct LastServer {
+    pub host: IpAddr,
+    pub port: u16,
+}
+
+impl Versioned for LastServer {
+    const FORMAT: &'static str = "solar4x-last-server";
+    const VERSION: u32 = 1;
+    const ENCODING: Encoding = Encoding::Json;
+}
+
+#[derive(Default, Clone)]
+pub struct ServerAddressContext {
+    host: String,
+    port: String,
+    selected: usize,
+}
+
+impl OptionsList<2> for ServerAddressContext {
+    fn current_index(&mut self) -> &mut usize {
+        &mut self.selected
+    }
+
+    fn fields_list(&mut self) -> [(&mut String, String); 2] {
+        [
+            (&mut self.host, "Host".into()),
+            (&mut self.port, "Port".into()),
+        ]
+    }
+}
+
+impl ServerAddressContext {
+    fn to_address(&self) -> Result<(IpAddr, u16), AddressError> {
+        Ok((self.host.trim().parse()?, self.port.trim().parse()?))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum AddressError {
+    Host(AddrParseError),
+    Port(ParseIntError),
+}
+
+impl From<AddrParseError> for AddressError {
+    fn from(value: AddrParseError) -> Self {
+        Self::Host(value)
+    }
+}
+
+impl From<ParseIntError> for AddressError {
+    fn from(value: ParseIntError) -> Self {
+        Self::Port(value)
+    }
+}
+
+impl Error for AddressError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Host(e) => Some(e),
+            Self::Port(e) => Some(e),
+        }
+    }
+}
+
+impl std::fmt::Display for AddressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Host(e) => write!(f, "Invalid host: {}", e),
+            Self::Port(e) => write!(f, "Invalid port: {}", e),
+        }
+    }
 }
 
 pub struct StartMenu;
@@ -41,16 +154,61 @@ pub fn plugin(app: &mut App) {
                 read_input.in_set(InputReading),
                 handle_events.in_set(EventHandling),
             )
-                .run_if(in_state(AppScreen::StartMenu)),
+                .run_if(in_state(AppScreen::StartMenu))
+                .run_if(not(resource_exists::<LoadingProgress>)),
         )
-        .add_systems(OnEnter(ClientMode::None), create_screen);
+        .add_systems(OnEnter(ClientMode::None), create_screen)
+        .add_systems(OnEnter(ClientMode::Multiplayer), remember_server);
 }
 
-fn create_screen(mut next_screen: ResMut<NextState<AppScreen>>) {
+fn create_screen(
+    mut commands: Commands,
+    mut next_screen: ResMut<NextState<AppScreen>>,
+    mut context: ResMut<StartMenuContext>,
+    server_info: Res<ServerNetworkInfo>,
+    reason: Option<Res<DisconnectReason>>,
+    files: Res<GameFiles>,
+) {
     next_screen.set(AppScreen::StartMenu);
+    if let Some(reason) = reason {
+        context.disconnect_reason = Some(reason.0.clone());
+        commands.remove_resource::<DisconnectReason>();
+    }
+    if context.last_server.is_none() {
+        context.last_server = match read_versioned(files.root.join(LAST_SERVER_FILE)) {
+            Ok(LastServer { host, port }) => Some((host, port)),
+            Err(PersistError::Io(e)) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => {
+                warn!("could not read the last server: {e}");
+                None
+            }
+        };
+    }
+    if context.address.host.is_empty() && context.address.port.is_empty() {
+        let ServerNetworkInfo(host, port) = *server_info;
+        let (host, port) = context.last_server.unwrap_or((host, port));
+        context.address.host = host.to_string();
+        context.address.port = port.to_string();
+    }
+}
+
+fn remember_server(
+    mut context: ResMut<StartMenuContext>,
+    server_info: Res<ServerNetworkInfo>,
+    files: Res<GameFiles>,
+) {
+    let ServerNetworkInfo(host, port) = *server_info;
+    context.last_server = Some((host, port));
+    if let Err(e) = write_versioned(
+        files.root.join(LAST_SERVER_FILE),
+        &LastServer { host, port },
+    ) {
+        warn!("could not save the last server: {e}");
+    }
 }
 
 fn read_input(
+    mut context: ResMut<StartMenuContext>,
     mut key_event: EventReader<KeyEvent>,
     keymap: Res<Keymap>,
     mut internal_event: EventWriter<StartMenuEvent>,
@@ -63,22 +221,35 @@ fn read_input(
         use StartMenuEvent::*;
 
         let keymap = &keymap.start_menu;
+        let editing_address = context.selected_option() == StartMenuOption::Multiplayer;
         internal_event.send(match event {
             e if keymap.select_next.matches(e) => Select(Down),
             e if keymap.select_previous.matches(e) => Select(Up),
             e if keymap.quit.matches(e) => Quit,
             e if keymap.validate.matches(e) => Validate,
+            e if editing_address && keymap.cycle_options.matches(e) => {
+                context.address.select_next();
+                continue;
+            }
+            e if editing_address && keymap.delete_char.matches(e) => {
+                context.address.selected_field().pop();
+                continue;
+            }
+            crossterm::event::KeyEvent {
+                code: KeyCode::Char(c),
+                ..
+            } if editing_address => {
+                context.address.selected_field().push(*c);
+                continue;
+            }
             _ => return,
         });
     }
 }
 
 impl StartMenuContext {
-    fn get_next_mode(&self) -> ClientMode {
-        match self.list_state.selected().unwrap() {
-            i if i < SCREENS.len() => SCREENS[i].0,
-            _ => unreachable!(),
-        }
+    fn selected_option(&self) -> StartMenuOption {
+        OPTIONS[self.list_state.selected().unwrap_or(0)].0
     }
 }
 
@@ -86,6 +257,10 @@ impl Default for StartMenuContext {
     fn default() -> Self {
         Self {
             list_state: ListState::default().with_selected(Some(0)),
+            address: ServerAddressContext::default(),
+            last_server: None,
+            address_error: None,
+            disconnect_reason: None,
         }
     }
 }
@@ -96,7 +271,7 @@ impl ClampedList for StartMenuContext {
     }
 
     fn len(&self) -> usize {
-        SCREENS.len()
+        OPTIONS.len()
     }
 }
 
@@ -105,14 +280,36 @@ pub fn handle_events(
     mut context: ResMut<StartMenuContext>,
     mut events: EventReader<StartMenuEvent>,
     mut quit: EventWriter<AppExit>,
+    mut server_info: ResMut<ServerNetworkInfo>,
 ) {
     for event in events.read() {
+        context.disconnect_reason = None;
         match event {
             StartMenuEvent::Quit => {
                 quit.send_default();
             }
             StartMenuEvent::Select(d) => context.select_adjacent(*d),
-            StartMenuEvent::Validate => next_mode.set(context.get_next_mode()),
+            StartMenuEvent::Validate => match context.selected_option() {
+                StartMenuOption::Singleplayer => next_mode.set(ClientMode::Singleplayer),
+                StartMenuOption::Explorer => next_mode.set(ClientMode::Explorer),
+                StartMenuOption::Multiplayer => match context.address.to_address() {
+                    Ok((host, port)) => {
+                        *server_info = ServerNetworkInfo(host, port);
+                        context.address_error = None;
+                        next_mode.set(ClientMode::Multiplayer);
+                    }
+                    Err(e) => context.address_error = Some(e),
+                },
+                StartMenuOption::LastServer => {
+                    if let Some((host, port)) = context.last_server {
+                        *server_info = ServerNetworkInfo(host, port);
+                        next_mode.set(ClientMode::Multiplayer);
+                    }
+                }
+                StartMenuOption::Quit => {
+                    quit.send_default();
+                }
+            },
         }
     }
 }
@@ -137,12 +334,29 @@ impl StatefulWidget for StartMenu {
         let chunks = Layout::vertical([
             Constraint::Length(title_height as u16),
             Constraint::Max(3),
-            Constraint::Length(SCREENS.len() as u16),
+            Constraint::Length(OPTIONS.len() as u16),
+            Constraint::Length(1),
+            // Server address fields
+            Constraint::Length(3),
+            Constraint::Length(1),
         ])
         .flex(Flex::Center)
         .split(area);
         Paragraph::new(title).centered().render(chunks[0], buf);
-        let (_, entries): (Vec<_>, Vec<&str>) = SCREENS.into_iter().unzip();
+        if let Some(reason) = &state.disconnect_reason {
+            Paragraph::new(format!("Disconnected by the server: {reason}").red())
+                .centered()
+                .render(chunks[1], buf);
+        }
+        let entries: Vec<String> = OPTIONS
+            .into_iter()
+            .map(|(option, name)| match (option, state.last_server) {
+                (StartMenuOption::LastServer, Some((host, port))) => {
+                    format!("{name} ({host}:{port})")
+                }
+                _ => name.to_owned(),
+            })
+            .collect();
         let list_width = entries.iter().map(|s| s.len()).max().unwrap();
         let entries = entries.into_iter().map(|s| Line::from(s).centered());
         let list = List::new(entries).highlight_symbol(">");
@@ -150,5 +364,191 @@ impl StatefulWidget for StartMenu {
             .flex(Flex::Center)
             .areas(chunks[2]);
         StatefulWidget::render(list, list_area, buf, &mut state.list_state);
+
+        if state.selected_option() == StartMenuOption::Multiplayer {
+            let [host, port] = Layout::horizontal([Constraint::Length(42), Constraint::Length(10)])
+                .flex(Flex::Center)
+                .areas(chunks[4]);
+            state.address.paragraph(0).render(host, buf);
+            state.address.paragraph(1).render(port, buf);
+            if let Some(error) = &state.address_error {
+                Paragraph::new(error.to_string().red())
+                    .centered()
+                    .render(chunks[5], buf);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        slice,
+    };
+
+    use bevy::{prelude::*, time::TimeUpdateStrategy};
+
+    use crate::{
+        client::ServerNetworkInfo,
+        game::GameFiles,
+        network::{
+            testing::{spawn_server, update_linked, LINKED_UPDATE_STEP},
+            transport::{LoopbackServer, TransportKind},
+        },
+        prelude::*,
+        utils::persist::{read_versioned, write_versioned},
+    };
+
+    use super::{
+        AddressError, LastServer, ServerAddressContext, StartMenuContext, StartMenuEvent,
+        LAST_SERVER_FILE,
+    };
+
+    fn new_app() -> App {
+        let mut app = App::new();
+        app.add_plugins((ClientPlugin::testing(), TuiPlugin::testing()));
+        app.update();
+        app
+    }
+
+    #[test]
+    fn test_server_address() {
+        let mut address = ServerAddressContext {
+            host: "10.0.0.2".into(),
+            port: "7000".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            address.to_address().unwrap(),
+            (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 7000)
+        );
+        address.port = "70000".into();
+        assert!(matches!(address.to_address(), Err(AddressError::Port(_))));
+        address.host = "localhost".into();
+        assert!(matches!(address.to_address(), Err(AddressError::Host(_))));
+    }
+
+    #[test]
+    fn test_start_menu() {
+        let mut app = new_app();
+        assert_eq!(
+            *app.world().resource::<State<AppScreen>>().get(),
+            AppScreen::StartMenu
+        );
+        let ctx = app.world().resource::<StartMenuContext>();
+        let ServerNetworkInfo(host, port) = *app.world().resource::<ServerNetworkInfo>();
+        // No server was joined from these game files
+        assert_eq!(ctx.last_server, None);
+        assert_eq!(ctx.address.host, host.to_string());
+        assert_eq!(ctx.address.port, port.to_string());
+
+        // An invalid address is reported and doesn't start the connection
+        app.world_mut()
+            .resource_mut::<StartMenuContext>()
+            .address
+            .port = "port".into();
+        for event in [
+            StartMenuEvent::Select(Direction2::Down),
+            StartMenuEvent::Select(Direction2::Down),
+            StartMenuEvent::Validate,
+        ] {
+            app.world_mut().send_event(event);
+        }
+        app.update();
+        app.update();
+        assert!(app
+            .world()
+            .resource::<StartMenuContext>()
+            .address_error
+            .is_some());
+        assert_eq!(
+            *app.world().resource::<State<ClientMode>>().get(),
+            ClientMode::None
+        );
+
+        app.world_mut()
+            .send_event(StartMenuEvent::Select(Direction2::Up));
+        app.world_mut().send_event(StartMenuEvent::Validate);
+        app.update();
+        app.update();
+        assert_eq!(
+            *app.world().resource::<State<ClientMode>>().get(),
+            ClientMode::Explorer
+        );
+    }
+
+    #[test]
+    fn test_last_server() {
+        let mut server = spawn_server();
+        let mut app = App::new();
+        app.add_plugins((
+            ClientPlugin::testing().with_transport(TransportKind::Loopback),
+            TuiPlugin::testing(),
+        ))
+        .insert_resource(
+            server
+                .world_mut()
+                .resource_mut::<LoopbackServer>()
+                .connect(),
+        )
+        .insert_resource(TimeUpdateStrategy::ManualDuration(LINKED_UPDATE_STEP));
+        app.update();
+        for event in [
+            StartMenuEvent::Select(Direction2::Down),
+            StartMenuEvent::Select(Direction2::Down),
+            StartMenuEvent::Validate,
+        ] {
+            app.world_mut().send_event(event);
+        }
+        // The system is loaded once the server sent its initial data
+        for _ in 0..3 {
+            app.update();
+            server.update();
+        }
+        assert_eq!(
+            *app.world().resource::<State<AppScreen>>().get(),
+            AppScreen::Lobby
+        );
+        let ServerNetworkInfo(host, port) = *app.world().resource::<ServerNetworkInfo>();
+        let path = app
+            .world()
+            .resource::<GameFiles>()
+            .root
+            .join(LAST_SERVER_FILE);
+        assert_eq!(
+            read_versioned::<LastServer>(&path).unwrap(),
+            LastServer { host, port }
+        );
+
+        // The lobby is left for the fleet once the system of the server is loaded
+        for _ in 0..20 {
+            update_linked(&mut server, slice::from_mut(&mut app), 1);
+            if *app.world().resource::<State<AppScreen>>().get() == AppScreen::Fleet {
+                break;
+            }
+        }
+        assert_eq!(
+            *app.world().resource::<State<AppScreen>>().get(),
+            AppScreen::Fleet
+        );
+
+        // The next launch offers to join it again
+        let mut app = App::new();
+        app.add_plugins((ClientPlugin::testing(), TuiPlugin::testing()));
+        let last = LastServer {
+            host: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            port: 7000,
+        };
+        let path = app
+            .world()
+            .resource::<GameFiles>()
+            .root
+            .join(LAST_SERVER_FILE);
+        write_versioned(&path, &last).unwrap();
+        app.update();
+        let ctx = app.world().resource::<StartMenuContext>();
+        assert_eq!(ctx.last_server, Some((last.host, last.port)));
+        assert_eq!(ctx.address.host, "10.0.0.2");
     }
 }
@@ -0,0 +1,242 @@
+//! Following a ship or a body on the map, which is the default view of spectators
+
+use std::fmt::Display;
+
+use bevy::prelude::*;
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::{Color, Stylize},
+    text::Line,
+    widgets::Widget,
+};
+
+use crate::{
+    client::ClientRole,
+    game::InGame,
+    network::Role,
+    prelude::*,
+    ui::{widget::space_map::SpaceMap, UiUpdate},
+};
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<SpectateTarget>()
+        .add_systems(
+            Update,
+            spectate_by_default
+                .run_if(in_state(InGame))
+                .run_if(resource_changed::<ClientRole>),
+        )
+        .add_systems(
+            PostUpdate,
+            (
+                resolve_spectate_target,
+                follow_target
+                    .run_if(in_state(AppScreen::Explorer))
+                    .run_if(resource_exists::<SpaceMap>)
+                    .run_if(resource_changed::<SpectateTarget>.or_else(resource_added::<SpaceMap>)),
+            )
+                .chain()
+                .before(UiUpdate)
+                .run_if(in_state(Loaded)),
+        );
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FollowTarget {
+    Ship(ShipID),
+    Body(BodyID),
+}
+
+impl Display for FollowTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ship(id) => write!(f, "ship {id}"),
+            Self::Body(id) => write!(f, "{id}"),
+        }
+    }
+}
+
+/// The object followed by the map
+#[derive(Resource, Default, Debug, Clone)]
+pub struct SpectateTarget {
+    pub target: Option<FollowTarget>,
+    /// Last body whose sphere of influence contained the followed ship, which is followed instead
+    /// if the ship is removed
+    last_host: Option<BodyID>,
+}
+
+impl SpectateTarget {
+    pub fn follow(&mut self, target: FollowTarget) {
+        self.target = Some(target);
+        self.last_host = None;
+    }
+
+    /// Follows the ship after the current one in the order of ids, or the first one if no ship is
+    /// followed
+    pub fn cycle_ships(&mut self, ships: impl IntoIterator<Item = ShipID>) {
+        let mut ids: Vec<_> = ships.into_iter().collect();
+        ids.sort();
+        let next = match self.target {
+            Some(FollowTarget::Ship(current)) => ids.iter().find(|&&id| id > current),
+            _ => None,
+        }
+        .or(ids.first());
+        if let Some(&id) = next {
+            self.follow(FollowTarget::Ship(id));
+        }
+    }
+
+    pub fn entity(&self, ships: &ShipsMapping, bodies: &BodiesMapping) -> Option<Entity> {
+        match self.target? {
+            FollowTarget::Ship(id) => ships.0.get(&id).copied(),
+            FollowTarget::Body(id) => bodies.0.get(&id).copied(),
+        }
+    }
+}
+
+/// Brings spectators to the map, following a ship
+fn spectate_by_default(
+    role: Res<ClientRole>,
+    mut next_screen: ResMut<NextState<AppScreen>>,
+    mut spectate: ResMut<SpectateTarget>,
+    ships: Option<Res<ShipsMapping>>,
+) {
+    if role.0 == Role::Spectator {
+        next_screen.set(AppScreen::Explorer);
+        if spectate.target.is_none() {
+            spectate.cycle_ships(ships.iter().flat_map(|s| s.0.keys().copied()));
+        }
+    }
+}
+
+/// Keeps track of the host body of the followed ship, and falls back to it once the ship is removed
+fn resolve_spectate_target(
+    mut spectate: ResMut<SpectateTarget>,
+    ships: Res<ShipsMapping>,
+    bodies: Res<BodiesMapping>,
+    influence: Query<&Influenced>,
+    infos: Query<&BodyInfo>,
+) {
+    match spectate.target {
+        Some(FollowTarget::Ship(id)) => match ships.0.get(&id) {
+            Some(&ship) => {
+                let host = influence
+                    .get(ship)
+                    .ok()
+                    .and_then(|i| i.main_influencer)
+                    .and_then(|e| infos.get(e).ok())
+                    .map(|info| info.0.id);
+                if host.is_some() && host != spectate.last_host {
+                    spectate.last_host = host;
+                }
+            }
+            None => {
+                spectate.target = spectate.last_host.map(FollowTarget::Body);
+            }
+        },
+        Some(FollowTarget::Body(id)) if !bodies.0.contains_key(&id) => spectate.target = None,
+        _ => {}
+    }
+}
+
+fn follow_target(
+    spectate: Res<SpectateTarget>,
+    mut space_map: ResMut<SpaceMap>,
+    ships: Res<ShipsMapping>,
+    bodies: Res<BodiesMapping>,
+) {
+    if let Some(entity) = spectate.entity(&ships, &bodies) {
+        if space_map.focus_body != Some(entity) {
+            space_map.focus(entity);
+        }
+    }
+}
+
+/// Area of the banner, centered at the top of `area`
+pub fn banner_area(area: Rect, target: &FollowTarget) -> Rect {
+    let width = (SpectateBanner(target).text().len() as u16).min(area.width);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y,
+        width,
+        height: 1.min(area.height),
+    }
+}
+
+pub struct SpectateBanner<'a>(pub &'a FollowTarget);
+
+impl SpectateBanner<'_> {
+    fn text(&self) -> String {
+        format!(" following {} ", self.0)
+    }
+}
+
+impl Widget for SpectateBanner<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        Line::from(self.text().black().bg(Color::Cyan)).render(area, buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use crate::{prelude::*, utils::algebra::circular_orbit_around_body};
+
+    use super::{FollowTarget, SpectateTarget};
+
+    #[test]
+    fn test_cycle_ships() {
+        let mut spectate = SpectateTarget::default();
+        let ships = ["b", "c", "a"].map(id_from);
+        spectate.cycle_ships(ships);
+        assert_eq!(spectate.target, Some(FollowTarget::Ship(id_from("a"))));
+        spectate.cycle_ships(ships);
+        assert_eq!(spectate.target, Some(FollowTarget::Ship(id_from("b"))));
+        spectate.follow(FollowTarget::Ship(id_from("c")));
+        spectate.cycle_ships(ships);
+        assert_eq!(spectate.target, Some(FollowTarget::Ship(id_from("a"))));
+    }
+
+    #[test]
+    fn test_removed_ship_fallback() {
+        let mut app = App::new();
+        app.add_plugins((
+            ClientPlugin::testing().in_mode(ClientMode::Singleplayer),
+            TuiPlugin::testing(),
+        ));
+        app.update();
+        let world = app.world_mut();
+        let earth = world.resource::<BodiesMapping>().0["terre"];
+        let (&Position(earth_pos), &Velocity(earth_vel), &Mass(earth_mass)) = world
+            .query::<(&Position, &Velocity, &Mass)>()
+            .get(world, earth)
+            .unwrap();
+        let (pos, vel) = circular_orbit_around_body(
+            1e4,
+            earth_mass,
+            earth_pos,
+            earth_vel,
+            &mut rand::thread_rng(),
+        );
+        world.send_event(ShipEvent::Create(ShipInfo {
+            id: id_from("s"),
+            spawn_pos: pos,
+            spawn_speed: vel,
+            spawn_orbit: None,
+        }));
+        world
+            .resource_mut::<SpectateTarget>()
+            .follow(FollowTarget::Ship(id_from("s")));
+        app.update();
+        app.update();
+
+        app.world_mut().send_event(ShipEvent::Remove(id_from("s")));
+        app.update();
+        assert_eq!(
+            app.world().resource::<SpectateTarget>().target,
+            Some(FollowTarget::Body(id_from("terre")))
+        );
+    }
+}
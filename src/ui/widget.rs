@@ -1,4 +1,7 @@
 pub mod tree;
 pub mod search;
 pub mod space_map;
-pub mod info;
\ No newline at end of file
+pub mod info;
+pub mod profiler;
+pub mod clock;
+pub mod loading;
\ No newline at end of file
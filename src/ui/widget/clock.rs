@@ -0,0 +1,88 @@
+//! Game time shown in a corner of the screen, with the limit on the speed of the simulation
+
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::Stylize,
+    text::Line,
+    widgets::{LineGauge, Widget},
+};
+
+use crate::physics::time::{GAMETIME_PER_SIMTICK, MAX_SIM_SPEED_STEP_LIMIT, SIMTICKS_PER_TICK};
+
+/// Width of the speed slider, next to the clock
+const SLIDER_WIDTH: u16 = 36;
+
+/// The game time at the given simtick
+pub struct GameClock(pub u64);
+
+impl GameClock {
+    fn text(&self) -> String {
+        format!(
+            " day {:.1} (tick {}) ",
+            self.0 as f64 * GAMETIME_PER_SIMTICK,
+            self.0 / SIMTICKS_PER_TICK
+        )
+    }
+}
+
+/// Area of the clock, in the bottom right corner of `area`
+pub fn clock_area(area: Rect, clock: &GameClock) -> Rect {
+    let width = (clock.text().len() as u16).min(area.width);
+    Rect {
+        x: area.right() - width,
+        y: area.bottom().saturating_sub(1).max(area.y),
+        width,
+        height: 1.min(area.height),
+    }
+}
+
+impl Widget for GameClock {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        Line::from(self.text().reversed()).render(area, buf);
+    }
+}
+
+/// The [MaxSimSpeed](crate::physics::time::MaxSimSpeed) as a slider going from 1 tick per second
+/// to no limit, with the measured speed
+pub struct SpeedSlider {
+    pub max: Option<u64>,
+    /// Ticks run over the last real second
+    pub current: f64,
+}
+
+impl SpeedSlider {
+    /// Position of the slider: the limits set step by step are evenly spaced
+    fn ratio(&self) -> f64 {
+        let steps = (MAX_SIM_SPEED_STEP_LIMIT as f64).log2() + 2.;
+        self.max.map_or(1., |max| {
+            (((max.max(1) as f64).log2() + 1.) / steps).min(1.)
+        })
+    }
+
+    fn label(&self) -> String {
+        match self.max {
+            Some(max) => format!("{:.0}/{max} ticks/s ", self.current),
+            None => format!("{:.0} ticks/s, no limit ", self.current),
+        }
+    }
+}
+
+/// Area of the slider, on the left of the clock in `clock_area`
+pub fn slider_area(clock_area: Rect) -> Rect {
+    let width = SLIDER_WIDTH.min(clock_area.x);
+    Rect {
+        x: clock_area.x - width,
+        width,
+        ..clock_area
+    }
+}
+
+impl Widget for SpeedSlider {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        LineGauge::default()
+            .ratio(self.ratio())
+            .label(self.label())
+            .render(area, buf);
+    }
+}
@@ -7,12 +7,17 @@ use crate::objects::prelude::BodyData;
 
 pub struct InfoWidget {
     pub body_info: BodyData,
+    /// Names of the bodies with a predicted conjunction, with its date (in days)
+    pub conjunctions: Vec<(String, f64)>,
+    /// Name of the body the body is merging with, their separation (in km) and the time left
+    /// before they merge (in days)
+    pub inspiral: Option<(String, f64, f64)>,
 }
 
 impl WidgetRef for InfoWidget {
     fn render_ref(&self, area: ratatui::layout::Rect, buf: &mut Buffer) {
         let body_info = &self.body_info;
-        let info = Paragraph::new(format!(
+        let mut text = format!(
             "Body type: {}\n\
             N of orbiting bodies: {}\n\
             Radius: {} km\n\
@@ -21,8 +26,16 @@ impl WidgetRef for InfoWidget {
             body_info.orbiting_bodies.len(),
             body_info.radius,
             body_info.revolution_period,
-        ))
-        .block(
+        );
+        for (name, date) in &self.conjunctions {
+            text.push_str(&format!("\nNext conjunction with {name}: day {date:.0}"));
+        }
+        if let Some((name, separation, days)) = &self.inspiral {
+            text.push_str(&format!(
+                "\nSeparation with {name}: {separation:.1} km, merging in {days:.3} days"
+            ));
+        }
+        let info = Paragraph::new(text).block(
             Block::default()
                 .title(&body_info.name[..])
                 .borders(Borders::ALL),
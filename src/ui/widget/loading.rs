@@ -0,0 +1,48 @@
+//! Screen shown while a system loads, with its current phase and the overall progress
+
+use ratatui::{
+    buffer::Buffer,
+    layout::{Constraint, Layout, Rect},
+    text::Line,
+    widgets::{Block, Gauge, Widget},
+};
+
+use crate::game::loading::LoadingProgress;
+
+/// Width of the progress bar, in the middle of the screen
+const BAR_WIDTH: u16 = 50;
+
+pub struct LoadingScreen<'a>(pub &'a LoadingProgress);
+
+impl Widget for LoadingScreen<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let [_, center, _] = Layout::vertical([
+            Constraint::Fill(1),
+            Constraint::Length(4),
+            Constraint::Fill(1),
+        ])
+        .areas(area);
+        let [_, center, _] = Layout::horizontal([
+            Constraint::Fill(1),
+            Constraint::Length(BAR_WIDTH),
+            Constraint::Fill(1),
+        ])
+        .areas(center);
+        let [bar, help] =
+            Layout::vertical([Constraint::Length(3), Constraint::Length(1)]).areas(center);
+        let progress = self.0;
+        let phase = if progress.total > 1 {
+            format!(
+                " {} ({}/{}) ",
+                progress.phase, progress.done, progress.total
+            )
+        } else {
+            format!(" {} ", progress.phase)
+        };
+        Gauge::default()
+            .block(Block::bordered().title(phase))
+            .ratio(progress.fraction())
+            .render(bar, buf);
+        Line::from("esc to cancel").centered().render(help, buf);
+    }
+}
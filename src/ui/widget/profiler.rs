@@ -0,0 +1,261 @@
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use ratatui::{
+    buffer::Buffer,
+    layout::{Constraint, Rect},
+    style::{Color, Stylize},
+    widgets::{Block, Borders, Clear, Row, Table, Widget},
+};
+
+use crate::{
+    physics::PhysicsUpdate,
+    prelude::*,
+    ui::{RenderSet, UiUpdate},
+};
+
+/// Weight of the most recent sample in the average physics duration
+const SMOOTHING: f64 = 0.1;
+const OVERLAY_WIDTH: u16 = 30;
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<ProfilerOverlay>()
+        .init_resource::<PhysicsTimings>()
+        .init_resource::<ProfilerReport>()
+        .init_resource::<ProfilerClocks>()
+        .add_systems(
+            FixedUpdate,
+            (
+                start_physics_clock.before(PhysicsUpdate),
+                stop_physics_clock.after(PhysicsUpdate),
+            )
+                .run_if(profiler_enabled),
+        )
+        .add_systems(
+            PostUpdate,
+            (
+                update_report.in_set(UiUpdate),
+                start_render_clock.after(UiUpdate).before(RenderSet),
+                stop_render_clock.after(RenderSet),
+            )
+                .run_if(profiler_enabled),
+        );
+}
+
+/// Whether the profiler table is drawn on top of the current screen
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq)]
+pub struct ProfilerOverlay {
+    pub enabled: bool,
+}
+
+/// Wall-clock duration of the fixed updates (in microseconds)
+#[derive(Resource, Default, Debug, Clone, Copy)]
+pub struct PhysicsTimings {
+    /// Duration of the most recent fixed update
+    pub last_us: u64,
+    /// Exponential moving average of the durations
+    pub avg_us: f64,
+}
+
+impl PhysicsTimings {
+    fn record(&mut self, duration: Duration) {
+        let us = duration.as_secs_f64() * 1e6;
+        self.last_us = duration.as_micros() as u64;
+        self.avg_us = if self.avg_us == 0. {
+            us
+        } else {
+            self.avg_us + SMOOTHING * (us - self.avg_us)
+        };
+    }
+}
+
+/// Everything displayed by the profiler overlay, gathered before rendering
+#[derive(Resource, Default, Debug, Clone)]
+pub struct ProfilerReport {
+    pub physics: PhysicsTimings,
+    /// Duration of the previous TUI render (in microseconds)
+    pub render_us: u64,
+    pub ships: usize,
+    pub bodies: usize,
+    /// Bytes currently allocated on the heap, only tracked with the `profiling` feature
+    pub heap_bytes: Option<usize>,
+}
+
+#[derive(Resource, Default)]
+struct ProfilerClocks {
+    physics: Option<Instant>,
+    render: Option<Instant>,
+    render_us: u64,
+}
+
+fn profiler_enabled(overlay: Res<ProfilerOverlay>) -> bool {
+    overlay.enabled
+}
+
+fn start_physics_clock(mut clocks: ResMut<ProfilerClocks>) {
+    clocks.physics = Some(Instant::now());
+}
+
+fn stop_physics_clock(mut clocks: ResMut<ProfilerClocks>, mut timings: ResMut<PhysicsTimings>) {
+    if let Some(start) = clocks.physics.take() {
+        timings.record(start.elapsed());
+    }
+}
+
+fn start_render_clock(mut clocks: ResMut<ProfilerClocks>) {
+    clocks.render = Some(Instant::now());
+}
+
+fn stop_render_clock(mut clocks: ResMut<ProfilerClocks>) {
+    if let Some(start) = clocks.render.take() {
+        clocks.render_us = start.elapsed().as_micros() as u64;
+    }
+}
+
+fn update_report(
+    mut report: ResMut<ProfilerReport>,
+    timings: Res<PhysicsTimings>,
+    clocks: Res<ProfilerClocks>,
+    ships: Query<(), With<ShipInfo>>,
+    bodies: Query<(), With<BodyInfo>>,
+) {
+    *report = ProfilerReport {
+        physics: *timings,
+        render_us: clocks.render_us,
+        ships: ships.iter().count(),
+        bodies: bodies.iter().count(),
+        heap_bytes: heap_usage(),
+    };
+}
+
+/// Area of the overlay, in the top-right corner of `area`
+pub fn overlay_area(area: Rect) -> Rect {
+    let width = OVERLAY_WIDTH.min(area.width);
+    Rect {
+        x: area.right() - width,
+        y: area.y,
+        width,
+        height: 7.min(area.height),
+    }
+}
+
+pub struct ProfilerTable<'a>(pub &'a ProfilerReport);
+
+impl Widget for ProfilerTable<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let report = self.0;
+        let heap = report
+            .heap_bytes
+            .map_or("n/a".into(), |b| format!("{:.1} MiB", b as f64 / 1048576.));
+        let rows = [
+            ("Physics (last)", format!("{} µs", report.physics.last_us)),
+            ("Physics (avg)", format!("{:.0} µs", report.physics.avg_us)),
+            ("Render", format!("{} µs", report.render_us)),
+            ("Ships", report.ships.to_string()),
+            ("Bodies", report.bodies.to_string()),
+            ("Heap", heap),
+        ]
+        .into_iter()
+        .map(|(name, value)| Row::new([name.to_owned(), value]));
+        Clear.render(area, buf);
+        Table::new(rows, [Constraint::Length(15), Constraint::Fill(1)])
+            .block(Block::new().borders(Borders::LEFT | Borders::BOTTOM))
+            .fg(Color::Yellow)
+            .render(area, buf);
+    }
+}
+
+#[cfg(feature = "profiling")]
+pub use counting_alloc::heap_usage;
+
+#[cfg(not(feature = "profiling"))]
+pub fn heap_usage() -> Option<usize> {
+    None
+}
+
+#[cfg(feature = "profiling")]
+mod counting_alloc {
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+    /// Wraps the system allocator to keep track of the number of allocated bytes
+    struct CountingAllocator;
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let ptr = System.alloc(layout);
+            if !ptr.is_null() {
+                ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+            }
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout);
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let new_ptr = System.realloc(ptr, layout, new_size);
+            if !new_ptr.is_null() {
+                ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+                ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+            }
+            new_ptr
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: CountingAllocator = CountingAllocator;
+
+    pub fn heap_usage() -> Option<usize> {
+        Some(ALLOCATED.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use crate::{
+        physics::time::SIMTICKS_PER_TICK, prelude::*, ui::screen::fleet::FleetScreenEvent,
+    };
+
+    use super::{PhysicsTimings, ProfilerOverlay, ProfilerReport};
+
+    #[test]
+    fn test_profiler_overlay() {
+        let mut app = App::new();
+        app.add_plugins((
+            ClientPlugin::testing().in_mode(ClientMode::Singleplayer),
+            TuiPlugin::testing(),
+        ));
+        app.update();
+        app.update();
+        app.update();
+        assert!(!app.world().resource::<ProfilerOverlay>().enabled);
+        app.world_mut().send_event(FleetScreenEvent::ToggleProfiler);
+        app.update();
+        assert!(app.world().resource::<ProfilerOverlay>().enabled);
+
+        app.world_mut()
+            .resource_mut::<NextState<GameStage>>()
+            .set(GameStage::Action);
+        while app.world().resource::<GameTime>().simtick < SIMTICKS_PER_TICK {
+            app.update();
+        }
+        app.update();
+        assert!(app.world().resource::<PhysicsTimings>().avg_us > 0.);
+        let report = app.world().resource::<ProfilerReport>();
+        assert_eq!(
+            report.bodies,
+            app.world().resource::<BodiesMapping>().0.len()
+        );
+        assert_eq!(report.ships, 0);
+        assert_eq!(report.heap_bytes.is_some(), cfg!(feature = "profiling"));
+    }
+}
@@ -122,6 +122,7 @@ impl SearchState {
         self.list_state.select(Some(0));
     }
 
+    /// Keeps the bodies whose name, id or an alias matches the search, best matches first
     pub fn update_search_entries<'a>(
         &mut self,
         bodies: impl Iterator<Item = &'a BodyInfo>,
@@ -129,8 +130,11 @@ impl SearchState {
     ) {
         let mut ids_score: Vec<_> = bodies
             .filter_map(|BodyInfo(body)| {
-                fuzzy_matcher
-                    .fuzzy_match(&body.name, &self.search_input)
+                [body.name.as_str(), body.id.as_str()]
+                    .into_iter()
+                    .chain(body.aliases.iter().map(String::as_str))
+                    .filter_map(|name| fuzzy_matcher.fuzzy_match(name, self.search_input.trim()))
+                    .max()
                     .map(|score| (body, score))
             })
             .collect();
@@ -148,7 +152,7 @@ mod tests {
     use bevy::app::App;
 
     use super::*;
-    use crate::ui::screen::explorer::{ExplorerContext, ExplorerEvent};
+    use crate::ui::screen::explorer::{ExplorerContext, ExplorerEvent, SidePaneMode, ViewEvent};
 
     #[test]
     fn test_search() {
@@ -174,6 +178,24 @@ mod tests {
         app.update();
         let ctx = app.world_mut().resource_mut::<ExplorerContext>();
         let id = ctx.tree_state.selected_body_id();
-        assert_eq!(id, id_from("lune"))
+        assert_eq!(id, id_from("lune"));
+
+        // The French names are aliases of the bodies
+        app.world_mut()
+            .send_event(ExplorerEvent::View(ViewEvent::ChangeSidePaneMode(
+                SidePaneMode::Search,
+            )));
+        app.update();
+        app.world_mut().send_event_batch(
+            "La Terre"
+                .chars()
+                .map(|c| ExplorerEvent::Search(WriteChar(c))),
+        );
+        app.update();
+        app.world_mut()
+            .send_event(ExplorerEvent::Search(SearchEvent::ValidateSearch));
+        app.update();
+        let ctx = app.world_mut().resource_mut::<ExplorerContext>();
+        assert_eq!(ctx.tree_state.selected_body_id(), id_from("terre"));
     }
 }
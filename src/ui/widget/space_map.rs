@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+
 use bevy::{
     math::{DVec2, DVec3},
     prelude::*,
@@ -9,15 +11,28 @@ use ratatui::{
     style::{Color, Stylize},
     widgets::{
         block::Title,
-        canvas::{Canvas, Circle},
+        canvas::{Canvas, Circle, Line, Points},
         Block, StatefulWidgetRef, WidgetRef,
     },
 };
 
-use crate::{prelude::*, utils::algebra::project_onto_plane};
+use crate::{
+    client::{FollowedRegion, LastServerUpdate},
+    network::{InterestFocus, InterestRegion},
+    physics::orbit::SystemSize,
+    prelude::*,
+    ui::screen::editor::EditorContext,
+    utils::algebra::{project_onto_plane, spin_axis_direction},
+};
 
 pub const OFFSET_STEP: f64 = 1e8;
 pub const ZOOM_STEP: f64 = 1.5;
+/// Smallest zoom level, at which the whole system takes a fifth of the map
+pub const MIN_ZOOM_LEVEL: f64 = 0.2;
+/// Number of dashes drawn along each edge of a ring system
+const RING_DASHES: usize = 36;
+/// Maximum number of characters of a label on the map
+const LABEL_LENGTH: usize = 8;
 
 #[derive(Debug)]
 pub enum SpaceMapEvent {
@@ -26,6 +41,76 @@ pub enum SpaceMapEvent {
     MapOffsetReset,
     FocusBody,
     Autoscale,
+    CycleLabels,
+    /// Follows the next ship, in the order of ids
+    FollowNextShip,
+}
+
+/// Which objects of the map are labeled
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LabelMode {
+    Off,
+    SelectedOnly,
+    #[default]
+    All,
+}
+
+impl LabelMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::SelectedOnly,
+            Self::SelectedOnly => Self::All,
+            Self::All => Self::Off,
+        }
+    }
+}
+
+/// How the space map is displayed, kept when leaving the screen
+#[derive(Resource, Debug, Default, Clone, Copy)]
+pub struct MapDisplaySettings {
+    pub labels: LabelMode,
+}
+
+/// A label to write next to an object drawn on the map
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelCandidate {
+    /// Cell of the object
+    pub cell: (i32, i32),
+    pub text: String,
+    /// Labels with a higher priority are placed first
+    pub priority: u8,
+}
+
+/// Places labels next to their objects so that they don't overlap each other or any object.
+///
+/// Labels are placed by decreasing priority (then in the given order), each one being tried at the
+/// right, at the left, above and below its object. A label that fits nowhere inside the bounds is
+/// dropped. Returns the first cell of each placed label along with its text
+pub fn place_labels(candidates: &[LabelCandidate], bounds: Rect) -> Vec<((u16, u16), &str)> {
+    let mut occupied: HashSet<(i32, i32)> = candidates.iter().map(|c| c.cell).collect();
+    let mut order: Vec<_> = candidates.iter().collect();
+    order.sort_by_key(|c| std::cmp::Reverse(c.priority));
+    let (left, top) = (bounds.left() as i32, bounds.top() as i32);
+    let (right, bottom) = (bounds.right() as i32, bounds.bottom() as i32);
+    let mut placed = Vec::new();
+    for candidate in order {
+        let (x, y) = candidate.cell;
+        let len = candidate.text.chars().count() as i32;
+        let cells = |(start, y): (i32, i32)| (start..start + len).map(move |x| (x, y));
+        let spot = [(x + 1, y), (x - len, y), (x, y - 1), (x, y + 1)]
+            .into_iter()
+            .find(|&(start, y)| {
+                start >= left
+                    && start + len <= right
+                    && (top..bottom).contains(&y)
+                    && cells((start, y)).all(|cell| !occupied.contains(&cell))
+            });
+        if let Some(spot) = spot {
+            occupied.extend(cells(spot));
+            placed.push(((spot.0 as u16, spot.1 as u16), candidate.text.as_str()));
+        }
+    }
+    placed
 }
 
 #[derive(Debug, Resource)]
@@ -53,7 +138,7 @@ impl SpaceMap {
     }
 
     pub fn zoom_out(&mut self) {
-        self.zoom_level /= ZOOM_STEP;
+        self.zoom_level = (self.zoom_level / ZOOM_STEP).max(MIN_ZOOM_LEVEL);
     }
 
     pub fn zoom(&mut self, direction: Direction2) {
@@ -81,7 +166,7 @@ impl SpaceMap {
     }
 
     pub fn autoscale(&mut self, id_mapping: &HashMap<BodyID, Entity>, bodies: &Query<&BodyInfo>) {
-        if let Some(focus_data) = self.focus_body.map(|f| &bodies.get(f).unwrap().0) {
+        if let Some(BodyInfo(focus_data)) = self.focus_body.and_then(|f| bodies.get(f).ok()) {
             if let Some(max_dist) = focus_data
                 .orbiting_bodies
                 .iter()
@@ -104,9 +189,52 @@ impl SpaceMap {
     }
 }
 
+/// Keeps the scale of the map in sync with the size of the system
+pub fn update_system_size(system_size: Res<SystemSize>, mut space_map: ResMut<SpaceMap>) {
+    space_map.system_size = system_size.0;
+}
+
+/// Asks the server to follow the part of the system shown around the focus of the map, along
+/// with the ship followed by the map and the one of the editor. The region of a followed ship is
+/// centered on its main influencer
+pub fn update_followed_region(
+    space_map: Res<SpaceMap>,
+    bodies: Query<&BodyInfo>,
+    ships: Query<(&ShipInfo, &Influenced)>,
+    editor: Option<Res<EditorContext>>,
+    mut region: ResMut<FollowedRegion>,
+) {
+    let followed = space_map.focus_body.and_then(|e| ships.get(e).ok());
+    let focus = followed.map_or(space_map.focus_body, |(_, influenced)| {
+        influenced.main_influencer
+    });
+    let Some(BodyInfo(focus)) = focus.and_then(|e| bodies.get(e).ok()) else {
+        return;
+    };
+    let always = followed
+        .map(|(info, _)| info.id)
+        .into_iter()
+        .chain(editor.map(|editor| editor.ship_info.id))
+        .collect();
+    region.set_if_neq(FollowedRegion(Some(InterestRegion {
+        focus: InterestFocus::Body(focus.id),
+        radius: space_map.system_size / space_map.zoom_level + space_map.offset_amount.length(),
+        always,
+    })));
+}
+
 #[derive(Default)]
 pub struct SpaceMapWidget {
     circles: Vec<Circle>,
+    /// Dashes of the edges of the ring systems
+    ring_dashes: Vec<Line>,
+    ships: Vec<(f64, f64)>,
+    /// Ships outside the region followed by the client, only known from the coarse updates of the
+    /// server
+    coarse_ships: Vec<(f64, f64)>,
+    waypoints: Vec<(f64, f64)>,
+    /// Position, text and priority of the labels to place on the map
+    labels: Vec<(DVec2, String, u8)>,
 }
 
 impl SpaceMapWidget {
@@ -114,14 +242,36 @@ impl SpaceMapWidget {
         &mut self,
         space_map: &SpaceMap,
         query: &Query<(Entity, &Position, &BodyInfo)>,
+        rings: &Query<(&Position, &BodyInfo, &EllipticalOrbit, &RingSystem)>,
+        ships: &Query<(Entity, &Position, &ShipInfo, Option<&LastServerUpdate>)>,
+        waypoints: &WaypointPositions,
+        settings: &MapDisplaySettings,
     ) {
         let mut circles = Vec::new();
-        let &Position(focus_pos) = space_map
-            .focus_body
-            .map_or(&Position::default(), |f| query.get(f).unwrap().1);
+        let mut labels = Vec::new();
+        // The selected object is labeled first, then ships and waypoints and then bodies
+        let mut label = |entity: Entity, pos: DVec2, text: &str, priority: u8| {
+            let selected = Some(entity) == space_map.selected;
+            if settings.labels == LabelMode::All
+                || (settings.labels == LabelMode::SelectedOnly && selected)
+            {
+                let text = text.chars().take(LABEL_LENGTH).collect();
+                labels.push((pos, text, if selected { u8::MAX } else { priority }));
+            }
+        };
+        // The focus can be a ship when it is followed
+        let focus_pos = space_map.focus_body.map_or(DVec3::ZERO, |f| {
+            query
+                .get(f)
+                .map(|(_, p, _)| p.0)
+                .or_else(|_| ships.get(f).map(|(_, p, ..)| p.0))
+                .unwrap_or_default()
+        });
+        let project = |pos: DVec3| {
+            project_onto_plane(pos - focus_pos, (DVec3::X, DVec3::Y)) - space_map.offset_amount
+        };
         for (entity, &Position(pos), BodyInfo(data)) in query.iter() {
-            let proj =
-                project_onto_plane(pos - focus_pos, (DVec3::X, DVec3::Y)) - space_map.offset_amount;
+            let proj = project(pos);
             let color = match data.body_type {
                 _ if Some(entity) == space_map.selected => Color::Red,
                 BodyType::Star => Color::Yellow,
@@ -129,6 +279,7 @@ impl SpaceMapWidget {
                 _ => Color::DarkGray,
             };
             let radius = data.radius;
+            label(entity, proj, &data.name, 0);
             circles.push(Circle {
                 x: proj.x,
                 y: proj.y,
@@ -137,6 +288,52 @@ impl SpaceMapWidget {
             });
         }
         self.circles = circles;
+        let (coarse, precise): (Vec<_>, Vec<_>) = ships
+            .iter()
+            .map(|(entity, &Position(pos), info, update)| {
+                let proj = project(pos);
+                label(entity, proj, &info.id, 1);
+                ((proj.x, proj.y), update.is_some_and(|update| update.coarse))
+            })
+            .partition(|(_, coarse)| *coarse);
+        self.ships = precise.into_iter().map(|(point, _)| point).collect();
+        self.coarse_ships = coarse.into_iter().map(|(point, _)| point).collect();
+        // Waypoints are never selected
+        self.waypoints = waypoints
+            .0
+            .iter()
+            .map(|(name, &pos)| {
+                let proj = project(pos);
+                label(Entity::PLACEHOLDER, proj, name, 1);
+                (proj.x, proj.y)
+            })
+            .collect();
+        self.labels = labels;
+
+        // The edges of the rings are circles in the equatorial plane of their body, which appear
+        // as ellipses once projected on the map
+        let mut ring_dashes = Vec::new();
+        for (&Position(pos), BodyInfo(data), orbit, ring) in rings.iter() {
+            let normal = spin_axis_direction(
+                data.axial_tilt.to_radians(),
+                orbit.long_asc_node.to_radians(),
+                orbit.inclination.to_radians(),
+            );
+            let u = normal.any_orthonormal_vector();
+            let v = normal.cross(u);
+            for radius in [ring.inner_radius_km, ring.outer_radius_km] {
+                let point = |i: usize| {
+                    let angle = i as f64 * std::f64::consts::PI / RING_DASHES as f64;
+                    project(pos + radius * (angle.cos() * u + angle.sin() * v))
+                };
+                // Every other arc of the edge is drawn
+                ring_dashes.extend((0..RING_DASHES).map(|i| {
+                    let (start, end) = (point(2 * i), point(2 * i + 1));
+                    Line::new(start.x, start.y, end.x, end.y, Color::Gray)
+                }));
+            }
+        }
+        self.ring_dashes = ring_dashes;
     }
 }
 
@@ -148,19 +345,58 @@ impl StatefulWidgetRef for SpaceMapWidget {
         let (width, height) = (area.width as f64, area.height as f64);
         let scale = state.system_size / (width.min(height) * state.zoom_level);
         let (width, height) = (width * scale, height * scale);
+        let block =
+            Block::bordered().title(Title::from("Space map".bold()).alignment(Alignment::Center));
+        let inner = block.inner(area);
         Canvas::default()
-            .block(
-                Block::bordered()
-                    .title(Title::from("Space map".bold()).alignment(Alignment::Center)),
-            )
+            .block(block)
             .x_bounds([-width / 2., width / 2.])
             .y_bounds([-height, height])
             .paint(|ctx| {
                 for circle in &self.circles {
                     ctx.draw(circle);
                 }
+                for dash in &self.ring_dashes {
+                    ctx.draw(dash);
+                }
+                ctx.draw(&Points {
+                    coords: &self.ships,
+                    color: Color::Green,
+                });
+                ctx.draw(&Points {
+                    coords: &self.coarse_ships,
+                    color: Color::DarkGray,
+                });
+                ctx.draw(&Points {
+                    coords: &self.waypoints,
+                    color: Color::Magenta,
+                });
             })
-            .render_ref(area, buf)
+            .render_ref(area, buf);
+
+        // Same conversion from map coordinates to cells as the canvas
+        let to_cell = |pos: DVec2| {
+            (
+                ((pos.x + width / 2.) * (inner.width.saturating_sub(1)) as f64 / width).floor()
+                    as i32
+                    + inner.left() as i32,
+                ((height - pos.y) * (inner.height.saturating_sub(1)) as f64 / (2. * height)).floor()
+                    as i32
+                    + inner.top() as i32,
+            )
+        };
+        let candidates: Vec<_> = self
+            .labels
+            .iter()
+            .map(|(pos, text, priority)| LabelCandidate {
+                cell: to_cell(*pos),
+                text: text.clone(),
+                priority: *priority,
+            })
+            .collect();
+        for ((x, y), text) in place_labels(&candidates, inner) {
+            buf.set_string(x, y, text, Color::White);
+        }
     }
 
     type State = SpaceMap;
@@ -192,8 +428,117 @@ mod tests {
         let map_widget = &ctx.space_map;
         let map = app.world().resource::<SpaceMap>();
         assert_eq!(map_widget.circles.len(), 9);
-        assert!(4459753056. < map.system_size);
-        assert!(map.system_size < 4537039826.);
+        // Both edges of Saturn's rings
+        assert_eq!(map_widget.ring_dashes.len(), 2 * RING_DASHES);
+        // At least Neptune's aphelion distance
+        assert!(4537039826. <= map.system_size);
+        assert!(map.system_size < 4.6e9);
+    }
+
+    #[test]
+    fn test_place_labels() {
+        let bounds = Rect::new(0, 0, 20, 20);
+        let candidate = |text: &str, priority| LabelCandidate {
+            cell: (10, 10),
+            text: text.into(),
+            priority,
+        };
+        // Three coincident ships get distinct cells, the highest priority being placed first
+        let candidates = [candidate("a", 0), candidate("b", 0), candidate("c", 1)];
+        assert_eq!(
+            place_labels(&candidates, bounds),
+            vec![((11, 10), "c"), ((9, 10), "a"), ((10, 9), "b")]
+        );
+        // Once the four cells around the object are used, the lowest priority labels are dropped
+        let candidates = [
+            candidate("a", 0),
+            candidate("b", 2),
+            candidate("c", 1),
+            candidate("d", 3),
+            candidate("e", 2),
+        ];
+        let placed = place_labels(&candidates, bounds);
+        assert_eq!(placed.len(), 4);
+        assert!(!placed.iter().any(|(_, text)| *text == "a"));
+        // Labels stay inside the bounds
+        let candidates = [LabelCandidate {
+            cell: (19, 0),
+            text: "long".into(),
+            priority: 0,
+        }];
+        assert_eq!(place_labels(&candidates, bounds), vec![((15, 0), "long")]);
+        let candidates = [LabelCandidate {
+            cell: (0, 0),
+            text: "way too long label".into(),
+            priority: 0,
+        }];
+        assert!(place_labels(&candidates, Rect::new(0, 0, 10, 1)).is_empty());
+    }
+
+    #[test]
+    fn test_labels_mode() {
+        let mut app = new_app();
+        let labels = |app: &App| {
+            app.world()
+                .resource::<ExplorerContext>()
+                .space_map
+                .labels
+                .len()
+        };
+        assert_eq!(labels(&app), 9);
+        app.world_mut()
+            .send_event(ExplorerEvent::SpaceMap(SpaceMapEvent::CycleLabels));
+        app.update();
+        assert_eq!(labels(&app), 0);
+        app.world_mut()
+            .send_event(ExplorerEvent::SpaceMap(SpaceMapEvent::CycleLabels));
+        app.update();
+        // Only the selected body
+        assert_eq!(labels(&app), 1);
+    }
+
+    #[test]
+    fn test_waypoint_markers() {
+        let mut app = new_app();
+        app.world_mut()
+            .resource_mut::<Waypoints>()
+            .insert("beacon", WaypointKind::Fixed(DVec3::new(1e8, 0., 0.)))
+            .unwrap();
+        app.update();
+        let ctx = app.world().resource::<ExplorerContext>();
+        assert_eq!(ctx.space_map.waypoints.len(), 1);
+        assert!(ctx
+            .space_map
+            .labels
+            .iter()
+            .any(|(_, text, priority)| text == "beacon" && *priority == 1));
+    }
+
+    #[test]
+    fn test_followed_region() {
+        let mut app = new_app();
+        let earth = app.world().resource::<BodiesMapping>().0["terre"];
+        let earth = app.world().entity(earth);
+        let (pos, speed) = (
+            earth.get::<Position>().unwrap().0,
+            earth.get::<Velocity>().unwrap().0,
+        );
+        app.world_mut().send_event(ShipEvent::Create(ShipInfo {
+            id: id_from("s"),
+            spawn_pos: pos + DVec3::X * 1e4,
+            spawn_speed: speed,
+            ..default()
+        }));
+        app.update();
+        app.world_mut()
+            .send_event(ExplorerEvent::SpaceMap(SpaceMapEvent::FollowNextShip));
+        app.update();
+        app.update();
+
+        // The region of a followed ship is the one around its host, and the ship is always followed
+        let region = app.world().resource::<FollowedRegion>().0.clone().unwrap();
+        assert_eq!(region.focus, InterestFocus::Body(id_from("terre")));
+        assert_eq!(region.always, vec![id_from("s")]);
     }
 
     #[test]
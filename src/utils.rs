@@ -4,6 +4,10 @@ pub mod de;
 pub mod ecs;
 pub mod hash;
 pub mod list;
+pub mod log;
+pub mod noise;
+pub mod persist;
+pub mod table;
 pub mod ui;
 
 #[derive(Debug, Clone, Copy)]
@@ -1,7 +1,8 @@
 use std::f64::consts::TAU;
 
-use bevy::math::{DMat3, DVec2, DVec3};
+use bevy::math::{DMat2, DMat3, DVec2, DVec3};
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 use crate::physics::G;
 
@@ -75,33 +76,619 @@ pub fn relative_axes(
     [forward, right, down]
 }
 
-/// Position and velocity for circular orbit at given altitude around body
-/// For now : in the eccliptic plane, rotating trigonometrically  (TODO: random inclination and direction too)
+/// Position and velocity for circular orbit at given altitude around body, starting at an angle drawn from `rng`,
+/// in the ecliptic plane and rotating trigonometrically
 pub fn circular_orbit_around_body(
     altitude: f64,
     body_mass: f64,
     body_pos: DVec3,
     body_speed: DVec3,
+    rng: &mut impl Rng,
 ) -> (DVec3, DVec3) {
-    let angle = rand::thread_rng().gen_range(0. ..TAU);
+    circular_orbit_in_plane(
+        altitude,
+        body_mass,
+        body_pos,
+        body_speed,
+        OrbitPlane::default(),
+        rng,
+    )
+}
+
+/// Orientation of a circular orbit, with angles in degrees. The orbit is in the ecliptic plane
+/// when the inclination and RAAN are left blank, and starts at an angle drawn at random when
+/// the phase is
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct OrbitPlane {
+    pub inclination: Option<f64>,
+    /// Right ascension of the ascending node, from the X axis
+    pub raan: Option<f64>,
+    /// Angle from the ascending node at which the orbit starts
+    pub phase: Option<f64>,
+}
+
+impl OrbitPlane {
+    pub fn validate(&self) -> Result<(), OrbitPlaneError> {
+        match *self {
+            Self {
+                inclination: Some(i),
+                ..
+            } if !(0. ..=180.).contains(&i) => Err(OrbitPlaneError::Inclination(i)),
+            Self { raan: Some(o), .. } if !(0. ..360.).contains(&o) => {
+                Err(OrbitPlaneError::Raan(o))
+            }
+            Self { phase: Some(p), .. } if !(0. ..360.).contains(&p) => {
+                Err(OrbitPlaneError::Phase(p))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// An angle of an [OrbitPlane] out of its range (in degrees)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum OrbitPlaneError {
+    Inclination(f64),
+    Raan(f64),
+    Phase(f64),
+}
+
+impl std::fmt::Display for OrbitPlaneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Inclination(i) => write!(f, "the inclination must be in [0°, 180°], not {i}°"),
+            Self::Raan(o) => write!(f, "the RAAN must be in [0°, 360°), not {o}°"),
+            Self::Phase(p) => write!(f, "the phase must be in [0°, 360°), not {p}°"),
+        }
+    }
+}
+
+impl std::error::Error for OrbitPlaneError {}
+
+#[allow(non_snake_case)]
+/// Same as [circular_orbit_around_body], on an orbit oriented by `plane`. The position and
+/// velocity are computed in the orbital plane, then rotated like the orbits of the bodies
+pub fn circular_orbit_in_plane(
+    altitude: f64,
+    body_mass: f64,
+    body_pos: DVec3,
+    body_speed: DVec3,
+    plane: OrbitPlane,
+    rng: &mut impl Rng,
+) -> (DVec3, DVec3) {
+    let angle = match plane.phase {
+        Some(phase) => phase.to_radians(),
+        None => rng.gen_range(0. ..TAU),
+    };
+    let O = plane.raan.unwrap_or(0.).to_radians();
+    let I = plane.inclination.unwrap_or(0.).to_radians();
     let unit_pos = DVec2::from_angle(angle);
     let unit_speed = unit_pos.perp();
-    let ((x, y), (vx, vy)) = (unit_pos.into(), unit_speed.into());
     (
-        altitude * DVec3::new(x, y, 0.) + body_pos,
-        (G * body_mass / altitude).sqrt() * DVec3::new(vx, vy, 0.) + body_speed,
+        altitude * rotate(unit_pos, 0., O, I) + body_pos,
+        (G * body_mass / altitude).sqrt() * rotate(unit_speed, 0., O, I) + body_speed,
     )
 }
 
+/// Velocity of a ship relative to the ground of a body spinning around the unit vector `spin_axis`
+/// (in km/day), which is what matters when landing on the body or taking off from it.
+/// Retrograde rotations have a negative rate
+pub fn surface_relative_velocity(
+    ship_vel: DVec3,
+    body_vel: DVec3,
+    spin_axis: DVec3,
+    body_rotation_rate_rad_per_day: f64,
+    ship_pos: DVec3,
+    body_pos: DVec3,
+) -> DVec3 {
+    let ground_vel = spin_axis.cross(ship_pos - body_pos) * body_rotation_rate_rad_per_day;
+    ship_vel - body_vel - ground_vel
+}
+
+/// Why a ship can't be spawned on a circular orbit around a body, with the allowed altitudes
+/// (in km above the surface)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum OrbitSpawnError {
+    /// The orbit would be inside the body
+    BelowSurface {
+        min_altitude: f64,
+    },
+    /// The orbit would leave the sphere of influence of the body, and the ship would escape it
+    OutsideHillSphere {
+        max_altitude: f64,
+    },
+    InvalidPlane(OrbitPlaneError),
+}
+
+impl std::fmt::Display for OrbitSpawnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BelowSurface { min_altitude } => write!(
+                f,
+                "the orbit is below the surface, the altitude must be at least {min_altitude:.0} km"
+            ),
+            Self::OutsideHillSphere { max_altitude } => write!(
+                f,
+                "the orbit leaves the sphere of influence, the altitude must be at most {max_altitude:.0} km"
+            ),
+            Self::InvalidPlane(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for OrbitSpawnError {}
+
+/// Checks that a circular orbit at `altitude` above the surface of a body stays between its
+/// surface and the limit of its sphere of influence
+pub fn check_spawn_altitude(
+    altitude: f64,
+    body_radius: f64,
+    hill_radius: f64,
+) -> Result<(), OrbitSpawnError> {
+    if altitude < 0. {
+        Err(OrbitSpawnError::BelowSurface { min_altitude: 0. })
+    } else if body_radius + altitude >= hill_radius {
+        Err(OrbitSpawnError::OutsideHillSphere {
+            max_altitude: hill_radius - body_radius,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Same as [circular_orbit_in_plane] with `altitude` measured from the surface of the body,
+/// which is checked by [check_spawn_altitude], and with the angles of the plane checked.
+/// The unchecked version remains available to spawn ships in unusual states on purpose
+#[allow(clippy::too_many_arguments)]
+pub fn checked_circular_orbit_around_body(
+    altitude: f64,
+    body_radius: f64,
+    hill_radius: f64,
+    body_mass: f64,
+    body_pos: DVec3,
+    body_speed: DVec3,
+    plane: OrbitPlane,
+    rng: &mut impl Rng,
+) -> Result<(DVec3, DVec3), OrbitSpawnError> {
+    check_spawn_altitude(altitude, body_radius, hill_radius)?;
+    plane.validate().map_err(OrbitSpawnError::InvalidPlane)?;
+    Ok(circular_orbit_in_plane(
+        body_radius + altitude,
+        body_mass,
+        body_pos,
+        body_speed,
+        plane,
+        rng,
+    ))
+}
+
+/// Shape of the two-body orbit followed by an object at some instant
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OsculatingElements {
+    /// Negative for escape trajectories (in km)
+    pub semimajor_axis: f64,
+    pub eccentricity: f64,
+    /// Distance to the focus at periapsis (in km)
+    pub periapsis: f64,
+    /// None for escape trajectories (in km)
+    pub apoapsis: Option<f64>,
+    /// None for escape trajectories (in days)
+    pub period: Option<f64>,
+}
+
+impl OsculatingElements {
+    pub fn is_escape(&self) -> bool {
+        self.eccentricity >= 1.
+    }
+}
+
+/// Osculating orbit of an object at `rel_pos` with `rel_speed` from a body of gravitational
+/// parameter `gm` (in km³/day²)
+pub fn osculating_elements(rel_pos: DVec3, rel_speed: DVec3, gm: f64) -> OsculatingElements {
+    let r = rel_pos.length();
+    let energy = rel_speed.length_squared() / 2. - gm / r;
+    let eccentricity_vector = rel_speed.cross(rel_pos.cross(rel_speed)) / gm - rel_pos / r;
+    let eccentricity = eccentricity_vector.length();
+    let h = rel_pos.cross(rel_speed).length();
+    let periapsis = h * h / (gm * (1. + eccentricity));
+    let semimajor_axis = -gm / (2. * energy);
+    let bound = eccentricity < 1.;
+    OsculatingElements {
+        semimajor_axis,
+        eccentricity,
+        periapsis,
+        apoapsis: bound.then_some(semimajor_axis * (1. + eccentricity)),
+        period: bound.then(|| TAU * (semimajor_axis.powi(3) / gm).sqrt()),
+    }
+}
+
 #[allow(non_snake_case)]
-pub fn center_to_periapsis_direction(o: f64, O: f64, I: f64) -> DVec3 {
-    DVec3::new(
-        O.cos() * o.cos() - O.sin() * I.cos() * o.sin(),
-        O.cos() * o.cos() + O.cos() * I.cos() * o.sin(),
-        I.sin() * o.sin(),
-    )
+/// Unit vector pointing from the focus of an orbit towards its periapsis, with angles in radians
+pub fn periapsis_direction(o: f64, O: f64, I: f64) -> DVec3 {
+    rotate(DVec2::X, o, O, I)
+}
+
+#[allow(non_snake_case)]
+/// Unit vector pointing from the focus of an orbit towards its ascending node, with the longitude of the ascending node in radians
+pub fn ascending_node_direction(O: f64) -> DVec3 {
+    DVec3::new(O.cos(), O.sin(), 0.)
+}
+
+#[allow(non_snake_case)]
+/// Unit vector along the rotation axis of a body, with angles in radians.
+///
+/// The axis is tilted from the normal of the orbital plane around the line of nodes.
+pub fn spin_axis_direction(axial_tilt: f64, O: f64, I: f64) -> DVec3 {
+    rotate(DVec2::new(0., -axial_tilt.sin()), 0., O, I) + orbit_normal(O, I) * axial_tilt.cos()
+}
+
+#[allow(non_snake_case)]
+/// Unit vector normal to the orbital plane, with angles in radians
+pub fn orbit_normal(O: f64, I: f64) -> DVec3 {
+    DVec3::new(I.sin() * O.sin(), -I.sin() * O.cos(), I.cos())
 }
 
 pub fn ellipse_half_sizes(a: f64, e: f64) -> DVec2 {
     DVec2::new(1., (1. - e * e).sqrt()) * a
 }
+
+/// Relative tolerance under which beacons are considered aligned or in a single plane
+const TRILATERATION_EPSILON: f64 = 1e-9;
+
+/// Position whose distances to the beacons best match the ranges measured to them, in the
+/// least-squares sense, from `(beacon position, range)` pairs.
+///
+/// The ranges to beacons in a single plane (in particular to only three beacons) are matched by
+/// two positions mirrored by that plane, in which case there is no answer, see
+/// [trilaterate_near] to choose between them
+pub fn trilaterate(beacons: &[(DVec3, f64)]) -> Option<DVec3> {
+    match trilateration_candidates(beacons)? {
+        (pos, None) => Some(pos),
+        (_, Some(_)) => None,
+    }
+}
+
+/// Like [trilaterate], but when the beacons are in a single plane, the position closest to
+/// `guess` is kept
+pub fn trilaterate_near(beacons: &[(DVec3, f64)], guess: DVec3) -> Option<DVec3> {
+    Some(match trilateration_candidates(beacons)? {
+        (pos, None) => pos,
+        (a, Some(b)) if a.distance_squared(guess) <= b.distance_squared(guess) => a,
+        (_, Some(b)) => b,
+    })
+}
+
+/// The least-squares position, and its mirror image when the beacons are in a single plane
+fn trilateration_candidates(beacons: &[(DVec3, f64)]) -> Option<(DVec3, Option<DVec3>)> {
+    if beacons.len() < 3 {
+        return None;
+    }
+    let (&(origin, r0), others) = beacons.split_first().unwrap();
+    // Subtracting the equation of the sphere around the first beacon from the others gives the
+    // linear equations d·x = (|d|² - r² + r0²) / 2, with x and d relative to the first beacon
+    let equations: Vec<_> = others
+        .iter()
+        .map(|&(pos, r)| {
+            let d = pos - origin;
+            (d, (d.length_squared() - r * r + r0 * r0) / 2.)
+        })
+        .collect();
+    let scale = equations.iter().map(|(d, _)| d.length()).fold(0., f64::max);
+    let normal = equations
+        .iter()
+        .flat_map(|(d, _)| equations.iter().map(|(e, _)| d.cross(*e)))
+        .max_by(|a, b| a.length_squared().total_cmp(&b.length_squared()))?;
+    if normal.length() <= TRILATERATION_EPSILON * scale * scale {
+        return None;
+    }
+    let normal = normal.normalize();
+
+    if equations
+        .iter()
+        .any(|(d, _)| d.dot(normal).abs() > TRILATERATION_EPSILON * scale)
+    {
+        // Normal equations of the overdetermined system
+        let (ata, atb) =
+            equations
+                .iter()
+                .fold((DMat3::ZERO, DVec3::ZERO), |(ata, atb), &(d, c)| {
+                    (
+                        ata + DMat3::from_cols(d * d.x, d * d.y, d * d.z),
+                        atb + d * c,
+                    )
+                });
+        return Some((origin + ata.inverse() * atb, None));
+    }
+
+    // Only the position in the plane of the beacons is given by the equations, the distance to
+    // the plane comes from the range to the first beacon
+    let u = equations[0].0.normalize();
+    let v = normal.cross(u);
+    let (ata, atb) = equations
+        .iter()
+        .fold((DMat2::ZERO, DVec2::ZERO), |(ata, atb), &(d, c)| {
+            let d = DVec2::new(d.dot(u), d.dot(v));
+            (ata + DMat2::from_cols(d * d.x, d * d.y), atb + d * c)
+        });
+    let in_plane = ata.inverse() * atb;
+    let in_plane = origin + in_plane.x * u + in_plane.y * v;
+    let height = (r0 * r0 - in_plane.distance_squared(origin)).max(0.).sqrt();
+    Some((in_plane + height * normal, Some(in_plane - height * normal)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::{FRAC_PI_2, PI};
+
+    use bevy::math::DVec3;
+
+    use super::*;
+
+    fn assert_close(a: DVec3, b: DVec3) {
+        assert!((a - b).length() < 1e-9, "{a} != {b}");
+    }
+
+    #[test]
+    fn test_surface_relative_velocity() {
+        let (body_pos, body_vel) = (DVec3::new(1e8, 0., 0.), DVec3::new(0., 1e6, 0.));
+        // A ship hovering above the equator of the Earth moves with the ground
+        let rate = TAU;
+        let offset = DVec3::new(6371., 0., 0.);
+        let ship_vel = body_vel + DVec3::new(0., 6371. * TAU, 0.);
+        assert_close(
+            surface_relative_velocity(
+                ship_vel,
+                body_vel,
+                DVec3::Z,
+                rate,
+                body_pos + offset,
+                body_pos,
+            ),
+            DVec3::ZERO,
+        );
+        // Above the pole, the rotation of the body doesn't matter
+        assert_close(
+            surface_relative_velocity(
+                body_vel + DVec3::Z,
+                body_vel,
+                DVec3::Z,
+                rate,
+                body_pos + DVec3::new(0., 0., 6371.),
+                body_pos,
+            ),
+            DVec3::Z,
+        );
+        // A body lying on its side, spinning around the x axis like Uranus
+        let pole = DVec3::new(6371., 0., 0.);
+        assert_close(
+            surface_relative_velocity(
+                body_vel,
+                body_vel,
+                DVec3::X,
+                rate,
+                body_pos + pole,
+                body_pos,
+            ),
+            DVec3::ZERO,
+        );
+        assert_close(
+            surface_relative_velocity(
+                body_vel + DVec3::new(0., 0., 6371. * TAU),
+                body_vel,
+                DVec3::X,
+                rate,
+                body_pos + DVec3::new(0., 6371., 0.),
+                body_pos,
+            ),
+            DVec3::ZERO,
+        );
+    }
+
+    #[test]
+    fn test_checked_circular_orbit() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        // Earth, with its Hill radius around the Sun
+        let (radius, hill, mass) = (6371., 1.5e6, 5.972e24);
+        let (pos, speed) = (DVec3::new(1.5e8, 0., 0.), DVec3::new(0., 2.6e6, 0.));
+        let checked = |altitude| {
+            checked_circular_orbit_around_body(
+                altitude,
+                radius,
+                hill,
+                mass,
+                pos,
+                speed,
+                OrbitPlane::default(),
+                &mut StdRng::seed_from_u64(0),
+            )
+        };
+        assert_eq!(
+            checked(400.),
+            Ok(circular_orbit_around_body(
+                radius + 400.,
+                mass,
+                pos,
+                speed,
+                &mut StdRng::seed_from_u64(0)
+            ))
+        );
+        assert_eq!(
+            checked(-100.),
+            Err(OrbitSpawnError::BelowSurface { min_altitude: 0. })
+        );
+        assert_eq!(
+            checked(2e6),
+            Err(OrbitSpawnError::OutsideHillSphere {
+                max_altitude: hill - radius
+            })
+        );
+    }
+
+    #[test]
+    fn test_inclined_circular_orbit() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let (mass, pos, speed) = (
+            5.972e24,
+            DVec3::new(1.5e8, 0., 0.),
+            DVec3::new(0., 2.6e6, 0.),
+        );
+        let orbit = |plane| {
+            circular_orbit_in_plane(
+                7000.,
+                mass,
+                pos,
+                speed,
+                plane,
+                &mut StdRng::seed_from_u64(3),
+            )
+        };
+        // Without angles, the same orbit as before the plane could be chosen
+        assert_eq!(
+            orbit(OrbitPlane::default()),
+            circular_orbit_around_body(7000., mass, pos, speed, &mut StdRng::seed_from_u64(3))
+        );
+
+        // A polar orbit turns around an axis in the ecliptic plane
+        let polar = OrbitPlane {
+            inclination: Some(90.),
+            raan: Some(30.),
+            phase: Some(0.),
+        };
+        let (p, v) = orbit(polar);
+        let momentum = (p - pos).cross(v - speed).normalize();
+        assert!(momentum.z.abs() < 1e-9, "{momentum}");
+        // Starting at the ascending node
+        assert_close(
+            (p - pos).normalize(),
+            ascending_node_direction(30f64.to_radians()),
+        );
+        assert!((v - speed).z > 0.);
+        assert!(((p - pos).length() - 7000.).abs() < 1e-6);
+
+        assert_eq!(polar.validate(), Ok(()));
+        let invalid = |plane: OrbitPlane| plane.validate().unwrap_err();
+        assert_eq!(
+            invalid(OrbitPlane {
+                inclination: Some(181.),
+                ..polar
+            }),
+            OrbitPlaneError::Inclination(181.)
+        );
+        assert_eq!(
+            invalid(OrbitPlane {
+                raan: Some(360.),
+                ..polar
+            }),
+            OrbitPlaneError::Raan(360.)
+        );
+        assert_eq!(
+            invalid(OrbitPlane {
+                phase: Some(-1.),
+                ..polar
+            }),
+            OrbitPlaneError::Phase(-1.)
+        );
+    }
+
+    #[test]
+    fn test_node_and_periapsis_directions() {
+        // Orbit in the ecliptic plane, everything aligned with the X axis
+        assert_close(ascending_node_direction(0.), DVec3::X);
+        assert_close(periapsis_direction(0., 0., 0.), DVec3::X);
+        assert_close(orbit_normal(0., 0.), DVec3::Z);
+
+        // Node on the Y axis, periapsis a quarter turn further in a polar orbit
+        assert_close(ascending_node_direction(FRAC_PI_2), DVec3::Y);
+        assert_close(
+            periapsis_direction(FRAC_PI_2, FRAC_PI_2, FRAC_PI_2),
+            DVec3::Z,
+        );
+        assert_close(orbit_normal(FRAC_PI_2, FRAC_PI_2), DVec3::X);
+
+        // Retrograde equatorial orbit: periapsis a quarter turn clockwise from the node
+        assert_close(periapsis_direction(FRAC_PI_2, 0., PI), -DVec3::Y);
+        assert_close(orbit_normal(0., PI), -DVec3::Z);
+
+        // The periapsis of any orbit lies in its plane, at the argument of periapsis from the node
+        let (o, O, I) = (
+            114.20783_f64.to_radians(),
+            348.73936_f64.to_radians(),
+            0.00005_f64.to_radians(),
+        );
+        let peri = periapsis_direction(o, O, I);
+        assert!(peri.dot(orbit_normal(O, I)).abs() < 1e-9);
+        assert!((peri.dot(ascending_node_direction(O)) - o.cos()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_osculating_elements() {
+        let gm = 1e15;
+        let (pos, speed) = circular_orbit_around_body(
+            1e4,
+            gm / G,
+            DVec3::ZERO,
+            DVec3::ZERO,
+            &mut rand::thread_rng(),
+        );
+        let elements = osculating_elements(pos, speed, gm);
+        assert!(elements.eccentricity < 1e-9);
+        assert!((elements.periapsis - 1e4).abs() < 1e-6);
+        assert!((elements.apoapsis.unwrap() - 1e4).abs() < 1e-6);
+        assert!((elements.period.unwrap() - TAU * (1e12 / gm).sqrt()).abs() < 1e-12);
+
+        // Faster than the escape velocity
+        let elements = osculating_elements(pos, speed * 1.5, gm);
+        assert!(elements.is_escape());
+        assert_eq!(elements.period, None);
+        assert!((elements.periapsis - 1e4).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_spin_axis_direction() {
+        assert_close(spin_axis_direction(0., 1., 0.5), orbit_normal(1., 0.5));
+        let (tilt, O, I) = (23.44_f64.to_radians(), 0.3, 0.1);
+        let axis = spin_axis_direction(tilt, O, I);
+        assert!((axis.length() - 1.).abs() < 1e-9);
+        assert!((axis.dot(orbit_normal(O, I)) - tilt.cos()).abs() < 1e-9);
+        assert!(axis.dot(ascending_node_direction(O)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_trilaterate() {
+        let target = DVec3::new(1.5e8, -2e4, 3e3);
+        let beacons: Vec<_> = [
+            DVec3::new(1.5e8, 0., 0.),
+            DVec3::new(1.5e8 + 4e5, 1e5, 0.),
+            DVec3::new(1.5e8 - 1e5, 3e5, 0.),
+            DVec3::new(1.5e8, 2e4, 5e4),
+        ]
+        .into_iter()
+        .map(|b| (b, b.distance(target)))
+        .collect();
+        let close = |a: DVec3, b: DVec3| (a - b).length() < 1e-3;
+        assert!(close(trilaterate(&beacons).unwrap(), target));
+        // A fifth range a kilometer off moves the fix a bit, but doesn't throw it away
+        let mut noisy = beacons.clone();
+        let fifth = DVec3::new(1.5e8, -1e5, -1e5);
+        noisy.push((fifth, fifth.distance(target) + 1.));
+        let fix = trilaterate(&noisy).unwrap();
+        assert!(!close(fix, target) && fix.distance(target) < 10.);
+
+        // Three beacons in the ecliptic can't tell whether the target is above or below it
+        assert_eq!(trilaterate(&beacons[..3]), None);
+        let above = trilaterate_near(&beacons[..3], target + DVec3::Z * 100.).unwrap();
+        assert!(close(above, target));
+        let below = trilaterate_near(&beacons[..3], target * DVec3::new(1., 1., -1.)).unwrap();
+        assert!(close(below, target * DVec3::new(1., 1., -1.)));
+
+        // Aligned beacons only give a circle
+        let aligned: Vec<_> = [0., 1e5, 2e5]
+            .map(|x| DVec3::new(x, 0., 0.))
+            .map(|b| (b, b.distance(target)))
+            .to_vec();
+        assert_eq!(trilaterate_near(&aligned, target), None);
+        assert_eq!(trilaterate(&beacons[..2]), None);
+    }
+}
@@ -19,3 +19,29 @@ pub fn get_keymap(mut args: Args) -> Result<Keymap, Box<dyn Error>> {
     }
     Ok(keymap)
 }
+
+/// Reads the seed of the world from the `--seed VALUE` flag, if present
+pub fn get_seed(mut args: Args) -> Result<Option<u64>, Box<dyn Error>> {
+    match args.position(|arg| arg == "--seed") {
+        Some(_) => Ok(Some(args.next().ok_or("Expected seed value")?.parse()?)),
+        None => Ok(None),
+    }
+}
+
+/// Reads the name of the player from the `--name NAME` flag, if present
+pub fn get_name(mut args: Args) -> Result<Option<String>, Box<dyn Error>> {
+    match args.position(|arg| arg == "--name") {
+        Some(_) => Ok(Some(args.next().ok_or("Expected player name")?)),
+        None => Ok(None),
+    }
+}
+
+/// Whether the `--quiet` flag is present, to only log warnings and errors
+pub fn get_quiet(mut args: Args) -> bool {
+    args.any(|arg| arg == "--quiet")
+}
+
+/// Whether the `--check` flag is present, to validate the bundled bodies and exit
+pub fn get_check(mut args: Args) -> bool {
+    args.any(|arg| arg == "--check")
+}
@@ -0,0 +1,227 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    log::{
+        tracing_subscriber::{reload, EnvFilter, Registry},
+        BoxedLayer, Level,
+    },
+    prelude::*,
+};
+
+/// Level at which the game logs when nothing else is asked
+pub const DEFAULT_LOG_LEVEL: Level = Level::DEBUG;
+
+/// Targets which are too verbose at the default level
+const QUIET_TARGETS: [&str; 2] = ["wgpu_core", "wgpu_hal"];
+
+/// Time during which identical throttled messages are only logged once
+pub const LOG_THROTTLE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Throttle shared by the hot-path logging sites, see [throttled]
+pub static LOG_THROTTLE: Mutex<LogThrottle> = Mutex::new(LogThrottle::new(LOG_THROTTLE_WINDOW));
+
+/// Level at which the log filter starts, inserted before the [bevy::log::LogPlugin] is built
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct InitialLogLevel(pub Level);
+
+/// Handle on the active log filter, which can be changed while the app runs
+#[derive(Resource, Clone)]
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    level: Level,
+    targets: BTreeMap<String, Level>,
+}
+
+impl LogFilter {
+    /// Creates a filter layer logging at the given level, along with the handle to change it
+    pub fn new(level: Level) -> (BoxedLayer, Self) {
+        let targets = QUIET_TARGETS
+            .iter()
+            .map(|target| (target.to_string(), Level::WARN))
+            .collect();
+        let directives = Self::format_directives(level, &targets);
+        let (layer, handle) = reload::Layer::new(EnvFilter::new(directives));
+        (
+            Box::new(layer),
+            Self {
+                handle,
+                level,
+                targets,
+            },
+        )
+    }
+
+    fn format_directives(level: Level, targets: &BTreeMap<String, Level>) -> String {
+        targets
+            .iter()
+            .fold(level.to_string(), |directives, (target, level)| {
+                format!("{directives},{target}={level}")
+            })
+    }
+
+    /// Directives of the active filter, in the [EnvFilter] format
+    pub fn directives(&self) -> String {
+        Self::format_directives(self.level, &self.targets)
+    }
+
+    /// Sets the level of the given target, or the default level of all the targets without one
+    pub fn set_level(&mut self, level: Level, target: Option<&str>) -> Result<(), reload::Error> {
+        match target {
+            Some(target) => {
+                self.targets.insert(target.into(), level);
+            }
+            None => self.level = level,
+        }
+        self.handle.reload(EnvFilter::new(self.directives()))
+    }
+}
+
+/// Builds the reloadable filter layer for the [bevy::log::LogPlugin], starting at the level of
+/// the [InitialLogLevel] resource if present
+pub fn reloadable_filter_layer(app: &mut App) -> Option<BoxedLayer> {
+    let level = app
+        .world()
+        .get_resource::<InitialLogLevel>()
+        .map_or(DEFAULT_LOG_LEVEL, |l| l.0);
+    let (layer, filter) = LogFilter::new(level);
+    app.insert_resource(filter);
+    Some(layer)
+}
+
+/// Lets identical messages through at most once per window, and counts the ones held back.
+/// Meant for constant messages: each distinct message is remembered
+#[derive(Debug)]
+pub struct LogThrottle {
+    window: Duration,
+    messages: BTreeMap<String, (Instant, u32)>,
+}
+
+impl LogThrottle {
+    pub const fn new(window: Duration) -> Self {
+        Self {
+            window,
+            messages: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the message to log, with the number of identical messages suppressed since the
+    /// last time it was logged, or None if it was logged less than a window ago
+    pub fn throttle(&mut self, message: String, now: Instant) -> Option<String> {
+        match self.messages.get_mut(&message) {
+            Some((last, suppressed)) if now.duration_since(*last) < self.window => {
+                *suppressed += 1;
+                None
+            }
+            Some((last, suppressed)) => {
+                *last = now;
+                Some(match std::mem::take(suppressed) {
+                    0 => message,
+                    n => format!("{message} ({n} similar messages suppressed)"),
+                })
+            }
+            None => {
+                self.messages.insert(message.clone(), (now, 0));
+                Some(message)
+            }
+        }
+    }
+}
+
+/// Passes the message through [LOG_THROTTLE]
+pub fn throttle(message: String) -> Option<String> {
+    LOG_THROTTLE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .throttle(message, Instant::now())
+}
+
+/// Logs a message at the given level, at most once per [LOG_THROTTLE_WINDOW]
+#[macro_export]
+macro_rules! throttled {
+    ($level:expr, $($arg:tt)+) => {
+        if ::bevy::utils::tracing::enabled!($level) {
+            if let Some(message) = $crate::utils::log::throttle(format!($($arg)+)) {
+                ::bevy::utils::tracing::event!($level, "{message}");
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use bevy::{
+        log::{
+            tracing_subscriber::{fmt, layer::SubscriberExt, Registry},
+            Level,
+        },
+        prelude::*,
+        utils::tracing::subscriber::with_default,
+    };
+
+    use super::{LogFilter, LogThrottle};
+
+    #[test]
+    fn test_log_throttle() {
+        let mut throttle = LogThrottle::new(Duration::from_secs(1));
+        let start = Instant::now();
+        let logged: Vec<_> = (0..1000)
+            .filter_map(|_| throttle.throttle("update".into(), start))
+            .collect();
+        assert_eq!(logged, vec!["update".to_string()]);
+        let next = throttle
+            .throttle("update".into(), start + Duration::from_secs(1))
+            .unwrap();
+        assert!(next.contains("999"), "{next}");
+        assert_eq!(
+            throttle.throttle("other".into(), start),
+            Some("other".into())
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_log_level() {
+        let buffer = Buffer::default();
+        let (layer, mut filter) = LogFilter::new(Level::INFO);
+        let writer = buffer.clone();
+        let subscriber = Registry::default().with(layer).with(
+            fmt::layer()
+                .with_ansi(false)
+                .with_writer(move || writer.clone()),
+        );
+        with_default(subscriber, || {
+            debug!("first");
+            filter.set_level(Level::DEBUG, None).unwrap();
+            debug!("second");
+            filter.set_level(Level::WARN, Some(module_path!())).unwrap();
+            info!("third");
+        });
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(!output.contains("first"));
+        assert!(output.contains("second"));
+        assert!(!output.contains("third"));
+        assert!(filter.directives().contains("=WARN"));
+    }
+}
@@ -0,0 +1,126 @@
+//! Gradient (Perlin) noise, to generate deterministic textures from a seed
+
+/// Number of lattice cells after which the noise repeats
+const PERIOD: usize = 256;
+
+/// 2D Perlin noise, whose lattice gradients are shuffled from a seed
+#[derive(Clone, Debug)]
+pub struct Perlin {
+    permutation: [u8; PERIOD],
+}
+
+/// Step of the splitmix64 generator, good enough to shuffle the permutation table
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Quintic interpolation curve, whose first and second derivatives vanish at 0 and 1
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+/// Dot product of the offset with one of 8 unit gradients
+fn gradient(hash: u8, x: f64, y: f64) -> f64 {
+    const DIAGONAL: f64 = std::f64::consts::FRAC_1_SQRT_2;
+    match hash & 7 {
+        0 => x,
+        1 => -x,
+        2 => y,
+        3 => -y,
+        4 => (x + y) * DIAGONAL,
+        5 => (x - y) * DIAGONAL,
+        6 => (-x + y) * DIAGONAL,
+        _ => (-x - y) * DIAGONAL,
+    }
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut permutation = [0; PERIOD];
+        for (i, p) in permutation.iter_mut().enumerate() {
+            *p = i as u8;
+        }
+        // Fisher-Yates shuffle
+        let mut state = seed;
+        for i in (1..PERIOD).rev() {
+            let j = (splitmix64(&mut state) % (i as u64 + 1)) as usize;
+            permutation.swap(i, j);
+        }
+        Self { permutation }
+    }
+
+    fn hash(&self, x: usize, y: usize) -> u8 {
+        let p = |i: usize| self.permutation[i % PERIOD] as usize;
+        p(p(x) + y) as u8
+    }
+
+    /// Noise at the given point, in [-1, 1]. It is 0 on the points of the integer lattice
+    pub fn noise(&self, x: f64, y: f64) -> f64 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+        let (xi, yi) = (
+            x0.rem_euclid(PERIOD as f64) as usize,
+            y0.rem_euclid(PERIOD as f64) as usize,
+        );
+        let corner =
+            |i: usize, j: usize| gradient(self.hash(xi + i, yi + j), dx - i as f64, dy - j as f64);
+        let (u, v) = (fade(dx), fade(dy));
+        // The largest value of a 2D noise with unit gradients is √2/2
+        std::f64::consts::SQRT_2
+            * lerp(
+                v,
+                lerp(u, corner(0, 0), corner(1, 0)),
+                lerp(u, corner(0, 1), corner(1, 1)),
+            )
+    }
+
+    /// Fractal noise: the sum of `octaves` layers of noise, each with twice the frequency and
+    /// half the amplitude of the previous one, normalized to [-1, 1]
+    pub fn fbm(&self, x: f64, y: f64, octaves: u32) -> f64 {
+        let (mut total, mut amplitude, mut frequency, mut max) = (0., 1., 1., 0.);
+        for _ in 0..octaves {
+            total += amplitude * self.noise(x * frequency, y * frequency);
+            max += amplitude;
+            amplitude /= 2.;
+            frequency *= 2.;
+        }
+        if max > 0. {
+            total / max
+        } else {
+            0.
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Perlin;
+
+    #[test]
+    fn test_perlin() {
+        let perlin = Perlin::new(1);
+        let samples: Vec<_> = (0..2000)
+            .map(|i| {
+                let (x, y) = (i as f64 * 0.137, i as f64 * 0.071 - 40.);
+                perlin.noise(x, y)
+            })
+            .collect();
+        assert!(samples.iter().all(|n| (-1. ..=1.).contains(n)));
+        assert!(samples.iter().any(|n| *n > 0.3) && samples.iter().any(|n| *n < -0.3));
+        assert_eq!(perlin.noise(3., -7.), 0.);
+
+        // Continuous, and the same for the same seed only
+        assert!((perlin.noise(0.5, 0.5) - perlin.noise(0.501, 0.5)).abs() < 0.01);
+        assert_eq!(perlin.noise(0.3, 0.6), Perlin::new(1).noise(0.3, 0.6));
+        assert_ne!(perlin.noise(0.3, 0.6), Perlin::new(2).noise(0.3, 0.6));
+        assert!((-1. ..=1.).contains(&perlin.fbm(12.3, 4.5, 5)));
+    }
+}
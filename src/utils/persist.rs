@@ -0,0 +1,377 @@
+//! Files written on disk, wrapped in an envelope recording the version of their schema, so that
+//! the files of older versions of the game can still be read after a change of their types.
+//!
+//! A file looks like `{ format = "solar4x-trajectory", version = 2, payload = ... }`. Files
+//! written before the envelope existed are read as version 1 of their format.
+use std::{
+    ffi::OsString,
+    fs::{copy, read_to_string, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+/// Version of the files written before the envelope existed
+pub const LEGACY_VERSION: u32 = 1;
+
+/// Text format of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    Toml,
+}
+
+/// Upgrades the payload of a file from its version to the next one
+pub type Migration = fn(&mut Value) -> Result<(), String>;
+
+/// A type stored on disk, along with the changes of its schema
+pub trait Versioned: Serialize + DeserializeOwned {
+    /// Name of the format, which tells the files of this type apart
+    const FORMAT: &'static str;
+    /// Version of the files written by this version of the game
+    const VERSION: u32;
+    const ENCODING: Encoding;
+    /// The migration from each version that can still be read to the next one, by order of
+    /// version. The oldest supported version is the first one
+    const MIGRATIONS: &'static [(u32, Migration)] = &[];
+
+    fn oldest_supported_version() -> u32 {
+        Self::MIGRATIONS
+            .first()
+            .map_or(Self::VERSION, |(version, _)| *version)
+    }
+}
+
+#[derive(Debug)]
+pub enum PersistError {
+    Io(std::io::Error),
+    Parse(String),
+    WrongFormat {
+        expected: &'static str,
+        found: String,
+    },
+    NewerVersion {
+        format: &'static str,
+        version: u32,
+        supported: u32,
+    },
+    UnsupportedVersion {
+        format: &'static str,
+        version: u32,
+        oldest: u32,
+        newest: u32,
+    },
+    Migration {
+        from: u32,
+        reason: String,
+    },
+}
+
+impl From<std::io::Error> for PersistError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl std::fmt::Display for PersistError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Parse(err) => write!(f, "invalid file: {err}"),
+            Self::WrongFormat { expected, found } => {
+                write!(f, "expected a {expected} file, found a {found} file")
+            }
+            Self::NewerVersion {
+                format,
+                version,
+                supported,
+            } => write!(
+                f,
+                "this {format} file was created by a newer version of the game (version {version}), \
+                 this one supports up to version {supported}"
+            ),
+            Self::UnsupportedVersion {
+                format,
+                version,
+                oldest,
+                newest,
+            } => write!(
+                f,
+                "version {version} of the {format} files is not supported anymore, \
+                 supported versions are {oldest} to {newest}"
+            ),
+            Self::Migration { from, reason } => {
+                write!(f, "could not migrate the file from version {from}: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PersistError {}
+
+/// Envelope of the files, as written
+#[derive(Serialize)]
+struct Envelope<'a, T> {
+    format: &'a str,
+    version: u32,
+    payload: &'a T,
+}
+
+/// Envelope of the files, as read
+#[derive(Deserialize)]
+struct RawEnvelope {
+    format: String,
+    version: u32,
+    payload: Value,
+}
+
+fn parse(content: &str, encoding: Encoding) -> Result<Value, PersistError> {
+    match encoding {
+        Encoding::Json => {
+            serde_json::from_str(content).map_err(|e| PersistError::Parse(e.to_string()))
+        }
+        Encoding::Toml => toml::from_str(content).map_err(|e| PersistError::Parse(e.to_string())),
+    }
+}
+
+/// Splits a parsed file into its format, version and payload.
+/// Files without an envelope are legacy files, of unknown format
+fn open_envelope(value: Value) -> Result<(Option<String>, u32, Value), PersistError> {
+    let is_envelope = value.as_object().is_some_and(|o| {
+        o.len() == 3
+            && ["format", "version", "payload"]
+                .iter()
+                .all(|k| o.contains_key(*k))
+    });
+    if is_envelope {
+        let envelope: RawEnvelope =
+            serde_json::from_value(value).map_err(|e| PersistError::Parse(e.to_string()))?;
+        Ok((Some(envelope.format), envelope.version, envelope.payload))
+    } else {
+        Ok((None, LEGACY_VERSION, value))
+    }
+}
+
+/// Brings a payload of the given version up to the current version of `T`
+fn migrate<T: Versioned>(mut payload: Value, version: u32) -> Result<Value, PersistError> {
+    if version > T::VERSION {
+        return Err(PersistError::NewerVersion {
+            format: T::FORMAT,
+            version,
+            supported: T::VERSION,
+        });
+    }
+    let Some(start) = T::MIGRATIONS
+        .iter()
+        .position(|(from, _)| *from == version)
+        .or((version == T::VERSION).then_some(T::MIGRATIONS.len()))
+    else {
+        return Err(PersistError::UnsupportedVersion {
+            format: T::FORMAT,
+            version,
+            oldest: T::oldest_supported_version(),
+            newest: T::VERSION,
+        });
+    };
+    for (from, migration) in &T::MIGRATIONS[start..] {
+        migration(&mut payload).map_err(|reason| PersistError::Migration {
+            from: *from,
+            reason,
+        })?;
+    }
+    Ok(payload)
+}
+
+/// Reads a value from the content of a file, migrating it from older versions if needed
+pub fn from_versioned_str<T: Versioned>(content: &str) -> Result<T, PersistError> {
+    let (format, version, payload) = open_envelope(parse(content, T::ENCODING)?)?;
+    if let Some(format) = format.filter(|f| f != T::FORMAT) {
+        return Err(PersistError::WrongFormat {
+            expected: T::FORMAT,
+            found: format,
+        });
+    }
+    serde_json::from_value(migrate::<T>(payload, version)?)
+        .map_err(|e| PersistError::Parse(e.to_string()))
+}
+
+/// Content of the file storing `value` in the current version
+pub fn to_versioned_string<T: Versioned>(value: &T) -> Result<String, PersistError> {
+    let envelope = Envelope {
+        format: T::FORMAT,
+        version: T::VERSION,
+        payload: value,
+    };
+    match T::ENCODING {
+        Encoding::Json => {
+            serde_json::to_string_pretty(&envelope).map_err(|e| PersistError::Parse(e.to_string()))
+        }
+        Encoding::Toml => {
+            toml::to_string_pretty(&envelope).map_err(|e| PersistError::Parse(e.to_string()))
+        }
+    }
+}
+
+pub fn read_versioned<T: Versioned>(path: impl AsRef<Path>) -> Result<T, PersistError> {
+    from_versioned_str(&read_to_string(path)?)
+}
+
+pub fn write_versioned<T: Versioned>(
+    path: impl AsRef<Path>,
+    value: &T,
+) -> Result<(), PersistError> {
+    let content = to_versioned_string(value)?;
+    Ok(File::create(path)?.write_all(content.as_bytes())?)
+}
+
+/// Format and version of a file, or None as format if it has no envelope
+pub fn read_header(path: impl AsRef<Path>) -> Result<(Option<String>, u32), PersistError> {
+    let content = read_to_string(path)?;
+    let value = parse(&content, Encoding::Json).or_else(|_| parse(&content, Encoding::Toml))?;
+    let (format, version, _) = open_envelope(value)?;
+    Ok((format, version))
+}
+
+/// Path of the backup of a file made before migrating it
+pub fn backup_path(path: impl AsRef<Path>) -> PathBuf {
+    let mut backup = OsString::from(path.as_ref());
+    backup.push(".bak");
+    backup.into()
+}
+
+/// Rewrites a file in the current version of its format, after copying it next to it.
+/// Returns the version the file was in, files already in the current version are left untouched
+pub fn migrate_file<T: Versioned>(path: impl AsRef<Path>) -> Result<u32, PersistError> {
+    let path = path.as_ref();
+    let (format, version) = read_header(path)?;
+    let value: T = read_versioned(path)?;
+    if format.is_some() && version == T::VERSION {
+        return Ok(version);
+    }
+    copy(path, backup_path(path))?;
+    write_versioned(path, &value)?;
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use serde_json::{json, Value};
+
+    use super::{
+        from_versioned_str, migrate_file, read_versioned, to_versioned_string, Encoding, Migration,
+        PersistError, Versioned,
+    };
+
+    /// Version 1 had a single `name`, version 2 a `first` and `last` name, and version 3 added
+    /// an age
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Person {
+        first: String,
+        last: String,
+        age: u32,
+    }
+
+    fn split_name(value: &mut Value) -> Result<(), String> {
+        let object = value.as_object_mut().ok_or("not an object")?;
+        let name = object.remove("name").ok_or("no name")?;
+        let name = name.as_str().ok_or("name is not a string")?;
+        let (first, last) = name.split_once(' ').unwrap_or((name, ""));
+        object.insert("first".into(), first.into());
+        object.insert("last".into(), last.into());
+        Ok(())
+    }
+
+    fn add_age(value: &mut Value) -> Result<(), String> {
+        value
+            .as_object_mut()
+            .ok_or("not an object")?
+            .insert("age".into(), 0.into());
+        Ok(())
+    }
+
+    impl Versioned for Person {
+        const FORMAT: &'static str = "test-person";
+        const VERSION: u32 = 3;
+        const ENCODING: Encoding = Encoding::Json;
+        const MIGRATIONS: &'static [(u32, Migration)] = &[(1, split_name), (2, add_age)];
+    }
+
+    #[test]
+    fn test_versioned() {
+        let person = Person {
+            first: "Ada".into(),
+            last: "Lovelace".into(),
+            age: 36,
+        };
+        let content = to_versioned_string(&person).unwrap();
+        let value: Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(value["format"], "test-person");
+        assert_eq!(value["version"], 3);
+        assert_eq!(from_versioned_str::<Person>(&content).unwrap(), person);
+
+        // Legacy files have no envelope, and go through every migration
+        let legacy = r#"{ "name": "Ada Lovelace" }"#;
+        let migrated = from_versioned_str::<Person>(legacy).unwrap();
+        assert_eq!(
+            (&migrated.first[..], &migrated.last[..], migrated.age),
+            ("Ada", "Lovelace", 0)
+        );
+        let v2 =
+            json!({"format": "test-person", "version": 2, "payload": {"first": "A", "last": "L"}});
+        assert_eq!(
+            from_versioned_str::<Person>(&v2.to_string()).unwrap().age,
+            0
+        );
+
+        let newer = json!({"format": "test-person", "version": 4, "payload": {}});
+        let error = from_versioned_str::<Person>(&newer.to_string()).unwrap_err();
+        assert!(matches!(
+            error,
+            PersistError::NewerVersion { version: 4, .. }
+        ));
+        assert!(error.to_string().contains("created by a newer version"));
+        let other = json!({"format": "test-pet", "version": 1, "payload": {}});
+        assert!(matches!(
+            from_versioned_str::<Person>(&other.to_string()),
+            Err(PersistError::WrongFormat { .. })
+        ));
+        let broken = r#"{ "surname": "Lovelace" }"#;
+        assert!(matches!(
+            from_versioned_str::<Person>(broken),
+            Err(PersistError::Migration { from: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        #[derive(Serialize, Deserialize, Debug)]
+        struct Pet;
+        impl Versioned for Pet {
+            const FORMAT: &'static str = "test-pet";
+            const VERSION: u32 = 3;
+            const ENCODING: Encoding = Encoding::Toml;
+            const MIGRATIONS: &'static [(u32, Migration)] = &[(2, |_| Ok(()))];
+        }
+        let error = from_versioned_str::<Pet>("name = \"Rex\"").unwrap_err();
+        assert!(error.to_string().contains("supported versions are 2 to 3"));
+    }
+
+    #[test]
+    fn test_migrate_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("person.json");
+        std::fs::write(&path, r#"{ "name": "Ada Lovelace" }"#).unwrap();
+        assert_eq!(migrate_file::<Person>(&path).unwrap(), 1);
+        let backup = std::fs::read_to_string(dir.path().join("person.json.bak")).unwrap();
+        assert!(backup.contains("Ada Lovelace"));
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.contains("\"version\": 3"));
+        assert_eq!(read_versioned::<Person>(&path).unwrap().first, "Ada");
+        std::fs::remove_file(dir.path().join("person.json.bak")).unwrap();
+        assert_eq!(migrate_file::<Person>(&path).unwrap(), 3);
+        assert!(!dir.path().join("person.json.bak").exists());
+    }
+}
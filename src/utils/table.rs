@@ -0,0 +1,109 @@
+//! Plain text tables with fixed-width columns, for the reports printed by the server console and
+//! written to log files
+
+use std::fmt::{Display, Formatter};
+
+/// Columns are separated by this many spaces
+const COLUMN_GAP: usize = 2;
+
+/// A table whose columns are as wide as their widest cell. Text is aligned to the left and
+/// numbers to the right
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    headers: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(headers: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            headers: headers.into_iter().map(Into::into).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Adds a row, padded with empty cells or cut to the number of headers
+    pub fn push_row(&mut self, cells: impl IntoIterator<Item = impl Into<String>>) {
+        let mut row: Vec<String> = cells.into_iter().map(Into::into).collect();
+        row.resize(self.headers.len(), String::new());
+        self.rows.push(row);
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    fn widths(&self) -> Vec<usize> {
+        self.headers
+            .iter()
+            .enumerate()
+            .map(|(i, header)| {
+                self.rows
+                    .iter()
+                    .map(|row| row[i].chars().count())
+                    .chain([header.chars().count()])
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+}
+
+fn write_row(
+    f: &mut Formatter<'_>,
+    cells: impl IntoIterator<Item = impl AsRef<str>>,
+    widths: &[usize],
+) -> std::fmt::Result {
+    let mut line = String::new();
+    for (i, (cell, width)) in cells.into_iter().zip(widths).enumerate() {
+        let cell = cell.as_ref();
+        if i > 0 {
+            line.push_str(&" ".repeat(COLUMN_GAP));
+        }
+        if cell.parse::<f64>().is_ok() {
+            line.push_str(&format!("{cell:>width$}"));
+        } else {
+            line.push_str(&format!("{cell:<width$}"));
+        }
+    }
+    writeln!(f, "{}", line.trim_end())
+}
+
+impl Display for Table {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let widths = self.widths();
+        write_row(f, &self.headers, &widths)?;
+        write_row(f, widths.iter().map(|w| "-".repeat(*w)), &widths)?;
+        for row in &self.rows {
+            write_row(f, row, &widths)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Table;
+
+    #[test]
+    fn test_table() {
+        let mut table = Table::new(["id", "mass"]);
+        table.push_row(["probe", "12.5"]);
+        table.push_row(["cargo ship", "1500"]);
+        table.push_row(["extra", "1", "cut"]);
+        assert_eq!(table.len(), 3);
+        assert_eq!(
+            table.to_string(),
+            "id          mass
+----------  ----
+probe       12.5
+cargo ship  1500
+extra          1
+"
+        );
+    }
+}
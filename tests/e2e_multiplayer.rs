@@ -0,0 +1,208 @@
+//! End-to-end test of a multiplayer session: a server and two clients in the same process,
+//! linked in memory, going through the steps of a game and checking after each one that
+//! everyone sees the same world.
+//!
+//! Saving the game, scenarios and rollbacks to a snapshot don't exist yet, so they are not part
+//! of the session. New multiplayer features should add their steps here, with the helpers of
+//! [rust_space_trading::network::testing]
+use bevy::{math::DVec3, prelude::*};
+use rust_space_trading::{
+    client::ClientRole,
+    game::{GameStage, WorldSeed},
+    network::{
+        testing::{
+            all_synced, assert_synced, change_stage, connect_client, disconnect_client,
+            reconnect_client, run_ticks, send_to_server, set_time_running, spawn_server,
+            update_linked, ReceivedDenials,
+        },
+        ClientMessage, PermissionDenied, Role,
+    },
+    physics::time::GAMETIME_PER_SIMTICK,
+    prelude::*,
+    server::{ClientNames, ShipOwners},
+    utils::algebra::{circular_orbit_in_plane, OrbitPlane},
+};
+
+const CONSTELLATION_SIZE: usize = 4;
+
+/// Number of orbital planes of the constellation, evenly spaced in RAAN
+const CONSTELLATION_PLANES: usize = 2;
+
+/// Altitude of the orbits of the constellation, from the center of the Earth (in km)
+const CONSTELLATION_ALTITUDE: f64 = 20_000.;
+
+fn ship_state(app: &App, id: ShipID) -> (DVec3, DVec3) {
+    let world = app.world();
+    let entity = world.resource::<ShipsMapping>().0[&id];
+    (
+        world.get::<Position>(entity).unwrap().0,
+        world.get::<Velocity>(entity).unwrap().0,
+    )
+}
+
+fn ship_count(app: &App) -> usize {
+    app.world()
+        .get_resource::<ShipsMapping>()
+        .map_or(0, |ships| ships.0.len())
+}
+
+/// Ships on inclined circular orbits around the Earth, evenly spread in a few planes
+fn constellation(client: &mut App) -> Vec<ShipInfo> {
+    let world = client.world_mut();
+    let earth = world.resource::<BodiesMapping>().0["terre"];
+    let (pos, speed, mass) = {
+        let earth = world.entity(earth);
+        (
+            earth.get::<Position>().unwrap().0,
+            earth.get::<Velocity>().unwrap().0,
+            earth.get::<BodyInfo>().unwrap().0.mass,
+        )
+    };
+    let mut rng = world.resource::<WorldSeed>().rng("constellation");
+    let per_plane = CONSTELLATION_SIZE / CONSTELLATION_PLANES;
+    (0..CONSTELLATION_SIZE)
+        .map(|i| {
+            let plane = OrbitPlane {
+                inclination: Some(55.),
+                raan: Some((i / per_plane) as f64 * 360. / CONSTELLATION_PLANES as f64),
+                phase: Some((i % per_plane) as f64 * 360. / per_plane as f64),
+            };
+            let (spawn_pos, spawn_speed) =
+                circular_orbit_in_plane(CONSTELLATION_ALTITUDE, mass, pos, speed, plane, &mut rng);
+            ShipInfo {
+                id: ShipID::new(&format!("sat{i}")).unwrap(),
+                spawn_pos,
+                spawn_speed,
+                spawn_orbit: None,
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn test_multiplayer_session() {
+    let mut server = spawn_server();
+    server.insert_resource(WorldSeed(7));
+    let mut clients = vec![
+        connect_client(&mut server, "alice"),
+        connect_client(&mut server, "bob"),
+    ];
+
+    // Handshake
+    update_linked(&mut server, &mut clients, 3);
+    assert!(all_synced(&clients));
+    for client in &clients {
+        assert_eq!(client.world().resource::<ClientRole>().0, Role::Player);
+        assert_eq!(client.world().resource::<WorldSeed>(), &WorldSeed(7));
+    }
+    let names = &server.world().resource::<ClientNames>().0;
+    assert_eq!(names.get(&1).map(String::as_str), Some("alice"));
+    assert_eq!(names.get(&2).map(String::as_str), Some("bob"));
+
+    // Alice creates a constellation, and Bob a ship of his own
+    let satellites = constellation(&mut clients[0]);
+    for info in &satellites {
+        clients[0].world_mut().send_event(ShipEvent::Create(*info));
+    }
+    let bob_ship = ShipInfo {
+        id: ShipID::new("bob").unwrap(),
+        ..satellites[0]
+    };
+    clients[1]
+        .world_mut()
+        .send_event(ShipEvent::Create(bob_ship));
+    update_linked(&mut server, &mut clients, 3);
+
+    let owners = &server.world().resource::<ShipOwners>().0;
+    assert!(satellites
+        .iter()
+        .all(|info| owners.get(&info.id) == Some(&1)));
+    assert_eq!(owners.get(&bob_ship.id), Some(&2));
+    for app in [&server, &clients[0], &clients[1]] {
+        assert_eq!(ship_count(app), CONSTELLATION_SIZE + 1);
+    }
+
+    // Bob thrusts his ship, but can't touch the ships of Alice
+    let dv = DVec3::new(0., 1000., 0.);
+    let (_, bob_velocity) = ship_state(&server, bob_ship.id);
+    let (_, satellite_velocity) = ship_state(&server, satellites[0].id);
+    for id in [bob_ship.id, satellites[0].id] {
+        send_to_server(&mut clients[1], ClientMessage::Thrust { id, dv });
+    }
+    update_linked(&mut server, &mut clients, 3);
+
+    assert_eq!(ship_state(&server, bob_ship.id).1, bob_velocity + dv);
+    assert_eq!(ship_state(&server, satellites[0].id).1, satellite_velocity);
+    assert_eq!(
+        clients[1].world().resource::<ReceivedDenials>().0,
+        vec![PermissionDenied::NotOwner(satellites[0].id)]
+    );
+    assert!(clients[0]
+        .world()
+        .resource::<ReceivedDenials>()
+        .0
+        .is_empty());
+    for client in &clients {
+        assert_eq!(ship_state(client, bob_ship.id).1, bob_velocity + dv);
+    }
+    let preparation = assert_synced(&mut server, &mut clients);
+
+    // The server starts the action, and the game runs for a while
+    change_stage(&mut server, GameStage::Action);
+    set_time_running(&mut server, true);
+    update_linked(&mut server, &mut clients, 2);
+    for client in &clients {
+        assert_eq!(
+            client.world().resource::<State<GameStage>>().get(),
+            &GameStage::Action
+        );
+        assert!(client.world().resource::<ToggleTime>().0);
+    }
+    let start = server.world().resource::<GameTime>().simtick;
+    run_ticks(&mut server, &mut clients, 1000);
+    assert!(server.world().resource::<GameTime>().simtick >= start + 1000);
+
+    // Between two updates from the server, the clients move the ships by themselves, so they
+    // can be a simtick away from the server
+    for info in satellites.iter().chain([&bob_ship]) {
+        let (expected, velocity) = ship_state(&server, info.id);
+        assert_ne!(expected, info.spawn_pos);
+        let tolerance = 2. * velocity.length() * GAMETIME_PER_SIMTICK;
+        for client in &clients {
+            let (pos, _) = ship_state(client, info.id);
+            assert!(
+                pos.distance(expected) < tolerance,
+                "{} is at {pos} on a client instead of {expected}",
+                info.id
+            );
+        }
+    }
+    let action = assert_synced(&mut server, &mut clients);
+    assert_ne!(action, preparation);
+
+    // Bob leaves, then comes back as a new client and gets the whole world again
+    disconnect_client(&mut clients[1]);
+    assert_eq!(ship_count(&clients[1]), 0);
+    update_linked(&mut server, &mut clients[..1], 1);
+    assert_eq!(server.world().resource::<ClientNames>().0.get(&2), None);
+
+    reconnect_client(&mut server, &mut clients[1]);
+    update_linked(&mut server, &mut clients, 5);
+    assert!(all_synced(&clients));
+    assert_eq!(ship_count(&clients[1]), CONSTELLATION_SIZE + 1);
+    assert_eq!(
+        server
+            .world()
+            .resource::<ClientNames>()
+            .0
+            .get(&3)
+            .map(String::as_str),
+        Some("bob")
+    );
+    // The time is stopped, so nothing moved while Bob was away
+    assert_eq!(assert_synced(&mut server, &mut clients), action);
+    assert_eq!(
+        server.world().resource::<ShipOwners>().0.len(),
+        CONSTELLATION_SIZE + 1
+    );
+}